@@ -0,0 +1,146 @@
+//! Index of what was worked out about each sound, kept next to the cache of
+//! decoded sounds, so that a sound is only analysed the first time it is
+//! loaded. Like the cache, it is keyed by a hash of the file's contents.
+
+use std::{collections::BTreeMap, path::Path, time::Duration};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use crate::{chroma::Key, util::to_toml};
+
+/// Name of the index file in the cache directory.
+pub const INDEX_FILE: &str = "analysis.toml";
+
+/// What was worked out about a sound.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Analysis {
+    /// Length of the sound, which its tempo is estimated from if it isn't
+    /// tagged with one.
+    pub duration: Duration,
+    /// See [`crate::audio::SoundInfo::peaks`].
+    pub peaks: Vec<f32>,
+    /// RMS level of the whole sound, in dBFS.
+    pub loudness_db: f32,
+    pub key: Option<Key>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AnalysisIndex {
+    /// analyses by content hash, in hex since TOML keys have to be strings
+    sounds: BTreeMap<String, Analysis>,
+    /// whether anything was added since the index was loaded
+    #[serde(skip)]
+    changed: bool,
+}
+
+impl AnalysisIndex {
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read analysis index {path:?}"))?;
+
+        toml::from_str(&text).with_context(|| format!("failed to parse analysis index {path:?}"))
+    }
+
+    /// Writes the index to the given file, if anything was added to it. The
+    /// file is replaced in one go, so that it isn't left half-written if the
+    /// app is stopped.
+    pub fn save(&mut self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+
+        if !self.changed {
+            return Ok(());
+        }
+
+        let text = to_toml(&*self).context("failed to serialize analysis index")?;
+
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("failed to create directory {dir:?}"))?;
+        }
+
+        let partial = path.with_extension("part");
+        std::fs::write(&partial, text)
+            .and_then(|_| std::fs::rename(&partial, path))
+            .with_context(|| format!("failed to write analysis index {path:?}"))?;
+
+        self.changed = false;
+        Ok(())
+    }
+
+    pub fn get(&self, hash: u64) -> Option<&Analysis> {
+        self.sounds.get(&format!("{hash:016x}"))
+    }
+
+    pub fn insert(&mut self, hash: u64, analysis: Analysis) {
+        self.sounds.insert(format!("{hash:016x}"), analysis);
+        self.changed = true;
+    }
+}
+
+/// RMS level of interleaved samples, in dBFS.
+pub fn loudness_db(samples: &[f32]) -> f32 {
+    let sum_sq: f64 = samples.iter().map(|&x| x as f64 * x as f64).sum();
+    let rms = (sum_sq / samples.len().max(1) as f64).sqrt() as f32;
+
+    20. * rms.max(f32::EPSILON).log10()
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{loudness_db, Analysis, AnalysisIndex};
+    use crate::{
+        chroma::{Key, Mode},
+        util::TempDir,
+    };
+
+    #[test]
+    fn index_round_trips() {
+        let dir = TempDir::new("analysis");
+        // saving creates the cache directory if it isn't there yet
+        let path = dir.join("cache").join("analysis.toml");
+
+        let analysis = Analysis {
+            duration: Duration::from_millis(2_500),
+            peaks: vec![0.5, 1., 0.25],
+            loudness_db: -12.5,
+            key: Some(Key {
+                tonic: 9,
+                mode: Mode::Minor,
+            }),
+        };
+
+        let mut index = AnalysisIndex::load(&path).unwrap();
+        assert_eq!(index.get(0xabc), None);
+
+        index.insert(0xabc, analysis.clone());
+        index.insert(
+            0xdef,
+            Analysis {
+                key: None,
+                ..analysis.clone()
+            },
+        );
+        index.save(&path).unwrap();
+
+        let loaded = AnalysisIndex::load(&path).unwrap();
+        assert_eq!(loaded.get(0xabc), Some(&analysis));
+        assert_eq!(loaded.get(0xdef).unwrap().key, None);
+    }
+
+    #[test]
+    fn loudness_of_full_scale_square_is_zero() {
+        assert_eq!(loudness_db(&[1., -1., 1., -1.]), 0.);
+        assert!((loudness_db(&[0.5; 8]) + 6.02).abs() < 0.01);
+        assert!(loudness_db(&[]) < -100.);
+    }
+}
@@ -0,0 +1,380 @@
+//! Animations that play on the grid, over whatever the LEDs are showing. The
+//! built-in ones play while the sounds load, when they have loaded or failed
+//! to, and when a bank of pads is switched. Animations can also be made in
+//! the config, as frames or from one of the generators, and take the place
+//! of a built-in one with the same name. The generators fill the whole grid,
+//! however big it is, and frames cover the top-left corner that their rows
+//! reach:
+//!
+//! ```toml
+//! [keyboard.animations.loading]
+//! kind = "chase"
+//! back = [0, 0, 0]
+//! front = [255, 0, 255]
+//! step_ms = 100
+//!
+//! [keyboard.animations.success]
+//! kind = "frames"
+//! frames = [
+//!     { duration_ms = 200, rows = [
+//!         [[0, 255, 0], [0, 0, 0], [0, 0, 0], [0, 255, 0]],
+//!         [[0, 0, 0], [0, 255, 0], [0, 255, 0], [0, 0, 0]],
+//!         [[0, 0, 0], [0, 255, 0], [0, 255, 0], [0, 0, 0]],
+//!         [[0, 255, 0], [0, 0, 0], [0, 0, 0], [0, 255, 0]],
+//!     ] },
+//! ]
+//! ```
+
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    driver::adafruit::seesaw::neopixel::Color, grid::Grid, keyboard::PixelState, palette::Rgb,
+};
+
+/// A frame of an animation made in the config.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Frame {
+    pub duration_ms: u64,
+    /// colours of the pixels, as rows from the top. Pixels that the rows
+    /// don't reach show the LEDs underneath.
+    pub rows: Vec<Vec<Rgb>>,
+}
+
+/// An animation, as frames or a generator of them.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Animation {
+    /// Plays the frames in order, and from the start again if `repeat`.
+    Frames {
+        frames: Vec<Frame>,
+        #[serde(default)]
+        repeat: bool,
+    },
+    /// One pixel in `front` going round the grid a row at a time, over the
+    /// rest in `back`, until it is stopped.
+    Chase { back: Rgb, front: Rgb, step_ms: u64 },
+    /// The whole grid flashing `times` times, with the LEDs showing through
+    /// in between.
+    Flash {
+        color: Rgb,
+        times: usize,
+        on_ms: u64,
+        off_ms: u64,
+    },
+    /// A column in `color` sweeping across the grid from left to right.
+    Wipe { color: Rgb, step_ms: u64 },
+}
+
+/// A frame that is ready to be drawn. Pixels that are None show the LEDs
+/// underneath.
+#[derive(Debug, Clone, PartialEq)]
+struct Step {
+    pixels: Grid<Option<Color>>,
+    duration: Duration,
+}
+
+impl Step {
+    fn new(
+        duration_ms: u64,
+        (width, height): (usize, usize),
+        pixel: impl Fn(usize, usize) -> Option<Color>,
+    ) -> Self {
+        Self {
+            pixels: Grid::from_fn(width, height, pixel),
+            // frames take some time, so that repeating ones can't spin
+            duration: Duration::from_millis(duration_ms.max(1)),
+        }
+    }
+}
+
+impl Animation {
+    /// Names of the built-in animations.
+    pub const BUILTIN: [&'static str; 4] = ["loading", "success", "error", "bank"];
+
+    /// The animation called `name`, from the ones in the config or the
+    /// built-in ones.
+    pub fn named(name: &str, custom: &BTreeMap<String, Animation>) -> Option<Self> {
+        if let Some(animation) = custom.get(name) {
+            return Some(animation.clone());
+        }
+
+        match name {
+            "loading" => Some(Self::Chase {
+                back: Rgb([0, 0, 76]),
+                front: Rgb([0, 51, 178]),
+                step_ms: 250,
+            }),
+            "success" => Some(Self::Flash {
+                color: Rgb([0, 255, 0]),
+                times: 1,
+                on_ms: 150,
+                off_ms: 0,
+            }),
+            "error" => Some(Self::Flash {
+                color: Rgb([255, 0, 0]),
+                times: 3,
+                on_ms: 150,
+                off_ms: 150,
+            }),
+            "bank" => Some(Self::Wipe {
+                color: Rgb([255, 255, 255]),
+                step_ms: 40,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Whether the animation starts over when it ends, until it is stopped.
+    fn repeats(&self) -> bool {
+        match self {
+            Self::Frames { repeat, .. } => *repeat,
+            Self::Chase { .. } => true,
+            Self::Flash { .. } | Self::Wipe { .. } => false,
+        }
+    }
+
+    /// The frames of the animation on a grid of `size`, as its width and
+    /// height.
+    fn steps(&self, size: (usize, usize)) -> Vec<Step> {
+        let (width, height) = size;
+
+        match self {
+            Self::Frames { frames, .. } => frames
+                .iter()
+                .map(|frame| {
+                    Step::new(frame.duration_ms, size, |x, y| {
+                        Some(frame.rows.get(y)?.get(x)?.color())
+                    })
+                })
+                .collect(),
+            Self::Chase {
+                back,
+                front,
+                step_ms,
+            } => (0..width * height)
+                .map(|lit| {
+                    Step::new(*step_ms, size, |x, y| {
+                        let color = if y * width + x == lit { front } else { back };
+                        Some(color.color())
+                    })
+                })
+                .collect(),
+            Self::Flash {
+                color,
+                times,
+                on_ms,
+                off_ms,
+            } => (0..*times)
+                .flat_map(|_| {
+                    [
+                        Step::new(*on_ms, size, |_, _| Some(color.color())),
+                        Step::new(*off_ms, size, |_, _| None),
+                    ]
+                })
+                .collect(),
+            Self::Wipe { color, step_ms } => (0..width)
+                .map(|column| {
+                    Step::new(*step_ms, size, |x, _| (x == column).then(|| color.color()))
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Plays an animation over the pixel states of a grid. Pixels keep
+/// animating underneath it, like they do under the VU meter.
+pub struct Player {
+    steps: Vec<Step>,
+    repeat: bool,
+    current: usize,
+    /// when the current step ends
+    until: Instant,
+    stopped: bool,
+    /// whether a step has been drawn, before which the pixels that the
+    /// animation doesn't cover are drawn again, in case another animation
+    /// was covering them
+    started: bool,
+    /// what the animation last drew on each pixel, so that it only sends
+    /// changes
+    drawn: Grid<Option<Color>>,
+}
+
+impl Player {
+    /// Starts playing `animation` on a grid of `size`, as its width and
+    /// height, or returns None if it has no frames.
+    pub fn new(animation: &Animation, size: (usize, usize), now: Instant) -> Option<Self> {
+        let steps = animation.steps(size);
+        let until = now + steps.first()?.duration;
+
+        Some(Self {
+            steps,
+            repeat: animation.repeats(),
+            current: 0,
+            until,
+            stopped: false,
+            started: false,
+            drawn: Grid::new(size.0, size.1),
+        })
+    }
+
+    /// Ends the animation on the next step.
+    pub fn stop(&mut self) {
+        self.stopped = true;
+    }
+
+    /// Advances the animation and the pixel states to `now`. Returns the
+    /// pixels that need to be set, and their colours, or None once the
+    /// animation is over, when the pixel states have to be drawn again. The
+    /// pixel states are the size of the grid that the animation was started
+    /// on.
+    pub fn step(
+        &mut self,
+        now: Instant,
+        pixel_states: &mut Grid<PixelState>,
+    ) -> Option<Vec<(u16, u16, Color)>> {
+        while now >= self.until && !self.stopped {
+            self.current += 1;
+
+            if self.current == self.steps.len() {
+                self.current = 0;
+                self.stopped = !self.repeat;
+            }
+
+            self.until += self.steps[self.current].duration;
+        }
+
+        if self.stopped {
+            return None;
+        }
+
+        let step = &self.steps[self.current];
+        let first = !std::mem::replace(&mut self.started, true);
+        let mut changes = vec![];
+        let positions = pixel_states.positions();
+
+        for (i, ((x, y), state)) in positions.zip(pixel_states.cells_mut()).enumerate() {
+            match step.pixels.cells()[i] {
+                Some(color) => {
+                    state.step();
+
+                    if self.drawn.cells_mut()[i].replace(color) != Some(color) {
+                        changes.push((x as u16, y as u16, color));
+                    }
+                }
+                None => {
+                    // uncovered, so the pixel's own colour has to be drawn
+                    if self.drawn.cells_mut()[i].take().is_some() || first {
+                        state.redraw();
+                    }
+
+                    if let Some(color) = state.step() {
+                        changes.push((x as u16, y as u16, color));
+                    }
+                }
+            }
+        }
+
+        Some(changes)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::BTreeMap,
+        time::{Duration, Instant},
+    };
+
+    use super::{Animation, Frame, Player};
+    use crate::{
+        driver::adafruit::seesaw::neopixel::Color, grid::Grid, keyboard::PixelState, palette::Rgb,
+    };
+
+    #[test]
+    fn animations_are_found_by_name() {
+        let mine = Animation::Wipe {
+            color: Rgb([255, 0, 255]),
+            step_ms: 10,
+        };
+        let custom = BTreeMap::from([("bank".to_owned(), mine.clone())]);
+
+        assert_eq!(Animation::named("bank", &custom), Some(mine));
+        assert_eq!(Animation::named("sparkle", &custom), None);
+
+        for name in Animation::BUILTIN {
+            assert!(Animation::named(name, &BTreeMap::new()).is_some());
+        }
+    }
+
+    #[test]
+    fn flash_shows_the_pixels_in_between_and_ends() {
+        let red = Color::from_u8(255, 0, 0);
+        let white = Color::from_u8(255, 255, 255);
+        let animation = Animation::Flash {
+            color: Rgb([255, 0, 0]),
+            times: 2,
+            on_ms: 100,
+            off_ms: 100,
+        };
+
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut player = Player::new(&animation, (4, 4), start).unwrap();
+        let mut pixels = Grid::from_fn(4, 4, |_, _| PixelState::Solid {
+            color: white,
+            update: true,
+        });
+
+        let changes = player.step(at(0), &mut pixels).unwrap();
+        assert_eq!(changes.len(), 16);
+        assert!(changes.iter().all(|&(_, _, color)| color == red));
+
+        // the pixels show through between the flashes
+        let changes = player.step(at(150), &mut pixels).unwrap();
+        assert_eq!(changes[0], (0, 0, white));
+
+        assert_eq!(player.step(at(250), &mut pixels).unwrap().len(), 16);
+        assert!(player.step(at(250), &mut pixels).unwrap().is_empty());
+        assert_eq!(player.step(at(400), &mut pixels), None);
+    }
+
+    #[test]
+    fn animations_fit_the_grid() {
+        let green = Rgb([0, 255, 0]);
+        let black = Color::from_u8(0, 0, 0);
+        let start = Instant::now();
+        let mut pixels = Grid::from_fn(8, 8, |_, _| PixelState::Solid {
+            color: black,
+            update: true,
+        });
+
+        // frames only cover the corner that their rows reach
+        let frames = Animation::Frames {
+            frames: vec![Frame {
+                duration_ms: 100,
+                rows: vec![vec![green; 4]; 4],
+            }],
+            repeat: false,
+        };
+        let mut player = Player::new(&frames, (8, 8), start).unwrap();
+        let changes = player.step(start, &mut pixels).unwrap();
+        let green = green.color();
+        assert_eq!(changes.iter().filter(|c| c.2 == green).count(), 16);
+        assert!(changes
+            .iter()
+            .all(|&(x, y, color)| (color == green) == (x < 4 && y < 4)));
+
+        // generators fill the whole grid
+        let wipe = Animation::Wipe {
+            color: Rgb([255, 255, 255]),
+            step_ms: 10,
+        };
+        let player = Player::new(&wipe, (8, 8), start).unwrap();
+        assert_eq!(player.steps.len(), 8);
+    }
+}
@@ -0,0 +1,162 @@
+//! The deck view, with the tracks that can be loaded and each deck's
+//! waveform, cues and loop.
+
+use egui::{Align, Layout, RichText};
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use pidj::deck::Deck;
+use pidj::engine::{self, DeckAction, Engine, PlayState};
+
+use super::{truncate, PAD_NAME_LEN};
+
+/// Draws the two decks side by side, and returns true if the list of tracks
+/// should be refreshed.
+pub(super) fn render_decks(
+    ui: &mut egui::Ui,
+    state: &PlayState,
+    engine: &Engine,
+    tracks: &[PathBuf],
+) -> bool {
+    let refresh = ui
+        .horizontal(|ui| {
+            ui.label(RichText::new("Decks").size(10.0));
+
+            ui.with_layout(Layout::right_to_left(Align::Center), |ui| {
+                ui.small_button("↻")
+                    .on_hover_text("Look for new tracks")
+                    .clicked()
+            })
+            .inner
+        })
+        .inner;
+
+    ui.columns(2, |columns| {
+        for (deck, ui) in Deck::ALL.into_iter().zip(columns.iter_mut()) {
+            render_deck(ui, state, engine, tracks, deck);
+        }
+    });
+
+    refresh
+}
+
+fn render_deck(
+    ui: &mut egui::Ui,
+    state: &PlayState,
+    engine: &Engine,
+    tracks: &[PathBuf],
+    deck: Deck,
+) {
+    let deck_state = &state.decks[deck.index()];
+    let send = |action| engine.send(engine::Command::Deck { deck, action });
+    let stem = |path: &PathBuf| {
+        path.file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned()
+    };
+
+    ui.label(RichText::new(format!("Deck {deck}")).size(8.0));
+
+    egui::ComboBox::from_id_source(("track", deck))
+        .selected_text(truncate(
+            &deck_state
+                .track
+                .as_ref()
+                .map_or_else(|| "Empty".to_owned(), stem),
+            PAD_NAME_LEN * 2,
+        ))
+        .show_ui(ui, |ui| {
+            if tracks.is_empty() {
+                ui.label("No tracks found");
+            }
+
+            for track in tracks {
+                let selected = deck_state.track.as_ref() == Some(track);
+
+                if ui.selectable_label(selected, stem(track)).clicked() {
+                    send(DeckAction::Load(track.clone()));
+                }
+            }
+        });
+
+    if deck_state.track.is_none() {
+        return;
+    }
+
+    let position = state.deck_position(deck);
+
+    match deck_state.status.duration {
+        Some(duration) => {
+            let mut secs = position.as_secs_f32();
+            let slider =
+                egui::Slider::new(&mut secs, 0.0..=duration.as_secs_f32()).show_value(false);
+
+            if ui.add(slider).changed() {
+                send(DeckAction::Seek(Duration::from_secs_f32(secs)));
+            }
+
+            ui.label(format!(
+                "{} / {}",
+                format_time(position),
+                format_time(duration)
+            ));
+        }
+        // mp3s don't say how long they are until they have been decoded
+        None => {
+            ui.label(format!("{} / …", format_time(position)));
+        }
+    }
+
+    ui.horizontal(|ui| {
+        let play = if deck_state.status.playing {
+            "⏸"
+        } else {
+            "▶"
+        };
+
+        if ui.button(play).clicked() {
+            send(DeckAction::PlayPause);
+        }
+
+        let looping = match (deck_state.loop_region, deck_state.loop_in) {
+            (Some(_), _) => "Loop off",
+            (None, Some(_)) => "Loop out",
+            (None, None) => "Loop in",
+        };
+
+        if ui.button(looping).clicked() {
+            send(DeckAction::CycleLoop);
+        }
+    });
+
+    let mut volume = deck_state.volume;
+
+    if ui
+        .add(egui::Slider::new(&mut volume, 0.0..=1.5).text("Vol"))
+        .changed()
+    {
+        send(DeckAction::SetVolume(volume));
+    }
+
+    // clicking a cue jumps to it or sets it, and right-clicking clears it
+    ui.horizontal_wrapped(|ui| {
+        for (i, cue) in deck_state.cues.iter().enumerate() {
+            let label = cue.map_or_else(|| format!("{} +", i + 1), format_time);
+            let response = ui.button(label);
+
+            if response.clicked() {
+                send(DeckAction::Cue(i));
+            } else if response.secondary_clicked() {
+                send(DeckAction::ClearCue(i));
+            }
+        }
+    });
+}
+
+/// Formats a position in a track as minutes and seconds.
+fn format_time(time: Duration) -> String {
+    let secs = time.as_secs();
+    format!("{}:{:02}", secs / 60, secs % 60)
+}
@@ -0,0 +1,185 @@
+//! The diagnostics view, with the health of the subsystems and the
+//! latency that they add.
+
+use egui::{Label, RichText, Widget};
+
+use std::time::Duration;
+
+use pidj::engine::Snapshot;
+use pidj::latency::{self, LatencyStats, Stage, Summary};
+use pidj::logbuf::{LogBuffer, LogLine};
+
+/// How often the diagnostics view is redrawn, to pick up new log messages.
+const DIAGNOSTICS_REFRESH: Duration = Duration::from_millis(500);
+
+/// Draws what the subsystems have reported about themselves and the recent
+/// log, and returns true if the view should be closed.
+pub(super) fn render_diagnostics(
+    ui: &mut egui::Ui,
+    snapshot: &Snapshot,
+    jitter: &Summary,
+    log: &LogBuffer,
+) -> bool {
+    ui.ctx().request_repaint_after(DIAGNOSTICS_REFRESH);
+
+    let closed = ui
+        .horizontal(|ui| {
+            let closed = ui.button("Back").clicked();
+            ui.label(RichText::new("Diagnostics").size(10.0));
+            closed
+        })
+        .inner;
+
+    let info = &snapshot.diagnostics;
+    let unknown = || "?".to_owned();
+
+    let rows = [
+        (
+            "Seesaw",
+            info.seesaw_version
+                .map_or_else(unknown, |version| format!("{version:#010x}")),
+        ),
+        (
+            "Seesaw address",
+            info.seesaw_address
+                .map_or_else(unknown, |address| format!("{address:#04x}")),
+        ),
+        (
+            "Seesaw temp",
+            info.seesaw_temperature.map_or_else(unknown, |temperature| {
+                let hot = if info.seesaw_hot { " (hot)" } else { "" };
+                format!("{temperature}°C{hot}")
+            }),
+        ),
+        (
+            "Audio device",
+            info.audio_device
+                .clone()
+                .unwrap_or_else(|| "none".to_owned()),
+        ),
+        ("Voices", info.voices.to_string()),
+        ("Duplicates", info.duplicates.to_string()),
+        (
+            "Audio period",
+            info.period_frames
+                .map_or_else(unknown, |frames| format!("{frames} frames")),
+        ),
+        ("Underruns", info.underruns.to_string()),
+        (
+            "CPU temp",
+            snapshot
+                .health
+                .temperature
+                .map_or_else(unknown, |temperature| format!("{temperature:.1}°C")),
+        ),
+        (
+            "Throttling",
+            snapshot.health.throttled.map_or_else(unknown, |throttled| {
+                let since_boot = if throttled.under_voltage_occurred() {
+                    ", under-voltage since boot"
+                } else {
+                    ""
+                };
+                format!("{:#x}{since_boot}", throttled.0)
+            }),
+        ),
+        (
+            "Tick jitter",
+            format!(
+                "p50 {:.1} p95 {:.1} max {:.1} ms",
+                jitter.p50.as_secs_f32() * 1000.,
+                jitter.p95.as_secs_f32() * 1000.,
+                jitter.max.as_secs_f32() * 1000.,
+            ),
+        ),
+    ];
+
+    egui::Grid::new("diagnostics").show(ui, |ui| {
+        for (name, value) in rows {
+            ui.label(RichText::new(name).size(6.0));
+            ui.label(RichText::new(value).size(6.0));
+            ui.end_row();
+        }
+    });
+
+    render_latency(ui, &snapshot.latency);
+
+    egui::ScrollArea::vertical()
+        .auto_shrink([false, false])
+        .stick_to_bottom(true)
+        .show(ui, |ui| {
+            for LogLine {
+                level,
+                target,
+                text,
+            } in log.lines()
+            {
+                let color = match level {
+                    tracing::Level::ERROR => egui::Color32::RED,
+                    tracing::Level::WARN => egui::Color32::YELLOW,
+                    _ => ui.visuals().text_color(),
+                };
+
+                Label::new(
+                    RichText::new(format!("{level} {target}: {text}"))
+                        .color(color)
+                        .size(5.0)
+                        .monospace(),
+                )
+                .wrap(true)
+                .ui(ui);
+            }
+        });
+
+    closed
+}
+
+pub(super) fn render_latency(ui: &mut egui::Ui, stats: &LatencyStats) {
+    let rows = Stage::ALL
+        .into_iter()
+        .map(|stage| (stage.to_string(), stats.stage(stage)))
+        .chain([("total".to_owned(), stats.total())]);
+
+    egui::Grid::new("latency").show(ui, |ui| {
+        for (name, summary) in rows {
+            ui.label(RichText::new(name).size(6.0).monospace());
+            ui.label(
+                RichText::new(format!(
+                    "p50 {:>5.1} p95 {:>5.1} max {:>5.1}",
+                    summary.p50.as_secs_f32() * 1000.,
+                    summary.p95.as_secs_f32() * 1000.,
+                    summary.max.as_secs_f32() * 1000.,
+                ))
+                .size(6.0)
+                .monospace(),
+            );
+            ui.label(
+                RichText::new(histogram_bars(&summary))
+                    .size(6.0)
+                    .monospace(),
+            );
+            ui.end_row();
+        }
+    });
+}
+
+/// Draws a histogram as a row of block characters, one per bucket, scaled to
+/// the fullest bucket.
+fn histogram_bars(summary: &Summary) -> String {
+    const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+    let max = summary.histogram.iter().copied().max().unwrap_or(0);
+
+    if max == 0 {
+        return " ".repeat(latency::BUCKETS.len() + 1);
+    }
+
+    summary
+        .histogram
+        .iter()
+        .map(|&count| match count {
+            0 => ' ',
+            count => BARS[(count * (BARS.len() - 1)).div_ceil(max)],
+        })
+        .collect()
+}
@@ -0,0 +1,146 @@
+//! The looper's controls: the arpeggiator, grooves and tempo ramps.
+
+use egui::{RichText, Widget};
+
+use pidj::config::GpioAction;
+use pidj::engine::{self, Arp, ArpPattern, Engine, PlayState, TempoRamp};
+
+/// Shows a button that turns the arpeggiator on or off and, while it is on, its
+/// pattern and speed, which can be changed by clicking on them.
+pub(super) fn render_arp(ui: &mut egui::Ui, arp: &Arp, engine: &Engine) {
+    let toggle = if arp.on { "ARP" } else { "Arp" };
+
+    if ui.small_button(toggle).clicked() {
+        engine.send(engine::Command::Action(GpioAction::ToggleArp));
+    }
+
+    if !arp.on {
+        return;
+    }
+
+    let pattern = match arp.pattern {
+        ArpPattern::Up => "↑",
+        ArpPattern::Down => "↓",
+        ArpPattern::Random => "?",
+    };
+    let rate = match arp.every {
+        60 => "1/4",
+        30 => "1/8",
+        20 => "1/8T",
+        15 => "1/16",
+        _ => "",
+    };
+
+    if ui.small_button(format!("{pattern} {rate}")).clicked() {
+        // steps through the speeds, then the patterns
+        let i = engine::ARP_RATES.iter().position(|&r| r == arp.every);
+        let (pattern, every) = match i.map(|i| engine::ARP_RATES.get(i + 1)) {
+            Some(Some(&every)) => (arp.pattern, every),
+            _ => (
+                match arp.pattern {
+                    ArpPattern::Up => ArpPattern::Down,
+                    ArpPattern::Down => ArpPattern::Random,
+                    ArpPattern::Random => ArpPattern::Up,
+                },
+                engine::ARP_RATES[0],
+            ),
+        };
+
+        engine.send(engine::Command::SetArp { pattern, every });
+    }
+}
+
+/// Shows a menu of the groove templates, to put one on the quantized loops or
+/// to record a new one from the pads under the name in `name`.
+pub(super) fn render_groove(
+    ui: &mut egui::Ui,
+    state: &PlayState,
+    name: &mut String,
+    engine: &Engine,
+) {
+    let label = match (&state.groove_recorder, state.groove) {
+        (Some(_), _) => "GRV ●".to_owned(),
+        (None, Some(i)) => format!("GRV {}", state.grooves[i].name),
+        (None, None) => "Groove".to_owned(),
+    };
+
+    ui.menu_button(label, |ui| {
+        if let Some(recorder) = &state.groove_recorder {
+            ui.label(format!("{} presses", recorder.presses()));
+            ui.text_edit_singleline(name);
+
+            ui.horizontal(|ui| {
+                if ui
+                    .add_enabled(!name.trim().is_empty(), egui::Button::new("Save"))
+                    .clicked()
+                {
+                    engine.send(engine::Command::SaveGroove(name.trim().to_owned()));
+                    ui.close_menu();
+                }
+
+                if ui.button("Discard").clicked() {
+                    engine.send(engine::Command::DiscardGroove);
+                    ui.close_menu();
+                }
+            });
+
+            return;
+        }
+
+        if ui
+            .button("Record")
+            .on_hover_text("Play the pads without quantization to record their timing")
+            .clicked()
+        {
+            engine.send(engine::Command::RecordGroove);
+        }
+
+        ui.separator();
+
+        if ui.selectable_label(state.groove.is_none(), "Off").clicked() {
+            engine.send(engine::Command::SetGroove(None));
+        }
+
+        for (i, groove) in state.grooves.iter().enumerate() {
+            ui.horizontal(|ui| {
+                if ui
+                    .selectable_label(state.groove == Some(i), groove.name.as_str())
+                    .clicked()
+                {
+                    engine.send(engine::Command::SetGroove(Some(i)));
+                }
+
+                if ui.small_button("✕").clicked() {
+                    engine.send(engine::Command::DeleteGroove(i));
+                }
+            });
+        }
+    });
+}
+
+/// Shows where the tempo is ramping to, or a menu to start a ramp to the BPM
+/// in `target` over its number of bars.
+pub(super) fn render_tempo_ramp(
+    ui: &mut egui::Ui,
+    ramp: &Option<TempoRamp>,
+    target: &mut (f32, usize),
+    engine: &Engine,
+) {
+    if let Some(ramp) = ramp {
+        ui.label(RichText::new(format!("→ {:.0}", ramp.to)).size(8.0));
+    }
+
+    ui.menu_button("Ramp", |ui| {
+        let (bpm, bars) = target;
+        egui::Slider::new(bpm, 20.0..=300.0).text("BPM").ui(ui);
+        egui::Slider::new(bars, 0..=16).text("Bars").ui(ui);
+
+        if ui.button("Go").clicked() {
+            engine.send(engine::Command::RampTempo {
+                bpm: *bpm,
+                bars: *bars,
+            });
+            ui.close_menu();
+        }
+    });
+}
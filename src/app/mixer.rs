@@ -0,0 +1,195 @@
+//! The mixer: the scenes, EQ kills and crossfader, and the level meter of
+//! the master mix.
+
+use egui::{RichText, Sense, Vec2};
+
+use pidj::audio;
+use pidj::engine::{self, Engine, PlayState};
+use pidj::eq::Band;
+use pidj::scene::Slot;
+use pidj::tape::TapeSplit;
+
+/// Kill switches for the EQ of each bus, laid out like the keyboard in mixer
+/// mode.
+pub(super) fn render_mixer(ui: &mut egui::Ui, state: &PlayState, engine: &Engine) {
+    ui.label(RichText::new("Mixer").size(10.0));
+
+    ui.columns(2, |columns| {
+        for (bus, ui) in [audio::Bus::A, audio::Bus::B]
+            .into_iter()
+            .zip(columns.iter_mut())
+        {
+            let kills = state.eq[bus.index()];
+
+            ui.label(RichText::new(format!("Bus {bus:?}")).size(8.0));
+
+            for (band, name) in [(Band::High, "High"), (Band::Mid, "Mid"), (Band::Low, "Low")] {
+                let killed = kills.is_killed(band);
+
+                if ui
+                    .selectable_label(!killed, RichText::new(name).size(8.0))
+                    .on_hover_text("Kill or bring back the band")
+                    .clicked()
+                {
+                    engine.send(engine::Command::ToggleKill { bus, band });
+                }
+            }
+        }
+    });
+
+    for (slot, name) in [(Slot::A, "A"), (Slot::B, "B")] {
+        let kept = state.scenes[slot.index()].is_some();
+        let morphing = state.morph.as_ref().is_some_and(|morph| morph.to == slot);
+
+        ui.horizontal(|ui| {
+            ui.label(RichText::new(format!("Scene {name}")).size(8.0));
+
+            if ui.small_button("Save").clicked() {
+                engine.send(engine::Command::SaveScene(slot));
+            }
+
+            if ui
+                .add_enabled(kept, egui::Button::new("Go").small())
+                .clicked()
+            {
+                engine.send(engine::Command::RecallScene { slot, bars: 0 });
+            }
+
+            let morph = if morphing { "Morphing" } else { "Morph" };
+
+            if ui
+                .add_enabled(kept, egui::Button::new(morph).small())
+                .on_hover_text("Morph to the scene over a bar")
+                .clicked()
+            {
+                engine.send(engine::Command::RecallScene { slot, bars: 1 });
+            }
+
+            if ui
+                .add_enabled(kept, egui::Button::new("Clear").small())
+                .clicked()
+            {
+                engine.send(engine::Command::ClearScene(slot));
+            }
+        });
+    }
+
+    // taping the master mix to disk, which can start a new file for each
+    // pattern or each time the looper starts
+    ui.horizontal(|ui| {
+        let label = if state.tape.is_some() {
+            "Stop tape"
+        } else {
+            "Tape"
+        };
+
+        if ui
+            .small_button(label)
+            .on_hover_text("Record the master mix to Recordings/Tapes")
+            .clicked()
+        {
+            engine.send(engine::Command::ToggleTape);
+        }
+
+        ui.label(RichText::new("New file").size(6.0));
+
+        let splits = [
+            (TapeSplit::Never, "never"),
+            (TapeSplit::Pattern, "per pattern"),
+            (TapeSplit::Transport, "per start"),
+        ];
+        let selected = splits
+            .iter()
+            .find(|(split, _)| *split == state.tape_split)
+            .map_or("", |(_, name)| name);
+
+        egui::ComboBox::from_id_source("tape split")
+            .selected_text(RichText::new(selected).size(6.0))
+            .show_ui(ui, |ui| {
+                let mut tape_split = state.tape_split;
+
+                for (split, name) in splits {
+                    if ui.selectable_value(&mut tape_split, split, name).changed() {
+                        engine.send(engine::Command::SetTapeSplit(tape_split));
+                    }
+                }
+            });
+    });
+
+    if ui
+        .add_enabled(
+            !state.loops.is_empty() && state.render.is_none(),
+            egui::Button::new("Export stems").small(),
+        )
+        .on_hover_text("Write the loops on each bus to Recordings")
+        .clicked()
+    {
+        engine.send(engine::Command::ExportStems);
+    }
+}
+
+/// Draws the crossfader between bus A (the left pads and deck A) and bus B.
+pub(super) fn render_crossfader(ui: &mut egui::Ui, crossfade: f32, engine: &Engine) {
+    ui.spacing_mut().slider_width = 40.0;
+
+    // the layout is right to left, so B comes first
+    ui.label(RichText::new("B").size(6.0));
+
+    let mut position = crossfade;
+
+    if ui
+        .add(egui::Slider::new(&mut position, 0.0..=1.0).show_value(false))
+        .changed()
+    {
+        engine.send(engine::Command::SetCrossfade(position));
+    }
+
+    ui.label(RichText::new("A").size(6.0));
+}
+
+/// Bottom of the level meter's scale, in dBFS.
+const METER_FLOOR_DB: f32 = -60.;
+
+/// How many dB of gain reduction fill the compressor's meter.
+const REDUCTION_RANGE_DB: f32 = 20.;
+
+/// Draws the level of the master mix as a bar that is filled up to the RMS
+/// level, with a line at the peak level. It turns red if the mix clips. The
+/// compressor's gain reduction is drawn down from the right across the top.
+pub(super) fn render_level(ui: &mut egui::Ui, level: &audio::Level) {
+    let (rect, _) = ui.allocate_exact_size(Vec2::new(40., 6.), Sense::hover());
+
+    // position of an amplitude on the meter, between 0 and 1
+    let position = |amplitude: f32| {
+        let db = 20. * amplitude.max(f32::EPSILON).log10();
+        ((db - METER_FLOOR_DB) / -METER_FLOOR_DB).clamp(0., 1.)
+    };
+
+    let color = if level.peak >= 1. {
+        egui::Color32::RED
+    } else {
+        egui::Color32::from_rgb(0, 200, 0)
+    };
+
+    let painter = ui.painter();
+    painter.rect_filled(rect, 0., egui::Color32::from_gray(30));
+
+    let mut rms = rect;
+    rms.set_width(rect.width() * position(level.rms));
+    painter.rect_filled(rms, 0., color);
+
+    painter.vline(
+        rect.left() + rect.width() * position(level.peak),
+        rect.y_range(),
+        egui::Stroke::new(1., color),
+    );
+
+    if level.gain_reduction > 0. {
+        let mut reduction = rect;
+        reduction.set_height(rect.height() / 3.);
+        reduction.set_left(
+            rect.right() - rect.width() * (level.gain_reduction / REDUCTION_RANGE_DB).clamp(0., 1.),
+        );
+        painter.rect_filled(reduction, 0., egui::Color32::from_rgb(255, 165, 0));
+    }
+}
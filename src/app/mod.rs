@@ -0,0 +1,963 @@
+use egui::{Align, Key, Label, Layout, RichText, Vec2, Widget};
+
+use std::collections::{BTreeSet, VecDeque};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::spawn;
+use tokio_util::sync::CancellationToken;
+use tracing::debug;
+
+use pidj::bus::Bus;
+use pidj::clock::Clock;
+use pidj::config::{self, Config, GpioAction, Rotation};
+use pidj::deck;
+use pidj::driver::adafruit::seesaw::neopixel::Color;
+use pidj::engine::{
+    self, AppState, Engine, Fault, KeyHint, LoadingStage, LoadingState, PlayState, Problem,
+    Snapshot, Toast,
+};
+use pidj::game;
+use pidj::logbuf::LogBuffer;
+use pidj::persist;
+use pidj::setlist::SetEntry;
+
+use crate::rotate::Rotated;
+use crate::theme;
+
+mod decks;
+mod diagnostics;
+mod looper;
+mod mixer;
+mod pads;
+mod reassign;
+mod settings;
+
+use decks::render_decks;
+use diagnostics::{render_diagnostics, render_latency};
+use looper::{render_arp, render_groove, render_tempo_ramp};
+use mixer::{render_crossfader, render_level, render_mixer};
+use pads::render_pad_detail;
+use reassign::{render_reassign, BrowserScroll, SearchInput};
+use settings::{render_settings, SettingsExit, SettingsForm};
+
+struct App {
+    engine: Engine,
+    snapshot: tokio::sync::watch::Receiver<Snapshot>,
+    cancel: CancellationToken,
+    diagnostics: bool,
+
+    /// the on-screen pad that the pointer is held down on
+    touched: Option<(usize, usize)>,
+
+    search: SearchInput,
+    browser: BrowserScroll,
+
+    /// the config that was loaded at startup, or last saved
+    config: Config,
+
+    /// open if the settings view is shown
+    settings: Option<SettingsForm>,
+
+    /// display scale in use, which themes are zoomed relative to
+    scale: f32,
+
+    /// whether the diagnostics view is shown
+    diagnostics_open: bool,
+    log: LogBuffer,
+
+    /// keys on the grid that are held down on the computer keyboard
+    held_keys: BTreeSet<(usize, usize)>,
+
+    /// BPM and number of bars in the tempo ramp menu
+    ramp: (f32, usize),
+
+    /// name that the groove being recorded is kept under
+    groove_name: String,
+
+    /// tracks that can be loaded onto the decks, listed when deck mode is
+    /// first shown
+    tracks: Option<Vec<PathBuf>>,
+}
+
+pub fn run(
+    ct: tokio_util::sync::CancellationToken,
+    clock: Arc<dyn Clock>,
+    config: Config,
+    display: config::DisplayConfig,
+    diagnostics: bool,
+    log: LogBuffer,
+    bus: Bus,
+) -> Result<(), anyhow::Error> {
+    let options = eframe::NativeOptions {
+        always_on_top: !display.windowed,
+        fullscreen: !display.windowed,
+        initial_window_size: display.size.map(Vec2::from),
+        min_window_size: None,
+        ..Default::default()
+    };
+
+    let engine = Engine::start(
+        ct.clone(),
+        clock,
+        bus,
+        config.play.clone(),
+        &config.keyboard,
+        PathBuf::from(persist::STATE_PATH),
+    );
+
+    let (ctx_tx, mut ctx_rx) = tokio::sync::watch::channel(None);
+
+    spawn({
+        let ct = ct.clone();
+        let mut changed = engine.snapshot();
+        async move {
+            // wait for the ui to start before trying to repaint it
+            if ctx_rx.changed().await.is_err() {
+                return;
+            }
+
+            let ctx: egui::Context = ctx_rx.borrow().clone().unwrap();
+
+            loop {
+                tokio::select! {
+                    // request a repaint after cancellation so that the
+                    // application calls eframe::App::update() and exits
+                    _ = ct.cancelled() => {
+                        ctx.request_repaint();
+                        break;
+                    }
+                    res = changed.changed() => {
+                        if res.is_err() {
+                            break;
+                        }
+
+                        ctx.request_repaint();
+                    }
+                }
+            }
+        }
+    });
+
+    eframe::run_native(
+        "PI DJ",
+        options,
+        Box::new(move |cc| {
+            let _ = ctx_tx.send(Some(cc.egui_ctx.clone()));
+
+            let app = App {
+                snapshot: engine.snapshot(),
+                engine,
+                cancel: ct,
+                diagnostics,
+                touched: None,
+                search: SearchInput::default(),
+                browser: BrowserScroll::default(),
+                config,
+                settings: None,
+                scale: display.scale,
+                diagnostics_open: false,
+                log,
+                held_keys: BTreeSet::new(),
+                ramp: (120., 4),
+                groove_name: String::new(),
+                tracks: None,
+            };
+
+            match display.rotation {
+                Rotation::None => {
+                    theme::apply(&cc.egui_ctx, display.theme, display.scale);
+                    Box::new(app)
+                }
+                rotation => {
+                    let rotated = Rotated::new(app, rotation);
+                    theme::apply(rotated.ctx(), display.theme, display.scale);
+                    Box::new(rotated)
+                }
+            }
+        }),
+    );
+
+    Ok(())
+}
+
+impl eframe::App for App {
+    fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
+        if self.cancel.is_cancelled() {
+            debug!("cancelled, exiting app");
+            frame.close();
+            return;
+        }
+
+        // clone the snapshot so that the engine can publish the next one while
+        // this one is being rendered
+        let snapshot = self.snapshot.borrow().clone();
+
+        if !snapshot.faults.is_empty() {
+            egui::TopBottomPanel::top("faults").show(ctx, |ui| {
+                for fault in snapshot.faults.values() {
+                    let text = match fault {
+                        Fault::Degraded { subsystem, error } => {
+                            format!("{subsystem} degraded: {error}")
+                        }
+                        Fault::Restarting {
+                            subsystem,
+                            attempt,
+                            error,
+                        } => format!("{subsystem} failed, restarting ({attempt}): {error}"),
+                        Fault::Failed { subsystem, error } => {
+                            format!("{subsystem} stopped: {error}")
+                        }
+                    };
+
+                    Label::new(RichText::new(text).color(egui::Color32::RED).size(6.0))
+                        .wrap(true)
+                        .ui(ui);
+                }
+            });
+        }
+
+        render_toasts(ctx, &snapshot.toasts);
+
+        if self.diagnostics {
+            egui::TopBottomPanel::top("latency").show(ctx, |ui| {
+                render_latency(ui, &snapshot.latency);
+            });
+        }
+
+        if self.diagnostics_open {
+            let closed = egui::CentralPanel::default()
+                .show(ctx, |ui| {
+                    render_diagnostics(ui, &snapshot, &self.engine.tick_jitter(), &self.log)
+                })
+                .inner;
+
+            if closed {
+                self.diagnostics_open = false;
+            }
+
+            return;
+        }
+
+        if let Some(form) = &mut self.settings {
+            let exit = egui::CentralPanel::default()
+                .show(ctx, |ui| {
+                    render_settings(ui, form, &mut self.config, &self.engine, self.scale)
+                })
+                .inner;
+
+            match exit {
+                SettingsExit::Stay => {}
+                SettingsExit::Back => {
+                    // undo a theme that was tried out but not saved
+                    theme::apply(ctx, self.config.display.theme, self.scale);
+                    self.settings = None;
+                }
+                SettingsExit::Diagnostics => self.diagnostics_open = true,
+            }
+
+            return;
+        }
+
+        match &snapshot.state {
+            AppState::Loading(LoadingState {
+                stage: LoadingStage::Failed { path, error },
+                ..
+            }) => {
+                egui::CentralPanel::default().show(ctx, |ui| {
+                    ui.label(
+                        RichText::new("Couldn't load sounds")
+                            .color(egui::Color32::RED)
+                            .size(10.0),
+                    );
+                    Label::new(RichText::new(format!("from {}", path.display())).size(6.0))
+                        .wrap(true)
+                        .ui(ui);
+                    Label::new(RichText::new(error).size(6.0)).wrap(true).ui(ui);
+
+                    ui.add_space(4.0);
+
+                    ui.horizontal(|ui| {
+                        if ui.button("Retry").clicked() {
+                            self.engine.send(engine::Command::RetryLoading);
+                        }
+
+                        if ui.button("⚙ Settings").clicked() {
+                            self.settings = Some(SettingsForm::new(&self.config, &snapshot));
+                        }
+                    });
+                });
+            }
+
+            AppState::Loading(LoadingState {
+                stage:
+                    LoadingStage::BufferingAudio {
+                        progress,
+                        num_files,
+                        current,
+                        started,
+                    },
+                ..
+            }) => {
+                egui::CentralPanel::default().show(ctx, |ui| {
+                    ui.label(RichText::new("Loading sounds").size(10.0));
+
+                    egui::ProgressBar::new(*progress as f32 / *num_files as f32)
+                        .text(format!("{progress}/{num_files}"))
+                        .ui(ui);
+
+                    let name = current.file_name().unwrap_or_default().to_string_lossy();
+                    Label::new(RichText::new(name).size(6.0)).wrap(false).ui(ui);
+
+                    // assume that the rest of the files take as long as the
+                    // ones so far
+                    if *progress > 0 {
+                        let remaining = started
+                            .elapsed()
+                            .mul_f64((num_files - progress) as f64 / *progress as f64);
+                        ui.label(
+                            RichText::new(format!("about {}s left", remaining.as_secs() + 1))
+                                .size(6.0),
+                        );
+                    }
+                });
+            }
+
+            AppState::Loading(_) => {
+                egui::CentralPanel::default().show(ctx, |ui| {
+                    ui.with_layout(
+                        Layout::centered_and_justified(egui::Direction::TopDown)
+                            .with_main_justify(false)
+                            .with_cross_justify(false),
+                        |ui| {
+                            ui.group(|ui| {
+                                Label::new("Loading").wrap(false).ui(ui);
+                                ui.spinner();
+                            });
+                        },
+                    )
+                });
+            }
+
+            AppState::Play(state) => {
+                egui::TopBottomPanel::bottom("bpm/div").show(ctx, |ui| {
+                    ui.with_layout(Layout::left_to_right(Align::Max), |ui| {
+                        ui.label(
+                            RichText::new(match state.loop_divider {
+                                Some(div) => {
+                                    if div > 0 {
+                                        format!("DIV = 1/{}", div)
+                                    } else if div == 0 {
+                                        "AUTODIV".to_string()
+                                    } else {
+                                        format!("DIV = {}", -div)
+                                    }
+                                }
+                                None => "NODIV".to_string(),
+                            })
+                            .size(8.0),
+                        );
+
+                        ui.add_space(4.0);
+
+                        let bpm = 1. / state.tick.as_secs_f32();
+                        ui.label(RichText::new(format!("BPM = {bpm:.1}")).size(8.0));
+                        render_tempo_ramp(ui, &state.tempo_ramp, &mut self.ramp, &self.engine);
+
+                        if state.quantize {
+                            ui.add_space(4.0);
+                            ui.label(RichText::new("Q").size(8.0));
+                        }
+
+                        if !state.running {
+                            ui.add_space(4.0);
+                            ui.label(RichText::new("STOP").size(8.0));
+                        }
+
+                        ui.add_space(4.0);
+                        render_arp(ui, &state.arp, &self.engine);
+                        render_groove(ui, state, &mut self.groove_name, &self.engine);
+
+                        if let Some(bounce) = &state.bounce {
+                            let label = if bounce.recording { "REC" } else { "ARMED" };
+                            ui.add_space(4.0);
+                            ui.label(RichText::new(label).size(8.0).color(egui::Color32::RED));
+                        }
+
+                        if let Some(tape) = &state.tape {
+                            ui.add_space(4.0);
+                            ui.label(
+                                RichText::new(format!("TAPE {}", tape.takes))
+                                    .size(8.0)
+                                    .color(egui::Color32::RED),
+                            );
+                        }
+
+                        if let Some(render) = &state.render {
+                            ui.add_space(4.0);
+                            ui.label(
+                                RichText::new(format!("RENDER {:.0}%", render.progress * 100.))
+                                    .size(8.0),
+                            );
+
+                            if ui
+                                .small_button("✕")
+                                .on_hover_text("Cancel the render")
+                                .clicked()
+                            {
+                                self.engine.send(engine::Command::CancelRender);
+                            }
+                        }
+
+                        ui.with_layout(Layout::right_to_left(Align::Max), |ui| {
+                            if ui.small_button("⚙").clicked() {
+                                self.settings = Some(SettingsForm::new(&self.config, &snapshot));
+                            }
+
+                            // edits to the pads, not the loops
+                            let history = &state.pad_history;
+
+                            if ui
+                                .add_enabled(history.can_redo(), egui::Button::new("Redo").small())
+                                .clicked()
+                            {
+                                self.engine.send(engine::Command::RedoPadEdit);
+                            }
+
+                            if ui
+                                .add_enabled(history.can_undo(), egui::Button::new("Undo").small())
+                                .clicked()
+                            {
+                                self.engine.send(engine::Command::UndoPadEdit);
+                            }
+
+                            let mode = if state.deck_mode { "Pads" } else { "Decks" };
+
+                            if ui.small_button(mode).clicked() {
+                                self.engine
+                                    .send(engine::Command::ShowDecks(!state.deck_mode));
+                            }
+
+                            let mode = if state.mixer_mode { "Pads" } else { "Mixer" };
+
+                            if ui.small_button(mode).clicked() {
+                                self.engine
+                                    .send(engine::Command::ShowMixer(!state.mixer_mode));
+                            }
+
+                            // the effects are played on the pads, so they
+                            // stay on screen
+                            let mode = if state.fx_mode { "Pads" } else { "FX" };
+
+                            if ui.small_button(mode).clicked() {
+                                self.engine.send(engine::Command::ShowFx(!state.fx_mode));
+                            }
+
+                            render_game(ui, state, &self.engine);
+
+                            // the halves of the grid each have their own
+                            // bank of pads
+                            let mode = if state.split_mode { "Whole" } else { "Split" };
+
+                            if ui.small_button(mode).clicked() {
+                                self.engine
+                                    .send(engine::Command::ShowSplit(!state.split_mode));
+                            }
+
+                            if state.split_mode {
+                                let [a, b] = state.banks.current;
+                                ui.label(
+                                    RichText::new(format!("Banks {} | {}", a + 1, b + 1)).size(8.0),
+                                );
+                            }
+
+                            ui.add_space(4.0);
+                            render_crossfader(ui, state.crossfade, &self.engine);
+
+                            ui.add_space(4.0);
+                            render_level(ui, &snapshot.level);
+
+                            // the pi's power supply and temperature affect
+                            // the audio, so problems stay on screen, as does
+                            // a NeoTrellis cooking in its box
+                            let mut warnings = snapshot.health.warnings();
+
+                            if snapshot.diagnostics.seesaw_hot {
+                                warnings.push("NeoTrellis hot");
+                            }
+
+                            if !warnings.is_empty() {
+                                ui.add_space(4.0);
+                                ui.label(
+                                    RichText::new(format!("⚠ {}", warnings.join(", ")))
+                                        .color(egui::Color32::RED)
+                                        .size(6.0),
+                                );
+                            }
+                        });
+                    });
+                });
+
+                if !state.set_list.entries.is_empty() && !state.deck_mode {
+                    egui::TopBottomPanel::bottom("set list").show(ctx, |ui| {
+                        render_set_list(ui, state, &self.engine);
+                    });
+                }
+
+                let held = egui::CentralPanel::default()
+                    .show(ctx, |ui| {
+                        if state.deck_mode {
+                            let tracks = self.tracks.get_or_insert_with(|| {
+                                deck::list_tracks(&self.config.audio.tracks_dir)
+                            });
+
+                            if render_decks(ui, state, &self.engine, tracks) {
+                                self.tracks = None;
+                            }
+
+                            return None;
+                        }
+
+                        if state.mixer_mode {
+                            render_mixer(ui, state, &self.engine);
+                            return None;
+                        }
+
+                        if state.reassign.is_some() {
+                            render_reassign(
+                                ui,
+                                state,
+                                &self.engine,
+                                &mut self.search,
+                                &mut self.browser,
+                            );
+                            return None;
+                        }
+
+                        // the next time the browser opens, it starts without a
+                        // search, at the top
+                        self.search = SearchInput::default();
+                        self.browser = BrowserScroll::default();
+
+                        if let Some(pad) = state.detail {
+                            // the settings don't all fit on the small screen
+                            egui::ScrollArea::vertical()
+                                .show(ui, |ui| render_pad_detail(ui, state, &self.engine, pad));
+                            return None;
+                        }
+
+                        render_pads(ui, state)
+                    })
+                    .inner;
+
+                if state.help {
+                    render_key_help(ctx, &state.key_help());
+                }
+
+                self.touch_pad(held);
+                self.keyboard_shortcuts(ctx);
+            }
+        }
+
+        // ctx.request_repaint();
+    }
+}
+
+impl App {
+    /// Presses and releases the on-screen pads as the pointer is held down on
+    /// them, so that the screen works like the keyboard.
+    fn touch_pad(&mut self, held: Option<(usize, usize)>) {
+        if held == self.touched {
+            return;
+        }
+
+        if let Some((x, y)) = self.touched {
+            self.engine.send(engine::Command::Key {
+                x,
+                y,
+                pressed: false,
+            });
+        }
+
+        if let Some((x, y)) = held {
+            self.engine.send(engine::Command::Key {
+                x,
+                y,
+                pressed: true,
+            });
+        }
+
+        self.touched = held;
+    }
+
+    /// Lets a computer keyboard stand in for the NeoTrellis: the grid keys
+    /// press the keys on the grid, space stops the looper or starts it from
+    /// the top, and +/- change the BPM.
+    fn keyboard_shortcuts(&mut self, ctx: &egui::Context) {
+        // typing into the search field shouldn't play sounds
+        if ctx.wants_keyboard_input() {
+            return;
+        }
+
+        let events = ctx.input().events.clone();
+
+        for event in events {
+            match event {
+                egui::Event::Key {
+                    key: Key::Space,
+                    pressed: true,
+                    ..
+                } => self
+                    .engine
+                    .send(engine::Command::Action(GpioAction::StartStop)),
+                egui::Event::Key { key, pressed, .. } => {
+                    let Some((x, y)) = grid_key(key) else {
+                        continue;
+                    };
+
+                    // held keys repeat, but the grid shouldn't
+                    let changed = if pressed {
+                        self.held_keys.insert((x, y))
+                    } else {
+                        self.held_keys.remove(&(x, y))
+                    };
+
+                    if changed {
+                        self.engine.send(engine::Command::Key { x, y, pressed });
+                    }
+                }
+                egui::Event::Text(text) => match text.as_str() {
+                    "+" | "=" => self.engine.send(engine::Command::Action(GpioAction::BpmUp)),
+                    "-" => self
+                        .engine
+                        .send(engine::Command::Action(GpioAction::BpmDown)),
+                    _ => {}
+                },
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Keys on a computer keyboard that stand in for the grid, by row. They
+/// cover the top four rows of a grid up to eight keys wide, and keys that
+/// are off the grid are ignored by the engine. The rest of a bigger grid can
+/// only be played on the screen.
+const GRID_KEYS: [&[Key]; 4] = [
+    &[
+        Key::Num1,
+        Key::Num2,
+        Key::Num3,
+        Key::Num4,
+        Key::Num5,
+        Key::Num6,
+        Key::Num7,
+        Key::Num8,
+    ],
+    &[
+        Key::Q,
+        Key::W,
+        Key::E,
+        Key::R,
+        Key::T,
+        Key::Y,
+        Key::U,
+        Key::I,
+    ],
+    &[
+        Key::A,
+        Key::S,
+        Key::D,
+        Key::F,
+        Key::G,
+        Key::H,
+        Key::J,
+        Key::K,
+    ],
+    &[Key::Z, Key::X, Key::C, Key::V, Key::B, Key::N, Key::M],
+];
+
+/// Position on the grid that a key on a computer keyboard stands in for.
+fn grid_key(key: Key) -> Option<(usize, usize)> {
+    GRID_KEYS
+        .iter()
+        .enumerate()
+        .find_map(|(y, row)| Some((row.iter().position(|k| *k == key)?, y)))
+}
+
+/// Draws the keys as a grid of buttons that mirrors the keyboard, and returns
+/// the key that the pointer is held down on.
+fn render_pads(ui: &mut egui::Ui, state: &PlayState) -> Option<(usize, usize)> {
+    // the Fn keys are the top row
+    let columns = state.sound_keys.width();
+    let rows = state.sound_keys.height() + 1;
+
+    let spacing = ui.spacing().item_spacing;
+    let size = Vec2::new(
+        (ui.available_width() - spacing.x * (columns - 1) as f32) / columns as f32,
+        (ui.available_height() - spacing.y * (rows - 1) as f32) / rows as f32,
+    );
+
+    let mut held = None;
+    let mut any_playing = false;
+
+    egui::Grid::new("free_play").show(ui, |ui| {
+        for y in 0..rows {
+            for x in 0..columns {
+                let (text, pressed, fill) = if y == 0 {
+                    match state.fn_keys.get(x) {
+                        Some(key) => (format!("F{}", x), key.pressed, None),
+                        None => (String::new(), false, None),
+                    }
+                } else {
+                    let key = &state.sound_keys[y - 1][x];
+                    let sound = key.binding.and_then(|id| state.sounds.get(id.0));
+
+                    let text = match (sound, &key.macro_binding) {
+                        (Some(sound), _) => {
+                            let name = truncate(&sound.name(), PAD_NAME_LEN);
+
+                            if state.is_playing(sound.id) {
+                                any_playing = true;
+                                format!("▶ {name}")
+                            } else if state.is_unavailable(sound.id) {
+                                format!("✖ {name}")
+                            } else if !sound.ready {
+                                format!("… {name}")
+                            } else {
+                                name
+                            }
+                        }
+                        (None, Some(m)) => format!("⚡ {}", truncate(&m.name, PAD_NAME_LEN)),
+                        (None, None) => String::new(),
+                    };
+
+                    let Color { r, g, b, .. } = state.key_color(x, y);
+                    let fill = (sound.is_some() || key.macro_binding.is_some())
+                        .then(|| egui::Color32::from_rgb(r, g, b));
+
+                    (text, key.pressed, fill)
+                };
+
+                let text = RichText::new(text).size(6.0).color(if pressed {
+                    egui::Color32::RED
+                } else {
+                    egui::Color32::WHITE
+                });
+
+                let mut button = egui::Button::new(text).min_size(size).wrap(true);
+
+                if let Some(fill) = fill {
+                    button = button.fill(fill);
+                }
+
+                if button.ui(ui).is_pointer_button_down_on() {
+                    held = Some((x, y));
+                }
+            }
+            ui.end_row();
+        }
+    });
+
+    // the playing indicators turn off as time passes, not when the state
+    // changes
+    if any_playing {
+        ui.ctx().request_repaint_after(Duration::from_millis(100));
+    }
+
+    held
+}
+
+/// How many characters of a sound's name fit on a pad.
+const PAD_NAME_LEN: usize = 10;
+
+/// Shortens `name` to at most `len` characters, marking where it was cut.
+fn truncate(name: &str, len: usize) -> String {
+    if name.chars().count() <= len {
+        name.to_owned()
+    } else {
+        name.chars().take(len - 1).chain(['…']).collect()
+    }
+}
+
+/// Shows where the set list is up to, and previews the next entry.
+fn render_set_list(ui: &mut egui::Ui, state: &PlayState, engine: &Engine) {
+    let entries = &state.set_list.entries;
+
+    let describe = |entry: &SetEntry| {
+        let mut parts = vec![entry.name.clone()];
+
+        if let Some(kit) = &entry.kit {
+            parts.push(
+                kit.file_stem()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .into_owned(),
+            );
+        }
+
+        parts.extend(entry.pattern.clone());
+        parts.push(format!("{:.0} BPM", entry.bpm));
+        parts.join(" · ")
+    };
+
+    ui.horizontal(|ui| {
+        let previous = state.set_entry.and_then(|i| i.checked_sub(1));
+
+        if ui
+            .add_enabled(previous.is_some(), egui::Button::new("◀").small())
+            .clicked()
+        {
+            engine.send(engine::Command::GoToSetEntry(previous.unwrap()));
+        }
+
+        let current = match state.set_entry {
+            Some(i) => format!("{}/{} {}", i + 1, entries.len(), describe(&entries[i])),
+            None => format!("set list of {}", entries.len()),
+        };
+
+        Label::new(RichText::new(current).size(6.0).strong())
+            .wrap(false)
+            .ui(ui);
+
+        let next = state.set_entry.map_or(0, |i| i + 1);
+
+        if let Some(entry) = entries.get(next) {
+            ui.add_space(4.0);
+            Label::new(
+                RichText::new(format!("next: {}", describe(entry)))
+                    .size(6.0)
+                    .weak(),
+            )
+            .wrap(false)
+            .ui(ui);
+
+            // F1 + F3 + F4 does the same
+            if ui.small_button("▶").clicked() {
+                engine.send(engine::Command::GoToSetEntry(next));
+            }
+        }
+    });
+}
+
+/// The rhythm trainer's menu of patterns, or a button to give up on the game
+/// that is being played, and its score so far. Laid out right to left.
+fn render_game(ui: &mut egui::Ui, state: &PlayState, engine: &Engine) {
+    if let Some(game) = &state.game {
+        if ui.small_button("Pads").clicked() {
+            engine.send(engine::Command::StopGame);
+        }
+
+        let score = &game.score;
+        ui.label(
+            RichText::new(format!(
+                "{} · {:.0}% · combo {}",
+                game::PATTERNS[game.pattern].name,
+                score.accuracy() * 100.,
+                score.combo
+            ))
+            .size(8.0),
+        );
+        return;
+    }
+
+    ui.menu_button("Game", |ui| {
+        for (i, pattern) in game::PATTERNS.iter().enumerate() {
+            if ui.button(pattern.name).clicked() {
+                engine.send(engine::Command::StartGame {
+                    pattern: i,
+                    bars: engine::GAME_BARS,
+                });
+                ui.close_menu();
+            }
+        }
+
+        if let Some(score) = &state.last_game {
+            ui.separator();
+            ui.label(format!(
+                "Last game: {:.0}%, best combo {}",
+                score.accuracy() * 100.,
+                score.best_combo
+            ));
+            ui.label(format!(
+                "{} perfect, {} good, {} ok, {} missed, {} stray",
+                score.perfect, score.good, score.ok, score.miss, score.stray
+            ));
+        }
+    });
+}
+
+/// How long a problem is shown for.
+const TOAST_DURATION: Duration = Duration::from_secs(5);
+
+/// Shows problems over the bottom of the screen for a few seconds after they
+/// happen.
+fn render_toasts(ctx: &egui::Context, toasts: &VecDeque<Toast>) {
+    let now = Instant::now();
+    let visible: Vec<_> = toasts
+        .iter()
+        .filter(|toast| now.saturating_duration_since(toast.at) < TOAST_DURATION)
+        .collect();
+
+    let Some(oldest) = visible.first() else { return; };
+
+    // repaint when the oldest one expires, so that it goes away on time
+    ctx.request_repaint_after(TOAST_DURATION - now.saturating_duration_since(oldest.at));
+
+    egui::Area::new("toasts")
+        .anchor(egui::Align2::CENTER_BOTTOM, Vec2::new(0., -16.))
+        .show(ctx, |ui| {
+            for Toast { problem, .. } in visible {
+                let Problem { subsystem, message } = problem;
+
+                let text = match subsystem {
+                    Some(subsystem) => format!("{subsystem}: {message}"),
+                    None => message.clone(),
+                };
+
+                egui::Frame::popup(ui.style()).show(ui, |ui| {
+                    Label::new(RichText::new(text).color(egui::Color32::RED).size(6.0))
+                        .wrap(true)
+                        .ui(ui);
+                });
+            }
+        });
+}
+
+/// Shows what the keys and chords do over the middle of the screen, while
+/// an Fn key is held down. The ones that the held keys start stand out, next
+/// to the colour that their key is lit in.
+fn render_key_help(ctx: &egui::Context, hints: &[KeyHint]) {
+    egui::Area::new("key_help")
+        .anchor(egui::Align2::CENTER_CENTER, Vec2::ZERO)
+        .show(ctx, |ui| {
+            egui::Frame::popup(ui.style()).show(ui, |ui| {
+                egui::Grid::new("key_help_rows").show(ui, |ui| {
+                    for hint in hints {
+                        let swatch = match hint.color.filter(|_| hint.ready) {
+                            Some(Color { r, g, b, .. }) => {
+                                RichText::new("■").color(egui::Color32::from_rgb(r, g, b))
+                            }
+                            None => RichText::new(""),
+                        };
+
+                        let (keys, does) = match hint.ready {
+                            true => (
+                                RichText::new(&hint.keys).strong(),
+                                RichText::new(&hint.does),
+                            ),
+                            false => (
+                                RichText::new(&hint.keys).weak(),
+                                RichText::new(&hint.does).weak(),
+                            ),
+                        };
+
+                        ui.label(swatch.size(6.0));
+                        ui.label(keys.size(6.0));
+                        ui.label(does.size(6.0));
+                        ui.end_row();
+                    }
+                });
+            });
+        });
+}
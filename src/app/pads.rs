@@ -0,0 +1,399 @@
+//! The view of a pad that is held down, with its sound's waveform and the
+//! pad's settings.
+
+use egui::{Label, RichText, Sense, Vec2, Widget};
+
+use std::time::Duration;
+
+use pidj::driver::adafruit::seesaw::neopixel::Color;
+use pidj::engine::{self, Engine, PlayState};
+use pidj::pad::{self, PadSettings, PlaybackMode};
+use pidj::slice;
+
+pub(super) fn render_pad_detail(
+    ui: &mut egui::Ui,
+    state: &PlayState,
+    engine: &Engine,
+    (x, y): (usize, usize),
+) {
+    ui.horizontal(|ui| {
+        if ui.button("Back").clicked() {
+            engine.send(engine::Command::HidePadDetail);
+        }
+
+        ui.label(format!("Pad ({x}, {y})"));
+
+        // the render replaces whatever is on the pad, and the loops
+        let can_render = !state.loops.is_empty() && state.render.is_none();
+
+        if ui
+            .add_enabled(can_render, egui::Button::new("Render loops here").small())
+            .on_hover_text("Bounce the loops onto this pad in the background")
+            .clicked()
+        {
+            engine.send(engine::Command::RenderToPad { x, y });
+        }
+    });
+
+    let sound = state.sound_keys[y - 1][x]
+        .binding
+        .and_then(|id| state.sounds.get(id.0));
+
+    let Some(sound) = sound else {
+        ui.label("No sound bound");
+
+        if ui.button("Choose a sound").clicked() {
+            engine.send(engine::Command::ReassignPad { x, y });
+        }
+
+        return;
+    };
+
+    ui.horizontal(|ui| {
+        Label::new(RichText::new(sound.name()).size(8.0))
+            .wrap(false)
+            .ui(ui);
+
+        if ui.small_button("Change").clicked() {
+            engine.send(engine::Command::ReassignPad { x, y });
+        }
+    });
+
+    if let Some(artist) = &sound.tags.artist {
+        Label::new(RichText::new(artist).size(6.0).weak())
+            .wrap(false)
+            .ui(ui);
+    }
+
+    let key = &state.sound_keys[y - 1][x];
+    let duration = sound.duration.as_secs_f32();
+    let markers: Vec<_> = key
+        .cues
+        .iter()
+        .map(|cue| cue.as_secs_f32() / duration)
+        .collect();
+
+    // while the sound is being sliced, the slices are shown too
+    let slicing = state.slicing.filter(|slicing| slicing.pad == (x, y));
+    let slices: Vec<_> = match slicing {
+        Some(slicing) => slice::transients(&sound.peaks, slicing.sensitivity, slice::MAX_SLICES)
+            .into_iter()
+            .skip(1)
+            .map(|i| i as f32 / sound.peaks.len() as f32)
+            .collect(),
+        None => vec![],
+    };
+
+    // clicking on the waveform adds a cue point there
+    let response = render_waveform(ui, &sound.peaks, &markers, &slices);
+
+    if let Some(pos) = response
+        .interact_pointer_pos()
+        .filter(|_| response.clicked())
+    {
+        let fraction = (pos.x - response.rect.left()) / response.rect.width();
+        engine.send(engine::Command::AddCue {
+            x,
+            y,
+            position: sound.duration.mul_f32(fraction.clamp(0., 1.)),
+        });
+    }
+
+    ui.horizontal(|ui| {
+        ui.label(RichText::new("Cues").size(6.0));
+
+        if key.cues.is_empty() {
+            ui.label(RichText::new("tap the waveform to add one").size(6.0));
+        }
+
+        // hold the pad and press F1-F4 to play from these
+        for (cue, position) in key.cues.iter().enumerate() {
+            let label = format!("F{} {:.2}s ✕", cue + 1, position.as_secs_f32());
+
+            if ui.small_button(label).clicked() {
+                engine.send(engine::Command::RemoveCue { x, y, cue });
+            }
+        }
+    });
+
+    // cuts the sound up at its hits onto this pad and the ones after it, again
+    // every time the slider moves
+    ui.horizontal(|ui| {
+        ui.label(RichText::new("Slice").size(6.0));
+
+        let mut sensitivity =
+            slicing.map_or(slice::DEFAULT_SENSITIVITY, |slicing| slicing.sensitivity);
+        let slider = egui::Slider::new(&mut sensitivity, 0.0..=1.0).show_value(false);
+
+        if ui.add(slider).changed() {
+            engine.send(engine::Command::SliceToPads { x, y, sensitivity });
+        }
+
+        if slicing.is_some() {
+            ui.label(RichText::new(format!("{} slices", slices.len() + 1)).size(6.0));
+        } else if ui.small_button("Slice").clicked() {
+            engine.send(engine::Command::SliceToPads { x, y, sensitivity });
+        }
+    });
+
+    ui.horizontal(|ui| {
+        ui.label(RichText::new(format!("{:.2}s", sound.duration.as_secs_f32())).size(8.0));
+        ui.add_space(4.0);
+
+        ui.label(
+            RichText::new(match (sound.tags.bpm, sound.estimated_bpm()) {
+                (Some(bpm), _) => format!("{bpm:.0} BPM"),
+                (None, Some(bpm)) => format!("~{bpm:.0} BPM"),
+                (None, None) => "one-shot".to_string(),
+            })
+            .size(8.0),
+        );
+
+        if let Some(key) = sound.key {
+            ui.add_space(4.0);
+            ui.label(RichText::new(key.to_string()).size(8.0));
+        }
+
+        ui.add_space(4.0);
+
+        if sound.ready {
+            ui.label(RichText::new(format!("{:.0} dB", sound.loudness_db)).size(8.0));
+        } else if state.is_unavailable(sound.id) {
+            ui.label(RichText::new("unplugged").size(8.0));
+        } else {
+            ui.label(RichText::new("decoding").size(8.0));
+        }
+    });
+
+    ui.horizontal(|ui| {
+        ui.label(RichText::new("Color").size(6.0));
+
+        let Color { r, g, b, .. } = state.key_color(x, y);
+        let mut rgb = [r, g, b];
+
+        if ui.color_edit_button_srgb(&mut rgb).changed() {
+            let [r, g, b] = rgb;
+            engine.send(engine::Command::SetPadColor {
+                x,
+                y,
+                color: Some(Color::from_u8(r, g, b)),
+            });
+        }
+
+        if key.color.is_some() && ui.small_button("By folder").clicked() {
+            engine.send(engine::Command::SetPadColor { x, y, color: None });
+        }
+    });
+
+    ui.horizontal(|ui| {
+        ui.label(RichText::new("Loop").size(6.0));
+
+        let selected = key
+            .loop_divider
+            .map_or_else(|| "Global".to_owned(), loop_length);
+
+        egui::ComboBox::from_id_source(("pad loop", x, y))
+            .selected_text(RichText::new(selected).size(6.0))
+            .show_ui(ui, |ui| {
+                let mut loop_divider = key.loop_divider;
+                let mut changed = ui
+                    .selectable_value(&mut loop_divider, None, "Global")
+                    .changed();
+
+                for div in engine::LOOP_DIVIDERS {
+                    changed |= ui
+                        .selectable_value(&mut loop_divider, Some(div), loop_length(div))
+                        .changed();
+                }
+
+                if changed {
+                    engine.send(engine::Command::SetPadLoop { x, y, loop_divider });
+                }
+            });
+    });
+
+    // key sync = wait for the next beat, or loop boundary, before playing
+    let mut key_sync = key.key_sync;
+
+    if ui
+        .checkbox(&mut key_sync, RichText::new("Key sync").size(6.0))
+        .changed()
+    {
+        engine.send(engine::Command::SetKeySync { x, y, on: key_sync });
+    }
+
+    render_pad_settings(ui, engine, (x, y), key.settings, sound.duration);
+}
+
+/// Shows how a pad plays its sound, with buttons rather than sliders, so that
+/// each tap is one change that can be undone.
+fn render_pad_settings(
+    ui: &mut egui::Ui,
+    engine: &Engine,
+    (x, y): (usize, usize),
+    settings: PadSettings,
+    duration: Duration,
+) {
+    let mut edited = settings;
+
+    // about 50 steps across the sound
+    let duration_ms = duration.as_millis() as u32;
+    let trim_step = (duration_ms / 50).max(10) / 10 * 10;
+    let trim = |ms: u32, step: i32, other: u32| {
+        let ms = (ms as i64 + (step * trim_step as i32) as i64).max(0) as u32;
+        ms.min(duration_ms.saturating_sub(other + trim_step))
+    };
+
+    egui::Grid::new(("pad settings", x, y)).show(ui, |ui| {
+        let step = stepper(ui, "Gain", format!("{:+.0} dB", settings.gain_db));
+        edited.gain_db += step as f32;
+
+        let step = stepper(ui, "Pan", pan_label(settings.pan));
+
+        if step != 0 {
+            edited.pan = ((settings.pan + 0.1 * step as f32) * 10.).round() / 10.;
+        }
+
+        let step = stepper(ui, "Pitch", format!("{:+} st", settings.pitch));
+        edited.pitch += step as i8;
+
+        let start = settings.trim_start().as_secs_f32();
+        let step = stepper(ui, "Trim start", format!("{start:.2}s"));
+
+        if step != 0 {
+            edited.trim_start_ms = trim(settings.trim_start_ms, step, settings.trim_end_ms);
+        }
+
+        let end = settings.trim_end().as_secs_f32();
+        let step = stepper(ui, "Trim end", format!("{end:.2}s"));
+
+        if step != 0 {
+            edited.trim_end_ms = trim(settings.trim_end_ms, step, settings.trim_start_ms);
+        }
+    });
+
+    ui.horizontal(|ui| {
+        ui.label(RichText::new("Choke").size(6.0));
+        ui.selectable_value(&mut edited.choke_group, None, "Off");
+
+        for group in 1..=pad::CHOKE_GROUPS {
+            ui.selectable_value(&mut edited.choke_group, Some(group), group.to_string());
+        }
+    });
+
+    ui.horizontal(|ui| {
+        ui.label(RichText::new("Mode").size(6.0));
+
+        for mode in PlaybackMode::ALL {
+            ui.selectable_value(&mut edited.mode, mode, mode.to_string());
+        }
+    });
+
+    if ui
+        .add_enabled(
+            settings != PadSettings::default(),
+            egui::Button::new("Reset"),
+        )
+        .clicked()
+    {
+        edited = PadSettings::default();
+    }
+
+    if edited != settings {
+        engine.send(engine::Command::SetPadSettings {
+            x,
+            y,
+            settings: edited,
+        });
+    }
+}
+
+/// Adds a row to a grid with a value and − and + buttons either side of it,
+/// which are easier to hit on a touch screen than a slider. Returns -1 or 1
+/// if one of them was tapped.
+fn stepper(ui: &mut egui::Ui, label: &str, value: String) -> i32 {
+    let mut step = 0;
+
+    ui.label(RichText::new(label).size(6.0));
+
+    if ui.button("−").clicked() {
+        step = -1;
+    }
+
+    ui.label(RichText::new(value).size(8.0));
+
+    if ui.button("+").clicked() {
+        step = 1;
+    }
+
+    ui.end_row();
+    step
+}
+
+/// Formats a pan position as how far left or right of the centre it is.
+fn pan_label(pan: f32) -> String {
+    match (pan * 100.).round() as i32 {
+        0 => "C".to_owned(),
+        percent if percent < 0 => format!("L{}", -percent),
+        percent => format!("R{percent}"),
+    }
+}
+
+/// Describes how long the loops added with a loop divider are.
+fn loop_length(loop_divider: isize) -> String {
+    match loop_divider {
+        0 => "Sound length".to_owned(),
+        -1 | 1 => "1 beat".to_owned(),
+        div if div < 0 => format!("{} beats", -div),
+        div => format!("1/{div} beat"),
+    }
+}
+
+/// Draws a waveform overview as one vertical line per peak, mirrored around
+/// the middle, with a line at each of `markers` and `slices`, which are
+/// fractions of the sound's length.
+fn render_waveform(
+    ui: &mut egui::Ui,
+    peaks: &[f32],
+    markers: &[f32],
+    slices: &[f32],
+) -> egui::Response {
+    let (rect, response) =
+        ui.allocate_exact_size(Vec2::new(ui.available_width(), 32.0), Sense::click());
+    let painter = ui.painter_at(rect);
+
+    painter.rect_filled(rect, 0.0, egui::Color32::from_gray(20));
+
+    let step = rect.width() / peaks.len().max(1) as f32;
+    let stroke = egui::Stroke::new(step.max(0.25), egui::Color32::LIGHT_BLUE);
+    let middle = rect.center().y;
+
+    for (i, peak) in peaks.iter().enumerate() {
+        let x = rect.left() + (i as f32 + 0.5) * step;
+        let height = peak * rect.height() / 2.;
+
+        painter.line_segment(
+            [
+                egui::pos2(x, middle - height),
+                egui::pos2(x, middle + height),
+            ],
+            stroke,
+        );
+    }
+
+    // markers go on top of the waveform
+    let lines = markers
+        .iter()
+        .map(|marker| (marker, egui::Color32::YELLOW))
+        .chain(slices.iter().map(|slice| (slice, egui::Color32::LIGHT_RED)));
+
+    for (marker, color) in lines {
+        let x = rect.left() + marker * rect.width();
+        painter.line_segment(
+            [egui::pos2(x, rect.top()), egui::pos2(x, rect.bottom())],
+            egui::Stroke::new(1.0, color),
+        );
+    }
+
+    response
+}
@@ -0,0 +1,366 @@
+//! The sound browser that a pad's sound is picked from, and its on-screen
+//! search keyboard.
+
+use egui::style::Margin;
+use egui::{Label, RichText, Sense, Widget};
+
+use std::ffi::OsString;
+use std::ops::Range;
+use std::path::PathBuf;
+
+use pidj::audio::SoundId;
+use pidj::engine::{self, Engine, PlayState, VirtualDir};
+
+/// What has been typed into the sound browser's search field. This is kept
+/// here rather than read back from the engine so that typing isn't held up
+/// by the engine.
+#[derive(Default)]
+pub(super) struct SearchInput {
+    query: String,
+    /// whether the on-screen keys for typing without a keyboard are shown
+    keys_open: bool,
+}
+
+pub(super) fn render_reassign(
+    ui: &mut egui::Ui,
+    state: &PlayState,
+    engine: &Engine,
+    search: &mut SearchInput,
+    scroll: &mut BrowserScroll,
+) {
+    let Some(reassign) = &state.reassign else { return; };
+
+    ui.vertical(|ui| {
+        let (x, y) = reassign.key;
+        ui.label(format!("Reassigning key ({x}, {y})"));
+
+        let mut changed = false;
+
+        ui.horizontal(|ui| {
+            changed |= egui::TextEdit::singleline(&mut search.query)
+                .hint_text("Search")
+                .desired_width(ui.available_width() - 16.)
+                .ui(ui)
+                .changed();
+
+            if ui.selectable_label(search.keys_open, "⌨").clicked() {
+                search.keys_open = !search.keys_open;
+            }
+        });
+
+        if search.keys_open {
+            changed |= render_search_keys(ui, &mut search.query);
+        }
+
+        if changed {
+            engine.send(engine::Command::Search(search.query.clone()));
+        }
+
+        let header = if !reassign.query.is_empty() {
+            format!("{} results", reassign.sounds_in_dir.len())
+        } else if let Some(dir) = reassign.virtual_dir {
+            format!("★ {dir}")
+        } else {
+            reassign.current_dir.to_string_lossy().into_owned()
+        };
+
+        ui.horizontal(|ui| {
+            if ui.small_button("⬆").clicked() {
+                engine.send(engine::Command::UpDir);
+            }
+
+            Label::new(egui::RichText::new(header).size(8.0))
+                .wrap(false)
+                .ui(ui);
+
+            ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                if ui.small_button(format!("{}", reassign.filter)).clicked() {
+                    engine.send(engine::Command::CycleFilter);
+                }
+
+                if ui.small_button(format!("↕ {}", reassign.sort)).clicked() {
+                    engine.send(engine::Command::CycleSort);
+                }
+
+                // the page of the listing that is on the pads
+                if state.pad_browser {
+                    ui.label(
+                        RichText::new(format!(
+                            "pads {}/{}",
+                            reassign.pad_page + 1,
+                            reassign.pad_pages()
+                        ))
+                        .size(6.0),
+                    );
+                }
+            });
+        });
+
+        if let Some(selection) = reassign.selection {
+            let clashes = state.key_clashes(reassign.key, selection);
+
+            if !clashes.is_empty() {
+                let names: Vec<_> = clashes
+                    .iter()
+                    .map(|id| {
+                        let sound = &state.sounds[id.0];
+                        format!("{} ({})", sound.name(), sound.key.unwrap())
+                    })
+                    .collect();
+
+                Label::new(
+                    RichText::new(format!("⚠ key clashes with {}", names.join(", ")))
+                        .size(6.)
+                        .color(egui::Color32::YELLOW),
+                )
+                .wrap(false)
+                .ui(ui);
+            }
+        }
+
+        // favorites and recent sounds are listed at the top of the base
+        // directory, along with roots that were skipped or have gone away
+        let at_base = reassign.current_dir == reassign.base_dir
+            && reassign.query.is_empty()
+            && reassign.virtual_dir.is_none();
+
+        let mut rows = vec![];
+
+        if at_base {
+            rows.extend(VirtualDir::ALL.map(BrowserRow::Virtual));
+            rows.extend(state.missing_roots.iter().map(BrowserRow::Missing));
+        }
+
+        rows.extend(reassign.subdirs_in_dir.iter().map(BrowserRow::Dir));
+        rows.extend(
+            reassign
+                .sounds_in_dir
+                .iter()
+                .map(|id| BrowserRow::Sound(*id)),
+        );
+
+        if reassign.scroll_to_selection {
+            let selected = rows.iter().position(
+                |row| matches!(row, BrowserRow::Sound(id) if reassign.selection == Some(*id)),
+            );
+
+            // in the middle of the list, where it is easy to see
+            if let Some(row) = selected {
+                scroll.jump = Some(row.saturating_sub(scroll.visible.len() / 2));
+            }
+
+            engine.send(engine::Command::ScrolledToSelection);
+        }
+
+        if rows.len() > BROWSER_INDEX_ROWS {
+            ui.horizontal_wrapped(|ui| {
+                let page = scroll.visible.len().max(1);
+
+                if ui.small_button("▲").clicked() {
+                    scroll.jump = Some(scroll.visible.start.saturating_sub(page));
+                }
+
+                if ui.small_button("▼").clicked() {
+                    scroll.jump = Some(scroll.visible.end.min(rows.len() - 1));
+                }
+
+                for (letter, row) in browser_index(&rows, state) {
+                    if ui.small_button(letter.to_string()).clicked() {
+                        scroll.jump = Some(row);
+                    }
+                }
+            });
+        }
+
+        // every row is as tall as the star buttons, so that only the rows on
+        // screen have to be drawn
+        let row_height = ui.spacing().interact_size.y + 12.;
+        let mut area = egui::ScrollArea::vertical()
+            .id_source(&reassign.current_dir)
+            .auto_shrink([false, false]);
+
+        if let Some(row) = scroll.jump.take() {
+            let spacing = ui.spacing().item_spacing.y;
+            area = area.vertical_scroll_offset(row as f32 * (row_height + spacing));
+        }
+
+        area.show_rows(ui, row_height, rows.len(), |ui, range| {
+            scroll.visible = range.clone();
+
+            for row in &rows[range] {
+                let f = egui::containers::Frame::default()
+                    .fill(egui::Color32::from_rgb(0, 0, 0))
+                    .inner_margin(Margin::symmetric(3., 6.))
+                    .show(ui, |ui| {
+                        ui.set_min_height(ui.spacing().interact_size.y);
+                        render_browser_row(ui, row, state, engine);
+                    });
+
+                if f.response.interact(Sense::click()).clicked() {
+                    match row {
+                        BrowserRow::Virtual(dir) => {
+                            engine.send(engine::Command::OpenVirtualDir(*dir))
+                        }
+                        // it can't be opened
+                        BrowserRow::Missing(_) => {}
+                        BrowserRow::Dir(dir) => {
+                            engine.send(engine::Command::SelectDir((*dir).clone()))
+                        }
+                        BrowserRow::Sound(id) => engine.send(engine::Command::SelectSound(*id)),
+                    }
+                }
+            }
+        });
+    });
+}
+
+/// A row of the sound browser's list.
+enum BrowserRow<'a> {
+    Virtual(VirtualDir),
+    /// an audio root that couldn't be read
+    Missing(&'a PathBuf),
+    Dir(&'a OsString),
+    Sound(SoundId),
+}
+
+/// Where the sound browser's list is scrolled to. Only the rows on screen are
+/// drawn, so rows that aren't are scrolled to by moving the scroll offset.
+#[derive(Default)]
+pub(super) struct BrowserScroll {
+    /// rows that were on screen in the last frame
+    visible: Range<usize>,
+    /// row to scroll to the top in the next frame
+    jump: Option<usize>,
+}
+
+/// The sound browser shows paging buttons and an index of first letters if it
+/// lists more rows than this.
+const BROWSER_INDEX_ROWS: usize = 30;
+
+fn render_browser_row(ui: &mut egui::Ui, row: &BrowserRow, state: &PlayState, engine: &Engine) {
+    match row {
+        BrowserRow::Virtual(dir) => {
+            Label::new(RichText::new(format!("★ {dir}")).italics().size(8.))
+                .wrap(false)
+                .ui(ui);
+        }
+        BrowserRow::Missing(root) => {
+            let name = root.file_name().unwrap_or(root.as_os_str());
+
+            Label::new(
+                RichText::new(format!("✖ {} (unreachable)", name.to_string_lossy()))
+                    .italics()
+                    .size(8.)
+                    .color(egui::Color32::GRAY),
+            )
+            .wrap(false)
+            .ui(ui);
+        }
+        BrowserRow::Dir(dir) => {
+            Label::new(RichText::new(dir.to_string_lossy()).italics().size(8.))
+                .wrap(false)
+                .ui(ui);
+        }
+        BrowserRow::Sound(id) => {
+            let sound_info = &state.sounds[id.0];
+            let mut rt = RichText::new(sound_info.name()).size(8.);
+
+            if state.reassign.as_ref().and_then(|r| r.selection) == Some(*id) {
+                rt = rt.strong();
+            }
+
+            ui.horizontal(|ui| {
+                let starred = state.favorites.contains(id);
+
+                if ui
+                    .selectable_label(starred, if starred { "★" } else { "☆" })
+                    .clicked()
+                {
+                    engine.send(engine::Command::ToggleFavorite(*id));
+                }
+
+                Label::new(rt).wrap(false).ui(ui);
+
+                // artist, tempo and key, whichever are known
+                let details: Vec<_> = [
+                    sound_info.tags.artist.clone(),
+                    sound_info.tags.bpm.map(|bpm| format!("{bpm:.0} BPM")),
+                    sound_info.key.map(|key| key.to_string()),
+                ]
+                .into_iter()
+                .flatten()
+                .collect();
+
+                if !details.is_empty() {
+                    Label::new(RichText::new(details.join(" · ")).size(6.).weak())
+                        .wrap(false)
+                        .ui(ui);
+                }
+            });
+        }
+    }
+}
+
+/// The first letter of each folder and sound name in the browser, and the
+/// row that it first appears in. Names that start with anything else are
+/// under #.
+fn browser_index(rows: &[BrowserRow], state: &PlayState) -> Vec<(char, usize)> {
+    let mut index: Vec<(char, usize)> = vec![];
+
+    for (i, row) in rows.iter().enumerate() {
+        let name = match row {
+            BrowserRow::Dir(dir) => dir.to_string_lossy(),
+            BrowserRow::Sound(id) => state.sounds[id.0].name(),
+            _ => continue,
+        };
+
+        let letter = match name.chars().next() {
+            Some(c) if c.is_alphabetic() => c.to_uppercase().next().unwrap_or(c),
+            _ => '#',
+        };
+
+        if !index.iter().any(|(l, _)| *l == letter) {
+            index.push((letter, i));
+        }
+    }
+
+    index
+}
+
+/// Characters on the on-screen keys for typing a search.
+const SEARCH_KEYS: [&str; 4] = ["1234567890", "qwertyuiop", "asdfghjkl", "zxcvbnm"];
+
+/// Draws on-screen keys for typing into `query` on the touchscreen, and
+/// returns whether it changed.
+fn render_search_keys(ui: &mut egui::Ui, query: &mut String) -> bool {
+    let mut changed = false;
+
+    for row in SEARCH_KEYS {
+        ui.horizontal(|ui| {
+            for c in row.chars() {
+                if ui.small_button(c.to_string()).clicked() {
+                    query.push(c);
+                    changed = true;
+                }
+            }
+        });
+    }
+
+    ui.horizontal(|ui| {
+        if ui.small_button("space").clicked() {
+            query.push(' ');
+            changed = true;
+        }
+
+        if ui.small_button("⌫").clicked() {
+            changed |= query.pop().is_some();
+        }
+
+        if ui.small_button("clear").clicked() {
+            changed |= !query.is_empty();
+            query.clear();
+        }
+    });
+
+    changed
+}
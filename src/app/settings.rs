@@ -0,0 +1,380 @@
+//! The settings view, which edits the config and the keyboard's own
+//! settings, like its palette and brightness.
+
+use egui::{Align, Label, Layout, RichText, Widget};
+
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use pidj::audio;
+use pidj::bundle;
+use pidj::config::{self, Config, Rotation, Theme};
+use pidj::driver::adafruit::seesaw::neotrellis;
+use pidj::engine::{self, AppState, Engine, Snapshot};
+use pidj::keyboard::DeviceSettings;
+use pidj::palette::Palette;
+
+use crate::theme;
+
+/// Changes to the config that haven't been saved yet.
+pub(super) struct SettingsForm {
+    config: Config,
+    audio_dir: String,
+    /// names of the audio output devices, listed when the form was opened
+    devices: Vec<String>,
+    /// kit bundles that can be imported, listed when the form was opened
+    kits: Vec<PathBuf>,
+    /// settings saved on the NeoTrellis and its address, which are changed
+    /// on the board straight away rather than saved with the config. None if
+    /// they haven't been read.
+    device: Option<(DeviceSettings, u8)>,
+    /// names of the set list's entries, which the unit can start on
+    entries: Vec<String>,
+    /// result of the last attempt to save
+    status: Option<Result<(), String>>,
+}
+
+impl SettingsForm {
+    pub(super) fn new(config: &Config, snapshot: &Snapshot) -> Self {
+        let diagnostics = &snapshot.diagnostics;
+        let entries = match &snapshot.state {
+            AppState::Play(state) => state.set_list.entries.iter(),
+            AppState::Loading(_) => [].iter(),
+        };
+
+        Self {
+            config: config.clone(),
+            audio_dir: config.audio.dir.to_string_lossy().into_owned(),
+            devices: audio::output_devices(),
+            kits: bundle::list(Path::new(bundle::KITS_DIR)),
+            device: diagnostics.device_settings.zip(diagnostics.seesaw_address),
+            entries: entries.map(|entry| entry.name.clone()).collect(),
+            status: None,
+        }
+    }
+}
+
+/// Where to go from the settings view.
+pub(super) enum SettingsExit {
+    Stay,
+    Back,
+    Diagnostics,
+}
+
+/// Output buffer sizes that can be picked in the settings.
+const BUFFER_FRAMES: [u32; 6] = [64, 128, 256, 512, 1024, 2048];
+
+/// Draws the settings view. Saved settings are written to the config file,
+/// and take effect the next time the app starts.
+pub(super) fn render_settings(
+    ui: &mut egui::Ui,
+    form: &mut SettingsForm,
+    saved: &mut Config,
+    engine: &Engine,
+    scale: f32,
+) -> SettingsExit {
+    let mut exit = SettingsExit::Stay;
+
+    ui.horizontal(|ui| {
+        if ui.button("Back").clicked() {
+            exit = SettingsExit::Back;
+        }
+
+        ui.label(RichText::new("Settings").size(10.0));
+
+        ui.with_layout(Layout::right_to_left(Align::Center), |ui| {
+            if ui.button("Diagnostics").clicked() {
+                exit = SettingsExit::Diagnostics;
+            }
+        });
+    });
+
+    egui::ScrollArea::vertical().show(ui, |ui| {
+        let config = &mut form.config;
+
+        ui.label(RichText::new("Audio").size(8.0));
+
+        ui.horizontal(|ui| {
+            ui.label("Sound directory");
+            ui.text_edit_singleline(&mut form.audio_dir);
+        });
+
+        egui::ComboBox::from_label("Output device")
+            .selected_text(config.audio.device.as_deref().unwrap_or("Default"))
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut config.audio.device, None, "Default");
+
+                for device in &form.devices {
+                    ui.selectable_value(
+                        &mut config.audio.device,
+                        Some(device.clone()),
+                        device.as_str(),
+                    );
+                }
+            });
+
+        egui::ComboBox::from_label("Buffer")
+            .selected_text(match config.audio.buffer_frames {
+                Some(frames) => format!("{frames} frames"),
+                None => "Default".to_owned(),
+            })
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut config.audio.buffer_frames, None, "Default");
+
+                for frames in BUFFER_FRAMES {
+                    ui.selectable_value(
+                        &mut config.audio.buffer_frames,
+                        Some(frames),
+                        format!("{frames} frames"),
+                    );
+                }
+            });
+
+        let compressor = &mut config.audio.compressor;
+        ui.checkbox(&mut compressor.enabled, "Compress the master mix");
+
+        ui.add_enabled_ui(compressor.enabled, |ui| {
+            egui::Slider::new(&mut compressor.threshold_db, -40.0..=0.0)
+                .text("Threshold (dB)")
+                .ui(ui);
+            egui::Slider::new(&mut compressor.ratio, 1.0..=20.0)
+                .text("Ratio")
+                .logarithmic(true)
+                .ui(ui);
+            egui::Slider::new(&mut compressor.attack_ms, 0.1..=100.0)
+                .text("Attack (ms)")
+                .logarithmic(true)
+                .ui(ui);
+            egui::Slider::new(&mut compressor.release_ms, 10.0..=1000.0)
+                .text("Release (ms)")
+                .logarithmic(true)
+                .ui(ui);
+            egui::Slider::new(&mut compressor.makeup_db, 0.0..=24.0)
+                .text("Makeup (dB)")
+                .ui(ui);
+        });
+
+        ui.add_space(4.0);
+        ui.label(RichText::new("Looper").size(8.0));
+
+        egui::Slider::new(&mut config.play.bpm, 20.0..=300.0)
+            .text("Default BPM")
+            .ui(ui);
+        ui.checkbox(&mut config.play.quantize, "Quantize loops");
+        ui.checkbox(
+            &mut config.play.launch_on_bar,
+            "Start pads on the next bar while looping",
+        );
+        ui.checkbox(
+            &mut config.play.warn_key_clashes,
+            "Warn about clashing keys",
+        );
+        ui.checkbox(
+            &mut config.play.keep_tempo,
+            "Keep the tempo when loading a kit",
+        );
+        ui.checkbox(&mut config.play.pad_browser, "Browse sounds on the pads");
+
+        ui.add_space(4.0);
+        ui.label(RichText::new("Kits").size(8.0));
+
+        if ui.button("Export the pads").clicked() {
+            let secs = SystemTime::UNIX_EPOCH
+                .elapsed()
+                .unwrap_or_default()
+                .as_secs();
+            let path =
+                Path::new(bundle::KITS_DIR).join(format!("kit-{secs}.{}", bundle::EXTENSION));
+
+            engine.send(engine::Command::ExportKit(path.clone()));
+
+            // it is written in the background, and shows up here straight
+            // away so that it is clear where it went
+            if !form.kits.contains(&path) {
+                form.kits.push(path);
+            }
+        }
+
+        for path in &form.kits {
+            ui.horizontal(|ui| {
+                ui.label(path.file_stem().unwrap_or_default().to_string_lossy());
+
+                if ui.small_button("Import").clicked() {
+                    engine.send(engine::Command::ImportKit(path.clone()));
+                }
+            });
+        }
+
+        ui.add_space(4.0);
+        ui.label(RichText::new("Display").size(8.0));
+
+        egui::Slider::new(&mut config.display.scale, 1.0..=8.0)
+            .text("Scale")
+            .ui(ui);
+        ui.checkbox(&mut config.display.windowed, "Windowed");
+
+        egui::ComboBox::from_label("Rotation")
+            .selected_text(config.display.rotation.to_string())
+            .show_ui(ui, |ui| {
+                for rotation in Rotation::ALL {
+                    ui.selectable_value(
+                        &mut config.display.rotation,
+                        rotation,
+                        rotation.to_string(),
+                    );
+                }
+            });
+
+        // themes are applied straight away, so that they can be tried out
+        let theme = config.display.theme;
+
+        egui::ComboBox::from_label("Theme")
+            .selected_text(theme.to_string())
+            .show_ui(ui, |ui| {
+                for theme in Theme::ALL {
+                    ui.selectable_value(&mut config.display.theme, theme, theme.to_string());
+                }
+            });
+
+        if config.display.theme != theme {
+            theme::apply(ui.ctx(), config.display.theme, scale);
+        }
+
+        ui.add_space(4.0);
+        ui.label(RichText::new("Keyboard").size(8.0));
+
+        match &mut form.device {
+            Some((settings, address)) => {
+                // these are saved on the NeoTrellis, whose EEPROM wears out,
+                // so the brightness is only sent once the slider is let go
+                let slider = egui::Slider::new(&mut settings.brightness, 0..=u8::MAX)
+                    .text("LED brightness")
+                    .ui(ui);
+
+                if slider.drag_released() || (slider.changed() && !slider.dragged()) {
+                    engine.send(engine::Command::SetBrightness(settings.brightness));
+                }
+
+                let kit = settings.kit;
+                let entry_name = |kit: Option<u8>| match kit {
+                    Some(index) => form
+                        .entries
+                        .get(index as usize)
+                        .cloned()
+                        .unwrap_or_else(|| format!("Entry {}", index + 1)),
+                    None => "Where it left off".to_owned(),
+                };
+
+                egui::ComboBox::from_label("Start on")
+                    .selected_text(entry_name(kit))
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut settings.kit, None, entry_name(None));
+
+                        for index in (0..form.entries.len()).filter_map(|i| u8::try_from(i).ok()) {
+                            ui.selectable_value(
+                                &mut settings.kit,
+                                Some(index),
+                                entry_name(Some(index)),
+                            );
+                        }
+                    });
+
+                if settings.kit != kit {
+                    engine.send(engine::Command::SetStartKit(settings.kit));
+                }
+
+                let old_address = *address;
+
+                egui::ComboBox::from_label("NeoTrellis address")
+                    .selected_text(format!("{address:#04x}"))
+                    .show_ui(ui, |ui| {
+                        for choice in neotrellis::ADDRESSES {
+                            ui.selectable_value(address, choice, format!("{choice:#04x}"));
+                        }
+                    });
+
+                if *address != old_address {
+                    engine.send(engine::Command::SetKeyboardAddress(*address));
+
+                    // a pinned address has to follow the board
+                    if config.keyboard.address.is_some() {
+                        config.keyboard.address = Some(*address);
+                    }
+                }
+            }
+            None => {
+                ui.label("The NeoTrellis's settings haven't been read");
+            }
+        }
+
+        let palette = config
+            .keyboard
+            .palette
+            .as_deref()
+            .unwrap_or("default")
+            .to_owned();
+        let custom: Vec<_> = config
+            .keyboard
+            .palettes
+            .keys()
+            .filter(|name| !Palette::BUILTIN.contains(&name.as_str()))
+            .cloned()
+            .collect();
+
+        egui::ComboBox::from_label("LED palette")
+            .selected_text(palette.as_str())
+            .show_ui(ui, |ui| {
+                for name in Palette::BUILTIN
+                    .map(str::to_owned)
+                    .into_iter()
+                    .chain(custom)
+                {
+                    if ui
+                        .selectable_label(palette == name, name.as_str())
+                        .clicked()
+                    {
+                        config.keyboard.palette = Some(name);
+                    }
+                }
+            });
+
+        let mut meter = config.keyboard.meter_column.is_some();
+
+        if ui
+            .checkbox(&mut meter, "VU meter on the right column")
+            .changed()
+        {
+            config.keyboard.meter_column = meter.then_some(3);
+        }
+    });
+
+    ui.add_space(4.0);
+
+    ui.horizontal(|ui| {
+        if ui.button("Save").clicked() {
+            form.config.audio.dir = PathBuf::from(&form.audio_dir);
+
+            let result = form.config.save(config::CONFIG_PATH);
+
+            if result.is_ok() {
+                *saved = form.config.clone();
+            }
+
+            form.status = Some(result.map_err(|err| format!("{err:#}")));
+        }
+
+        match &form.status {
+            Some(Ok(())) => {
+                ui.label(RichText::new("Saved, restart to apply").size(6.0));
+            }
+            Some(Err(err)) => {
+                Label::new(RichText::new(err).color(egui::Color32::RED).size(6.0))
+                    .wrap(true)
+                    .ui(ui);
+            }
+            None => {}
+        }
+    });
+
+    exit
+}
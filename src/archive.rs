@@ -0,0 +1,117 @@
+//! Sounds inside zipped sample packs, which are loaded without being
+//! extracted. A file in an archive has the path of the archive followed by
+//! its path inside it, e.g. `audio/Lofi.zip/Claps/Old Clap.wav`, so that the
+//! archive is a folder in the sound browser. Archives are only ever read.
+
+use std::{
+    fs::File,
+    io::{self, BufReader, Cursor, Read, Seek},
+    path::{Path, PathBuf},
+};
+
+use zip::ZipArchive;
+
+pub trait ReadSeek: Read + Seek + Send + Sync {}
+
+impl<T: Read + Seek + Send + Sync> ReadSeek for T {}
+
+/// Whether `path` is a zip archive, going by its extension.
+pub fn is_archive(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("zip"))
+}
+
+/// Splits the path of a file in an archive into the path of the archive and
+/// the name of the file in it. Returns None for a path that isn't in an
+/// archive.
+pub fn split(path: &Path) -> Option<(&Path, String)> {
+    let archive = path
+        .ancestors()
+        .skip(1)
+        .find(|ancestor| is_archive(ancestor) && ancestor.is_file())?;
+
+    // names in zips always use forward slashes
+    let name = path
+        .strip_prefix(archive)
+        .ok()?
+        .iter()
+        .map(|part| part.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/");
+
+    Some((archive, name))
+}
+
+/// Lists the files in the archive at `path` that `filter` accepts, as paths
+/// that [`open`] can read.
+pub fn list(path: &Path, filter: impl Fn(&Path) -> bool) -> io::Result<Vec<PathBuf>> {
+    let mut archive = ZipArchive::new(BufReader::new(File::open(path)?))?;
+    let mut files = vec![];
+
+    for i in 0..archive.len() {
+        let file = archive.by_index(i)?;
+
+        // names that would escape the archive, like ../x.wav, are left out
+        let Some(name) = file.enclosed_name() else {
+            continue;
+        };
+
+        if file.is_file() && filter(name) {
+            files.push(path.join(name));
+        }
+    }
+
+    Ok(files)
+}
+
+/// Opens a file for reading, whether it is in an archive or not. Files in
+/// archives are decompressed into memory.
+pub fn open(path: &Path) -> io::Result<Box<dyn ReadSeek>> {
+    let Some((archive, name)) = split(path) else { return Ok(Box::new(BufReader::new(File::open(path)?))); };
+
+    let mut archive = ZipArchive::new(BufReader::new(File::open(archive)?))?;
+    let mut file = archive.by_name(&name)?;
+    let mut bytes = Vec::with_capacity(file.size() as usize);
+    file.read_to_end(&mut bytes)?;
+
+    Ok(Box::new(Cursor::new(bytes)))
+}
+
+#[cfg(test)]
+mod test {
+    use std::{io::Read, io::Write, path::Path};
+
+    use zip::{write::FileOptions, ZipWriter};
+
+    use super::{list, open, split};
+    use crate::util::TempDir;
+
+    #[test]
+    fn files_in_archives_are_listed_and_read() {
+        let dir = TempDir::new("archive");
+        let path = dir.join("Lofi.zip");
+
+        let mut zip = ZipWriter::new(std::fs::File::create(&path).unwrap());
+        for (name, contents) in [("Claps/Old Clap.wav", "clap"), ("readme.txt", "hi")] {
+            zip.start_file(name, FileOptions::default()).unwrap();
+            zip.write_all(contents.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap();
+
+        let is_wav = |name: &Path| name.extension().is_some_and(|ext| ext == "wav");
+        let clap = path.join("Claps/Old Clap.wav");
+        assert_eq!(list(&path, is_wav).unwrap(), vec![clap.clone()]);
+        assert_eq!(
+            split(&clap),
+            Some((path.as_path(), "Claps/Old Clap.wav".to_owned()))
+        );
+
+        let mut contents = String::new();
+        open(&clap).unwrap().read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "clap");
+
+        // files outside of archives are read as they are
+        assert_eq!(split(&path), None);
+        assert!(open(&dir.join("missing.wav")).is_err());
+    }
+}
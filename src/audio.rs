@@ -1,24 +1,329 @@
-use std::{fs::File, io::BufReader, path::PathBuf, time::Duration};
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicU8, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant, SystemTime},
+};
 
-use anyhow::Context;
+use anyhow::{bail, Context};
 use futures::stream::StreamExt;
-use rodio::{Decoder, OutputStream, Source};
+use rodio::{
+    cpal::{
+        self,
+        traits::{DeviceTrait, HostTrait, StreamTrait},
+        BufferSize, OutputCallbackInfo, SampleFormat, StreamConfig, StreamInstant,
+        SupportedBufferSize, SupportedStreamConfig,
+    },
+    dynamic_mixer::{self, DynamicMixerController},
+    source::{UniformSourceIterator, Zero},
+    Decoder, Source, StreamError,
+};
 use tokio::{
     runtime::{self},
     sync::oneshot,
 };
 use tokio_util::sync::CancellationToken;
-use tracing::{debug, info, trace, warn};
+use tracing::{debug, info, trace, trace_span, warn};
+
+use crate::{
+    analysis::{self, Analysis, AnalysisIndex},
+    archive, bundle,
+    cache::{content_hash, SampleCache},
+    chroma::{self, Key},
+    compressor::Compressor,
+    config::{AudioConfig, AudioSink, CompressorConfig},
+    deck::{Deck, DeckCommand, DeckStatus, Track, TrackSource},
+    effect::{Chain, ChainUpdate, EffectConfig, Registry},
+    engine::{Problem, Subsystem},
+    eq::{KillEq, Kills},
+    fx::{Fx, MasterFx},
+    latency::Trace,
+    pad::PadSettings,
+    pool::{VoicePool, Voicing, MAX_VOICES},
+    render::{self, Hit, RenderStatus, RenderTarget},
+    setlist::Kit,
+    tags::Tags,
+    tape::{self, TapeMessage},
+};
 
 #[derive(Debug, Clone)]
 pub enum Command {
-    Play { sound_id: SoundId },
+    Play {
+        sound_id: SoundId,
+        bus: Bus,
+        /// where in the sound to start playing from
+        start: Duration,
+        /// how the pad that the sound is played from plays it
+        settings: PadSettings,
+        /// the pad, so that [`Command::Release`] can stop the sound. Pads are
+        /// numbered across the rows, from 0 at the top left, 16 to a row.
+        pad: Option<u8>,
+        /// Set if the sound was triggered by a key press, so that the time it
+        /// takes to start playing can be measured.
+        trace: Option<Trace>,
+    },
+    /// Stops the sounds that a pad played.
+    Release { pad: u8 },
+    /// Plays a sound over and over without a gap, going back to its start
+    /// after `length`, until [`Command::StopLoop`]. `seam` is how long it
+    /// fades out and back in for where it wraps round.
+    PlayLoop {
+        sound_id: SoundId,
+        bus: Bus,
+        settings: PadSettings,
+        /// the looper's id for it
+        voice: u32,
+        length: Duration,
+        seam: Duration,
+    },
+    /// Stops a sound that [`Command::PlayLoop`] started.
+    StopLoop { voice: u32 },
+    /// Sets the master volume, where 1.0 is unity gain.
+    SetVolume { volume: f32 },
+    /// Moves the crossfader, from 0.0 (only bus A) to 1.0 (only bus B).
+    SetCrossfade { position: f32 },
+    /// Sets the cutoff frequency of the master low-pass filter.
+    SetFilterCutoff { hz: u32 },
+    /// Tries loading the sounds again after loading failed.
+    Reload,
+    /// Controls one of the decks that play full-length tracks.
+    Deck { deck: Deck, command: DeckCommand },
+    /// Cuts out bands of the EQ of one of the crossfader's buses. Sounds on
+    /// the bus go through it, but decks don't.
+    SetEq { bus: Bus, kills: Kills },
+    /// Puts an effect on the master mix, or takes it off if None.
+    SetFx { fx: Option<Fx> },
+    /// Replaces the effect chain of one of the crossfader's buses. See
+    /// [`crate::effect`].
+    SetChain {
+        bus: Bus,
+        effects: Vec<EffectConfig>,
+    },
+    /// Tells the effect chains how long a beat is.
+    SetTempo { beat: Duration },
+    /// Records the next `duration` of the master mix into a new sound, which
+    /// is sent back in an [`Event::Recorded`]. Ignored if there is no output.
+    Record { duration: Duration },
+    /// Writes a kit bundle in the background. See [`bundle::export`].
+    ExportKit {
+        path: PathBuf,
+        kit: Kit,
+        sounds: Vec<(PathBuf, PathBuf)>,
+    },
+    /// Imports a kit bundle into the audio directory in the background, and
+    /// sends back an [`Event::KitImported`].
+    ImportKit { path: PathBuf },
+    /// Renders `length` of the given sounds in the background, replacing a
+    /// render that is already running. How it gets on is sent back in
+    /// [`Event::Render`]. See [`crate::render`].
+    Render {
+        hits: Vec<RenderHit>,
+        length: Duration,
+        target: RenderTarget,
+    },
+    /// Gives up on the render that is running.
+    CancelRender,
+    /// Starts taping the master mix to a new file in the recordings, named
+    /// after the time and `pattern`. A file that was already being taped to
+    /// is finished. See [`crate::tape`].
+    StartTake { pattern: Option<String> },
+    /// Stops taping the master mix.
+    StopTape,
+}
+
+/// A sound that starts playing during a render.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderHit {
+    pub sound: SoundId,
+    /// how far into the render it starts
+    pub at: Duration,
+    pub bus: Bus,
+    pub settings: PadSettings,
+}
+
+/// One side of the crossfader. Pads in the left two columns and deck A play on
+/// bus A, and the rest on bus B.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Bus {
+    A,
+    B,
+}
+
+impl Bus {
+    pub fn index(self) -> usize {
+        match self {
+            Bus::A => 0,
+            Bus::B => 1,
+        }
+    }
+
+    /// The bus that the pads in column `x` play on.
+    pub fn of_column(x: usize) -> Self {
+        if x < 2 {
+            Bus::A
+        } else {
+            Bus::B
+        }
+    }
+}
+
+impl From<Deck> for Bus {
+    fn from(deck: Deck) -> Self {
+        match deck {
+            Deck::A => Bus::A,
+            Deck::B => Bus::B,
+        }
+    }
+}
+
+/// Gains of buses A and B with the crossfader at `position`. Both are at unity
+/// gain in the middle, and each fades out towards the other end, so that
+/// nothing dips in level while crossing over.
+pub fn crossfade_gains(position: f32) -> [f32; 2] {
+    let position = position.clamp(0., 1.);
+    [(2. * (1. - position)).min(1.), (2. * position).min(1.)]
+}
+
+/// Cutoff frequency at which the master low-pass filter is effectively off.
+pub const FILTER_CUTOFF_MAX: u32 = 20_000;
+
+/// How many times to try opening the output device before falling back to
+/// dropping sounds, and how long to wait after the first failed attempt. The
+/// wait doubles after each attempt.
+const OUTPUT_ATTEMPTS: u32 = 6;
+const OUTPUT_BACKOFF: Duration = Duration::from_millis(250);
+
+/// How often to try opening the output device again once it has been given up
+/// on.
+const OUTPUT_RETRY_PERIOD: Duration = Duration::from_secs(10);
+
+/// How long the output stream can go without asking for samples before it is
+/// taken to have died, e.g. because a USB interface was unplugged, and is
+/// opened again. Periods are a few milliseconds, so this is many of them.
+const OUTPUT_WATCHDOG_PERIOD: Duration = Duration::from_secs(1);
+
+/// Format of the master mix. Sounds are converted to it as they are played.
+const MIX_CHANNELS: u16 = 2;
+const MIX_SAMPLE_RATE: u32 = 44_100;
+
+/// How many frames are played into a sink at a time, in place of a device's
+/// period.
+const SINK_PERIOD_FRAMES: usize = 441;
+
+/// How often the level of the master mix is reported.
+const LEVEL_PERIOD: Duration = Duration::from_millis(50);
+
+/// How often the audio roots are checked for having been unplugged or plugged
+/// back in, or for a network share having hung.
+const ROOT_CHECK_PERIOD: Duration = Duration::from_secs(2);
+
+/// How many samples the meter adds up before handing them over, so that the
+/// output thread isn't taking a lock for every sample. Recordings are handed
+/// over in blocks of the same size.
+const METER_BLOCK: usize = 1024;
+
+/// How many frames of a bus go through its effect chain at a time.
+const CHAIN_FRAMES: usize = 64;
+
+/// Level of the master mix over one [`LEVEL_PERIOD`], where 1.0 is full scale.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Level {
+    pub peak: f32,
+    pub rms: f32,
+    /// How many dB the master compressor is turning the mix down by.
+    pub gain_reduction: f32,
 }
 
 #[derive(Debug, Clone)]
 pub enum Event {
     LoadingStart,
-    LoadingEnd { sounds: Vec<SoundInfo> },
+    /// The sounds couldn't be loaded from `path`. Loading is retried when a
+    /// [`Command::Reload`] is received.
+    LoadingFailed {
+        path: PathBuf,
+        error: String,
+    },
+    /// The loader is about to look `path` up in the cache, which is file
+    /// number `progress` (counting from 0) of `num_files`.
+    LoadingProgress {
+        progress: usize,
+        num_files: usize,
+        path: PathBuf,
+    },
+    /// Every sound has been found. The ones that weren't in the cache aren't
+    /// ready yet, and are decoded in the background. `missing_roots` are the
+    /// audio roots that were skipped because they couldn't be read.
+    LoadingEnd {
+        sounds: Vec<SoundInfo>,
+        missing_roots: Vec<PathBuf>,
+    },
+    /// A sound that wasn't ready when loading ended has been decoded, and can
+    /// be played.
+    SoundReady {
+        sound: SoundInfo,
+    },
+    /// An audio root has gone away, e.g. a USB stick was unplugged, or come
+    /// back. Sounds in it that aren't ready can't be decoded while it is gone.
+    RootAvailability {
+        root: PathBuf,
+        available: bool,
+    },
+    /// No output device could be opened, so sounds are being dropped until
+    /// one can be.
+    OutputUnavailable {
+        error: String,
+    },
+    /// An output device was opened, either when starting up or after being
+    /// unavailable.
+    OutputAvailable {
+        device: String,
+    },
+    /// The output was opened again after being lost, so the sounds that were
+    /// playing on it have stopped.
+    OutputRestarted,
+    /// The number of sounds that are playing has changed.
+    Voices(usize),
+    /// How many of the sounds that were loaded are byte-identical copies of
+    /// another one, and share its samples.
+    Duplicates(usize),
+    /// How the output stream is doing, sent every [`LEVEL_PERIOD`] while it
+    /// changes.
+    OutputStats {
+        /// frames that the device asks for at a time
+        period_frames: usize,
+        /// how many times the device has run out of samples to play
+        underruns: usize,
+    },
+    /// A sound triggered by a key press has started playing.
+    Played {
+        trace: Trace,
+    },
+    /// Level of the master mix, sent every [`LEVEL_PERIOD`] unless it has been
+    /// silent since the last one.
+    Level(Level),
+    /// What a deck is doing, sent every [`LEVEL_PERIOD`] while it changes.
+    Deck {
+        deck: Deck,
+        status: DeckStatus,
+    },
+    /// A recording of the master mix has finished, and can be played like
+    /// the sounds that were loaded. It is lost if the audio subsystem
+    /// restarts.
+    Recorded {
+        sound: SoundInfo,
+    },
+    /// A kit bundle has been imported. Its sounds aren't ready yet, and are
+    /// decoded in the background.
+    KitImported {
+        kit: Kit,
+        sounds: Vec<SoundInfo>,
+    },
+    /// How the render that is running is getting on.
+    Render(RenderStatus),
 }
 
 #[derive(Debug, Clone, PartialEq, PartialOrd, Eq, Ord, Hash, Copy)]
@@ -28,90 +333,209 @@ pub struct SoundId(pub usize);
 pub struct SoundInfo {
     pub id: SoundId,
     pub path: PathBuf,
+    /// Audio root that the sound was found in.
+    pub root: PathBuf,
     pub duration: Duration,
+    /// Overview of the waveform: the loudest sample in each of [`PEAK_POINTS`]
+    /// equal slices of the sound, between 0 and 1.
+    pub peaks: Vec<f32>,
+    /// RMS level of the whole sound, in dBFS.
+    pub loudness_db: f32,
+    /// Musical key of the sound, or None if it doesn't have one, e.g. drums.
+    pub key: Option<Key>,
+    pub tags: Tags,
+    /// Whether the sound has been decoded, so that it can be played. Until it
+    /// is, its analysis may be missing too.
+    pub ready: bool,
+}
+
+/// Number of points in a sound's waveform overview.
+pub const PEAK_POINTS: usize = 256;
+
+impl SoundInfo {
+    fn new(id: SoundId, root: PathBuf, path: PathBuf, analysis: Analysis, ready: bool) -> Self {
+        Self {
+            id,
+            tags: Tags::read(&path),
+            path,
+            root,
+            duration: analysis.duration,
+            peaks: analysis.peaks,
+            loudness_db: analysis.loudness_db,
+            key: analysis.key,
+            ready,
+        }
+    }
+
+    /// A sound that was recorded or rendered to `path`, which isn't written
+    /// anywhere.
+    fn recorded(id: SoundId, path: PathBuf, root: PathBuf, recording: &Pcm) -> Self {
+        let duration = recording.total_duration().unwrap_or_default();
+
+        Self {
+            id,
+            path,
+            root,
+            duration,
+            peaks: peaks(recording, duration),
+            loudness_db: analysis::loudness_db(&recording.samples),
+            key: None,
+            tags: Tags::default(),
+            ready: true,
+        }
+    }
+
+    /// Whether the sound is a loop, going by its tempo tag, which sample packs
+    /// only give to their loops.
+    pub fn is_loop(&self) -> bool {
+        self.tags.bpm.is_some()
+    }
+
+    /// The sound's title tag, or its file name if it doesn't have one.
+    pub fn name(&self) -> Cow<'_, str> {
+        match &self.tags.title {
+            Some(title) => Cow::Borrowed(title),
+            None => self.path.file_stem().unwrap_or_default().to_string_lossy(),
+        }
+    }
+
+    /// Where the sound is in the sound browser: its path in its audio root,
+    /// under a folder named after the root.
+    pub fn library_path(&self) -> PathBuf {
+        let relative = self.path.strip_prefix(&self.root).unwrap_or(&self.path);
+        Path::new(self.root.file_name().unwrap_or_default()).join(relative)
+    }
+
+    /// The sound's tempo tag, or an estimate if it doesn't have one.
+    pub fn bpm(&self) -> Option<f32> {
+        self.tags.bpm.or_else(|| self.estimated_bpm())
+    }
+
+    /// Guesses the tempo of the sound, assuming that it is a loop that lasts a
+    /// power-of-two number of beats at between 80 and 160 BPM. Sounds shorter
+    /// than a second are assumed to be one-shots, which have no tempo.
+    pub fn estimated_bpm(&self) -> Option<f32> {
+        let secs = self.duration.as_secs_f32();
+
+        if secs < 1. {
+            return None;
+        }
+
+        let mut bpm = 60. / secs;
+
+        while bpm < 80. {
+            bpm *= 2.;
+        }
+
+        while bpm >= 160. {
+            bpm /= 2.;
+        }
+
+        Some(bpm)
+    }
 }
 
 pub async fn run(
     ct: CancellationToken,
+    config: AudioConfig,
     cmd_rx: flume::Receiver<Command>,
     event_tx: flume::Sender<Event>,
+    problem_tx: flume::Sender<Problem>,
 ) -> anyhow::Result<()> {
-    let _ = event_tx.send(Event::LoadingStart);
-
-    info!("locating audio files");
+    let dir = std::env::current_dir()?.join(&config.dir);
+    let recordings_dir = dir.join("Recordings");
+    let roots = std::iter::once(&config.dir)
+        .chain(&config.extra_dirs)
+        .map(|root| Ok(std::env::current_dir()?.join(root)))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    let read_timeout = Duration::from_millis(config.read_timeout_ms);
+    let cache_dir = std::env::current_dir()?.join(&config.cache_dir);
+    let cache = SampleCache::new(&cache_dir, MIX_SAMPLE_RATE, MIX_CHANNELS);
+    let index_path = cache_dir.join(analysis::INDEX_FILE);
 
-    let cwd = std::env::current_dir()?;
-    let glob_pattern = cwd.to_string_lossy().to_string() + "/audio/**/*.{wav,flac,mp3}";
+    let loaded = loop {
+        let _ = event_tx.send(Event::LoadingStart);
 
-    debug!("globbing {glob_pattern:?}");
-
-    let mut walkdir = async_walkdir::WalkDir::new(cwd.join("audio"));
-    let mut paths = vec![];
+        match load_sounds(
+            &ct,
+            &roots,
+            read_timeout,
+            &cache,
+            &index_path,
+            &event_tx,
+            &problem_tx,
+        )
+        .await
+        {
+            Ok(Some(loaded)) => break loaded,
+            // cancelled while loading
+            Ok(None) => return Ok(()),
+            Err(err) => {
+                warn!("failed to load sounds: {err:?}");
 
-    loop {
-        tokio::select! {
-            _ = ct.cancelled() => { break; }
-            entry = walkdir.next() => {
-                match entry {
-                    Some(entry) => {
-                        let entry = entry?;
-                        let path = entry.path();
+                let _ = event_tx.send(Event::LoadingFailed {
+                    path: dir.clone(),
+                    error: format!("{err:#}"),
+                });
 
-                        match path.extension() {
-                            Some(ext) => {
-                                match ext.to_str() {
-                                    Some("wav") | Some("flac") | Some("mp3") => {
-                                        trace!("loaded file {path:?}");
-                                        paths.push(path.to_path_buf());
-                                    }
-                                    _ => {}
-                                }
-                            }
-                            _ => {}
+                // wait until someone has fixed the problem and asks to retry
+                loop {
+                    tokio::select! {
+                        _ = ct.cancelled() => return Ok(()),
+                        cmd = cmd_rx.recv_async() => match cmd {
+                            Ok(Command::Reload) => break,
+                            Ok(cmd) => debug!("ignoring {cmd:?}, no sounds are loaded"),
+                            Err(_) => return Ok(()),
                         }
                     }
-                    None => { break; }
                 }
             }
         }
-    }
+    };
 
-    debug!("globbed");
+    let Loaded {
+        sounds,
+        mut decoded,
+        unready,
+        index,
+        skipped,
+        duplicates,
+    } = loaded;
 
-    let (sounds, decoders): (Vec<_>, Vec<_>) = tokio::task::block_in_place(|| {
-        paths
-            .into_iter()
-            .enumerate()
-            .map(|(index, path)| -> anyhow::Result<_> {
-                let file = File::open(&path).context("failed to open audio file")?;
-                let reader = BufReader::new(file);
-                let decoder = Decoder::new(reader)
-                    .with_context(|| format!("failed to decode audio file {:?}", path))?;
-                let decoder = decoder.convert_samples::<f32>().buffered();
+    info!(
+        "found audio files, {} are still to be decoded",
+        unready.len()
+    );
 
-                let sound = SoundInfo {
-                    id: SoundId(index),
-                    path,
-                    duration: decoder
-                        .total_duration()
-                        .context("couldn't get duration of sound")?,
-                };
-
-                Ok((sound, decoder))
-            })
-            .filter_map(|r| match r {
-                Ok(r) => Some(r),
-                Err(err) => {
-                    warn!("failed to load sound: {err:?}");
-                    None
-                }
-            })
-            .unzip()
+    let _ = event_tx.send(Event::LoadingEnd {
+        sounds,
+        missing_roots: skipped.clone(),
     });
+    let _ = event_tx.send(Event::Duplicates(duplicates));
+
+    let available = roots.iter().map(|root| !skipped.contains(root)).collect();
+    tokio::spawn(watch_roots(
+        ct.clone(),
+        roots,
+        available,
+        read_timeout,
+        event_tx.clone(),
+        problem_tx.clone(),
+    ));
 
-    let _ = event_tx.send(Event::LoadingEnd { sounds });
+    let (ready_tx, ready_rx) = flume::unbounded();
+    let (import_tx, import_rx) = flume::unbounded::<(Kit, Vec<(PathBuf, u64)>)>();
+    let (rendered_tx, rendered_rx) = flume::unbounded();
 
-    info!("loaded audio files");
+    spawn_decoder(
+        ct.clone(),
+        unready,
+        cache.clone(),
+        index,
+        index_path.clone(),
+        ready_tx.clone(),
+        problem_tx.clone(),
+    );
 
     // rodio::OutputStream is !Send and !Sync, but if it is dropped, then the
     // rodio::OutputStreamHandle will stop working. This is the easiest way to
@@ -121,27 +545,443 @@ pub async fn run(
 
     std::thread::spawn(move || {
         let rt = runtime::Builder::new_current_thread()
+            .enable_time()
             .build()
             .expect("failed to construct tokio runtime");
 
         let result = rt.block_on(async {
-            let (_stream, stream_handle) =
-                OutputStream::try_default().context("no audio output stream available")?;
+            let device = config.device.as_deref();
+
+            let (tap_tx, tap_rx) = flume::unbounded();
+            let (tape_tx, tape_rx) = flume::unbounded();
+            let mut controls = Controls::new(config.compressor, tap_tx, tape_tx);
+            spawn_tape_writer(tape_rx, problem_tx.clone());
+
+            // the recording that is being made, and how many samples it needs
+            let mut pending: Option<(Vec<f32>, usize)> = None;
+            let mut bounces = 0;
+            let mut renders = 0;
+            // set to stop the render that is running
+            let mut render_cancel: Option<Arc<AtomicBool>> = None;
+            let mut last_level = Level::default();
+            let mut level_interval = tokio::time::interval(LEVEL_PERIOD);
+            let mut last_voices = 0;
+            let mut last_stats = (0, 0);
 
-            debug!("opened audio output");
+            let mut tracks: [Option<Track>; 2] = [None, None];
+            let mut last_status = [DeckStatus::default(); 2];
+
+            // if there is no output device, keep going without one so that
+            // the rest of the app is still usable
+            let opened = match &config.sink {
+                Some(sink) => Output::start_sink(sink, &controls, &event_tx),
+                None => open_output(&ct, device)
+                    .await
+                    .and_then(|stream| Output::start(stream, &controls, &event_tx, config.buffer_frames)),
+            };
+
+            let mut output = match opened {
+                Ok(output) => {
+                    debug!("opened audio output {:?}", output.device);
+                    let _ = event_tx.send(Event::OutputAvailable {
+                        device: output.device.clone(),
+                    });
+                    Some(output)
+                }
+                Err(err) => {
+                    warn!("no audio output, sounds will not play: {err:?}");
+                    let _ = event_tx.send(Event::OutputUnavailable {
+                        error: format!("{err:#}"),
+                    });
+                    None
+                }
+            };
+
+            let mut retry = tokio::time::interval(OUTPUT_RETRY_PERIOD);
+            retry.reset();
+
+            let mut watchdog = tokio::time::interval(OUTPUT_WATCHDOG_PERIOD);
+            watchdog.reset();
+            let mut last_callbacks = controls.callbacks.load(Ordering::Relaxed);
 
             loop {
                 tokio::select! {
                     _ = ct.cancelled() => { break; }
+                    _ = retry.tick(), if output.is_none() => {
+                        let opened = match &config.sink {
+                            Some(sink) => Output::start_sink(sink, &controls, &event_tx),
+                            None => open_stream(device)
+                                .map_err(anyhow::Error::from)
+                                .and_then(|stream| Output::start(stream, &controls, &event_tx, config.buffer_frames)),
+                        };
+
+                        if let Ok(opened) = opened {
+                            info!("opened audio output {:?}", opened.device);
+
+                            // the tracks that were loaded before the output
+                            // was lost pick up where they were
+                            for deck in Deck::ALL {
+                                if let Some(track) = &tracks[deck.index()] {
+                                    opened.add_track(deck, track.source(), &controls);
+                                }
+                            }
+
+                            let _ = event_tx.send(Event::OutputAvailable {
+                                device: opened.device.clone(),
+                            });
+                            let _ = event_tx.send(Event::OutputRestarted);
+                            output = Some(opened);
+                            last_callbacks = controls.callbacks.load(Ordering::Relaxed);
+                            watchdog.reset();
+                        }
+                    }
+                    _ = watchdog.tick(), if output.is_some() => {
+                        let callbacks = controls.callbacks.load(Ordering::Relaxed);
+
+                        if callbacks != last_callbacks {
+                            last_callbacks = callbacks;
+                            continue;
+                        }
+
+                        // dropping the output closes its stream, and the
+                        // voices and tracks that were playing on it
+                        let lost = output.take().unwrap();
+                        warn!("audio output {:?} stopped asking for samples, opening it again", lost.device);
+                        drop(lost);
+
+                        let _ = event_tx.send(Event::OutputUnavailable {
+                            error: "the output stopped responding".to_owned(),
+                        });
+                        let _ = problem_tx.send(Problem {
+                            subsystem: Some(Subsystem::Audio),
+                            message: "The audio output stopped responding, so it is being restarted".to_owned(),
+                        });
+
+                        // try straight away, and then every retry period
+                        retry = tokio::time::interval(OUTPUT_RETRY_PERIOD);
+                    }
+                    Ok((sound, pcm)) = ready_rx.recv_async() => {
+                        decoded[sound.id.0] = Some(pcm);
+                        let _ = event_tx.send(Event::SoundReady { sound });
+                    }
+                    Ok((kit, files)) = import_rx.recv_async() => {
+                        let mut sounds = vec![];
+                        let mut unready = vec![];
+
+                        for (path, hash) in files {
+                            let id = SoundId(decoded.len());
+                            decoded.push(None);
+                            let analysis = Analysis::default();
+                            sounds.push(SoundInfo::new(id, dir.clone(), path.clone(), analysis, false));
+                            unready.push(Unready { id, root: dir.clone(), path, hash });
+                        }
+
+                        info!("imported kit with {} sounds", sounds.len());
+
+                        let _ = event_tx.send(Event::KitImported { kit, sounds });
+
+                        // the analyses that the first decoder is adding are
+                        // only lost if both save at once, and then the
+                        // sounds are analysed again next time
+                        let index = AnalysisIndex::load(&index_path).unwrap_or_default();
+                        spawn_decoder(
+                            ct.clone(),
+                            unready,
+                            cache.clone(),
+                            index,
+                            index_path.clone(),
+                            ready_tx.clone(),
+                            problem_tx.clone(),
+                        );
+                    }
+                    Ok(block) = tap_rx.recv_async() => {
+                        let Some((samples, len)) = &mut pending else { continue; };
+                        samples.extend(block);
+
+                        if samples.len() < *len {
+                            continue;
+                        }
+
+                        let (mut samples, len) = pending.take().unwrap();
+                        samples.truncate(len);
+
+                        let recording = Pcm::new(samples);
+                        bounces += 1;
+                        let path = recordings_dir.join(format!("Bounce {bounces}"));
+                        let sound = SoundInfo::recorded(SoundId(decoded.len()), path, dir.clone(), &recording);
+
+                        info!("recorded {:?} of the master mix as {:?}", sound.duration, sound.id);
+
+                        decoded.push(Some(recording));
+                        let _ = event_tx.send(Event::Recorded { sound });
+                    }
+                    Ok(samples) = rendered_rx.recv_async() => {
+                        let rendering = Pcm::new(samples);
+                        renders += 1;
+                        let path = recordings_dir.join(format!("Render {renders}"));
+                        let sound = SoundInfo::recorded(SoundId(decoded.len()), path, dir.clone(), &rendering);
+
+                        info!("rendered {:?} of the loops as {:?}", sound.duration, sound.id);
+
+                        decoded.push(Some(rendering));
+                        let _ = event_tx.send(Event::Render(RenderStatus::Bounced(sound)));
+                    }
+                    _ = level_interval.tick() => {
+                        let mut level = controls.meter.lock().unwrap().take().unwrap_or_default();
+                        level.gain_reduction = f32::from_bits(controls.gain_reduction.load(Ordering::Relaxed));
+
+                        if level != Level::default() || last_level != Level::default() {
+                            let _ = event_tx.try_send(Event::Level(level));
+                        }
+
+                        last_level = level;
+
+                        let playing = controls.voices.load(Ordering::Relaxed);
+
+                        if playing != last_voices {
+                            let _ = event_tx.try_send(Event::Voices(playing));
+                            last_voices = playing;
+                        }
+
+                        let stats = (
+                            controls.period_frames.load(Ordering::Relaxed),
+                            controls.underruns.load(Ordering::Relaxed),
+                        );
+
+                        if stats != last_stats {
+                            let (period_frames, underruns) = stats;
+                            let _ = event_tx.try_send(Event::OutputStats { period_frames, underruns });
+                            last_stats = stats;
+                        }
+
+                        for deck in Deck::ALL {
+                            let Some(track) = &tracks[deck.index()] else { continue; };
+                            let status = track.status();
+
+                            if status != last_status[deck.index()] {
+                                let _ = event_tx.try_send(Event::Deck { deck, status });
+                                last_status[deck.index()] = status;
+                            }
+                        }
+                    }
                     cmd = cmd_rx.recv_async() => {
                         match cmd {
                             Ok(cmd) => match cmd {
-                                Command::Play { sound_id } => {
+                                Command::Play { sound_id, bus, start, settings, pad, trace } => {
+                                    let _span = trace_span!("play", ?sound_id).entered();
+
+                                    let Some(output) = &output else {
+                                        trace!("no audio output, dropping sound {sound_id:?}");
+                                        continue;
+                                    };
+
+                                    let Some(sound) = decoded.get(sound_id.0) else {
+                                        warn!("there is no sound {sound_id:?}");
+                                        continue;
+                                    };
+
+                                    let Some(sound) = sound else {
+                                        debug!("sound {sound_id:?} hasn't been decoded yet, dropping it");
+                                        continue;
+                                    };
+
                                     debug!("playing sound {sound_id:?}");
 
-                                    stream_handle
-                                        .play_raw(decoders[sound_id.0].clone())
-                                        .context("failed to play sound")?;
+                                    let trace = trace.map(|trace| Trace {
+                                        received: Some(Instant::now()),
+                                        ..trace
+                                    });
+
+                                    let voicing = voicing(&settings, sound.samples.len(), pad);
+
+                                    // the group is cut off on the other bus too
+                                    if let Some(group) = settings.choke_group {
+                                        let other = 1 - bus.index();
+                                        let _ = output.triggers[other].try_send(Trigger::Choke(group));
+                                    }
+
+                                    let trigger = Trigger::Start {
+                                        samples: sound.samples.clone(),
+                                        position: sample_index(start),
+                                        voicing,
+                                        trace,
+                                    };
+
+                                    if output.triggers[bus.index()].try_send(trigger).is_err() {
+                                        warn!("too many sounds triggered at once, dropping {sound_id:?}");
+                                    }
+                                }
+                                Command::Release { pad } => {
+                                    let Some(output) = &output else { continue; };
+
+                                    debug!("releasing pad {pad}");
+
+                                    for triggers in &output.triggers {
+                                        let _ = triggers.try_send(Trigger::Release(pad));
+                                    }
+                                }
+                                Command::PlayLoop { sound_id, bus, settings, voice, length, seam } => {
+                                    let (Some(output), Some(Some(sound))) = (&output, decoded.get(sound_id.0)) else {
+                                        debug!("can't loop sound {sound_id:?} yet, dropping it");
+                                        continue;
+                                    };
+
+                                    debug!("looping sound {sound_id:?} every {length:?}");
+
+                                    let trigger = loop_trigger(sound, &settings, voice, length, seam);
+                                    let _ = output.triggers[bus.index()].try_send(trigger);
+                                }
+                                Command::StopLoop { voice } => {
+                                    let Some(output) = &output else { continue; };
+
+                                    for triggers in &output.triggers {
+                                        let _ = triggers.try_send(Trigger::StopLoop(voice));
+                                    }
+                                }
+                                Command::SetVolume { volume: v } => {
+                                    debug!("setting master volume to {v}");
+                                    controls.volume.store(v.to_bits(), Ordering::Relaxed);
+                                }
+                                Command::SetCrossfade { position } => {
+                                    debug!("setting crossfader to {position}");
+
+                                    for (gain, value) in controls.bus_gains.iter().zip(crossfade_gains(position)) {
+                                        gain.store(value.to_bits(), Ordering::Relaxed);
+                                    }
+                                }
+                                Command::SetFilterCutoff { hz } => {
+                                    debug!("setting filter cutoff to {hz}Hz");
+                                    controls.filter_cutoff.store(hz, Ordering::Relaxed);
+                                }
+                                Command::SetEq { bus, kills } => {
+                                    debug!("setting EQ kills of bus {bus:?} to {kills:?}");
+                                    controls.eq_kills[bus.index()].store(kills.bits(), Ordering::Relaxed);
+                                }
+                                Command::SetFx { fx } => {
+                                    debug!("setting master effect to {fx:?}");
+                                    controls.fx.store(Fx::to_bits(fx), Ordering::Relaxed);
+                                }
+                                Command::SetChain { bus, effects } => {
+                                    debug!("setting effect chain of bus {bus:?} to {effects:?}");
+                                    controls.set_chain(bus, effects, output.as_ref());
+                                }
+                                Command::SetTempo { beat } => {
+                                    debug!("setting beat length of the effect chains to {beat:?}");
+                                    let micros = beat.as_micros().min(u64::MAX as u128) as u64;
+                                    controls.beat.store(micros, Ordering::Relaxed);
+                                }
+                                Command::Reload => debug!("sounds are already loaded"),
+                                Command::Deck { deck, command: DeckCommand::Load(path) } => {
+                                    // stops the track that was loaded before
+                                    tracks[deck.index()] = None;
+
+                                    let Some(output) = &output else {
+                                        debug!("no audio output, not loading {path:?}");
+                                        continue;
+                                    };
+
+                                    match Track::open(&path) {
+                                        Ok((track, source)) => {
+                                            info!("loaded {path:?} onto deck {deck}");
+
+                                            output.add_track(deck, source, &controls);
+                                            tracks[deck.index()] = Some(track);
+                                        }
+                                        Err(err) => {
+                                            warn!("failed to load track: {err:?}");
+
+                                            let name = path
+                                                .file_name()
+                                                .unwrap_or_default()
+                                                .to_string_lossy();
+                                            let _ = problem_tx.send(Problem {
+                                                subsystem: Some(Subsystem::Audio),
+                                                message: format!(
+                                                    "{name} failed to load: {:#}",
+                                                    err.root_cause()
+                                                ),
+                                            });
+                                        }
+                                    }
+                                }
+                                Command::Deck { deck, command } => match &tracks[deck.index()] {
+                                    Some(track) => track.command(command),
+                                    None => debug!("no track on deck {deck}, ignoring {command:?}"),
+                                },
+                                Command::Record { duration } => {
+                                    if output.is_none() {
+                                        debug!("no audio output, not recording");
+                                        continue;
+                                    }
+
+                                    // whole frames, so that the channels stay in order
+                                    let frames = (duration.as_secs_f64() * MIX_SAMPLE_RATE as f64) as usize;
+                                    let len = frames * MIX_CHANNELS as usize;
+
+                                    debug!("recording {duration:?} of the master mix");
+
+                                    pending = Some((Vec::with_capacity(len), len));
+                                    controls.recorder.start(len);
+                                }
+                                Command::Render { hits, length, target } => {
+                                    if let Some(cancel) = render_cancel.take() {
+                                        cancel.store(true, Ordering::Relaxed);
+                                    }
+
+                                    debug!("rendering {length:?} of {} sounds for {target:?}", hits.len());
+
+                                    let cancel = Arc::new(AtomicBool::new(false));
+                                    render_cancel = Some(cancel.clone());
+
+                                    spawn_render(
+                                        render_hits(&decoded, hits),
+                                        length,
+                                        target,
+                                        cancel,
+                                        event_tx.clone(),
+                                        rendered_tx.clone(),
+                                        problem_tx.clone(),
+                                        recordings_dir.clone(),
+                                    );
+                                }
+                                Command::StartTake { pattern } => {
+                                    let name = tape::take_name(SystemTime::now(), pattern.as_deref());
+                                    let path = recordings_dir.join("Tapes").join(format!("{name}.wav"));
+
+                                    controls.recorder.start_take(path);
+                                }
+                                Command::StopTape => controls.recorder.stop_tape(),
+                                Command::CancelRender => {
+                                    if let Some(cancel) = render_cancel.take() {
+                                        debug!("cancelling the render");
+                                        cancel.store(true, Ordering::Relaxed);
+                                    }
+                                }
+                                Command::ExportKit { path, kit, sounds } => {
+                                    let problem_tx = problem_tx.clone();
+
+                                    // sounds can take a while to copy, and
+                                    // this thread has to keep playing them
+                                    std::thread::spawn(move || {
+                                        match bundle::export(&path, &kit, &sounds) {
+                                            Ok(()) => info!("exported kit to {path:?}"),
+                                            Err(err) => report_kit_problem(&err, &problem_tx),
+                                        }
+                                    });
+                                }
+                                Command::ImportKit { path } => {
+                                    let dir = dir.clone();
+                                    let import_tx = import_tx.clone();
+                                    let problem_tx = problem_tx.clone();
+
+                                    std::thread::spawn(move || {
+                                        match import_kit(&path, &dir) {
+                                            Ok(imported) => {
+                                                let _ = import_tx.send(imported);
+                                            }
+                                            Err(err) => report_kit_problem(&err, &problem_tx),
+                                        }
+                                    });
                                 }
                             },
 
@@ -163,3 +1003,1858 @@ pub async fn run(
 
     Ok(())
 }
+
+/// The sounds that were found while loading.
+struct Loaded {
+    sounds: Vec<SoundInfo>,
+    /// the sounds that were in the cache, by id
+    decoded: Vec<Option<Pcm>>,
+    /// the sounds that still have to be decoded or analysed
+    unready: Vec<Unready>,
+    index: AnalysisIndex,
+    /// roots that couldn't be read
+    skipped: Vec<PathBuf>,
+    /// how many of the sounds are copies of one that was found earlier
+    duplicates: usize,
+}
+
+/// A sound that wasn't in the cache or the analysis index when it was found.
+struct Unready {
+    id: SoundId,
+    root: PathBuf,
+    path: PathBuf,
+    hash: u64,
+}
+
+/// Finds the sounds in `roots`, and reads the ones that have been decoded and
+/// analysed before from `cache` and the index at `index_path`, so that they
+/// can be played straight away. Files with the same contents share their
+/// samples, rather than taking up memory for each copy. Roots and sounds that
+/// can't be read are
+/// skipped, but it is an error if none of the sounds can be. Returns None if
+/// cancelled.
+async fn load_sounds(
+    ct: &CancellationToken,
+    roots: &[PathBuf],
+    read_timeout: Duration,
+    cache: &SampleCache,
+    index_path: &Path,
+    event_tx: &flume::Sender<Event>,
+    problem_tx: &flume::Sender<Problem>,
+) -> anyhow::Result<Option<Loaded>> {
+    let mut paths = vec![];
+    let mut skipped = vec![];
+    let mut first_err = None;
+
+    for root in roots {
+        info!("locating audio files in {root:?}");
+
+        // a root may be on a USB stick that isn't plugged in, or a network
+        // share that has hung, so the others are loaded without it
+        let found = match find_sounds(ct, root, read_timeout, problem_tx).await {
+            Ok(Some(found)) => found,
+            Ok(None) => return Ok(None),
+            Err(err) => {
+                warn!("skipping audio root: {err:?}");
+
+                let _ = problem_tx.send(Problem {
+                    subsystem: Some(Subsystem::Audio),
+                    message: format!("{err:#}, skipping it"),
+                });
+
+                skipped.push(root.clone());
+                first_err.get_or_insert(err);
+                continue;
+            }
+        };
+
+        paths.extend(found.into_iter().map(|path| (root.clone(), path)));
+    }
+
+    if let Some(err) = first_err {
+        if skipped.len() == roots.len() {
+            return Err(err.context("none of the audio directories could be read"));
+        }
+    }
+
+    if paths.is_empty() {
+        bail!("no .wav, .flac or .mp3 files in {roots:?}");
+    }
+
+    debug!("found {} audio files", paths.len());
+
+    tokio::task::block_in_place(|| {
+        let mut sounds = vec![];
+        let mut decoded: Vec<Option<Pcm>> = vec![];
+        let mut unready = vec![];
+        let mut first_err = None;
+        // the first sound that was found with each hash
+        let mut originals: HashMap<u64, SoundId> = HashMap::new();
+        let mut duplicates = 0;
+        let num_files = paths.len();
+        let index = AnalysisIndex::load(index_path).unwrap_or_else(|err| {
+            warn!("{err:?}, sounds will be analysed again");
+            AnalysisIndex::default()
+        });
+
+        for (progress, (root, path)) in paths.into_iter().enumerate() {
+            // hashing can take a while too, so give up promptly on ctrl+c
+            if ct.is_cancelled() {
+                return Ok(None);
+            }
+
+            let _ = event_tx.send(Event::LoadingProgress {
+                progress,
+                num_files,
+                path: path.clone(),
+            });
+
+            let hash = match content_hash(&path).context("failed to read audio file") {
+                Ok(hash) => hash,
+                Err(err) => {
+                    report_unloadable(&path, &err, problem_tx);
+                    first_err.get_or_insert(err);
+                    continue;
+                }
+            };
+
+            let id = SoundId(sounds.len());
+
+            // sample packs often have the same file under different names
+            let samples = match originals.get(&hash) {
+                Some(&original) => {
+                    trace!("{path:?} is a copy of {original:?}");
+                    duplicates += 1;
+                    decoded[original.0].clone()
+                }
+                None => {
+                    originals.insert(hash, id);
+                    cache.get(hash).map(Pcm::new)
+                }
+            };
+
+            match (index.get(hash), samples) {
+                (Some(analysis), Some(samples)) => {
+                    trace!("read {path:?} from the cache");
+                    sounds.push(SoundInfo::new(id, root, path, analysis.clone(), true));
+                    decoded.push(Some(samples));
+                }
+                (analysis, _) => {
+                    let analysis = analysis.cloned().unwrap_or_default();
+                    sounds.push(SoundInfo::new(
+                        id,
+                        root.clone(),
+                        path.clone(),
+                        analysis,
+                        false,
+                    ));
+                    decoded.push(None);
+                    unready.push(Unready {
+                        id,
+                        root,
+                        path,
+                        hash,
+                    });
+                }
+            }
+        }
+
+        match first_err {
+            Some(err) if sounds.is_empty() => {
+                Err(err.context("none of the audio files could be read"))
+            }
+            _ => Ok(Some(Loaded {
+                sounds,
+                decoded,
+                unready,
+                index,
+                skipped,
+                duplicates,
+            })),
+        }
+    })
+}
+
+/// Whether `path` is an audio file that can be loaded, going by its extension.
+fn is_sound(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|ext| ext.to_str()),
+        Some("wav") | Some("flac") | Some("mp3")
+    )
+}
+
+/// Finds the audio files in `root`, including the ones in zip archives,
+/// giving up if it takes longer than `timeout` to read the root or the next
+/// entry in it. Returns None if cancelled.
+async fn find_sounds(
+    ct: &CancellationToken,
+    root: &Path,
+    timeout: Duration,
+    problem_tx: &flume::Sender<Problem>,
+) -> anyhow::Result<Option<Vec<PathBuf>>> {
+    match tokio::time::timeout(timeout, tokio::fs::metadata(root)).await {
+        Ok(Ok(_)) => {}
+        Ok(Err(err)) => {
+            return Err(anyhow::Error::from(err).context(format!("{} isn't there", root.display())))
+        }
+        Err(_) => bail!("{} didn't respond within {timeout:?}", root.display()),
+    }
+
+    let mut walkdir = async_walkdir::WalkDir::new(root);
+    let mut paths = vec![];
+
+    loop {
+        tokio::select! {
+            _ = ct.cancelled() => return Ok(None),
+            entry = tokio::time::timeout(timeout, walkdir.next()) => {
+                match entry {
+                    Err(_) => bail!("reading {} took longer than {timeout:?}", root.display()),
+                    Ok(Some(entry)) => {
+                        let entry = entry.with_context(|| format!("failed to read {root:?}"))?;
+                        let path = entry.path();
+
+                        let is_file = entry.file_type().await.is_ok_and(|t| t.is_file());
+
+                        // a folder named like an archive is still a folder
+                        if is_file && archive::is_archive(&path) {
+                            // reading the index of an archive is quick next to
+                            // decoding what is in it
+                            match tokio::task::block_in_place(|| archive::list(&path, is_sound)) {
+                                Ok(files) => {
+                                    trace!("found {} files in {path:?}", files.len());
+                                    paths.extend(files);
+                                }
+                                Err(err) => {
+                                    warn!("skipping archive {path:?}: {err}");
+
+                                    let name = path.file_name().unwrap_or_default().to_string_lossy();
+                                    let _ = problem_tx.send(Problem {
+                                        subsystem: Some(Subsystem::Audio),
+                                        message: format!("{name} couldn't be opened: {err}"),
+                                    });
+                                }
+                            }
+                        } else if is_sound(&path) {
+                            trace!("found file {path:?}");
+                            paths.push(path.to_path_buf());
+                        }
+                    }
+                    Ok(None) => return Ok(Some(paths)),
+                }
+            }
+        }
+    }
+}
+
+/// Checks every [`ROOT_CHECK_PERIOD`] whether the audio roots can be read,
+/// and tells the engine when one goes away or comes back. `available` is
+/// whether each root could be read while loading.
+async fn watch_roots(
+    ct: CancellationToken,
+    roots: Vec<PathBuf>,
+    mut available: Vec<bool>,
+    timeout: Duration,
+    event_tx: flume::Sender<Event>,
+    problem_tx: flume::Sender<Problem>,
+) {
+    let mut interval = tokio::time::interval(ROOT_CHECK_PERIOD);
+
+    loop {
+        tokio::select! {
+            _ = ct.cancelled() => return,
+            _ = interval.tick() => {}
+        }
+
+        for (root, was_available) in roots.iter().zip(&mut available) {
+            // a hung network share counts as gone
+            let now_available = matches!(
+                tokio::time::timeout(timeout, tokio::fs::metadata(root)).await,
+                Ok(Ok(_))
+            );
+
+            if now_available == *was_available {
+                continue;
+            }
+
+            if now_available {
+                info!("audio root {root:?} is back");
+            } else {
+                warn!("audio root {root:?} has gone away");
+
+                let _ = problem_tx.send(Problem {
+                    subsystem: Some(Subsystem::Audio),
+                    message: format!(
+                        "{} has gone away, sounds in it that aren't loaded can't be played",
+                        root.display()
+                    ),
+                });
+            }
+
+            *was_available = now_available;
+            let _ = event_tx.send(Event::RootAvailability {
+                root: root.clone(),
+                available: now_available,
+            });
+        }
+    }
+}
+
+/// The sounds that a render plays on each bus, with shared copies of their
+/// samples. Sounds that haven't been decoded are left out.
+fn render_hits(decoded: &[Option<Pcm>], hits: Vec<RenderHit>) -> [Vec<Hit>; 2] {
+    let mut buses = [vec![], vec![]];
+
+    for hit in hits {
+        let Some(Some(sound)) = decoded.get(hit.sound.0) else {
+            continue;
+        };
+
+        buses[hit.bus.index()].push(Hit {
+            frame: (hit.at.as_secs_f64() * MIX_SAMPLE_RATE as f64) as usize,
+            samples: sound.samples.clone(),
+            position: sample_index(hit.settings.trim_start()),
+            voicing: voicing(&hit.settings, sound.samples.len(), None),
+        });
+    }
+
+    buses
+}
+
+/// Renders `length` of the sounds on each bus on a thread of its own,
+/// reporting how it is getting on until `cancel` is set. A render onto a pad
+/// is sent back on `rendered_tx` to be added to the sounds, and stems are
+/// written to a new folder in `recordings_dir`, reporting a failure to
+/// `problem_tx`.
+#[allow(clippy::too_many_arguments)]
+fn spawn_render(
+    buses: [Vec<Hit>; 2],
+    length: Duration,
+    target: RenderTarget,
+    cancel: Arc<AtomicBool>,
+    event_tx: flume::Sender<Event>,
+    rendered_tx: flume::Sender<Vec<f32>>,
+    problem_tx: flume::Sender<Problem>,
+    recordings_dir: PathBuf,
+) {
+    let frames = (length.as_secs_f64() * MIX_SAMPLE_RATE as f64) as usize;
+
+    std::thread::spawn(move || {
+        // stems are rendered one bus at a time
+        let renders: Vec<_> = match target {
+            RenderTarget::Pad(..) => vec![buses.concat()],
+            RenderTarget::Stems => buses.into_iter().collect(),
+        };
+        let count = renders.len();
+        let mut reported = 0.;
+        let mut rendered = vec![];
+
+        for (i, hits) in renders.into_iter().enumerate() {
+            let samples = render::render(hits, frames, |done| {
+                let done = (i as f32 + done) / count as f32;
+
+                // often enough for a progress bar
+                if done - reported >= 0.05 {
+                    let _ = event_tx.send(Event::Render(RenderStatus::Progress(done)));
+                    reported = done;
+                }
+
+                !cancel.load(Ordering::Relaxed)
+            });
+
+            let Some(samples) = samples else {
+                let _ = event_tx.send(Event::Render(RenderStatus::Cancelled));
+                return;
+            };
+
+            rendered.push(samples);
+        }
+
+        if target != RenderTarget::Stems {
+            let _ = rendered_tx.send(rendered.remove(0));
+            return;
+        }
+
+        let status = match write_stems(&recordings_dir, &rendered) {
+            Ok(dir) => {
+                info!("exported stems to {dir:?}");
+                RenderStatus::Exported(dir)
+            }
+            Err(err) => {
+                warn!("failed to export stems: {err:?}");
+                let message = format!("failed to export stems: {err:#}");
+
+                let _ = problem_tx.send(Problem {
+                    subsystem: Some(Subsystem::Audio),
+                    message: message.clone(),
+                });
+
+                RenderStatus::Failed(message)
+            }
+        };
+
+        let _ = event_tx.send(Event::Render(status));
+    });
+}
+
+/// Writes the takes of the tape of the master mix on a thread of its own,
+/// until the audio subsystem stops.
+fn spawn_tape_writer(rx: flume::Receiver<TapeMessage>, problem_tx: flume::Sender<Problem>) {
+    std::thread::spawn(move || {
+        tape::write(rx, MIX_SAMPLE_RATE, |path, err| {
+            warn!("failed to tape to {path:?}: {err}");
+
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            let _ = problem_tx.send(Problem {
+                subsystem: Some(Subsystem::Audio),
+                message: format!("{name} couldn't be taped to: {err}"),
+            });
+        });
+    });
+}
+
+/// Writes a WAV file for each bus to a new folder in `recordings_dir`, and
+/// returns the folder.
+fn write_stems(recordings_dir: &Path, stems: &[Vec<f32>]) -> anyhow::Result<PathBuf> {
+    let dir = (1..)
+        .map(|n| recordings_dir.join(format!("Stems {n}")))
+        .find(|dir| !dir.exists())
+        .unwrap();
+
+    std::fs::create_dir_all(&dir).with_context(|| format!("failed to create {dir:?}"))?;
+
+    for (bus, samples) in [Bus::A, Bus::B].into_iter().zip(stems) {
+        let path = dir.join(format!("Bus {bus:?}.wav"));
+        render::write_wav(&path, samples, MIX_SAMPLE_RATE)
+            .with_context(|| format!("failed to write {path:?}"))?;
+    }
+
+    Ok(dir)
+}
+
+/// Index of the sample that is `time` into a sound, at the start of a frame
+/// so that the channels stay in order.
+fn sample_index(time: Duration) -> usize {
+    (time.as_secs_f64() * MIX_SAMPLE_RATE as f64) as usize * MIX_CHANNELS as usize
+}
+
+/// Starts `sound` looping every `length` from the pad's trimmed start, or at
+/// its trimmed end if that comes first. A pad that is pitched goes through
+/// more or less of the sound in that time.
+fn loop_trigger(
+    sound: &Pcm,
+    settings: &PadSettings,
+    looper: u32,
+    length: Duration,
+    seam: Duration,
+) -> Trigger {
+    let start = sample_index(settings.trim_start());
+    let mut voicing = voicing(settings, sound.samples.len(), None);
+    let end = voicing.end.unwrap_or(sound.samples.len());
+
+    voicing.end = Some(end.min(start + sample_index(length.mul_f32(voicing.rate))));
+    voicing.looped = Some(start);
+    voicing.seam = sample_index(seam) / MIX_CHANNELS as usize;
+    voicing.looper = Some(looper);
+
+    Trigger::Start {
+        samples: sound.samples.clone(),
+        position: start,
+        voicing,
+        trace: None,
+    }
+}
+
+/// How a sound of `len` samples is played with a pad's settings.
+fn voicing(settings: &PadSettings, len: usize, pad: Option<u8>) -> Voicing {
+    let end =
+        (settings.trim_end_ms > 0).then(|| len.saturating_sub(sample_index(settings.trim_end())));
+
+    Voicing {
+        gains: settings.gains(),
+        rate: settings.rate(),
+        end,
+        choke: settings.choke_group,
+        pad,
+        ..Voicing::default()
+    }
+}
+
+/// Decodes `unready` on a thread of its own, and then saves the analyses that
+/// were added to `index`.
+fn spawn_decoder(
+    ct: CancellationToken,
+    unready: Vec<Unready>,
+    cache: SampleCache,
+    mut index: AnalysisIndex,
+    index_path: PathBuf,
+    ready_tx: flume::Sender<(SoundInfo, Pcm)>,
+    problem_tx: flume::Sender<Problem>,
+) {
+    std::thread::spawn(move || {
+        decode_unready(&ct, unready, &cache, &mut index, &ready_tx, &problem_tx);
+
+        if let Err(err) = index.save(&index_path) {
+            warn!("failed to save analysis index: {err:?}");
+        }
+    });
+}
+
+/// Imports the kit bundle at `path` into `dir`, and hashes the sounds in it
+/// so that they can be decoded.
+fn import_kit(path: &Path, dir: &Path) -> anyhow::Result<(Kit, Vec<(PathBuf, u64)>)> {
+    let (kit, paths) = bundle::import(path, dir)?;
+    let mut files = vec![];
+
+    for path in paths.into_iter().filter(|path| is_sound(path)) {
+        let hash = content_hash(&path).with_context(|| format!("failed to read {path:?}"))?;
+        files.push((path, hash));
+    }
+
+    Ok((kit, files))
+}
+
+fn report_kit_problem(err: &anyhow::Error, problem_tx: &flume::Sender<Problem>) {
+    warn!("{err:?}");
+
+    let _ = problem_tx.send(Problem {
+        subsystem: Some(Subsystem::Audio),
+        message: format!("{err:#}"),
+    });
+}
+
+/// Decodes and analyses the sounds that weren't ready when loading ended, and
+/// sends each one to `ready_tx` as soon as it can be played. Sounds with the
+/// same content are decoded once, from whichever of them is in a root that is
+/// there, and share the samples. Sounds that are all in roots that have gone
+/// away wait until one of them is back.
+fn decode_unready(
+    ct: &CancellationToken,
+    unready: Vec<Unready>,
+    cache: &SampleCache,
+    index: &mut AnalysisIndex,
+    ready_tx: &flume::Sender<(SoundInfo, Pcm)>,
+    problem_tx: &flume::Sender<Problem>,
+) {
+    // the copies of each sound, in the order that the first of them was found
+    let mut copies: Vec<Vec<Unready>> = vec![];
+    let mut by_hash: HashMap<u64, usize> = HashMap::new();
+
+    for sound in unready {
+        match by_hash.get(&sound.hash) {
+            Some(&i) => copies[i].push(sound),
+            None => {
+                by_hash.insert(sound.hash, copies.len());
+                copies.push(vec![sound]);
+            }
+        }
+    }
+
+    loop {
+        let mut waiting = vec![];
+
+        for sounds in copies {
+            if ct.is_cancelled() {
+                return;
+            }
+
+            let Some(source) = sounds.iter().find(|sound| sound.root.exists()) else {
+                waiting.push(sounds);
+                continue;
+            };
+
+            match load_sound(&source.path, source.hash, cache, index) {
+                Ok((pcm, analysis)) => {
+                    for sound in sounds {
+                        let info = SoundInfo::new(
+                            sound.id,
+                            sound.root,
+                            sound.path,
+                            analysis.clone(),
+                            true,
+                        );
+
+                        // the audio subsystem has stopped
+                        if ready_tx.send((info, pcm.clone())).is_err() {
+                            return;
+                        }
+                    }
+                }
+                // it was unplugged while the sound was being decoded
+                Err(_) if !source.root.exists() => waiting.push(sounds),
+                Err(err) => report_unloadable(&source.path, &err, problem_tx),
+            }
+        }
+
+        if waiting.is_empty() {
+            break;
+        }
+
+        std::thread::sleep(ROOT_CHECK_PERIOD);
+        copies = waiting;
+    }
+
+    debug!("decoded every audio file");
+}
+
+fn report_unloadable(path: &Path, err: &anyhow::Error, problem_tx: &flume::Sender<Problem>) {
+    warn!("failed to load sound: {err:?}");
+
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let _ = problem_tx.send(Problem {
+        subsystem: Some(Subsystem::Audio),
+        message: format!("{name} failed to decode: {:#}", err.root_cause()),
+    });
+}
+
+/// Decodes the sound with the given content hash, or reads it from `cache` if
+/// it has been decoded before, and looks up its analysis in `index`. Sounds
+/// that aren't in the index yet are analysed and added to it.
+fn load_sound(
+    path: &Path,
+    hash: u64,
+    cache: &SampleCache,
+    index: &mut AnalysisIndex,
+) -> anyhow::Result<(Pcm, Analysis)> {
+    let sound = match cache.get(hash) {
+        Some(samples) => {
+            trace!("read {path:?} from the cache");
+            Pcm::new(samples)
+        }
+        None => {
+            let sound = decode_sound(path)?;
+
+            // the sound still plays without a cache entry, it just has to be
+            // decoded again next time
+            if let Err(err) = cache.put(hash, &sound.samples) {
+                warn!("failed to cache {path:?}: {err}");
+            }
+
+            sound
+        }
+    };
+
+    let analysis = match index.get(hash) {
+        Some(analysis) => analysis.clone(),
+        None => {
+            let analysis = analyse(&sound);
+            index.insert(hash, analysis.clone());
+            analysis
+        }
+    };
+
+    Ok((sound, analysis))
+}
+
+/// Works out the length, waveform overview, loudness and key of a sound.
+fn analyse(sound: &Pcm) -> Analysis {
+    let duration = sound.total_duration().unwrap_or_default();
+
+    Analysis {
+        duration,
+        peaks: peaks(sound, duration),
+        loudness_db: analysis::loudness_db(&sound.samples),
+        key: chroma::detect_key(sound.clone(), MIX_SAMPLE_RATE, MIX_CHANNELS),
+    }
+}
+
+/// Decodes a sound and converts it to the mix's sample rate and channels, so
+/// that nothing has to be converted while it plays.
+fn decode_sound(path: &Path) -> anyhow::Result<Pcm> {
+    let reader = archive::open(path).context("failed to open audio file")?;
+    let decoder =
+        Decoder::new(reader).with_context(|| format!("failed to decode audio file {:?}", path))?;
+
+    let samples: Vec<f32> = UniformSourceIterator::new(
+        decoder.convert_samples::<f32>(),
+        MIX_CHANNELS,
+        MIX_SAMPLE_RATE,
+    )
+    .collect();
+
+    Ok(Pcm::new(samples))
+}
+
+/// Computes the waveform overview of a sound.
+fn peaks(sound: &(impl Source<Item = f32> + Clone), duration: Duration) -> Vec<f32> {
+    let samples = duration.as_secs_f64() * sound.sample_rate() as f64 * sound.channels() as f64;
+    let chunk = (samples as usize / PEAK_POINTS).max(1);
+
+    let mut peaks = vec![0f32; PEAK_POINTS];
+
+    for (i, sample) in sound.clone().enumerate() {
+        let peak = &mut peaks[(i / chunk).min(PEAK_POINTS - 1)];
+        *peak = peak.max(sample.abs().min(1.));
+    }
+
+    peaks
+}
+
+/// Names of the output devices that sounds can be played on.
+pub fn output_devices() -> Vec<String> {
+    match rodio::cpal::default_host().output_devices() {
+        Ok(devices) => devices.filter_map(|device| device.name().ok()).collect(),
+        Err(err) => {
+            warn!("failed to list audio output devices: {err}");
+            vec![]
+        }
+    }
+}
+
+/// An output device, its name and the config that it plays with by default.
+type Stream = (String, cpal::Device, SupportedStreamConfig);
+
+/// Finds the output device with the given name, or the default one.
+fn open_stream(device: Option<&str>) -> Result<Stream, StreamError> {
+    let host = cpal::default_host();
+
+    let (name, device) = match device {
+        None => {
+            let device = host.default_output_device().ok_or(StreamError::NoDevice)?;
+            let name = device.name().unwrap_or_else(|_| "default".to_owned());
+            (name, device)
+        }
+        Some(name) => {
+            let device = host
+                .output_devices()
+                .ok()
+                .and_then(|mut devices| devices.find(|d| d.name().is_ok_and(|n| n == name)))
+                .ok_or(StreamError::NoDevice)?;
+            (name.to_owned(), device)
+        }
+    };
+
+    let config = device.default_output_config()?;
+    Ok((name, device, config))
+}
+
+/// Opens the output device. ALSA may not be ready yet if the Pi has just
+/// booted, so this retries with backoff before giving up.
+async fn open_output(ct: &CancellationToken, device: Option<&str>) -> anyhow::Result<Stream> {
+    let mut backoff = OUTPUT_BACKOFF;
+    let mut attempt = 1;
+
+    loop {
+        match open_stream(device) {
+            Ok(output) => return Ok(output),
+            Err(err) if attempt < OUTPUT_ATTEMPTS && !ct.is_cancelled() => {
+                debug!("failed to open audio output (attempt {attempt}), retrying in {backoff:?}: {err}");
+
+                tokio::select! {
+                    _ = ct.cancelled() => {}
+                    _ = tokio::time::sleep(backoff) => {}
+                }
+
+                backoff *= 2;
+                attempt += 1;
+            }
+            Err(err) => return Err(err).context("no audio output stream available"),
+        }
+    }
+}
+
+/// Parts of the mix that are shared with the output stream's thread, which
+/// picks up changes to them as it plays. Numbers are stored as bits in atomics
+/// so that it never has to wait for them.
+struct Controls {
+    /// master volume, as f32 bits
+    volume: Arc<AtomicU32>,
+    /// gain of each bus from the crossfader, as f32 bits
+    bus_gains: [Arc<AtomicU32>; 2],
+    filter_cutoff: Arc<AtomicU32>,
+    /// EQ kills of each bus, as Kills::bits
+    eq_kills: [Arc<AtomicU8>; 2],
+    /// effect on the master mix, as Fx::to_bits
+    fx: Arc<AtomicU64>,
+    /// effect chain of each bus, which is made again when the output is
+    chains: [Vec<EffectConfig>; 2],
+    effects: Registry,
+    /// length of a beat in microseconds, for the effect chains
+    beat: Arc<AtomicU64>,
+    compressor: CompressorConfig,
+    /// gain reduction of the master compressor, as f32 bits
+    gain_reduction: Arc<AtomicU32>,
+    meter: Arc<Mutex<Meter>>,
+    recorder: Arc<Recorder>,
+    /// how many sounds are playing
+    voices: Arc<AtomicUsize>,
+    /// frames that the output device asked for last time
+    period_frames: Arc<AtomicUsize>,
+    /// how many times the output device has run out of samples
+    underruns: Arc<AtomicUsize>,
+    /// how many times the output device has asked for samples, which stops
+    /// going up if the stream has died
+    callbacks: Arc<AtomicU64>,
+}
+
+impl Controls {
+    /// Makes the effect chain of `bus`, at the current tempo. A chain that
+    /// can't be made is left empty.
+    fn chain(&self, bus: Bus) -> Chain {
+        let configs = &self.chains[bus.index()];
+
+        match self.effects.chain(configs, MIX_CHANNELS, MIX_SAMPLE_RATE) {
+            Ok(mut chain) => {
+                let beat = Duration::from_micros(self.beat.load(Ordering::Relaxed));
+                chain.set_tempo(beat);
+                chain
+            }
+            Err(err) => {
+                warn!("failed to make the effect chain of bus {bus:?}: {err:?}");
+                Chain::default()
+            }
+        }
+    }
+
+    /// Changes the effect chain of `bus`. If it has the same effects as
+    /// before, their parameters are changed in place, so that e.g. a delay
+    /// keeps its tail. Otherwise a new chain is made here, so that the output
+    /// thread doesn't allocate.
+    fn set_chain(&mut self, bus: Bus, effects: Vec<EffectConfig>, output: Option<&Output>) {
+        let old = std::mem::replace(&mut self.chains[bus.index()], effects);
+        let Some(output) = output else { return; };
+        let chain_tx = &output.chains[bus.index()];
+
+        match self.effects.updates(&old, &self.chains[bus.index()]) {
+            Some(updates) => {
+                for (effect, name, value) in updates {
+                    let _ = chain_tx.send(ChainUpdate::Param {
+                        effect,
+                        name,
+                        value,
+                    });
+                }
+            }
+            None => {
+                let _ = chain_tx.send(ChainUpdate::Replace(self.chain(bus)));
+            }
+        }
+    }
+
+    fn new(
+        compressor: CompressorConfig,
+        tap_tx: flume::Sender<Vec<f32>>,
+        tape_tx: flume::Sender<TapeMessage>,
+    ) -> Self {
+        let unity = || Arc::new(AtomicU32::new(1f32.to_bits()));
+
+        Self {
+            volume: unity(),
+            bus_gains: [unity(), unity()],
+            filter_cutoff: Arc::new(AtomicU32::new(FILTER_CUTOFF_MAX)),
+            eq_kills: [Arc::new(AtomicU8::new(0)), Arc::new(AtomicU8::new(0))],
+            fx: Arc::new(AtomicU64::new(0)),
+            chains: Default::default(),
+            effects: Registry::builtin(),
+            beat: Arc::new(AtomicU64::new(1_000_000)),
+            compressor,
+            gain_reduction: Arc::new(AtomicU32::new(0)),
+            meter: Arc::new(Mutex::new(Meter::default())),
+            recorder: Arc::new(Recorder::new(tap_tx, tape_tx)),
+            voices: Arc::new(AtomicUsize::new(0)),
+            period_frames: Arc::new(AtomicUsize::new(0)),
+            underruns: Arc::new(AtomicUsize::new(0)),
+            callbacks: Arc::new(AtomicU64::new(0)),
+        }
+    }
+}
+
+/// An open output stream, playing a mixer that decks are added to. Sounds are
+/// triggered on the voices of their bus, which play through the bus's EQ into
+/// the mixer. The stream stops when this is dropped.
+struct Output {
+    device: String,
+    /// the device's stream, or the thread playing into a sink in its place
+    _stream: Option<cpal::Stream>,
+    _sink: Option<SinkThread>,
+    mixer: Arc<DynamicMixerController<f32>>,
+    triggers: [flume::Sender<Trigger>; 2],
+    /// where new effect chains are handed over to the buses
+    chains: [flume::Sender<ChainUpdate>; 2],
+}
+
+/// The parts of the mix that [`Output`] keeps, and the master mix that is
+/// played.
+type Mix<S> = (
+    Arc<DynamicMixerController<f32>>,
+    [flume::Sender<Trigger>; 2],
+    [flume::Sender<ChainUpdate>; 2],
+    S,
+);
+
+impl Output {
+    /// Starts playing the mix on `stream`, with periods of `buffer_frames` if
+    /// it is set and the device can do it.
+    fn start(
+        (name, device, supported): Stream,
+        controls: &Controls,
+        event_tx: &flume::Sender<Event>,
+        buffer_frames: Option<u32>,
+    ) -> anyhow::Result<Self> {
+        let (mixer, triggers, chains, master) = Self::mix(controls, event_tx);
+
+        let buffer_size = match (buffer_frames, supported.buffer_size()) {
+            (Some(frames), SupportedBufferSize::Range { min, max }) => {
+                BufferSize::Fixed(frames.clamp(*min, *max))
+            }
+            (Some(frames), SupportedBufferSize::Unknown) => BufferSize::Fixed(frames),
+            (None, _) => BufferSize::Default,
+        };
+        let config = StreamConfig {
+            channels: supported.channels(),
+            sample_rate: supported.sample_rate(),
+            buffer_size,
+        };
+
+        info!(
+            "playing on {name:?} at {}Hz, {} channels of {:?}, with {:?} periods (the device can do {:?})",
+            config.sample_rate.0,
+            config.channels,
+            supported.sample_format(),
+            config.buffer_size,
+            supported.buffer_size(),
+        );
+
+        let mut callback = Callback {
+            master: UniformSourceIterator::new(master, config.channels, config.sample_rate.0),
+            channels: config.channels as usize,
+            sample_rate: config.sample_rate.0,
+            expected: None,
+            period_frames: controls.period_frames.clone(),
+            underruns: controls.underruns.clone(),
+            callbacks: controls.callbacks.clone(),
+        };
+        let underruns = controls.underruns.clone();
+        let on_error = move |err| {
+            warn!("audio output error: {err}");
+            underruns.fetch_add(1, Ordering::Relaxed);
+        };
+
+        let stream = match supported.sample_format() {
+            SampleFormat::F32 => device.build_output_stream(
+                &config,
+                move |data: &mut [f32], info| callback.fill(data, info),
+                on_error,
+            ),
+            SampleFormat::I16 => device.build_output_stream(
+                &config,
+                move |data: &mut [i16], info| callback.fill(data, info),
+                on_error,
+            ),
+            SampleFormat::U16 => device.build_output_stream(
+                &config,
+                move |data: &mut [u16], info| callback.fill(data, info),
+                on_error,
+            ),
+        }
+        .context("failed to open output stream")?;
+
+        stream.play().context("failed to start master mix")?;
+
+        Ok(Self {
+            device: name,
+            _stream: Some(stream),
+            _sink: None,
+            mixer,
+            triggers,
+            chains,
+        })
+    }
+
+    /// Starts playing the mix into `sink`, in place of a device.
+    fn start_sink(
+        sink: &AudioSink,
+        controls: &Controls,
+        event_tx: &flume::Sender<Event>,
+    ) -> anyhow::Result<Self> {
+        let (mixer, triggers, chains, master) = Self::mix(controls, event_tx);
+
+        let name = match sink {
+            AudioSink::Null => "null sink".to_owned(),
+            AudioSink::File(path) => format!("file {path:?}"),
+        };
+
+        info!("playing into the {name}");
+
+        Ok(Self {
+            device: name,
+            _stream: None,
+            _sink: Some(SinkThread::spawn(sink, master, controls)?),
+            mixer,
+            triggers,
+            chains,
+        })
+    }
+
+    /// Makes the mixer, the buses that play into it, and the master mix
+    /// that comes out of it.
+    fn mix(
+        controls: &Controls,
+        event_tx: &flume::Sender<Event>,
+    ) -> Mix<impl Source<Item = f32> + Send + 'static> {
+        let (mixer, mix) = dynamic_mixer::mixer(MIX_CHANNELS, MIX_SAMPLE_RATE);
+
+        // the mixer ends when it runs out of sounds, so give it one that
+        // doesn't
+        mixer.add(Zero::<f32>::new(MIX_CHANNELS, MIX_SAMPLE_RATE));
+
+        let [(trigger_a, chain_a), (trigger_b, chain_b)] = [Bus::A, Bus::B].map(|bus| {
+            let (trigger_tx, trigger_rx) = flume::bounded(MAX_VOICES);
+            let (chain_tx, chain_rx) = flume::unbounded();
+
+            let volume = controls.volume.clone();
+            let bus_gain = controls.bus_gains[bus.index()].clone();
+            let filter_cutoff = controls.filter_cutoff.clone();
+
+            // the filter and gain are the same for every sound on the bus, so
+            // they are put on the bus rather than on each sound
+            let voices = Voices::new(trigger_rx, controls.voices.clone(), event_tx.clone())
+                .low_pass(FILTER_CUTOFF_MAX)
+                .amplify(1.)
+                .periodic_access(Duration::from_millis(5), move |src| {
+                    src.set_factor(
+                        f32::from_bits(volume.load(Ordering::Relaxed))
+                            * f32::from_bits(bus_gain.load(Ordering::Relaxed)),
+                    );
+                    src.inner_mut()
+                        .to_low_pass(filter_cutoff.load(Ordering::Relaxed));
+                });
+
+            mixer.add(Chained::new(
+                Equalized::new(voices, controls.eq_kills[bus.index()].clone()),
+                controls.chain(bus),
+                chain_rx,
+                controls.beat.clone(),
+            ));
+
+            (trigger_tx, chain_tx)
+        });
+
+        let master = Metered::new(
+            Tapped::new(
+                Compressed::new(
+                    Effected::new(mix, controls.fx.clone()),
+                    &controls.compressor,
+                    controls.gain_reduction.clone(),
+                ),
+                controls.recorder.clone(),
+            ),
+            controls.meter.clone(),
+        );
+
+        (mixer, [trigger_a, trigger_b], [chain_a, chain_b], master)
+    }
+
+    /// Plays a deck's track through the mixer, at the volume of the deck's
+    /// bus.
+    fn add_track(&self, deck: Deck, source: TrackSource, controls: &Controls) {
+        let volume = controls.volume.clone();
+        let bus_gain = controls.bus_gains[Bus::from(deck).index()].clone();
+        let source = source
+            .amplify(1.)
+            .periodic_access(Duration::from_millis(5), move |src| {
+                src.set_factor(
+                    f32::from_bits(volume.load(Ordering::Relaxed))
+                        * f32::from_bits(bus_gain.load(Ordering::Relaxed)),
+                )
+            });
+
+        self.mixer.add(source);
+    }
+}
+
+/// Plays the master mix into a sink on its own thread, a period at a time and
+/// as fast as a device would ask for it. The thread stops, and the file is
+/// finished, when this is dropped.
+struct SinkThread {
+    stop: Arc<AtomicBool>,
+    join: Option<std::thread::JoinHandle<()>>,
+}
+
+impl SinkThread {
+    fn spawn(
+        sink: &AudioSink,
+        mut master: impl Source<Item = f32> + Send + 'static,
+        controls: &Controls,
+    ) -> anyhow::Result<Self> {
+        let mut file = match sink {
+            AudioSink::Null => None,
+            AudioSink::File(path) => Some(
+                tape::WavWriter::create(path, MIX_SAMPLE_RATE)
+                    .with_context(|| format!("failed to create {path:?}"))?,
+            ),
+        };
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let period_frames = controls.period_frames.clone();
+        let callbacks = controls.callbacks.clone();
+        let period = Duration::from_secs_f64(SINK_PERIOD_FRAMES as f64 / MIX_SAMPLE_RATE as f64);
+
+        let join = std::thread::spawn({
+            let stop = stop.clone();
+            move || {
+                let mut buffer = vec![0.; SINK_PERIOD_FRAMES * MIX_CHANNELS as usize];
+                let mut next = Instant::now();
+
+                while !stop.load(Ordering::Relaxed) {
+                    for sample in buffer.iter_mut() {
+                        *sample = master.next().unwrap_or(0.);
+                    }
+
+                    period_frames.store(SINK_PERIOD_FRAMES, Ordering::Relaxed);
+                    callbacks.fetch_add(1, Ordering::Relaxed);
+
+                    if let Some(Err(err)) = file.as_mut().map(|file| file.write(&buffer)) {
+                        warn!("failed to write the mix, it will be thrown away: {err}");
+                        file = None;
+                    }
+
+                    next += period;
+                    std::thread::sleep(next.saturating_duration_since(Instant::now()));
+                }
+
+                if let Some(Err(err)) = file.map(tape::WavWriter::finish) {
+                    warn!("failed to finish writing the mix: {err}");
+                }
+            }
+        });
+
+        Ok(Self {
+            stop,
+            join: Some(join),
+        })
+    }
+}
+
+impl Drop for SinkThread {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+
+        if let Some(join) = self.join.take() {
+            let _ = join.join();
+        }
+    }
+}
+
+/// Fills the output device's buffer from the master mix, and keeps track of
+/// the times that the device ran out of samples.
+struct Callback<S: Source<Item = f32>> {
+    /// the master mix, converted to the device's sample rate and channels
+    master: UniformSourceIterator<S, f32>,
+    channels: usize,
+    sample_rate: u32,
+    /// when the next buffer should start playing, if it follows straight on
+    /// from the last one
+    expected: Option<StreamInstant>,
+    period_frames: Arc<AtomicUsize>,
+    underruns: Arc<AtomicUsize>,
+    callbacks: Arc<AtomicU64>,
+}
+
+impl<S: Source<Item = f32>> Callback<S> {
+    fn fill<T: cpal::Sample>(&mut self, data: &mut [T], info: &OutputCallbackInfo) {
+        for sample in data.iter_mut() {
+            *sample = T::from(&self.master.next().unwrap_or(0.));
+        }
+
+        let frames = data.len() / self.channels;
+        let period = Duration::from_secs_f64(frames as f64 / self.sample_rate as f64);
+        self.period_frames.store(frames, Ordering::Relaxed);
+        self.callbacks.fetch_add(1, Ordering::Relaxed);
+
+        // if this buffer starts playing well after the last one finished,
+        // there was a gap in between
+        let playback = info.timestamp().playback;
+        let late = self
+            .expected
+            .and_then(|expected| playback.duration_since(&expected))
+            .unwrap_or_default();
+
+        if late > period / 2 {
+            self.underruns.fetch_add(1, Ordering::Relaxed);
+        }
+
+        self.expected = playback.add(period);
+    }
+}
+
+/// Running totals of the samples in the master mix since the level was last
+/// taken.
+#[derive(Debug, Default)]
+struct Meter {
+    peak: f32,
+    sum_sq: f64,
+    count: usize,
+}
+
+impl Meter {
+    fn add(&mut self, other: &Meter) {
+        self.peak = self.peak.max(other.peak);
+        self.sum_sq += other.sum_sq;
+        self.count += other.count;
+    }
+
+    /// Takes the level since the last call, or None if nothing has played.
+    fn take(&mut self) -> Option<Level> {
+        if self.count == 0 {
+            return None;
+        }
+
+        let level = Level {
+            peak: self.peak,
+            rms: (self.sum_sq / self.count as f64).sqrt() as f32,
+            gain_reduction: 0.,
+        };
+
+        *self = Meter::default();
+
+        Some(level)
+    }
+}
+
+/// Wraps a source and adds its samples to a [`Meter`].
+struct Metered<S> {
+    inner: S,
+    meter: Arc<Mutex<Meter>>,
+    /// samples that haven't been handed over to the meter yet
+    block: Meter,
+}
+
+impl<S> Metered<S> {
+    fn new(inner: S, meter: Arc<Mutex<Meter>>) -> Self {
+        Self {
+            inner,
+            meter,
+            block: Meter::default(),
+        }
+    }
+}
+
+impl<S: Source<Item = f32>> Iterator for Metered<S> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let sample = self.inner.next()?;
+
+        self.block.peak = self.block.peak.max(sample.abs());
+        self.block.sum_sq += (sample as f64).powi(2);
+        self.block.count += 1;
+
+        // if the lock is held, keep adding up and try again later rather than
+        // holding up the output
+        if self.block.count >= METER_BLOCK {
+            if let Ok(mut meter) = self.meter.try_lock() {
+                meter.add(&self.block);
+                self.block = Meter::default();
+            }
+        }
+
+        Some(sample)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<S: Source<Item = f32>> Source for Metered<S> {
+    fn current_frame_len(&self) -> Option<usize> {
+        self.inner.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+}
+
+/// Wraps the master mix and runs it through a [`Compressor`], if it is
+/// enabled.
+struct Compressed<S> {
+    inner: S,
+    compressor: Option<Compressor>,
+    /// where the gain reduction is handed over to the audio loop
+    gain_reduction: Arc<AtomicU32>,
+}
+
+impl<S: Source<Item = f32>> Compressed<S> {
+    fn new(inner: S, config: &CompressorConfig, gain_reduction: Arc<AtomicU32>) -> Self {
+        Self {
+            compressor: config
+                .enabled
+                .then(|| Compressor::new(config, inner.channels(), inner.sample_rate())),
+            inner,
+            gain_reduction,
+        }
+    }
+}
+
+impl<S: Source<Item = f32>> Iterator for Compressed<S> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let sample = self.inner.next()?;
+        let Some(compressor) = &mut self.compressor else { return Some(sample); };
+
+        let sample = compressor.process(sample);
+        self.gain_reduction
+            .store(compressor.gain_reduction().to_bits(), Ordering::Relaxed);
+
+        Some(sample)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<S: Source<Item = f32>> Source for Compressed<S> {
+    fn current_frame_len(&self) -> Option<usize> {
+        self.inner.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+}
+
+/// Wraps the master mix and runs it through [`MasterFx`].
+struct Effected<S> {
+    inner: S,
+    master: MasterFx,
+    fx: Arc<AtomicU64>,
+    /// channel of the next sample
+    channel: u16,
+}
+
+impl<S: Source<Item = f32>> Effected<S> {
+    fn new(inner: S, fx: Arc<AtomicU64>) -> Self {
+        Self {
+            master: MasterFx::new(inner.channels(), inner.sample_rate()),
+            inner,
+            fx,
+            channel: 0,
+        }
+    }
+}
+
+impl<S: Source<Item = f32>> Iterator for Effected<S> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let sample = self.inner.next()?;
+        let channel = self.channel;
+        self.channel = (channel + 1) % self.inner.channels();
+
+        let fx = Fx::from_bits(self.fx.load(Ordering::Relaxed));
+        Some(self.master.process(channel as usize, sample, fx))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<S: Source<Item = f32>> Source for Effected<S> {
+    fn current_frame_len(&self) -> Option<usize> {
+        self.inner.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+}
+
+/// Wraps a bus and runs it through an effect [`Chain`], a block at a time.
+/// Changes to the chain are picked up between blocks.
+struct Chained<S> {
+    inner: S,
+    chain: Chain,
+    chain_rx: flume::Receiver<ChainUpdate>,
+    /// length of a beat in microseconds
+    beat: Arc<AtomicU64>,
+    last_beat: u64,
+    block: Vec<f32>,
+    /// index of the next sample in `block`
+    position: usize,
+}
+
+impl<S: Source<Item = f32>> Chained<S> {
+    fn new(
+        inner: S,
+        chain: Chain,
+        chain_rx: flume::Receiver<ChainUpdate>,
+        beat: Arc<AtomicU64>,
+    ) -> Self {
+        let block = Vec::with_capacity(CHAIN_FRAMES * inner.channels() as usize);
+
+        Self {
+            inner,
+            chain,
+            chain_rx,
+            last_beat: beat.load(Ordering::Relaxed),
+            beat,
+            block,
+            position: 0,
+        }
+    }
+
+    fn next_block(&mut self) {
+        // an old chain is dropped here, but chains are only replaced when a
+        // kit is loaded
+        for update in self.chain_rx.try_iter() {
+            self.chain.update(update);
+        }
+
+        let beat = self.beat.load(Ordering::Relaxed);
+
+        if beat != self.last_beat {
+            self.chain.set_tempo(Duration::from_micros(beat));
+            self.last_beat = beat;
+        }
+
+        let len = self.block.capacity();
+        self.block.clear();
+        self.block.extend(self.inner.by_ref().take(len));
+        self.chain.process(&mut self.block);
+        self.position = 0;
+    }
+}
+
+impl<S: Source<Item = f32>> Iterator for Chained<S> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.position == self.block.len() {
+            self.next_block();
+        }
+
+        let sample = *self.block.get(self.position)?;
+        self.position += 1;
+        Some(sample)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<S: Source<Item = f32>> Source for Chained<S> {
+    fn current_frame_len(&self) -> Option<usize> {
+        self.inner.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+}
+
+/// Wraps a bus and runs it through a [`KillEq`].
+struct Equalized<S> {
+    inner: S,
+    eq: KillEq,
+    kills: Arc<AtomicU8>,
+    /// channel of the next sample
+    channel: u16,
+}
+
+impl<S: Source<Item = f32>> Equalized<S> {
+    fn new(inner: S, kills: Arc<AtomicU8>) -> Self {
+        Self {
+            eq: KillEq::new(inner.channels(), inner.sample_rate()),
+            inner,
+            kills,
+            channel: 0,
+        }
+    }
+}
+
+impl<S: Source<Item = f32>> Iterator for Equalized<S> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let sample = self.inner.next()?;
+        let channel = self.channel;
+        self.channel = (channel + 1) % self.inner.channels();
+
+        let kills = Kills::from_bits(self.kills.load(Ordering::Relaxed));
+        Some(self.eq.process(channel as usize, sample, kills))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<S: Source<Item = f32>> Source for Equalized<S> {
+    fn current_frame_len(&self) -> Option<usize> {
+        self.inner.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+}
+
+/// Hands the master mix over to the audio loop while a recording is being
+/// made, and to the tape writer while it is being taped.
+#[derive(Debug)]
+struct Recorder {
+    /// how many more samples the recording needs
+    wanted: AtomicUsize,
+    tx: flume::Sender<Vec<f32>>,
+    taping: AtomicBool,
+    tape_tx: flume::Sender<TapeMessage>,
+}
+
+impl Recorder {
+    fn new(tx: flume::Sender<Vec<f32>>, tape_tx: flume::Sender<TapeMessage>) -> Self {
+        Self {
+            wanted: AtomicUsize::new(0),
+            tx,
+            taping: AtomicBool::new(false),
+            tape_tx,
+        }
+    }
+
+    /// Starts handing over the next `len` samples, from the start of the next
+    /// frame. Only one recording is made at a time.
+    fn start(&self, len: usize) {
+        self.wanted.store(len, Ordering::Relaxed);
+    }
+
+    /// Finishes the take that is being taped, if there is one, and starts
+    /// taping to `path`.
+    fn start_take(&self, path: PathBuf) {
+        let _ = self.tape_tx.send(TapeMessage::Take(path));
+        self.taping.store(true, Ordering::Relaxed);
+    }
+
+    fn stop_tape(&self) {
+        self.taping.store(false, Ordering::Relaxed);
+        let _ = self.tape_tx.send(TapeMessage::Stop);
+    }
+}
+
+/// Wraps the master mix and copies its samples to a [`Recorder`].
+struct Tapped<S> {
+    inner: S,
+    recorder: Arc<Recorder>,
+    /// samples that haven't been handed over yet
+    block: Vec<f32>,
+    /// samples that haven't been handed over to the tape writer yet
+    tape_block: Vec<f32>,
+    /// channel of the next sample
+    channel: u16,
+}
+
+impl<S> Tapped<S> {
+    fn new(inner: S, recorder: Arc<Recorder>) -> Self {
+        Self {
+            inner,
+            recorder,
+            block: vec![],
+            tape_block: vec![],
+            channel: 0,
+        }
+    }
+}
+
+impl<S: Source<Item = f32>> Iterator for Tapped<S> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let sample = self.inner.next()?;
+        let channel = self.channel;
+        self.channel = (channel + 1) % self.inner.channels();
+
+        let wanted = self.recorder.wanted.load(Ordering::Relaxed);
+
+        if wanted > self.block.len() && (channel == 0 || !self.block.is_empty()) {
+            self.block.push(sample);
+
+            if self.block.len() >= METER_BLOCK.min(wanted) {
+                let block = std::mem::take(&mut self.block);
+                self.recorder
+                    .wanted
+                    .fetch_sub(block.len(), Ordering::Relaxed);
+
+                // the audio loop may be busy, but it never lets the channel
+                // fill up, so this doesn't block the output
+                let _ = self.recorder.tx.try_send(block);
+            }
+        }
+
+        let taping = self.recorder.taping.load(Ordering::Relaxed);
+
+        if taping && (channel == 0 || !self.tape_block.is_empty()) {
+            self.tape_block.push(sample);
+        }
+
+        // what is left is handed over when the tape stops
+        if self.tape_block.len() >= METER_BLOCK || (!taping && !self.tape_block.is_empty()) {
+            let block = std::mem::take(&mut self.tape_block);
+            let _ = self.recorder.tape_tx.try_send(TapeMessage::Samples(block));
+        }
+
+        Some(sample)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<S: Source<Item = f32>> Source for Tapped<S> {
+    fn current_frame_len(&self) -> Option<usize> {
+        self.inner.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+}
+
+/// A sound at the mix's sample rate and channels, either decoded from a file
+/// or recorded from the master mix. Its samples are shared with the voices
+/// that play it.
+#[derive(Debug, Clone)]
+struct Pcm {
+    samples: Arc<[f32]>,
+    /// index of the next sample
+    position: usize,
+}
+
+impl Pcm {
+    fn new(samples: Vec<f32>) -> Self {
+        Self {
+            samples: samples.into(),
+            position: 0,
+        }
+    }
+}
+
+impl Iterator for Pcm {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let sample = self.samples.get(self.position).copied()?;
+        self.position += 1;
+        Some(sample)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let left = self.samples.len() - self.position;
+        (left, Some(left))
+    }
+}
+
+impl Source for Pcm {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        MIX_CHANNELS
+    }
+
+    fn sample_rate(&self) -> u32 {
+        MIX_SAMPLE_RATE
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        let frames = self.samples.len() / MIX_CHANNELS as usize;
+        Some(Duration::from_secs_f64(
+            frames as f64 / MIX_SAMPLE_RATE as f64,
+        ))
+    }
+}
+
+/// A change to the sounds that are playing on a bus.
+enum Trigger {
+    /// Starts playing a sound.
+    Start {
+        samples: Arc<[f32]>,
+        /// index of the sample to start from, which is the start of a frame
+        position: usize,
+        voicing: Voicing,
+        trace: Option<Trace>,
+    },
+    /// Cuts off the sounds in a choke group.
+    Choke(u8),
+    /// Stops the sounds that a pad played.
+    Release(u8),
+    /// Stops a looping sound.
+    StopLoop(u32),
+}
+
+/// How many frames apart new sounds are picked up by [`Voices`], so that it
+/// isn't checking for them on every frame.
+const TRIGGER_FRAMES: usize = 32;
+
+/// Plays the sounds that are triggered on one bus, on a [`VoicePool`].
+struct Voices {
+    pool: VoicePool,
+    trigger_rx: flume::Receiver<Trigger>,
+    /// how many sounds are playing on all the buses, which this adds its
+    /// own to
+    voices: Arc<AtomicUsize>,
+    counted: usize,
+    event_tx: flume::Sender<Event>,
+    /// index of the next sample in the current block of [`TRIGGER_FRAMES`]
+    sample: usize,
+}
+
+impl Voices {
+    fn new(
+        trigger_rx: flume::Receiver<Trigger>,
+        voices: Arc<AtomicUsize>,
+        event_tx: flume::Sender<Event>,
+    ) -> Self {
+        Self {
+            pool: VoicePool::new(),
+            trigger_rx,
+            voices,
+            counted: 0,
+            event_tx,
+            sample: 0,
+        }
+    }
+
+    /// Starts and stops the sounds that have been triggered, and counts the
+    /// ones that are playing.
+    fn start_triggered(&mut self) {
+        for trigger in self.trigger_rx.try_iter() {
+            let (samples, position, voicing, trace) = match trigger {
+                Trigger::Start {
+                    samples,
+                    position,
+                    voicing,
+                    trace,
+                } => (samples, position, voicing, trace),
+                Trigger::Choke(group) => {
+                    self.pool.choke(group);
+                    continue;
+                }
+                Trigger::Release(pad) => {
+                    self.pool.release(pad);
+                    continue;
+                }
+                Trigger::StopLoop(looper) => {
+                    self.pool.stop_loop(looper);
+                    continue;
+                }
+            };
+
+            self.pool.start_voiced(samples, position, voicing);
+
+            if let Some(trace) = trace {
+                let trace = Trace {
+                    first_sample: Some(Instant::now()),
+                    ..trace
+                };
+
+                // this runs on the output stream's thread, so it must not
+                // block
+                let _ = self.event_tx.try_send(Event::Played { trace });
+            }
+        }
+
+        let playing = self.pool.len();
+
+        if playing > self.counted {
+            self.voices
+                .fetch_add(playing - self.counted, Ordering::Relaxed);
+        } else {
+            self.voices
+                .fetch_sub(self.counted - playing, Ordering::Relaxed);
+        }
+
+        self.counted = playing;
+    }
+}
+
+impl Drop for Voices {
+    fn drop(&mut self) {
+        self.voices.fetch_sub(self.counted, Ordering::Relaxed);
+    }
+}
+
+impl Iterator for Voices {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.sample == 0 {
+            self.start_triggered();
+        }
+
+        self.sample = (self.sample + 1) % (TRIGGER_FRAMES * MIX_CHANNELS as usize);
+
+        // keeps going when nothing is playing, like Zero
+        Some(self.pool.next_sample())
+    }
+}
+
+impl Source for Voices {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        MIX_CHANNELS
+    }
+
+    fn sample_rate(&self) -> u32 {
+        MIX_SAMPLE_RATE
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
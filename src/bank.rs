@@ -0,0 +1,91 @@
+//! Banks of pads for split mode, where two people jam on one keypad. Each
+//! half of the grid, split by the bus that its columns play on, flips between
+//! its own banks of pads without touching the other half.
+
+use crate::{audio::Bus, grid::Grid};
+
+/// How many banks each half of the grid has.
+pub const BANKS: usize = 4;
+
+/// The banks of both halves of the grid. The bank that a half is on lives in
+/// the grid itself, and the others are kept here.
+#[derive(Debug, Clone, Default)]
+pub struct Banks<T> {
+    /// the bank that each half is on, by the bus that it plays on
+    pub current: [usize; 2],
+    /// the pads of each half's banks, a row at a time. The one that is on
+    /// the grid is left empty, and so are the ones that haven't been used.
+    stored: [[Vec<T>; BANKS]; 2],
+}
+
+impl<T: Default> Banks<T> {
+    /// Puts the half of `grid` that plays on `side` away in its bank, and
+    /// brings out the pads of `bank` in its place.
+    pub fn switch(&mut self, side: Bus, bank: usize, grid: &mut Grid<T>) {
+        let bank = bank % BANKS;
+        let half = side.index();
+        let from = self.current[half];
+
+        if bank == from {
+            return;
+        }
+
+        let mut stored = std::mem::take(&mut self.stored[half][bank]).into_iter();
+        let mut away = vec![];
+
+        for (x, y) in grid.positions() {
+            if Bus::of_column(x) != side {
+                continue;
+            }
+
+            let pad = &mut grid[y][x];
+            away.push(std::mem::take(pad));
+            *pad = stored.next().unwrap_or_default();
+        }
+
+        self.stored[half][from] = away;
+        self.current[half] = bank;
+    }
+
+    /// Switches the half that plays on `side` to its next bank, going round
+    /// to the first after the last.
+    pub fn next(&mut self, side: Bus, grid: &mut Grid<T>) {
+        let bank = self.current[side.index()] + 1;
+        self.switch(side, bank, grid);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Banks, BANKS};
+    use crate::{audio::Bus, grid::Grid};
+
+    #[test]
+    fn each_half_switches_banks_on_its_own() {
+        let start = Grid::from_fn(4, 3, |x, y| y * 4 + x + 1);
+        let mut grid = start.clone();
+        let rows = |grid: &Grid<usize>| grid.iter().map(<[_]>::to_vec).collect::<Vec<_>>();
+        let mut banks = Banks::default();
+
+        // the right half goes to an empty bank, and the left half is left
+        // alone
+        banks.next(Bus::B, &mut grid);
+        assert_eq!(banks.current, [0, 1]);
+        assert_eq!(rows(&grid), [[1, 2, 0, 0], [5, 6, 0, 0], [9, 10, 0, 0]]);
+
+        grid[0][3] = 40;
+        banks.next(Bus::A, &mut grid);
+        assert_eq!(rows(&grid), [[0, 0, 0, 40], [0; 4], [0; 4]]);
+
+        // going round brings the first banks back
+        for _ in 1..BANKS {
+            banks.next(Bus::B, &mut grid);
+        }
+        banks.switch(Bus::A, 0, &mut grid);
+        assert_eq!(banks.current, [0, 0]);
+        assert_eq!(grid, start);
+
+        banks.switch(Bus::B, 1, &mut grid);
+        assert_eq!(grid[0][3], 40);
+    }
+}
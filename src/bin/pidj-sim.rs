@@ -0,0 +1,182 @@
+//! Runs the engine without a screen or any hardware, on the virtual keypad and
+//! a virtual clock, with the keys pressed by a script (see [`pidj::sim`]).
+//! The mix is thrown away, or written to a WAV file with `--out`. It exits
+//! with an error if a subsystem fails, or if replaying the engine's journal
+//! doesn't come out with the same loops, so that it can be run on CI.
+//!
+//! ```text
+//! pidj-sim <script.yaml> [--config <pidj.toml>] [--out <mix.wav>]
+//! ```
+
+use std::{sync::Arc, time::Duration};
+
+use anyhow::{anyhow, bail, Context};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+use tracing_subscriber::EnvFilter;
+
+use pidj::{
+    audio,
+    bus::Bus,
+    clock::{Clock, VirtualClock},
+    config::{self, AudioSink},
+    driver::{adafruit::seesaw::neotrellis, mock::MockI2c},
+    engine::{AppState, Engine, PlayState},
+    keyboard,
+    sim::Script,
+};
+
+/// How long the sounds can take to load before the run is given up on.
+const LOAD_TIMEOUT: Duration = Duration::from_secs(120);
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::from_default_env())
+        .init();
+
+    let script_path = std::env::args()
+        .nth(1)
+        .filter(|arg| !arg.starts_with("--"))
+        .context("usage: pidj-sim <script.yaml> [--config <pidj.toml>] [--out <mix.wav>]")?;
+    let script = Script::load(&script_path)?;
+
+    let config_path = arg_value("--config").unwrap_or_else(|| config::CONFIG_PATH.to_owned());
+    let mut config = config::Config::load(config_path)?;
+
+    // the keypad is always the virtual one, and the mix is only kept if it is
+    // asked for
+    config.audio.sink = Some(match arg_value("--out") {
+        Some(path) => AudioSink::File(path.into()),
+        None => AudioSink::Null,
+    });
+
+    // the virtual keypad doesn't answer a scan for its hardware ID, so it is
+    // looked for where a NeoTrellis is by default
+    config
+        .keyboard
+        .address
+        .get_or_insert(neotrellis::DEFAULT_ADDRESS);
+
+    // every run starts from nothing, and leaves the saved state alone
+    let state_path = std::env::temp_dir().join(format!("pidj-sim-{}.toml", std::process::id()));
+
+    let ct = CancellationToken::new();
+    let bus = Bus::new();
+    let i2c = MockI2c::new();
+
+    let kb_join = std::thread::spawn({
+        let ct = ct.clone();
+        let config = config.keyboard.clone();
+        let i2c = i2c.clone();
+        let kb_cmd_rx = bus.keyboard.commands.rx();
+        let kb_evt_tx = bus.keyboard.events.tx();
+        let problem_tx = bus.problems.tx();
+        move || keyboard::run_with_bus(ct, config, i2c, kb_cmd_rx, kb_evt_tx, problem_tx)
+    });
+
+    let audio_join = tokio::spawn(audio::run(
+        ct.clone(),
+        config.audio.clone(),
+        bus.audio.commands.rx(),
+        bus.audio.events.tx(),
+        bus.problems.tx(),
+    ));
+
+    let clock = VirtualClock::new();
+    tokio::spawn(run_virtual_clock(ct.clone(), clock.clone()));
+
+    let engine = Engine::start(
+        ct.clone(),
+        Arc::new(clock.clone()),
+        bus,
+        config.play,
+        &config.keyboard,
+        state_path.clone(),
+    );
+
+    let mut snapshot = engine.snapshot();
+
+    tokio::time::timeout(LOAD_TIMEOUT, async {
+        while !matches!(snapshot.borrow().state, AppState::Play(_)) {
+            snapshot.changed().await?;
+        }
+
+        anyhow::Ok(())
+    })
+    .await
+    .context("the sounds took too long to load")??;
+
+    info!("playing {script_path:?}");
+
+    let start = clock.now();
+
+    for event in script.events() {
+        clock.sleep_until(start + event.at).await;
+        i2c.push_key(event.key, event.edge);
+    }
+
+    clock.sleep_until(start + script.duration()).await;
+
+    let end = snapshot.borrow().clone();
+    ct.cancel();
+
+    audio_join.await.context("the audio subsystem panicked")??;
+    kb_join
+        .join()
+        .map_err(|_| anyhow!("the keyboard panicked"))??;
+
+    let _ = std::fs::remove_file(&state_path);
+
+    for toast in &end.toasts {
+        warn!("problem along the way: {}", toast.problem.message);
+    }
+
+    if let AppState::Play(state) = &end.state {
+        info!("finished with {} loops", state.loops.len());
+
+        let (replayed, _) = engine
+            .journal()
+            .replay(clock.now())
+            .context("the journal never started")?;
+
+        if loops(&replayed) != loops(state) {
+            bail!(
+                "replaying the journal came out with {:?}, not {:?}",
+                loops(&replayed),
+                loops(state)
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// The sounds that are looped, and when.
+fn loops(state: &PlayState) -> Vec<(usize, isize, usize)> {
+    state
+        .loops
+        .iter()
+        .map(|l| (l.sound.0, l.offset, l.period))
+        .collect()
+}
+
+/// Gets the argument that follows `name` on the command line.
+fn arg_value(name: &str) -> Option<String> {
+    let mut args = std::env::args();
+    args.find(|arg| arg == name)?;
+    args.next()
+}
+
+/// Advances the virtual clock in fixed steps, like the `pidj` binary does
+/// with `--simulate`.
+async fn run_virtual_clock(ct: CancellationToken, clock: VirtualClock) {
+    const STEP: Duration = Duration::from_millis(1);
+
+    let mut interval = tokio::time::interval(STEP);
+
+    while !ct.is_cancelled() {
+        interval.tick().await;
+        clock.advance(STEP);
+    }
+}
@@ -0,0 +1,243 @@
+//! Kits bundled with the sounds that they play, so that they can be moved
+//! between units or backed up. A bundle is a zip archive with the kit in
+//! `kit.toml` and its sounds under `sounds/`, where the kit's paths point.
+//! Importing one extracts it into the `Kits` folder of the audio directory.
+
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context};
+use zip::{result::ZipError, write::FileOptions, CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::{
+    archive,
+    setlist::{Kit, MacroAction},
+};
+
+/// Extension of bundle files.
+pub const EXTENSION: &str = "pidjkit";
+
+/// Directory that the app exports bundles to and lists them from, relative
+/// to the working directory.
+pub const KITS_DIR: &str = "kits";
+
+/// Folder of the audio directory that bundles are imported into.
+pub const IMPORT_DIR: &str = "Kits";
+
+const KIT_FILE: &str = "kit.toml";
+const SOUNDS_DIR: &str = "sounds";
+
+/// Writes `kit` and the sounds that it plays to a bundle at `path`. Each sound
+/// is given as the file to read it from, which can be in an archive, and the
+/// path that the kit refers to it by.
+pub fn export(path: &Path, kit: &Kit, sounds: &[(PathBuf, PathBuf)]) -> anyhow::Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("failed to create directory {dir:?}"))?;
+    }
+
+    // written next to the bundle first, so that a failed export doesn't
+    // leave a broken one behind
+    let partial = path.with_extension("part");
+    let file = File::create(&partial).with_context(|| format!("failed to create {partial:?}"))?;
+    let mut zip = ZipWriter::new(BufWriter::new(file));
+
+    let result = (|| {
+        zip.start_file(KIT_FILE, FileOptions::default())?;
+        zip.write_all(kit.to_toml()?.as_bytes())?;
+
+        // sounds are mostly compressed already
+        let stored = FileOptions::default().compression_method(CompressionMethod::Stored);
+
+        for (source, name) in sounds {
+            let mut reader = archive::open(source)
+                .with_context(|| format!("failed to read sound {source:?}"))?;
+
+            zip.start_file(entry_name(name), stored)?;
+            io::copy(&mut reader, &mut zip)?;
+        }
+
+        zip.finish()?.flush()?;
+        std::fs::rename(&partial, path)?;
+
+        Ok::<_, anyhow::Error>(())
+    })();
+
+    if result.is_err() {
+        let _ = std::fs::remove_file(&partial);
+    }
+
+    result.with_context(|| format!("failed to export kit to {path:?}"))
+}
+
+/// Extracts the bundle at `path` into a folder named after it in the
+/// [`IMPORT_DIR`] of `audio_dir`, and returns its kit, which refers to the
+/// extracted sounds, and the paths of the sounds. The kit is saved in the
+/// folder too.
+pub fn import(path: &Path, audio_dir: &Path) -> anyhow::Result<(Kit, Vec<PathBuf>)> {
+    let stem = path.file_stem().unwrap_or_default();
+    let folder = Path::new(IMPORT_DIR).join(stem);
+    let dest = audio_dir.join(&folder);
+
+    if dest.exists() {
+        bail!("{} has already been imported", stem.to_string_lossy());
+    }
+
+    let file = File::open(path).with_context(|| format!("failed to open {path:?}"))?;
+    let mut zip = ZipArchive::new(BufReader::new(file))
+        .with_context(|| format!("{path:?} isn't a kit bundle"))?;
+
+    let mut kit = match zip.by_name(KIT_FILE) {
+        Ok(mut file) => {
+            let mut text = String::new();
+            file.read_to_string(&mut text)?;
+            Kit::from_toml(&text).with_context(|| format!("failed to parse kit in {path:?}"))?
+        }
+        Err(ZipError::FileNotFound) => bail!("{path:?} doesn't have a kit in it"),
+        Err(err) => return Err(err.into()),
+    };
+
+    // extracted next to the folder first, so that a failed import can be
+    // tried again
+    let partial = dest.with_extension("part");
+    let _ = std::fs::remove_dir_all(&partial);
+
+    let result = (|| {
+        let mut sounds = vec![];
+
+        for i in 0..zip.len() {
+            let mut file = zip.by_index(i)?;
+
+            // names that would escape the folder, like ../x.wav, are left out
+            let Some(name) = file
+                .enclosed_name()
+                .and_then(|name| name.strip_prefix(SOUNDS_DIR).ok())
+                .map(Path::to_path_buf)
+            else {
+                continue;
+            };
+
+            if !file.is_file() {
+                continue;
+            }
+
+            let target = partial.join(&name);
+
+            if let Some(dir) = target.parent() {
+                std::fs::create_dir_all(dir)?;
+            }
+
+            io::copy(&mut file, &mut File::create(&target)?)?;
+            sounds.push(dest.join(name));
+        }
+
+        for pad in &mut kit.pads {
+            pad.sound = folder.join(&pad.sound);
+        }
+
+        for m in &mut kit.macros {
+            for action in &mut m.actions {
+                if let MacroAction::Play(sound) = action {
+                    *sound = folder.join(&*sound);
+                }
+            }
+        }
+
+        std::fs::write(partial.join(KIT_FILE), kit.to_toml()?)?;
+        std::fs::rename(&partial, &dest)?;
+
+        Ok::<_, anyhow::Error>(sounds)
+    })();
+
+    match result {
+        Ok(sounds) => Ok((kit, sounds)),
+        Err(err) => {
+            let _ = std::fs::remove_dir_all(&partial);
+            Err(err.context(format!("failed to import kit from {path:?}")))
+        }
+    }
+}
+
+/// Lists the bundles in `dir`, sorted by name.
+pub fn list(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(dir) else { return vec![]; };
+
+    let mut bundles: Vec<_> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == EXTENSION))
+        .collect();
+
+    bundles.sort();
+    bundles
+}
+
+/// Name of a sound in a bundle. Names in zips always use forward slashes.
+fn entry_name(name: &Path) -> String {
+    Path::new(SOUNDS_DIR)
+        .join(name)
+        .iter()
+        .map(|part| part.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use super::{export, import, list};
+    use crate::{
+        setlist::{Kit, KitMacro, KitPad, MacroAction},
+        util::TempDir,
+    };
+
+    #[test]
+    fn bundles_round_trip() {
+        let dir = TempDir::new("bundle");
+
+        let source = dir.join("Deep Kick.wav");
+        std::fs::write(&source, "kick").unwrap();
+
+        let sound = PathBuf::from("audio/Kicks/Deep Kick.wav");
+        let kit = Kit {
+            bpm: Some(122.),
+            pads: vec![KitPad {
+                pad: [0, 1],
+                sound: sound.clone(),
+                color: Some([255, 0, 0]),
+            }],
+            macros: vec![KitMacro {
+                pad: [3, 3],
+                name: "drop".to_owned(),
+                actions: vec![MacroAction::Play(sound.clone()), MacroAction::Bpm(128.)],
+                color: None,
+            }],
+            patterns: vec![],
+            ..Default::default()
+        };
+
+        let bundle = dir.join("kits/house.pidjkit");
+        export(&bundle, &kit, &[(source, sound)]).unwrap();
+        assert_eq!(list(&dir.join("kits")), vec![bundle.clone()]);
+
+        let audio = dir.join("audio");
+        let (imported, sounds) = import(&bundle, &audio).unwrap();
+        let extracted = PathBuf::from("Kits/house/audio/Kicks/Deep Kick.wav");
+
+        assert_eq!(sounds, vec![audio.join(&extracted)]);
+        assert_eq!(std::fs::read_to_string(&sounds[0]).unwrap(), "kick");
+        assert_eq!(imported.pads[0].sound, extracted);
+        assert_eq!(imported.macros[0].actions[0], MacroAction::Play(extracted));
+        assert_eq!(
+            Kit::load(audio.join("Kits/house/kit.toml")).unwrap(),
+            imported
+        );
+
+        // importing it again would load its sounds twice
+        assert!(import(&bundle, &audio).is_err());
+    }
+}
@@ -0,0 +1,96 @@
+//! The channels that the subsystems and the engine talk through. They are all
+//! made here, so that a new subsystem needs a field on [`Bus`] rather than
+//! one more parameter on every function between `main` and the engine.
+//!
+//! Each end of a channel is taken by whatever needs it, and the bus is
+//! dropped once everything has taken its ends. That way a channel closes when
+//! nothing is on the other end, e.g. when there is no encoder.
+
+use crate::{
+    audio, encoder,
+    engine::{Fault, Problem},
+    health::Health,
+    keyboard, lighting,
+};
+
+/// Both ends of a channel, which can each be taken as many times as needed.
+pub struct Channel<T> {
+    tx: flume::Sender<T>,
+    rx: flume::Receiver<T>,
+}
+
+impl<T> Channel<T> {
+    fn bounded(capacity: usize) -> Self {
+        let (tx, rx) = flume::bounded(capacity);
+        Self { tx, rx }
+    }
+
+    fn unbounded() -> Self {
+        let (tx, rx) = flume::unbounded();
+        Self { tx, rx }
+    }
+
+    pub fn tx(&self) -> flume::Sender<T> {
+        self.tx.clone()
+    }
+
+    pub fn rx(&self) -> flume::Receiver<T> {
+        self.rx.clone()
+    }
+}
+
+/// The commands that the engine sends a subsystem, and the events that the
+/// subsystem sends back.
+pub struct Link<C, E> {
+    pub commands: Channel<C>,
+    pub events: Channel<E>,
+}
+
+impl<C, E> Link<C, E> {
+    fn bounded(capacity: usize) -> Self {
+        Self {
+            commands: Channel::bounded(capacity),
+            events: Channel::bounded(capacity),
+        }
+    }
+}
+
+/// All of the channels between the subsystems and the engine. It isn't
+/// cloned, so that it is only kept until everything has taken its ends.
+pub struct Bus {
+    /// the keypad, or the MIDI grid in its place. Footswitches send keyboard
+    /// events too.
+    pub keyboard: Link<keyboard::Command, keyboard::Event>,
+    pub encoder: Link<encoder::Command, encoder::Event>,
+    pub audio: Link<audio::Command, audio::Event>,
+    /// subsystems that failed, or are being restarted
+    pub faults: Channel<Fault>,
+    /// problems that subsystems got past, which are shown to the user
+    pub problems: Channel<Problem>,
+    pub health: Channel<Health>,
+    /// pulses on the trigger output
+    pub trigger: Channel<()>,
+    /// the LED strip
+    pub lighting: Channel<lighting::Event>,
+}
+
+impl Bus {
+    pub fn new() -> Self {
+        Self {
+            keyboard: Link::bounded(256),
+            encoder: Link::bounded(256),
+            audio: Link::bounded(256),
+            faults: Channel::unbounded(),
+            problems: Channel::unbounded(),
+            health: Channel::unbounded(),
+            trigger: Channel::bounded(16),
+            lighting: Channel::bounded(256),
+        }
+    }
+}
+
+impl Default for Bus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
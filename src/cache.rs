@@ -0,0 +1,176 @@
+//! Cache of decoded sounds on disk, so that sounds that haven't changed don't
+//! have to be decoded again every time the app starts. Entries are keyed by a
+//! hash of the file's contents, so a file that is edited, renamed or moved
+//! between packs is still found, or decoded again. What was worked out about
+//! each sound is kept alongside, in [`crate::analysis`].
+
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use tracing::debug;
+
+/// Start of every entry. The number goes up when the layout changes, so that
+/// old entries are decoded again rather than misread.
+const MAGIC: &[u8; 8] = b"PIDJPCM2";
+
+#[derive(Debug, Clone)]
+pub struct SampleCache {
+    dir: PathBuf,
+    sample_rate: u32,
+    channels: u16,
+}
+
+impl SampleCache {
+    /// Opens the cache in `dir`, which is created when the first entry is
+    /// written. Entries that were decoded at a different sample rate or
+    /// number of channels are ignored.
+    pub fn new(dir: impl Into<PathBuf>, sample_rate: u32, channels: u16) -> Self {
+        Self {
+            dir: dir.into(),
+            sample_rate,
+            channels,
+        }
+    }
+
+    fn path(&self, hash: u64) -> PathBuf {
+        self.dir.join(format!("{hash:016x}.pcm"))
+    }
+
+    /// Reads the interleaved samples of the file with the given content hash,
+    /// or None if there isn't a usable entry.
+    pub fn get(&self, hash: u64) -> Option<Vec<f32>> {
+        let path = self.path(hash);
+
+        match self.read(&path) {
+            Ok(samples) => samples,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+            Err(err) => {
+                debug!("failed to read cache entry {path:?}: {err}");
+                None
+            }
+        }
+    }
+
+    fn read(&self, path: &Path) -> io::Result<Option<Vec<f32>>> {
+        let mut reader = BufReader::new(File::open(path)?);
+
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+
+        if &magic != MAGIC
+            || read_u32(&mut reader)? != self.sample_rate
+            || read_u32(&mut reader)? != self.channels as u32
+        {
+            return Ok(None);
+        }
+
+        read_f32s(&mut reader).map(Some)
+    }
+
+    /// Writes the interleaved samples of the file with the given content hash.
+    /// The entry only appears once it has been written in full.
+    pub fn put(&self, hash: u64, samples: &[f32]) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+
+        let path = self.path(hash);
+        let partial = path.with_extension("part");
+        let mut writer = BufWriter::new(File::create(&partial)?);
+
+        writer.write_all(MAGIC)?;
+        writer.write_all(&self.sample_rate.to_le_bytes())?;
+        writer.write_all(&(self.channels as u32).to_le_bytes())?;
+        write_f32s(&mut writer, samples)?;
+        writer
+            .into_inner()
+            .map_err(|err| err.into_error())?
+            .sync_all()?;
+
+        fs::rename(partial, path)
+    }
+}
+
+/// Hashes the contents of a file with 64-bit FNV-1a. Files in archives are
+/// hashed by what they decompress to.
+pub fn content_hash(path: &Path) -> io::Result<u64> {
+    let mut file = crate::archive::open(path)?;
+    let mut buf = vec![0; 64 * 1024];
+    let mut hash = 0xcbf29ce484222325u64;
+
+    loop {
+        let len = file.read(&mut buf)?;
+
+        if len == 0 {
+            return Ok(hash);
+        }
+
+        for &b in &buf[..len] {
+            hash = (hash ^ b as u64).wrapping_mul(0x100000001b3);
+        }
+    }
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+/// Reads a length and then that many samples.
+fn read_f32s(reader: &mut impl Read) -> io::Result<Vec<f32>> {
+    let mut len = [0; 8];
+    reader.read_exact(&mut len)?;
+
+    let mut bytes = vec![0; u64::from_le_bytes(len) as usize * 4];
+    reader.read_exact(&mut bytes)?;
+
+    Ok(bytes
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes(b.try_into().unwrap()))
+        .collect())
+}
+
+fn write_f32s(writer: &mut impl Write, samples: &[f32]) -> io::Result<()> {
+    writer.write_all(&(samples.len() as u64).to_le_bytes())?;
+
+    for sample in samples {
+        writer.write_all(&sample.to_le_bytes())?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{content_hash, SampleCache};
+    use crate::util::TempDir;
+
+    #[test]
+    fn entries_round_trip() {
+        let dir = TempDir::new("cache");
+        let cache = SampleCache::new(&*dir, 44_100, 2);
+        let samples = vec![0.5, -0.25, 1., 0.];
+
+        assert_eq!(cache.get(1), None);
+        cache.put(1, &samples).unwrap();
+        assert_eq!(cache.get(1), Some(samples));
+
+        // entries at another rate are decoded again
+        assert_eq!(SampleCache::new(&*dir, 48_000, 2).get(1), None);
+    }
+
+    #[test]
+    fn hash_follows_the_contents() {
+        let dir = TempDir::new("hash");
+        let (a, b) = (dir.join("a.wav"), dir.join("b.wav"));
+
+        std::fs::write(&a, b"RIFF1234").unwrap();
+        std::fs::write(&b, b"RIFF1234").unwrap();
+        assert_eq!(content_hash(&a).unwrap(), content_hash(&b).unwrap());
+
+        std::fs::write(&b, b"RIFF1235").unwrap();
+        assert_ne!(content_hash(&a).unwrap(), content_hash(&b).unwrap());
+    }
+}
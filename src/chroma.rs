@@ -0,0 +1,277 @@
+//! Works out the musical key of a melodic sound from its chromagram: how much
+//! energy it has in each of the 12 pitch classes, whatever the octave.
+
+use std::f32::consts::PI;
+
+use serde::{Deserialize, Serialize};
+
+/// Sounds are mixed down to mono and decimated to about this rate before
+/// analysis, which leaves plenty of room above the highest note.
+const ANALYSIS_RATE: u32 = 11_025;
+
+/// Samples per analysis frame at [`ANALYSIS_RATE`], which is long enough to
+/// tell neighbouring semitones apart in the lowest octave.
+const FRAME_LEN: usize = 4096;
+
+/// Only this many frames are analysed, so that long sounds don't hold up
+/// loading.
+const MAX_FRAMES: usize = 64;
+
+/// MIDI notes that the chromagram is made from, C3 to B6.
+const LOWEST_NOTE: u8 = 48;
+const HIGHEST_NOTE: u8 = 95;
+
+/// How well the chromagram has to match a key profile for the sound to count
+/// as being in that key. Drums and noise match every profile about equally
+/// badly.
+const MIN_CORRELATION: f32 = 0.6;
+
+/// Krumhansl-Kessler key profiles, starting from the tonic.
+const MAJOR_PROFILE: [f32; 12] = [
+    6.35, 2.23, 3.48, 2.33, 4.38, 4.09, 2.52, 5.19, 2.39, 3.66, 2.29, 2.88,
+];
+const MINOR_PROFILE: [f32; 12] = [
+    6.33, 2.68, 3.52, 5.38, 2.60, 3.53, 2.54, 4.75, 3.98, 2.69, 3.34, 3.17,
+];
+
+const NOTE_NAMES: [&str; 12] = [
+    "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Mode {
+    Major,
+    Minor,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Key {
+    /// pitch class of the tonic, where 0 is C
+    pub tonic: u8,
+    pub mode: Mode,
+}
+
+impl Key {
+    /// Position on the circle of fifths, counting relative majors and minors
+    /// as the same position.
+    fn fifths(&self) -> u8 {
+        // the relative major of a minor key is 3 semitones up
+        let major_tonic = match self.mode {
+            Mode::Major => self.tonic,
+            Mode::Minor => (self.tonic + 3) % 12,
+        };
+
+        (major_tonic * 7) % 12
+    }
+
+    /// Whether the keys sound bad together. Keys that are the same, relative
+    /// major and minor, or a fifth apart share most of their notes.
+    pub fn clashes(&self, other: &Key) -> bool {
+        let distance = (12 + self.fifths() - other.fifths()) % 12;
+        !matches!(distance, 0 | 1 | 11)
+    }
+}
+
+impl std::fmt::Display for Key {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(NOTE_NAMES[self.tonic as usize])?;
+
+        match self.mode {
+            Mode::Major => Ok(()),
+            Mode::Minor => f.write_str("m"),
+        }
+    }
+}
+
+/// Works out the key of interleaved samples, or None if the sound doesn't
+/// sound like it is in one.
+pub fn detect_key(
+    samples: impl Iterator<Item = f32>,
+    sample_rate: u32,
+    channels: u16,
+) -> Option<Key> {
+    let chroma = chromagram(samples, sample_rate, channels)?;
+
+    let mut best = None;
+
+    for tonic in 0..12 {
+        for (mode, profile) in [(Mode::Major, &MAJOR_PROFILE), (Mode::Minor, &MINOR_PROFILE)] {
+            let rotated: Vec<f32> = (0..12).map(|i| chroma[(tonic + i) % 12]).collect();
+            let r = correlation(&rotated, profile);
+
+            if best.is_none_or(|(best_r, _)| r > best_r) {
+                best = Some((
+                    r,
+                    Key {
+                        tonic: tonic as u8,
+                        mode,
+                    },
+                ));
+            }
+        }
+    }
+
+    best.filter(|(r, _)| *r >= MIN_CORRELATION)
+        .map(|(_, key)| key)
+}
+
+/// Energy in each pitch class, or None if the sound is too short to analyse.
+fn chromagram(
+    samples: impl Iterator<Item = f32>,
+    sample_rate: u32,
+    channels: u16,
+) -> Option<[f32; 12]> {
+    let decimation = (sample_rate / ANALYSIS_RATE).max(1) as usize;
+    let rate = sample_rate as f32 / decimation as f32;
+    let step = channels as usize * decimation;
+
+    // mix down to mono and average each group of frames, which also filters
+    // out what would alias
+    let mut mono = Vec::with_capacity(FRAME_LEN * MAX_FRAMES);
+    let mut sum = 0.;
+    let mut count = 0;
+
+    for sample in samples {
+        sum += sample;
+        count += 1;
+
+        if count == step {
+            mono.push(sum / step as f32);
+            sum = 0.;
+            count = 0;
+
+            if mono.len() == FRAME_LEN * MAX_FRAMES {
+                break;
+            }
+        }
+    }
+
+    if mono.len() < FRAME_LEN {
+        return None;
+    }
+
+    let window: Vec<f32> = (0..FRAME_LEN)
+        .map(|i| 0.5 - 0.5 * (2. * PI * i as f32 / FRAME_LEN as f32).cos())
+        .collect();
+
+    let mut chroma = [0.; 12];
+
+    for frame in mono.chunks_exact(FRAME_LEN) {
+        for note in LOWEST_NOTE..=HIGHEST_NOTE {
+            let freq = 440. * 2f32.powf((note as f32 - 69.) / 12.);
+            chroma[note as usize % 12] += goertzel(frame, &window, freq / rate);
+        }
+    }
+
+    Some(chroma)
+}
+
+/// Power of `frame` at one frequency, given in cycles per sample.
+fn goertzel(frame: &[f32], window: &[f32], freq: f32) -> f32 {
+    let coeff = 2. * (2. * PI * freq).cos();
+    let (mut s1, mut s2) = (0., 0.);
+
+    for (sample, w) in frame.iter().zip(window) {
+        let s = sample * w + coeff * s1 - s2;
+        s2 = s1;
+        s1 = s;
+    }
+
+    s1 * s1 + s2 * s2 - coeff * s1 * s2
+}
+
+/// Pearson correlation of two equally long series.
+fn correlation(a: &[f32], b: &[f32]) -> f32 {
+    let n = a.len() as f32;
+    let mean_a = a.iter().sum::<f32>() / n;
+    let mean_b = b.iter().sum::<f32>() / n;
+
+    let (mut cov, mut var_a, mut var_b) = (0., 0., 0.);
+
+    for (x, y) in a.iter().zip(b) {
+        cov += (x - mean_a) * (y - mean_b);
+        var_a += (x - mean_a).powi(2);
+        var_b += (y - mean_b).powi(2);
+    }
+
+    if var_a == 0. || var_b == 0. {
+        return 0.;
+    }
+
+    cov / (var_a * var_b).sqrt()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Two seconds of stereo sine waves at the given MIDI notes.
+    fn chord(notes: &[u8]) -> Vec<f32> {
+        let rate = 44_100;
+
+        (0..rate * 2)
+            .flat_map(|i| {
+                let t = i as f32 / rate as f32;
+                let sample = notes
+                    .iter()
+                    .map(|&note| {
+                        let freq = 440. * 2f32.powf((note as f32 - 69.) / 12.);
+                        (2. * PI * freq * t).sin() / notes.len() as f32
+                    })
+                    .sum::<f32>();
+                [sample, sample]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn detects_major_and_minor_chords() {
+        // C E G
+        let key = detect_key(chord(&[60, 64, 67]).into_iter(), 44_100, 2);
+        assert_eq!(
+            key,
+            Some(Key {
+                tonic: 0,
+                mode: Mode::Major
+            })
+        );
+
+        // A C E
+        let key = detect_key(chord(&[57, 60, 64]).into_iter(), 44_100, 2);
+        assert_eq!(
+            key,
+            Some(Key {
+                tonic: 9,
+                mode: Mode::Minor
+            })
+        );
+    }
+
+    #[test]
+    fn silence_and_short_sounds_have_no_key() {
+        assert_eq!(
+            detect_key(vec![0.; 44_100 * 2].into_iter(), 44_100, 2),
+            None
+        );
+        assert_eq!(
+            detect_key(chord(&[60]).into_iter().take(100), 44_100, 2),
+            None
+        );
+    }
+
+    #[test]
+    fn neighbouring_keys_dont_clash() {
+        let c = Key {
+            tonic: 0,
+            mode: Mode::Major,
+        };
+        let key = |tonic, mode| Key { tonic, mode };
+
+        assert!(!c.clashes(&key(7, Mode::Major)));
+        assert!(!c.clashes(&key(5, Mode::Major)));
+        assert!(!c.clashes(&key(9, Mode::Minor)));
+        assert!(c.clashes(&key(6, Mode::Major)));
+        assert!(c.clashes(&key(1, Mode::Minor)));
+    }
+}
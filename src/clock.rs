@@ -0,0 +1,101 @@
+//! Time sources for the looper. The engine reads the time through [`Clock`]
+//! instead of calling [`Instant::now`] so that tests and the simulator can run
+//! it on a virtual clock.
+
+use std::{
+    fmt::Debug,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use futures::{future::BoxFuture, FutureExt};
+use tokio::sync::watch;
+
+pub trait Clock: Debug + Send + Sync {
+    fn now(&self) -> Instant;
+
+    /// Returns a future that completes once [`Clock::now`] has reached
+    /// `deadline`.
+    fn sleep_until(&self, deadline: Instant) -> BoxFuture<'static, ()>;
+}
+
+/// The system's monotonic clock.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> BoxFuture<'static, ()> {
+        tokio::time::sleep_until(deadline.into()).boxed()
+    }
+}
+
+/// A clock that only moves when [`VirtualClock::advance`] is called. Clones
+/// share the same time.
+#[derive(Debug, Clone)]
+pub struct VirtualClock {
+    start: Instant,
+    elapsed: Arc<watch::Sender<Duration>>,
+}
+
+impl VirtualClock {
+    pub fn new() -> Self {
+        Self::starting_at(Instant::now())
+    }
+
+    /// Creates a clock that reads `start` until it is advanced.
+    pub fn starting_at(start: Instant) -> Self {
+        let (elapsed, _) = watch::channel(Duration::ZERO);
+
+        Self {
+            start,
+            elapsed: Arc::new(elapsed),
+        }
+    }
+
+    /// How far the clock has been advanced since it was created.
+    pub fn elapsed(&self) -> Duration {
+        *self.elapsed.borrow()
+    }
+
+    /// Moves the clock forward, waking any sleepers whose deadline has passed.
+    pub fn advance(&self, by: Duration) {
+        self.elapsed.send_modify(|elapsed| *elapsed += by);
+    }
+}
+
+impl Default for VirtualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for VirtualClock {
+    fn now(&self) -> Instant {
+        self.start + self.elapsed()
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> BoxFuture<'static, ()> {
+        let target = deadline.saturating_duration_since(self.start);
+        let mut elapsed = self.elapsed.subscribe();
+
+        async move {
+            loop {
+                let reached = *elapsed.borrow() >= target;
+
+                if reached {
+                    break;
+                }
+
+                if elapsed.changed().await.is_err() {
+                    // the clock is gone, so it will never get there
+                    std::future::pending::<()>().await;
+                }
+            }
+        }
+        .boxed()
+    }
+}
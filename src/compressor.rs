@@ -0,0 +1,116 @@
+//! Compressor for the master mix, which turns the mix down while it is over a
+//! threshold so that stacked loops sit together. All the channels share one
+//! envelope, so that the stereo image doesn't move when one side is louder.
+
+use crate::config::CompressorConfig;
+
+/// Compressor for an interleaved signal.
+#[derive(Debug, Clone)]
+pub struct Compressor {
+    threshold_db: f32,
+    /// how much of each dB over the threshold is taken off
+    slope: f32,
+    makeup: f32,
+    /// how far the envelope moves towards a louder or quieter sample
+    attack: f32,
+    release: f32,
+    /// level of the signal that the gain follows, as an amplitude
+    envelope: f32,
+    /// current gain reduction in dB
+    reduction_db: f32,
+}
+
+impl Compressor {
+    pub fn new(config: &CompressorConfig, channels: u16, sample_rate: u32) -> Self {
+        // the envelope moves on every sample, rather than every frame
+        let rate = sample_rate as f32 * channels as f32;
+        let coefficient = |ms: f32| 1. - (-1000. / (ms.max(0.01) * rate)).exp();
+
+        Self {
+            threshold_db: config.threshold_db,
+            slope: 1. - 1. / config.ratio.max(1.),
+            makeup: db_to_gain(config.makeup_db),
+            attack: coefficient(config.attack_ms),
+            release: coefficient(config.release_ms),
+            envelope: 0.,
+            reduction_db: 0.,
+        }
+    }
+
+    pub fn process(&mut self, sample: f32) -> f32 {
+        let level = sample.abs();
+        let coefficient = if level > self.envelope {
+            self.attack
+        } else {
+            self.release
+        };
+        self.envelope += (level - self.envelope) * coefficient;
+
+        let over = gain_to_db(self.envelope) - self.threshold_db;
+        self.reduction_db = over.max(0.) * self.slope;
+
+        sample * db_to_gain(-self.reduction_db) * self.makeup
+    }
+
+    /// How many dB the signal is being turned down by, before the makeup gain.
+    pub fn gain_reduction(&self) -> f32 {
+        self.reduction_db
+    }
+}
+
+fn db_to_gain(db: f32) -> f32 {
+    10f32.powf(db / 20.)
+}
+
+fn gain_to_db(gain: f32) -> f32 {
+    20. * gain.max(f32::EPSILON).log10()
+}
+
+#[cfg(test)]
+mod test {
+    use super::Compressor;
+    use crate::config::CompressorConfig;
+
+    const RATE: u32 = 44_100;
+
+    /// Peak level of a 1kHz sine wave with the given amplitude through the
+    /// compressor, after it has settled.
+    fn level(compressor: &mut Compressor, amplitude: f32) -> f32 {
+        (0..RATE as usize)
+            .map(|i| amplitude * (i as f32 * 2_000. * std::f32::consts::PI / RATE as f32).sin())
+            .map(|x| compressor.process(x))
+            .skip(RATE as usize / 2)
+            .fold(0f32, |peak, y| peak.max(y.abs()))
+    }
+
+    #[test]
+    fn quiet_signals_only_get_the_makeup_gain() {
+        let config = CompressorConfig {
+            threshold_db: -12.,
+            makeup_db: 6.,
+            ..Default::default()
+        };
+        let mut compressor = Compressor::new(&config, 1, RATE);
+
+        let level = level(&mut compressor, 0.1);
+        assert!((level - 0.2).abs() < 0.01, "{level}");
+        assert_eq!(compressor.gain_reduction(), 0.);
+    }
+
+    #[test]
+    fn loud_signals_are_turned_down_by_the_ratio() {
+        let config = CompressorConfig {
+            threshold_db: -20.,
+            ratio: 4.,
+            makeup_db: 0.,
+            ..Default::default()
+        };
+        let mut compressor = Compressor::new(&config, 1, RATE);
+
+        // 20dB over the threshold comes out around 5dB over it, give or take
+        // the ripple of the envelope
+        level(&mut compressor, 1.);
+        let reduction = compressor.gain_reduction();
+        assert!((13. ..17.).contains(&reduction), "{reduction}");
+    }
+}
@@ -0,0 +1,702 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context};
+use serde::{de, Deserialize, Deserializer, Serialize};
+use tracing::warn;
+
+use crate::animation::Animation;
+use crate::driver::adafruit::seesaw::rotary_encoder;
+use crate::grid;
+use crate::keymap::KeyBinding;
+use crate::palette::Palette;
+use crate::tape::TapeSplit;
+use crate::util::to_toml;
+
+/// Path of the configuration file, relative to the working directory.
+pub const CONFIG_PATH: &str = "pidj.toml";
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Config {
+    pub audio: AudioConfig,
+
+    pub play: PlayConfig,
+
+    pub display: DisplayConfig,
+
+    pub keyboard: KeyboardConfig,
+
+    /// Rotary encoder settings. The encoder is only used if this is present.
+    pub encoder: Option<EncoderConfig>,
+
+    /// Footswitches wired to the Pi's GPIO pins.
+    pub footswitches: Vec<FootswitchConfig>,
+
+    /// Trigger output on one of the Pi's GPIO pins, which pulses every
+    /// [`PlayConfig::trigger_every`] ticks. There is none if this isn't set.
+    pub trigger_out: Option<TriggerOutConfig>,
+
+    /// APA102 LED strip that lights up with the level and the beat. There is
+    /// none if this isn't set.
+    pub led_strip: Option<LedStripConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct AudioConfig {
+    /// Directory that sounds are loaded from, relative to the working
+    /// directory.
+    pub dir: PathBuf,
+
+    /// Other directories that sounds are loaded from as well, e.g. on a USB
+    /// stick. With more than one, each is a top-level folder in the sound
+    /// browser. One that isn't there is skipped.
+    pub extra_dirs: Vec<PathBuf>,
+
+    /// How long reading a directory can take before it is given up on, in
+    /// milliseconds, e.g. when a network share has hung. A root that doesn't
+    /// answer in time is skipped and shown as unreachable.
+    pub read_timeout_ms: u64,
+
+    /// Name of the output device. If this is not set, the default device is
+    /// used.
+    pub device: Option<String>,
+
+    /// Directory that full-length tracks are loaded onto the decks from,
+    /// relative to the working directory. Tracks aren't loaded up front like
+    /// sounds are.
+    pub tracks_dir: PathBuf,
+
+    /// Directory that decoded sounds are cached in, relative to the working
+    /// directory. See [`crate::cache`].
+    pub cache_dir: PathBuf,
+
+    /// Compressor on the master mix. See [`crate::compressor`].
+    pub compressor: CompressorConfig,
+
+    /// How many frames the output device asks for at a time. Smaller periods
+    /// play sounds sooner after a key is pressed, but are more likely to run
+    /// out and click. If this is not set, the device's default is used. The
+    /// number of periods in the buffer is up to the device.
+    pub buffer_frames: Option<u32>,
+
+    /// Where the mix goes instead of an output device, e.g. when running
+    /// without a sound card:
+    ///
+    /// ```toml
+    /// sink = "null"
+    /// sink = { file = "mix.wav" }
+    /// ```
+    ///
+    /// If this is not set, the mix is played on [`AudioConfig::device`].
+    pub sink: Option<AudioSink>,
+}
+
+/// Somewhere other than a device that the mix can be played into. It is
+/// played at the same pace as it would be on a device.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AudioSink {
+    /// The mix is thrown away.
+    Null,
+    /// The mix is written to a 16-bit stereo WAV file.
+    File(PathBuf),
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        Self {
+            dir: PathBuf::from("audio"),
+            extra_dirs: vec![],
+            read_timeout_ms: 5_000,
+            device: None,
+            tracks_dir: PathBuf::from("tracks"),
+            cache_dir: PathBuf::from("cache"),
+            compressor: CompressorConfig::default(),
+            buffer_frames: None,
+            sink: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct CompressorConfig {
+    /// Whether the master mix goes through the compressor.
+    pub enabled: bool,
+
+    /// Level above which the mix is turned down, in dBFS.
+    pub threshold_db: f32,
+
+    /// How many dB the mix has to go over the threshold for it to come out
+    /// 1dB over.
+    pub ratio: f32,
+
+    /// How quickly the compressor turns the mix down, in milliseconds.
+    pub attack_ms: f32,
+
+    /// How quickly it brings the mix back up, in milliseconds.
+    pub release_ms: f32,
+
+    /// Gain added after compression, in dB.
+    pub makeup_db: f32,
+}
+
+impl Default for CompressorConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            threshold_db: -12.,
+            ratio: 4.,
+            attack_ms: 10.,
+            release_ms: 100.,
+            makeup_db: 0.,
+        }
+    }
+}
+
+/// What the looper starts with.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct PlayConfig {
+    pub bpm: f32,
+
+    /// Whether loops start on the next multiple of their period, rather than
+    /// when they were added.
+    pub quantize: bool,
+
+    /// Warn when a sound picked in the browser is in a key that clashes with
+    /// the other sounds on the same side of the crossfader.
+    pub warn_key_clashes: bool,
+
+    /// Set list to step through with F1 + F3 + F4. See [`crate::setlist`].
+    pub set_list: Option<PathBuf>,
+
+    /// Keep the current tempo when a kit is loaded, rather than switching to
+    /// the kit's own.
+    pub keep_tempo: bool,
+
+    /// How many ticks apart the pulses on the trigger output are, where there
+    /// are 60 ticks to a beat.
+    pub trigger_every: usize,
+
+    /// How many bars of the master mix F2 + F3 + F4 + a pad records onto the
+    /// pad, where there are 4 beats to a bar.
+    pub bounce_bars: usize,
+
+    /// Browse the sounds on the pads while reassigning a pad, for units
+    /// without a screen. The pads are the folders and then the sounds in the
+    /// current folder. If there are more than 12, the bottom right two pads
+    /// turn back and forward through pages of 10, as does F3.
+    pub pad_browser: bool,
+
+    /// Rhai script with hooks that run when pads are pressed, on every beat
+    /// and when loops play. See [`crate::script`].
+    pub script: Option<PathBuf>,
+
+    /// How far back pad presses are moved when they are recorded into loops,
+    /// in milliseconds, to make up for the time it takes to read the keypad
+    /// and play the sound. If this is not set, the measured key to audio
+    /// latency is used.
+    pub latency_compensation_ms: Option<u64>,
+
+    /// How long loops that play without a gap fade out and back in for where
+    /// they wrap round, in milliseconds. A few milliseconds hides the click of
+    /// a loop that wasn't cut at a zero crossing. See
+    /// [`crate::engine::LoopState::seamless`].
+    pub loop_seam_ms: u64,
+
+    /// When a tape of the master mix starts a new file. See [`crate::tape`].
+    pub tape_split: TapeSplit,
+
+    /// Chords of the function keys that are bound to other actions than the
+    /// built-in ones. See [`crate::keymap`].
+    pub keymap: Vec<KeyBinding>,
+
+    /// While the looper is on, pads start at the top of the next bar like
+    /// clips in a clip launcher, rather than when they are pressed, so that
+    /// loops can't start off the beat even without quantization.
+    pub launch_on_bar: bool,
+}
+
+impl Default for PlayConfig {
+    fn default() -> Self {
+        Self {
+            bpm: 60.,
+            quantize: true,
+            warn_key_clashes: true,
+            set_list: None,
+            keep_tempo: false,
+            trigger_every: 60,
+            bounce_bars: 4,
+            pad_browser: false,
+            script: None,
+            latency_compensation_ms: None,
+            loop_seam_ms: 0,
+            tape_split: TapeSplit::Never,
+            keymap: vec![],
+            launch_on_bar: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct DisplayConfig {
+    /// How many physical pixels make up one point of the UI.
+    pub scale: f32,
+
+    /// Show the UI in a window instead of fullscreen.
+    pub windowed: bool,
+
+    pub theme: Theme,
+
+    /// Size of the window in pixels, as [width, height] of the screen as it
+    /// is mounted, i.e. before the UI is turned by `rotation`.
+    pub size: Option<[f32; 2]>,
+
+    /// How far the UI is turned clockwise, in degrees, for a screen that is
+    /// mounted sideways or upside down: 0, 90, 180 or 270. Touches are turned
+    /// with it.
+    pub rotation: Rotation,
+}
+
+impl Default for DisplayConfig {
+    fn default() -> Self {
+        Self {
+            scale: 4.,
+            windowed: false,
+            theme: Theme::default(),
+            size: None,
+            rotation: Rotation::default(),
+        }
+    }
+}
+
+/// How far the UI is turned clockwise. It is written in degrees in the
+/// config file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "u16", into = "u16")]
+pub enum Rotation {
+    #[default]
+    None,
+    /// a quarter turn clockwise
+    Clockwise,
+    UpsideDown,
+    /// a quarter turn anticlockwise
+    Anticlockwise,
+}
+
+impl Rotation {
+    pub const ALL: [Rotation; 4] = [
+        Rotation::None,
+        Rotation::Clockwise,
+        Rotation::UpsideDown,
+        Rotation::Anticlockwise,
+    ];
+
+    /// Whether the UI is on its side, so that its width and height are
+    /// swapped.
+    pub fn is_sideways(self) -> bool {
+        matches!(self, Rotation::Clockwise | Rotation::Anticlockwise)
+    }
+}
+
+impl TryFrom<u16> for Rotation {
+    type Error = String;
+
+    fn try_from(degrees: u16) -> Result<Self, Self::Error> {
+        match degrees {
+            0 => Ok(Rotation::None),
+            90 => Ok(Rotation::Clockwise),
+            180 => Ok(Rotation::UpsideDown),
+            270 => Ok(Rotation::Anticlockwise),
+            _ => Err(format!("rotation must be 0, 90, 180 or 270, not {degrees}")),
+        }
+    }
+}
+
+impl From<Rotation> for u16 {
+    fn from(rotation: Rotation) -> Self {
+        match rotation {
+            Rotation::None => 0,
+            Rotation::Clockwise => 90,
+            Rotation::UpsideDown => 180,
+            Rotation::Anticlockwise => 270,
+        }
+    }
+}
+
+impl std::fmt::Display for Rotation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}°", u16::from(*self))
+    }
+}
+
+/// Look of the UI.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Theme {
+    #[default]
+    Dark,
+    /// High contrast with big text, for reading from a distance.
+    Stage,
+    Light,
+}
+
+impl Theme {
+    pub const ALL: [Theme; 3] = [Theme::Dark, Theme::Stage, Theme::Light];
+}
+
+impl std::fmt::Display for Theme {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Theme::Dark => "dark",
+            Theme::Stage => "stage",
+            Theme::Light => "light",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct KeyboardConfig {
+    /// I2C bus that the NeoTrellis is connected to. If this is not set, the
+    /// default bus for the board is used.
+    pub bus: Option<I2cBus>,
+
+    /// I2C address of the NeoTrellis. If this is not set, the address is
+    /// detected by scanning the bus.
+    pub address: Option<u8>,
+
+    /// Buttons or footswitches wired to the spare Seesaw pins. Each one pulls
+    /// its pin to ground when pressed.
+    pub gpio_inputs: Vec<GpioInputConfig>,
+
+    /// Potentiometers wired to the Seesaw's analog pins.
+    pub analog_inputs: Vec<AnalogInputConfig>,
+
+    /// Outputs wired to the Seesaw's PWM pins, e.g. an LED bar that follows
+    /// the level of the master mix, or a small fan. See [`crate::pwm`].
+    pub pwm_outputs: Vec<PwmOutputConfig>,
+
+    /// Other Seesaw-based boards whose events are merged into the keyboard's
+    /// events.
+    pub aux_devices: Vec<AuxDeviceConfig>,
+
+    /// Run the keyboard as a task on the tokio runtime using the async driver,
+    /// instead of on dedicated threads. The async driver only handles the
+    /// keypad, its LEDs, the saved settings and the PWM outputs.
+    pub use_async: bool,
+
+    /// Brightness of the keypad LEDs, where 255 is full brightness, on a
+    /// NeoTrellis that doesn't have one saved. Once it is set in the
+    /// settings view, the one saved on the board is used instead. If neither
+    /// is set, the LEDs are at full brightness.
+    pub brightness: Option<u8>,
+
+    /// Column of keypad LEDs (counting from 0 on the left) that shows the
+    /// level of the master mix as a VU meter. The meter takes over the whole
+    /// grid while the keys are left alone. If this is not set, there is no
+    /// meter.
+    pub meter_column: Option<u16>,
+
+    /// USB MIDI grid controller to use instead of the NeoTrellis. The other
+    /// keyboard settings don't apply to it.
+    pub midi_grid: Option<MidiGridConfig>,
+
+    /// Colours of the LEDs: one of [`Palette::BUILTIN`], or one of
+    /// `palettes`. If this is not set, the default palette is used.
+    pub palette: Option<String>,
+
+    /// Palettes of LED colours made in the config, by name. See
+    /// [`crate::palette`].
+    pub palettes: BTreeMap<String, Palette>,
+
+    /// Animations of the LEDs made in the config, by name, which take the
+    /// place of the built-in ones with the same name. See
+    /// [`crate::animation`].
+    pub animations: BTreeMap<String, Animation>,
+}
+
+impl KeyboardConfig {
+    /// The palette that is picked, or the default one if there isn't one by
+    /// that name.
+    pub fn palette(&self) -> Palette {
+        let Some(name) = &self.palette else { return Palette::default(); };
+
+        Palette::named(name, &self.palettes).unwrap_or_else(|| {
+            warn!("there is no LED palette called {name:?}, using the default one");
+            Palette::default()
+        })
+    }
+
+    /// Width and height of the keyboard's grid: the MIDI grid controller's,
+    /// if there is one, or else the NeoTrellis's.
+    pub fn grid_size(&self) -> (usize, usize) {
+        match self.midi_grid {
+            Some(_) => grid::MIDI_GRID,
+            None => grid::NEOTRELLIS,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MidiGridConfig {
+    /// ALSA raw MIDI device of the controller, e.g. `/dev/snd/midiC1D0`.
+    pub device: PathBuf,
+
+    pub model: MidiGridModel,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MidiGridModel {
+    /// Launchpad Mini MK3 or Launchpad X, in programmer mode.
+    Launchpad,
+    /// Akai APC Mini, whose pads can only be green, red or yellow.
+    ApcMini,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum AuxDeviceConfig {
+    /// NeoKey 1x4, whose keys act as extra function keys.
+    NeoKey {
+        #[serde(default)]
+        bus: Option<I2cBus>,
+        address: u8,
+    },
+    /// NeoSlider, which acts as an analog input.
+    NeoSlider {
+        #[serde(default)]
+        bus: Option<I2cBus>,
+        address: u8,
+        control: AnalogControl,
+    },
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct GpioInputConfig {
+    /// Seesaw pin that the input is connected to, from 0 to 31.
+    #[serde(deserialize_with = "deserialize_seesaw_pin")]
+    pub pin: u8,
+
+    /// What pressing the input does.
+    pub action: GpioAction,
+}
+
+/// Reads a Seesaw GPIO pin, refusing pins that don't fit in the 32-bit masks
+/// that the Seesaw's GPIO functions take.
+fn deserialize_seesaw_pin<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u8, D::Error> {
+    let pin = u8::deserialize(deserializer)?;
+
+    if u32::from(pin) >= u32::BITS {
+        return Err(de::Error::custom(format!(
+            "there is no seesaw pin {pin}, pins go from 0 to 31"
+        )));
+    }
+
+    Ok(pin)
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct FootswitchConfig {
+    /// BCM number of the Pi GPIO pin that the switch is connected to. The
+    /// switch pulls the pin to ground when pressed.
+    pub pin: u8,
+
+    /// What pressing the switch does.
+    pub action: GpioAction,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TriggerOutConfig {
+    /// BCM number of the Pi GPIO pin that the sync input is connected to.
+    pub pin: u8,
+
+    /// How long each pulse is high for, in milliseconds.
+    #[serde(default = "default_pulse_ms")]
+    pub pulse_ms: u64,
+}
+
+fn default_pulse_ms() -> u64 {
+    10
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct LedStripConfig {
+    /// SPI bus that the strip is connected to. The strip's data goes to MOSI
+    /// and its clock to SCLK.
+    pub bus: u8,
+
+    /// Number of LEDs on the strip.
+    pub len: usize,
+
+    /// Global brightness of the strip, from 0 to 31.
+    pub brightness: u8,
+
+    /// SPI clock rate in Hz. Long strips may need it lower.
+    pub clock_hz: u32,
+}
+
+impl Default for LedStripConfig {
+    fn default() -> Self {
+        Self {
+            bus: 0,
+            len: 30,
+            brightness: 8,
+            clock_hz: 4_000_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GpioAction {
+    /// Stops the looper, or starts it again from the top of the bar.
+    StartStop,
+    ClearLoops,
+    ToggleQuantize,
+    CycleLoopMode,
+    BpmUp,
+    BpmDown,
+    /// Turns the arpeggiator on or off.
+    ToggleArp,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AnalogInputConfig {
+    /// Seesaw ADC channel that the input is connected to.
+    pub channel: u8,
+
+    /// What the input controls.
+    pub control: AnalogControl,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PwmOutputConfig {
+    /// Seesaw pin that the output is connected to. On the NeoTrellis, only
+    /// pins 4-7 can do PWM.
+    pub pin: u8,
+
+    /// PWM frequency in Hz, e.g. 25000 for a PC fan. If this is not set, the
+    /// Seesaw's default is used.
+    pub frequency: Option<u16>,
+
+    /// What the output follows.
+    pub source: PwmSource,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum PwmSource {
+    /// The level of the master mix, on the same scale as the VU meter.
+    Level,
+    /// The NeoTrellis's temperature, from off at `from` °C to fully on at
+    /// `to` °C.
+    Temperature { from: u32, to: u32 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AnalogControl {
+    Volume,
+    FilterCutoff,
+    Crossfade,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct EncoderConfig {
+    /// I2C bus that the encoder is connected to. If this is not set, the
+    /// default bus for the board is used.
+    pub bus: Option<I2cBus>,
+
+    /// I2C address of the encoder.
+    pub address: u8,
+}
+
+impl Default for EncoderConfig {
+    fn default() -> Self {
+        Self {
+            bus: None,
+            address: rotary_encoder::DEFAULT_ADDRESS,
+        }
+    }
+}
+
+/// An I2C bus, given either as a bus number (`3`) or as the path of its device
+/// node (`"/dev/i2c-3"`). Software (bit-banged) buses created with the
+/// `i2c-gpio` overlay are selected the same way.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum I2cBus {
+    Number(u8),
+    Path(PathBuf),
+}
+
+impl I2cBus {
+    /// Gets the bus number, parsing it out of the device path if necessary.
+    pub fn number(&self) -> anyhow::Result<u8> {
+        match self {
+            I2cBus::Number(bus) => Ok(*bus),
+            I2cBus::Path(path) => path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_prefix("i2c-"))
+                .and_then(|bus| bus.parse().ok())
+                .ok_or_else(|| anyhow!("{path:?} is not an i2c device path")),
+        }
+    }
+}
+
+impl Config {
+    /// Loads the configuration from the given file, or returns the default
+    /// configuration if the file does not exist.
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read config file {path:?}"))?;
+
+        toml::from_str(&text).with_context(|| format!("failed to parse config file {path:?}"))
+    }
+
+    /// Writes the configuration to the given file. The file is replaced in one
+    /// go, so that a unit that is switched off while saving doesn't lose its
+    /// config.
+    pub fn save(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+
+        let text = to_toml(self).context("failed to serialize config")?;
+
+        let partial = path.with_extension("part");
+        std::fs::write(&partial, text)
+            .and_then(|_| std::fs::rename(&partial, path))
+            .with_context(|| format!("failed to write config file {path:?}"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn gpio_pins_have_to_fit_in_the_seesaw_masks() {
+        let config =
+            |pin| format!("[[keyboard.gpio_inputs]]\npin = {pin}\naction = \"start_stop\"\n");
+
+        let valid: Config = toml::from_str(&config(31)).unwrap();
+        assert_eq!(valid.keyboard.gpio_inputs[0].pin, 31);
+
+        let err = toml::from_str::<Config>(&config(32)).unwrap_err();
+        assert!(err.to_string().contains("no seesaw pin 32"), "{err}");
+    }
+}
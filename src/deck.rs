@@ -0,0 +1,362 @@
+//! Plays full-length tracks in deck mode. Tracks are too long to decode before
+//! starting like sounds are, so each one is decoded on its own thread while it
+//! plays. The decoded samples are kept, so that seeking, loops and hot cues
+//! don't have to decode anything again.
+
+use std::{
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use anyhow::Context;
+use rodio::{Decoder, Source};
+use tracing::{debug, warn};
+
+/// One of the two decks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Deck {
+    A,
+    B,
+}
+
+impl Deck {
+    pub const ALL: [Deck; 2] = [Deck::A, Deck::B];
+
+    pub fn index(self) -> usize {
+        match self {
+            Deck::A => 0,
+            Deck::B => 1,
+        }
+    }
+}
+
+impl std::fmt::Display for Deck {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Deck::A => "A",
+            Deck::B => "B",
+        })
+    }
+}
+
+/// Requests to a deck's player.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeckCommand {
+    /// Stops whatever the deck is playing and opens a track, paused at the
+    /// start.
+    Load(PathBuf),
+    Play(bool),
+    Seek(Duration),
+    /// Plays the region between two positions over and over, or stops
+    /// looping.
+    Loop(Option<(Duration, Duration)>),
+    /// Sets the deck's volume, where 1.0 is unity gain.
+    Volume(f32),
+}
+
+/// What a deck's player is doing, as reported to the engine.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct DeckStatus {
+    pub playing: bool,
+    pub position: Duration,
+    /// None until the whole track has been decoded, unless the format says
+    /// how long it is up front
+    pub duration: Option<Duration>,
+}
+
+/// How many samples are decoded at a time. The player only takes a lock when
+/// it moves on to the next block.
+const BLOCK_LEN: usize = 8192;
+
+/// Extensions of the files that can be loaded onto a deck.
+const EXTENSIONS: [&str; 3] = ["wav", "flac", "mp3"];
+
+/// The decoded part of a track, and the controls that the output thread picks
+/// up. Positions are in samples, counting every channel.
+struct Shared {
+    blocks: Mutex<Vec<Arc<[i16]>>>,
+    /// length of the track, which is set once the whole track has been
+    /// decoded
+    len: AtomicU64,
+    /// set when the track is unloaded, which ends the player and the decoder
+    stopped: AtomicBool,
+    playing: AtomicBool,
+    /// stored as the bits of an f32
+    volume: AtomicU32,
+    cursor: AtomicU64,
+    /// u64::MAX if the deck isn't looping
+    loop_start: AtomicU64,
+    loop_end: AtomicU64,
+}
+
+/// A track loaded onto a deck. The track stops playing when this is dropped.
+pub struct Track {
+    shared: Arc<Shared>,
+    channels: u16,
+    sample_rate: u32,
+    /// from the file's header, if it has one
+    duration: Option<Duration>,
+}
+
+impl Track {
+    /// Opens `path`, and starts decoding it on a new thread. The returned
+    /// source plays the track, and should be added to the mixer.
+    pub fn open(path: &Path) -> anyhow::Result<(Self, TrackSource)> {
+        let decoder = open_decoder(path)?;
+
+        let shared = Arc::new(Shared {
+            blocks: Mutex::new(vec![]),
+            len: AtomicU64::new(u64::MAX),
+            stopped: AtomicBool::new(false),
+            playing: AtomicBool::new(false),
+            volume: AtomicU32::new(1f32.to_bits()),
+            cursor: AtomicU64::new(0),
+            loop_start: AtomicU64::new(u64::MAX),
+            loop_end: AtomicU64::new(u64::MAX),
+        });
+
+        let track = Self {
+            shared: shared.clone(),
+            channels: decoder.channels(),
+            sample_rate: decoder.sample_rate(),
+            duration: decoder.total_duration(),
+        };
+
+        let source = track.source();
+
+        std::thread::spawn({
+            let path = path.to_owned();
+            move || decode(&path, decoder, &shared)
+        });
+
+        Ok((track, source))
+    }
+
+    /// Another source that plays the track from where it is, for when the
+    /// one that was in the mixer has been lost along with its output.
+    pub fn source(&self) -> TrackSource {
+        TrackSource {
+            shared: self.shared.clone(),
+            channels: self.channels,
+            sample_rate: self.sample_rate,
+            block: None,
+        }
+    }
+
+    pub fn command(&self, command: DeckCommand) {
+        let shared = &self.shared;
+
+        match command {
+            // handled by whatever owns the track
+            DeckCommand::Load(_) => {}
+            DeckCommand::Play(playing) => shared.playing.store(playing, Ordering::Relaxed),
+            DeckCommand::Seek(position) => {
+                shared
+                    .cursor
+                    .store(self.to_samples(position), Ordering::Relaxed);
+            }
+            DeckCommand::Loop(Some((start, end))) if start < end => {
+                // the player only loops once it sees the end, so turn the
+                // loop off while the start changes
+                shared.loop_end.store(u64::MAX, Ordering::Relaxed);
+                shared
+                    .loop_start
+                    .store(self.to_samples(start), Ordering::Relaxed);
+                shared
+                    .loop_end
+                    .store(self.to_samples(end), Ordering::Relaxed);
+            }
+            DeckCommand::Loop(_) => shared.loop_end.store(u64::MAX, Ordering::Relaxed),
+            DeckCommand::Volume(volume) => shared.volume.store(volume.to_bits(), Ordering::Relaxed),
+        }
+    }
+
+    pub fn status(&self) -> DeckStatus {
+        let shared = &self.shared;
+
+        let duration = match shared.len.load(Ordering::Relaxed) {
+            u64::MAX => self.duration,
+            len => Some(self.to_duration(len)),
+        };
+
+        DeckStatus {
+            playing: shared.playing.load(Ordering::Relaxed),
+            position: self.to_duration(shared.cursor.load(Ordering::Relaxed)),
+            duration,
+        }
+    }
+
+    fn to_samples(&self, position: Duration) -> u64 {
+        let frames = (position.as_secs_f64() * self.sample_rate as f64) as u64;
+        frames * self.channels as u64
+    }
+
+    fn to_duration(&self, samples: u64) -> Duration {
+        let frames = samples / self.channels as u64;
+        Duration::from_secs_f64(frames as f64 / self.sample_rate as f64)
+    }
+}
+
+impl Drop for Track {
+    fn drop(&mut self) {
+        self.shared.stopped.store(true, Ordering::Relaxed);
+    }
+}
+
+fn open_decoder(path: &Path) -> anyhow::Result<Decoder<BufReader<File>>> {
+    let file = File::open(path).with_context(|| format!("failed to open track {path:?}"))?;
+    Decoder::new(BufReader::new(file)).with_context(|| format!("failed to decode track {path:?}"))
+}
+
+fn decode(path: &Path, mut decoder: Decoder<BufReader<File>>, shared: &Shared) {
+    debug!("decoding track {path:?}");
+
+    let mut len = 0;
+
+    loop {
+        if shared.stopped.load(Ordering::Relaxed) {
+            debug!("track {path:?} was unloaded before it was decoded");
+            return;
+        }
+
+        let block: Vec<i16> = decoder.by_ref().take(BLOCK_LEN).collect();
+
+        if block.is_empty() {
+            break;
+        }
+
+        len += block.len() as u64;
+        shared.blocks.lock().unwrap().push(block.into());
+    }
+
+    shared.len.store(len, Ordering::Relaxed);
+
+    debug!("decoded track {path:?}");
+}
+
+/// Plays a [`Track`]. It plays silence while the track is paused, or while the
+/// part that it is up to hasn't been decoded yet, and ends when the track is
+/// unloaded.
+pub struct TrackSource {
+    shared: Arc<Shared>,
+    channels: u16,
+    sample_rate: u32,
+    /// the block that the cursor is in, and its index
+    block: Option<(usize, Arc<[i16]>)>,
+}
+
+/// Looks up the sample at `cursor`, keeping the block that it is in. Returns
+/// None if it hasn't been decoded yet.
+fn sample(shared: &Shared, block: &mut Option<(usize, Arc<[i16]>)>, cursor: u64) -> Option<i16> {
+    let index = cursor as usize / BLOCK_LEN;
+
+    if !matches!(block, Some((i, _)) if *i == index) {
+        // if the decoder holds the lock, this is played as a dropout rather
+        // than holding up the output
+        let blocks = shared.blocks.try_lock().ok()?;
+        *block = Some((index, blocks.get(index)?.clone()));
+    }
+
+    let (_, block) = block.as_ref()?;
+    block.get(cursor as usize % BLOCK_LEN).copied()
+}
+
+impl Iterator for TrackSource {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let shared = &*self.shared;
+
+        if shared.stopped.load(Ordering::Relaxed) {
+            return None;
+        }
+
+        if !shared.playing.load(Ordering::Relaxed) {
+            return Some(0.);
+        }
+
+        let cursor = shared.cursor.load(Ordering::Relaxed);
+
+        if cursor >= shared.len.load(Ordering::Relaxed) {
+            // played to the end, so go back to the start like a CDJ does
+            shared.playing.store(false, Ordering::Relaxed);
+            shared.cursor.store(0, Ordering::Relaxed);
+            return Some(0.);
+        }
+
+        let Some(sample) = sample(shared, &mut self.block, cursor) else { return Some(0.); };
+
+        let mut next = cursor + 1;
+
+        // once the loop's end is reached, stay in the loop even after seeking
+        // past it
+        if next >= shared.loop_end.load(Ordering::Relaxed) {
+            next = shared.loop_start.load(Ordering::Relaxed);
+        }
+
+        // a seek from another thread wins over moving on by one sample
+        let _ = shared
+            .cursor
+            .compare_exchange(cursor, next, Ordering::Relaxed, Ordering::Relaxed);
+
+        let volume = f32::from_bits(shared.volume.load(Ordering::Relaxed));
+        Some(sample as f32 / i16::MAX as f32 * volume)
+    }
+}
+
+impl Source for TrackSource {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// Finds the tracks in `dir` and its subdirectories, sorted by path.
+pub fn list_tracks(dir: &Path) -> Vec<PathBuf> {
+    let mut tracks = vec![];
+    let mut dirs = vec![dir.to_owned()];
+
+    while let Some(dir) = dirs.pop() {
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(err) => {
+                warn!("failed to read tracks in {dir:?}: {err}");
+                continue;
+            }
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+
+            if path.is_dir() {
+                dirs.push(path);
+            } else if path
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| EXTENSIONS.contains(&ext))
+            {
+                tracks.push(path);
+            }
+        }
+    }
+
+    tracks.sort();
+    tracks
+}
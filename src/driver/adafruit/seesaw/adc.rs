@@ -0,0 +1,22 @@
+pub const BASE: u8 = 0x09;
+
+pub mod functions {
+    pub const STATUS: u8 = 0x00;
+    pub const INTEN: u8 = 0x02;
+    pub const INTENCLR: u8 = 0x03;
+    pub const WINMODE: u8 = 0x04;
+    pub const WINTHRESH: u8 = 0x05;
+    /// Channel registers start here, one per ADC channel.
+    pub const CHANNEL_OFFSET: u8 = 0x07;
+}
+
+/// Largest value returned by a 10-bit ADC read.
+pub const MAX_VALUE: u16 = 1023;
+
+pub const fn read_delay_us(function: u8) -> u32 {
+    if function >= functions::CHANNEL_OFFSET {
+        500
+    } else {
+        super::DEFAULT_READ_DELAY_US
+    }
+}
@@ -0,0 +1,268 @@
+//! Async variant of the Seesaw driver, built on the embedded-hal-async traits.
+//! Only the functions needed to drive a NeoTrellis are implemented.
+
+use std::collections::HashMap;
+
+use bytes::{Buf, BufMut, BytesMut};
+use embedded_hal_async::{delay::DelayNs, i2c::I2c};
+use num_traits::FromPrimitive;
+
+use super::{
+    eeprom,
+    keypad::{self, Edge},
+    neopixel::{self, Color, ColorOrder},
+    neotrellis::{self, KeyEvent},
+    standard_read_delay_us, status, timer, Error, SeeSawError, PAYLOAD_MAX,
+};
+
+pub struct AsyncSeeSaw<I2C> {
+    pub i2c: I2C,
+    pub address: u8,
+
+    /// Per-register read delays (in µs) that take precedence over the standard
+    /// delays, keyed by (base, function).
+    pub read_delays: HashMap<(u8, u8), u32>,
+}
+
+impl<I2C: I2c> AsyncSeeSaw<I2C> {
+    pub fn new(i2c: I2C, address: u8) -> Self {
+        Self {
+            i2c,
+            address,
+            read_delays: HashMap::new(),
+        }
+    }
+
+    /// Gets the delay used when reading the given register.
+    pub fn read_delay(&self, base: u8, function: u8) -> u32 {
+        self.read_delays
+            .get(&(base, function))
+            .copied()
+            .unwrap_or_else(|| standard_read_delay_us(base, function))
+    }
+
+    async fn write(&mut self, base: u8, function: u8, buf: &[u8]) -> Result<(), Error<I2C::Error>> {
+        if buf.len() > PAYLOAD_MAX {
+            return Err(Error::SeeSaw(SeeSawError::InvalidSize));
+        }
+
+        let mut tx_buf: [u8; 32] = [0u8; 32];
+
+        let end = 2 + buf.len();
+
+        tx_buf[0] = base;
+        tx_buf[1] = function;
+        tx_buf[2..end].copy_from_slice(buf);
+
+        self.i2c
+            .write(self.address, &tx_buf[..end])
+            .await
+            .map_err(Error::I2c)
+    }
+
+    async fn read<DELAY: DelayNs>(
+        &mut self,
+        base: u8,
+        function: u8,
+        delay: &mut DELAY,
+        buf: &mut [u8],
+    ) -> Result<(), Error<I2C::Error>> {
+        let read_delay = self.read_delay(base, function);
+
+        if read_delay == 0 {
+            return self
+                .i2c
+                .write_read(self.address, &[base, function], buf)
+                .await
+                .map_err(Error::I2c);
+        }
+
+        self.write(base, function, &[]).await?;
+        delay.delay_us(read_delay).await;
+        self.i2c.read(self.address, buf).await.map_err(Error::I2c)
+    }
+
+    pub async fn sw_reset(&mut self) -> Result<(), Error<I2C::Error>> {
+        self.write(status::BASE, status::functions::SWRST, &[0xFF])
+            .await
+    }
+
+    pub async fn get_version<DELAY: DelayNs>(
+        &mut self,
+        delay: &mut DELAY,
+    ) -> Result<u32, Error<I2C::Error>> {
+        let mut buf = [0u8; 4];
+        self.read(status::BASE, status::functions::VERSION, delay, &mut buf)
+            .await?;
+        Ok(u32::from_be_bytes(buf))
+    }
+
+    /// Set the PWM duty cycle of a pin, where `u16::MAX` is fully on.
+    pub async fn set_pwm(&mut self, pin: u8, duty: u16) -> Result<(), Error<I2C::Error>> {
+        let [hi, lo] = duty.to_be_bytes();
+        self.write(timer::BASE, timer::functions::PWM, &[pin, hi, lo])
+            .await
+    }
+
+    /// Set the PWM frequency of a pin in Hz.
+    pub async fn set_pwm_frequency(
+        &mut self,
+        pin: u8,
+        frequency: u16,
+    ) -> Result<(), Error<I2C::Error>> {
+        let [hi, lo] = frequency.to_be_bytes();
+        self.write(timer::BASE, timer::functions::FREQ, &[pin, hi, lo])
+            .await
+    }
+
+    /// Read bytes from the EEPROM, starting at `address`.
+    pub async fn read_eeprom<DELAY: DelayNs>(
+        &mut self,
+        address: u8,
+        buf: &mut [u8],
+        delay: &mut DELAY,
+    ) -> Result<(), Error<I2C::Error>> {
+        if address as usize + buf.len() > eeprom::SIZE as usize {
+            return Err(Error::SeeSaw(SeeSawError::InvalidAddress));
+        }
+
+        self.read(eeprom::BASE, address, delay, buf).await
+    }
+
+    /// Write bytes to the EEPROM, starting at `address`. The EEPROM has limited
+    /// write endurance, so this should not be called often.
+    pub async fn write_eeprom(&mut self, address: u8, buf: &[u8]) -> Result<(), Error<I2C::Error>> {
+        if address as usize + buf.len() > eeprom::SIZE as usize {
+            return Err(Error::SeeSaw(SeeSawError::InvalidAddress));
+        }
+
+        self.write(eeprom::BASE, address, buf).await
+    }
+
+    /// Get temperature in Celsius.
+    pub async fn get_temp<DELAY: DelayNs>(
+        &mut self,
+        delay: &mut DELAY,
+    ) -> Result<u32, Error<I2C::Error>> {
+        let mut buf = [0u8; 4];
+        self.read(status::BASE, status::functions::TEMP, delay, &mut buf)
+            .await?;
+        Ok(u32::from_be_bytes(buf) / (1 << 16))
+    }
+
+    pub async fn set_keypad_event(
+        &mut self,
+        key: u8,
+        edge: Edge,
+        enable: bool,
+    ) -> Result<(), Error<I2C::Error>> {
+        let stat: u8 = (1 << ((edge as u8) + 1)) | (enable as u8);
+        self.write(keypad::BASE, keypad::functions::EVENT, &[key, stat])
+            .await
+    }
+
+    pub async fn get_keypad_event_count<DELAY: DelayNs>(
+        &mut self,
+        delay: &mut DELAY,
+    ) -> Result<u8, Error<I2C::Error>> {
+        let mut buf = [0u8; 1];
+        self.read(keypad::BASE, keypad::functions::COUNT, delay, &mut buf)
+            .await?;
+        Ok(buf[0])
+    }
+
+    pub async fn get_keypad_events_raw<DELAY: DelayNs>(
+        &mut self,
+        buf: &mut [u8],
+        delay: &mut DELAY,
+    ) -> Result<(), Error<I2C::Error>> {
+        self.read(keypad::BASE, keypad::functions::FIFO, delay, buf)
+            .await
+    }
+}
+
+/// Async counterpart of [`super::neotrellis::NeoTrellis`].
+pub struct AsyncNeoTrellis<I2C>(pub AsyncSeeSaw<I2C>);
+
+impl<I2C: I2c> AsyncNeoTrellis<I2C> {
+    pub fn new(seesaw: AsyncSeeSaw<I2C>) -> Self {
+        Self(seesaw)
+    }
+
+    pub async fn init(&mut self) -> Result<(), Error<I2C::Error>> {
+        use neopixel::{functions, BASE};
+
+        // NeoTrellis pin is 3
+        self.0.write(BASE, functions::PIN, &[3]).await?;
+        self.0.write(BASE, functions::SPEED, &[1]).await?;
+
+        let buf = u16::to_be_bytes((16 * neopixel::GRB::BYTES_PER_PIXEL) as u16);
+        self.0.write(BASE, functions::BUF_LENGTH, &buf[..]).await
+    }
+
+    pub async fn set_pixel_color(
+        &mut self,
+        pixel_x: u16,
+        pixel_y: u16,
+        color: Color,
+    ) -> Result<(), Error<I2C::Error>> {
+        let pixel = neotrellis::neotrellis_xy_to_key(pixel_x, pixel_y);
+
+        let mut buf = BytesMut::new();
+        buf.put_u16(pixel * neopixel::GRB::BYTES_PER_PIXEL as u16);
+        neopixel::GRB::put(&mut buf, color);
+        self.0
+            .write(neopixel::BASE, neopixel::functions::BUF, &buf[..])
+            .await
+    }
+
+    pub async fn show(&mut self) -> Result<(), Error<I2C::Error>> {
+        self.0
+            .write(neopixel::BASE, neopixel::functions::SHOW, &[])
+            .await
+    }
+
+    pub async fn set_keypad_event(
+        &mut self,
+        pixel_x: u16,
+        pixel_y: u16,
+        edge: Edge,
+        enable: bool,
+    ) -> Result<(), Error<I2C::Error>> {
+        let key = neotrellis::neotrellis_key_to_seesaw(neotrellis::neotrellis_xy_to_key(
+            pixel_x, pixel_y,
+        ));
+
+        self.0.set_keypad_event(key as u8, edge, enable).await
+    }
+
+    pub async fn get_keypad_events<DELAY: DelayNs>(
+        &mut self,
+        delay: &mut DELAY,
+    ) -> Result<Vec<KeyEvent>, Error<I2C::Error>> {
+        let evt_count = self.0.get_keypad_event_count(delay).await? as usize;
+        if evt_count == 0 {
+            return Ok(Vec::new());
+        }
+
+        let mut evt_buf = BytesMut::zeroed(evt_count + 2);
+        let mut evt_vec = Vec::new();
+        self.0
+            .get_keypad_events_raw(&mut evt_buf[..], delay)
+            .await?;
+
+        for _ in 0..evt_count {
+            let evt = evt_buf.get_u8();
+            let evt = KeyEvent::from_u8(evt).ok_or(Error::SeeSaw(SeeSawError::InvalidKeycode))?;
+
+            if evt.key.0 > 3 || evt.key.1 > 3 {
+                // tiled neotrellis not supported
+                continue;
+            }
+
+            evt_vec.push(evt);
+        }
+
+        Ok(evt_vec)
+    }
+}
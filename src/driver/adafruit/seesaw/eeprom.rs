@@ -0,0 +1,9 @@
+pub const BASE: u8 = 0x0D;
+
+/// Size of the EEPROM in bytes. On the Seesaw, the "function" byte of an
+/// EEPROM transaction is the address within the EEPROM.
+pub const SIZE: u8 = 0x40;
+
+/// Address of the byte holding the I2C address that the Seesaw uses after a
+/// reset.
+pub const I2C_ADDR: u8 = 0x3F;
@@ -0,0 +1,9 @@
+pub const BASE: u8 = 0x11;
+
+pub mod functions {
+    pub const STATUS: u8 = 0x00;
+    pub const INTENSET: u8 = 0x10;
+    pub const INTENCLR: u8 = 0x20;
+    pub const POSITION: u8 = 0x30;
+    pub const DELTA: u8 = 0x40;
+}
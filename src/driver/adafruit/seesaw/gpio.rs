@@ -0,0 +1,29 @@
+pub const BASE: u8 = 0x01;
+
+pub mod functions {
+    pub const DIRSET_BULK: u8 = 0x02;
+    pub const DIRCLR_BULK: u8 = 0x03;
+    pub const BULK: u8 = 0x04;
+    pub const BULK_SET: u8 = 0x05;
+    pub const BULK_CLR: u8 = 0x06;
+    pub const BULK_TOGGLE: u8 = 0x07;
+    pub const INTENSET: u8 = 0x08;
+    pub const INTENCLR: u8 = 0x09;
+    pub const INTFLAG: u8 = 0x0A;
+    pub const PULLENSET: u8 = 0x0B;
+    pub const PULLENCLR: u8 = 0x0C;
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PinMode {
+    Input,
+    InputPullUp,
+    InputPullDown,
+    Output,
+}
+
+/// Converts a list of pin numbers, which are below 32, into the bitmask used
+/// by the bulk functions.
+pub fn pin_mask(pins: impl IntoIterator<Item = u8>) -> u32 {
+    pins.into_iter().fold(0, |mask, pin| mask | (1 << pin))
+}
@@ -12,6 +12,14 @@ pub mod functions {
     pub const FIFO: u8 = 0x10;
 }
 
+pub const fn read_delay_us(function: u8) -> u32 {
+    match function {
+        functions::COUNT => 500,
+        functions::FIFO => 1000,
+        _ => super::DEFAULT_READ_DELAY_US,
+    }
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct KeyEvent {
     pub key: u16,
@@ -1,22 +1,25 @@
 //! Driver for the Adafruit Seesaw.
 //! Based on https://github.com/ferrous-systems/adafruit-seesaw/blob/main/src/lib.rs.
 
-use embedded_hal::blocking::{
-    delay::DelayUs,
-    i2c::{Read, Write},
-};
+use embedded_hal::{delay::DelayNs, i2c::I2c};
+use std::collections::HashMap;
+
 use thiserror::Error;
 use tracing::info;
 
 pub struct SeeSaw<I2C> {
     pub i2c: I2C,
     pub address: u8,
+
+    /// Per-register read delays (in µs) that take precedence over the standard
+    /// delays, keyed by (base, function).
+    pub read_delays: HashMap<(u8, u8), u32>,
 }
 
 #[derive(Debug, Error)]
-pub enum Error {
+pub enum Error<E> {
     #[error("i2c error")]
-    I2c,
+    I2c(#[source] E),
     #[error("seesaw protocol error")]
     SeeSaw(#[from] SeeSawError),
 }
@@ -29,21 +32,68 @@ pub enum SeeSawError {
     InvalidArgument,
     #[error("invalid key code")]
     InvalidKeycode,
+    #[error("address out of range")]
+    InvalidAddress,
 }
 
 const BUFFER_MAX: usize = 32;
 const PAYLOAD_MAX: usize = BUFFER_MAX - 2;
 
+/// Delay between requesting a register and reading it back, used for registers
+/// that don't have a more specific delay.
+pub const DEFAULT_READ_DELAY_US: u32 = 250;
+
+/// Returns how long the Seesaw needs to prepare the given register before it
+/// can be read, based on the delays used by Adafruit's reference driver.
+pub const fn standard_read_delay_us(base: u8, function: u8) -> u32 {
+    match base {
+        adc::BASE => adc::read_delay_us(function),
+        keypad::BASE => keypad::read_delay_us(function),
+        status::BASE => status::read_delay_us(function),
+        _ => DEFAULT_READ_DELAY_US,
+    }
+}
+
+pub mod adc;
+pub mod asynch;
+pub mod eeprom;
+pub mod encoder;
+pub mod gpio;
 pub mod keypad;
+pub mod neokey;
 pub mod neopixel;
+pub mod neoslider;
 pub mod neotrellis;
+pub mod rotary_encoder;
 pub mod status;
+pub mod timer;
 
 impl<I2C> SeeSaw<I2C>
 where
-    I2C: Read + Write,
+    I2C: I2c,
 {
-    fn write(&mut self, base: u8, function: u8, buf: &[u8]) -> Result<(), Error> {
+    pub fn new(i2c: I2C, address: u8) -> Self {
+        Self {
+            i2c,
+            address,
+            read_delays: HashMap::new(),
+        }
+    }
+
+    /// Overrides the delay used when reading the given register.
+    pub fn set_read_delay(&mut self, base: u8, function: u8, delay_us: u32) {
+        self.read_delays.insert((base, function), delay_us);
+    }
+
+    /// Gets the delay used when reading the given register.
+    pub fn read_delay(&self, base: u8, function: u8) -> u32 {
+        self.read_delays
+            .get(&(base, function))
+            .copied()
+            .unwrap_or_else(|| standard_read_delay_us(base, function))
+    }
+
+    fn write(&mut self, base: u8, function: u8, buf: &[u8]) -> Result<(), Error<I2C::Error>> {
         if buf.len() > PAYLOAD_MAX {
             info!("payload max!");
             return Err(Error::SeeSaw(SeeSawError::InvalidSize));
@@ -59,37 +109,270 @@ where
 
         self.i2c
             .write(self.address, &tx_buf[..end])
-            .map_err(|_| Error::I2c)
+            .map_err(Error::I2c)
     }
 
-    fn read<DELAY: DelayUs<u32>>(
+    fn read<DELAY: DelayNs>(
         &mut self,
         base: u8,
         function: u8,
         delay: &mut DELAY,
         buf: &mut [u8],
-    ) -> Result<(), Error> {
+    ) -> Result<(), Error<I2C::Error>> {
+        let read_delay = self.read_delay(base, function);
+
+        // registers that don't need time to prepare can be read in a single
+        // transaction; the rest need the bus released while the Seesaw works
+        if read_delay == 0 {
+            return self
+                .i2c
+                .write_read(self.address, &[base, function], buf)
+                .map_err(Error::I2c);
+        }
+
         self.write(base, function, &[])?;
-        delay.delay_us(14000);
-        self.i2c.read(self.address, buf).map_err(|_| Error::I2c)
+        delay.delay_us(read_delay);
+        self.i2c.read(self.address, buf).map_err(Error::I2c)
+    }
+
+    /// Checks whether a Seesaw device responds at the current address.
+    pub fn probe<DELAY: DelayNs>(&mut self, delay: &mut DELAY) -> bool {
+        match self.get_status_hwid(delay) {
+            Ok(hw_id) => status::is_seesaw_hw_id(hw_id),
+            Err(_) => false,
+        }
+    }
+
+    /// Probes each of the given addresses and returns those at which a Seesaw
+    /// device responded. The current address is left unchanged.
+    pub fn scan<DELAY: DelayNs>(
+        &mut self,
+        addresses: impl IntoIterator<Item = u8>,
+        delay: &mut DELAY,
+    ) -> Vec<u8> {
+        let original = self.address;
+        let mut found = vec![];
+
+        for address in addresses {
+            self.address = address;
+
+            if self.probe(delay) {
+                found.push(address);
+            }
+        }
+
+        self.address = original;
+        found
     }
 
-    pub fn sw_reset(&mut self) -> Result<(), Error> {
+    pub fn sw_reset(&mut self) -> Result<(), Error<I2C::Error>> {
         self.write(status::BASE, status::functions::SWRST, &[0xFF])
     }
 
+    /// Set the mode of all of the pins in the bitmask.
+    pub fn set_gpio_pin_mode_bulk(
+        &mut self,
+        pins: u32,
+        mode: gpio::PinMode,
+    ) -> Result<(), Error<I2C::Error>> {
+        use gpio::functions::{BULK_CLR, BULK_SET, DIRCLR_BULK, DIRSET_BULK, PULLENCLR, PULLENSET};
+
+        let pins = pins.to_be_bytes();
+
+        match mode {
+            gpio::PinMode::Output => {
+                self.write(gpio::BASE, DIRSET_BULK, &pins)?;
+            }
+            gpio::PinMode::Input => {
+                self.write(gpio::BASE, DIRCLR_BULK, &pins)?;
+                self.write(gpio::BASE, PULLENCLR, &pins)?;
+            }
+            gpio::PinMode::InputPullUp => {
+                self.write(gpio::BASE, DIRCLR_BULK, &pins)?;
+                self.write(gpio::BASE, PULLENSET, &pins)?;
+                self.write(gpio::BASE, BULK_SET, &pins)?;
+            }
+            gpio::PinMode::InputPullDown => {
+                self.write(gpio::BASE, DIRCLR_BULK, &pins)?;
+                self.write(gpio::BASE, PULLENSET, &pins)?;
+                self.write(gpio::BASE, BULK_CLR, &pins)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Read the level of all of the GPIO pins as a bitmask.
+    pub fn get_gpio_bulk<DELAY: DelayNs>(
+        &mut self,
+        delay: &mut DELAY,
+    ) -> Result<u32, Error<I2C::Error>> {
+        let mut buf = [0u8; 4];
+        self.read(gpio::BASE, gpio::functions::BULK, delay, &mut buf)?;
+        Ok(u32::from_be_bytes(buf))
+    }
+
+    /// Drive all of the output pins in the bitmask high or low.
+    pub fn set_gpio_bulk(&mut self, pins: u32, high: bool) -> Result<(), Error<I2C::Error>> {
+        use gpio::functions::{BULK_CLR, BULK_SET};
+
+        let func = if high { BULK_SET } else { BULK_CLR };
+        self.write(gpio::BASE, func, &pins.to_be_bytes())
+    }
+
+    /// Toggle all of the output pins in the bitmask.
+    pub fn toggle_gpio_bulk(&mut self, pins: u32) -> Result<(), Error<I2C::Error>> {
+        self.write(
+            gpio::BASE,
+            gpio::functions::BULK_TOGGLE,
+            &pins.to_be_bytes(),
+        )
+    }
+
+    /// Enable or disable the interrupt on all of the pins in the bitmask. The
+    /// interrupt fires whenever one of the pins changes level.
+    pub fn set_gpio_interrupts(
+        &mut self,
+        pins: u32,
+        enable: bool,
+    ) -> Result<(), Error<I2C::Error>> {
+        use gpio::functions::{INTENCLR, INTENSET};
+
+        let func = if enable { INTENSET } else { INTENCLR };
+        self.write(gpio::BASE, func, &pins.to_be_bytes())
+    }
+
+    /// Get the bitmask of pins that have changed level since the last time the
+    /// flags were read. Reading the flags clears them.
+    pub fn get_gpio_interrupt_flags<DELAY: DelayNs>(
+        &mut self,
+        delay: &mut DELAY,
+    ) -> Result<u32, Error<I2C::Error>> {
+        let mut buf = [0u8; 4];
+        self.read(gpio::BASE, gpio::functions::INTFLAG, delay, &mut buf)?;
+        Ok(u32::from_be_bytes(buf))
+    }
+
+    /// Read an ADC channel. On SAMD09-based boards channels 0-3 are pins 2-5;
+    /// on ATtiny-based boards the channel is the pin number.
+    pub fn get_analog<DELAY: DelayNs>(
+        &mut self,
+        channel: u8,
+        delay: &mut DELAY,
+    ) -> Result<u16, Error<I2C::Error>> {
+        let mut buf = [0u8; 2];
+        self.read(
+            adc::BASE,
+            adc::functions::CHANNEL_OFFSET + channel,
+            delay,
+            &mut buf,
+        )?;
+        Ok(u16::from_be_bytes(buf))
+    }
+
+    /// Set the PWM duty cycle of a pin, where `u16::MAX` is fully on. On
+    /// SAMD09-based boards only pins 4-7 support PWM.
+    pub fn set_pwm(&mut self, pin: u8, duty: u16) -> Result<(), Error<I2C::Error>> {
+        let [hi, lo] = duty.to_be_bytes();
+        self.write(timer::BASE, timer::functions::PWM, &[pin, hi, lo])
+    }
+
+    /// Set the PWM frequency of a pin in Hz.
+    pub fn set_pwm_frequency(&mut self, pin: u8, frequency: u16) -> Result<(), Error<I2C::Error>> {
+        let [hi, lo] = frequency.to_be_bytes();
+        self.write(timer::BASE, timer::functions::FREQ, &[pin, hi, lo])
+    }
+
+    /// Drive a hobby servo connected to a PWM pin to the given angle (0-180
+    /// degrees).
+    pub fn set_servo_angle(&mut self, pin: u8, angle: f32) -> Result<(), Error<I2C::Error>> {
+        self.set_pwm_frequency(pin, timer::SERVO_FREQUENCY)?;
+        self.set_pwm(pin, timer::servo_duty(angle))
+    }
+
+    /// Read bytes from the EEPROM, starting at `address`.
+    pub fn read_eeprom<DELAY: DelayNs>(
+        &mut self,
+        address: u8,
+        buf: &mut [u8],
+        delay: &mut DELAY,
+    ) -> Result<(), Error<I2C::Error>> {
+        if address as usize + buf.len() > eeprom::SIZE as usize {
+            return Err(Error::SeeSaw(SeeSawError::InvalidAddress));
+        }
+
+        self.read(eeprom::BASE, address, delay, buf)
+    }
+
+    /// Write bytes to the EEPROM, starting at `address`. The EEPROM has limited
+    /// write endurance, so this should not be called often.
+    pub fn write_eeprom(&mut self, address: u8, buf: &[u8]) -> Result<(), Error<I2C::Error>> {
+        if address as usize + buf.len() > eeprom::SIZE as usize {
+            return Err(Error::SeeSaw(SeeSawError::InvalidAddress));
+        }
+
+        self.write(eeprom::BASE, address, buf)
+    }
+
+    /// Change the I2C address of the Seesaw. The new address is stored in the
+    /// EEPROM and takes effect immediately.
+    pub fn set_i2c_address(&mut self, address: u8) -> Result<(), Error<I2C::Error>> {
+        self.write_eeprom(eeprom::I2C_ADDR, &[address])?;
+        self.sw_reset()?;
+        self.address = address;
+        Ok(())
+    }
+
+    /// Get the absolute position of the encoder.
+    pub fn get_encoder_position<DELAY: DelayNs>(
+        &mut self,
+        delay: &mut DELAY,
+    ) -> Result<i32, Error<I2C::Error>> {
+        let mut buf = [0u8; 4];
+        self.read(encoder::BASE, encoder::functions::POSITION, delay, &mut buf)?;
+        Ok(i32::from_be_bytes(buf))
+    }
+
+    /// Set the absolute position of the encoder.
+    pub fn set_encoder_position(&mut self, position: i32) -> Result<(), Error<I2C::Error>> {
+        self.write(
+            encoder::BASE,
+            encoder::functions::POSITION,
+            &position.to_be_bytes(),
+        )
+    }
+
+    /// Get how far the encoder has moved since the last time the position or
+    /// delta was read.
+    pub fn get_encoder_delta<DELAY: DelayNs>(
+        &mut self,
+        delay: &mut DELAY,
+    ) -> Result<i32, Error<I2C::Error>> {
+        let mut buf = [0u8; 4];
+        self.read(encoder::BASE, encoder::functions::DELTA, delay, &mut buf)?;
+        Ok(i32::from_be_bytes(buf))
+    }
+
+    /// Enable or disable the encoder interrupt
+    pub fn set_encoder_interrupt(&mut self, enable: bool) -> Result<(), Error<I2C::Error>> {
+        use encoder::functions::{INTENCLR, INTENSET};
+
+        let func = if enable { INTENSET } else { INTENCLR };
+        self.write(encoder::BASE, func, &[1])
+    }
+
     /// Get the count of pending key events on the keypad
-    pub fn get_keypad_event_count<DELAY: DelayUs<u32>>(
+    pub fn get_keypad_event_count<DELAY: DelayNs>(
         &mut self,
         delay: &mut DELAY,
-    ) -> Result<u8, Error> {
+    ) -> Result<u8, Error<I2C::Error>> {
         let mut buf = [0u8; 1];
         self.read(keypad::BASE, keypad::functions::COUNT, delay, &mut buf)?;
         Ok(buf[0])
     }
 
     /// Enable or disable the interrupt
-    pub fn set_keypad_interrupt(&mut self, enable: bool) -> Result<(), Error> {
+    pub fn set_keypad_interrupt(&mut self, enable: bool) -> Result<(), Error<I2C::Error>> {
         use keypad::functions::{INTENCLR, INTENSET};
 
         let func = if enable { INTENSET } else { INTENCLR };
@@ -102,45 +385,53 @@ where
         key: u8,
         edge: keypad::Edge,
         enable: bool,
-    ) -> Result<(), Error> {
+    ) -> Result<(), Error<I2C::Error>> {
         let stat: u8 = (1 << ((edge as u8) + 1)) | (enable as u8);
         self.write(keypad::BASE, keypad::functions::EVENT, &[key, stat])
     }
 
-    pub fn get_keypad_events_raw<DELAY: DelayUs<u32>>(
+    pub fn get_keypad_events_raw<DELAY: DelayNs>(
         &mut self,
         buf: &mut [u8],
         delay: &mut DELAY,
-    ) -> Result<(), Error> {
+    ) -> Result<(), Error<I2C::Error>> {
         self.read(keypad::BASE, keypad::functions::FIFO, delay, buf)
     }
 
-    pub fn get_status_hwid<DELAY: DelayUs<u32>>(&mut self, delay: &mut DELAY) -> Result<u8, Error> {
+    pub fn get_status_hwid<DELAY: DelayNs>(
+        &mut self,
+        delay: &mut DELAY,
+    ) -> Result<u8, Error<I2C::Error>> {
         let mut buf = [0u8; 1];
-        self.read(status::BASE, status::functions::HW_ID, delay, &mut buf)
-            .map_err(|_| Error::I2c)?;
+        self.read(status::BASE, status::functions::HW_ID, delay, &mut buf)?;
         Ok(buf[0])
     }
 
-    pub fn get_version<DELAY: DelayUs<u32>>(&mut self, delay: &mut DELAY) -> Result<u32, Error> {
+    pub fn get_version<DELAY: DelayNs>(
+        &mut self,
+        delay: &mut DELAY,
+    ) -> Result<u32, Error<I2C::Error>> {
         let mut buf = [0u8; 4];
-        self.read(status::BASE, status::functions::VERSION, delay, &mut buf)
-            .map_err(|_| Error::I2c)?;
+        self.read(status::BASE, status::functions::VERSION, delay, &mut buf)?;
         Ok(u32::from_be_bytes(buf))
     }
 
-    pub fn get_options<DELAY: DelayUs<u32>>(&mut self, delay: &mut DELAY) -> Result<u32, Error> {
+    pub fn get_options<DELAY: DelayNs>(
+        &mut self,
+        delay: &mut DELAY,
+    ) -> Result<u32, Error<I2C::Error>> {
         let mut buf = [0u8; 4];
-        self.read(status::BASE, status::functions::OPTIONS, delay, &mut buf)
-            .map_err(|_| Error::I2c)?;
+        self.read(status::BASE, status::functions::OPTIONS, delay, &mut buf)?;
         Ok(u32::from_be_bytes(buf))
     }
 
     /// Get temperature in Celsius.
-    pub fn get_temp<DELAY: DelayUs<u32>>(&mut self, delay: &mut DELAY) -> Result<u32, Error> {
+    pub fn get_temp<DELAY: DelayNs>(
+        &mut self,
+        delay: &mut DELAY,
+    ) -> Result<u32, Error<I2C::Error>> {
         let mut buf = [0u8; 4];
-        self.read(status::BASE, status::functions::TEMP, delay, &mut buf)
-            .map_err(|_| Error::I2c)?;
+        self.read(status::BASE, status::functions::TEMP, delay, &mut buf)?;
         Ok(u32::from_be_bytes(buf) / (1 << 16))
     }
 }
@@ -0,0 +1,88 @@
+use std::ops::{Deref, DerefMut};
+
+use super::{
+    gpio,
+    neopixel::{self, Color, NeoPixel},
+    Error, SeeSaw, SeeSawError,
+};
+use embedded_hal::{delay::DelayNs, i2c::I2c};
+
+/// Default I2C address of the NeoKey 1x4 (no address jumpers bridged).
+pub const DEFAULT_ADDRESS: u8 = 0x30;
+
+/// Number of keys on the NeoKey 1x4.
+pub const KEY_COUNT: usize = 4;
+
+/// Seesaw pins that the keys are connected to, from left to right.
+const KEY_PINS: [u8; KEY_COUNT] = [4, 5, 6, 7];
+
+/// Seesaw pin that the NeoPixels under the keys are connected to.
+const NEOPIXEL_PIN: u8 = 3;
+
+/// Adafruit NeoKey 1x4: a Seesaw with four mechanical keys, each with a
+/// NeoPixel underneath.
+pub struct NeoKey<
+    I2C: I2c,
+    S: DerefMut<Target = SeeSaw<I2C>>,
+    NP: DerefMut<Target = NeoPixel<I2C, S, neopixel::GRB, 4>>,
+>(NP);
+
+impl<
+        I2C: I2c,
+        S: DerefMut<Target = SeeSaw<I2C>>,
+        NP: DerefMut<Target = NeoPixel<I2C, S, neopixel::GRB, 4>>,
+    > Deref for NeoKey<I2C, S, NP>
+{
+    type Target = NeoPixel<I2C, S, neopixel::GRB, 4>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<
+        I2C: I2c,
+        S: DerefMut<Target = SeeSaw<I2C>>,
+        NP: DerefMut<Target = NeoPixel<I2C, S, neopixel::GRB, 4>>,
+    > DerefMut for NeoKey<I2C, S, NP>
+{
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl<
+        I2C: I2c,
+        S: DerefMut<Target = SeeSaw<I2C>>,
+        NP: DerefMut<Target = NeoPixel<I2C, S, neopixel::GRB, 4>>,
+    > NeoKey<I2C, S, NP>
+{
+    pub fn new(inner: NP) -> Self {
+        Self(inner)
+    }
+
+    pub fn init(&mut self) -> Result<(), Error<I2C::Error>> {
+        self.0.init(true, NEOPIXEL_PIN)?;
+        self.0
+            .set_gpio_pin_mode_bulk(gpio::pin_mask(KEY_PINS), gpio::PinMode::InputPullUp)
+    }
+
+    /// Check which keys are currently held down, from left to right.
+    pub fn get_keys<DELAY: DelayNs>(
+        &mut self,
+        delay: &mut DELAY,
+    ) -> Result<[bool; KEY_COUNT], Error<I2C::Error>> {
+        let levels = self.0.get_gpio_bulk(delay)?;
+
+        // keys pull their pins low when pressed
+        Ok(KEY_PINS.map(|pin| levels & (1 << pin) == 0))
+    }
+
+    pub fn set_key_color(&mut self, key: usize, color: Color) -> Result<(), Error<I2C::Error>> {
+        if key >= KEY_COUNT {
+            return Err(Error::SeeSaw(SeeSawError::InvalidArgument));
+        }
+
+        self.0.set_pixel_color(key as u16, color)
+    }
+}
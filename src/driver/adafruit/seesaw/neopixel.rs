@@ -4,7 +4,7 @@ use std::{
 };
 
 use bytes::{BufMut, BytesMut};
-use embedded_hal::blocking::i2c::{Read, Write};
+use embedded_hal::i2c::I2c;
 
 use super::{Error, SeeSaw};
 pub use color::*;
@@ -113,22 +113,30 @@ pub mod color {
         pub fn from_u8(r: u8, g: u8, b: u8) -> Color {
             Self { r, g, b, w: 255 }
         }
+
+        /// Scales the color by `brightness`, where 255 leaves it unchanged.
+        pub fn scale(self, brightness: u8) -> Color {
+            let scale = |c: u8| ((c as u16 * brightness as u16) / 255) as u8;
+
+            Self {
+                r: scale(self.r),
+                g: scale(self.g),
+                b: scale(self.b),
+                w: scale(self.w),
+            }
+        }
     }
 }
 
 pub struct NeoPixel<
-    I2C: Read + Write,
+    I2C: I2c,
     S: DerefMut<Target = SeeSaw<I2C>>,
     P: ColorOrder,
     const PIXEL_COUNT: u8,
 >(S, PhantomData<P>);
 
-impl<
-        I2C: Read + Write,
-        S: DerefMut<Target = SeeSaw<I2C>>,
-        P: ColorOrder,
-        const PIXEL_COUNT: u8,
-    > Deref for NeoPixel<I2C, S, P, PIXEL_COUNT>
+impl<I2C: I2c, S: DerefMut<Target = SeeSaw<I2C>>, P: ColorOrder, const PIXEL_COUNT: u8> Deref
+    for NeoPixel<I2C, S, P, PIXEL_COUNT>
 {
     type Target = SeeSaw<I2C>;
 
@@ -137,30 +145,22 @@ impl<
     }
 }
 
-impl<
-        I2C: Read + Write,
-        S: DerefMut<Target = SeeSaw<I2C>>,
-        P: ColorOrder,
-        const PIXEL_COUNT: u8,
-    > DerefMut for NeoPixel<I2C, S, P, PIXEL_COUNT>
+impl<I2C: I2c, S: DerefMut<Target = SeeSaw<I2C>>, P: ColorOrder, const PIXEL_COUNT: u8> DerefMut
+    for NeoPixel<I2C, S, P, PIXEL_COUNT>
 {
     fn deref_mut(&mut self) -> &mut Self::Target {
         &mut self.0
     }
 }
 
-impl<
-        I2C: Read + Write,
-        S: DerefMut<Target = SeeSaw<I2C>>,
-        P: ColorOrder,
-        const PIXEL_COUNT: u8,
-    > NeoPixel<I2C, S, P, PIXEL_COUNT>
+impl<I2C: I2c, S: DerefMut<Target = SeeSaw<I2C>>, P: ColorOrder, const PIXEL_COUNT: u8>
+    NeoPixel<I2C, S, P, PIXEL_COUNT>
 {
     pub fn new(inner: S) -> Self {
         Self(inner, PhantomData)
     }
 
-    pub fn init(&mut self, high_speed: bool, pin: u8) -> Result<(), Error> {
+    pub fn init(&mut self, high_speed: bool, pin: u8) -> Result<(), Error<I2C::Error>> {
         self.write(BASE, functions::PIN, &[pin])?;
         self.write(BASE, functions::SPEED, &[high_speed as u8])?;
 
@@ -170,14 +170,14 @@ impl<
         Ok(())
     }
 
-    pub fn set_pixel_color(&mut self, pixel: u16, color: Color) -> Result<(), Error> {
+    pub fn set_pixel_color(&mut self, pixel: u16, color: Color) -> Result<(), Error<I2C::Error>> {
         let mut buf = BytesMut::new();
         buf.put_u16(pixel * P::BYTES_PER_PIXEL as u16);
         P::put(&mut buf, color);
         self.write(BASE, functions::BUF, &buf[..])
     }
 
-    pub fn show(&mut self) -> Result<(), Error> {
+    pub fn show(&mut self) -> Result<(), Error<I2C::Error>> {
         self.write(BASE, functions::SHOW, &[])
     }
 }
@@ -0,0 +1,85 @@
+use std::ops::{Deref, DerefMut};
+
+use super::{
+    adc,
+    neopixel::{self, Color, NeoPixel},
+    Error, SeeSaw,
+};
+use embedded_hal::{delay::DelayNs, i2c::I2c};
+
+/// Default I2C address of the NeoSlider (no address jumpers bridged).
+pub const DEFAULT_ADDRESS: u8 = 0x30;
+
+/// ADC channel that the slider potentiometer is connected to.
+const SLIDER_CHANNEL: u8 = 18;
+
+/// Seesaw pin that the NeoPixels along the slider are connected to.
+const NEOPIXEL_PIN: u8 = 14;
+
+/// Adafruit NeoSlider: a Seesaw with a slide potentiometer and four NeoPixels
+/// along its length.
+pub struct NeoSlider<
+    I2C: I2c,
+    S: DerefMut<Target = SeeSaw<I2C>>,
+    NP: DerefMut<Target = NeoPixel<I2C, S, neopixel::GRB, 4>>,
+>(NP);
+
+impl<
+        I2C: I2c,
+        S: DerefMut<Target = SeeSaw<I2C>>,
+        NP: DerefMut<Target = NeoPixel<I2C, S, neopixel::GRB, 4>>,
+    > Deref for NeoSlider<I2C, S, NP>
+{
+    type Target = NeoPixel<I2C, S, neopixel::GRB, 4>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<
+        I2C: I2c,
+        S: DerefMut<Target = SeeSaw<I2C>>,
+        NP: DerefMut<Target = NeoPixel<I2C, S, neopixel::GRB, 4>>,
+    > DerefMut for NeoSlider<I2C, S, NP>
+{
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl<
+        I2C: I2c,
+        S: DerefMut<Target = SeeSaw<I2C>>,
+        NP: DerefMut<Target = NeoPixel<I2C, S, neopixel::GRB, 4>>,
+    > NeoSlider<I2C, S, NP>
+{
+    pub fn new(inner: NP) -> Self {
+        Self(inner)
+    }
+
+    pub fn init(&mut self) -> Result<(), Error<I2C::Error>> {
+        self.0.init(true, NEOPIXEL_PIN)
+    }
+
+    /// Get the position of the slider, between 0 and 1.
+    pub fn get_value<DELAY: DelayNs>(
+        &mut self,
+        delay: &mut DELAY,
+    ) -> Result<f32, Error<I2C::Error>> {
+        let value = self.0.get_analog(SLIDER_CHANNEL, delay)?;
+        Ok(value as f32 / adc::MAX_VALUE as f32)
+    }
+
+    /// Light the NeoPixels along the slider up to `value` (between 0 and 1).
+    pub fn set_level(&mut self, value: f32, color: Color) -> Result<(), Error<I2C::Error>> {
+        let lit = (value.clamp(0., 1.) * 4.).round() as u16;
+
+        for pixel in 0..4 {
+            self.0
+                .set_pixel_color(pixel, if pixel < lit { color } else { Color::BLACK })?;
+        }
+
+        self.0.show()
+    }
+}
@@ -1,4 +1,4 @@
-use std::ops::{Deref, DerefMut};
+use std::ops::{Deref, DerefMut, RangeInclusive};
 
 use super::{
     keypad::Edge,
@@ -6,20 +6,17 @@ use super::{
     Error, SeeSaw, SeeSawError,
 };
 use bytes::{Buf, BytesMut};
-use embedded_hal::blocking::{
-    delay::DelayUs,
-    i2c::{Read, Write},
-};
+use embedded_hal::{delay::DelayNs, i2c::I2c};
 use num_traits::FromPrimitive;
 
 pub struct NeoTrellis<
-    I2C: Read + Write,
+    I2C: I2c,
     S: DerefMut<Target = SeeSaw<I2C>>,
     NP: DerefMut<Target = NeoPixel<I2C, S, neopixel::GRB, 16>>,
 >(NP);
 
 impl<
-        I2C: Read + Write,
+        I2C: I2c,
         S: DerefMut<Target = SeeSaw<I2C>>,
         NP: DerefMut<Target = NeoPixel<I2C, S, neopixel::GRB, 16>>,
     > Deref for NeoTrellis<I2C, S, NP>
@@ -32,7 +29,7 @@ impl<
 }
 
 impl<
-        I2C: Read + Write,
+        I2C: I2c,
         S: DerefMut<Target = SeeSaw<I2C>>,
         NP: DerefMut<Target = NeoPixel<I2C, S, neopixel::GRB, 16>>,
     > DerefMut for NeoTrellis<I2C, S, NP>
@@ -42,6 +39,12 @@ impl<
     }
 }
 
+/// Default I2C address of the NeoTrellis (no address jumpers bridged).
+pub const DEFAULT_ADDRESS: u8 = 0x2E;
+
+/// Addresses the NeoTrellis can be strapped to using its address jumpers.
+pub const ADDRESSES: RangeInclusive<u8> = 0x2E..=0x32;
+
 // converts x and y into a neotrellis key code
 pub const fn neotrellis_xy_to_key(x: u16, y: u16) -> u16 {
     y * 4 + x
@@ -53,7 +56,7 @@ pub const fn neotrellis_key_to_xy(k: u16) -> (u16, u16) {
 }
 
 // converts neotrellis keycode into seesaw key code
-const fn neotrellis_key_to_seesaw(k: u16) -> u16 {
+pub const fn neotrellis_key_to_seesaw(k: u16) -> u16 {
     k / 4 * 8 + k % 4
 }
 
@@ -81,11 +84,11 @@ impl From<super::keypad::KeyEvent> for KeyEvent {
     }
 }
 
-impl Into<super::keypad::KeyEvent> for KeyEvent {
-    fn into(self) -> super::keypad::KeyEvent {
-        super::keypad::KeyEvent {
-            key: neotrellis_key_to_seesaw(neotrellis_xy_to_key(self.key.0, self.key.1)),
-            edge: self.edge,
+impl From<KeyEvent> for super::keypad::KeyEvent {
+    fn from(event: KeyEvent) -> Self {
+        Self {
+            key: neotrellis_key_to_seesaw(neotrellis_xy_to_key(event.key.0, event.key.1)),
+            edge: event.edge,
         }
     }
 }
@@ -101,7 +104,7 @@ impl FromPrimitive for KeyEvent {
 }
 
 impl<
-        I2C: Read + Write,
+        I2C: I2c,
         S: DerefMut<Target = SeeSaw<I2C>>,
         NP: DerefMut<Target = NeoPixel<I2C, S, neopixel::GRB, 16>>,
     > NeoTrellis<I2C, S, NP>
@@ -110,7 +113,7 @@ impl<
         Self(inner)
     }
 
-    pub fn init(&mut self) -> Result<(), Error> {
+    pub fn init(&mut self) -> Result<(), Error<I2C::Error>> {
         // NeoTrellis pin is 3
         self.0.init(true, 3)
     }
@@ -120,7 +123,7 @@ impl<
         pixel_x: u16,
         pixel_y: u16,
         color: Color,
-    ) -> Result<(), Error> {
+    ) -> Result<(), Error<I2C::Error>> {
         self.0
             .set_pixel_color(neotrellis_xy_to_key(pixel_x, pixel_y), color)
     }
@@ -131,7 +134,7 @@ impl<
         pixel_y: u16,
         edge: Edge,
         enable: bool,
-    ) -> Result<(), Error> {
+    ) -> Result<(), Error<I2C::Error>> {
         self.0.set_keypad_event(
             neotrellis_key_to_seesaw(neotrellis_xy_to_key(pixel_x, pixel_y)) as u8,
             edge,
@@ -139,10 +142,10 @@ impl<
         )
     }
 
-    pub fn get_keypad_events<DELAY: DelayUs<u32>>(
+    pub fn get_keypad_events<DELAY: DelayNs>(
         &mut self,
         delay: &mut DELAY,
-    ) -> Result<Vec<KeyEvent>, Error> {
+    ) -> Result<Vec<KeyEvent>, Error<I2C::Error>> {
         let evt_count = self.0.get_keypad_event_count(delay)? as usize;
         if evt_count == 0 {
             return Ok(Vec::new());
@@ -0,0 +1,89 @@
+use std::ops::{Deref, DerefMut};
+
+use super::{
+    gpio,
+    neopixel::{self, Color, NeoPixel},
+    Error, SeeSaw,
+};
+use embedded_hal::{delay::DelayNs, i2c::I2c};
+
+/// Default I2C address of the Adafruit I2C QT rotary encoder (no address
+/// jumpers bridged).
+pub const DEFAULT_ADDRESS: u8 = 0x36;
+
+/// Seesaw pin that the push-button of the encoder is connected to.
+const BUTTON_PIN: u8 = 24;
+
+/// Seesaw pin that the NeoPixel of the encoder is connected to.
+const NEOPIXEL_PIN: u8 = 6;
+
+/// Adafruit I2C QT rotary encoder: a Seesaw with one encoder, a push-button and
+/// a single NeoPixel.
+pub struct RotaryEncoder<
+    I2C: I2c,
+    S: DerefMut<Target = SeeSaw<I2C>>,
+    NP: DerefMut<Target = NeoPixel<I2C, S, neopixel::GRB, 1>>,
+>(NP);
+
+impl<
+        I2C: I2c,
+        S: DerefMut<Target = SeeSaw<I2C>>,
+        NP: DerefMut<Target = NeoPixel<I2C, S, neopixel::GRB, 1>>,
+    > Deref for RotaryEncoder<I2C, S, NP>
+{
+    type Target = NeoPixel<I2C, S, neopixel::GRB, 1>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<
+        I2C: I2c,
+        S: DerefMut<Target = SeeSaw<I2C>>,
+        NP: DerefMut<Target = NeoPixel<I2C, S, neopixel::GRB, 1>>,
+    > DerefMut for RotaryEncoder<I2C, S, NP>
+{
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl<
+        I2C: I2c,
+        S: DerefMut<Target = SeeSaw<I2C>>,
+        NP: DerefMut<Target = NeoPixel<I2C, S, neopixel::GRB, 1>>,
+    > RotaryEncoder<I2C, S, NP>
+{
+    pub fn new(inner: NP) -> Self {
+        Self(inner)
+    }
+
+    pub fn init(&mut self) -> Result<(), Error<I2C::Error>> {
+        self.0.init(true, NEOPIXEL_PIN)?;
+        self.0
+            .set_gpio_pin_mode_bulk(1 << BUTTON_PIN, gpio::PinMode::InputPullUp)
+    }
+
+    /// Get how many detents the encoder has been turned since the last call.
+    pub fn get_delta<DELAY: DelayNs>(
+        &mut self,
+        delay: &mut DELAY,
+    ) -> Result<i32, Error<I2C::Error>> {
+        self.0.get_encoder_delta(delay)
+    }
+
+    /// Check whether the push-button is currently held down.
+    pub fn is_pressed<DELAY: DelayNs>(
+        &mut self,
+        delay: &mut DELAY,
+    ) -> Result<bool, Error<I2C::Error>> {
+        // button pulls the pin low when pressed
+        Ok(self.0.get_gpio_bulk(delay)? & (1 << BUTTON_PIN) == 0)
+    }
+
+    pub fn set_color(&mut self, color: Color) -> Result<(), Error<I2C::Error>> {
+        self.0.set_pixel_color(0, color)?;
+        self.0.show()
+    }
+}
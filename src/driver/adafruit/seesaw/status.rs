@@ -8,4 +8,20 @@ pub mod functions {
     pub const SWRST: u8 = 0x7F;
 }
 
+pub const fn read_delay_us(function: u8) -> u32 {
+    match function {
+        functions::TEMP => 1000,
+        _ => super::DEFAULT_READ_DELAY_US,
+    }
+}
+
+/// HW_ID reported by SAMD09-based boards such as the NeoTrellis.
 pub const HW_ID_CODE: u8 = 0x55;
+
+/// HW_IDs reported by ATtiny8x7/16x7-based boards such as the NeoKey 1x4.
+pub const HW_ID_CODES_ATTINY: [u8; 6] = [0x84, 0x85, 0x86, 0x87, 0x88, 0x89];
+
+/// Returns true if the HW_ID belongs to a known Seesaw chip.
+pub fn is_seesaw_hw_id(hw_id: u8) -> bool {
+    hw_id == HW_ID_CODE || HW_ID_CODES_ATTINY.contains(&hw_id)
+}
@@ -0,0 +1,20 @@
+pub const BASE: u8 = 0x08;
+
+pub mod functions {
+    pub const STATUS: u8 = 0x00;
+    pub const PWM: u8 = 0x01;
+    pub const FREQ: u8 = 0x02;
+}
+
+/// PWM frequency expected by hobby servos.
+pub const SERVO_FREQUENCY: u16 = 50;
+
+/// Converts a servo angle (0-180 degrees) into the duty cycle of a 1-2ms pulse
+/// repeated at [`SERVO_FREQUENCY`].
+pub fn servo_duty(angle: f32) -> u16 {
+    let angle = angle.clamp(0., 180.);
+    let pulse_ms = 1. + angle / 180.;
+    let period_ms = 1000. / SERVO_FREQUENCY as f32;
+
+    (pulse_ms / period_ms * u16::MAX as f32) as u16
+}
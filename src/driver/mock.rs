@@ -0,0 +1,195 @@
+//! An in-memory I2C bus that pretends to be a Seesaw, for testing the drivers
+//! without hardware.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+use embedded_hal::i2c::{ErrorKind, ErrorType, I2c, NoAcknowledgeSource, Operation};
+
+use super::adafruit::seesaw::{
+    keypad,
+    neotrellis::{neotrellis_key_to_seesaw, neotrellis_xy_to_key},
+};
+
+/// A write to a Seesaw register that was recorded by [`MockI2c`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegisterWrite {
+    pub address: u8,
+    pub base: u8,
+    pub function: u8,
+    pub data: Vec<u8>,
+}
+
+/// The error returned by every transaction on a [`MockI2c`] that has been
+/// disconnected.
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error("mock i2c device is disconnected")]
+pub struct Disconnected;
+
+impl embedded_hal::i2c::Error for Disconnected {
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address)
+    }
+}
+
+#[derive(Debug, Default)]
+struct MockState {
+    writes: Vec<RegisterWrite>,
+
+    /// register that the next read will return, set by the last write
+    selected: Option<(u8, u8)>,
+
+    /// whether the last write was a bare register address on its own, which
+    /// is either a command like SHOW or the first half of a delayed read
+    bare_address: bool,
+
+    /// values returned when reading registers, keyed by (base, function)
+    registers: HashMap<(u8, u8), Vec<u8>>,
+
+    /// raw keypad events waiting to be read out of the FIFO
+    keypad_fifo: VecDeque<u8>,
+
+    /// whether every transaction fails, like it would if the device was
+    /// unplugged
+    disconnected: bool,
+}
+
+/// Mock I2C bus which records every register write and answers reads like a
+/// Seesaw would. Clones share the same state, so a test can keep a handle to
+/// the bus after handing it to a driver.
+///
+/// Registers that haven't been set read back as zeroes, except for the keypad
+/// count and FIFO registers, which are backed by [`MockI2c::push_keypad_event`].
+#[derive(Debug, Clone, Default)]
+pub struct MockI2c(Arc<Mutex<MockState>>);
+
+impl MockI2c {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the value returned when the given register is read.
+    pub fn set_register(&self, base: u8, function: u8, value: &[u8]) {
+        let mut state = self.0.lock().unwrap();
+        state.registers.insert((base, function), value.to_vec());
+    }
+
+    /// Queues a keypad event to be read out of the keypad FIFO.
+    pub fn push_keypad_event(&self, event: keypad::KeyEvent) {
+        let raw = (event.key as u8) << 2 | event.edge as u8;
+        self.0.lock().unwrap().keypad_fifo.push_back(raw);
+    }
+
+    /// Queues a press (`Rising`) or release (`Falling`) of the NeoTrellis key
+    /// at (x, y).
+    pub fn push_key(&self, (x, y): (u16, u16), edge: keypad::Edge) {
+        self.push_keypad_event(keypad::KeyEvent {
+            key: neotrellis_key_to_seesaw(neotrellis_xy_to_key(x, y)),
+            edge,
+        });
+    }
+
+    /// Makes every transaction from now on fail with [`Disconnected`].
+    pub fn disconnect(&self) {
+        self.0.lock().unwrap().disconnected = true;
+    }
+
+    /// Returns every register write recorded so far.
+    pub fn writes(&self) -> Vec<RegisterWrite> {
+        self.0.lock().unwrap().writes.clone()
+    }
+
+    /// Returns every register write recorded so far and forgets them.
+    pub fn take_writes(&self) -> Vec<RegisterWrite> {
+        std::mem::take(&mut self.0.lock().unwrap().writes)
+    }
+
+    fn handle_write(state: &mut MockState, address: u8, buf: &[u8], read_follows: bool) {
+        let [base, function, data @ ..] = buf else {
+            return;
+        };
+
+        state.selected = Some((*base, *function));
+
+        // the register address of a `write_read` is a request to read, not a
+        // write
+        if read_follows {
+            return;
+        }
+
+        state.writes.push(RegisterWrite {
+            address,
+            base: *base,
+            function: *function,
+            data: data.to_vec(),
+        });
+        state.bare_address = data.is_empty();
+    }
+
+    fn handle_read(state: &mut MockState, buf: &mut [u8]) {
+        // a bare register address that gets read straight after was the first
+        // half of a delayed read, so it is taken back
+        if std::mem::take(&mut state.bare_address) {
+            let selected = state.selected;
+            let last = state.writes.last();
+
+            if last.is_some_and(|w| w.data.is_empty() && Some((w.base, w.function)) == selected) {
+                state.writes.pop();
+            }
+        }
+
+        buf.fill(0);
+
+        match state.selected {
+            Some((keypad::BASE, keypad::functions::COUNT)) => {
+                buf[0] = state.keypad_fifo.len() as u8;
+            }
+            Some((keypad::BASE, keypad::functions::FIFO)) => {
+                for b in buf.iter_mut() {
+                    match state.keypad_fifo.pop_front() {
+                        Some(evt) => *b = evt,
+                        None => break,
+                    }
+                }
+            }
+            Some(register) => {
+                if let Some(value) = state.registers.get(&register) {
+                    let len = value.len().min(buf.len());
+                    buf[..len].copy_from_slice(&value[..len]);
+                }
+            }
+            None => {}
+        }
+    }
+}
+
+impl ErrorType for MockI2c {
+    type Error = Disconnected;
+}
+
+impl I2c for MockI2c {
+    fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        let mut state = self.0.lock().unwrap();
+
+        if state.disconnected {
+            return Err(Disconnected);
+        }
+
+        for i in 0..operations.len() {
+            let read_follows = matches!(operations.get(i + 1), Some(Operation::Read(_)));
+
+            match &mut operations[i] {
+                Operation::Write(buf) => Self::handle_write(&mut state, address, buf, read_follows),
+                Operation::Read(buf) => Self::handle_read(&mut state, buf),
+            }
+        }
+
+        Ok(())
+    }
+}
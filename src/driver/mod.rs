@@ -1,29 +1,114 @@
-use std::time::Duration;
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::Context;
+use embedded_hal::i2c::{ErrorType, Operation};
+use rppal::i2c::I2c;
+
+use crate::config::I2cBus;
 
 pub mod adafruit;
+pub mod mock;
+
+/// Opens the given I2C bus, or the default bus for the board if none is given.
+pub fn open_i2c(bus: Option<&I2cBus>) -> anyhow::Result<I2c> {
+    match bus {
+        Some(bus) => {
+            let bus = bus.number()?;
+            I2c::with_bus(bus).with_context(|| format!("failed to open i2c bus {bus}"))
+        }
+        None => I2c::new().context("failed to open i2c bus"),
+    }
+}
 
 pub struct ThreadDelay;
 
-impl embedded_hal::blocking::delay::DelayUs<u32> for ThreadDelay {
+impl embedded_hal::delay::DelayNs for ThreadDelay {
+    fn delay_ns(&mut self, ns: u32) {
+        std::thread::sleep(Duration::from_nanos(ns as u64))
+    }
+
     fn delay_us(&mut self, us: u32) {
         std::thread::sleep(Duration::from_micros(us as u64))
     }
+
+    fn delay_ms(&mut self, ms: u32) {
+        std::thread::sleep(Duration::from_millis(ms as u64))
+    }
 }
 
-impl embedded_hal::blocking::delay::DelayUs<u64> for ThreadDelay {
-    fn delay_us(&mut self, us: u64) {
-        std::thread::sleep(Duration::from_micros(us))
+pub struct TokioDelay;
+
+impl embedded_hal_async::delay::DelayNs for TokioDelay {
+    async fn delay_ns(&mut self, ns: u32) {
+        tokio::time::sleep(Duration::from_nanos(ns as u64)).await
     }
 }
 
-impl embedded_hal::blocking::delay::DelayMs<u32> for ThreadDelay {
-    fn delay_ms(&mut self, us: u32) {
-        std::thread::sleep(Duration::from_millis(us as u64))
+/// Adapts a blocking I2C bus to the async I2C trait by running each transaction
+/// on tokio's blocking thread pool.
+pub struct SpawnBlockingI2c<I2C>(Arc<Mutex<I2C>>);
+
+impl<I2C> SpawnBlockingI2c<I2C> {
+    pub fn new(i2c: I2C) -> Self {
+        Self(Arc::new(Mutex::new(i2c)))
     }
 }
 
-impl embedded_hal::blocking::delay::DelayMs<u64> for ThreadDelay {
-    fn delay_ms(&mut self, us: u64) {
-        std::thread::sleep(Duration::from_millis(us))
+impl<I2C: ErrorType> ErrorType for SpawnBlockingI2c<I2C> {
+    type Error = I2C::Error;
+}
+
+impl<I2C> embedded_hal_async::i2c::I2c for SpawnBlockingI2c<I2C>
+where
+    I2C: embedded_hal::i2c::I2c + Send + 'static,
+    I2C::Error: Send,
+{
+    async fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        // the blocking thread can't borrow the caller's buffers, so copy them
+        // into owned buffers and copy the reads back afterwards
+        let mut buffers: Vec<(bool, Vec<u8>)> = operations
+            .iter()
+            .map(|op| match op {
+                Operation::Read(buf) => (true, vec![0; buf.len()]),
+                Operation::Write(buf) => (false, buf.to_vec()),
+            })
+            .collect();
+
+        let i2c = self.0.clone();
+
+        let (result, buffers) = tokio::task::spawn_blocking(move || {
+            let mut ops: Vec<_> = buffers
+                .iter_mut()
+                .map(|(is_read, buf)| {
+                    if *is_read {
+                        Operation::Read(&mut buf[..])
+                    } else {
+                        Operation::Write(&buf[..])
+                    }
+                })
+                .collect();
+
+            let result = i2c.lock().unwrap().transaction(address, &mut ops);
+            drop(ops);
+
+            (result, buffers)
+        })
+        .await
+        .expect("i2c transaction panicked");
+
+        for (op, (_, buf)) in operations.iter_mut().zip(buffers) {
+            if let Operation::Read(dst) = op {
+                dst.copy_from_slice(&buf);
+            }
+        }
+
+        result
     }
 }
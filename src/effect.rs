@@ -0,0 +1,663 @@
+//! Chains of effects on the crossfader's buses, which the kit that is loaded
+//! sets up:
+//!
+//! ```toml
+//! [[chains.a]]
+//! effect = "filter"
+//! params = { cutoff = 800, high_pass = 1 }
+//!
+//! [[chains.b]]
+//! effect = "delay"
+//! params = { beats = 0.75, feedback = 0.4, mix = 0.3 }
+//!
+//! [[chains.b]]
+//! effect = "limiter"
+//! ```
+//!
+//! Effects implement [`AudioEffect`], and are made by name from a
+//! [`Registry`], so that a new one only has to be registered to be usable in
+//! kits. The sounds on a bus go through its chain in order, after its EQ.
+
+use std::{collections::BTreeMap, time::Duration};
+
+use anyhow::bail;
+use serde::{Deserialize, Serialize};
+
+use crate::{audio::Bus, compressor::Compressor, config::CompressorConfig, eq::Biquad};
+
+/// Longest delay that a delay can be set to, in seconds.
+const MAX_DELAY_SECS: usize = 4;
+
+/// An effect that a block of samples can be run through.
+pub trait AudioEffect: Send {
+    /// Runs an interleaved block of whole frames through the effect, in
+    /// place. This is called on the output stream's thread, so it mustn't
+    /// allocate or block.
+    fn process(&mut self, block: &mut [f32]);
+
+    /// Names of the effect's parameters.
+    fn params(&self) -> &'static [&'static str];
+
+    fn param(&self, name: &str) -> Option<f32>;
+
+    /// Sets a parameter, bringing it into range. Returns false if the effect
+    /// doesn't have it.
+    fn set_param(&mut self, name: &str, value: f32) -> bool;
+
+    /// Tells the effect how long a beat is, when the tempo changes, so that it
+    /// can keep in time.
+    fn set_tempo(&mut self, _beat: Duration) {}
+}
+
+/// An effect in a kit's chain, and the parameters that it is set up with.
+/// The ones that are left out keep their defaults.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EffectConfig {
+    pub effect: String,
+    #[serde(default)]
+    pub params: BTreeMap<String, f32>,
+}
+
+/// The effect chain of each bus.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Chains {
+    pub a: Vec<EffectConfig>,
+    pub b: Vec<EffectConfig>,
+}
+
+impl Chains {
+    pub fn bus(&self, bus: Bus) -> &[EffectConfig] {
+        match bus {
+            Bus::A => &self.a,
+            Bus::B => &self.b,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.a.is_empty() && self.b.is_empty()
+    }
+}
+
+/// Makes an effect for a signal with the given channels and sample rate.
+pub type Factory = fn(channels: u16, sample_rate: u32) -> Box<dyn AudioEffect>;
+
+/// The effects that can be put in a chain, by name.
+pub struct Registry {
+    /// how to make each effect, and the names of its parameters
+    factories: BTreeMap<&'static str, (Factory, &'static [&'static str])>,
+}
+
+/// A change to the effect chain of a bus, which the output stream picks up
+/// between blocks.
+pub enum ChainUpdate {
+    /// Swaps in a new chain.
+    Replace(Chain),
+    /// Sets a parameter of one of the effects in the chain, which keeps the
+    /// rest of its state, like the tail of a delay.
+    Param {
+        effect: usize,
+        name: &'static str,
+        value: f32,
+    },
+}
+
+impl Registry {
+    /// A registry with the effects that come with the app.
+    pub fn builtin() -> Self {
+        let mut registry = Self {
+            factories: BTreeMap::new(),
+        };
+
+        registry.register("filter", |channels, rate| {
+            Box::new(Filter::new(channels, rate))
+        });
+        registry.register("delay", |channels, rate| {
+            Box::new(Delay::new(channels, rate))
+        });
+        registry.register("limiter", |channels, rate| {
+            Box::new(Limiter::new(channels, rate))
+        });
+        registry.register("compressor", |channels, rate| {
+            Box::new(CompressorEffect::new(channels, rate))
+        });
+
+        registry
+    }
+
+    pub fn register(&mut self, name: &'static str, factory: Factory) {
+        // the parameters are the same for any signal, so a small one will do
+        let params = factory(1, 8_000).params();
+        self.factories.insert(name, (factory, params));
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.factories.keys().copied()
+    }
+
+    /// Makes the effect that `config` names, with its parameters set.
+    pub fn create(
+        &self,
+        config: &EffectConfig,
+        channels: u16,
+        sample_rate: u32,
+    ) -> anyhow::Result<Box<dyn AudioEffect>> {
+        let Some((factory, _)) = self.factories.get(config.effect.as_str()) else {
+            bail!("there is no effect called {:?}", config.effect);
+        };
+
+        let mut effect = factory(channels, sample_rate);
+
+        for (name, value) in &config.params {
+            if !effect.set_param(name, *value) {
+                bail!(
+                    "{} has no parameter {name:?}, only {:?}",
+                    config.effect,
+                    effect.params()
+                );
+            }
+        }
+
+        Ok(effect)
+    }
+
+    /// Checks that the effect that `config` names exists, and has its
+    /// parameters.
+    pub fn check(&self, config: &EffectConfig) -> anyhow::Result<()> {
+        // only the parameters are looked at, so a small one will do
+        self.create(config, 1, 8_000).map(drop)
+    }
+
+    /// Makes the effects in `configs`, chained in order.
+    pub fn chain(
+        &self,
+        configs: &[EffectConfig],
+        channels: u16,
+        sample_rate: u32,
+    ) -> anyhow::Result<Chain> {
+        let effects = configs
+            .iter()
+            .map(|config| self.create(config, channels, sample_rate))
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(Chain { effects })
+    }
+
+    /// The parameters to set to turn a chain made from `old` into one made
+    /// from `new`, as the index of the effect, the parameter and its value.
+    /// None if the chain has to be made again, because the effects are
+    /// different or a parameter has gone back to its default.
+    pub fn updates(
+        &self,
+        old: &[EffectConfig],
+        new: &[EffectConfig],
+    ) -> Option<Vec<(usize, &'static str, f32)>> {
+        if old.len() != new.len() {
+            return None;
+        }
+
+        let mut updates = vec![];
+
+        for (i, (old, new)) in old.iter().zip(new).enumerate() {
+            if old.effect != new.effect || old.params.keys().any(|k| !new.params.contains_key(k)) {
+                return None;
+            }
+
+            let (_, params) = self.factories.get(new.effect.as_str())?;
+
+            for (name, &value) in &new.params {
+                if old.params.get(name) != Some(&value) {
+                    let name = params.iter().find(|p| **p == name.as_str())?;
+                    updates.push((i, *name, value));
+                }
+            }
+        }
+
+        Some(updates)
+    }
+}
+
+/// Effects that a signal goes through one after another.
+#[derive(Default)]
+pub struct Chain {
+    effects: Vec<Box<dyn AudioEffect>>,
+}
+
+impl Chain {
+    pub fn is_empty(&self) -> bool {
+        self.effects.is_empty()
+    }
+
+    pub fn process(&mut self, block: &mut [f32]) {
+        for effect in &mut self.effects {
+            effect.process(block);
+        }
+    }
+
+    pub fn set_tempo(&mut self, beat: Duration) {
+        for effect in &mut self.effects {
+            effect.set_tempo(beat);
+        }
+    }
+
+    pub fn update(&mut self, update: ChainUpdate) {
+        match update {
+            ChainUpdate::Replace(chain) => *self = chain,
+            ChainUpdate::Param {
+                effect,
+                name,
+                value,
+            } => {
+                if let Some(effect) = self.effects.get_mut(effect) {
+                    effect.set_param(name, value);
+                }
+            }
+        }
+    }
+}
+
+/// Low-pass or high-pass filter.
+struct Filter {
+    sample_rate: u32,
+    cutoff: f32,
+    high_pass: bool,
+    /// a filter for each channel
+    filters: Vec<Biquad>,
+}
+
+impl Filter {
+    fn new(channels: u16, sample_rate: u32) -> Self {
+        let mut filter = Self {
+            sample_rate,
+            cutoff: 1_000.,
+            high_pass: false,
+            filters: vec![Biquad::default(); channels as usize],
+        };
+        filter.design();
+        filter
+    }
+
+    fn design(&mut self) {
+        let biquad = match self.high_pass {
+            true => Biquad::high_pass(self.cutoff, self.sample_rate),
+            false => Biquad::low_pass(self.cutoff, self.sample_rate),
+        };
+        self.filters.fill(biquad);
+    }
+}
+
+impl AudioEffect for Filter {
+    fn process(&mut self, block: &mut [f32]) {
+        for frame in block.chunks_exact_mut(self.filters.len()) {
+            for (sample, filter) in frame.iter_mut().zip(&mut self.filters) {
+                *sample = filter.process(*sample);
+            }
+        }
+    }
+
+    fn params(&self) -> &'static [&'static str] {
+        &["cutoff", "high_pass"]
+    }
+
+    fn param(&self, name: &str) -> Option<f32> {
+        match name {
+            "cutoff" => Some(self.cutoff),
+            "high_pass" => Some(self.high_pass as u8 as f32),
+            _ => None,
+        }
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) -> bool {
+        match name {
+            // kept under the Nyquist frequency
+            "cutoff" => self.cutoff = value.clamp(20., self.sample_rate as f32 * 0.45),
+            "high_pass" => self.high_pass = value != 0.,
+            _ => return false,
+        }
+
+        self.design();
+        true
+    }
+}
+
+/// Echoes the signal a number of beats later.
+struct Delay {
+    channels: usize,
+    sample_rate: u32,
+    /// the last [`MAX_DELAY_SECS`] of frames, indexed by frame number modulo
+    /// its length
+    history: Vec<f32>,
+    frame: usize,
+    beats: f32,
+    /// how much of each echo comes back again
+    feedback: f32,
+    /// level of the echoes
+    mix: f32,
+    beat: Duration,
+    /// the delay in frames, from the beats and the tempo
+    delay: usize,
+}
+
+impl Delay {
+    fn new(channels: u16, sample_rate: u32) -> Self {
+        let len = MAX_DELAY_SECS * sample_rate as usize;
+
+        let mut delay = Self {
+            channels: channels as usize,
+            sample_rate,
+            history: vec![0.; len * channels as usize],
+            frame: 0,
+            beats: 0.5,
+            feedback: 0.4,
+            mix: 0.5,
+            beat: Duration::from_millis(500),
+            delay: 0,
+        };
+        delay.retime();
+        delay
+    }
+
+    fn retime(&mut self) {
+        let len = self.history.len() / self.channels;
+        let frames = self.beat.as_secs_f32() * self.beats * self.sample_rate as f32;
+        self.delay = (frames as usize).clamp(1, len - 1);
+    }
+}
+
+impl AudioEffect for Delay {
+    fn process(&mut self, block: &mut [f32]) {
+        let len = self.history.len() / self.channels;
+
+        for frame in block.chunks_exact_mut(self.channels) {
+            let write = self.frame % len * self.channels;
+            let read = (self.frame + len - self.delay) % len * self.channels;
+
+            for (channel, sample) in frame.iter_mut().enumerate() {
+                let echo = self.history[read + channel];
+                self.history[write + channel] = *sample + self.feedback * echo;
+                *sample += self.mix * echo;
+            }
+
+            self.frame += 1;
+        }
+    }
+
+    fn params(&self) -> &'static [&'static str] {
+        &["beats", "feedback", "mix"]
+    }
+
+    fn param(&self, name: &str) -> Option<f32> {
+        match name {
+            "beats" => Some(self.beats),
+            "feedback" => Some(self.feedback),
+            "mix" => Some(self.mix),
+            _ => None,
+        }
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) -> bool {
+        match name {
+            "beats" => self.beats = value.clamp(1. / 16., 4.),
+            // any more and the echoes would build up forever
+            "feedback" => self.feedback = value.clamp(0., 0.95),
+            "mix" => self.mix = value.clamp(0., 1.),
+            _ => return false,
+        }
+
+        self.retime();
+        true
+    }
+
+    fn set_tempo(&mut self, beat: Duration) {
+        self.beat = beat;
+        self.retime();
+    }
+}
+
+/// Keeps the peaks of the signal under a ceiling. It turns the signal down
+/// straight away when a peak would go over, and brings it back up slowly.
+struct Limiter {
+    channels: usize,
+    sample_rate: u32,
+    ceiling_db: f32,
+    release_ms: f32,
+    ceiling: f32,
+    /// how far the gain moves back up each frame
+    release: f32,
+    gain: f32,
+}
+
+impl Limiter {
+    fn new(channels: u16, sample_rate: u32) -> Self {
+        let mut limiter = Self {
+            channels: channels as usize,
+            sample_rate,
+            ceiling_db: -0.3,
+            release_ms: 100.,
+            ceiling: 1.,
+            release: 0.,
+            gain: 1.,
+        };
+        limiter.update();
+        limiter
+    }
+
+    fn update(&mut self) {
+        self.ceiling = 10f32.powf(self.ceiling_db / 20.);
+        self.release = 1. - (-1000. / (self.release_ms.max(1.) * self.sample_rate as f32)).exp();
+    }
+}
+
+impl AudioEffect for Limiter {
+    fn process(&mut self, block: &mut [f32]) {
+        for frame in block.chunks_exact_mut(self.channels) {
+            let peak = frame
+                .iter()
+                .fold(0f32, |peak, sample| peak.max(sample.abs()));
+            let target = (self.ceiling / peak.max(f32::EPSILON)).min(1.);
+
+            if target < self.gain {
+                self.gain = target;
+            } else {
+                self.gain += (target - self.gain) * self.release;
+            }
+
+            for sample in frame {
+                *sample *= self.gain;
+            }
+        }
+    }
+
+    fn params(&self) -> &'static [&'static str] {
+        &["ceiling_db", "release_ms"]
+    }
+
+    fn param(&self, name: &str) -> Option<f32> {
+        match name {
+            "ceiling_db" => Some(self.ceiling_db),
+            "release_ms" => Some(self.release_ms),
+            _ => None,
+        }
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) -> bool {
+        match name {
+            "ceiling_db" => self.ceiling_db = value.clamp(-24., 0.),
+            "release_ms" => self.release_ms = value.clamp(1., 2_000.),
+            _ => return false,
+        }
+
+        self.update();
+        true
+    }
+}
+
+/// A [`Compressor`] on a bus, rather than on the master mix.
+struct CompressorEffect {
+    channels: u16,
+    sample_rate: u32,
+    config: CompressorConfig,
+    compressor: Compressor,
+}
+
+impl CompressorEffect {
+    fn new(channels: u16, sample_rate: u32) -> Self {
+        let config = CompressorConfig {
+            enabled: true,
+            ..Default::default()
+        };
+
+        Self {
+            channels,
+            sample_rate,
+            compressor: Compressor::new(&config, channels, sample_rate),
+            config,
+        }
+    }
+}
+
+impl AudioEffect for CompressorEffect {
+    fn process(&mut self, block: &mut [f32]) {
+        for sample in block {
+            *sample = self.compressor.process(*sample);
+        }
+    }
+
+    fn params(&self) -> &'static [&'static str] {
+        &[
+            "threshold_db",
+            "ratio",
+            "attack_ms",
+            "release_ms",
+            "makeup_db",
+        ]
+    }
+
+    fn param(&self, name: &str) -> Option<f32> {
+        match name {
+            "threshold_db" => Some(self.config.threshold_db),
+            "ratio" => Some(self.config.ratio),
+            "attack_ms" => Some(self.config.attack_ms),
+            "release_ms" => Some(self.config.release_ms),
+            "makeup_db" => Some(self.config.makeup_db),
+            _ => None,
+        }
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) -> bool {
+        match name {
+            "threshold_db" => self.config.threshold_db = value.clamp(-60., 0.),
+            "ratio" => self.config.ratio = value.clamp(1., 20.),
+            "attack_ms" => self.config.attack_ms = value.clamp(0.1, 500.),
+            "release_ms" => self.config.release_ms = value.clamp(1., 2_000.),
+            "makeup_db" => self.config.makeup_db = value.clamp(0., 24.),
+            _ => return false,
+        }
+
+        self.compressor = Compressor::new(&self.config, self.channels, self.sample_rate);
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+    use std::time::Duration;
+
+    use super::{EffectConfig, Registry};
+
+    const RATE: u32 = 1_000;
+
+    fn config(effect: &str, params: &[(&str, f32)]) -> EffectConfig {
+        EffectConfig {
+            effect: effect.to_owned(),
+            params: params
+                .iter()
+                .map(|(name, value)| (name.to_string(), *value))
+                .collect::<BTreeMap<_, _>>(),
+        }
+    }
+
+    #[test]
+    fn registry_makes_effects_with_their_params() {
+        let registry = Registry::builtin();
+
+        let delay = registry
+            .create(&config("delay", &[("beats", 0.25), ("mix", 2.)]), 1, RATE)
+            .unwrap();
+        assert_eq!(delay.param("beats"), Some(0.25));
+        assert_eq!(delay.param("mix"), Some(1.));
+
+        assert!(registry.create(&config("flanger", &[]), 1, RATE).is_err());
+        assert!(registry
+            .create(&config("limiter", &[("drive", 1.)]), 1, RATE)
+            .is_err());
+    }
+
+    #[test]
+    fn params_are_updated_in_place() {
+        let registry = Registry::builtin();
+        let old = [
+            config("filter", &[("cutoff", 800.)]),
+            config("limiter", &[]),
+        ];
+
+        let new = [
+            config("filter", &[("cutoff", 400.), ("high_pass", 1.)]),
+            config("limiter", &[]),
+        ];
+        assert_eq!(
+            registry.updates(&old, &new),
+            Some(vec![(0, "cutoff", 400.), (0, "high_pass", 1.)])
+        );
+
+        // a parameter going back to its default, or a different effect, needs
+        // a new chain
+        assert_eq!(registry.updates(&new, &old), None);
+        assert_eq!(
+            registry.updates(&old, &[config("delay", &[]), config("limiter", &[])]),
+            None
+        );
+    }
+
+    #[test]
+    fn delay_follows_the_tempo() {
+        let registry = Registry::builtin();
+        let mut chain = registry
+            .chain(
+                &[config(
+                    "delay",
+                    &[("beats", 0.5), ("feedback", 0.), ("mix", 1.)],
+                )],
+                1,
+                RATE,
+            )
+            .unwrap();
+
+        // half a beat at 60 bpm is 500 frames
+        chain.set_tempo(Duration::from_secs(1));
+        let mut block = vec![0.; 1_000];
+        block[0] = 1.;
+        chain.process(&mut block);
+        assert_eq!(block[500], 1.);
+
+        // and 250 at 120 bpm
+        chain.set_tempo(Duration::from_millis(500));
+        let mut block = vec![0.; 1_000];
+        block[0] = 1.;
+        chain.process(&mut block);
+        assert_eq!(block[250], 1.);
+    }
+
+    #[test]
+    fn limiter_keeps_peaks_under_the_ceiling() {
+        let registry = Registry::builtin();
+        let mut chain = registry
+            .chain(&[config("limiter", &[("ceiling_db", -6.)])], 2, RATE)
+            .unwrap();
+
+        let mut block = vec![0.2, -1., 0.2, 0.2];
+        chain.process(&mut block);
+        assert!(block.iter().all(|sample| sample.abs() <= 0.502));
+    }
+}
@@ -0,0 +1,106 @@
+use std::time::Duration;
+
+use anyhow::Context;
+
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, trace};
+
+use crate::{
+    config::EncoderConfig,
+    driver::{
+        adafruit::seesaw::{
+            neopixel::{Color, NeoPixel},
+            rotary_encoder::RotaryEncoder,
+            SeeSaw,
+        },
+        open_i2c, ThreadDelay,
+    },
+    util::Interval,
+};
+
+#[derive(Debug, Clone, Copy)]
+pub enum Command {
+    SetColor(Color),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Event {
+    /// The encoder was turned by `delta` detents.
+    Rotate { delta: i32 },
+    /// The push-button was pressed or released.
+    Button { pressed: bool },
+}
+
+pub fn run(
+    ct: CancellationToken,
+    config: EncoderConfig,
+    cmd_rx: flume::Receiver<Command>,
+    evt_tx: flume::Sender<Event>,
+) -> anyhow::Result<()> {
+    let i2c = open_i2c(config.bus.as_ref())?;
+    let mut seesaw = SeeSaw::new(i2c, config.address);
+    let mut delay = ThreadDelay;
+
+    seesaw
+        .sw_reset()
+        .context("failed to reset encoder seesaw")?;
+    let seesaw_ver = seesaw
+        .get_version(&mut delay)
+        .context("failed to get encoder seesaw version")?;
+    debug!("initialized encoder seesaw driver, ver = {seesaw_ver}");
+
+    let mut np = NeoPixel::new(&mut seesaw);
+    let mut encoder = RotaryEncoder::new(&mut np);
+    encoder.init().context("failed to initialize encoder")?;
+    encoder
+        .set_color(Color::BLACK)
+        .context("failed to set encoder color")?;
+
+    debug!("running encoder loop");
+
+    // sample encoder at 60Hz so that fast turns don't feel sluggish
+    let mut interval = Interval::new(Duration::from_millis(1000 / 60));
+    let mut pressed = false;
+
+    'main: while !ct.is_cancelled() {
+        interval.tick();
+
+        let delta = encoder
+            .get_delta(&mut delay)
+            .context("failed to read encoder delta")?;
+
+        if delta != 0 {
+            trace!("encoder moved by {delta}");
+            let _ = evt_tx.send(Event::Rotate { delta });
+        }
+
+        let now_pressed = encoder
+            .is_pressed(&mut delay)
+            .context("failed to read encoder button")?;
+
+        if now_pressed != pressed {
+            pressed = now_pressed;
+            trace!("encoder button pressed = {pressed}");
+            let _ = evt_tx.send(Event::Button { pressed });
+        }
+
+        loop {
+            match cmd_rx.try_recv() {
+                Ok(Command::SetColor(color)) => encoder
+                    .set_color(color)
+                    .context("failed to set encoder color")?,
+                Err(flume::TryRecvError::Empty) => break,
+                Err(flume::TryRecvError::Disconnected) => break 'main,
+            }
+        }
+    }
+
+    // when program is exited, turn the encoder LED off
+    encoder
+        .set_color(Color::BLACK)
+        .context("failed to set encoder color")?;
+
+    debug!("encoder task exited");
+
+    Ok(())
+}
@@ -0,0 +1,324 @@
+//! The deck page, which plays long tracks on two decks with hot cues and
+//! loops. See [`crate::deck`].
+
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use crate::deck::{Deck, DeckCommand, DeckStatus};
+
+use super::{Effect, PlayState};
+
+/// What can be done to a deck in deck mode.
+#[derive(Debug, Clone)]
+pub enum DeckAction {
+    /// Loads a track onto the deck, paused at the start. Cues and loops from
+    /// the last track are cleared.
+    Load(PathBuf),
+    PlayPause,
+    Seek(Duration),
+    /// Marks the current position as the start of a loop.
+    LoopIn,
+    /// Starts looping from the loop's start to the current position.
+    LoopOut,
+    LoopOff,
+    /// Steps through starting, closing and turning off a loop.
+    CycleLoop,
+    SetVolume(f32),
+    /// Jumps to a hot cue, or sets it to the current position if it isn't set.
+    Cue(usize),
+    ClearCue(usize),
+}
+
+/// Number of hot cues on each deck, which is how many pads each deck gets in
+/// deck mode.
+pub const CUES_PER_DECK: usize = 6;
+
+#[derive(Clone, Debug)]
+pub struct DeckState {
+    pub track: Option<PathBuf>,
+
+    /// as last reported by the audio subsystem
+    pub status: DeckStatus,
+
+    /// when the status was reported, so that the position can be worked out
+    /// in between reports
+    pub status_at: Instant,
+
+    /// where the next loop starts, once its end has been picked
+    pub loop_in: Option<Duration>,
+
+    pub loop_region: Option<(Duration, Duration)>,
+
+    /// where 1.0 is unity gain
+    pub volume: f32,
+
+    pub cues: [Option<Duration>; CUES_PER_DECK],
+}
+
+impl DeckState {
+    pub(super) fn new(now: Instant) -> Self {
+        Self {
+            track: None,
+            status: DeckStatus::default(),
+            status_at: now,
+            loop_in: None,
+            loop_region: None,
+            volume: 1.,
+            cues: [None; CUES_PER_DECK],
+        }
+    }
+
+    /// Where the deck is up to at `now`, assuming that it kept playing since
+    /// its status was reported.
+    pub fn position(&self, now: Instant) -> Duration {
+        let DeckStatus {
+            playing,
+            position,
+            duration,
+        } = self.status;
+
+        if !playing {
+            return position;
+        }
+
+        let mut position = position + now.saturating_duration_since(self.status_at);
+
+        if let Some((start, end)) = self.loop_region {
+            if position >= end {
+                let length = (end - start).as_secs_f64();
+                position =
+                    start + Duration::from_secs_f64((position - start).as_secs_f64() % length);
+            }
+        }
+
+        duration.map_or(position, |duration| position.min(duration))
+    }
+
+    /// Changes the position that the deck is playing from.
+    fn set_position(&mut self, position: Duration, now: Instant) {
+        self.status.position = position;
+        self.status_at = now;
+    }
+}
+
+impl PlayState {
+    /// Handles a key being pressed in deck mode. F1 leaves deck mode, and F2
+    /// and F3 play or pause decks A and B, or step through their loops while
+    /// F4 is held. The left two columns of pads are deck A's hot cues and the
+    /// right two are deck B's, and F4 + a pad clears a cue.
+    pub(super) fn deck_key(&mut self, x: usize, y: usize) -> Vec<Effect> {
+        let shift = self.fn_keys[3].pressed;
+
+        match (x, y) {
+            (0, 0) => {
+                self.deck_mode = false;
+                vec![]
+            }
+            (1 | 2, 0) => {
+                let deck = if x == 1 { Deck::A } else { Deck::B };
+
+                if shift {
+                    self.deck(deck, DeckAction::CycleLoop)
+                } else {
+                    self.deck(deck, DeckAction::PlayPause)
+                }
+            }
+            // F4 = shift
+            (_, 0) => vec![],
+            _ => {
+                let deck = if x < 2 { Deck::A } else { Deck::B };
+                let cue = (y - 1) * 2 + x % 2;
+
+                if shift {
+                    self.deck(deck, DeckAction::ClearCue(cue))
+                } else {
+                    self.deck(deck, DeckAction::Cue(cue))
+                }
+            }
+        }
+    }
+
+    /// Carries out an action on a deck. Everything but loading a track is
+    /// ignored if the deck is empty.
+    pub fn deck(&mut self, deck: Deck, action: DeckAction) -> Vec<Effect> {
+        let now = self.clock.now();
+
+        let action = match action {
+            DeckAction::CycleLoop => {
+                let state = &self.decks[deck.index()];
+
+                if state.loop_region.is_some() {
+                    DeckAction::LoopOff
+                } else if state.loop_in.is_some() {
+                    DeckAction::LoopOut
+                } else {
+                    DeckAction::LoopIn
+                }
+            }
+            action => action,
+        };
+
+        let state = &mut self.decks[deck.index()];
+        let position = state.position(now);
+        let mut commands = vec![];
+
+        if state.track.is_none() && !matches!(action, DeckAction::Load(_)) {
+            return vec![];
+        }
+
+        match action {
+            DeckAction::Load(path) => {
+                // the volume is a setting of the deck, not of the track
+                *state = DeckState {
+                    track: Some(path.clone()),
+                    volume: state.volume,
+                    ..DeckState::new(now)
+                };
+
+                commands.push(DeckCommand::Load(path));
+                commands.push(DeckCommand::Volume(state.volume));
+            }
+            DeckAction::PlayPause => {
+                state.status.playing = !state.status.playing;
+                state.set_position(position, now);
+                commands.push(DeckCommand::Play(state.status.playing));
+            }
+            DeckAction::Seek(position) => {
+                state.set_position(position, now);
+                commands.push(DeckCommand::Seek(position));
+            }
+            DeckAction::LoopIn => state.loop_in = Some(position),
+            DeckAction::LoopOut => {
+                if let Some(start) = state.loop_in.filter(|start| *start < position) {
+                    state.loop_in = None;
+                    state.loop_region = Some((start, position));
+                    commands.push(DeckCommand::Loop(state.loop_region));
+                }
+            }
+            DeckAction::LoopOff => {
+                state.loop_in = None;
+                state.loop_region = None;
+                commands.push(DeckCommand::Loop(None));
+            }
+            DeckAction::CycleLoop => unreachable!(),
+            DeckAction::SetVolume(volume) => {
+                state.volume = volume;
+                commands.push(DeckCommand::Volume(volume));
+            }
+            DeckAction::Cue(cue) => match state.cues.get_mut(cue) {
+                Some(Some(cue)) => {
+                    let cue = *cue;
+                    state.set_position(cue, now);
+                    commands.push(DeckCommand::Seek(cue));
+                }
+                Some(cue) => *cue = Some(position),
+                None => {}
+            },
+            DeckAction::ClearCue(cue) => {
+                if let Some(cue) = state.cues.get_mut(cue) {
+                    *cue = None;
+                }
+            }
+        }
+
+        commands
+            .into_iter()
+            .map(|command| Effect::Deck { deck, command })
+            .collect()
+    }
+
+    /// Where a deck is up to now.
+    pub fn deck_position(&self, deck: Deck) -> Duration {
+        self.decks[deck.index()].position(self.clock.now())
+    }
+
+    /// Takes note of what the audio subsystem says that a deck is doing.
+    pub fn deck_status(&mut self, deck: Deck, status: DeckStatus) -> Vec<Effect> {
+        let state = &mut self.decks[deck.index()];
+        let stopped = state.status.playing && !status.playing;
+
+        state.status = status;
+        state.status_at = self.clock.now();
+
+        // decks stop by themselves at the end of the track
+        if stopped && self.deck_mode {
+            self.keyboard_leds()
+        } else {
+            vec![]
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    use crate::clock::VirtualClock;
+    use crate::deck::{Deck, DeckCommand};
+    use crate::driver::adafruit::seesaw::neopixel::Color;
+    use crate::engine::test::{led, play_state, tap};
+    use crate::engine::{Command, DeckAction, Effect};
+
+    fn deck_commands(effects: &[Effect]) -> Vec<(Deck, DeckCommand)> {
+        effects
+            .iter()
+            .filter_map(|e| match e {
+                Effect::Deck { deck, command } => Some((*deck, command.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn deck_mode_hot_cues() {
+        let clock = VirtualClock::new();
+        let mut state = play_state(&clock);
+
+        // F1 + F2 = deck mode
+        state.key(0, 0, true);
+        tap(&mut state, 1, 0);
+        state.key(0, 0, false);
+        assert!(state.deck_mode);
+
+        // empty decks ignore the keys
+        assert!(deck_commands(&tap(&mut state, 1, 0)).is_empty());
+
+        let track = PathBuf::from("tracks/song.mp3");
+        state.command(Command::Deck {
+            deck: Deck::A,
+            action: DeckAction::Load(track.clone()),
+        });
+
+        // F2 = play deck A
+        let effects = tap(&mut state, 1, 0);
+        assert_eq!(
+            deck_commands(&effects),
+            vec![(Deck::A, DeckCommand::Play(true))]
+        );
+        assert_eq!(led(&effects, 1, 0), Some(Color::from_u8(0, 200, 255)));
+
+        // the first press of a pad sets its cue where the deck is up to
+        clock.advance(Duration::from_secs(10));
+        assert!(deck_commands(&tap(&mut state, 1, 2)).is_empty());
+        assert_eq!(state.decks[0].cues[3], Some(Duration::from_secs(10)));
+
+        // and later presses jump back to it
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(
+            deck_commands(&tap(&mut state, 1, 2)),
+            vec![(Deck::A, DeckCommand::Seek(Duration::from_secs(10)))]
+        );
+
+        // F4 + pad clears the cue
+        state.key(3, 0, true);
+        tap(&mut state, 1, 2);
+        state.key(3, 0, false);
+        assert_eq!(state.decks[0].cues[3], None);
+
+        // F1 goes back to the pads, and deck A keeps playing
+        tap(&mut state, 0, 0);
+        assert!(!state.deck_mode);
+        assert!(state.decks[0].status.playing);
+    }
+}
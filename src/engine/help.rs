@@ -0,0 +1,246 @@
+//! The help for the keys that is shown while a Fn key is held down.
+
+use std::time::Duration;
+
+use crate::driver::adafruit::seesaw::neopixel::Color;
+use crate::keymap::ChordKey;
+
+use super::{action_color, Effect, PlayState};
+
+/// How long an Fn key has to be held to show the help for the keys.
+pub const HELP_HOLD: Duration = Duration::from_secs(1);
+
+/// What a key or chord does, in the help for the keys.
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeyHint {
+    /// the keys, written like `F1+Pad`
+    pub keys: String,
+    pub does: String,
+    /// whether pressing the last of the keys does it, with the Fn keys that
+    /// are held down now
+    pub ready: bool,
+    /// colour that the last of the keys is lit in while it is ready, if the
+    /// help lights it
+    pub color: Option<Color>,
+}
+
+impl KeyHint {
+    /// A hint for a key of a mode that is laid out the same way every time.
+    fn fixed((keys, does): (&str, &str)) -> Self {
+        Self {
+            keys: keys.to_owned(),
+            does: does.to_owned(),
+            ready: true,
+            color: None,
+        }
+    }
+}
+
+/// What the keys do in the modes with their own layouts, which are the same
+/// every time.
+const REASSIGN_HELP: [(&str, &str); 5] = [
+    ("F1", "Leave without picking a sound"),
+    ("F2", "Up one folder"),
+    ("F3", "Next sort order, or next page of pads"),
+    ("F4", "Pick the selected sound"),
+    ("Pad", "Open its folder, or hear its sound"),
+];
+
+const DECK_HELP: [(&str, &str); 6] = [
+    ("F1", "Leave deck mode"),
+    ("F2 / F3", "Play or pause deck A / B"),
+    ("F4+F2 / F3", "Loop in, out and off on deck A / B"),
+    ("Left pads", "Deck A's hot cues"),
+    ("Right pads", "Deck B's hot cues"),
+    ("F4+Pad", "Clear the hot cue"),
+];
+
+const MIXER_HELP: [(&str, &str); 4] = [
+    ("F1", "Leave the mixer"),
+    ("F2 / F3", "Recall scene A / B, or keep the mix in it"),
+    ("F4+F2 / F3", "Morph to scene A / B over a bar"),
+    (
+        "Pad rows",
+        "Kill the high, mid and low bands of the column's bus",
+    ),
+];
+
+const FX_HELP: [(&str, &str); 5] = [
+    ("F1", "Leave FX mode"),
+    ("Column 1", "Sweep the filter down, while held"),
+    ("Column 2", "Repeat the end of the mix, while held"),
+    ("Column 3", "Throw the mix into a delay, while held"),
+    ("Column 4", "Stop the mix like a tape, while held"),
+];
+
+const GAME_HELP: [(&str, &str); 2] = [("F1", "Give up"), ("Pad", "Play the hit as it lights up")];
+
+const SPLIT_HELP: [(&str, &str); 4] = [
+    ("F1 / F4", "Next loop mode of the left / right half"),
+    ("F2 / F3", "Clear the loops of the left / right half"),
+    ("F1+F2 / F4+F3", "Next bank of the left / right half"),
+    ("F1+F4", "Leave split mode"),
+];
+
+impl PlayState {
+    /// Shows the help for the keys once an Fn key has been held down for
+    /// [`HELP_HOLD`].
+    pub(super) fn show_help(&mut self) -> Vec<Effect> {
+        let now = self.clock.now();
+        let held = self
+            .fn_keys
+            .iter()
+            .filter_map(|k| k.pressed_at)
+            .any(|at| now.saturating_duration_since(at) >= HELP_HOLD);
+
+        if self.help || !held {
+            return vec![];
+        }
+
+        self.help = true;
+        self.keyboard_leds()
+    }
+
+    /// Whether the keys do what the keymap says, rather than what a mode's
+    /// own layout does.
+    pub(super) fn uses_keymap(&self) -> bool {
+        self.reassign.is_none()
+            && !self.deck_mode
+            && !self.mixer_mode
+            && !self.fx_mode
+            && self.game.is_none()
+            && !self.split_mode
+    }
+
+    /// What each key and chord does in the active mode. Outside of the modes
+    /// with their own layouts, these are the chords of the keymap, and the
+    /// ones that the Fn keys that are held down start are ready.
+    pub fn key_help(&self) -> Vec<KeyHint> {
+        let layout: &[(&str, &str)] = if self.reassign.is_some() {
+            &REASSIGN_HELP
+        } else if self.deck_mode {
+            &DECK_HELP
+        } else if self.mixer_mode {
+            &MIXER_HELP
+        } else if self.fx_mode {
+            &FX_HELP
+        } else if self.game.is_some() {
+            &GAME_HELP
+        } else if self.split_mode {
+            &SPLIT_HELP
+        } else {
+            return self.chord_help();
+        };
+
+        layout.iter().copied().map(KeyHint::fixed).collect()
+    }
+
+    fn chord_help(&self) -> Vec<KeyHint> {
+        let held: Vec<_> = self.fn_keys.iter().map(|k| k.pressed).collect();
+
+        let mut hints: Vec<_> = self
+            .keymap
+            .bindings()
+            .iter()
+            .map(|b| {
+                let free = match b.chord.key {
+                    ChordKey::Fn(x) => !held.get(x).copied().unwrap_or(true),
+                    ChordKey::Pad => true,
+                };
+
+                KeyHint {
+                    keys: b.chord.to_string(),
+                    does: b.action.describe().to_owned(),
+                    ready: free && self.keymap.resolve(&held, b.chord.key) == Some(b),
+                    color: Some(action_color(b.action)),
+                }
+            })
+            .collect();
+
+        hints.push(KeyHint {
+            keys: "Pad+F1…F4".to_owned(),
+            does: "Play the held pad from one of its cues".to_owned(),
+            ready: false,
+            color: None,
+        });
+
+        hints
+    }
+
+    /// LEDs of the help for the keys, where the keys that do something with
+    /// the Fn keys that are held down are in the colour of what they do, and
+    /// the Fn keys that are held are white. The pads keep their colours, but
+    /// dimmed, if they don't do anything.
+    pub(super) fn help_leds(&self) -> Vec<Effect> {
+        let held: Vec<_> = self.fn_keys.iter().map(|k| k.pressed).collect();
+        let action = |key| {
+            self.keymap
+                .resolve(&held, key)
+                .map(|b| action_color(b.action))
+        };
+        let mut leds = vec![];
+
+        for x in 0..self.sound_keys.width() {
+            let color = match held.get(x) {
+                Some(true) => Color::WHITE,
+                Some(false) => action(ChordKey::Fn(x)).unwrap_or(Color::BLACK),
+                None => Color::BLACK,
+            };
+
+            leds.push(Effect::SetLed { x, y: 0, color });
+        }
+
+        let pad = action(ChordKey::Pad);
+
+        for (x, y) in self.pads() {
+            let color = pad.unwrap_or_else(|| self.key_color(x, y).scale(30));
+            leds.push(Effect::SetLed { x, y, color });
+        }
+
+        leds
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::clock::VirtualClock;
+    use crate::driver::adafruit::seesaw::neopixel::Color;
+    use crate::engine::test::{led, play_state};
+    use crate::engine::HELP_HOLD;
+    use crate::keymap::KeyAction;
+
+    #[test]
+    fn holding_an_fn_key_shows_the_help() {
+        let clock = VirtualClock::new();
+        let mut state = play_state(&clock);
+        let mut last_tick = None;
+
+        state.key(0, 0, true);
+        state.tick_due(&mut last_tick);
+        assert!(!state.help);
+
+        clock.advance(HELP_HOLD);
+        let (effects, _) = state.tick_due(&mut last_tick).unwrap();
+        assert!(state.help);
+
+        // F1 + a pad reassigns it, so the pads are lit in its colour
+        assert_eq!(led(&effects, 2, 3), Some(action_color(KeyAction::Reassign)));
+        assert_eq!(led(&effects, 0, 0), Some(Color::WHITE));
+
+        let hints = state.key_help();
+        let ready: Vec<_> = hints
+            .iter()
+            .filter(|hint| hint.ready)
+            .map(|hint| hint.keys.as_str())
+            .collect();
+        assert!(ready.contains(&"F1+Pad"));
+        assert!(ready.contains(&"F1+F2"));
+        // F2 on its own is taken over by the chord
+        assert!(!ready.contains(&"F2"));
+
+        state.key(0, 0, false);
+        assert!(!state.help);
+    }
+}
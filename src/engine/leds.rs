@@ -0,0 +1,490 @@
+//! What the keyboard's LEDs show, in the colours of the palette. See
+//! [`crate::palette`].
+
+use crate::audio::{Bus, SoundId};
+use crate::deck::Deck;
+use crate::driver::adafruit::seesaw::neopixel::Color;
+use crate::eq::Band;
+use crate::game::Judgement;
+use crate::grid;
+use crate::keymap::KeyAction;
+use crate::scene::Slot;
+
+use super::{Effect, PadEntry, PlayState, SortMode, MIXER_BANDS, TICKS_PER_BEAT};
+
+/// Fully saturated color with the given hue, in degrees.
+fn hue_color(hue: f32) -> Color {
+    let h = hue / 60.;
+    let x = 1. - (h % 2. - 1.).abs();
+
+    let (r, g, b) = match h as u32 {
+        0 => (1., x, 0.),
+        1 => (x, 1., 0.),
+        2 => (0., 1., x),
+        3 => (0., x, 1.),
+        4 => (x, 0., 1.),
+        _ => (1., 0., x),
+    };
+
+    Color::from_f32(r, g, b)
+}
+
+/// Color of a bound pad that has neither a custom color nor a folder color.
+pub const DEFAULT_PAD_COLOR: Color = Color {
+    r: 50,
+    g: 50,
+    b: 50,
+    w: 255,
+};
+
+/// Colour that the keys for `action` are lit in by the help for the keys,
+/// which is the same every run.
+pub(super) fn action_color(action: KeyAction) -> Color {
+    hue_color(crate::util::hue_hash(&format!("{action:?}")))
+}
+
+impl PlayState {
+    /// Color of the sound key at (x, y), where y is between 1 and 3.
+    pub fn key_color(&self, x: usize, y: usize) -> Color {
+        let key = &self.sound_keys[y - 1][x];
+
+        // a pad that is being bounced onto is red, and dim until the
+        // recording starts
+        match self.bounce {
+            Some(b) if b.pad == (x, y) && b.recording => return self.palette.back.color(),
+            Some(b) if b.pad == (x, y) => return self.palette.back.dim(60),
+            _ => {}
+        }
+
+        // in split mode, each half is in its own colour, unless a pad has a
+        // custom one
+        let half = self.palette.decks[Bus::of_column(x).index()];
+        let side = self.split_mode.then(|| half.color());
+        let color = key
+            .color
+            .or(side)
+            .or(key.folder_color)
+            .unwrap_or(DEFAULT_PAD_COLOR);
+
+        match (key.binding, &key.macro_binding) {
+            (None, None) if self.split_mode => half.dim(20),
+            (None, None) => Color::BLACK,
+            // dim until the sound has been decoded and can be played
+            (Some(id), _) if self.sounds.get(id.0).is_some_and(|s| !s.ready) => color.scale(40),
+            _ => color,
+        }
+    }
+
+    /// Sets or clears the custom color of the pad at (x, y). Pads without one
+    /// are colored by folder.
+    pub fn set_pad_color(&mut self, x: usize, y: usize, color: Option<Color>) {
+        self.edit_pads(|state| state.sound_keys[y - 1][x].color = color);
+    }
+
+    /// Color of the top-level folder that a sound is in, e.g. Kicks or FX, so
+    /// that kits can be read at a glance. Sounds that aren't in a folder have
+    /// none.
+    pub(super) fn folder_color(&self, sound: SoundId) -> Option<Color> {
+        let path = self.sounds.get(sound.0)?.library_path();
+        let relative = path.strip_prefix(self.sounds_dir()).ok()?;
+        let mut components = relative.components();
+        let folder = components.next()?;
+
+        // the first component is the file itself if there is no folder
+        components.next()?;
+
+        Some(hue_color(crate::util::hue_hash(
+            &folder.as_os_str().to_string_lossy(),
+        )))
+    }
+
+    /// LED colours that reflect the bindings and modes.
+    pub fn keyboard_leds(&self) -> Vec<Effect> {
+        if self.help && self.uses_keymap() {
+            return self.help_leds();
+        }
+
+        let mut leds = vec![];
+        let mut set = |x, y, color| leds.push(Effect::SetLed { x, y, color });
+        let palette = &self.palette;
+        // the modes other than free play are laid out on the NeoTrellis's
+        // part of the grid
+        let (width, height) = grid::NEOTRELLIS;
+
+        if self.reassign.is_some() || self.deck_mode || self.mixer_mode || self.fx_mode {
+            for (x, y) in self.led_positions() {
+                if !Self::in_layout(x, y) {
+                    set(x, y, Color::BLACK);
+                }
+            }
+        }
+
+        if let Some(reassign) = &self.reassign {
+            set(0, 0, palette.back.color());
+            set(1, 0, palette.stored.color());
+
+            if self.pad_browser {
+                // F3 is lit if there are more pages
+                if reassign.pad_pages() > 1 {
+                    set(2, 0, palette.pages.color());
+                } else {
+                    set(2, 0, Color::BLACK);
+                }
+            } else {
+                // F3 shows the sort mode
+                set(
+                    2,
+                    0,
+                    match reassign.sort {
+                        SortMode::Name => palette.sort[0].color(),
+                        SortMode::Duration => palette.sort[1].color(),
+                        SortMode::Bpm => palette.sort[2].color(),
+                    },
+                );
+            }
+
+            // if something is selected, save button is bright, otherwise
+            // dim
+            if reassign.selection.is_some() {
+                set(3, 0, palette.active.color());
+            } else {
+                set(3, 0, palette.active.dim(50));
+            }
+
+            for x in 0..width {
+                for y in 1..height {
+                    if self.pad_browser {
+                        // folders are in their own colors, and sounds are
+                        // white, brightest if selected
+                        let color = match reassign.pad_entry(x, y) {
+                            Some(PadEntry::Dir(dir)) => {
+                                hue_color(crate::util::hue_hash(&dir.to_string_lossy()))
+                            }
+                            Some(PadEntry::Sound(id)) if reassign.selection == Some(id) => {
+                                Color::WHITE
+                            }
+                            Some(PadEntry::Sound(_)) => Color::WHITE.scale(40),
+                            Some(PadEntry::PreviousPage | PadEntry::NextPage) => {
+                                palette.pages.color()
+                            }
+                            None => Color::BLACK,
+                        };
+
+                        set(x, y, color);
+                    } else if (x, y) == reassign.key {
+                        // the key being reassigned is highlighted if the
+                        // selected sound is starred
+                        match reassign.selection {
+                            Some(id) if self.favorites.contains(&id) => {
+                                set(x, y, palette.highlight.color())
+                            }
+                            _ => set(x, y, Color::WHITE),
+                        }
+                    } else {
+                        set(x, y, Color::BLACK);
+                    }
+                }
+            }
+
+            return leds;
+        }
+
+        if self.deck_mode {
+            let shift = self.fn_keys[3].pressed;
+
+            // F1 = back to the pads
+            set(0, 0, palette.back.color());
+
+            for deck in Deck::ALL {
+                let state = &self.decks[deck.index()];
+                let color = palette.decks[deck.index()].color();
+
+                // F2 and F3 show whether their deck is playing, or its loop
+                // while F4 is held
+                let transport = match (&state.track, shift) {
+                    (None, _) => Color::BLACK,
+                    (Some(_), true) if state.loop_region.is_some() => palette.highlight.color(),
+                    (Some(_), true) if state.loop_in.is_some() => palette.highlight.dim(60),
+                    (Some(_), true) => Color::BLACK,
+                    (Some(_), false) if state.status.playing => color,
+                    (Some(_), false) => color.scale(40),
+                };
+                set(1 + deck.index(), 0, transport);
+
+                // set cues are bright, and empty ones are dim
+                for (cue, position) in state.cues.iter().enumerate() {
+                    let x = deck.index() * 2 + cue % 2;
+                    let y = 1 + cue / 2;
+
+                    set(
+                        x,
+                        y,
+                        match (&state.track, position) {
+                            (None, _) => Color::BLACK,
+                            (Some(_), Some(_)) => color,
+                            (Some(_), None) => color.scale(40),
+                        },
+                    );
+                }
+            }
+
+            set(
+                3,
+                0,
+                if shift {
+                    Color::WHITE
+                } else {
+                    Color::from_u8(40, 40, 40)
+                },
+            );
+
+            return leds;
+        }
+
+        if self.mixer_mode {
+            // F1 = back to the pads
+            set(0, 0, palette.back.color());
+
+            // F2 and F3 = the scenes that are kept, and F4 = morph
+            for (x, slot) in [(1, Slot::A), (2, Slot::B)] {
+                let color = match (&self.morph, &self.scenes[slot.index()]) {
+                    (Some(morph), _) if morph.to == slot => Color::WHITE,
+                    (_, Some(_)) => palette.stored.color(),
+                    (_, None) => Color::BLACK,
+                };
+
+                set(x, 0, color);
+            }
+
+            set(3, 0, Color::from_u8(40, 40, 40));
+
+            // bands that are playing are lit, and killed ones are dark
+            for x in 0..width {
+                let kills = self.eq[Bus::of_column(x).index()];
+
+                for (y, band) in (1..height).zip(MIXER_BANDS) {
+                    let color = match band {
+                        Band::High => palette.bands[0].color(),
+                        Band::Mid => palette.bands[1].color(),
+                        Band::Low => palette.bands[2].color(),
+                    };
+
+                    set(
+                        x,
+                        y,
+                        if kills.is_killed(band) {
+                            Color::BLACK
+                        } else {
+                            color
+                        },
+                    );
+                }
+            }
+
+            return leds;
+        }
+
+        if self.fx_mode {
+            // F1 = back to the pads
+            set(0, 0, palette.back.color());
+
+            for x in 1..width {
+                set(x, 0, Color::BLACK);
+            }
+
+            // each column is the colour of its effect, brighter down the
+            // rows, and the held pad is white
+            for x in 0..width {
+                let color = palette.fx[x].color();
+
+                for y in 1..height {
+                    if self.fx_pad == Some((x, y)) {
+                        set(x, y, Color::WHITE);
+                    } else {
+                        set(x, y, color.scale(60 + 65 * (y as u8 - 1)));
+                    }
+                }
+            }
+
+            return leds;
+        }
+
+        if let Some(game) = &self.game {
+            // F1 = give up
+            set(0, 0, palette.back.color());
+
+            for x in 1..self.sound_keys.width() {
+                set(x, 0, Color::BLACK);
+            }
+
+            // the pads of the game are dim, and light up over the beat
+            // before each hit, then flash how it was played
+            let now = self.loop_time();
+
+            for (x, y) in self.pads() {
+                let color = match game.flash((x, y), now) {
+                    Some(Judgement::Perfect) => palette.active.color(),
+                    Some(Judgement::Good) => palette.highlight.color(),
+                    Some(Judgement::Ok) => palette.stored.color(),
+                    Some(Judgement::Miss) => palette.back.color(),
+                    None => match game.next_hit((x, y), now) {
+                        Some(lead) if lead < TICKS_PER_BEAT => {
+                            let lead = lead as u32 * 215 / TICKS_PER_BEAT as u32;
+                            palette.beat.dim(255 - lead as u8)
+                        }
+                        _ if game.pads.contains(&(x, y)) => Color::WHITE.scale(20),
+                        _ => Color::BLACK,
+                    },
+                };
+
+                set(x, y, color);
+            }
+
+            return leds;
+        }
+
+        for x in 0..self.sound_keys.width() {
+            match x < self.fn_keys.len() {
+                true => set(x, 0, self.fn_led(x)),
+                false => set(x, 0, Color::BLACK),
+            }
+        }
+
+        for (x, y) in self.pads() {
+            set(x, y, self.key_color(x, y));
+        }
+
+        leds
+    }
+
+    /// Colour of an Fn LED outside of reassign and deck mode, where the beat
+    /// strip lights over what it normally shows.
+    pub(super) fn fn_led(&self, x: usize) -> Color {
+        if self.split_mode {
+            let side = Bus::of_column(x);
+            let color = self.palette.decks[side.index()];
+
+            return match x {
+                // outer keys = the half's loop mode, bright while its looper
+                // is on
+                0 | 3 if self.split_loops[side.index()].is_some() => color.color(),
+                0 | 3 => color.dim(40),
+                // inner keys = clear its loops
+                _ => Color::WHITE,
+            };
+        }
+
+        if let Some(color) = self.beat_strip.color(x, &self.palette) {
+            return color;
+        }
+
+        match self.keymap.tap(x) {
+            // quantization key white if it is on
+            Some(KeyAction::ToggleQuantize) if self.quantize => Color::WHITE,
+            Some(KeyAction::ToggleQuantize) => Color::BLACK,
+            // loop mode key dim while the looper is on
+            Some(KeyAction::CycleLoopMode) if self.loop_divider.is_some() => {
+                Color::from_u8(40, 40, 40)
+            }
+            Some(KeyAction::CycleLoopMode) => Color::BLACK,
+            // the rest always white
+            _ => Color::WHITE,
+        }
+    }
+
+    /// The F1 LED while the Pi has a health warning, which blinks red. It is
+    /// left alone in the other modes, where F1 is already red.
+    pub fn health_led(&self, on: bool) -> Vec<Effect> {
+        if self.reassign.is_some()
+            || self.deck_mode
+            || self.mixer_mode
+            || self.fx_mode
+            || self.game.is_some()
+        {
+            return vec![];
+        }
+
+        let color = if on {
+            self.palette.back.color()
+        } else {
+            Color::BLACK
+        };
+
+        vec![Effect::SetLed { x: 0, y: 0, color }]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use crate::audio::{SoundId, SoundInfo};
+    use crate::clock::VirtualClock;
+    use crate::driver::adafruit::seesaw::neopixel::Color;
+    use crate::engine::test::{led, play_state};
+    use crate::engine::{Command, PlayState, DEFAULT_PAD_COLOR};
+
+    #[test]
+    fn pads_are_dim_until_their_sound_is_ready() {
+        let clock = VirtualClock::new();
+        let mut state = play_state(&clock);
+        let mut sound = state.sounds[0].clone();
+        state.sound_keys[0][0].binding = Some(SoundId(0));
+
+        Arc::make_mut(&mut state.sounds)[0].ready = false;
+        let dim = state.key_color(0, 1);
+
+        sound.duration = Duration::from_secs(2);
+        let effects = state.sound_ready(sound);
+        assert_eq!(state.sounds[0].duration, Duration::from_secs(2));
+        assert_eq!(led(&effects, 0, 1), Some(state.key_color(0, 1)));
+        assert_eq!(dim, state.key_color(0, 1).scale(40));
+    }
+
+    #[test]
+    fn pads_are_colored_by_folder() {
+        let clock = VirtualClock::new();
+        let sounds = ["Kicks/Deep Kick.wav", "Kicks/Hard Kick.wav", "FX/Riser.wav"]
+            .into_iter()
+            .enumerate()
+            .map(|(i, path)| SoundInfo {
+                id: SoundId(i),
+                path: PathBuf::from("audio").join(path),
+                root: PathBuf::from("audio"),
+                duration: Duration::from_millis(500),
+                peaks: vec![],
+                loudness_db: -12.,
+                key: None,
+                tags: Default::default(),
+                ready: true,
+            })
+            .collect();
+        let mut state = PlayState::new(sounds, Arc::new(clock.clone()));
+
+        for (x, id) in [(0, 0), (1, 1), (2, 2)] {
+            state.reassign_sound_begin((x, 1));
+            state.command(Command::SelectSound(SoundId(id)));
+            state.reassign_sound_save();
+        }
+
+        assert_eq!(state.key_color(0, 1), state.key_color(1, 1));
+        assert_ne!(state.key_color(0, 1), state.key_color(2, 1));
+        assert_ne!(state.key_color(0, 1), DEFAULT_PAD_COLOR);
+
+        // a custom color wins until another sound is assigned
+        let red = Color::from_u8(255, 0, 0);
+        state.command(Command::SetPadColor {
+            x: 2,
+            y: 1,
+            color: Some(red),
+        });
+        assert_eq!(state.key_color(2, 1), red);
+
+        state.reassign_sound_begin((2, 1));
+        state.command(Command::SelectSound(SoundId(1)));
+        state.reassign_sound_save();
+        assert_eq!(state.key_color(2, 1), state.key_color(0, 1));
+    }
+}
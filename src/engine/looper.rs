@@ -0,0 +1,1405 @@
+//! The looper: the loops that the pads are added to, the tempo that they
+//! play at, and what records them, like bounces, tapes and renders.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::info;
+
+use crate::audio::{Bus, RenderHit, SoundId, SoundInfo};
+use crate::driver::adafruit::seesaw::neopixel::Color;
+use crate::pad::PadSettings;
+use crate::palette::Palette;
+use crate::render::{RenderStatus, RenderTarget};
+use crate::tape::TapeSplit;
+
+use super::{Effect, PlayState};
+
+/// The loop divider after `divider` in [`LOOP_DIVIDERS`], or None to turn
+/// the looper off after the last one.
+pub(super) fn next_loop_divider(divider: Option<isize>) -> Option<isize> {
+    match divider {
+        None => Some(-8),
+        Some(-8) => Some(-6),
+        Some(-6) => Some(-4),
+        Some(-4) => Some(-3),
+        Some(-3) => Some(-2),
+        Some(-2) => Some(0),
+        // loop divider 0 means period is based on length of audio
+        // useful for long snippets
+        Some(0) => Some(1),
+        // at 60 BPM, loop divider higher than 6 is probably not useful
+        // fractional loop divider can only be factors of 60
+        Some(1) => Some(2),
+        Some(2) => Some(3),
+        Some(3) => Some(4),
+        Some(4) => Some(5),
+        Some(5) => Some(6),
+        Some(6) => None,
+        // Some(10) => Some(12),
+        // Some(12) => Some(15),
+        // Some(15) => Some(20),
+        // Some(20) => Some(30),
+        // Some(30) => Some(60),
+        // Some(60) => None,
+        _ => unreachable!(),
+    }
+}
+
+/// The loop divider before `divider` in [`LOOP_DIVIDERS`], undoing
+/// [`next_loop_divider`].
+pub(super) fn previous_loop_divider(divider: Option<isize>) -> Option<isize> {
+    match divider {
+        None => LOOP_DIVIDERS.last().copied(),
+        Some(divider) => LOOP_DIVIDERS
+            .iter()
+            .position(|&d| d == divider)
+            .and_then(|i| i.checked_sub(1))
+            .map(|i| LOOP_DIVIDERS[i]),
+    }
+}
+
+/// The looper counts this many ticks to a beat.
+pub(crate) const TICKS_PER_BEAT: usize = 60;
+
+/// The Fn LEDs count this many beats to a bar.
+pub(crate) const BEATS_PER_BAR: usize = 4;
+
+/// Loop dividers that F4 steps through, in order. Negative values are
+/// multipliers, 0 loops over the length of the sound, and divisors of 60 are
+/// fractions of a beat.
+pub const LOOP_DIVIDERS: [isize; 12] = [-8, -6, -4, -3, -2, 0, 1, 2, 3, 4, 5, 6];
+
+/// The LED of a key sync pad that is waiting to play blinks on and off for
+/// this many ticks each.
+const ARMED_BLINK: usize = TICKS_PER_BEAT / 4;
+
+/// Shows where the looper is in the bar on the Fn LEDs. The LED of the
+/// current beat lights up for the first half of the beat, in orange on the
+/// downbeat, and shows what it normally does the rest of the time.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BeatStrip {
+    /// the beat whose LED is lit, counting from 0
+    lit: Option<usize>,
+}
+
+impl BeatStrip {
+    /// Moves the strip on to the given tick, and returns the beats whose LEDs
+    /// changed. Nothing is lit unless the looper is on.
+    pub(super) fn advance(&mut self, tick: usize, looping: bool) -> Vec<usize> {
+        let lit = (looping && tick % TICKS_PER_BEAT < TICKS_PER_BEAT / 2)
+            .then_some(tick / TICKS_PER_BEAT % BEATS_PER_BAR);
+        let old = std::mem::replace(&mut self.lit, lit);
+
+        if old == lit {
+            return vec![];
+        }
+
+        old.into_iter().chain(lit).collect()
+    }
+
+    /// Colour of the LED of `beat`, or None if it isn't lit.
+    pub fn color(&self, beat: usize, palette: &Palette) -> Option<Color> {
+        match self.lit {
+            Some(0) if beat == 0 => Some(palette.downbeat.color()),
+            Some(lit) if beat == lit => Some(palette.beat.color()),
+            _ => None,
+        }
+    }
+}
+
+/// A recording of the next few bars of the master mix onto a pad.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Bounce {
+    /// the pad that the recording is bound to, as (x, y)
+    pub pad: (usize, usize),
+    /// the tick that the recording starts on, at the start of a bar
+    pub start: usize,
+    /// how many ticks the recording lasts
+    pub ticks: usize,
+    /// whether the recording has started
+    pub recording: bool,
+}
+
+/// A tape of the master mix that is running. See [`crate::tape`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tape {
+    /// how many files it has been split into
+    pub takes: usize,
+    /// whether a new take starts on the next tick
+    pub split: bool,
+}
+
+/// A render of the loops that is running in the background. See
+/// [`PlayState::start_render`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Render {
+    pub target: RenderTarget,
+    /// the fraction that is done, from 0.0 to 1.0
+    pub progress: f32,
+    /// the tick that the render starts from, at the start of a bar
+    pub start: usize,
+    /// how many ticks it lasts
+    pub ticks: usize,
+}
+
+/// A glide of the tempo from one BPM to another. See
+/// [`PlayState::ramp_tempo`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TempoRamp {
+    pub from: f32,
+    pub to: f32,
+    /// the tick that the ramp started on
+    pub start: usize,
+    /// how many ticks the ramp lasts
+    pub ticks: usize,
+    /// the tick length that the ramp last set, to tell if the tempo has been
+    /// changed some other way
+    set: Duration,
+}
+
+impl TempoRamp {
+    /// BPM that the ramp has got to on the given tick.
+    pub fn bpm_at(&self, tick: usize) -> f32 {
+        let progress = tick.saturating_sub(self.start) as f32 / self.ticks.max(1) as f32;
+        self.from + (self.to - self.from) * progress.min(1.)
+    }
+}
+
+/// Order that the arpeggiator plays the held pads in, where up is from the
+/// top left pad to the bottom right one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ArpPattern {
+    #[default]
+    Up,
+    Down,
+    Random,
+}
+
+/// Lengths of the arpeggiator's steps in ticks: quarter, eighth, triplet
+/// eighth and sixteenth notes.
+pub const ARP_RATES: [usize; 4] = [60, 30, 20, 15];
+
+/// Plays the bound pads that are held down one after another, in time with
+/// the looper, instead of when they are pressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Arp {
+    pub on: bool,
+    pub pattern: ArpPattern,
+    /// ticks between steps
+    pub every: usize,
+    /// steps played since the pads were last all let go
+    pub(super) step: usize,
+    /// state of the random pattern's generator
+    seed: u32,
+}
+
+impl Default for Arp {
+    fn default() -> Self {
+        Self {
+            on: false,
+            pattern: ArpPattern::Up,
+            every: 15,
+            step: 0,
+            seed: 0x9e37_79b9,
+        }
+    }
+}
+
+impl Arp {
+    /// Picks which of `n` held pads plays on this step, and moves on to the
+    /// next one.
+    fn next(&mut self, n: usize) -> usize {
+        let i = match self.pattern {
+            ArpPattern::Up => self.step % n,
+            ArpPattern::Down => n - 1 - self.step % n,
+            ArpPattern::Random => {
+                // xorshift, which is plenty for picking pads
+                self.seed ^= self.seed << 13;
+                self.seed ^= self.seed >> 17;
+                self.seed ^= self.seed << 5;
+                self.seed as usize % n
+            }
+        };
+
+        self.step += 1;
+        i
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct LoopState {
+    /// offset from the start of the cycle in ticks
+    pub offset: isize,
+    /// period in ticks
+    pub period: usize,
+    pub sound: SoundId,
+    /// the crossfader bus of the pad that the loop was added from
+    pub bus: Bus,
+    /// the settings of the pad that the loop was added from
+    pub settings: PadSettings,
+    /// whether it plays as one voice that wraps round without a gap, rather
+    /// than being triggered every period, which loops of sounds that are
+    /// tagged as loops do when they are looped over their own length
+    pub seamless: bool,
+    /// the looper's id for the voice that a seamless loop is playing on
+    pub voice: Option<u32>,
+    /// whether it was put on the beat, so that a groove can move it
+    pub quantized: bool,
+}
+
+impl LoopState {
+    /// Whether the loop comes round on the given tick.
+    pub fn is_due(&self, tick: usize) -> bool {
+        (tick as isize - self.offset).rem_euclid(self.period as isize) == 0
+    }
+}
+
+impl PlayState {
+    pub fn loop_time(&self) -> usize {
+        let time = self.clock.now() - self.beginning;
+        (time.as_nanos() / self.tick.as_nanos()) as usize
+    }
+
+    /// Time of the looper in ticks when what was just pressed was heard,
+    /// which is [`PlayState::latency_compensation`] ago.
+    pub(super) fn pressed_loop_time(&self) -> usize {
+        let time = (self.clock.now() - self.beginning).saturating_sub(self.latency_compensation);
+        (time.as_nanos() / self.tick.as_nanos()) as usize
+    }
+
+    /// Time at which the looper reaches the given tick.
+    pub fn tick_deadline(&self, tick: usize) -> Instant {
+        self.beginning + self.tick * tick as u32
+    }
+
+    /// Changes the length of a tick, moving the start of the looper so that
+    /// the current position in ticks doesn't jump.
+    pub fn set_tick(&mut self, tick: Duration) {
+        let now = self.clock.now();
+        let position = (now - self.beginning).as_secs_f64() / self.tick.as_secs_f64();
+
+        self.beginning = now.checked_sub(tick.mul_f64(position)).unwrap_or(now);
+        self.tick = tick;
+    }
+
+    /// Loops that are due on the given tick.
+    fn due_loops(&self, tick: usize) -> impl Iterator<Item = &LoopState> + '_ {
+        self.loops.iter().filter(move |l| self.is_loop_due(l, tick))
+    }
+
+    /// Whether a loop comes round on the given tick, once the groove that is
+    /// on has moved its triggers if it is quantized. Seamless loops aren't
+    /// moved, since they are one voice.
+    pub(super) fn is_loop_due(&self, l: &LoopState, tick: usize) -> bool {
+        match self.groove.and_then(|i| self.grooves.get(i)) {
+            Some(groove) if l.quantized && !l.seamless => {
+                groove.sources(tick).any(|source| l.is_due(source))
+            }
+            _ => l.is_due(tick),
+        }
+    }
+
+    /// Whether a loop of `sound` with the given loop divider plays as one
+    /// voice that wraps round, rather than being triggered every period: it
+    /// has to be a loop that is looped over its own length.
+    fn is_seamless(&self, sound: SoundId, loop_divider: Option<isize>) -> bool {
+        loop_divider == Some(0) && self.sounds.get(sound.0).is_some_and(|s| s.is_loop())
+    }
+
+    /// Starts the voice of the seamless loop at `i` in the loops, unless it is
+    /// playing already. A voice that was started at another tempo is started
+    /// again, since it wraps round after the period at that tempo.
+    pub(super) fn start_loop_voice(&mut self, i: usize) -> Vec<Effect> {
+        let l = &mut self.loops[i];
+        let mut effects = vec![];
+
+        if let Some(voice) = l.voice {
+            match self.loop_voices.get(&voice) {
+                Some(&tick) if tick == self.tick => return effects,
+                Some(_) => {
+                    self.loop_voices.remove(&voice);
+                    effects.push(Effect::StopLoop(voice));
+                }
+                None => {}
+            }
+        }
+
+        let voice = self.next_loop_voice;
+        self.next_loop_voice = self.next_loop_voice.wrapping_add(1);
+        self.loop_voices.insert(voice, self.tick);
+        l.voice = Some(voice);
+
+        self.last_played.insert(l.sound, self.clock.now());
+        effects.push(Effect::PlayLoop {
+            sound: l.sound,
+            bus: l.bus,
+            settings: l.settings,
+            voice,
+            length: self.tick * l.period as u32,
+            seam: self.loop_seam,
+        });
+
+        effects
+    }
+
+    /// Stops the voices of seamless loops that aren't in the loops any more,
+    /// or all of them if the looper has stopped.
+    pub(super) fn stop_loop_voices(&mut self) -> Vec<Effect> {
+        let loops = &self.loops;
+        let running = self.running;
+        let mut effects = vec![];
+
+        self.loop_voices.retain(|&voice, _| {
+            let playing = running && loops.iter().any(|l| l.voice == Some(voice));
+
+            if !playing {
+                effects.push(Effect::StopLoop(voice));
+            }
+
+            playing
+        });
+
+        effects
+    }
+
+    /// Sounds that the looper should play on the given tick.
+    pub fn loops_on_tick(&self, tick: usize) -> impl Iterator<Item = SoundId> + '_ {
+        self.due_loops(tick).map(|l| l.sound)
+    }
+
+    /// Period in ticks of a loop of `sound` with the given loop divider, or
+    /// None if the looper is off.
+    fn loop_period(&self, sound: SoundId, loop_divider: Option<isize>) -> Option<usize> {
+        let loop_divider = loop_divider?;
+
+        let period = if loop_divider < 0 {
+            60 * -loop_divider
+        } else if loop_divider == 0 {
+            (self.sounds[sound.0].duration.as_secs_f32() / self.tick.as_secs_f32()) as isize
+        } else {
+            60 / loop_divider
+        } as usize;
+
+        Some(period)
+    }
+
+    /// The next tick that the key sync pad at (x, y) would play `sound` on:
+    /// the next multiple of its loop period if the looper is on, or else the
+    /// next beat.
+    pub(super) fn sync_tick(&self, x: usize, y: usize, sound: SoundId) -> usize {
+        let period = self
+            .loop_period(sound, self.pad_divider(x, y))
+            .unwrap_or(TICKS_PER_BEAT)
+            .max(1);
+
+        (self.loop_time() / period + 1) * period
+    }
+
+    /// Whether the pad at (x, y) waits for the next bar to start when it is
+    /// pressed, which it does while it would be added to the loops. See
+    /// [`PlayConfig::launch_on_bar`].
+    pub(super) fn launches_on_bar(&self, x: usize, y: usize) -> bool {
+        self.launch_on_bar && self.running && self.pad_divider(x, y).is_some()
+    }
+
+    /// The tick at the top of the bar after the one that the looper is in.
+    pub(super) fn next_bar(&self) -> usize {
+        let bar = TICKS_PER_BEAT * BEATS_PER_BAR;
+        (self.loop_time() / bar + 1) * bar
+    }
+
+    /// Adds a loop of `sound` with the global loop divider.
+    pub fn add_to_loops(&mut self, sound: SoundId, bus: Bus) {
+        self.add_loop(sound, bus, self.loop_divider, PadSettings::default());
+    }
+
+    /// Adds a loop of `sound` with the given loop divider, starting when it
+    /// was heard or on the last multiple of its period before that if
+    /// quantizing. Nothing is added if the divider is None.
+    pub(super) fn add_loop(
+        &mut self,
+        sound: SoundId,
+        bus: Bus,
+        loop_divider: Option<isize>,
+        settings: PadSettings,
+    ) {
+        if let Some(period) = self.loop_period(sound, loop_divider) {
+            let mut offset = self.pressed_loop_time();
+
+            if self.quantize {
+                offset = offset - (offset % period);
+            }
+
+            let ls = LoopState {
+                offset: offset as isize,
+                period,
+                sound,
+                bus,
+                settings,
+                seamless: self.is_seamless(sound, loop_divider),
+                voice: None,
+                quantized: self.quantize,
+            };
+
+            info!("adding sound to loops: {ls:?}");
+            self.loops.push(ls);
+        }
+    }
+
+    /// Stops the looper, or starts it again from the top of the bar.
+    pub fn start_stop(&mut self) {
+        if !self.running {
+            self.beginning = self.clock.now();
+        }
+
+        self.running = !self.running;
+
+        if self.running {
+            self.split_tape(TapeSplit::Transport);
+        }
+
+        // the looper starts from the top of a bar
+        if let Some(bounce) = &mut self.bounce {
+            if self.running && !bounce.recording {
+                bounce.start = 0;
+            }
+        }
+    }
+
+    /// Starts taping the master mix to disk, or stops it if it is running.
+    pub fn toggle_tape(&mut self) -> Effect {
+        match self.tape.take() {
+            Some(tape) => {
+                info!("stopped the tape after {} takes", tape.takes);
+                Effect::StopTape
+            }
+            None => {
+                self.tape = Some(Tape {
+                    takes: 1,
+                    split: false,
+                });
+
+                Effect::StartTake(self.pattern_name.clone())
+            }
+        }
+    }
+
+    /// Starts a new take on the tape on the next tick, if it is running and
+    /// is split on `when`.
+    pub(super) fn split_tape(&mut self, when: TapeSplit) {
+        match &mut self.tape {
+            Some(tape) if self.tape_split == when => tape.split = true,
+            _ => {}
+        }
+    }
+
+    /// Starts the take that the tape was split for, named after the pattern
+    /// that was started.
+    pub(super) fn tick_tape(&mut self) -> Option<Effect> {
+        let tape = self.tape.as_mut().filter(|tape| tape.split)?;
+        tape.split = false;
+        tape.takes += 1;
+
+        Some(Effect::StartTake(self.pattern_name.clone()))
+    }
+
+    /// Arms a recording of the master mix onto the pad at (x, y), which starts
+    /// at the next bar and lasts [`PlayState::bounce_bars`] bars. When it has
+    /// been made, it is bound to the pad and replaces the loops, so that a
+    /// busy loop stack can be built on further. Arming the same pad again
+    /// cancels it, unless it has started.
+    pub fn arm_bounce(&mut self, x: usize, y: usize) {
+        match self.bounce {
+            Some(b) if b.recording => return,
+            Some(b) if b.pad == (x, y) => {
+                self.bounce = None;
+                return;
+            }
+            _ => {}
+        }
+
+        let bar = TICKS_PER_BEAT * BEATS_PER_BAR;
+
+        self.bounce = Some(Bounce {
+            pad: (x, y),
+            start: (self.loop_time() / bar + 1) * bar,
+            ticks: self.bounce_bars * bar,
+            recording: false,
+        });
+    }
+
+    /// Adds a recording that the audio subsystem has made, binding it to the
+    /// pad of the bounce and making it the only loop.
+    pub fn bounce_recorded(&mut self, sound: SoundInfo) -> Vec<Effect> {
+        let id = sound.id;
+        Arc::make_mut(&mut self.sounds).push(sound);
+
+        let Some(Bounce {
+            pad: (x, y),
+            start,
+            ticks,
+            ..
+        }) = self.bounce.take()
+        else {
+            return vec![];
+        };
+
+        self.bind_loop(x, y, id, start, ticks)
+    }
+
+    /// Renders [`PlayState::bounce_bars`] bars of the loops in the background,
+    /// from the start of the stretch of that many bars that is playing, so
+    /// that the render lines up with the loops when it replaces them. They are rendered
+    /// with their pads' settings, but without the mixer or the effects. A
+    /// render that is already running has to finish or be cancelled first.
+    pub fn start_render(&mut self, target: RenderTarget) -> Vec<Effect> {
+        if self.render.is_some() {
+            return vec![];
+        }
+
+        let ticks = self.bounce_bars * TICKS_PER_BEAT * BEATS_PER_BAR;
+        let start = self.loop_time() / ticks * ticks;
+
+        let state = &*self;
+        let hits: Vec<_> = (start..start + ticks)
+            .flat_map(|tick| {
+                state.due_loops(tick).map(move |l| RenderHit {
+                    sound: l.sound,
+                    at: state.tick * (tick - start) as u32,
+                    bus: l.bus,
+                    settings: l.settings,
+                })
+            })
+            .collect();
+
+        if hits.is_empty() {
+            info!("there are no loops to render");
+            return vec![];
+        }
+
+        self.render = Some(Render {
+            target: target.clone(),
+            progress: 0.,
+            start,
+            ticks,
+        });
+
+        vec![Effect::Render {
+            hits,
+            length: self.tick * ticks as u32,
+            target,
+        }]
+    }
+
+    /// Handles a report on the render that is running. A render onto a pad
+    /// replaces the loops with it, like a bounce.
+    pub(super) fn render_status(&mut self, status: RenderStatus) -> Vec<Effect> {
+        match status {
+            RenderStatus::Progress(done) => {
+                if let Some(render) = &mut self.render {
+                    render.progress = done;
+                }
+
+                vec![]
+            }
+            RenderStatus::Bounced(sound) => {
+                let id = sound.id;
+                Arc::make_mut(&mut self.sounds).push(sound);
+
+                match self.render.take() {
+                    Some(Render {
+                        target: RenderTarget::Pad(x, y),
+                        start,
+                        ticks,
+                        ..
+                    }) => self.bind_loop(x, y, id, start, ticks),
+                    _ => vec![],
+                }
+            }
+            RenderStatus::Exported(_) | RenderStatus::Failed(_) => {
+                self.render = None;
+                vec![]
+            }
+            // the render was already dropped when it was cancelled
+            RenderStatus::Cancelled => vec![],
+        }
+    }
+
+    /// Binds a recording of the loops to the pad at (x, y), and makes it the
+    /// only loop, looping every `ticks` from `start`.
+    fn bind_loop(
+        &mut self,
+        x: usize,
+        y: usize,
+        id: SoundId,
+        start: usize,
+        ticks: usize,
+    ) -> Vec<Effect> {
+        let folder_color = self.folder_color(id);
+        self.edit_pads(|state| {
+            let key = &mut state.sound_keys[y - 1][x];
+            key.binding = Some(id);
+            key.folder_color = folder_color;
+            key.color = None;
+            key.cues.clear();
+            key.macro_binding = None;
+            key.settings = PadSettings::default();
+        });
+
+        // it picks up where the recording ended
+        self.loops = vec![LoopState {
+            offset: start as isize,
+            period: ticks,
+            sound: id,
+            bus: Bus::of_column(x),
+            settings: PadSettings::default(),
+            seamless: false,
+            voice: None,
+            // it is a recording of the loops, which were moved already
+            quantized: false,
+        }];
+        self.loop_divider.get_or_insert(-(BEATS_PER_BAR as isize));
+
+        self.keyboard_leds()
+    }
+
+    pub fn bpm_up(&mut self) {
+        let bpm = f32::floor(1. / self.tick.as_secs_f32());
+        self.set_tick(Duration::from_secs_f32(1. / (bpm + 1.5)));
+    }
+
+    pub fn bpm_down(&mut self) {
+        let bpm = f32::floor(1. / self.tick.as_secs_f32());
+        self.set_tick(Duration::from_secs_f32(1. / (bpm - 0.5)));
+    }
+
+    /// Doubles the BPM, if it stays within a usable range. See
+    /// [`PlayState::rescale_tempo`].
+    pub fn bpm_double(&mut self) {
+        self.rescale_tempo(self.tick / 2);
+    }
+
+    /// Halves the BPM, if it stays within a usable range. See
+    /// [`PlayState::rescale_tempo`].
+    pub fn bpm_halve(&mut self) {
+        self.rescale_tempo(self.tick * 2);
+    }
+
+    /// Changes the length of a tick while the loops keep playing at the same
+    /// times, since the sounds that they play aren't sped up or slowed down
+    /// with the tempo. Their periods and offsets are scaled in ticks around
+    /// the current position.
+    fn rescale_tempo(&mut self, tick: Duration) {
+        let bpm = 1. / tick.as_secs_f32();
+
+        if !(20. ..=300.).contains(&bpm) {
+            return;
+        }
+
+        let scale = self.tick.as_secs_f64() / tick.as_secs_f64();
+        let now = self.loop_time() as isize;
+
+        for l in &mut self.loops {
+            l.period = ((l.period as f64 * scale).round() as usize).max(1);
+            l.offset = now - ((now - l.offset) as f64 * scale).round() as isize;
+        }
+
+        self.set_tick(tick);
+    }
+
+    /// Changes the BPM by `delta` beats, keeping it within a usable range.
+    pub fn bpm_nudge(&mut self, delta: f32) {
+        let bpm = 1. / self.tick.as_secs_f32();
+        let bpm = (bpm + delta).clamp(20., 300.);
+        self.set_tick(Duration::from_secs_f32(1. / bpm));
+    }
+
+    /// Glides the tempo to `bpm` over `bars` bars from now, a little on every
+    /// tick. The loops keep their places in the bar, so they speed up or slow
+    /// down with it. With no bars, the tempo changes straight away. Changing
+    /// the tempo some other way stops the ramp.
+    pub fn ramp_tempo(&mut self, bpm: f32, bars: usize) {
+        let bpm = bpm.clamp(20., 300.);
+        self.tempo_ramp = None;
+
+        if bars == 0 {
+            self.set_tick(Duration::from_secs_f32(1. / bpm));
+            return;
+        }
+
+        self.tempo_ramp = Some(TempoRamp {
+            from: 1. / self.tick.as_secs_f32(),
+            to: bpm,
+            start: self.loop_time(),
+            ticks: bars * BEATS_PER_BAR * TICKS_PER_BEAT,
+            set: self.tick,
+        });
+    }
+
+    /// Moves the tempo along the ramp for the given tick. The ramp is dropped
+    /// once it reaches its tempo, or if the tempo was changed some other way.
+    pub(super) fn tick_tempo_ramp(&mut self, tick: usize) {
+        let Some(ramp) = self.tempo_ramp else { return; };
+
+        if ramp.set != self.tick {
+            self.tempo_ramp = None;
+            return;
+        }
+
+        let set = Duration::from_secs_f32(1. / ramp.bpm_at(tick));
+        self.set_tick(set);
+        self.tempo_ramp = (tick < ramp.start + ramp.ticks).then_some(TempoRamp { set, ..ramp });
+    }
+
+    pub fn clear_loops(&mut self) {
+        if self.loop_divider.is_some() {
+            self.loops.clear();
+            self.loop_divider = None;
+        }
+    }
+
+    /// Switches to the next of [`LOOP_DIVIDERS`], or turns the looper off
+    /// after the last one.
+    pub fn cycle_loop_mode(&mut self) {
+        self.loop_divider = next_loop_divider(self.loop_divider);
+    }
+
+    /// Goes back to the loop mode before the one that F4 switched to.
+    pub(super) fn cycle_loop_mode_back(&mut self) {
+        self.loop_divider = previous_loop_divider(self.loop_divider);
+    }
+
+    /// Stops recording a groove, and keeps it under `name`, in place of the
+    /// groove that already has that name. Nothing is kept if no pads were
+    /// played.
+    pub(super) fn save_groove(&mut self, name: String) {
+        let Some(recorder) = self.groove_recorder.take() else { return; };
+
+        if recorder.presses() == 0 {
+            return;
+        }
+
+        let groove = recorder.groove(name);
+
+        match self.grooves.iter().position(|g| g.name == groove.name) {
+            Some(i) => self.grooves[i] = groove,
+            None => self.grooves.push(groove),
+        }
+    }
+
+    pub fn cycle_quantize(&mut self) {
+        self.quantize = !self.quantize;
+    }
+
+    /// Plays the next of the bound pads that are held down, in the order of the
+    /// arpeggiator's pattern. The pattern starts again once they are all let
+    /// go.
+    pub(super) fn arp_step(&mut self) -> Option<Effect> {
+        let held: Vec<_> = self
+            .pads()
+            .filter_map(|(x, y)| {
+                let key = &self.sound_keys[y - 1][x];
+                key.binding
+                    .filter(|_| key.pressed)
+                    .map(|id| (x, id, key.settings))
+            })
+            .collect();
+
+        if held.is_empty() {
+            self.arp.step = 0;
+            return None;
+        }
+
+        let (x, id, settings) = held[self.arp.next(held.len())];
+        Some(self.play_with(id, Bus::of_column(x), settings, None))
+    }
+
+    /// Plays the key sync pads and the pads launched on the bar that are due
+    /// on the given tick, adding them to the loops from that tick, and blinks
+    /// the LEDs of the ones that are still waiting.
+    pub(super) fn tick_armed(&mut self, tick: usize) -> Vec<Effect> {
+        let mut effects = vec![];
+
+        for (x, y) in self.pads() {
+            let key = &self.sound_keys[y - 1][x];
+            let (Some(at), Some(id)) = (key.armed_at, key.binding) else {
+                continue;
+            };
+
+            let color = if at <= tick {
+                self.sound_keys[y - 1][x].armed_at = None;
+                let bus = Bus::of_column(x);
+
+                let divider = self.pad_divider(x, y);
+
+                if let Some(period) = self.loop_period(id, divider) {
+                    self.loops.push(LoopState {
+                        offset: tick as isize,
+                        period,
+                        sound: id,
+                        bus,
+                        settings: self.sound_keys[y - 1][x].settings,
+                        seamless: self.is_seamless(id, divider),
+                        voice: None,
+                        quantized: true,
+                    });
+                }
+
+                effects.push(self.play_pad(x, y, id, None));
+                self.key_color(x, y)
+            } else if tick.is_multiple_of(ARMED_BLINK) {
+                match tick / ARMED_BLINK % 2 {
+                    0 => self.key_color(x, y),
+                    _ => Color::BLACK,
+                }
+            } else {
+                continue;
+            };
+
+            effects.push(Effect::SetLed { x, y, color });
+        }
+
+        effects
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use crate::audio::{self, Bus, SoundId, SoundInfo};
+    use crate::clock::{Clock, VirtualClock};
+    use crate::config::GpioAction;
+    use crate::driver::adafruit::seesaw::neopixel::Color;
+    use crate::engine::test::{led, play_state, run_to_tick, sounds, tap};
+    use crate::engine::{ArpPattern, Command, Effect, PlayState, TICKS_PER_BEAT};
+    use crate::render::RenderStatus;
+    use crate::tape::TapeSplit;
+
+    fn ticks_with_sound(state: &PlayState, ticks: std::ops::Range<usize>) -> Vec<usize> {
+        ticks
+            .filter(|&tick| state.loops_on_tick(tick).next().is_some())
+            .collect()
+    }
+
+    #[test]
+    fn quantized_loop_fires_on_period() {
+        let clock = VirtualClock::new();
+        let mut state = play_state(&clock);
+
+        // 1/2 divider = one loop every 30 ticks
+        state.loop_divider = Some(2);
+        clock.advance(state.tick * 37);
+        assert_eq!(state.loop_time(), 37);
+
+        state.add_to_loops(SoundId(0), Bus::A);
+
+        assert_eq!(ticks_with_sound(&state, 0..100), vec![0, 30, 60, 90]);
+    }
+
+    #[test]
+    fn unquantized_loop_fires_from_when_it_was_added() {
+        let clock = VirtualClock::new();
+        let mut state = play_state(&clock);
+
+        state.loop_divider = Some(2);
+        state.quantize = false;
+        clock.advance(state.tick * 37);
+
+        state.add_to_loops(SoundId(0), Bus::A);
+
+        assert_eq!(ticks_with_sound(&state, 0..100), vec![7, 37, 67, 97]);
+    }
+
+    #[test]
+    fn loops_are_added_when_the_press_was_heard() {
+        let clock = VirtualClock::new();
+        let mut state = play_state(&clock);
+
+        state.loop_divider = Some(2);
+        state.quantize = false;
+        state.command(Command::SetLatencyCompensation(state.tick * 3));
+        clock.advance(state.tick * 37);
+
+        state.add_to_loops(SoundId(0), Bus::A);
+        assert_eq!(ticks_with_sound(&state, 0..100), vec![4, 34, 64, 94]);
+    }
+
+    #[test]
+    fn bpm_change_keeps_position() {
+        let clock = VirtualClock::new();
+        let mut state = play_state(&clock);
+
+        state.loop_divider = Some(2);
+        state.add_to_loops(SoundId(0), Bus::A);
+
+        // move to the middle of tick 10, then double the tempo
+        clock.advance(state.tick * 10 + state.tick / 2);
+        state.set_tick(state.tick / 2);
+        assert_eq!(state.loop_time(), 10);
+
+        // the next loop is 20 ticks away, which now takes half as long
+        let deadline = state.tick_deadline(30);
+        assert_eq!(
+            deadline - state.tick_deadline(10),
+            state.tick * 20,
+            "ticks should be evenly spaced at the new tempo"
+        );
+
+        clock.advance(state.tick * 19);
+        assert_eq!(state.loop_time(), 29);
+        clock.advance(state.tick);
+        assert_eq!(state.loop_time(), 30);
+        assert_eq!(
+            state.loops_on_tick(30).collect::<Vec<_>>(),
+            vec![SoundId(0)]
+        );
+    }
+
+    #[test]
+    fn trigger_pulses_every_beat_while_running() {
+        let clock = VirtualClock::new();
+        let mut state = play_state(&clock);
+        let pulses = |state: &mut PlayState| {
+            (0..240)
+                .filter(|&tick| state.tick(tick).contains(&Effect::TriggerPulse))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(pulses(&mut state), vec![0, 60, 120, 180]);
+
+        state.trigger_every = 30;
+        assert_eq!(pulses(&mut state).len(), 8);
+
+        state.start_stop();
+        assert!(pulses(&mut state).is_empty());
+    }
+
+    #[test]
+    fn stopped_looper_starts_from_the_top() {
+        let clock = VirtualClock::new();
+        let mut state = play_state(&clock);
+
+        state.loop_divider = Some(1);
+        state.add_to_loops(SoundId(0), Bus::A);
+        clock.advance(state.tick * 90);
+
+        state.gpio(GpioAction::StartStop, true);
+        assert!(sounds(&state.tick(120)).is_empty());
+
+        clock.advance(state.tick * 1000);
+        state.gpio(GpioAction::StartStop, true);
+        assert_eq!(state.loop_time(), 0);
+        assert_eq!(sounds(&state.tick(0)), vec![SoundId(0)]);
+    }
+
+    #[test]
+    fn key_sync_pad_waits_for_the_beat() {
+        let clock = VirtualClock::new();
+        let mut state = play_state(&clock);
+
+        state.sound_keys[0][0].binding = Some(SoundId(0));
+        state.command(Command::SetKeySync {
+            x: 0,
+            y: 1,
+            on: true,
+        });
+        clock.advance(state.tick * 50);
+
+        // the press arms the pad instead of playing
+        assert!(sounds(&tap(&mut state, 0, 1)).is_empty());
+        assert_eq!(state.sound_keys[0][0].armed_at, Some(60));
+
+        // its LED blinks until it plays on the next beat
+        assert_eq!(led(&state.tick(45), 0, 1), Some(Color::BLACK));
+        assert!(sounds(&state.tick(59)).is_empty());
+        let effects = state.tick(60);
+        assert_eq!(sounds(&effects), vec![SoundId(0)]);
+        assert_eq!(led(&effects, 0, 1), Some(state.key_color(0, 1)));
+        assert_eq!(state.sound_keys[0][0].armed_at, None);
+
+        // with the looper on, it waits for the loop period and loops from there
+        state.loop_divider = Some(-4);
+        clock.advance(state.tick * 50);
+        tap(&mut state, 0, 1);
+        assert_eq!(state.sound_keys[0][0].armed_at, Some(240));
+        state.tick(240);
+        assert_eq!(ticks_with_sound(&state, 240..600), vec![240, 480]);
+    }
+
+    #[test]
+    fn pad_loop_divider_overrides_the_global_one() {
+        let clock = VirtualClock::new();
+        let mut state = play_state(&clock);
+
+        state.sound_keys[0][0].binding = Some(SoundId(0));
+        state.sound_keys[0][1].binding = Some(SoundId(0));
+        state.command(Command::SetPadLoop {
+            x: 1,
+            y: 1,
+            loop_divider: Some(-4),
+        });
+
+        // nothing loops while the looper is off
+        tap(&mut state, 1, 1);
+        assert!(state.loops.is_empty());
+
+        state.loop_divider = Some(1);
+        tap(&mut state, 0, 1);
+        tap(&mut state, 1, 1);
+        state.tick(240);
+        let periods: Vec<_> = state.loops.iter().map(|l| l.period).collect();
+        assert_eq!(periods, vec![60, 240]);
+    }
+
+    #[test]
+    fn arp_cycles_through_held_pads() {
+        let clock = VirtualClock::new();
+        let mut state = play_state(&clock);
+        let kick_on = |bus| Effect::PlaySound(SoundId(0), bus);
+
+        state.sound_keys[0][0].binding = Some(SoundId(0));
+        state.sound_keys[0][3].binding = Some(SoundId(0));
+        state.gpio(GpioAction::ToggleArp, true);
+        state.command(Command::SetArp {
+            pattern: ArpPattern::Up,
+            every: 30,
+        });
+
+        // held pads wait for the arpeggiator instead of playing
+        assert!(sounds(&state.key(0, 1, true)).is_empty());
+        assert!(sounds(&state.key(3, 1, true)).is_empty());
+
+        assert!(state.tick(0).contains(&kick_on(Bus::A)));
+        assert!(sounds(&state.tick(15)).is_empty());
+        assert!(state.tick(30).contains(&kick_on(Bus::B)));
+        assert!(state.tick(60).contains(&kick_on(Bus::A)));
+
+        state.command(Command::SetArp {
+            pattern: ArpPattern::Down,
+            every: 30,
+        });
+        assert!(state.tick(90).contains(&kick_on(Bus::B)));
+
+        state.key(0, 1, false);
+        state.key(3, 1, false);
+        assert!(sounds(&state.tick(120)).is_empty());
+    }
+
+    #[test]
+    fn bounce_replaces_the_loops() {
+        let clock = VirtualClock::new();
+        let mut state = play_state(&clock);
+
+        state.loop_divider = Some(1);
+        state.add_to_loops(SoundId(0), Bus::A);
+        clock.advance(state.tick * 100);
+
+        // F2 + F3 + F4 + pad arms the bounce without changing any modes
+        let tick = state.tick;
+        state.key(1, 0, true);
+        state.key(2, 0, true);
+        state.key(3, 0, true);
+        tap(&mut state, 2, 3);
+        state.key(3, 0, false);
+        state.key(2, 0, false);
+        state.key(1, 0, false);
+        assert!(state.quantize);
+        assert_eq!(state.loop_divider, Some(1));
+        assert_eq!(state.tick, tick);
+
+        // it starts recording at the next bar
+        let recording = |effects: &[Effect]| {
+            effects
+                .iter()
+                .any(|effect| matches!(effect, Effect::Record(_)))
+        };
+        assert!(!recording(&state.tick(200)));
+        let effects = state.tick(240);
+        assert!(effects.contains(&Effect::Record(state.tick * 960)));
+        assert_eq!(led(&effects, 2, 3), Some(Color::from_u8(255, 0, 0)));
+
+        let effects = state.bounce_recorded(SoundInfo {
+            id: SoundId(1),
+            path: PathBuf::from("audio/Recordings/Bounce 1"),
+            root: PathBuf::from("audio"),
+            duration: Duration::from_secs(16),
+            peaks: vec![],
+            loudness_db: -12.,
+            key: None,
+            tags: Default::default(),
+            ready: true,
+        });
+        assert_eq!(state.sound_keys[2][2].binding, Some(SoundId(1)));
+        assert!(led(&effects, 2, 3).is_some());
+        assert_eq!(ticks_with_sound(&state, 0..2000), vec![240, 1200]);
+        assert!(state.bounce.is_none());
+
+        // recordings are lost when the audio subsystem restarts
+        let sounds = state.sounds[..1].to_vec();
+        state.reload(sounds);
+        assert_eq!(state.sound_keys[2][2].binding, None);
+        assert!(state.loops.is_empty());
+    }
+
+    #[test]
+    fn beat_strip_counts_through_the_bar() {
+        let clock = VirtualClock::new();
+        let mut state = play_state(&clock);
+
+        // nothing is lit until the looper is on
+        assert_eq!(led(&state.tick(0), 0, 0), None);
+
+        state.loop_divider = Some(1);
+        assert_eq!(led(&state.tick(0), 0, 0), Some(Color::from_u8(255, 100, 0)));
+
+        // off halfway through the beat
+        assert_eq!(led(&state.tick(30), 0, 0), Some(Color::WHITE));
+
+        let effects = state.tick(TICKS_PER_BEAT * 2);
+        assert_eq!(led(&effects, 2, 0), Some(Color::from_u8(0, 200, 255)));
+
+        // back to the downbeat in the next bar
+        state.tick(TICKS_PER_BEAT * 3 + 30);
+        let effects = state.tick(TICKS_PER_BEAT * 4);
+        assert_eq!(led(&effects, 0, 0), Some(Color::from_u8(255, 100, 0)));
+
+        // turning the looper off puts the LED back
+        state.clear_loops();
+        assert_eq!(
+            led(&state.tick(TICKS_PER_BEAT * 4 + 1), 0, 0),
+            Some(Color::WHITE)
+        );
+    }
+
+    #[test]
+    fn bound_key_is_added_to_loops_when_looper_is_on() {
+        let clock = VirtualClock::new();
+        let mut state = play_state(&clock);
+        state.sound_keys[0][2].binding = Some(SoundId(0));
+
+        // F4 turns the looper on
+        tap(&mut state, 3, 0);
+        assert_eq!(state.loop_divider, Some(-8));
+
+        // the pad waits for the next bar to play and loop
+        assert!(sounds(&tap(&mut state, 2, 1)).is_empty());
+        assert_eq!(state.sound_keys[0][2].armed_at, Some(240));
+        assert_eq!(led(&state.tick(225), 2, 1), Some(Color::BLACK));
+        assert_eq!(sounds(&state.tick(240)), vec![SoundId(0)]);
+        assert_eq!(state.loops.len(), 1);
+        assert_eq!(state.loops[0].offset, 240);
+
+        // F3 clears the loops and turns the looper off
+        tap(&mut state, 2, 0);
+        assert!(state.loops.is_empty());
+        assert_eq!(state.loop_divider, None);
+    }
+
+    #[test]
+    fn doubling_the_tempo_keeps_loops_in_time() {
+        let clock = VirtualClock::new();
+        let mut state = play_state(&clock);
+        let tick = state.tick;
+
+        state.loop_divider = Some(-4);
+        state.add_to_loops(SoundId(0), Bus::A);
+        clock.advance(tick * 100);
+
+        // F2 + F4 = double BPM
+        state.key(1, 0, true);
+        tap(&mut state, 3, 0);
+        state.key(1, 0, false);
+        assert!(state.quantize);
+        assert_eq!(state.tick, tick / 2);
+
+        // the loop still plays every 4 seconds, which is now 8 beats
+        assert_eq!(ticks_with_sound(&state, 200..1200), vec![380, 860]);
+
+        // F2 + F3 = halve BPM
+        state.key(1, 0, true);
+        tap(&mut state, 2, 0);
+        state.key(1, 0, false);
+        assert_eq!(state.tick, tick);
+        assert_eq!(ticks_with_sound(&state, 200..600), vec![240, 480]);
+    }
+
+    #[test]
+    fn tempo_ramps_over_bars() {
+        let clock = VirtualClock::new();
+        let mut state = play_state(&clock);
+        let mut last_tick = None;
+        let start = clock.now();
+        let bpm = |state: &PlayState| (1. / state.tick.as_secs_f32()).round();
+
+        state.command(Command::RampTempo { bpm: 120., bars: 1 });
+
+        // halfway through the bar
+        run_to_tick(&mut state, &clock, &mut last_tick, 120);
+        assert_eq!(bpm(&state), 90.);
+
+        run_to_tick(&mut state, &clock, &mut last_tick, 240);
+        assert_eq!(bpm(&state), 120.);
+        assert_eq!(state.tempo_ramp, None);
+
+        // the bar was quicker than at 60 BPM, and slower than at 120 BPM
+        let took = clock.now() - start;
+        assert!(took > Duration::from_secs(2) && took < Duration::from_secs(4));
+
+        // changing the tempo stops the ramp
+        state.command(Command::RampTempo { bpm: 60., bars: 2 });
+        run_to_tick(&mut state, &clock, &mut last_tick, 250);
+        state.bpm_nudge(5.);
+        let nudged = bpm(&state);
+        run_to_tick(&mut state, &clock, &mut last_tick, 260);
+        assert_eq!(state.tempo_ramp, None);
+        assert_eq!(bpm(&state), nudged);
+    }
+
+    #[test]
+    fn tape_starts_a_take_when_the_looper_starts() {
+        let clock = VirtualClock::new();
+        let mut state = play_state(&clock);
+        let takes = |effects: Vec<Effect>| {
+            effects
+                .into_iter()
+                .filter(|effect| matches!(effect, Effect::StartTake(_)))
+                .count()
+        };
+
+        state.tape_split = TapeSplit::Transport;
+        assert_eq!(
+            state.command(Command::ToggleTape),
+            vec![Effect::StartTake(None)]
+        );
+
+        // stopping the looper keeps the take going
+        state.start_stop();
+        assert_eq!(takes(state.tick(1)), 0);
+
+        state.start_stop();
+        assert_eq!(takes(state.tick(2)), 1);
+        assert_eq!(takes(state.tick(3)), 0);
+        assert_eq!(state.tape.as_ref().unwrap().takes, 2);
+
+        assert_eq!(state.command(Command::ToggleTape), vec![Effect::StopTape]);
+        assert!(state.tape.is_none());
+    }
+
+    #[test]
+    fn tagged_loops_play_without_a_gap() {
+        let clock = VirtualClock::new();
+        let mut state = play_state(&clock);
+
+        Arc::make_mut(&mut state.sounds)[0].tags.bpm = Some(120.);
+        state.loop_divider = Some(0);
+        state.add_to_loops(SoundId(0), Bus::A);
+        assert!(state.loops[0].seamless);
+
+        // it starts one voice, which is left to wrap round on its own
+        let period = state.loops[0].period;
+        let effects = state.tick(period);
+        let Some(Effect::PlayLoop { voice, length, .. }) = effects.first().cloned() else {
+            panic!("no loop voice");
+        };
+        assert_eq!(length, state.tick * period as u32);
+
+        let effects = state.tick(2 * period);
+        assert!(!effects
+            .iter()
+            .any(|effect| matches!(effect, Effect::PlayLoop { .. })));
+        assert!(sounds(&effects).is_empty());
+
+        // at another tempo, it starts again on its next period
+        state.set_tick(state.tick / 2);
+        let effects = state.tick(3 * period);
+        assert_eq!(effects[0], Effect::StopLoop(voice));
+        assert!(matches!(effects[1], Effect::PlayLoop { .. }));
+
+        // a restarted output stopped it, so it starts again without being
+        // told to stop
+        state.audio_event(audio::Event::OutputRestarted);
+        let effects = state.tick(4 * period);
+        assert!(matches!(effects[0], Effect::PlayLoop { .. }));
+
+        // and it stops when the loops are cleared
+        state.loops.clear();
+        let effects = state.tick(4 * period + 1);
+        assert!(matches!(effects[0], Effect::StopLoop(_)));
+        assert!(state.loop_voices.is_empty());
+    }
+
+    #[test]
+    fn renders_run_in_the_background() {
+        let clock = VirtualClock::new();
+        let mut state = play_state(&clock);
+
+        state.loop_divider = Some(1);
+        state.add_to_loops(SoundId(0), Bus::A);
+        clock.advance(state.tick * 300);
+
+        // the render covers as many bars as a bounce would, from the start
+        // of the stretch of them that is playing
+        let effects = state.command(Command::RenderToPad { x: 1, y: 2 });
+        let Some(Effect::Render { hits, length, .. }) = effects.first() else {
+            panic!("no render");
+        };
+        assert_eq!(*length, state.tick * 960);
+        assert_eq!(hits.len(), 16);
+        assert_eq!(hits[1].at, state.tick * 60);
+        assert!(state.command(Command::ExportStems).is_empty());
+
+        state.audio_event(audio::Event::Render(RenderStatus::Progress(0.5)));
+        assert_eq!(state.render.as_ref().unwrap().progress, 0.5);
+
+        let mut sound = state.sounds[0].clone();
+        sound.id = SoundId(1);
+        state.audio_event(audio::Event::Render(RenderStatus::Bounced(sound)));
+        assert_eq!(state.sound_keys[1][1].binding, Some(SoundId(1)));
+        assert_eq!(ticks_with_sound(&state, 0..2000), vec![0, 960, 1920]);
+        assert!(state.render.is_none());
+
+        // a cancelled render is forgotten straight away
+        state.command(Command::ExportStems);
+        let effects = state.command(Command::CancelRender);
+        assert_eq!(effects, vec![Effect::CancelRender]);
+        assert!(state.render.is_none());
+    }
+
+    #[test]
+    fn recorded_grooves_move_the_quantized_loops() {
+        let clock = VirtualClock::new();
+        let mut state = play_state(&clock);
+        state.sound_keys[0][0].binding = Some(SoundId(0));
+        state.quantize = false;
+
+        // each beat of a bar is played 3 ticks late
+        state.command(Command::RecordGroove);
+        clock.advance(state.tick * 63);
+        tap(&mut state, 0, 1);
+
+        for _ in 0..3 {
+            clock.advance(state.tick * 60);
+            tap(&mut state, 0, 1);
+        }
+
+        state.command(Command::SaveGroove("late".to_owned()));
+        assert!(state.groove_recorder.is_none());
+        assert_eq!(state.grooves[0].offsets[..5], [3, 0, 0, 0, 3]);
+
+        // a quantized loop is moved by the groove while it is on
+        state.quantize = true;
+        state.loop_divider = Some(1);
+        state.launch_on_bar = false;
+        tap(&mut state, 0, 1);
+        assert_eq!(state.loops[0].offset, 240);
+
+        state.command(Command::SetGroove(Some(0)));
+        assert_eq!(ticks_with_sound(&state, 240..400), vec![243, 303, 363]);
+
+        state.command(Command::SetGroove(None));
+        assert_eq!(ticks_with_sound(&state, 240..400), vec![240, 300, 360]);
+    }
+}
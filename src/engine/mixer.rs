@@ -0,0 +1,385 @@
+//! The mixer and FX pages: the EQ kills, the crossfader, the A/B scenes and
+//! the effects that are held on from the pads.
+
+use crate::audio::Bus;
+use crate::eq::Band;
+use crate::fx::Fx;
+use crate::scene::{Morph, Scene, Slot};
+
+use super::{Effect, PlayState, BEATS_PER_BAR, TICKS_PER_BEAT};
+
+/// Cutoffs that the rows of the first column sweep the filter down to in FX
+/// mode.
+const FX_SWEEPS: [u32; 3] = [2_000, 600, 150];
+
+/// Lengths of the beat repeats on the second column in FX mode, in ticks.
+const FX_REPEATS: [usize; 3] = [TICKS_PER_BEAT / 2, TICKS_PER_BEAT / 4, TICKS_PER_BEAT / 8];
+
+/// Times of the delay throws on the third column in FX mode, in ticks.
+const FX_DELAYS: [usize; 3] = [TICKS_PER_BEAT, TICKS_PER_BEAT * 3 / 4, TICKS_PER_BEAT / 2];
+
+/// How long the tape stops on the fourth column in FX mode take, in ticks.
+const FX_STOPS: [usize; 3] = [TICKS_PER_BEAT * 2, TICKS_PER_BEAT, TICKS_PER_BEAT / 2];
+
+/// The band that each row of pads kills in the loop mixer, from the top.
+pub(super) const MIXER_BANDS: [Band; 3] = [Band::High, Band::Mid, Band::Low];
+
+impl PlayState {
+    /// Handles a key being pressed in the loop mixer. F1 leaves the mixer, and
+    /// the rows of pads kill the high, mid and low bands of the bus that their
+    /// column plays on. F2 and F3 switch to scenes A and B, or keep the mix in
+    /// them if they are empty, and with F4 held they morph to them over a bar.
+    pub(super) fn mixer_key(&mut self, x: usize, y: usize) -> Vec<Effect> {
+        match (x, y) {
+            (0, 0) => {
+                self.mixer_mode = false;
+                vec![]
+            }
+            (1 | 2, 0) => {
+                let slot = if x == 1 { Slot::A } else { Slot::B };
+
+                if self.scenes[slot.index()].is_none() {
+                    self.save_scene(slot);
+                    vec![]
+                } else if self.fn_keys[3].pressed {
+                    self.recall_scene(slot, 1)
+                } else {
+                    self.recall_scene(slot, 0)
+                }
+            }
+            (_, 0) => vec![],
+            _ => self.toggle_kill(Bus::of_column(x), MIXER_BANDS[y - 1]),
+        }
+    }
+
+    /// Handles a key being pressed or released in FX mode. F1 leaves FX mode,
+    /// and each pad puts an effect on the master mix while it is held: the
+    /// first column sweeps the filter down, the second repeats the end of the
+    /// mix, the third throws it into a delay and the fourth stops it like a
+    /// tape. The rows go from the gentlest to the strongest.
+    pub(super) fn fx_key(&mut self, x: usize, y: usize, pressed: bool) -> Vec<Effect> {
+        match (x, y, pressed) {
+            (0, 0, true) => {
+                self.fx_mode = false;
+                self.release_fx()
+            }
+            (_, 0, _) => vec![],
+            (_, _, true) => {
+                let mut effects = self.release_fx();
+                self.fx_pad = Some((x, y));
+
+                let row = y - 1;
+                let fx = match x {
+                    0 => {
+                        // the sweep is moved on by the ticks
+                        self.sweep = Some((FX_SWEEPS[row], self.loop_time()));
+                        None
+                    }
+                    1 => Some(Fx::Repeat(self.tick * FX_REPEATS[row] as u32)),
+                    2 => Some(Fx::Delay(self.tick * FX_DELAYS[row] as u32)),
+                    _ => Some(Fx::TapeStop(self.tick * FX_STOPS[row] as u32)),
+                };
+
+                effects.extend(fx.map(|fx| Effect::SetFx(Some(fx))));
+                effects
+            }
+            (_, _, false) if self.fx_pad == Some((x, y)) => self.release_fx(),
+            (_, _, false) => vec![],
+        }
+    }
+
+    /// Takes the effect of the held FX pad off, going back to the dry mix.
+    fn release_fx(&mut self) -> Vec<Effect> {
+        let Some((x, _)) = self.fx_pad.take() else { return vec![]; };
+
+        if x == 0 {
+            self.sweep = None;
+            vec![Effect::SetFilterCutoff(self.filter_cutoff)]
+        } else {
+            vec![Effect::SetFx(None)]
+        }
+    }
+
+    /// Moves a held filter sweep on, from the filter's cutoff to the sweep's
+    /// over a beat.
+    pub(super) fn tick_sweep(&self, tick: usize) -> Option<Effect> {
+        let (to, start) = self.sweep?;
+        let elapsed = tick.checked_sub(start)?;
+
+        if elapsed > TICKS_PER_BEAT {
+            return None;
+        }
+
+        // exponential, so that it sounds even
+        let from = self.filter_cutoff.max(to) as f32;
+        let progress = elapsed as f32 / TICKS_PER_BEAT as f32;
+        let hz = from * (to as f32 / from).powf(progress);
+
+        Some(Effect::SetFilterCutoff(hz.round() as u32))
+    }
+
+    /// The mix and the loops as they are now.
+    pub fn scene(&self) -> Scene {
+        Scene {
+            volume: self.volume,
+            crossfade: self.crossfade,
+            filter_cutoff: self.filter_cutoff,
+            eq: self.eq,
+            chains: self.chains.clone(),
+            loops: self.loops.clone(),
+        }
+    }
+
+    pub fn save_scene(&mut self, slot: Slot) {
+        self.scenes[slot.index()] = Some(self.scene());
+    }
+
+    /// Switches to the scene in `slot`, or starts morphing to it if `bars`
+    /// isn't 0. Nothing happens if the slot is empty.
+    pub fn recall_scene(&mut self, slot: Slot, bars: usize) -> Vec<Effect> {
+        self.morph = None;
+
+        let Some(scene) = self.scenes[slot.index()].clone() else { return vec![]; };
+
+        if bars == 0 {
+            self.loops = scene.loops.clone();
+            return self.set_mix(&scene);
+        }
+
+        self.morph = Some(Morph {
+            from: self.scene(),
+            to: slot,
+            start: self.loop_time(),
+            ticks: bars * BEATS_PER_BAR * TICKS_PER_BEAT,
+            switched: false,
+        });
+
+        vec![]
+    }
+
+    /// Moves the mix along the morph for the given tick. The loops switch to
+    /// the scene's halfway through.
+    pub(super) fn tick_morph(&mut self, tick: usize) -> Vec<Effect> {
+        let Some(mut morph) = self.morph.take() else { return vec![]; };
+        let Some(to) = self.scenes[morph.to.index()].clone() else { return vec![]; };
+
+        let t = morph.progress(tick);
+
+        if t >= 0.5 && !morph.switched {
+            self.loops = to.loops.clone();
+            morph.switched = true;
+        }
+
+        let mut effects = self.set_mix(&morph.from.morph(&to, t));
+
+        if t < 1. {
+            self.morph = Some(morph);
+        } else if self.mixer_mode {
+            // the scene's key stops showing the morph
+            effects.extend(self.keyboard_leds());
+        }
+
+        effects
+    }
+
+    /// Sets the mixer and the effects to those in `scene`, telling the audio
+    /// subsystem about the ones that have changed.
+    fn set_mix(&mut self, scene: &Scene) -> Vec<Effect> {
+        let mut effects = vec![];
+
+        if self.volume != scene.volume {
+            self.volume = scene.volume;
+            effects.push(Effect::SetVolume(self.volume));
+        }
+
+        if self.crossfade != scene.crossfade {
+            self.crossfade = scene.crossfade;
+            effects.push(Effect::SetCrossfade(self.crossfade));
+        }
+
+        if self.filter_cutoff != scene.filter_cutoff {
+            self.filter_cutoff = scene.filter_cutoff;
+            effects.push(Effect::SetFilterCutoff(self.filter_cutoff));
+        }
+
+        for bus in [Bus::A, Bus::B] {
+            if self.eq[bus.index()] != scene.eq[bus.index()] {
+                self.eq[bus.index()] = scene.eq[bus.index()];
+                effects.push(Effect::SetEq(bus, self.eq[bus.index()]));
+            }
+        }
+
+        // sent to the audio subsystem when it is synced
+        self.chains = scene.chains.clone();
+
+        effects
+    }
+
+    /// Kills or brings back a band of a bus's EQ.
+    pub fn toggle_kill(&mut self, bus: Bus, band: Band) -> Vec<Effect> {
+        let kills = &mut self.eq[bus.index()];
+        kills.toggle(band);
+
+        vec![Effect::SetEq(bus, *kills)]
+    }
+
+    /// Enters or leaves FX mode, taking off the effect of a held pad when
+    /// leaving.
+    pub(super) fn show_fx(&mut self, show: bool) -> Vec<Effect> {
+        self.fx_mode = show;
+
+        let mut effects = if show { vec![] } else { self.release_fx() };
+        effects.extend(self.keyboard_leds());
+        effects
+    }
+
+    /// Moves the crossfader, from 0.0 (only bus A) to 1.0 (only bus B).
+    pub fn set_crossfade(&mut self, position: f32) -> Vec<Effect> {
+        self.crossfade = position.clamp(0., 1.);
+        vec![Effect::SetCrossfade(self.crossfade)]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::audio::{Bus, SoundId};
+    use crate::clock::VirtualClock;
+    use crate::driver::adafruit::seesaw::neopixel::Color;
+    use crate::engine::test::{led, play_state, run_to_tick, tap};
+    use crate::engine::{Command, Effect};
+    use crate::eq::{Band, Kills};
+    use crate::fx::Fx;
+
+    #[test]
+    fn pads_play_on_their_side_of_the_crossfader() {
+        let clock = VirtualClock::new();
+        let mut state = play_state(&clock);
+        state.sound_keys[0][1].binding = Some(SoundId(0));
+        state.sound_keys[0][2].binding = Some(SoundId(0));
+
+        assert!(tap(&mut state, 1, 1).contains(&Effect::PlaySound(SoundId(0), Bus::A)));
+        assert!(tap(&mut state, 2, 1).contains(&Effect::PlaySound(SoundId(0), Bus::B)));
+
+        assert_eq!(
+            state.command(Command::SetCrossfade(1.5)),
+            vec![Effect::SetCrossfade(1.)]
+        );
+    }
+
+    #[test]
+    fn mixer_mode_kills_bands() {
+        let clock = VirtualClock::new();
+        let mut state = play_state(&clock);
+
+        state.sound_keys[0][3].binding = Some(SoundId(0));
+        state.command(Command::ShowMixer(true));
+
+        // the top row kills the highs of the bus under it, without playing
+        // the pad
+        let effects = tap(&mut state, 3, 1);
+        let mut kills = Kills::default();
+        kills.toggle(Band::High);
+        assert!(effects.contains(&Effect::SetEq(Bus::B, kills)));
+        assert!(!effects.iter().any(|e| matches!(e, Effect::PlaySound(..))));
+        assert_eq!(led(&effects, 2, 1), Some(Color::BLACK));
+        assert_eq!(state.eq, [Kills::default(), kills]);
+
+        // the bottom row is the lows, and pressing again brings them back
+        tap(&mut state, 0, 3);
+        let effects = tap(&mut state, 1, 3);
+        assert!(effects.contains(&Effect::SetEq(Bus::A, Kills::default())));
+
+        // F1 goes back to the pads, and the kills stay
+        tap(&mut state, 0, 0);
+        assert!(!state.mixer_mode);
+        assert_eq!(state.eq[1], kills);
+    }
+
+    #[test]
+    fn mixer_scenes_switch_and_morph() {
+        let clock = VirtualClock::new();
+        let mut state = play_state(&clock);
+        let mut last_tick = None;
+
+        state.loop_divider = Some(1);
+        state.add_to_loops(SoundId(0), Bus::A);
+        state.command(Command::ShowMixer(true));
+
+        // F2 keeps the mix in scene A, since it is empty
+        tap(&mut state, 1, 0);
+        assert!(state.scenes[0].is_some());
+
+        // the loops are cleared and the lows of bus A are killed, which is
+        // kept in scene B
+        state.loops.clear();
+        state.volume = 0.5;
+        tap(&mut state, 0, 3);
+        let effects = tap(&mut state, 2, 0);
+        assert!(state.scenes[1].is_some());
+        assert_eq!(led(&effects, 2, 0), Some(Color::from_u8(255, 160, 0)));
+
+        // F2 switches straight back to scene A
+        let effects = tap(&mut state, 1, 0);
+        assert!(effects.contains(&Effect::SetEq(Bus::A, Kills::default())));
+        assert!(effects.contains(&Effect::SetVolume(1.)));
+        assert_eq!(state.loops.len(), 1);
+
+        // F4 + F3 morphs to scene B over a bar, switching the kill and the
+        // loops halfway
+        state.key(3, 0, true);
+        tap(&mut state, 2, 0);
+        state.key(3, 0, false);
+
+        run_to_tick(&mut state, &clock, &mut last_tick, 60);
+        assert_eq!(state.volume, 0.875);
+        assert_eq!(state.eq[0], Kills::default());
+        assert_eq!(state.loops.len(), 1);
+
+        run_to_tick(&mut state, &clock, &mut last_tick, 240);
+        assert_eq!(state.volume, 0.5);
+        assert!(state.eq[0].is_killed(Band::Low));
+        assert!(state.loops.is_empty());
+        assert!(state.morph.is_none());
+    }
+
+    #[test]
+    fn fx_pads_hold_effects_on() {
+        let clock = VirtualClock::new();
+        let mut state = play_state(&clock);
+
+        // F2 + F1 = FX mode, which leaves quantization alone
+        state.key(1, 0, true);
+        tap(&mut state, 0, 0);
+        state.key(1, 0, false);
+        assert!(state.fx_mode);
+        assert!(state.quantize);
+
+        // the second column repeats the end of the mix until it is let go
+        let effects = state.key(1, 2, true);
+        let repeat = Fx::Repeat(state.tick * 15);
+        assert!(effects.contains(&Effect::SetFx(Some(repeat))));
+        assert_eq!(state.key(1, 2, false)[0], Effect::SetFx(None));
+
+        // the first column sweeps the filter down over a beat, and puts it
+        // back when it is let go
+        state.key(0, 3, true);
+        let Some(Effect::SetFilterCutoff(hz)) = state.tick_sweep(30) else {
+            panic!("no sweep");
+        };
+        assert!((1_500..2_000).contains(&hz), "{hz}");
+        assert_eq!(state.tick_sweep(60), Some(Effect::SetFilterCutoff(150)));
+        assert_eq!(state.tick_sweep(61), None);
+
+        let effects = state.key(0, 3, false);
+        assert_eq!(
+            effects[0],
+            Effect::SetFilterCutoff(crate::audio::FILTER_CUTOFF_MAX)
+        );
+
+        // leaving FX mode takes a held effect off
+        state.key(3, 1, true);
+        let effects = tap(&mut state, 0, 0);
+        assert!(!state.fx_mode);
+        assert!(effects.contains(&Effect::SetFx(None)));
+    }
+}
@@ -0,0 +1,3464 @@
+//! The app core: the looper state, and the logic that reacts to events from
+//! the keyboard, encoder and audio subsystems. Front-ends render the state and
+//! are notified when it changes.
+
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::spawn;
+use tokio::sync::{watch, Mutex};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+use crate::audio::{Bus, RenderHit, SoundId, SoundInfo};
+use crate::bank::Banks;
+use crate::clock::Clock;
+use crate::config::{AnalogControl, GpioAction, KeyboardConfig, PlayConfig};
+use crate::deck::{Deck, DeckCommand};
+use crate::driver::adafruit::seesaw::neopixel::Color;
+use crate::effect::Chains;
+use crate::eq::{Band, Kills};
+use crate::fx::Fx;
+use crate::game::{self, Game, Score};
+use crate::grid::{self, Grid};
+use crate::groove::{Groove, GrooveRecorder};
+use crate::health::Health;
+use crate::journal::Journal;
+use crate::keymap::{ChordKey, KeyAction, Keymap};
+use crate::latency::{LatencyStats, Summary, TickJitter};
+use crate::pad::{PadSettings, PlaybackMode};
+use crate::palette::Palette;
+use crate::persist::{SavedPad, SavedState};
+use crate::pwm::PwmOutputs;
+use crate::render::RenderTarget;
+use crate::scene::{Morph, Scene, Slot};
+use crate::script::ScriptAction;
+use crate::setlist::{Kit, KitMacro, KitPad, MacroAction, Pattern, SetList};
+use crate::tape::TapeSplit;
+use crate::{audio, bus, encoder, keyboard, slice};
+
+mod decks;
+mod help;
+mod leds;
+mod looper;
+mod mixer;
+mod reassign;
+mod tasks;
+
+use leds::action_color;
+use looper::{next_loop_divider, previous_loop_divider};
+use mixer::MIXER_BANDS;
+use reassign::RECENT_LEN;
+use tasks::{play_animation, process_events, process_loops, Inputs, Outputs};
+
+pub use decks::{DeckAction, DeckState, CUES_PER_DECK};
+pub use help::{KeyHint, HELP_HOLD};
+pub use leds::DEFAULT_PAD_COLOR;
+pub use looper::{
+    Arp, ArpPattern, BeatStrip, Bounce, LoopState, Render, Tape, TempoRamp, ARP_RATES,
+    LOOP_DIVIDERS,
+};
+pub(crate) use looper::{BEATS_PER_BAR, TICKS_PER_BEAT};
+pub use reassign::{
+    LengthFilter, PadEntry, ReassignState, SortMode, VirtualDir, ONE_SHOT_MAX, PAD_PAGE_LEN,
+};
+
+/// Handle to a running engine. Cloning it is cheap.
+#[derive(Clone)]
+pub struct Engine {
+    cmd_tx: flume::Sender<Command>,
+    snapshot: watch::Receiver<Snapshot>,
+    /// kept out of the snapshot, since it changes on every tick
+    jitter: Arc<std::sync::Mutex<TickJitter>>,
+    /// kept out of the snapshot, since it grows with every input
+    journal: Arc<std::sync::Mutex<Journal>>,
+}
+
+/// A copy of the engine's state, published every time it changes so that
+/// front-ends can render it without holding up the engine.
+#[derive(Clone)]
+pub struct Snapshot {
+    pub state: AppState,
+
+    /// The latest fault reported for each subsystem that has failed.
+    pub faults: BTreeMap<Subsystem, Fault>,
+
+    /// How long recent key presses took to be heard.
+    pub latency: LatencyStats,
+
+    /// The most recent problems, oldest first.
+    pub toasts: VecDeque<Toast>,
+
+    /// Level of the master mix.
+    pub level: audio::Level,
+
+    pub diagnostics: Diagnostics,
+
+    /// The latest reading of the Pi's health.
+    pub health: Health,
+}
+
+/// What the subsystems have reported about themselves, for debugging on the
+/// device.
+#[derive(Debug, Clone, Default)]
+pub struct Diagnostics {
+    /// firmware version of the NeoTrellis's Seesaw
+    pub seesaw_version: Option<u32>,
+    /// temperature of the NeoTrellis's Seesaw in °C
+    pub seesaw_temperature: Option<u32>,
+    /// whether the NeoTrellis is too hot. See [`keyboard::HOT_TEMPERATURE`].
+    pub seesaw_hot: bool,
+    /// I2C address of the NeoTrellis's Seesaw
+    pub seesaw_address: Option<u8>,
+    /// settings saved on the NeoTrellis, once they have been read
+    pub device_settings: Option<keyboard::DeviceSettings>,
+    /// name of the audio output device, if one is open
+    pub audio_device: Option<String>,
+    /// number of sounds playing
+    pub voices: usize,
+    /// number of sounds that share their samples with a copy of them
+    pub duplicates: usize,
+    /// frames that the audio output device asks for at a time, once it has
+    /// started
+    pub period_frames: Option<usize>,
+    /// how many times the audio output has run out of samples
+    pub underruns: usize,
+}
+
+/// Requests from a front-end to change the engine's state.
+#[derive(Debug, Clone)]
+pub enum Command {
+    /// Opens a subdirectory in the sound browser.
+    SelectDir(OsString),
+    /// Selects a sound in the sound browser.
+    SelectSound(SoundId),
+    /// The front-end has scrolled the selected sound into view.
+    ScrolledToSelection,
+    /// Goes up a directory in the sound browser.
+    UpDir,
+    /// Opens one of the sound browser's virtual directories.
+    OpenVirtualDir(VirtualDir),
+    /// Stars or unstars a sound.
+    ToggleFavorite(SoundId),
+    /// Switches the sound browser to the next sort mode.
+    CycleSort,
+    /// Switches the sound browser to the next length filter.
+    CycleFilter,
+    /// Searches all sounds in the sound browser by name. An empty query goes
+    /// back to browsing the current directory.
+    Search(String),
+    /// Tries loading the sounds again after loading failed.
+    RetryLoading,
+    /// Presses or releases the key at (x, y), as if it was pressed on the
+    /// keyboard.
+    Key {
+        x: usize,
+        y: usize,
+        pressed: bool,
+    },
+    /// Opens the detail view of the pad at (x, y).
+    ShowPadDetail {
+        x: usize,
+        y: usize,
+    },
+    /// Closes the pad detail view.
+    HidePadDetail,
+    /// Does what an input wired to a spare Seesaw pin would do when pressed.
+    Action(GpioAction),
+    /// Switches between the pads and deck mode.
+    ShowDecks(bool),
+    /// Switches between the pads and the loop mixer.
+    ShowMixer(bool),
+    /// Switches between the pads and FX mode.
+    ShowFx(bool),
+    /// Kills or brings back a band of a bus's EQ.
+    ToggleKill {
+        bus: Bus,
+        band: Band,
+    },
+    /// Moves the crossfader, from 0.0 (only bus A) to 1.0 (only bus B).
+    SetCrossfade(f32),
+    /// Controls one of the decks.
+    Deck {
+        deck: Deck,
+        action: DeckAction,
+    },
+    /// Adds a cue point to the pad at (x, y).
+    AddCue {
+        x: usize,
+        y: usize,
+        position: Duration,
+    },
+    /// Removes one of the cue points of the pad at (x, y).
+    RemoveCue {
+        x: usize,
+        y: usize,
+        cue: usize,
+    },
+    /// Goes to an entry in the set list.
+    GoToSetEntry(usize),
+    /// Sets the custom color of the pad at (x, y), or goes back to coloring
+    /// it by folder.
+    SetPadColor {
+        x: usize,
+        y: usize,
+        color: Option<Color>,
+    },
+    /// Sets the loop divider that the pad at (x, y) adds loops with, or goes
+    /// back to the global one.
+    SetPadLoop {
+        x: usize,
+        y: usize,
+        loop_divider: Option<isize>,
+    },
+    /// Sets the order and speed of the arpeggiator.
+    SetArp {
+        pattern: ArpPattern,
+        every: usize,
+    },
+    /// Turns key sync on or off for the pad at (x, y). See
+    /// [`SoundKeyState::key_sync`].
+    SetKeySync {
+        x: usize,
+        y: usize,
+        on: bool,
+    },
+    /// Sets how the pad at (x, y) plays its sound. See [`PadSettings`].
+    SetPadSettings {
+        x: usize,
+        y: usize,
+        settings: PadSettings,
+    },
+    /// Closes the pad detail view and opens the sound browser to bind another
+    /// sound to the pad at (x, y).
+    ReassignPad {
+        x: usize,
+        y: usize,
+    },
+    /// Cuts the sound on the pad at (x, y) at its transients, found with the
+    /// given sensitivity from 0.0 to 1.0, and puts the slices on that pad and
+    /// the ones after it. See [`crate::slice`].
+    SliceToPads {
+        x: usize,
+        y: usize,
+        sensitivity: f32,
+    },
+    /// Undoes the last edit to what is bound to the pads. See [`PadHistory`].
+    UndoPadEdit,
+    /// Redoes the last pad edit that was undone.
+    RedoPadEdit,
+    /// Bundles what is on the pads, and the sounds that they play, into a kit
+    /// bundle at the given path.
+    ExportKit(PathBuf),
+    /// Imports a kit bundle, adding its sounds and loading its kit.
+    ImportKit(PathBuf),
+    /// Sets the brightness of the keypad LEDs, which is saved on the
+    /// NeoTrellis. See [`keyboard::DeviceSettings`].
+    SetBrightness(u8),
+    /// Sets the set list entry that this unit starts on, which is saved on
+    /// the NeoTrellis, or None to start where it left off.
+    SetStartKit(Option<u8>),
+    /// Moves the NeoTrellis to another I2C address.
+    SetKeyboardAddress(u8),
+    /// Glides the tempo to `bpm` over `bars` bars. See
+    /// [`PlayState::ramp_tempo`].
+    RampTempo {
+        bpm: f32,
+        bars: usize,
+    },
+    /// Keeps the mix and the loops in a scene slot. See [`crate::scene`].
+    SaveScene(Slot),
+    /// Empties a scene slot.
+    ClearScene(Slot),
+    /// Switches to the scene in a slot, morphing to it over `bars` bars if
+    /// there are any.
+    RecallScene {
+        slot: Slot,
+        bars: usize,
+    },
+    /// Renders the loops onto the pad at (x, y) in the background. See
+    /// [`PlayState::start_render`].
+    RenderToPad {
+        x: usize,
+        y: usize,
+    },
+    /// Renders the loops on each bus to a WAV file in the background.
+    ExportStems,
+    /// Gives up on the render that is running.
+    CancelRender,
+    /// Starts or stops taping the master mix to disk. See [`crate::tape`].
+    ToggleTape,
+    SetTapeSplit(TapeSplit),
+    /// Sets how far back pad presses are moved when they are recorded into
+    /// loops. See [`PlayState::latency_compensation`].
+    SetLatencyCompensation(Duration),
+    /// Starts the rhythm trainer with a pattern in [`game::PATTERNS`], for
+    /// `bars` bars. See [`crate::game`].
+    StartGame {
+        pattern: usize,
+        bars: usize,
+    },
+    /// Gives up on the rhythm trainer.
+    StopGame,
+    /// Splits the grid between two players, or puts it back together. See
+    /// [`PlayState::split_mode`].
+    ShowSplit(bool),
+    /// Starts taking note of the timing of the pads that are played, to make
+    /// a groove template from. See [`crate::groove`].
+    RecordGroove,
+    /// Stops recording a groove, and keeps it under a name, in place of one
+    /// that already has it.
+    SaveGroove(String),
+    /// Stops recording a groove without keeping it.
+    DiscardGroove,
+    /// Puts one of the grooves on the quantized loops, or takes it off.
+    SetGroove(Option<usize>),
+    DeleteGroove(usize),
+}
+
+/// A part of the app that runs separately from the engine and can fail on its
+/// own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Subsystem {
+    Keyboard,
+    Encoder,
+    Audio,
+    Footswitches,
+    Trigger,
+    LedStrip,
+}
+
+impl std::fmt::Display for Subsystem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Subsystem::Keyboard => "keyboard",
+            Subsystem::Encoder => "encoder",
+            Subsystem::Audio => "audio",
+            Subsystem::Footswitches => "footswitches",
+            Subsystem::Trigger => "trigger output",
+            Subsystem::LedStrip => "LED strip",
+        })
+    }
+}
+
+/// Reported to the engine by whatever supervises the subsystems, or worked out
+/// by the engine from the subsystems' events.
+#[derive(Debug, Clone)]
+pub enum Fault {
+    /// The subsystem is running, but can't do everything it should.
+    Degraded { subsystem: Subsystem, error: String },
+    /// The subsystem failed and is being restarted.
+    Restarting {
+        subsystem: Subsystem,
+        attempt: usize,
+        error: String,
+    },
+    /// The subsystem failed too many times and won't be restarted.
+    Failed { subsystem: Subsystem, error: String },
+}
+
+/// Something that went wrong without stopping a subsystem, e.g. a sound that
+/// couldn't be decoded. Subsystems send these to the engine so that front-ends
+/// can show them for a moment.
+#[derive(Debug, Clone)]
+pub struct Problem {
+    /// None if the engine itself ran into the problem.
+    pub subsystem: Option<Subsystem>,
+    pub message: String,
+}
+
+/// A [`Problem`], and when the engine received it.
+#[derive(Debug, Clone)]
+pub struct Toast {
+    pub problem: Problem,
+    pub at: Instant,
+}
+
+/// How many of the most recent problems are kept.
+const TOAST_LIMIT: usize = 8;
+
+fn push_toast(toasts: &mut VecDeque<Toast>, problem: Problem) {
+    if toasts.len() == TOAST_LIMIT {
+        toasts.pop_front();
+    }
+
+    toasts.push_back(Toast {
+        problem,
+        at: Instant::now(),
+    });
+}
+
+impl Fault {
+    pub fn subsystem(&self) -> Subsystem {
+        match self {
+            Fault::Degraded { subsystem, .. }
+            | Fault::Restarting { subsystem, .. }
+            | Fault::Failed { subsystem, .. } => *subsystem,
+        }
+    }
+}
+
+impl Engine {
+    /// Starts the engine on the current tokio runtime. It plays the loading
+    /// animation on the keyboard until the audio subsystem has loaded the
+    /// sounds, and then drives the keyboard, encoder and audio from their events.
+    /// It takes its ends of the channels on `bus`, which is dropped after.
+    /// The pads are laid out on the keyboard in `keyboard`, in its palette,
+    /// and its PWM outputs are driven from the engine.
+    pub fn start(
+        ct: CancellationToken,
+        clock: Arc<dyn Clock>,
+        bus: bus::Bus,
+        play_config: PlayConfig,
+        keyboard: &KeyboardConfig,
+        state_path: PathBuf,
+    ) -> Self {
+        let kb_cmd_tx = bus.keyboard.commands.tx();
+
+        let state = AppState::Loading(LoadingState {
+            animation: LoadingAnimation::start(&ct, &kb_cmd_tx),
+            stage: LoadingStage::DiscoveringAudio,
+        });
+
+        let (cmd_tx, cmd_rx) = flume::unbounded();
+        let (snapshot_tx, snapshot_rx) = watch::channel(Snapshot {
+            state: state.clone(),
+            faults: BTreeMap::new(),
+            latency: LatencyStats::default(),
+            toasts: VecDeque::new(),
+            level: audio::Level::default(),
+            diagnostics: Diagnostics::default(),
+            health: Health::default(),
+        });
+
+        let state = Arc::new(Mutex::new(state));
+        let (hook_tx, hook_rx) = flume::unbounded();
+
+        let outputs = Outputs {
+            kb_cmd_tx,
+            grid_size: keyboard.grid_size(),
+            enc_cmd_tx: bus.encoder.commands.tx(),
+            audio_cmd_tx: bus.audio.commands.tx(),
+            trigger_tx: bus.trigger.tx(),
+            light_tx: bus.lighting.tx(),
+            hook_tx: play_config.script.is_some().then_some(hook_tx),
+        };
+
+        let jitter = Arc::new(std::sync::Mutex::new(TickJitter::default()));
+        let journal = Arc::new(std::sync::Mutex::new(Journal::default()));
+
+        spawn(process_loops(
+            state.clone(),
+            clock.clone(),
+            outputs.clone(),
+            jitter.clone(),
+        ));
+
+        spawn(process_events(
+            ct,
+            state,
+            outputs,
+            Inputs {
+                cmd_rx,
+                kb_evt_rx: bus.keyboard.events.rx(),
+                enc_evt_rx: bus.encoder.events.rx(),
+                audio_evt_rx: bus.audio.events.rx(),
+                fault_rx: bus.faults.rx(),
+                problem_rx: bus.problems.rx(),
+                health_rx: bus.health.rx(),
+                hook_rx,
+            },
+            clock,
+            snapshot_tx,
+            play_config,
+            keyboard.palette(),
+            PwmOutputs::new(keyboard.pwm_outputs.clone()),
+            state_path,
+            journal.clone(),
+        ));
+
+        Self {
+            cmd_tx,
+            snapshot: snapshot_rx,
+            jitter,
+            journal,
+        }
+    }
+
+    /// How late the looper has recently been in playing its ticks.
+    pub fn tick_jitter(&self) -> Summary {
+        self.jitter.lock().unwrap().summary()
+    }
+
+    /// A copy of the inputs that have changed the looper's state, which it
+    /// can be rebuilt from.
+    pub fn journal(&self) -> Journal {
+        self.journal.lock().unwrap().clone()
+    }
+
+    /// Returns a receiver for snapshots of the engine's state, which is
+    /// notified every time an event has been processed. Front-ends should
+    /// clone the snapshot out of the receiver instead of holding onto it,
+    /// since the engine can't publish a new one while it is borrowed.
+    pub fn snapshot(&self) -> watch::Receiver<Snapshot> {
+        self.snapshot.clone()
+    }
+
+    pub fn send(&self, cmd: Command) {
+        let _ = self.cmd_tx.send(cmd);
+    }
+}
+
+/// Something from outside the looper that changes its state. Every change
+/// goes through [`PlayState::apply`], apart from the looper's ticks, so that
+/// the changes can be kept in a [`Journal`] and replayed.
+#[derive(Debug, Clone)]
+pub enum Input {
+    Key {
+        x: usize,
+        y: usize,
+        pressed: bool,
+    },
+    Gpio {
+        action: GpioAction,
+        pressed: bool,
+    },
+    Analog {
+        control: AnalogControl,
+        value: f32,
+    },
+    Encoder(encoder::Event),
+    Command(Command),
+    /// A report from the audio subsystem, like a sound being decoded or a
+    /// deck moving on.
+    Audio(audio::Event),
+    /// Something that a script asked for. See [`crate::script`].
+    Script(ScriptAction),
+}
+
+/// State of the whole app, which front-ends render.
+#[derive(Clone)]
+pub enum AppState {
+    Loading(LoadingState),
+    Play(Box<PlayState>),
+}
+
+#[derive(Clone)]
+pub struct LoadingState {
+    animation: LoadingAnimation,
+    pub stage: LoadingStage,
+}
+
+/// The loading animation on the keyboard, which plays until its token is
+/// cancelled, when the sounds have loaded or failed to.
+#[derive(Clone)]
+struct LoadingAnimation {
+    ct: CancellationToken,
+    kb_cmd_tx: flume::Sender<keyboard::Command>,
+}
+
+impl LoadingAnimation {
+    fn start(ct: &CancellationToken, kb_cmd_tx: &flume::Sender<keyboard::Command>) -> Self {
+        play_animation(kb_cmd_tx, "loading");
+
+        Self {
+            ct: ct.child_token(),
+            kb_cmd_tx: kb_cmd_tx.clone(),
+        }
+    }
+
+    /// Stops the animation, unless it was already stopped, so that an
+    /// animation played after it isn't stopped instead.
+    fn cancel(&self) {
+        if self.ct.is_cancelled() {
+            return;
+        }
+
+        self.ct.cancel();
+        let _ = self.kb_cmd_tx.send(keyboard::Command::StopAnimation);
+    }
+}
+
+#[derive(Clone)]
+pub enum LoadingStage {
+    DiscoveringAudio,
+    BufferingAudio {
+        progress: usize,
+        num_files: usize,
+        /// the file that is being decoded
+        current: PathBuf,
+        /// when the first file started decoding
+        started: Instant,
+    },
+    /// The sounds couldn't be loaded from `path`, and won't be until a
+    /// [`Command::RetryLoading`] is sent.
+    Failed {
+        path: PathBuf,
+        error: String,
+    },
+}
+
+#[derive(Clone, Debug)]
+pub struct PlayState {
+    /// shared between snapshots, since it doesn't change after loading
+    pub sounds: Arc<Vec<SoundInfo>>,
+
+    // one row less than the keyboard, b/c top row is reserved for fn keys
+    pub sound_keys: Grid<SoundKeyState>,
+
+    pub fn_keys: [FnKeyState; 4],
+
+    /// what the function keys do
+    pub keymap: Keymap,
+
+    /// colours of the LEDs
+    pub palette: Palette,
+
+    pub reassign: Option<ReassignState>,
+
+    pub quantize: bool,
+
+    /// whether the browser warns about sounds in clashing keys
+    pub warn_key_clashes: bool,
+
+    /// whether loading a kit keeps the current tempo instead of the kit's
+    pub keep_tempo: bool,
+
+    /// whether the sound browser is on the pads while reassigning. See
+    /// [`PlayConfig::pad_browser`].
+    pub pad_browser: bool,
+
+    /// whether pads wait for the next bar while the looper is on. See
+    /// [`PlayConfig::launch_on_bar`].
+    pub launch_on_bar: bool,
+
+    /// when a new sound is added to loops, this will control the period of that
+    /// sound. None means looper is not active. Negative values mean it's a loop
+    /// multiplier instead of a loop divider.
+    pub loop_divider: Option<isize>,
+
+    pub loops: Vec<LoopState>,
+
+    /// counts the beats of the bar on the Fn LEDs while the looper is on
+    pub beat_strip: BeatStrip,
+
+    /// plays the pads that are held down one after another while it is on
+    pub arp: Arp,
+
+    /// whether the looper is playing its loops. It starts again from the top
+    /// of the bar.
+    pub running: bool,
+
+    /// ticks between pulses on the trigger output
+    pub trigger_every: usize,
+
+    /// a recording of the master mix that is waiting for the next bar or
+    /// being made
+    pub bounce: Option<Bounce>,
+
+    /// the glide to another tempo that is under way
+    pub tempo_ramp: Option<TempoRamp>,
+
+    /// a render of the loops that is running in the background
+    pub render: Option<Render>,
+
+    /// the tape of the master mix that is running
+    pub tape: Option<Tape>,
+    /// when the tape starts a new file
+    pub tape_split: TapeSplit,
+    /// the pattern or set list entry that was started last, which takes on
+    /// the tape are named after
+    pub pattern_name: Option<String>,
+
+    /// how many bars a bounce records
+    pub bounce_bars: usize,
+
+    pub beginning: Instant,
+
+    /// how long it takes from a key being pressed to it being heard, which
+    /// is taken off the time that loops are added at so that they don't drag
+    /// behind the beat
+    pub latency_compensation: Duration,
+
+    /// the voices that seamless loops are playing on, by the looper's id for
+    /// them, with the length of a tick when they started
+    pub loop_voices: BTreeMap<u32, Duration>,
+    /// the id of the next voice that a seamless loop starts
+    next_loop_voice: u32,
+    /// how long seamless loops fade out and back in for where they wrap round
+    pub loop_seam: Duration,
+
+    /// how long is one tick? controls bpm
+    pub tick: Duration,
+
+    /// master volume, where 1.0 is unity gain
+    pub volume: f32,
+
+    /// position of the crossfader, from 0.0 (only bus A) to 1.0 (only bus B)
+    pub crossfade: f32,
+
+    /// cutoff frequency of the master low-pass filter in Hz
+    pub filter_cutoff: u32,
+
+    /// whether the push-button on the rotary encoder is held down
+    pub encoder_pressed: bool,
+
+    /// the pad whose detail view is open, as (x, y)
+    pub detail: Option<(usize, usize)>,
+
+    /// whether the help for the keys is showing, which it does once an Fn
+    /// key has been held down for [`HELP_HOLD`], until they are all let go.
+    /// See [`PlayState::key_help`].
+    pub help: bool,
+
+    /// the pad that a sound was last sliced from, if nothing else has been
+    /// edited since
+    pub slicing: Option<Slicing>,
+
+    /// when each sound was last triggered, so front-ends can show which pads
+    /// are playing
+    pub last_played: BTreeMap<SoundId, Instant>,
+
+    /// sounds that have been starred in the sound browser
+    pub favorites: BTreeSet<SoundId>,
+
+    /// audio roots that couldn't be read while loading or have gone away
+    /// since, e.g. a USB stick that was unplugged or a network share that
+    /// hung
+    pub missing_roots: BTreeSet<PathBuf>,
+
+    /// sounds that were recently assigned to a key, most recent first
+    pub recent: VecDeque<SoundId>,
+
+    /// earlier versions of what is bound to the pads, for undo and redo
+    pub pad_history: PadHistory,
+
+    /// whether the keyboard controls the decks instead of the pads
+    pub deck_mode: bool,
+
+    /// the decks keep playing when deck mode is left
+    pub decks: [DeckState; 2],
+
+    /// whether the keyboard is the loop mixer, where each row kills a band of
+    /// the EQ and each pair of columns is a bus
+    pub mixer_mode: bool,
+
+    /// bands of each bus's EQ that are cut out
+    pub eq: [Kills; 2],
+
+    /// effects that each bus goes through, from the kit that was loaded last
+    pub chains: Chains,
+
+    /// the scenes kept in slots A and B
+    pub scenes: [Option<Scene>; 2],
+
+    /// the morph to one of the scenes that is under way
+    pub morph: Option<Morph>,
+
+    /// whether the pads are held down to put effects on the master mix
+    pub fx_mode: bool,
+
+    /// the pad whose effect is on in FX mode, as (x, y)
+    pub fx_pad: Option<(usize, usize)>,
+
+    /// the rhythm trainer that is being played on the pads
+    pub game: Option<Game>,
+
+    /// whether the grid is split between two players. Each half, two
+    /// columns wide, has its own colour, banks of pads and loops, and its
+    /// own two Fn keys.
+    pub split_mode: bool,
+
+    /// the banks of pads of each half of the grid
+    pub banks: Banks<SoundKeyState>,
+
+    /// the loop divider of each half of the grid in split mode, by the bus
+    /// that it plays on. None means its looper is off.
+    pub split_loops: [Option<isize>; 2],
+
+    /// groove templates that have been recorded, which are kept between runs
+    pub grooves: Vec<Groove>,
+
+    /// the groove that the quantized loops are played with, if any
+    pub groove: Option<usize>,
+
+    /// the timing of the pads that are being played into a new groove
+    pub groove_recorder: Option<GrooveRecorder>,
+
+    /// the score of the last game of the rhythm trainer that was played to
+    /// the end
+    pub last_game: Option<Score>,
+
+    /// filter sweep that is held on in FX mode, as the cutoff that it goes
+    /// to and the tick that it started on
+    sweep: Option<(u32, usize)>,
+
+    /// shared between snapshots, since it doesn't change after loading
+    pub set_list: Arc<SetList>,
+
+    /// the set list entry that was gone to last
+    pub set_entry: Option<usize>,
+
+    /// goes up whenever the state changes, so that it is only copied out to
+    /// the front-ends and saved when it has
+    revision: u64,
+
+    clock: Arc<dyn Clock>,
+}
+
+impl PlayState {
+    /// Creates the state of the looper after `sounds` have been loaded, with
+    /// no keys bound, the looper off, quantization on and 60 BPM. The looper
+    /// starts counting ticks from the current time on `clock`.
+    pub fn new(sounds: Vec<SoundInfo>, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            sounds: Arc::new(sounds),
+            sound_keys: Grid::new(grid::NEOTRELLIS.0, grid::NEOTRELLIS.1 - 1),
+            fn_keys: Default::default(),
+            keymap: Keymap::default(),
+            palette: Palette::default(),
+            reassign: None,
+            loop_divider: None,
+            quantize: true,
+            warn_key_clashes: true,
+            keep_tempo: false,
+            pad_browser: false,
+            launch_on_bar: true,
+            beginning: clock.now(),
+            loops: vec![],
+            beat_strip: BeatStrip::default(),
+            arp: Arp::default(),
+            running: true,
+            trigger_every: TICKS_PER_BEAT,
+            bounce: None,
+            tempo_ramp: None,
+            render: None,
+            tape: None,
+            tape_split: TapeSplit::Never,
+            pattern_name: None,
+            bounce_bars: 4,
+            latency_compensation: Duration::ZERO,
+            loop_voices: BTreeMap::new(),
+            next_loop_voice: 0,
+            loop_seam: Duration::ZERO,
+            tick: Duration::from_micros(1_000_000 / 60),
+            volume: 1.,
+            crossfade: 0.5,
+            filter_cutoff: audio::FILTER_CUTOFF_MAX,
+            encoder_pressed: false,
+            detail: None,
+            help: false,
+            slicing: None,
+            last_played: BTreeMap::new(),
+            favorites: BTreeSet::new(),
+            missing_roots: BTreeSet::new(),
+            recent: VecDeque::new(),
+            pad_history: PadHistory::default(),
+            deck_mode: false,
+            decks: [DeckState::new(clock.now()), DeckState::new(clock.now())],
+            mixer_mode: false,
+            eq: [Kills::default(); 2],
+            scenes: [None, None],
+            morph: None,
+            chains: Chains::default(),
+            fx_mode: false,
+            fx_pad: None,
+            game: None,
+            last_game: None,
+            split_mode: false,
+            banks: Banks::default(),
+            split_loops: [None; 2],
+            grooves: vec![],
+            groove: None,
+            groove_recorder: None,
+            sweep: None,
+            set_list: Default::default(),
+            set_entry: None,
+            revision: 0,
+            clock,
+        }
+    }
+
+    /// The same state, with the time read from `clock` instead. Times in the
+    /// state are kept as they are, so `clock` should be at the same time as
+    /// the current one.
+    pub fn with_clock(self, clock: Arc<dyn Clock>) -> Self {
+        Self { clock, ..self }
+    }
+
+    /// The current time on the state's clock.
+    pub fn now(&self) -> Instant {
+        self.clock.now()
+    }
+
+    /// How many times the state has changed. It changes with every input,
+    /// and with the ticks that do something.
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// Changes the state for an input, and returns what should be done
+    /// because of it.
+    pub fn apply(&mut self, input: Input) -> Vec<Effect> {
+        self.revision += 1;
+
+        match input {
+            Input::Key { x, y, pressed } => self.key(x, y, pressed),
+            Input::Gpio { action, pressed } => self.gpio(action, pressed),
+            Input::Analog { control, value } => self.analog(control, value),
+            Input::Encoder(event) => self.encoder(event),
+            Input::Command(cmd) => self.command(cmd),
+            Input::Audio(event) => self.audio_event(event),
+            Input::Script(action) => self.script_action(action),
+        }
+    }
+
+    /// Handles a report from the audio subsystem about the sounds or the
+    /// decks.
+    fn audio_event(&mut self, event: audio::Event) -> Vec<Effect> {
+        match event {
+            audio::Event::LoadingEnd {
+                sounds,
+                missing_roots,
+            } => {
+                // the audio subsystem was restarted, so keep the bindings and
+                // loops
+                self.reload(sounds);
+                self.missing_roots = missing_roots.into_iter().collect();
+                self.keyboard_leds()
+            }
+            audio::Event::Deck { deck, status } => self.deck_status(deck, status),
+            audio::Event::Recorded { sound } => self.bounce_recorded(sound),
+            audio::Event::KitImported { kit, sounds } => self.kit_imported(kit, sounds),
+            audio::Event::SoundReady { sound } => self.sound_ready(sound),
+            audio::Event::Render(status) => self.render_status(status),
+            audio::Event::OutputRestarted => {
+                // they stopped with the output, and are started again when
+                // they are next due
+                self.loop_voices.clear();
+                vec![]
+            }
+            audio::Event::RootAvailability { root, available } => {
+                self.set_root_available(root, available);
+                vec![]
+            }
+            _ => vec![],
+        }
+    }
+
+    /// Restores the parts of the state that are kept between runs. Sounds
+    /// that no longer exist are dropped.
+    pub fn restore(&mut self, saved: &SavedState) {
+        if let Some(bpm) = saved.bpm.filter(|bpm| (20. ..=300.).contains(bpm)) {
+            self.set_tick(Duration::from_secs_f32(1. / bpm));
+        }
+
+        let ids: BTreeMap<_, _> = self.sounds.iter().map(|s| (&s.path, s.id)).collect();
+
+        self.favorites = saved
+            .favorites
+            .iter()
+            .filter_map(|path| ids.get(path).copied())
+            .collect();
+
+        self.recent = saved
+            .recent
+            .iter()
+            .filter_map(|path| ids.get(path).copied())
+            .take(RECENT_LEN)
+            .collect();
+
+        self.chains = saved.chains.clone();
+        self.grooves = saved.grooves.clone();
+
+        for pad in &saved.pads {
+            let [x, y] = pad.pad;
+            let Some(&id) = ids.get(&pad.sound) else {
+                continue;
+            };
+            let folder_color = self.folder_color(id);
+            let Some(key) = self
+                .sound_keys
+                .get_mut(y.wrapping_sub(1))
+                .and_then(|row| row.get_mut(x))
+            else {
+                continue;
+            };
+
+            key.binding = Some(id);
+            key.color = pad.color.map(|[r, g, b]| Color::from_u8(r, g, b));
+            key.folder_color = folder_color;
+            key.loop_divider = pad.loop_divider.filter(|d| LOOP_DIVIDERS.contains(d));
+            key.key_sync = pad.key_sync;
+            key.settings = pad.settings.clamped();
+        }
+    }
+
+    /// Replaces the sounds after the audio subsystem has restarted. Recordings
+    /// don't survive a restart, so the pads and loops that play them are
+    /// cleared.
+    pub fn reload(&mut self, sounds: Vec<SoundInfo>) {
+        let len = sounds.len();
+        let lost = |id: SoundId| id.0 >= len;
+
+        for key in self.sound_keys.iter_mut().flatten() {
+            if key.binding.is_some_and(lost) {
+                key.binding = None;
+                key.cues.clear();
+            }
+        }
+
+        self.loops.retain(|l| !lost(l.sound));
+        self.last_played.retain(|id, _| !lost(*id));
+        self.favorites.retain(|id| !lost(*id));
+        self.recent.retain(|id| !lost(*id));
+        self.bounce = None;
+        self.render = None;
+        // they stopped with the audio subsystem
+        self.loop_voices.clear();
+        self.tape = None;
+        self.sounds = Arc::new(sounds);
+
+        // earlier versions of the pads could have lost sounds on them
+        self.pad_history = PadHistory::default();
+    }
+
+    /// The parts of the state that are kept between runs.
+    pub fn saved(&self) -> SavedState {
+        let path = |id: &SoundId| self.sounds[id.0].path.clone();
+
+        // rounded, so that the state isn't saved again for a rounding error
+        let bpm = (10. / self.tick.as_secs_f32()).round() / 10.;
+
+        let pads = self
+            .pads()
+            .filter_map(|(x, y)| {
+                let key = &self.sound_keys[y - 1][x];
+
+                Some(SavedPad {
+                    pad: [x, y],
+                    sound: path(&key.binding?),
+                    color: key.color.map(|c| [c.r, c.g, c.b]),
+                    loop_divider: key.loop_divider,
+                    key_sync: key.key_sync,
+                    settings: key.settings,
+                })
+            })
+            .collect();
+
+        SavedState {
+            favorites: self.favorites.iter().map(path).collect(),
+            recent: self.recent.iter().map(path).collect(),
+            bpm: Some(bpm),
+            pads,
+            chains: self.chains.clone(),
+            grooves: self.grooves.clone(),
+        }
+    }
+
+    /// Applies the starting BPM and quantization from the config.
+    pub fn configure(&mut self, config: &PlayConfig) {
+        if config.bpm > 0. {
+            self.set_tick(Duration::from_secs_f32(1. / config.bpm));
+        }
+
+        self.quantize = config.quantize;
+        self.warn_key_clashes = config.warn_key_clashes;
+        self.keep_tempo = config.keep_tempo;
+        self.pad_browser = config.pad_browser;
+        self.launch_on_bar = config.launch_on_bar;
+        self.trigger_every = config.trigger_every;
+        self.bounce_bars = config.bounce_bars.max(1);
+
+        if let Some(ms) = config.latency_compensation_ms {
+            self.latency_compensation = Duration::from_millis(ms);
+        }
+
+        self.loop_seam = Duration::from_millis(config.loop_seam_ms);
+        self.tape_split = config.tape_split;
+        self.keymap = Keymap::with_overrides(&config.keymap);
+    }
+
+    /// Triggers a sound on one of the crossfader's buses, noting when it
+    /// started.
+    fn play(&mut self, sound: SoundId, bus: Bus) -> Effect {
+        self.last_played.insert(sound, self.clock.now());
+        Effect::PlaySound(sound, bus)
+    }
+
+    /// Triggers a sound with a pad's settings, or like [`PlayState::play`] if
+    /// they are the defaults. `pad` is given if letting go of the pad, or
+    /// pressing it again, can stop the sound.
+    fn play_with(
+        &mut self,
+        sound: SoundId,
+        bus: Bus,
+        settings: PadSettings,
+        pad: Option<(usize, usize)>,
+    ) -> Effect {
+        if settings == PadSettings::default() {
+            return self.play(sound, bus);
+        }
+
+        self.last_played.insert(sound, self.clock.now());
+
+        Effect::PlayPad {
+            sound,
+            bus,
+            start: settings.trim_start(),
+            settings,
+            pad,
+        }
+    }
+
+    /// Plays `sound`, which is bound to the pad at (x, y), with the pad's
+    /// settings, from `start` if given or else from its trimmed start.
+    fn play_pad(&mut self, x: usize, y: usize, sound: SoundId, start: Option<Duration>) -> Effect {
+        let now = self.clock.now();
+        let bus = Bus::of_column(x);
+        let settings = self.sound_keys[y - 1][x].settings;
+        let start = start.unwrap_or(settings.trim_start());
+
+        if let Some(info) = self.sounds.get(sound.0) {
+            self.sound_keys[y - 1][x].playing_until =
+                Some(now + settings.length_from(info.duration, start));
+        }
+
+        if start.is_zero() {
+            return self.play_with(sound, bus, settings, Some((x, y)));
+        }
+
+        // back-date it so that front-ends show where it is playing from
+        self.last_played
+            .insert(sound, now.checked_sub(start).unwrap_or(now));
+
+        if settings == PadSettings::default() {
+            return Effect::PlaySoundFrom(sound, bus, start);
+        }
+
+        Effect::PlayPad {
+            sound,
+            bus,
+            start,
+            settings,
+            pad: Some((x, y)),
+        }
+    }
+
+    /// Plays the sound bound to the pad at (x, y) from one of its cue points.
+    /// The pad doesn't open its detail view when it is released.
+    fn play_cue(&mut self, x: usize, y: usize, cue: usize) -> Option<Effect> {
+        let key = &mut self.sound_keys[y - 1][x];
+        let (Some(id), Some(&start)) = (key.binding, key.cues.get(cue)) else {
+            return None;
+        };
+        key.pressed_at = None;
+
+        Some(self.play_pad(x, y, id, Some(start)))
+    }
+
+    /// Makes the pads fit a keyboard with `width` columns and `height` rows,
+    /// including the Fn keys, which clears them. Keyboards smaller than the
+    /// NeoTrellis are taken to be as big as it, since the modes other than
+    /// free play are laid out on its keys.
+    pub fn set_grid_size(&mut self, width: usize, height: usize) {
+        let (min_width, min_height) = grid::NEOTRELLIS;
+        self.sound_keys = Grid::new(width.max(min_width), height.max(min_height) - 1);
+        self.banks = Banks::default();
+        self.pad_history = PadHistory::default();
+    }
+
+    /// Positions of the pads on the keyboard, a row at a time from the top.
+    fn pads(&self) -> impl Iterator<Item = (usize, usize)> {
+        self.sound_keys.positions().map(|(x, y)| (x, y + 1))
+    }
+
+    /// Positions of all of the LEDs, counting the top row. The top row can
+    /// be wider than the Fn keys.
+    fn led_positions(&self) -> impl Iterator<Item = (usize, usize)> {
+        (0..self.sound_keys.width())
+            .map(|x| (x, 0))
+            .chain(self.pads())
+    }
+
+    fn is_pad(&self, x: usize, y: usize) -> bool {
+        y > 0 && self.sound_keys.contains(x, y - 1)
+    }
+
+    /// Whether (x, y) is one of the keys, counting the Fn keys.
+    fn is_key(&self, x: usize, y: usize) -> bool {
+        match y {
+            0 => x < self.fn_keys.len(),
+            _ => self.is_pad(x, y),
+        }
+    }
+
+    /// Whether (x, y) is on the part of the keyboard that the modes other
+    /// than free play are laid out on.
+    fn in_layout(x: usize, y: usize) -> bool {
+        let (width, height) = grid::NEOTRELLIS;
+        x < width && y < height
+    }
+
+    /// The bound pad with cue points that is held down, if there is one.
+    fn held_cue_pad(&self) -> Option<(usize, usize)> {
+        self.pads().find(|&(x, y)| {
+            let key = &self.sound_keys[y - 1][x];
+            key.pressed && key.binding.is_some() && !key.cues.is_empty()
+        })
+    }
+
+    /// Adds a cue point to the pad at (x, y), if it has room for one.
+    pub fn add_cue(&mut self, x: usize, y: usize, position: Duration) {
+        let key = &mut self.sound_keys[y - 1][x];
+
+        if key.binding.is_some() && key.cues.len() < CUES_PER_PAD {
+            key.cues.push(position);
+            key.cues.sort();
+        }
+    }
+
+    /// Whether the sound was triggered recently enough that it is still
+    /// playing.
+    pub fn is_playing(&self, sound: SoundId) -> bool {
+        let (Some(at), Some(info)) = (self.last_played.get(&sound), self.sounds.get(sound.0))
+        else {
+            return false;
+        };
+
+        self.clock.now() < *at + info.duration
+    }
+
+    /// What is bound to each of the pads.
+    fn pad_bindings(&self) -> PadBindings {
+        self.sound_keys.map(PadBinding::of)
+    }
+
+    /// Makes an edit to what is bound to the pads, which can be undone if it
+    /// changed anything.
+    fn edit_pads(&mut self, edit: impl FnOnce(&mut Self)) {
+        self.slicing = None;
+        let before = self.pad_bindings();
+        edit(self);
+
+        if self.pad_bindings() != before {
+            self.pad_history.push(before);
+        }
+    }
+
+    /// Puts the pads back to how they were before the last edit. Returns
+    /// false if there is nothing to undo.
+    pub fn undo_pad_edit(&mut self) -> bool {
+        self.slicing = None;
+        let current = self.pad_bindings();
+        let Some(pads) = self.pad_history.undo(current) else { return false; };
+        self.rebind_pads(pads);
+        true
+    }
+
+    /// Makes the last edit that was undone again. Returns false if there is
+    /// nothing to redo.
+    pub fn redo_pad_edit(&mut self) -> bool {
+        self.slicing = None;
+        let current = self.pad_bindings();
+        let Some(pads) = self.pad_history.redo(current) else { return false; };
+        self.rebind_pads(pads);
+        true
+    }
+
+    /// Puts the slices of the sound on the pad at (x, y) on that pad and the
+    /// ones after it, across the rows, trimmed to each slice but otherwise
+    /// played like the pad. Pads after the last slice that play the sound,
+    /// like the slices from a higher sensitivity, are cleared. Slicing the
+    /// same pad again straight after is part of the same edit, so that moving
+    /// the sensitivity around can be undone in one go.
+    fn slice_to_pads(&mut self, x: usize, y: usize, sensitivity: f32) {
+        let source = &self.sound_keys[y - 1][x];
+        let Some(id) = source.binding else { return; };
+        let (color, settings) = (source.color, source.settings);
+
+        let first = (y - 1) * self.sound_keys.width() + x;
+        let sound = &self.sounds[id.0];
+        let duration = sound.duration;
+        let slices = slice::slices(
+            &sound.peaks,
+            duration,
+            sensitivity,
+            (self.sound_keys.cells().len() - first).min(slice::MAX_SLICES),
+        );
+        let folder_color = self.folder_color(id);
+
+        let slice = |state: &mut Self| {
+            let keys = state.sound_keys.cells_mut().iter_mut().skip(first);
+
+            for (n, key) in keys.enumerate() {
+                let Some(&(start, end)) = slices.get(n) else {
+                    if key.binding == Some(id) {
+                        key.binding = None;
+                        key.color = None;
+                        key.folder_color = None;
+                        key.cues.clear();
+                        key.settings = PadSettings::default();
+                    }
+
+                    continue;
+                };
+
+                if key.binding != Some(id) {
+                    key.binding = Some(id);
+                    key.folder_color = folder_color;
+                    key.cues.clear();
+                    key.macro_binding = None;
+                }
+
+                key.color = color;
+                key.settings = PadSettings {
+                    trim_start_ms: start.as_millis() as u32,
+                    trim_end_ms: duration.saturating_sub(end).as_millis() as u32,
+                    ..settings
+                };
+            }
+        };
+
+        if self.slicing.map(|slicing| slicing.pad) == Some((x, y)) {
+            slice(self);
+        } else {
+            self.edit_pads(slice);
+        }
+
+        self.slicing = Some(Slicing {
+            pad: (x, y),
+            sensitivity,
+        });
+    }
+
+    fn rebind_pads(&mut self, pads: PadBindings) {
+        let keys = self.sound_keys.cells_mut().iter_mut();
+
+        for (key, binding) in keys.zip(pads.into_cells()) {
+            binding.apply(key);
+        }
+    }
+
+    /// The directory in the sound browser that all of the sounds are in. It
+    /// is empty if they are in more than one audio root.
+    fn sounds_dir(&self) -> PathBuf {
+        self.sounds
+            .iter()
+            .map(|s| s.library_path())
+            .fold(None, |acc, next| {
+                Some(match acc {
+                    Some(acc) => crate::util::path_intersection(acc, next),
+                    None => next,
+                })
+            })
+            .unwrap_or(PathBuf::new())
+    }
+
+    /// The loop divider that the pad at (x, y) adds loops with: its own if it
+    /// has one, or else the global one. None if the looper is off.
+    fn pad_divider(&self, x: usize, y: usize) -> Option<isize> {
+        let global = match self.split_mode {
+            true => self.split_loops[Bus::of_column(x).index()]?,
+            false => self.loop_divider?,
+        };
+        Some(self.sound_keys[y - 1][x].loop_divider.unwrap_or(global))
+    }
+
+    /// Goes to the entry after the current one in the set list, if there is
+    /// one.
+    pub fn next_set_entry(&mut self) {
+        let next = self.set_entry.map_or(0, |i| i + 1);
+
+        if next < self.set_list.entries.len() {
+            self.go_to_set_entry(next);
+        }
+    }
+
+    /// Loads an entry's kit, starts its pattern in place of the loops, and
+    /// sets its BPM.
+    pub fn go_to_set_entry(&mut self, index: usize) {
+        let set_list = self.set_list.clone();
+        let Some(entry) = set_list.entries.get(index) else { return; };
+        let (kit, pattern) = set_list.resolve(entry);
+
+        info!("going to set list entry {index}: {}", entry.name);
+        self.set_entry = Some(index);
+        self.pattern_name = Some(entry.name.clone());
+        self.split_tape(TapeSplit::Pattern);
+
+        if let Some(kit) = kit {
+            self.edit_pads(|state| state.load_kit(kit));
+        }
+
+        self.loops.clear();
+
+        if let Some(pattern) = pattern {
+            self.start_pattern(pattern);
+        }
+
+        if entry.bpm > 0. {
+            self.set_tick(Duration::from_secs_f32(1. / entry.bpm));
+        }
+    }
+
+    /// Binds the kit's sounds to its pads, and unbinds the other pads. Sounds
+    /// that aren't loaded are left out. Switches to the kit's tempo, unless
+    /// the current one is being kept.
+    fn load_kit(&mut self, kit: &Kit) {
+        match kit.bpm {
+            Some(bpm) if bpm > 0. && !self.keep_tempo => {
+                self.set_tick(Duration::from_secs_f32(1. / bpm));
+            }
+            _ => {}
+        }
+
+        self.chains = kit.chains.clone();
+
+        for key in self.sound_keys.iter_mut().flatten() {
+            key.binding = None;
+            key.macro_binding = None;
+            key.color = None;
+            key.folder_color = None;
+            key.cues.clear();
+        }
+
+        let folder_colors: Vec<_> = self
+            .sounds
+            .iter()
+            .map(|s| self.folder_color(s.id))
+            .collect();
+
+        for pad in &kit.pads {
+            let [x, y] = pad.pad;
+            let Some(key) = self
+                .sound_keys
+                .get_mut(y.wrapping_sub(1))
+                .and_then(|row| row.get_mut(x))
+            else {
+                warn!("kit has a sound on pad ({x}, {y}), which doesn't exist");
+                continue;
+            };
+
+            let Some(sound) = self.sounds.iter().find(|s| s.path.ends_with(&pad.sound)) else {
+                warn!("kit has sound {:?}, which isn't loaded", pad.sound);
+                continue;
+            };
+
+            key.binding = Some(sound.id);
+            key.color = pad.color.map(|[r, g, b]| Color::from_u8(r, g, b));
+            key.folder_color = folder_colors[sound.id.0];
+        }
+
+        for m in &kit.macros {
+            let [x, y] = m.pad;
+            let Some(key) = self
+                .sound_keys
+                .get_mut(y.wrapping_sub(1))
+                .and_then(|row| row.get_mut(x))
+            else {
+                warn!(
+                    "kit has macro {:?} on pad ({x}, {y}), which doesn't exist",
+                    m.name
+                );
+                continue;
+            };
+
+            let mut steps = vec![];
+
+            for action in &m.actions {
+                steps.push(match action {
+                    MacroAction::Play(path) => {
+                        let Some(sound) = self.sounds.iter().find(|s| s.path.ends_with(path))
+                        else {
+                            warn!(
+                                "macro {:?} plays sound {path:?}, which isn't loaded",
+                                m.name
+                            );
+                            continue;
+                        };
+
+                        MacroStep::Play(sound.id)
+                    }
+                    MacroAction::Bpm(bpm) => MacroStep::SetBpm(*bpm),
+                    MacroAction::ToggleQuantize => MacroStep::ToggleQuantize,
+                    // checked when the kit was loaded
+                    MacroAction::Pattern(name) => match kit.pattern(name) {
+                        Some(pattern) => MacroStep::StartPattern(pattern.clone()),
+                        None => continue,
+                    },
+                });
+            }
+
+            key.binding = None;
+            key.macro_binding = Some(Macro {
+                name: m.name.clone(),
+                steps,
+            });
+            key.color = m.color.map(|[r, g, b]| Color::from_u8(r, g, b));
+        }
+    }
+
+    /// What is on the pads as a kit, with the tempo, and the sounds that it
+    /// plays, as the file that each is read from and the path that the kit
+    /// refers to it by. Sounds are referred to by their path in the sound
+    /// browser, so that sounds from different roots don't clash.
+    pub fn export_kit(&self) -> (Kit, Vec<(PathBuf, PathBuf)>) {
+        let mut kit = Kit {
+            bpm: Some((10. / self.tick.as_secs_f32()).round() / 10.),
+            chains: self.chains.clone(),
+            ..Kit::default()
+        };
+        let mut sounds: Vec<(PathBuf, PathBuf)> = vec![];
+
+        let mut add_sound = |id: SoundId| {
+            let sound = &self.sounds[id.0];
+            let name = sound.library_path();
+
+            if !sounds.iter().any(|(path, _)| *path == sound.path) {
+                sounds.push((sound.path.clone(), name.clone()));
+            }
+
+            name
+        };
+
+        for (row, keys) in self.sound_keys.iter().enumerate() {
+            for (x, key) in keys.iter().enumerate() {
+                let pad = [x, row + 1];
+                let color = key.color.map(|c| [c.r, c.g, c.b]);
+
+                if let Some(m) = &key.macro_binding {
+                    let mut actions = vec![];
+
+                    for step in &m.steps {
+                        actions.push(match step {
+                            MacroStep::Play(id) => MacroAction::Play(add_sound(*id)),
+                            MacroStep::SetBpm(bpm) => MacroAction::Bpm(*bpm),
+                            MacroStep::ToggleQuantize => MacroAction::ToggleQuantize,
+                            MacroStep::StartPattern(pattern) => {
+                                if kit.pattern(&pattern.name).is_none() {
+                                    kit.patterns.push(pattern.clone());
+                                }
+
+                                MacroAction::Pattern(pattern.name.clone())
+                            }
+                        });
+                    }
+
+                    kit.macros.push(KitMacro {
+                        pad,
+                        name: m.name.clone(),
+                        actions,
+                        color,
+                    });
+                } else if let Some(id) = key.binding {
+                    kit.pads.push(KitPad {
+                        pad,
+                        sound: add_sound(id),
+                        color,
+                    });
+                }
+            }
+        }
+
+        (kit, sounds)
+    }
+
+    /// Adds the sounds of a kit that was imported, and loads the kit.
+    pub fn kit_imported(&mut self, kit: Kit, sounds: Vec<SoundInfo>) -> Vec<Effect> {
+        Arc::make_mut(&mut self.sounds).extend(sounds);
+        self.edit_pads(|state| state.load_kit(&kit));
+        self.keyboard_leds()
+    }
+
+    /// Carries out something that a script asked for. Sounds are played like
+    /// the pads play them, but without pressing the pads.
+    fn script_action(&mut self, action: ScriptAction) -> Vec<Effect> {
+        match action {
+            ScriptAction::Play { x, y } => {
+                let key = &self.sound_keys[y - 1][x];
+                let Some(id) = key.binding else { return vec![]; };
+                let settings = key.settings;
+                vec![self.play_with(id, Bus::of_column(x), settings, None)]
+            }
+            ScriptAction::PlaySound(path) => {
+                let sound = self.sounds.iter().find(|s| s.path.ends_with(&path));
+                let Some(id) = sound.map(|s| s.id) else {
+                    warn!("script played {path:?}, which isn't loaded");
+                    return vec![];
+                };
+                vec![self.play(id, Bus::A)]
+            }
+            ScriptAction::SetLed { x, y, color } => vec![Effect::SetLed { x, y, color }],
+            ScriptAction::SetBpm(bpm) if (20. ..=300.).contains(&bpm) => {
+                self.set_tick(Duration::from_secs_f32(1. / bpm));
+                vec![]
+            }
+            ScriptAction::SetBpm(bpm) => {
+                warn!("script set the tempo to {bpm} bpm, which is out of range");
+                vec![]
+            }
+            ScriptAction::RampBpm { bpm, bars } => {
+                self.ramp_tempo(bpm, bars);
+                vec![]
+            }
+        }
+    }
+
+    /// Runs a macro pad's steps in order. They are carried out in one state
+    /// transition, so nothing else can happen in between them.
+    fn run_macro(&mut self, x: usize, m: &Macro) -> Vec<Effect> {
+        let mut effects = vec![];
+
+        for step in &m.steps {
+            match step {
+                MacroStep::Play(id) => effects.push(self.play(*id, Bus::of_column(x))),
+                MacroStep::SetBpm(bpm) if *bpm > 0. => {
+                    self.set_tick(Duration::from_secs_f32(1. / bpm));
+                }
+                MacroStep::SetBpm(_) => {}
+                MacroStep::ToggleQuantize => self.cycle_quantize(),
+                MacroStep::StartPattern(pattern) => {
+                    self.loops.clear();
+                    self.start_pattern(pattern);
+                }
+            }
+        }
+
+        effects
+    }
+
+    /// Adds a pattern's loops, on the looper's grid like quantized loops are.
+    fn start_pattern(&mut self, pattern: &Pattern) {
+        self.pattern_name = Some(pattern.name.clone());
+        self.split_tape(TapeSplit::Pattern);
+
+        for l in &pattern.loops {
+            let [x, y] = l.pad;
+            let binding = self
+                .sound_keys
+                .get(y.wrapping_sub(1))
+                .and_then(|row| row.get(x))
+                .and_then(|key| key.binding);
+
+            let Some(sound) = binding else {
+                warn!(
+                    "pattern {:?} loops pad ({x}, {y}), which has no sound",
+                    pattern.name
+                );
+                continue;
+            };
+
+            let period = (l.every * TICKS_PER_BEAT as f32).round() as usize;
+
+            if period == 0 {
+                continue;
+            }
+
+            self.loops.push(LoopState {
+                offset: (l.offset * TICKS_PER_BEAT as f32).round() as isize,
+                period,
+                sound,
+                bus: Bus::of_column(x),
+                settings: PadSettings::default(),
+                seamless: false,
+                voice: None,
+                quantized: true,
+            });
+        }
+    }
+
+    /// Whether the sound can't be played because its audio root went away
+    /// before it was decoded.
+    pub fn is_unavailable(&self, sound: SoundId) -> bool {
+        self.sounds
+            .get(sound.0)
+            .is_some_and(|s| !s.ready && self.missing_roots.contains(&s.root))
+    }
+
+    pub fn set_root_available(&mut self, root: PathBuf, available: bool) {
+        if available {
+            self.missing_roots.remove(&root);
+        } else {
+            self.missing_roots.insert(root);
+        }
+    }
+
+    /// Replaces the info of a sound that has been decoded in the background,
+    /// which can now be played, and lights up the pads that it is on.
+    pub fn sound_ready(&mut self, sound: SoundInfo) -> Vec<Effect> {
+        let id = sound.id;
+        let Some(info) = Arc::make_mut(&mut self.sounds).get_mut(id.0) else { return vec![]; };
+        *info = sound;
+
+        if self
+            .sound_keys
+            .iter()
+            .flatten()
+            .any(|key| key.binding == Some(id))
+        {
+            self.keyboard_leds()
+        } else {
+            vec![]
+        }
+    }
+
+    /// Changes the master volume by `delta`, where 1.0 is unity gain.
+    pub fn volume_nudge(&mut self, delta: f32) {
+        self.volume = (self.volume + delta).clamp(0., 2.);
+    }
+
+    /// Enters or leaves split mode. The halves of the grid start with the
+    /// loop mode of the whole grid.
+    pub fn show_split(&mut self, show: bool) {
+        if show && !self.split_mode {
+            self.split_loops = [self.loop_divider; 2];
+        }
+
+        self.split_mode = show;
+    }
+
+    /// Handles a function key being pressed in split mode, where F1 and F2
+    /// are the left half's and F3 and F4 the right half's. The outer key
+    /// switches to the half's next loop mode, and the inner one clears its
+    /// loops, or goes to its next bank while the outer one is held. Both
+    /// outer keys together leave split mode.
+    fn split_fn_key(&mut self, x: usize) -> Vec<Effect> {
+        let side = Bus::of_column(x);
+        let outer = match side {
+            Bus::A => 0,
+            Bus::B => 3,
+        };
+
+        // the outer key that is held switched the loop mode of its half
+        // when it was pressed, which is undone
+        let undo_outer = |state: &mut Self, x: usize| {
+            if state.fn_keys[x].fired.take().is_some() {
+                state.cycle_split_loops_back(Bus::of_column(x));
+            }
+        };
+
+        if x == outer && self.fn_keys[3 - outer].pressed {
+            undo_outer(self, 3 - outer);
+            self.split_mode = false;
+        } else if x == outer {
+            let divider = &mut self.split_loops[side.index()];
+            *divider = next_loop_divider(*divider);
+            self.fn_keys[x].fired = Some(KeyAction::CycleLoopMode);
+        } else if self.fn_keys[outer].pressed {
+            undo_outer(self, outer);
+            self.next_bank(side);
+            return vec![Effect::PlayAnimation("bank".to_owned())];
+        } else {
+            self.clear_split_loops(side);
+        }
+
+        vec![]
+    }
+
+    fn cycle_split_loops_back(&mut self, side: Bus) {
+        let divider = &mut self.split_loops[side.index()];
+        *divider = previous_loop_divider(*divider);
+    }
+
+    /// Clears the loops of one half of the grid in split mode, and turns its
+    /// looper off. The other half's loops keep playing.
+    fn clear_split_loops(&mut self, side: Bus) {
+        if self.split_loops[side.index()].is_some() {
+            self.loops.retain(|l| l.bus != side);
+            self.split_loops[side.index()] = None;
+        }
+    }
+
+    /// Switches one half of the grid to its next bank. Edits to the pads
+    /// before it can't be undone, since they were made to another bank.
+    fn next_bank(&mut self, side: Bus) {
+        self.banks.next(side, &mut self.sound_keys);
+        self.pad_history = PadHistory::default();
+        self.slicing = None;
+        self.detail = None;
+    }
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct FnKeyState {
+    pub pressed: bool,
+    /// when the key was last pressed, if it is held down
+    pub pressed_at: Option<Instant>,
+    /// what the key did when it was pressed, while it is held, so that a
+    /// chord that it starts can undo it
+    pub fired: Option<KeyAction>,
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct SoundKeyState {
+    pub binding: Option<SoundId>,
+    pub pressed: bool,
+    /// when the key was last pressed, if it is held down
+    pub pressed_at: Option<Instant>,
+    /// custom color of the pad, used on its LED and on screen when it is bound
+    pub color: Option<Color>,
+    /// color of the top-level folder that the bound sound is in, which is used
+    /// if the pad doesn't have a custom color
+    pub folder_color: Option<Color>,
+    /// positions in the bound sound that can be jumped to, in order, at most
+    /// [`CUES_PER_PAD`] of them
+    pub cues: Vec<Duration>,
+    /// what the key does instead of playing a sound, if it is a macro pad
+    pub macro_binding: Option<Macro>,
+    /// whether a press waits for the next quantize boundary before playing,
+    /// so that the sound starts on the beat even if the pad is pressed early
+    pub key_sync: bool,
+    /// the tick that the pad plays on, if it has key sync and is waiting
+    pub armed_at: Option<usize>,
+    /// loop divider that the pad adds loops with instead of
+    /// [`PlayState::loop_divider`] while the looper is on
+    pub loop_divider: Option<isize>,
+    /// how the pad plays its sound
+    pub settings: PadSettings,
+    /// when the sound that the pad last played ends, so that a toggle pad
+    /// knows whether pressing it stops the sound or plays it again
+    pub playing_until: Option<Instant>,
+}
+
+/// What is bound to a pad, and its settings: everything about it that an
+/// edit can be undone back to.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PadBinding {
+    binding: Option<SoundId>,
+    color: Option<Color>,
+    folder_color: Option<Color>,
+    cues: Vec<Duration>,
+    macro_binding: Option<Macro>,
+    key_sync: bool,
+    loop_divider: Option<isize>,
+    settings: PadSettings,
+}
+
+impl PadBinding {
+    fn of(key: &SoundKeyState) -> Self {
+        Self {
+            binding: key.binding,
+            color: key.color,
+            folder_color: key.folder_color,
+            cues: key.cues.clone(),
+            macro_binding: key.macro_binding.clone(),
+            key_sync: key.key_sync,
+            loop_divider: key.loop_divider,
+            settings: key.settings,
+        }
+    }
+
+    /// Binds `key` to this, leaving whether it is held down alone.
+    fn apply(self, key: &mut SoundKeyState) {
+        key.binding = self.binding;
+        key.color = self.color;
+        key.folder_color = self.folder_color;
+        key.cues = self.cues;
+        key.macro_binding = self.macro_binding;
+        key.key_sync = self.key_sync;
+        key.loop_divider = self.loop_divider;
+        key.settings = self.settings;
+        key.armed_at = None;
+    }
+}
+
+type PadBindings = Grid<PadBinding>;
+
+/// How many pad edits can be undone.
+pub const UNDO_DEPTH: usize = 32;
+
+/// A sound being cut up onto the pads. See [`Command::SliceToPads`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Slicing {
+    /// the pad that the sound is on, which the first slice goes on
+    pub pad: (usize, usize),
+    pub sensitivity: f32,
+}
+
+/// Earlier versions of what is bound to the pads, so that reassigning a pad,
+/// changing its color or cues, or loading a kit can be undone, and redone
+/// again. The loops aren't part of it.
+#[derive(Clone, Debug, Default)]
+pub struct PadHistory {
+    undo: Vec<PadBindings>,
+    redo: Vec<PadBindings>,
+}
+
+impl PadHistory {
+    /// Remembers how the pads were before an edit. The edits that were undone
+    /// can't be redone after it.
+    fn push(&mut self, before: PadBindings) {
+        if self.undo.len() == UNDO_DEPTH {
+            self.undo.remove(0);
+        }
+
+        self.undo.push(before);
+        self.redo.clear();
+    }
+
+    /// How the pads were before the last edit, if there was one. `current` is
+    /// kept so that it can be redone.
+    fn undo(&mut self, current: PadBindings) -> Option<PadBindings> {
+        let pads = self.undo.pop()?;
+        self.redo.push(current);
+        Some(pads)
+    }
+
+    fn redo(&mut self, current: PadBindings) -> Option<PadBindings> {
+        let pads = self.redo.pop()?;
+        self.undo.push(current);
+        Some(pads)
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+}
+
+/// A macro pad from a kit, with its sounds looked up.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Macro {
+    pub name: String,
+    pub steps: Vec<MacroStep>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum MacroStep {
+    Play(SoundId),
+    SetBpm(f32),
+    ToggleQuantize,
+    /// Replaces the loops with a pattern's.
+    StartPattern(Pattern),
+}
+
+/// How many cue points a pad can have, one for each function key.
+pub const CUES_PER_PAD: usize = 4;
+
+/// Number that the audio subsystem knows the pad at (x, y) by, which doesn't
+/// depend on how wide the grid is. See [`audio::Command::Play`].
+fn pad_number((x, y): (usize, usize)) -> u8 {
+    ((y - 1) * 16 + x) as u8
+}
+
+/// How long a pad has to be held to open its detail view.
+pub const LONG_PRESS: Duration = Duration::from_millis(600);
+
+/// A change to the NeoTrellis's saved settings. See
+/// [`keyboard::DeviceSettings`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceChange {
+    Brightness(u8),
+    Kit(Option<u8>),
+    Address(u8),
+}
+
+/// A side effect of a state transition. Transitions on [`PlayState`] only
+/// change the state and return their effects, which the engine then carries
+/// out, so that they can be tested without any hardware.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Effect {
+    PlaySound(SoundId, Bus),
+    /// Plays a sound from a position other than its start.
+    PlaySoundFrom(SoundId, Bus, Duration),
+    /// Plays a sound with a pad's settings. `pad` is set if letting go of the
+    /// pad, or pressing it again, can stop it.
+    PlayPad {
+        sound: SoundId,
+        bus: Bus,
+        start: Duration,
+        settings: PadSettings,
+        pad: Option<(usize, usize)>,
+    },
+    /// Stops the sounds that the pad at (x, y) played.
+    ReleasePad(usize, usize),
+    /// Plays the sound of a seamless loop, wrapping round every `length`. See
+    /// [`LoopState::seamless`].
+    PlayLoop {
+        sound: SoundId,
+        bus: Bus,
+        settings: PadSettings,
+        voice: u32,
+        length: Duration,
+        seam: Duration,
+    },
+    StopLoop(u32),
+    SetVolume(f32),
+    /// Moves the crossfader, from 0.0 (only bus A) to 1.0 (only bus B).
+    SetCrossfade(f32),
+    SetFilterCutoff(u32),
+    /// Plays one of the animations over the LEDs. See [`crate::animation`].
+    PlayAnimation(String),
+    /// Sets the LED under a key to a solid colour.
+    SetLed {
+        x: usize,
+        y: usize,
+        color: Color,
+    },
+    SetEncoderColor(Color),
+    Deck {
+        deck: Deck,
+        command: DeckCommand,
+    },
+    /// Pulses the trigger output.
+    TriggerPulse,
+    /// The looper reached a beat, counting from 0 at the start of the bar.
+    Beat(usize),
+    /// Records the master mix for the given time.
+    Record(Duration),
+    /// Cuts out bands of a bus's EQ.
+    SetEq(Bus, Kills),
+    /// Puts an effect on the master mix, or takes it off.
+    SetFx(Option<Fx>),
+    /// Writes a kit bundle. See [`crate::bundle::export`].
+    ExportKit {
+        path: PathBuf,
+        kit: Kit,
+        sounds: Vec<(PathBuf, PathBuf)>,
+    },
+    /// Imports a kit bundle. See [`crate::bundle::import`].
+    ImportKit(PathBuf),
+    /// Changes the settings saved on the NeoTrellis, or its address. These
+    /// aren't kept in the state.
+    SetDevice(DeviceChange),
+    /// Renders sounds in the background. See [`crate::render`].
+    Render {
+        hits: Vec<RenderHit>,
+        length: Duration,
+        target: RenderTarget,
+    },
+    CancelRender,
+    /// Starts taping the master mix to a new file, named after the pattern.
+    StartTake(Option<String>),
+    StopTape,
+    /// A loop played the sound with this path in its audio root. Only
+    /// scripts are told about it.
+    LoopTriggered(String),
+}
+
+impl PlayState {
+    /// Handles a key on the grid being pressed or released. The top row
+    /// (`y == 0`) is the function keys.
+    pub fn key(&mut self, x: usize, y: usize, pressed: bool) -> Vec<Effect> {
+        if !self.is_key(x, y) {
+            return vec![];
+        }
+
+        let mut effects = vec![];
+
+        // how long the key was held for, if it was just released
+        let mut held = None;
+
+        if y == 0 {
+            self.fn_keys[x].pressed = pressed;
+            self.fn_keys[x].pressed_at = pressed.then(|| self.clock.now());
+            self.fn_keys[x].fired = None;
+
+            if !self.fn_keys.iter().any(|k| k.pressed) {
+                self.help = false;
+            }
+        } else {
+            let key = &mut self.sound_keys[y - 1][x];
+            let now = self.clock.now();
+            key.pressed = pressed;
+
+            if pressed {
+                key.pressed_at = Some(now);
+            } else {
+                held = key
+                    .pressed_at
+                    .take()
+                    .map(|at| now.saturating_duration_since(at));
+            }
+        }
+
+        // the rest of a bigger keyboard does nothing in these modes
+        let in_layout = Self::in_layout(x, y);
+
+        if self.deck_mode {
+            if pressed && in_layout {
+                effects.extend(self.deck_key(x, y));
+            }
+
+            effects.extend(self.keyboard_leds());
+            return effects;
+        }
+
+        if self.mixer_mode {
+            if pressed && in_layout {
+                effects.extend(self.mixer_key(x, y));
+            }
+
+            effects.extend(self.keyboard_leds());
+            return effects;
+        }
+
+        if self.fx_mode {
+            if in_layout {
+                effects.extend(self.fx_key(x, y, pressed));
+            }
+
+            effects.extend(self.keyboard_leds());
+            return effects;
+        }
+
+        if self.game.is_some() {
+            effects.extend(self.game_key(x, y, pressed));
+            effects.extend(self.keyboard_leds());
+            return effects;
+        }
+
+        if self.reassign.is_some() {
+            if pressed {
+                if y == 0 {
+                    match x {
+                        // F1 = exit
+                        0 => self.reassign_sound_quit(),
+                        // F2 = up one dir
+                        1 => self.reassign_sound_up(),
+                        // F3 = next page in the pad browser
+                        2 if self.pad_browser => {
+                            if let Some(reassign) = &mut self.reassign {
+                                reassign.next_pad_page();
+                            }
+                        }
+                        // F3 = next sort mode
+                        2 => self.reassign_sound_cycle_sort(),
+                        // F4 = select & exit
+                        3 => self.reassign_sound_save(),
+                        _ => unreachable!(),
+                    }
+                } else if self.pad_browser && in_layout {
+                    // pad = open its folder, or pick its sound and hear it
+                    effects.extend(self.reassign_sound_pad(x, y));
+                } else if let Some(ReassignState {
+                    key,
+                    selection: Some(id),
+                    ..
+                }) = self.reassign
+                {
+                    // key being reassigned = star or unstar the selected sound
+                    if key == (x, y) {
+                        self.toggle_favorite(id);
+                    }
+                }
+            }
+        } else {
+            if pressed {
+                if y > 0 {
+                    let held: Vec<_> = self.fn_keys.iter().map(|k| k.pressed).collect();
+                    let binding = match self.split_mode {
+                        // the Fn keys belong to the halves in split mode
+                        true => None,
+                        false => self.keymap.resolve(&held, ChordKey::Pad),
+                    };
+
+                    if let Some(binding) = binding {
+                        // Fn keys + button = e.g. reassign key, or bounce the
+                        // next bars onto it
+                        let action = binding.action;
+                        self.key_action(action, Some((x, y)));
+                    } else if let Some(m) = self.sound_keys[y - 1][x].macro_binding.clone() {
+                        // macro pad = run its actions
+                        effects.extend(self.run_macro(x, &m));
+                    } else if self.arp.on && self.sound_keys[y - 1][x].binding.is_some() {
+                        // arp mode = button is played by the arpeggiator
+                        // while it is held
+                    } else if self.sound_keys[y - 1][x].key_sync {
+                        // key sync button = play sound on the next boundary
+                        if let Some(id) = self.sound_keys[y - 1][x].binding {
+                            self.sound_keys[y - 1][x].armed_at = Some(self.sync_tick(x, y, id));
+                        }
+                    } else if let Some(id) = self.sound_keys[y - 1][x].binding {
+                        let key = &mut self.sound_keys[y - 1][x];
+                        let now = self.clock.now();
+
+                        if key.settings.mode == PlaybackMode::Toggle
+                            && key.playing_until.is_some_and(|until| now < until)
+                        {
+                            // toggle button that is playing = stop it
+                            key.playing_until = None;
+                            effects.push(Effect::ReleasePad(x, y));
+                        } else {
+                            let settings = key.settings;
+
+                            // the press is timed for the groove that is
+                            // being recorded
+                            let at = self.pressed_loop_time();
+                            if let Some(recorder) = &mut self.groove_recorder {
+                                recorder.press(at);
+                            }
+
+                            if self.launches_on_bar(x, y) {
+                                // looper on = launch the pad at the top of
+                                // the next bar, blinking until then
+                                self.sound_keys[y - 1][x].armed_at = Some(self.next_bar());
+                            } else {
+                                // button = play sound if bound
+                                self.add_loop(
+                                    id,
+                                    Bus::of_column(x),
+                                    self.pad_divider(x, y),
+                                    settings,
+                                );
+
+                                effects.push(self.play_pad(x, y, id, None));
+                            }
+                        }
+                    }
+                } else if let Some((px, py)) = self.held_cue_pad() {
+                    // pad + Fn key = play the pad from one of its cues
+                    effects.extend(self.play_cue(px, py, x));
+                } else if self.split_mode {
+                    effects.extend(self.split_fn_key(x));
+                } else {
+                    // the key that was just pressed isn't part of what is
+                    // held
+                    let mut held: Vec<_> = self.fn_keys.iter().map(|k| k.pressed).collect();
+                    held[x] = false;
+
+                    if let Some(binding) = self.keymap.resolve(&held, ChordKey::Fn(x)) {
+                        let binding = binding.clone();
+
+                        // the keys that a chord starts with did what they do
+                        // on their own when they were pressed, so a toggle
+                        // is undone, e.g. F2's in F2 + F1
+                        for &k in &binding.chord.held {
+                            if let Some(fired) = self.fn_keys[k].fired.take() {
+                                if fired.is_toggle() {
+                                    self.undo_key_action(fired);
+                                }
+                            }
+                        }
+
+                        self.fn_keys[x].fired = Some(binding.action);
+                        self.key_action(binding.action, None);
+                    }
+                }
+            } else if y > 0 {
+                let key = &mut self.sound_keys[y - 1][x];
+
+                if key.binding.is_some() && key.settings.mode == PlaybackMode::Gate {
+                    // gate button = stop its sound when it is let go
+                    key.playing_until = None;
+                    effects.push(Effect::ReleasePad(x, y));
+                }
+
+                let key = &self.sound_keys[y - 1][x];
+
+                if held.is_some_and(|held| held >= LONG_PRESS)
+                    && key.binding.is_some()
+                    && !self.arp.on
+                {
+                    // holding a bound pad opens its detail view
+                    self.detail = Some((x, y));
+                }
+            }
+        }
+
+        effects.extend(self.keyboard_leds());
+        effects
+    }
+
+    /// Handles a key being pressed or released in the rhythm trainer. F1
+    /// gives up, and the pads play their sounds without adding them to the
+    /// loops, and are judged against the hits of the game.
+    fn game_key(&mut self, x: usize, y: usize, pressed: bool) -> Vec<Effect> {
+        match (x, y, pressed) {
+            (0, 0, true) => {
+                self.game = None;
+                vec![]
+            }
+            (_, 0, _) => vec![],
+            (_, _, true) => {
+                let at =
+                    (self.clock.now() - self.beginning).saturating_sub(self.latency_compensation);
+                let now = self.loop_time();
+
+                if let Some(game) = &mut self.game {
+                    game.press((x, y), at, self.tick, now);
+                }
+
+                match self.sound_keys[y - 1][x].binding {
+                    Some(id) => vec![self.play_pad(x, y, id, None)],
+                    None => vec![],
+                }
+            }
+            (_, _, false) => {
+                let key = &self.sound_keys[y - 1][x];
+
+                if key.binding.is_some() && key.settings.mode == PlaybackMode::Gate {
+                    vec![Effect::ReleasePad(x, y)]
+                } else {
+                    vec![]
+                }
+            }
+        }
+    }
+
+    /// Starts the rhythm trainer with a pattern in [`game::PATTERNS`], played
+    /// on the pads that are bound, after the bar that is playing and a bar of
+    /// count-in. The looper is started if it is stopped, and the other modes
+    /// are left.
+    pub fn start_game(&mut self, pattern: usize, bars: usize) {
+        if self.reassign.is_some() {
+            return;
+        }
+
+        if !self.running {
+            self.start_stop();
+        }
+
+        self.deck_mode = false;
+        self.mixer_mode = false;
+        self.fx_mode = false;
+        self.fx_pad = None;
+
+        let pads: Vec<_> = self
+            .pads()
+            .filter(|&(x, y)| self.sound_keys[y - 1][x].binding.is_some())
+            .collect();
+
+        self.game = Some(Game::new(pattern, &pads, self.loop_time(), bars.max(1)));
+    }
+
+    /// Misses the hits of the rhythm trainer that have gone by, and keeps its
+    /// score once it is over. The pads are lit again on every step, so that
+    /// the hits light up as they come.
+    fn tick_game(&mut self, tick: usize) -> Vec<Effect> {
+        let Some(game) = &mut self.game else { return vec![]; };
+
+        // stopping the looper stops the game
+        if !self.running {
+            self.game = None;
+            return self.keyboard_leds();
+        }
+
+        let at = (self.tick * tick as u32).saturating_sub(self.latency_compensation);
+        let missed = game.expire(at, self.tick, tick);
+
+        if game.is_over(tick) {
+            self.last_game = Some(game.score);
+            self.game = None;
+            return self.keyboard_leds();
+        }
+
+        if missed || tick.is_multiple_of(game::STEP) {
+            self.keyboard_leds()
+        } else {
+            vec![]
+        }
+    }
+
+    /// Handles a request from a front-end.
+    pub fn command(&mut self, cmd: Command) -> Vec<Effect> {
+        match cmd {
+            Command::Key { x, y, pressed } => return self.key(x, y, pressed),
+            Command::ShowPadDetail { x, y } => self.detail = Some((x, y)),
+            Command::UpDir => self.reassign_sound_up(),
+            Command::OpenVirtualDir(dir) => self.reassign_sound_virtual_dir(dir),
+            Command::ToggleFavorite(id) => {
+                self.toggle_favorite(id);
+                return self.keyboard_leds();
+            }
+            Command::CycleSort => self.reassign_sound_cycle_sort(),
+            Command::CycleFilter => self.reassign_sound_cycle_filter(),
+            Command::HidePadDetail => self.detail = None,
+            Command::Action(action) => return self.gpio(action, true),
+            Command::SetCrossfade(position) => return self.set_crossfade(position),
+            Command::AddCue { x, y, position } if self.is_pad(x, y) => {
+                self.edit_pads(|state| state.add_cue(x, y, position))
+            }
+            Command::RemoveCue { x, y, cue } if self.is_pad(x, y) => self.edit_pads(|state| {
+                let cues = &mut state.sound_keys[y - 1][x].cues;
+
+                if cue < cues.len() {
+                    cues.remove(cue);
+                }
+            }),
+            Command::SetPadColor { x, y, color } if self.is_pad(x, y) => {
+                self.set_pad_color(x, y, color);
+                return self.keyboard_leds();
+            }
+            Command::SetKeySync { x, y, on } if self.is_pad(x, y) => {
+                self.edit_pads(|state| state.sound_keys[y - 1][x].key_sync = on);
+
+                if !on {
+                    self.sound_keys[y - 1][x].armed_at = None;
+                    return self.keyboard_leds();
+                }
+            }
+            Command::SetPadLoop { x, y, loop_divider } if self.is_pad(x, y) => {
+                let loop_divider = loop_divider.filter(|d| LOOP_DIVIDERS.contains(d));
+                self.edit_pads(|state| state.sound_keys[y - 1][x].loop_divider = loop_divider);
+            }
+            Command::SetPadSettings { x, y, settings } if self.is_pad(x, y) => {
+                self.edit_pads(|state| state.sound_keys[y - 1][x].settings = settings.clamped());
+            }
+            Command::ReassignPad { x, y } if self.is_pad(x, y) => {
+                self.detail = None;
+                self.reassign_sound_begin((x, y));
+                return self.keyboard_leds();
+            }
+            Command::SliceToPads { x, y, sensitivity } if self.is_pad(x, y) => {
+                self.slice_to_pads(x, y, sensitivity);
+                return self.keyboard_leds();
+            }
+            Command::UndoPadEdit => {
+                self.undo_pad_edit();
+                return self.keyboard_leds();
+            }
+            Command::RedoPadEdit => {
+                self.redo_pad_edit();
+                return self.keyboard_leds();
+            }
+            Command::RampTempo { bpm, bars } => self.ramp_tempo(bpm, bars),
+            Command::SaveScene(slot) => {
+                self.save_scene(slot);
+                return self.keyboard_leds();
+            }
+            Command::ClearScene(slot) => {
+                self.scenes[slot.index()] = None;
+                return self.keyboard_leds();
+            }
+            Command::RecallScene { slot, bars } => {
+                let mut effects = self.recall_scene(slot, bars);
+                effects.extend(self.keyboard_leds());
+                return effects;
+            }
+            Command::RenderToPad { x, y } if self.is_pad(x, y) => {
+                return self.start_render(RenderTarget::Pad(x, y));
+            }
+            Command::RenderToPad { .. } => {}
+            Command::ExportStems => return self.start_render(RenderTarget::Stems),
+            Command::CancelRender => {
+                self.render = None;
+                return vec![Effect::CancelRender];
+            }
+            Command::ToggleTape => return vec![self.toggle_tape()],
+            Command::SetTapeSplit(split) => self.tape_split = split,
+            Command::SetArp { pattern, every } => {
+                self.arp.pattern = pattern;
+                self.arp.every = every.max(1);
+                self.arp.step = 0;
+            }
+            Command::AddCue { .. }
+            | Command::RemoveCue { .. }
+            | Command::SetPadColor { .. }
+            | Command::SetPadLoop { .. }
+            | Command::SetPadSettings { .. }
+            | Command::ReassignPad { .. }
+            | Command::SliceToPads { .. }
+            | Command::SetKeySync { .. } => {}
+            Command::ExportKit(path) => {
+                let (kit, sounds) = self.export_kit();
+                return vec![Effect::ExportKit { path, kit, sounds }];
+            }
+            Command::ImportKit(path) => return vec![Effect::ImportKit(path)],
+            Command::SetBrightness(brightness) => {
+                return vec![Effect::SetDevice(DeviceChange::Brightness(brightness))];
+            }
+            Command::SetStartKit(kit) => return vec![Effect::SetDevice(DeviceChange::Kit(kit))],
+            Command::SetKeyboardAddress(address) => {
+                return vec![Effect::SetDevice(DeviceChange::Address(address))];
+            }
+            Command::GoToSetEntry(index) => {
+                self.go_to_set_entry(index);
+                return self.keyboard_leds();
+            }
+            Command::ShowDecks(show) => {
+                self.game = self.game.take().filter(|_| !show);
+                self.deck_mode = show;
+                self.mixer_mode &= !show;
+                return self.show_fx(self.fx_mode && !show);
+            }
+            Command::ShowMixer(show) => {
+                self.game = self.game.take().filter(|_| !show);
+                self.mixer_mode = show;
+                self.deck_mode &= !show;
+                return self.show_fx(self.fx_mode && !show);
+            }
+            Command::ShowFx(show) => {
+                self.game = self.game.take().filter(|_| !show);
+                self.deck_mode &= !show;
+                self.mixer_mode &= !show;
+                return self.show_fx(show);
+            }
+            Command::ToggleKill { bus, band } => {
+                let mut effects = self.toggle_kill(bus, band);
+                effects.extend(self.keyboard_leds());
+                return effects;
+            }
+            Command::Deck { deck, action } => {
+                let mut effects = self.deck(deck, action);
+                effects.extend(self.keyboard_leds());
+                return effects;
+            }
+            Command::SetLatencyCompensation(compensation) => {
+                self.latency_compensation = compensation;
+            }
+            Command::StartGame { pattern, bars } => {
+                self.start_game(pattern, bars);
+                return self.keyboard_leds();
+            }
+            Command::StopGame => {
+                self.game = None;
+                return self.keyboard_leds();
+            }
+            Command::ShowSplit(show) => {
+                self.show_split(show);
+                return self.keyboard_leds();
+            }
+            Command::RecordGroove => self.groove_recorder = Some(GrooveRecorder::default()),
+            Command::SaveGroove(name) => self.save_groove(name),
+            Command::DiscardGroove => self.groove_recorder = None,
+            Command::SetGroove(groove) => {
+                self.groove = groove.filter(|&i| i < self.grooves.len());
+            }
+            Command::DeleteGroove(i) if i < self.grooves.len() => {
+                self.grooves.remove(i);
+                self.groove = match self.groove {
+                    Some(on) if on == i => None,
+                    Some(on) if on > i => Some(on - 1),
+                    on => on,
+                };
+            }
+            Command::DeleteGroove(_) => {}
+            Command::RetryLoading => {}
+            cmd => return self.reassign_command(cmd),
+        }
+
+        vec![]
+    }
+
+    /// Handles an input wired to one of the spare Seesaw pins.
+    pub fn gpio(&mut self, action: GpioAction, pressed: bool) -> Vec<Effect> {
+        if !pressed || self.reassign.is_some() || self.deck_mode {
+            return vec![];
+        }
+
+        match action {
+            GpioAction::StartStop => self.start_stop(),
+            GpioAction::ClearLoops => self.clear_loops(),
+            GpioAction::ToggleQuantize => self.cycle_quantize(),
+            GpioAction::CycleLoopMode => self.cycle_loop_mode(),
+            GpioAction::BpmUp => self.bpm_up(),
+            GpioAction::BpmDown => self.bpm_down(),
+            GpioAction::ToggleArp => self.arp.on = !self.arp.on,
+        }
+
+        self.keyboard_leds()
+    }
+
+    /// Does what a chord of the function keys is bound to. `pad` is the pad
+    /// that completed the chord, if it was one, and actions on a pad do
+    /// nothing without one.
+    fn key_action(&mut self, action: KeyAction, pad: Option<(usize, usize)>) {
+        match action {
+            KeyAction::Reassign => {
+                if let Some(pad) = pad {
+                    self.reassign_sound_begin(pad);
+                }
+            }
+            KeyAction::Bounce => {
+                if let Some((x, y)) = pad {
+                    self.arm_bounce(x, y);
+                }
+            }
+            KeyAction::ToggleQuantize => self.cycle_quantize(),
+            KeyAction::ClearLoops => self.clear_loops(),
+            KeyAction::CycleLoopMode => self.cycle_loop_mode(),
+            KeyAction::BpmUp => self.bpm_up(),
+            KeyAction::BpmDown => self.bpm_down(),
+            KeyAction::BpmHalve => self.bpm_halve(),
+            KeyAction::BpmDouble => self.bpm_double(),
+            KeyAction::FxMode => self.fx_mode = true,
+            KeyAction::DeckMode => self.deck_mode = true,
+            KeyAction::UndoPadEdit => {
+                self.undo_pad_edit();
+            }
+            KeyAction::RedoPadEdit => {
+                self.redo_pad_edit();
+            }
+            KeyAction::NextSetEntry => self.next_set_entry(),
+            KeyAction::StartStop => self.start_stop(),
+            KeyAction::ToggleArp => self.arp.on = !self.arp.on,
+            KeyAction::RhythmGame => self.start_game(0, GAME_BARS),
+            KeyAction::SplitMode => self.show_split(true),
+        }
+    }
+
+    /// Undoes an action that [`KeyAction::is_toggle`].
+    fn undo_key_action(&mut self, action: KeyAction) {
+        match action {
+            KeyAction::ToggleQuantize => self.cycle_quantize(),
+            KeyAction::CycleLoopMode => self.cycle_loop_mode_back(),
+            _ => {}
+        }
+    }
+
+    /// Handles an analog input moving to `value`, which is between 0 and 1.
+    pub fn analog(&mut self, control: AnalogControl, value: f32) -> Vec<Effect> {
+        match control {
+            AnalogControl::Volume => {
+                // centre of the pot is unity gain
+                self.volume = value * 2.;
+                vec![Effect::SetVolume(self.volume)]
+            }
+            AnalogControl::Crossfade => self.set_crossfade(value),
+            AnalogControl::FilterCutoff => {
+                // exponential sweep from 20Hz to 20kHz, which sounds linear
+                self.filter_cutoff = (20. * 1000f32.powf(value)) as u32;
+                vec![Effect::SetFilterCutoff(self.filter_cutoff)]
+            }
+        }
+    }
+
+    pub fn encoder(&mut self, event: encoder::Event) -> Vec<Effect> {
+        let mut effects = vec![];
+
+        match event {
+            encoder::Event::Button { pressed } => {
+                self.encoder_pressed = pressed;
+            }
+            encoder::Event::Rotate { delta } => {
+                if self.reassign.is_some() {
+                    // encoder = scroll through sounds
+                    self.reassign_sound_scroll(delta as isize);
+                    effects.extend(self.keyboard_leds());
+                } else if self.deck_mode && !self.encoder_pressed {
+                    // encoder = crossfader in deck mode
+                    effects.extend(self.set_crossfade(self.crossfade + delta as f32 * 0.05));
+                } else if self.encoder_pressed {
+                    // encoder + push = master volume
+                    self.volume_nudge(delta as f32 * 0.05);
+                    effects.push(Effect::SetVolume(self.volume));
+                } else if self.fn_keys[0].pressed {
+                    // F1 + encoder = fine BPM, for beat-matching
+                    self.bpm_nudge(delta as f32 * 0.1);
+                } else {
+                    // encoder = BPM
+                    self.bpm_nudge(delta as f32);
+                }
+            }
+        }
+
+        // light the encoder while turning it controls the volume
+        effects.push(Effect::SetEncoderColor(if self.encoder_pressed {
+            self.palette.active.color()
+        } else {
+            Color::BLACK
+        }));
+
+        effects
+    }
+
+    /// Plays the ticks that have come due since `last_tick`, and moves it on.
+    /// Returns their effects and when the next tick is due. While the looper
+    /// is paused for a sound to be picked, it returns None, unless the help
+    /// for the keys has just been shown.
+    pub fn tick_due(&mut self, last_tick: &mut Option<usize>) -> Option<(Vec<Effect>, Instant)> {
+        // the help shows whether or not the looper is paused
+        let mut effects = self.show_help();
+
+        if self.reassign.is_some() {
+            *last_tick = None;
+
+            let now = self.clock.now();
+            return (!effects.is_empty()).then_some((effects, now));
+        }
+
+        let now = self.loop_time();
+
+        let first = match *last_tick {
+            Some(last) if last < now => last + 1,
+            Some(last) if last == now => now + 1,
+            // the looper was started again from the top
+            _ => now,
+        };
+
+        effects.extend((first..=now).flat_map(|tick| self.tick(tick)));
+
+        if !effects.is_empty() {
+            self.revision += 1;
+        }
+
+        *last_tick = Some(now);
+        Some((effects, self.tick_deadline(now + 1)))
+    }
+
+    /// Moves the tempo along its ramp and the mix along its morph, plays the
+    /// loops and armed pads that are due on the given tick, pulses the
+    /// trigger output, marks the beats, starts a bounce and moves the beat
+    /// strip on.
+    pub fn tick(&mut self, tick: usize) -> Vec<Effect> {
+        self.tick_tempo_ramp(tick);
+        let mut effects = self.tick_morph(tick);
+
+        effects.extend(self.stop_loop_voices());
+
+        let due: Vec<_> = match self.running {
+            true => (0..self.loops.len())
+                .filter(|&i| self.is_loop_due(&self.loops[i], tick))
+                .collect(),
+            false => vec![],
+        };
+
+        for i in due {
+            let LoopState {
+                sound: id,
+                bus,
+                settings,
+                seamless,
+                ..
+            } = self.loops[i];
+
+            match seamless {
+                true => effects.extend(self.start_loop_voice(i)),
+                false => effects.push(self.play_with(id, bus, settings, None)),
+            }
+
+            let sound = &self.sounds[id.0];
+            let path = sound.path.strip_prefix(&sound.root).unwrap_or(&sound.path);
+            effects.push(Effect::LoopTriggered(path.to_string_lossy().into_owned()));
+        }
+
+        effects.extend(self.tick_armed(tick));
+        effects.extend(self.tick_sweep(tick));
+        effects.extend(self.tick_tape());
+        effects.extend(self.tick_game(tick));
+
+        if self.running && self.arp.on && tick.is_multiple_of(self.arp.every) {
+            effects.extend(self.arp_step());
+        }
+
+        if self.running && self.trigger_every > 0 && tick.is_multiple_of(self.trigger_every) {
+            effects.push(Effect::TriggerPulse);
+        }
+
+        if self.running && tick.is_multiple_of(TICKS_PER_BEAT) {
+            effects.push(Effect::Beat(tick / TICKS_PER_BEAT % BEATS_PER_BAR));
+        }
+
+        match &mut self.bounce {
+            Some(bounce) if self.running && !bounce.recording && tick >= bounce.start => {
+                bounce.recording = true;
+                bounce.start = tick;
+
+                let (x, y) = bounce.pad;
+                effects.push(Effect::Record(self.tick * bounce.ticks as u32));
+                effects.push(Effect::SetLed {
+                    x,
+                    y,
+                    color: self.key_color(x, y),
+                });
+            }
+            _ => {}
+        }
+
+        // the Fn LEDs are controls in the other modes
+        if self.deck_mode
+            || self.mixer_mode
+            || self.fx_mode
+            || self.game.is_some()
+            || self.split_mode
+        {
+            self.beat_strip = BeatStrip::default();
+            return effects;
+        }
+
+        // the help for the keys is showing on them
+        if self.help {
+            return effects;
+        }
+
+        let looping = self.running && self.loop_divider.is_some();
+
+        for x in self.beat_strip.advance(tick, looping) {
+            effects.push(Effect::SetLed {
+                x,
+                y: 0,
+                color: self.fn_led(x),
+            });
+        }
+
+        effects
+    }
+}
+
+/// How many bars the rhythm trainer lasts when it is started from the keys
+/// or a front-end.
+pub const GAME_BARS: usize = 8;
+
+#[cfg(test)]
+mod test {
+    use std::path::{Path, PathBuf};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use super::{Command, Effect, Macro, MacroStep, PlayState, DEFAULT_PAD_COLOR, LONG_PRESS};
+    use crate::audio::{Bus, SoundId, SoundInfo};
+    use crate::clock::{Clock, VirtualClock};
+    use crate::driver::adafruit::seesaw::neopixel::Color;
+    use crate::pad::{PadSettings, PlaybackMode};
+    use crate::setlist::{
+        Kit, KitMacro, KitPad, MacroAction, Pattern, PatternLoop, SetEntry, SetList,
+    };
+
+    pub(super) fn play_state(clock: &VirtualClock) -> PlayState {
+        let sounds = vec![SoundInfo {
+            id: SoundId(0),
+            path: PathBuf::from("audio/kick.wav"),
+            root: PathBuf::from("audio"),
+            duration: Duration::from_millis(500),
+            peaks: vec![],
+            loudness_db: -12.,
+            key: None,
+            tags: Default::default(),
+            ready: true,
+        }];
+
+        PlayState::new(sounds, Arc::new(clock.clone()))
+    }
+
+    pub(super) fn sounds(effects: &[Effect]) -> Vec<SoundId> {
+        effects
+            .iter()
+            .filter_map(|e| match e {
+                Effect::PlaySound(id, _) | Effect::PlayPad { sound: id, .. } => Some(*id),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub(super) fn led(effects: &[Effect], x: usize, y: usize) -> Option<Color> {
+        effects.iter().rev().find_map(|e| match *e {
+            Effect::SetLed {
+                x: ex,
+                y: ey,
+                color,
+            } if (ex, ey) == (x, y) => Some(color),
+            _ => None,
+        })
+    }
+
+    /// Presses and releases a key.
+    pub(super) fn tap(state: &mut PlayState, x: usize, y: usize) -> Vec<Effect> {
+        let mut effects = state.key(x, y, true);
+        effects.extend(state.key(x, y, false));
+        effects
+    }
+
+    #[test]
+    fn bound_key_plays_sound() {
+        let clock = VirtualClock::new();
+        let mut state = play_state(&clock);
+        state.sound_keys[0][2].binding = Some(SoundId(0));
+
+        assert_eq!(sounds(&tap(&mut state, 2, 1)), vec![SoundId(0)]);
+        assert!(state.loops.is_empty());
+
+        // unbound keys do nothing
+        assert!(sounds(&tap(&mut state, 3, 1)).is_empty());
+    }
+
+    #[test]
+    fn pads_fill_a_bigger_grid() {
+        let clock = VirtualClock::new();
+        let mut state = play_state(&clock);
+        state.set_grid_size(8, 8);
+        state.sound_keys[6][7].binding = Some(SoundId(0));
+
+        assert_eq!(sounds(&tap(&mut state, 7, 7)), vec![SoundId(0)]);
+        assert!(tap(&mut state, 8, 7).is_empty());
+
+        // the top row past the Fn keys is dark, and does nothing
+        let leds = state.keyboard_leds();
+        assert_eq!(led(&leds, 5, 0), Some(Color::BLACK));
+        assert_eq!(led(&leds, 7, 7), Some(DEFAULT_PAD_COLOR));
+        assert!(tap(&mut state, 5, 0).is_empty());
+    }
+
+    #[test]
+    fn pad_and_fn_key_plays_from_cue() {
+        let clock = VirtualClock::new();
+        let mut state = play_state(&clock);
+        state.sound_keys[0][0].binding = Some(SoundId(0));
+        state.add_cue(0, 1, Duration::from_millis(300));
+        state.add_cue(0, 1, Duration::from_millis(100));
+
+        // cues are kept in order, so F2 is the later one
+        state.key(0, 1, true);
+        let effects = tap(&mut state, 1, 0);
+        assert!(effects.contains(&Effect::PlaySoundFrom(
+            SoundId(0),
+            Bus::A,
+            Duration::from_millis(300)
+        )));
+        assert!(state.quantize);
+
+        // jumping to a cue doesn't open the detail view, however long the pad
+        // was held for
+        clock.advance(LONG_PRESS);
+        state.key(0, 1, false);
+        assert_eq!(state.detail, None);
+    }
+
+    #[test]
+    fn holding_bound_key_opens_detail() {
+        let clock = VirtualClock::new();
+        let mut state = play_state(&clock);
+        state.sound_keys[0][2].binding = Some(SoundId(0));
+
+        // a tap doesn't open it
+        tap(&mut state, 2, 1);
+        assert_eq!(state.detail, None);
+
+        state.key(2, 1, true);
+        clock.advance(LONG_PRESS);
+        state.key(2, 1, false);
+        assert_eq!(state.detail, Some((2, 1)));
+
+        state.command(Command::HidePadDetail);
+        assert_eq!(state.detail, None);
+    }
+
+    #[test]
+    fn fn_key_combinations() {
+        let clock = VirtualClock::new();
+        let mut state = play_state(&clock);
+        let tick = state.tick;
+
+        // F2 toggles quantization, and its LED follows
+        let effects = tap(&mut state, 1, 0);
+        assert!(!state.quantize);
+        assert_eq!(led(&effects, 1, 0), Some(Color::BLACK));
+        let effects = tap(&mut state, 1, 0);
+        assert!(state.quantize);
+        assert_eq!(led(&effects, 1, 0), Some(Color::WHITE));
+
+        // F1 + F4 = BPM up, F1 + F3 = BPM down
+        state.key(0, 0, true);
+        tap(&mut state, 3, 0);
+        assert!(state.tick < tick);
+        assert_eq!(state.loop_divider, None);
+
+        tap(&mut state, 2, 0);
+        tap(&mut state, 2, 0);
+        assert!(state.tick > tick);
+        state.key(0, 0, false);
+
+        // F1 on its own does nothing
+        let before = (state.quantize, state.loop_divider, state.tick);
+        tap(&mut state, 0, 0);
+        assert_eq!((state.quantize, state.loop_divider, state.tick), before);
+    }
+
+    /// Steps the clock from one tick to the next until the looper reaches
+    /// `until`.
+    pub(super) fn run_to_tick(
+        state: &mut PlayState,
+        clock: &VirtualClock,
+        last_tick: &mut Option<usize>,
+        until: usize,
+    ) {
+        while state.loop_time() < until {
+            let (_, deadline) = state.tick_due(last_tick).unwrap();
+            clock.advance(deadline.saturating_duration_since(clock.now()));
+        }
+
+        state.tick_due(last_tick);
+    }
+
+    #[test]
+    fn fn_chord_steps_through_set_list() {
+        let clock = VirtualClock::new();
+        let mut state = play_state(&clock);
+
+        let kit = Kit {
+            bpm: Some(100.),
+            pads: vec![KitPad {
+                pad: [0, 1],
+                sound: PathBuf::from("kick.wav"),
+                color: None,
+            }],
+            macros: vec![],
+            patterns: vec![Pattern {
+                name: "four on the floor".to_owned(),
+                loops: vec![PatternLoop {
+                    pad: [0, 1],
+                    every: 1.,
+                    offset: 0.,
+                }],
+            }],
+            ..Default::default()
+        };
+        let entry = |name: &str, kit: Option<&str>, pattern: Option<&str>, bpm| SetEntry {
+            name: name.to_owned(),
+            kit: kit.map(PathBuf::from),
+            pattern: pattern.map(str::to_owned),
+            bpm,
+        };
+
+        state.set_list = Arc::new(SetList {
+            entries: vec![
+                entry("intro", Some("house.toml"), Some("four on the floor"), 120.),
+                entry("breakdown", None, None, 90.),
+                entry("house", Some("house.toml"), None, 0.),
+            ],
+            kits: [(PathBuf::from("house.toml"), kit)].into_iter().collect(),
+        });
+
+        let chord = |state: &mut PlayState| {
+            for pressed in [true, false] {
+                state.key(0, 0, pressed);
+                state.key(2, 0, pressed);
+                state.key(3, 0, pressed);
+            }
+        };
+
+        chord(&mut state);
+        assert_eq!(state.set_entry, Some(0));
+        assert_eq!(state.sound_keys[0][0].binding, Some(SoundId(0)));
+        assert_eq!(state.loops.len(), 1);
+        assert_eq!(state.loops[0].period, 60);
+        assert_eq!(state.tick, Duration::from_secs_f32(1. / 120.));
+
+        // entries without a kit keep the pads, and without a pattern stop the
+        // loops
+        chord(&mut state);
+        assert_eq!(state.set_entry, Some(1));
+        assert_eq!(state.sound_keys[0][0].binding, Some(SoundId(0)));
+        assert!(state.loops.is_empty());
+        assert_eq!(state.tick, Duration::from_secs_f32(1. / 90.));
+
+        // entries without a BPM use the kit's, unless it is kept
+        chord(&mut state);
+        assert_eq!(state.tick, Duration::from_secs_f32(1. / 100.));
+
+        state.keep_tempo = true;
+        state.go_to_set_entry(1);
+        state.go_to_set_entry(2);
+        assert_eq!(state.tick, Duration::from_secs_f32(1. / 90.));
+
+        // the last entry stays put
+        chord(&mut state);
+        assert_eq!(state.set_entry, Some(2));
+    }
+
+    #[test]
+    fn macro_pad_runs_its_actions() {
+        let clock = VirtualClock::new();
+        let mut state = play_state(&clock);
+
+        let kit = Kit {
+            bpm: None,
+            pads: vec![KitPad {
+                pad: [0, 1],
+                sound: PathBuf::from("kick.wav"),
+                color: None,
+            }],
+            macros: vec![KitMacro {
+                pad: [3, 3],
+                name: "drop".to_owned(),
+                actions: vec![
+                    MacroAction::Play(PathBuf::from("kick.wav")),
+                    MacroAction::Bpm(100.),
+                    MacroAction::ToggleQuantize,
+                    MacroAction::Pattern("kicks".to_owned()),
+                ],
+                color: None,
+            }],
+            patterns: vec![Pattern {
+                name: "kicks".to_owned(),
+                loops: vec![PatternLoop {
+                    pad: [0, 1],
+                    every: 2.,
+                    offset: 0.,
+                }],
+            }],
+            ..Default::default()
+        };
+
+        state.set_list = Arc::new(SetList {
+            entries: vec![SetEntry {
+                name: "intro".to_owned(),
+                kit: Some(PathBuf::from("kit.toml")),
+                pattern: None,
+                bpm: 120.,
+            }],
+            kits: [(PathBuf::from("kit.toml"), kit)].into_iter().collect(),
+        });
+        state.go_to_set_entry(0);
+        assert!(state.sound_keys[2][3].macro_binding.is_some());
+
+        let effects = tap(&mut state, 3, 3);
+        assert!(effects.contains(&Effect::PlaySound(SoundId(0), Bus::B)));
+        assert_eq!(state.tick, Duration::from_secs_f32(1. / 100.));
+        assert!(!state.quantize);
+        assert_eq!(state.loops.len(), 1);
+        assert_eq!(state.loops[0].period, 120);
+    }
+
+    #[test]
+    fn kits_are_exported_and_imported() {
+        let clock = VirtualClock::new();
+        let mut state = play_state(&clock);
+
+        let pattern = Pattern {
+            name: "kicks".to_owned(),
+            loops: vec![PatternLoop {
+                pad: [0, 1],
+                every: 2.,
+                offset: 0.,
+            }],
+        };
+
+        state.sound_keys[0][0].binding = Some(SoundId(0));
+        state.sound_keys[0][0].color = Some(Color::from_u8(255, 0, 0));
+        state.sound_keys[2][3].macro_binding = Some(Macro {
+            name: "drop".to_owned(),
+            steps: vec![
+                MacroStep::Play(SoundId(0)),
+                MacroStep::StartPattern(pattern.clone()),
+            ],
+        });
+
+        let (mut kit, sounds) = state.export_kit();
+        let kick = PathBuf::from("audio/kick.wav");
+
+        // the kick is only bundled once
+        assert_eq!(sounds, vec![(kick.clone(), kick.clone())]);
+        assert_eq!(
+            kit.pads,
+            vec![KitPad {
+                pad: [0, 1],
+                sound: kick.clone(),
+                color: Some([255, 0, 0]),
+            }]
+        );
+        assert_eq!(
+            kit.macros[0].actions,
+            vec![
+                MacroAction::Play(kick),
+                MacroAction::Pattern("kicks".to_owned())
+            ]
+        );
+        assert_eq!(kit.patterns, vec![pattern]);
+
+        // importing points the kit at the extracted copies of its sounds
+        let imported = PathBuf::from("Kits/house/audio/kick.wav");
+        kit.pads[0].sound = imported.clone();
+        kit.macros[0].actions[0] = MacroAction::Play(imported.clone());
+
+        let sound = SoundInfo {
+            id: SoundId(1),
+            path: Path::new("audio").join(&imported),
+            ready: false,
+            ..state.sounds[0].clone()
+        };
+        state.kit_imported(kit, vec![sound]);
+
+        assert_eq!(state.sounds.len(), 2);
+        assert_eq!(state.sound_keys[0][0].binding, Some(SoundId(1)));
+        assert_eq!(
+            state.sound_keys[2][3].macro_binding.as_ref().unwrap().steps[0],
+            MacroStep::Play(SoundId(1))
+        );
+    }
+
+    #[test]
+    fn pad_edits_are_undone_and_redone() {
+        let clock = VirtualClock::new();
+        let mut state = play_state(&clock);
+        let red = Some(Color::from_u8(255, 0, 0));
+
+        state.reassign_sound_begin((1, 2));
+        state.reassign.as_mut().unwrap().select_sound(SoundId(0));
+        state.reassign_sound_save();
+        state.command(Command::SetPadColor {
+            x: 1,
+            y: 2,
+            color: red,
+        });
+
+        // setting the same color again isn't an edit
+        state.command(Command::SetPadColor {
+            x: 1,
+            y: 2,
+            color: red,
+        });
+
+        let undo = |state: &mut PlayState| {
+            state.key(3, 0, true);
+            tap(state, 0, 0);
+            state.key(3, 0, false);
+        };
+
+        // F4 + F1 = undo, which leaves the loop mode as it was
+        undo(&mut state);
+        assert_eq!(state.sound_keys[1][1].binding, Some(SoundId(0)));
+        assert_eq!(state.sound_keys[1][1].color, None);
+        assert_eq!(state.loop_divider, None);
+
+        undo(&mut state);
+        assert_eq!(state.sound_keys[1][1].binding, None);
+        assert!(!state.pad_history.can_undo());
+
+        // F4 + F2 = redo
+        state.key(3, 0, true);
+        tap(&mut state, 1, 0);
+        state.key(3, 0, false);
+        assert_eq!(state.sound_keys[1][1].binding, Some(SoundId(0)));
+        assert!(state.quantize);
+        assert_eq!(state.loop_divider, None);
+
+        // a new edit can't be followed by the edits that were undone
+        state.command(Command::SetPadLoop {
+            x: 1,
+            y: 2,
+            loop_divider: Some(2),
+        });
+        assert!(!state.pad_history.can_redo());
+
+        state.command(Command::UndoPadEdit);
+        assert_eq!(state.sound_keys[1][1].loop_divider, None);
+        assert_eq!(state.sound_keys[1][1].binding, Some(SoundId(0)));
+    }
+
+    #[test]
+    fn pads_play_with_their_settings() {
+        let clock = VirtualClock::new();
+        let mut state = play_state(&clock);
+        state.sound_keys[0][2].binding = Some(SoundId(0));
+
+        let gate = PadSettings {
+            pan: -1.,
+            trim_start_ms: 100,
+            choke_group: Some(1),
+            mode: PlaybackMode::Gate,
+            ..Default::default()
+        };
+        state.command(Command::SetPadSettings {
+            x: 2,
+            y: 1,
+            settings: gate,
+        });
+
+        // gate = plays from the trimmed start, and stops when let go
+        let effects = tap(&mut state, 2, 1);
+        assert!(effects.contains(&Effect::PlayPad {
+            sound: SoundId(0),
+            bus: Bus::B,
+            start: Duration::from_millis(100),
+            settings: gate,
+            pad: Some((2, 1)),
+        }));
+        assert!(effects.contains(&Effect::ReleasePad(2, 1)));
+
+        // toggle = pressing it again while it plays stops it
+        let toggle = PadSettings {
+            mode: PlaybackMode::Toggle,
+            ..Default::default()
+        };
+        state.command(Command::SetPadSettings {
+            x: 2,
+            y: 1,
+            settings: toggle,
+        });
+        assert_eq!(sounds(&tap(&mut state, 2, 1)), vec![SoundId(0)]);
+
+        let effects = tap(&mut state, 2, 1);
+        assert!(sounds(&effects).is_empty());
+        assert!(effects.contains(&Effect::ReleasePad(2, 1)));
+
+        // once the sound has finished, it plays again
+        tap(&mut state, 2, 1);
+        clock.advance(Duration::from_millis(500));
+        assert_eq!(sounds(&tap(&mut state, 2, 1)), vec![SoundId(0)]);
+
+        // the pad and its settings are kept between runs
+        let mut restored = play_state(&clock);
+        restored.restore(&state.saved());
+        assert_eq!(restored.sound_keys[0][2].binding, Some(SoundId(0)));
+        assert_eq!(restored.sound_keys[0][2].settings, toggle);
+
+        // and changing them can be undone
+        state.command(Command::UndoPadEdit);
+        assert_eq!(state.sound_keys[0][2].settings, gate);
+    }
+
+    #[test]
+    fn encoder_push_and_turn_sets_volume() {
+        let clock = VirtualClock::new();
+        let mut state = play_state(&clock);
+        let tick = state.tick;
+
+        let effects = state.encoder(crate::encoder::Event::Button { pressed: true });
+        assert_eq!(
+            effects,
+            vec![Effect::SetEncoderColor(Color::from_u8(0, 255, 0))]
+        );
+
+        let effects = state.encoder(crate::encoder::Event::Rotate { delta: -2 });
+        assert_eq!(effects[0], Effect::SetVolume(state.volume));
+        assert!(state.volume < 1.);
+        assert_eq!(state.tick, tick);
+
+        state.encoder(crate::encoder::Event::Button { pressed: false });
+        state.encoder(crate::encoder::Event::Rotate { delta: 1 });
+        assert!(state.tick < tick);
+    }
+
+    #[test]
+    fn split_halves_have_their_own_banks_and_loops() {
+        let clock = VirtualClock::new();
+        let mut state = play_state(&clock);
+        state.sound_keys[0][0].binding = Some(SoundId(0));
+        state.sound_keys[0][2].binding = Some(SoundId(0));
+
+        // F4 + F3 = split mode, which leaves the loop mode alone
+        state.loop_divider = Some(1);
+        state.key(3, 0, true);
+        tap(&mut state, 2, 0);
+        state.key(3, 0, false);
+        assert!(state.split_mode);
+        assert_eq!(state.split_loops, [Some(1), Some(1)]);
+        assert_eq!(state.loop_divider, Some(1));
+
+        // each half is in its own colour
+        assert_eq!(state.key_color(0, 1), state.palette.decks[0].color());
+        assert_eq!(state.key_color(2, 1), state.palette.decks[1].color());
+
+        // F4 = the right half's next loop mode
+        tap(&mut state, 3, 0);
+        assert_eq!(state.split_loops, [Some(1), Some(2)]);
+
+        // both halves loop their pads from the next bar, and F2 clears only
+        // the left half's
+        tap(&mut state, 0, 1);
+        tap(&mut state, 2, 1);
+        state.tick(240);
+        assert_eq!(state.loops.len(), 2);
+        tap(&mut state, 1, 0);
+        assert_eq!(state.loops.len(), 1);
+        assert_eq!(state.loops[0].bus, Bus::B);
+        assert_eq!(state.split_loops, [None, Some(2)]);
+
+        // F4 + F3 = the right half's next bank, which is empty
+        state.key(3, 0, true);
+        tap(&mut state, 2, 0);
+        state.key(3, 0, false);
+        assert_eq!(state.split_loops, [None, Some(2)]);
+        assert_eq!(state.banks.current, [0, 1]);
+        assert!(state.sound_keys[0][2].binding.is_none());
+        assert!(state.sound_keys[0][0].binding.is_some());
+
+        // F1 + F4 = back to the whole grid
+        state.key(0, 0, true);
+        tap(&mut state, 3, 0);
+        state.key(0, 0, false);
+        assert!(!state.split_mode);
+        assert_eq!(state.split_loops, [None, Some(2)]);
+    }
+
+    #[test]
+    fn rhythm_trainer_judges_presses_and_keeps_the_score() {
+        let clock = VirtualClock::new();
+        let mut state = play_state(&clock);
+        state.sound_keys[0][0].binding = Some(SoundId(0));
+        state.loop_divider = Some(1);
+
+        // it starts after the bar that is playing and a bar of count-in
+        clock.advance(state.tick * 10);
+        state.command(Command::StartGame {
+            pattern: 0,
+            bars: 1,
+        });
+        let game = state.game.as_ref().unwrap();
+        assert_eq!((game.start, &game.pads[..]), (480, &[(0, 1)][..]));
+
+        // the pad lights up over the beat before each hit
+        clock.advance(state.tick * 440);
+        let lit = led(&state.tick(450), 0, 1).unwrap();
+        assert_ne!(lit, Color::BLACK);
+        clock.advance(state.tick * 30);
+        assert_eq!(
+            led(&state.tick(480), 0, 1),
+            Some(state.palette.beat.color())
+        );
+
+        // a press on the hit plays the pad without looping it, and flashes
+        let effects = tap(&mut state, 0, 1);
+        assert_eq!(sounds(&effects), vec![SoundId(0)]);
+        assert_eq!(led(&effects, 0, 1), Some(state.palette.active.color()));
+        assert!(state.loops.is_empty());
+
+        // the rest of the hits go by, and the score is kept at the end
+        for tick in 481..=720 {
+            state.tick(tick);
+        }
+
+        assert!(state.game.is_none());
+        let score = state.last_game.unwrap();
+        assert_eq!((score.perfect, score.miss, score.best_combo), (1, 3, 1));
+    }
+
+    #[test]
+    fn slicing_maps_pads_and_undoes_in_one_go() {
+        let clock = VirtualClock::new();
+        let mut state = play_state(&clock);
+
+        // four hits, two seconds apart
+        let sound = &mut Arc::make_mut(&mut state.sounds)[0];
+        sound.duration = Duration::from_secs(8);
+        sound.peaks = (0..64).map(|i| 0.8f32.powi(i % 16)).collect();
+        state.sound_keys[0][0].binding = Some(SoundId(0));
+
+        let slice = |state: &mut PlayState, sensitivity| {
+            state.command(Command::SliceToPads {
+                x: 0,
+                y: 1,
+                sensitivity,
+            });
+        };
+        let trims = |state: &PlayState| {
+            state.sound_keys[0]
+                .iter()
+                .map(|key| {
+                    key.binding
+                        .map(|_| (key.settings.trim_start_ms, key.settings.trim_end_ms))
+                })
+                .collect::<Vec<_>>()
+        };
+
+        slice(&mut state, 0.5);
+        assert_eq!(
+            trims(&state),
+            vec![
+                Some((0, 6_000)),
+                Some((2_000, 4_000)),
+                Some((4_000, 2_000)),
+                Some((6_000, 0)),
+            ]
+        );
+
+        // fewer slices clear the pads that the others were on
+        slice(&mut state, 0.);
+        assert_eq!(trims(&state), vec![Some((0, 0)), None, None, None]);
+
+        // and undoing goes back to before slicing, however many times it
+        // was moved
+        slice(&mut state, 0.5);
+        state.command(Command::UndoPadEdit);
+        assert_eq!(trims(&state), vec![Some((0, 0)), None, None, None]);
+        assert_eq!(state.slicing, None);
+        assert!(!state.pad_history.can_undo());
+    }
+}
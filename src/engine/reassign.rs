@@ -0,0 +1,900 @@
+//! The sound browser that a pad's sound is picked from, on the screen or
+//! on the pads themselves.
+
+use std::collections::BTreeSet;
+use std::ffi::{OsStr, OsString};
+use std::path::PathBuf;
+use std::time::Duration;
+use tracing::{info, trace, warn};
+
+use crate::audio::{Bus, SoundId, SoundInfo};
+
+use super::{Command, Effect, PlayState};
+
+#[derive(Clone, Debug)]
+pub struct ReassignState {
+    pub key: (usize, usize),
+
+    pub base_dir: PathBuf,
+    pub current_dir: PathBuf,
+    /// sounds listed in the browser: the ones in the current directory, or the
+    /// search results, best match first
+    pub sounds_in_dir: Vec<SoundId>,
+    pub subdirs_in_dir: BTreeSet<OsString>,
+
+    pub selection: Option<SoundId>,
+
+    /// if true, the browser will scroll the selected sound into view on the
+    /// next frame
+    pub scroll_to_selection: bool,
+
+    /// search query; if not empty, the browser lists sounds from all
+    /// directories whose names match it instead of the current directory
+    pub query: String,
+
+    /// virtual directory that is open instead of the current directory
+    pub virtual_dir: Option<VirtualDir>,
+
+    pub sort: SortMode,
+    pub filter: LengthFilter,
+
+    /// page of the listing that is on the pads in the pad browser
+    pub pad_page: usize,
+}
+
+/// How many entries of the sound browser fit on the pads at once. If there
+/// are more, the last two pads turn the pages instead.
+pub const PAD_PAGE_LEN: usize = 12;
+
+/// What a pad does in the pad browser.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PadEntry {
+    Dir(OsString),
+    Sound(SoundId),
+    PreviousPage,
+    NextPage,
+}
+
+/// Order of the sounds listed in the sound browser.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SortMode {
+    /// by path in directories, by relevance in search results and by recency
+    /// in the recent sounds
+    #[default]
+    Name,
+    /// shortest first
+    Duration,
+    /// slowest first; sounds without a tempo go last
+    Bpm,
+}
+
+impl SortMode {
+    pub fn next(self) -> Self {
+        match self {
+            SortMode::Name => SortMode::Duration,
+            SortMode::Duration => SortMode::Bpm,
+            SortMode::Bpm => SortMode::Name,
+        }
+    }
+}
+
+impl std::fmt::Display for SortMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            SortMode::Name => "name",
+            SortMode::Duration => "duration",
+            SortMode::Bpm => "BPM",
+        })
+    }
+}
+
+/// Sounds shorter than this are one-shots, and longer ones are loops.
+pub const ONE_SHOT_MAX: Duration = Duration::from_secs(2);
+
+/// Which sounds the sound browser lists, by length.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LengthFilter {
+    #[default]
+    All,
+    OneShots,
+    Loops,
+}
+
+impl LengthFilter {
+    pub fn next(self) -> Self {
+        match self {
+            LengthFilter::All => LengthFilter::OneShots,
+            LengthFilter::OneShots => LengthFilter::Loops,
+            LengthFilter::Loops => LengthFilter::All,
+        }
+    }
+
+    pub fn matches(self, sound: &SoundInfo) -> bool {
+        match self {
+            LengthFilter::All => true,
+            LengthFilter::OneShots => sound.duration < ONE_SHOT_MAX,
+            LengthFilter::Loops => sound.duration >= ONE_SHOT_MAX,
+        }
+    }
+}
+
+impl std::fmt::Display for LengthFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            LengthFilter::All => "all",
+            LengthFilter::OneShots => "one-shots",
+            LengthFilter::Loops => "loops",
+        })
+    }
+}
+
+/// Lists of sounds that the sound browser shows as directories at the top of
+/// the base directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VirtualDir {
+    Favorites,
+    Recent,
+}
+
+impl VirtualDir {
+    pub const ALL: [VirtualDir; 2] = [VirtualDir::Favorites, VirtualDir::Recent];
+}
+
+impl std::fmt::Display for VirtualDir {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            VirtualDir::Favorites => "Favorites",
+            VirtualDir::Recent => "Recent",
+        })
+    }
+}
+
+/// How many recently assigned sounds are remembered.
+pub(super) const RECENT_LEN: usize = 16;
+
+impl ReassignState {
+    fn update(&mut self, sounds: &[SoundInfo]) {
+        self.virtual_dir = None;
+        self.pad_page = 0;
+
+        if !self.query.is_empty() {
+            let mut matches: Vec<_> = sounds
+                .iter()
+                .filter_map(|s| {
+                    // the best match out of the file name and the tags
+                    let name = s.path.file_stem()?.to_string_lossy();
+                    let score = [
+                        Some(&*name),
+                        s.tags.title.as_deref(),
+                        s.tags.artist.as_deref(),
+                    ]
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|text| crate::util::fuzzy_score(&self.query, text))
+                    .max()?;
+
+                    Some((score, s))
+                })
+                .collect();
+
+            matches.sort_by(|(a, sa), (b, sb)| b.cmp(a).then_with(|| sa.path.cmp(&sb.path)));
+
+            self.sounds_in_dir = matches.into_iter().map(|(_, s)| s.id).collect();
+            self.subdirs_in_dir.clear();
+            self.arrange(sounds);
+            return;
+        }
+
+        self.sounds_in_dir = sounds
+            .iter()
+            .filter_map(|s| {
+                if let Some(parent) = s.library_path().parent() {
+                    if parent == self.current_dir {
+                        Some(s.id)
+                    } else {
+                        None
+                    }
+                } else {
+                    None
+                }
+            })
+            .collect();
+
+        self.sounds_in_dir
+            .sort_by_key(|id| sounds[id.0].library_path());
+        self.arrange(sounds);
+
+        self.subdirs_in_dir = sounds
+            .iter()
+            .filter_map(|s| {
+                let path = s.library_path();
+
+                if let Ok(partial_dir) = path.strip_prefix(&self.current_dir) {
+                    if partial_dir.iter().count() > 1 {
+                        trace!(
+                            "partial_dir = {partial_dir:?}, parent = {:?}, go",
+                            partial_dir.parent()
+                        );
+                        // path has multiple segments, grab the first one
+                        partial_dir.iter().nth(0).map(|s| s.to_owned())
+                    } else {
+                        trace!("partial_dir = {partial_dir:?}, no");
+                        // this is the last segment of the path, meaning that this
+                        // is not a subdir, but a file
+                        None
+                    }
+                } else {
+                    None
+                }
+            })
+            .collect();
+
+        info!("subdirs = {:?}", &self.subdirs_in_dir);
+    }
+
+    #[tracing::instrument(skip(sounds))]
+    pub fn select_dir(&mut self, dir: &OsStr, sounds: &[SoundInfo]) {
+        info!("selecting dir");
+        self.current_dir.push(dir);
+        self.update(sounds);
+    }
+
+    fn open_virtual_dir(&mut self, dir: VirtualDir, ids: Vec<SoundId>, sounds: &[SoundInfo]) {
+        info!("opening {dir} virtual dir");
+        self.query.clear();
+        self.virtual_dir = Some(dir);
+        self.pad_page = 0;
+        self.sounds_in_dir = ids;
+        self.subdirs_in_dir.clear();
+        self.arrange(sounds);
+    }
+
+    /// Drops the listed sounds that don't pass the length filter, and sorts
+    /// the rest. Sorting by name keeps the order that the list is already in.
+    fn arrange(&mut self, sounds: &[SoundInfo]) {
+        let filter = self.filter;
+        self.sounds_in_dir
+            .retain(|id| filter.matches(&sounds[id.0]));
+
+        match self.sort {
+            SortMode::Name => {}
+            SortMode::Duration => self.sounds_in_dir.sort_by_key(|id| sounds[id.0].duration),
+            SortMode::Bpm => {
+                self.sounds_in_dir
+                    .sort_by(|a, b| match (sounds[a.0].bpm(), sounds[b.0].bpm()) {
+                        (Some(a), Some(b)) => a.total_cmp(&b),
+                        (a, b) => a.is_none().cmp(&b.is_none()),
+                    })
+            }
+        }
+    }
+
+    #[tracing::instrument(skip(sounds))]
+    pub fn up_dir(&mut self, sounds: &[SoundInfo]) {
+        info!("going up a dir");
+
+        // virtual dirs are in the base dir, so just go back to the listing
+        if self.virtual_dir.is_some() {
+            self.update(sounds);
+            return;
+        }
+
+        if self.current_dir.starts_with(&self.base_dir) && self.current_dir != self.base_dir {
+            self.current_dir.pop();
+            self.update(sounds);
+        }
+    }
+
+    #[tracing::instrument(skip(sounds))]
+    pub fn search(&mut self, query: String, sounds: &[SoundInfo]) {
+        self.query = query;
+        self.update(sounds);
+    }
+
+    #[tracing::instrument]
+    pub fn select_sound(&mut self, id: SoundId) {
+        info!("selecting sound");
+        self.selection = Some(id);
+    }
+
+    /// What is on the pad at (x, y) in the pad browser. The listing is the
+    /// subdirectories and then the sounds, in rows from the top left.
+    pub fn pad_entry(&self, x: usize, y: usize) -> Option<PadEntry> {
+        let slot = (y - 1) * 4 + x;
+        let page_len = self.pad_page_len();
+
+        if slot == page_len {
+            return Some(PadEntry::PreviousPage);
+        } else if slot > page_len {
+            return Some(PadEntry::NextPage);
+        }
+
+        let index = self.pad_page * page_len + slot;
+        let dirs = self.subdirs_in_dir.len();
+
+        if index < dirs {
+            self.subdirs_in_dir
+                .iter()
+                .nth(index)
+                .cloned()
+                .map(PadEntry::Dir)
+        } else {
+            self.sounds_in_dir
+                .get(index - dirs)
+                .copied()
+                .map(PadEntry::Sound)
+        }
+    }
+
+    /// How many entries of the listing are on each page of the pad browser.
+    fn pad_page_len(&self) -> usize {
+        let len = self.subdirs_in_dir.len() + self.sounds_in_dir.len();
+
+        if len > PAD_PAGE_LEN {
+            PAD_PAGE_LEN - 2
+        } else {
+            PAD_PAGE_LEN
+        }
+    }
+
+    /// How many pages the listing takes up on the pads. There is always at
+    /// least one.
+    pub fn pad_pages(&self) -> usize {
+        let len = self.subdirs_in_dir.len() + self.sounds_in_dir.len();
+        let page_len = self.pad_page_len();
+        len.div_ceil(page_len).max(1)
+    }
+
+    /// Turns to the next page of the pad browser, or back to the first.
+    pub fn next_pad_page(&mut self) {
+        self.pad_page = (self.pad_page + 1) % self.pad_pages();
+    }
+
+    /// Turns to the previous page of the pad browser, or round to the last.
+    pub fn previous_pad_page(&mut self) {
+        let pages = self.pad_pages();
+        self.pad_page = (self.pad_page + pages - 1) % pages;
+    }
+
+    /// Moves the selection `delta` sounds forward or backward in the current
+    /// directory.
+    pub fn scroll_selection(&mut self, delta: isize) {
+        if self.sounds_in_dir.is_empty() {
+            return;
+        }
+
+        let current = self
+            .selection
+            .and_then(|id| self.sounds_in_dir.iter().position(|s| *s == id));
+
+        let next = match current {
+            Some(current) => current as isize + delta,
+            // nothing selected yet, so start from the top
+            None => delta.max(1) - 1,
+        };
+
+        let next = next.clamp(0, self.sounds_in_dir.len() as isize - 1) as usize;
+
+        self.selection = Some(self.sounds_in_dir[next]);
+        self.scroll_to_selection = true;
+    }
+}
+
+impl PlayState {
+    #[tracing::instrument(skip(self))]
+    pub fn reassign_sound_begin(&mut self, key: (usize, usize)) -> &mut ReassignState {
+        let base_dir = self.sounds_dir();
+
+        let mut state = ReassignState {
+            key,
+            current_dir: base_dir.clone(),
+            base_dir,
+            sounds_in_dir: vec![],
+            subdirs_in_dir: BTreeSet::new(),
+            selection: None,
+            scroll_to_selection: false,
+            query: String::new(),
+            virtual_dir: None,
+            sort: SortMode::default(),
+            filter: LengthFilter::default(),
+            pad_page: 0,
+        };
+
+        // update sounds_in_dir and subdirs_in_dir
+        state.update(&self.sounds[..]);
+
+        self.reassign = Some(state);
+
+        self.reassign.as_mut().unwrap()
+    }
+
+    pub fn reassign_sound_save(&mut self) {
+        if let Some(reassign) = &mut self.reassign {
+            let (x, y) = reassign.key;
+            let selection = reassign.selection;
+            let folder_color = selection.and_then(|id| self.folder_color(id));
+
+            self.edit_pads(|state| {
+                let key = &mut state.sound_keys[y - 1][x];
+
+                // cues and custom colors belong to the sound that they were
+                // set on
+                if key.binding != selection {
+                    key.cues.clear();
+                    key.color = None;
+                }
+
+                key.binding = selection;
+                key.folder_color = folder_color;
+                key.macro_binding = None;
+            });
+            self.reassign_sound_quit();
+
+            if let Some(id) = selection {
+                self.recent.retain(|r| *r != id);
+                self.recent.push_front(id);
+                self.recent.truncate(RECENT_LEN);
+            }
+        }
+    }
+
+    /// Sounds on the same side of the crossfader as the pad at (x, y) that are
+    /// in keys that clash with `sound`. Empty if the warning is turned off.
+    pub fn key_clashes(&self, (x, y): (usize, usize), sound: SoundId) -> Vec<SoundId> {
+        let Some(key) = self.sounds[sound.0].key.filter(|_| self.warn_key_clashes) else { return vec![]; };
+        let bus = Bus::of_column(x);
+
+        let mut clashes = vec![];
+
+        for (row, keys) in self.sound_keys.iter().enumerate() {
+            for (col, k) in keys.iter().enumerate() {
+                // the pad's own sound is about to be replaced
+                if Bus::of_column(col) != bus || (col, row + 1) == (x, y) {
+                    continue;
+                }
+
+                let Some(id) = k.binding else {
+                    continue;
+                };
+
+                if matches!(self.sounds[id.0].key, Some(other) if key.clashes(&other))
+                    && !clashes.contains(&id)
+                {
+                    clashes.push(id);
+                }
+            }
+        }
+
+        clashes
+    }
+
+    /// Opens a virtual directory in the sound browser.
+    pub fn reassign_sound_virtual_dir(&mut self, dir: VirtualDir) {
+        let sounds = match dir {
+            VirtualDir::Favorites => {
+                let mut favorites: Vec<_> = self.favorites.iter().copied().collect();
+                favorites.sort_by_key(|id| &self.sounds[id.0].path);
+                favorites
+            }
+            VirtualDir::Recent => self.recent.iter().copied().collect(),
+        };
+
+        if let Some(reassign) = &mut self.reassign {
+            reassign.open_virtual_dir(dir, sounds, &self.sounds[..]);
+        }
+    }
+
+    pub fn reassign_sound_cycle_sort(&mut self) {
+        if let Some(reassign) = &mut self.reassign {
+            reassign.sort = reassign.sort.next();
+            self.reassign_sound_refresh();
+        }
+    }
+
+    pub fn reassign_sound_cycle_filter(&mut self) {
+        if let Some(reassign) = &mut self.reassign {
+            reassign.filter = reassign.filter.next();
+            self.reassign_sound_refresh();
+        }
+    }
+
+    /// Lists the sounds in the browser again, after the sort mode or filter
+    /// has changed.
+    fn reassign_sound_refresh(&mut self) {
+        let Some(reassign) = &mut self.reassign else { return; };
+
+        match reassign.virtual_dir {
+            Some(dir) => self.reassign_sound_virtual_dir(dir),
+            None => reassign.update(&self.sounds[..]),
+        }
+    }
+
+    /// Stars the sound if it isn't starred, and unstars it if it is.
+    pub fn toggle_favorite(&mut self, id: SoundId) {
+        if !self.favorites.remove(&id) {
+            self.favorites.insert(id);
+        }
+
+        // keep the list up to date if it is open
+        if let Some(ReassignState {
+            virtual_dir: Some(VirtualDir::Favorites),
+            ..
+        }) = self.reassign
+        {
+            self.reassign_sound_virtual_dir(VirtualDir::Favorites);
+        }
+    }
+
+    pub fn reassign_sound_quit(&mut self) {
+        self.reassign = None;
+    }
+
+    pub fn reassign_sound_up(&mut self) {
+        if let Some(reassign) = &mut self.reassign {
+            reassign.up_dir(&self.sounds[..]);
+        }
+    }
+
+    /// Handles a pad being pressed in the pad browser: a folder is opened, and
+    /// a sound is selected and played on the bus of the pad being reassigned.
+    pub(super) fn reassign_sound_pad(&mut self, x: usize, y: usize) -> Vec<Effect> {
+        let Some(reassign) = &mut self.reassign else { return vec![]; };
+
+        match reassign.pad_entry(x, y) {
+            Some(PadEntry::Dir(dir)) => {
+                reassign.select_dir(&dir, &self.sounds[..]);
+                vec![]
+            }
+            Some(PadEntry::Sound(id)) => {
+                reassign.select_sound(id);
+                reassign.scroll_to_selection = true;
+
+                let bus = Bus::of_column(reassign.key.0);
+                vec![self.play(id, bus)]
+            }
+            Some(PadEntry::PreviousPage) => {
+                reassign.previous_pad_page();
+                vec![]
+            }
+            Some(PadEntry::NextPage) => {
+                reassign.next_pad_page();
+                vec![]
+            }
+            None => vec![],
+        }
+    }
+
+    pub fn reassign_sound_scroll(&mut self, delta: isize) {
+        if let Some(reassign) = &mut self.reassign {
+            reassign.scroll_selection(delta);
+        }
+    }
+
+    pub(super) fn reassign_command(&mut self, cmd: Command) -> Vec<Effect> {
+        let Some(reassign) = &mut self.reassign else { return vec![]; };
+
+        match cmd {
+            Command::SelectDir(dir) => reassign.select_dir(&dir, &self.sounds[..]),
+            Command::SelectSound(id) => reassign.select_sound(id),
+            Command::Search(query) => reassign.search(query, &self.sounds[..]),
+            Command::ScrolledToSelection => {
+                reassign.scroll_to_selection = false;
+                return vec![];
+            }
+            _ => return vec![],
+        }
+
+        self.keyboard_leds()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use crate::audio::{Bus, SoundId, SoundInfo};
+    use crate::chroma::{Key, Mode};
+    use crate::clock::VirtualClock;
+    use crate::driver::adafruit::seesaw::neopixel::Color;
+    use crate::engine::test::{led, play_state, sounds, tap};
+    use crate::engine::{Command, Effect, PadEntry, PlayState};
+
+    #[test]
+    fn warn_about_clashing_keys_on_the_same_side() {
+        let clock = VirtualClock::new();
+        // C, G and F# major
+        let sounds = [0, 7, 6]
+            .into_iter()
+            .enumerate()
+            .map(|(i, tonic)| SoundInfo {
+                id: SoundId(i),
+                path: PathBuf::from(format!("audio/{i}.wav")),
+                root: PathBuf::from("audio"),
+                duration: Duration::from_secs(4),
+                peaks: vec![],
+                loudness_db: -12.,
+                key: Some(Key {
+                    tonic,
+                    mode: Mode::Major,
+                }),
+                tags: Default::default(),
+                ready: true,
+            })
+            .collect();
+        let mut state = PlayState::new(sounds, Arc::new(clock.clone()));
+        state.sound_keys[0][0].binding = Some(SoundId(0));
+        state.sound_keys[0][3].binding = Some(SoundId(2));
+
+        assert_eq!(state.key_clashes((1, 2), SoundId(2)), vec![SoundId(0)]);
+        assert!(state.key_clashes((1, 2), SoundId(1)).is_empty());
+        assert_eq!(state.key_clashes((2, 2), SoundId(0)), vec![SoundId(2)]);
+
+        // replacing a pad's sound doesn't clash with the sound being replaced
+        assert!(state.key_clashes((0, 1), SoundId(2)).is_empty());
+
+        state.warn_key_clashes = false;
+        assert!(state.key_clashes((1, 2), SoundId(2)).is_empty());
+    }
+
+    #[test]
+    fn reassign_and_save() {
+        let clock = VirtualClock::new();
+        let mut state = play_state(&clock);
+
+        // F1 + key = reassign that key
+        state.key(0, 0, true);
+        let effects = state.key(1, 2, true);
+        state.key(1, 2, false);
+        state.key(0, 0, false);
+
+        assert_eq!(state.reassign.as_ref().map(|r| r.key), Some((1, 2)));
+        assert!(sounds(&effects).is_empty());
+        assert_eq!(led(&effects, 1, 2), Some(Color::WHITE));
+        assert_eq!(led(&effects, 3, 0), Some(Color::from_u8(0, 50, 0)));
+
+        // sound keys don't play anything while reassigning
+        assert!(sounds(&tap(&mut state, 2, 1)).is_empty());
+
+        state.reassign.as_mut().unwrap().select_sound(SoundId(0));
+
+        // F4 = save
+        let effects = tap(&mut state, 3, 0);
+        assert!(state.reassign.is_none());
+        assert_eq!(state.sound_keys[1][1].binding, Some(SoundId(0)));
+        assert_eq!(led(&effects, 1, 2), Some(Color::from_u8(50, 50, 50)));
+    }
+
+    #[test]
+    fn reassign_and_quit() {
+        let clock = VirtualClock::new();
+        let mut state = play_state(&clock);
+
+        state.key(0, 0, true);
+        state.key(1, 2, true);
+        state.key(0, 0, false);
+        state.reassign.as_mut().unwrap().select_sound(SoundId(0));
+
+        // F1 = quit without saving
+        tap(&mut state, 0, 0);
+        assert!(state.reassign.is_none());
+        assert_eq!(state.sound_keys[1][1].binding, None);
+    }
+
+    #[test]
+    fn search_lists_matches_from_all_dirs() {
+        let clock = VirtualClock::new();
+        let sounds = [
+            "Kicks/Deep Kick.wav",
+            "Claps/Old Clap.wav",
+            "Claps/Cool Lamp Pad.wav",
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(i, path)| SoundInfo {
+            id: SoundId(i),
+            path: PathBuf::from("audio").join(path),
+            root: PathBuf::from("audio"),
+            duration: Duration::from_millis(500),
+            peaks: vec![],
+            loudness_db: -12.,
+            key: None,
+            tags: Default::default(),
+            ready: true,
+        })
+        .collect();
+        let mut state = PlayState::new(sounds, Arc::new(clock.clone()));
+
+        state.reassign_sound_begin((0, 1));
+        state.command(Command::Search("clap".to_owned()));
+
+        let reassign = state.reassign.as_ref().unwrap();
+        assert_eq!(reassign.sounds_in_dir, vec![SoundId(1), SoundId(2)]);
+        assert!(reassign.subdirs_in_dir.is_empty());
+
+        // clearing the search goes back to the directory
+        state.command(Command::Search(String::new()));
+
+        let reassign = state.reassign.as_ref().unwrap();
+        assert_eq!(reassign.current_dir, PathBuf::from("audio"));
+        assert_eq!(reassign.subdirs_in_dir.len(), 2);
+    }
+
+    #[test]
+    fn each_audio_root_is_a_top_level_folder() {
+        let clock = VirtualClock::new();
+        let sounds = [
+            ("/home/pi/audio", "Kicks/Deep Kick.wav"),
+            ("/media/usb/packs", "Lofi/Claps/Old Clap.wav"),
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(i, (root, path))| SoundInfo {
+            id: SoundId(i),
+            path: PathBuf::from(root).join(path),
+            root: PathBuf::from(root),
+            duration: Duration::from_millis(500),
+            peaks: vec![],
+            loudness_db: -12.,
+            key: None,
+            tags: Default::default(),
+            ready: i == 0,
+        })
+        .collect();
+        let mut state = PlayState::new(sounds, Arc::new(clock.clone()));
+        let subdirs = |state: &PlayState| {
+            let reassign = state.reassign.as_ref().unwrap();
+            reassign.subdirs_in_dir.iter().cloned().collect::<Vec<_>>()
+        };
+
+        state.reassign_sound_begin((0, 1));
+        assert_eq!(subdirs(&state), ["audio", "packs"]);
+
+        state.command(Command::SelectDir("packs".into()));
+        assert_eq!(subdirs(&state), ["Lofi"]);
+
+        // sounds that were decoded before the stick was unplugged still play
+        state.set_root_available(PathBuf::from("/media/usb/packs"), false);
+        assert!(state.is_unavailable(SoundId(1)));
+        state.set_root_available(PathBuf::from("/home/pi/audio"), false);
+        assert!(!state.is_unavailable(SoundId(0)));
+
+        state.set_root_available(PathBuf::from("/media/usb/packs"), true);
+        assert!(!state.is_unavailable(SoundId(1)));
+    }
+
+    #[test]
+    fn reassign_from_the_pads() {
+        let clock = VirtualClock::new();
+        let paths = ["Kicks/Deep Kick.wav".to_owned(), "FX/Riser.wav".to_owned()]
+            .into_iter()
+            .chain((0..12).map(|i| format!("Loop {i:02}.wav")));
+        let sounds = paths
+            .enumerate()
+            .map(|(i, path)| SoundInfo {
+                id: SoundId(i),
+                path: PathBuf::from("audio").join(path),
+                root: PathBuf::from("audio"),
+                duration: Duration::from_millis(500),
+                peaks: vec![],
+                loudness_db: -12.,
+                key: None,
+                tags: Default::default(),
+                ready: true,
+            })
+            .collect();
+        let mut state = PlayState::new(sounds, Arc::new(clock.clone()));
+        state.pad_browser = true;
+
+        state.key(0, 0, true);
+        tap(&mut state, 0, 1);
+        state.key(0, 0, false);
+
+        // folders first, then sounds
+        let entry = |state: &PlayState, x, y| state.reassign.as_ref().unwrap().pad_entry(x, y);
+        assert_eq!(entry(&state, 0, 1), Some(PadEntry::Dir("FX".into())));
+        assert_eq!(entry(&state, 1, 1), Some(PadEntry::Dir("Kicks".into())));
+        assert_eq!(entry(&state, 2, 1), Some(PadEntry::Sound(SoundId(2))));
+
+        // there are more than 12, so the last two pads turn the pages
+        assert_eq!(entry(&state, 1, 3), Some(PadEntry::Sound(SoundId(9))));
+        assert_eq!(entry(&state, 2, 3), Some(PadEntry::PreviousPage));
+        assert_eq!(entry(&state, 3, 3), Some(PadEntry::NextPage));
+
+        tap(&mut state, 3, 3);
+        assert_eq!(entry(&state, 0, 1), Some(PadEntry::Sound(SoundId(10))));
+        assert_eq!(entry(&state, 0, 2), None);
+
+        // F3 = next page too, and both wrap around
+        tap(&mut state, 2, 0);
+        assert_eq!(entry(&state, 0, 1), Some(PadEntry::Dir("FX".into())));
+        tap(&mut state, 2, 3);
+        tap(&mut state, 2, 3);
+        assert_eq!(entry(&state, 0, 1), Some(PadEntry::Dir("FX".into())));
+
+        // a sound is picked and heard on the bus of the pad being reassigned
+        let effects = tap(&mut state, 2, 1);
+        assert!(effects.contains(&Effect::PlaySound(SoundId(2), Bus::A)));
+        assert_eq!(led(&effects, 2, 1), Some(Color::WHITE));
+
+        // pressing a folder opens it
+        tap(&mut state, 1, 1);
+        assert_eq!(entry(&state, 0, 1), Some(PadEntry::Sound(SoundId(0))));
+        tap(&mut state, 0, 1);
+
+        // F4 = save
+        tap(&mut state, 3, 0);
+        assert!(state.reassign.is_none());
+        assert_eq!(state.sound_keys[0][0].binding, Some(SoundId(0)));
+    }
+
+    #[test]
+    fn sort_and_filter_by_length() {
+        let clock = VirtualClock::new();
+        let sounds = [("a.wav", 4000), ("b.wav", 300), ("c.wav", 1000)]
+            .into_iter()
+            .enumerate()
+            .map(|(i, (path, ms))| SoundInfo {
+                id: SoundId(i),
+                path: PathBuf::from("audio").join(path),
+                root: PathBuf::from("audio"),
+                duration: Duration::from_millis(ms),
+                peaks: vec![],
+                loudness_db: -12.,
+                key: None,
+                tags: Default::default(),
+                ready: true,
+            })
+            .collect();
+        let mut state = PlayState::new(sounds, Arc::new(clock.clone()));
+        let listed = |state: &PlayState| state.reassign.as_ref().unwrap().sounds_in_dir.clone();
+
+        state.reassign_sound_begin((0, 1));
+        assert_eq!(listed(&state), vec![SoundId(0), SoundId(1), SoundId(2)]);
+
+        // F3 = sort by duration
+        tap(&mut state, 2, 0);
+        assert_eq!(listed(&state), vec![SoundId(1), SoundId(2), SoundId(0)]);
+
+        state.command(Command::CycleFilter);
+        assert_eq!(listed(&state), vec![SoundId(1), SoundId(2)]);
+
+        state.command(Command::CycleFilter);
+        assert_eq!(listed(&state), vec![SoundId(0)]);
+    }
+
+    #[test]
+    fn favorites_and_recent_are_saved() {
+        let clock = VirtualClock::new();
+        let mut state = play_state(&clock);
+
+        // F1 + key, select, key = star, F4 = save
+        state.key(0, 0, true);
+        state.key(1, 2, true);
+        state.key(0, 0, false);
+        state.key(1, 2, false);
+        state.reassign.as_mut().unwrap().select_sound(SoundId(0));
+        let effects = tap(&mut state, 1, 2);
+        assert_eq!(led(&effects, 1, 2), Some(Color::from_u8(255, 200, 0)));
+        tap(&mut state, 3, 0);
+
+        let saved = state.saved();
+        let path = PathBuf::from("audio/kick.wav");
+        assert_eq!(
+            saved.favorites.into_iter().collect::<Vec<_>>(),
+            vec![path.clone()]
+        );
+        assert_eq!(saved.recent, vec![path]);
+
+        // and so is the BPM
+        state.bpm_nudge(0.1);
+        assert_eq!(state.saved().bpm, Some(60.1));
+
+        let mut restored = play_state(&clock);
+        restored.restore(&state.saved());
+        assert!(restored.favorites.contains(&SoundId(0)));
+        assert_eq!(restored.recent, [SoundId(0)]);
+        assert_eq!(restored.saved().bpm, Some(60.1));
+    }
+}
@@ -0,0 +1,973 @@
+//! The tasks that [`Engine::start`] spawns, which run the looper and pass
+//! events and the effects of the state's transitions between the
+//! subsystems.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{watch, Mutex};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, trace, trace_span, warn};
+
+use crate::audio::Bus;
+use crate::clock::Clock;
+use crate::config::PlayConfig;
+use crate::driver::adafruit::seesaw::keypad;
+use crate::driver::adafruit::seesaw::neopixel::Color;
+use crate::effect::Chains;
+use crate::grid::Grid;
+use crate::health::Health;
+use crate::journal::Journal;
+use crate::keyboard::Priority;
+use crate::latency::{LatencyStats, TickJitter, Trace};
+use crate::pad::PadSettings;
+use crate::palette::Palette;
+use crate::persist::SavedState;
+use crate::pwm::PwmOutputs;
+use crate::script::{Hook, Script};
+use crate::setlist::SetList;
+use crate::{audio, encoder, keyboard, lighting};
+
+use super::{
+    pad_number, push_toast, AppState, Command, DeviceChange, Diagnostics, Effect, Fault, Input,
+    LoadingAnimation, LoadingStage, LoadingState, PlayState, Problem, Snapshot, Subsystem, Toast,
+    TICKS_PER_BEAT,
+};
+
+/// How long the F1 LED stays on or off while blinking a health warning of the
+/// Pi or the NeoTrellis.
+const HEALTH_BLINK: Duration = Duration::from_millis(400);
+
+/// Channels that the engine sends the effects of state transitions to.
+#[derive(Clone)]
+pub(super) struct Outputs {
+    pub(super) kb_cmd_tx: flume::Sender<keyboard::Command>,
+    /// width and height of the keyboard's grid
+    pub(super) grid_size: (usize, usize),
+    pub(super) enc_cmd_tx: flume::Sender<encoder::Command>,
+    pub(super) audio_cmd_tx: flume::Sender<audio::Command>,
+    pub(super) trigger_tx: flume::Sender<()>,
+    pub(super) light_tx: flume::Sender<lighting::Event>,
+    /// None if there is no script to run the hooks of
+    pub(super) hook_tx: Option<flume::Sender<Hook>>,
+}
+
+/// Channels that the engine hears commands and the subsystems' events on.
+pub(super) struct Inputs {
+    pub(super) cmd_rx: flume::Receiver<Command>,
+    pub(super) kb_evt_rx: flume::Receiver<keyboard::Event>,
+    pub(super) enc_evt_rx: flume::Receiver<encoder::Event>,
+    pub(super) audio_evt_rx: flume::Receiver<audio::Event>,
+    pub(super) fault_rx: flume::Receiver<Fault>,
+    pub(super) problem_rx: flume::Receiver<Problem>,
+    pub(super) health_rx: flume::Receiver<Health>,
+    pub(super) hook_rx: flume::Receiver<Hook>,
+}
+
+impl Outputs {
+    /// Passes `hook` on to the script, if there is one.
+    fn hook(&self, hook: Hook) {
+        if let Some(hook_tx) = &self.hook_tx {
+            let _ = hook_tx.send(hook);
+        }
+    }
+
+    /// Carries out `effects`. LED changes are drawn with the given priority.
+    fn execute(&self, effects: impl IntoIterator<Item = Effect>, priority: Priority) {
+        self.execute_traced(effects, priority, None)
+    }
+
+    /// Carries out `effects` that were caused by a key press, passing its
+    /// trace on with any sounds that it plays.
+    fn execute_traced(
+        &self,
+        effects: impl IntoIterator<Item = Effect>,
+        priority: Priority,
+        trace: Option<Trace>,
+    ) {
+        // LEDs are sent after the rest, so that a whole grid of them can be
+        // sent as one frame. The last colour of each one wins.
+        let mut leds = BTreeMap::new();
+
+        for effect in effects {
+            match effect {
+                Effect::PlaySound(sound_id, bus) => {
+                    let _ = self.audio_cmd_tx.send(audio::Command::Play {
+                        sound_id,
+                        bus,
+                        start: Duration::ZERO,
+                        settings: PadSettings::default(),
+                        pad: None,
+                        trace,
+                    });
+                }
+                Effect::PlaySoundFrom(sound_id, bus, start) => {
+                    let _ = self.audio_cmd_tx.send(audio::Command::Play {
+                        sound_id,
+                        bus,
+                        start,
+                        settings: PadSettings::default(),
+                        pad: None,
+                        trace,
+                    });
+                }
+                Effect::PlayPad {
+                    sound,
+                    bus,
+                    start,
+                    settings,
+                    pad,
+                } => {
+                    let _ = self.audio_cmd_tx.send(audio::Command::Play {
+                        sound_id: sound,
+                        bus,
+                        start,
+                        settings,
+                        pad: pad.map(pad_number),
+                        trace,
+                    });
+                }
+                Effect::ReleasePad(x, y) => {
+                    let _ = self.audio_cmd_tx.send(audio::Command::Release {
+                        pad: pad_number((x, y)),
+                    });
+                }
+                Effect::PlayLoop {
+                    sound,
+                    bus,
+                    settings,
+                    voice,
+                    length,
+                    seam,
+                } => {
+                    let _ = self.audio_cmd_tx.send(audio::Command::PlayLoop {
+                        sound_id: sound,
+                        bus,
+                        settings,
+                        voice,
+                        length,
+                        seam,
+                    });
+                }
+                Effect::StopLoop(voice) => {
+                    let _ = self.audio_cmd_tx.send(audio::Command::StopLoop { voice });
+                }
+                Effect::SetVolume(volume) => {
+                    let _ = self.audio_cmd_tx.send(audio::Command::SetVolume { volume });
+                }
+                Effect::SetCrossfade(position) => {
+                    let _ = self
+                        .audio_cmd_tx
+                        .send(audio::Command::SetCrossfade { position });
+                }
+                Effect::SetFilterCutoff(hz) => {
+                    let _ = self
+                        .audio_cmd_tx
+                        .send(audio::Command::SetFilterCutoff { hz });
+                }
+                Effect::SetEq(bus, kills) => {
+                    let _ = self.audio_cmd_tx.send(audio::Command::SetEq { bus, kills });
+                }
+                Effect::SetFx(fx) => {
+                    let _ = self.audio_cmd_tx.send(audio::Command::SetFx { fx });
+                }
+                Effect::PlayAnimation(name) => play_animation(&self.kb_cmd_tx, &name),
+                Effect::SetLed { x, y, color } => {
+                    leds.insert((x, y), color);
+                }
+                Effect::SetEncoderColor(color) => {
+                    let _ = self.enc_cmd_tx.send(encoder::Command::SetColor(color));
+                }
+                Effect::Deck { deck, command } => {
+                    let _ = self
+                        .audio_cmd_tx
+                        .send(audio::Command::Deck { deck, command });
+                }
+                Effect::TriggerPulse => {
+                    let _ = self.trigger_tx.send(());
+                }
+                Effect::Beat(beat) => {
+                    let _ = self.light_tx.send(lighting::Event::Beat(beat));
+                    self.hook(Hook::Beat(beat));
+                }
+                Effect::Record(duration) => {
+                    let _ = self.audio_cmd_tx.send(audio::Command::Record { duration });
+                }
+                Effect::ExportKit { path, kit, sounds } => {
+                    let _ = self
+                        .audio_cmd_tx
+                        .send(audio::Command::ExportKit { path, kit, sounds });
+                }
+                Effect::ImportKit(path) => {
+                    let _ = self.audio_cmd_tx.send(audio::Command::ImportKit { path });
+                }
+                Effect::Render {
+                    hits,
+                    length,
+                    target,
+                } => {
+                    let _ = self.audio_cmd_tx.send(audio::Command::Render {
+                        hits,
+                        length,
+                        target,
+                    });
+                }
+                Effect::CancelRender => {
+                    let _ = self.audio_cmd_tx.send(audio::Command::CancelRender);
+                }
+                Effect::StartTake(pattern) => {
+                    let _ = self
+                        .audio_cmd_tx
+                        .send(audio::Command::StartTake { pattern });
+                }
+                Effect::StopTape => {
+                    let _ = self.audio_cmd_tx.send(audio::Command::StopTape);
+                }
+                Effect::LoopTriggered(sound) => self.hook(Hook::LoopTrigger(sound)),
+                Effect::SetDevice(change) => {
+                    let _ = self.kb_cmd_tx.send(match change {
+                        DeviceChange::Brightness(brightness) => {
+                            keyboard::Command::SetBrightness { brightness }
+                        }
+                        DeviceChange::Kit(kit) => keyboard::Command::SetKit { kit },
+                        DeviceChange::Address(address) => keyboard::Command::SetAddress { address },
+                    });
+                }
+            }
+        }
+
+        self.set_leds(leds, priority);
+    }
+
+    /// Sets the LEDs at the (x, y) positions to their colours. They are
+    /// drawn in one frame if every one of them is set, e.g. when switching
+    /// modes.
+    fn set_leds(&self, mut leds: BTreeMap<(usize, usize), Color>, priority: Priority) {
+        let (width, height) = self.grid_size;
+        let whole = (0..height).all(|y| (0..width).all(|x| leds.contains_key(&(x, y))));
+
+        if whole {
+            let frame = Grid::from_fn(width, height, |x, y| keyboard::PixelState::Solid {
+                color: leds.remove(&(x, y)).unwrap_or_default(),
+                update: true,
+            });
+
+            let _ = self.kb_cmd_tx.send(keyboard::Command::SetFrame(frame));
+        }
+
+        for ((x, y), color) in leds {
+            set_solid_color(&self.kb_cmd_tx, x, y, color, priority);
+        }
+    }
+
+    /// Sets every LED on the keyboard to `color`.
+    fn set_all_keys(&self, color: Color) {
+        let (width, height) = self.grid_size;
+        let leds = (0..height).flat_map(|y| (0..width).map(move |x| ((x, y), color)));
+        self.set_leds(leds.collect(), Priority::Bulk);
+    }
+}
+
+pub(super) async fn process_loops(
+    state: Arc<Mutex<AppState>>,
+    clock: Arc<dyn Clock>,
+    outputs: Outputs,
+    jitter: Arc<std::sync::Mutex<TickJitter>>,
+) {
+    // the last tick that was processed, so that every tick is processed
+    // exactly once even if this task wakes up late
+    let mut last_tick = None;
+
+    loop {
+        let due = match &mut *state.lock().await {
+            AppState::Play(state) => state.tick_due(&mut last_tick),
+            AppState::Loading(_) => {
+                last_tick = None;
+                None
+            }
+        };
+
+        let deadline = match due {
+            Some((effects, deadline)) => {
+                outputs.execute(effects, Priority::Bulk);
+                deadline
+            }
+            None => clock.now() + Duration::from_millis(250),
+        };
+
+        clock.sleep_until(deadline).await;
+
+        if last_tick.is_some() {
+            let late = clock.now().saturating_duration_since(deadline);
+            jitter.lock().unwrap().record(late);
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(super) async fn process_events(
+    ct: CancellationToken,
+    state: Arc<Mutex<AppState>>,
+    outputs: Outputs,
+    inputs: Inputs,
+    clock: Arc<dyn Clock>,
+    snapshot_tx: watch::Sender<Snapshot>,
+    play_config: PlayConfig,
+    palette: Palette,
+    mut pwm: PwmOutputs,
+    state_path: PathBuf,
+    journal: Arc<std::sync::Mutex<Journal>>,
+) -> anyhow::Result<()> {
+    let Inputs {
+        cmd_rx,
+        kb_evt_rx,
+        enc_evt_rx,
+        audio_evt_rx,
+        fault_rx,
+        problem_rx,
+        health_rx,
+        hook_rx,
+    } = inputs;
+    let mut faults = BTreeMap::new();
+    let mut latency = LatencyStats::default();
+    let mut toasts = VecDeque::new();
+    let mut level = audio::Level::default();
+    let mut diagnostics = Diagnostics::default();
+    let mut health = Health::default();
+
+    // the F1 LED blinks while the Pi or the NeoTrellis has a health warning
+    let mut blink = tokio::time::interval(HEALTH_BLINK);
+    blink.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let mut blink_on = false;
+
+    // the revision of the state that was last published, if it was playing
+    let mut published = None;
+
+    // the set list entry that the NeoTrellis is set to start on, until the
+    // sounds have loaded and it has been gone to
+    let mut start_kit: Option<u8> = None;
+
+    let mut saved = SavedState::load(&state_path).unwrap_or_else(|err| {
+        warn!("failed to load saved state: {err:?}");
+        push_toast(
+            &mut toasts,
+            Problem {
+                subsystem: None,
+                message: format!("favorites and recent sounds were lost: {err:#}"),
+            },
+        );
+        SavedState::default()
+    });
+
+    let set_list = match &play_config.set_list {
+        Some(path) => SetList::load(path).unwrap_or_else(|err| {
+            warn!("failed to load set list: {err:?}");
+            push_toast(
+                &mut toasts,
+                Problem {
+                    subsystem: None,
+                    message: format!("the set list couldn't be loaded: {err:#}"),
+                },
+            );
+            SetList::default()
+        }),
+        None => SetList::default(),
+    };
+    let set_list = Arc::new(set_list);
+
+    // what the audio subsystem was last told, so that it is only told again
+    // when it changes
+    let mut sent_beat = None;
+    let mut sent_chains = None;
+
+    let mut script = play_config.script.as_ref().and_then(|path| {
+        Script::load(path, outputs.grid_size)
+            .map_err(|err| {
+                warn!("failed to load script: {err:?}");
+                push_toast(
+                    &mut toasts,
+                    Problem {
+                        subsystem: None,
+                        message: format!("the script couldn't be loaded: {err:#}"),
+                    },
+                );
+            })
+            .ok()
+    });
+
+    loop {
+        tokio::select! {
+            _ = ct.cancelled() => break,
+            Ok(cmd) = cmd_rx.recv_async() => {
+                match &mut *state.lock().await {
+                    AppState::Play(state) => {
+                        let effects = journal.lock().unwrap().apply(state, Input::Command(cmd));
+                        outputs.execute(effects, Priority::Urgent);
+                    }
+                    AppState::Loading(state) => process_loading_command(state, cmd, &ct, &outputs),
+                }
+            }
+            // disabled if the keyboard's channel is closed, so that the rest
+            // of the unit keeps running without it
+            Ok(evt) = kb_evt_rx.recv_async() => {
+                // the keyboard sends its settings as soon as it has started,
+                // so any event means that it is running again
+                clear_restarting(&mut faults, Subsystem::Keyboard);
+
+                match evt {
+                    keyboard::Event::Status { version, temperature } => {
+                        for cmd in pwm.temperature(temperature) {
+                            let _ = outputs.kb_cmd_tx.send(cmd);
+                        }
+
+                        debug!("neotrellis temperature: {temperature}°C");
+                        let hot = keyboard::is_hot(temperature, diagnostics.seesaw_hot);
+
+                        if hot && !diagnostics.seesaw_hot {
+                            warn!("the neotrellis is hot: {temperature}°C");
+                            push_toast(
+                                &mut toasts,
+                                Problem {
+                                    subsystem: Some(Subsystem::Keyboard),
+                                    message: format!("NeoTrellis is hot: {temperature}°C"),
+                                },
+                            );
+                        } else if !hot && diagnostics.seesaw_hot {
+                            info!("the neotrellis has cooled down to {temperature}°C");
+
+                            // put F1 back unless the Pi still has warnings
+                            if health.warnings().is_empty() {
+                                if let AppState::Play(state) = &*state.lock().await {
+                                    outputs.execute(state.keyboard_leds(), Priority::Bulk);
+                                }
+                            }
+                        }
+
+                        diagnostics.seesaw_version = Some(version);
+                        diagnostics.seesaw_temperature = Some(temperature);
+                        diagnostics.seesaw_hot = hot;
+                    }
+                    keyboard::Event::Settings { settings, address } => {
+                        debug!("neotrellis at {address:#04x} has settings {settings:?}");
+
+                        // they are sent when the keyboard starts, and when it
+                        // has been moved to another address and reset
+                        for cmd in pwm.start() {
+                            let _ = outputs.kb_cmd_tx.send(cmd);
+                        }
+
+                        // the unit starts on its kit when the settings are
+                        // first read, rather than whenever they change
+                        if diagnostics.device_settings.is_none() {
+                            start_kit = settings.kit;
+                        }
+
+                        diagnostics.device_settings = Some(settings);
+                        diagnostics.seesaw_address = Some(address);
+                    }
+                    evt => process_keyboard_event(&mut *state.lock().await, evt, &journal, &outputs),
+                }
+            }
+            // the encoder is optional, so this branch is disabled if its
+            // channel is closed
+            Ok(evt) = enc_evt_rx.recv_async() => {
+                clear_restarting(&mut faults, Subsystem::Encoder);
+
+                if let AppState::Play(state) = &mut *state.lock().await {
+                    let effects = journal.lock().unwrap().apply(state, Input::Encoder(evt));
+                    outputs.execute(effects, Priority::Urgent);
+                }
+            }
+            // likewise for the audio subsystem
+            Ok(evt) = audio_evt_rx.recv_async() => {
+                match evt {
+                    audio::Event::Played { trace } => {
+                        trace!(
+                            "key to audio latency: {:?}, {:?}",
+                            trace.total(),
+                            trace.stages().collect::<Vec<_>>()
+                        );
+                        latency.record(&trace);
+                        compensate_latency(&mut *state.lock().await, &latency, &play_config, &journal);
+                    }
+                    audio::Event::Level(new_level) => {
+                        level = new_level;
+                        let _ = outputs.kb_cmd_tx.send(keyboard::Command::Level(level.peak));
+
+                        for cmd in pwm.level(level.peak) {
+                            let _ = outputs.kb_cmd_tx.send(cmd);
+                        }
+                        let _ = outputs.light_tx.send(lighting::Event::Level(level.peak));
+                    }
+                    audio::Event::OutputUnavailable { error } => {
+                        diagnostics.audio_device = None;
+                        faults.insert(
+                            Subsystem::Audio,
+                            Fault::Degraded {
+                                subsystem: Subsystem::Audio,
+                                error: format!("sounds are muted: {error}"),
+                            },
+                        );
+                    }
+                    audio::Event::Voices(voices) => diagnostics.voices = voices,
+                    audio::Event::Duplicates(duplicates) => diagnostics.duplicates = duplicates,
+                    audio::Event::OutputStats { period_frames, underruns } => {
+                        diagnostics.period_frames = (period_frames > 0).then_some(period_frames);
+
+                        if underruns > diagnostics.underruns {
+                            warn!("audio output has run out of samples {underruns} times");
+                        }
+
+                        diagnostics.underruns = underruns;
+                    }
+                    audio::Event::OutputAvailable { device } => {
+                        diagnostics.audio_device = Some(device);
+
+                        if let Some(Fault::Degraded { .. }) = faults.get(&Subsystem::Audio) {
+                            faults.remove(&Subsystem::Audio);
+                        }
+                    }
+                    evt => {
+                        // a restarted audio subsystem starts over without them
+                        if let audio::Event::LoadingEnd { .. } = &evt {
+                            sent_beat = None;
+                            sent_chains = None;
+                            clear_restarting(&mut faults, Subsystem::Audio);
+                        }
+
+                        process_audio_event(
+                            &mut *state.lock().await,
+                            evt,
+                            clock.clone(),
+                            &play_config,
+                            &saved,
+                            &set_list,
+                            &palette,
+                            &journal,
+                            &outputs,
+                        );
+                    }
+                }
+            }
+            // disabled if there is no script
+            Ok(hook) = hook_rx.recv_async() => {
+                if let AppState::Play(state) = &mut *state.lock().await {
+                    process_hook(state, &mut script, hook, &journal, &outputs, &mut toasts);
+                }
+            }
+            Ok(fault) = fault_rx.recv_async() => {
+                process_fault(&*state.lock().await, &fault, &outputs);
+                faults.insert(fault.subsystem(), fault);
+            }
+            Ok(problem) = problem_rx.recv_async() => {
+                debug!("problem reported: {problem:?}");
+                push_toast(&mut toasts, problem);
+            }
+            // health is only read on a pi, so this branch is disabled if its
+            // channel is closed
+            Ok(new_health) = health_rx.recv_async() => {
+                let old_warnings = health.warnings();
+
+                for warning in new_health.warnings() {
+                    if !old_warnings.contains(&warning) {
+                        warn!("pi health warning: {warning} ({new_health:?})");
+                        push_toast(
+                            &mut toasts,
+                            Problem {
+                                subsystem: None,
+                                message: format!("Pi warning: {warning}"),
+                            },
+                        );
+                    }
+                }
+
+                // put F1 back once the warnings are gone
+                if new_health.warnings().is_empty()
+                    && !old_warnings.is_empty()
+                    && !diagnostics.seesaw_hot
+                {
+                    if let AppState::Play(state) = &*state.lock().await {
+                        outputs.execute(state.keyboard_leds(), Priority::Bulk);
+                    }
+                }
+
+                health = new_health;
+            }
+            _ = blink.tick(), if !health.warnings().is_empty() || diagnostics.seesaw_hot => {
+                blink_on = !blink_on;
+
+                if let AppState::Play(state) = &*state.lock().await {
+                    outputs.execute(state.health_led(blink_on), Priority::Bulk);
+                }
+            }
+        }
+
+        if let Some(index) = start_kit {
+            if let AppState::Play(state) = &mut *state.lock().await {
+                start_kit = None;
+
+                let cmd = Command::GoToSetEntry(index as usize);
+                let effects = journal.lock().unwrap().apply(state, Input::Command(cmd));
+                outputs.execute(effects, Priority::Bulk);
+            }
+        }
+
+        // the state is only copied out, and checked for anything to save,
+        // when it has changed. It's always copied while loading, which is
+        // cheap
+        let current = {
+            let state = state.lock().await;
+            let revision = match &*state {
+                AppState::Play(state) => Some(state.revision()),
+                AppState::Loading(_) => None,
+            };
+
+            (revision.is_none() || revision != published).then(|| {
+                published = revision;
+                state.clone()
+            })
+        };
+
+        let mut to_save = None;
+
+        if let Some(AppState::Play(state)) = &current {
+            sync_audio(state, &mut sent_beat, &mut sent_chains, &outputs);
+
+            // only write the saved state when something in it has changed
+            to_save = Some(state.saved()).filter(|s| *s != saved);
+        }
+
+        snapshot_tx.send_modify(|snapshot| {
+            if let Some(current) = current {
+                snapshot.state = current;
+            }
+
+            snapshot.faults = faults.clone();
+            snapshot.latency = latency.clone();
+            snapshot.toasts = toasts.clone();
+            snapshot.level = level;
+            snapshot.diagnostics = diagnostics.clone();
+            snapshot.health = health;
+        });
+
+        if let Some(to_save) = to_save {
+            if let Err(err) = to_save.save(&state_path).await {
+                warn!("failed to save state: {err:?}");
+                push_toast(
+                    &mut toasts,
+                    Problem {
+                        subsystem: None,
+                        message: format!("failed to save favorites: {err:#}"),
+                    },
+                );
+            }
+
+            saved = to_save;
+        }
+    }
+
+    Ok(())
+}
+
+/// Tells the audio subsystem how long a beat is and what the effect chains
+/// are, if they have changed since it was last told, so that the effects keep
+/// in time with the looper.
+fn sync_audio(
+    state: &PlayState,
+    sent_beat: &mut Option<Duration>,
+    sent_chains: &mut Option<Chains>,
+    outputs: &Outputs,
+) {
+    let beat = state.tick * TICKS_PER_BEAT as u32;
+
+    if *sent_beat != Some(beat) {
+        let _ = outputs.audio_cmd_tx.send(audio::Command::SetTempo { beat });
+        *sent_beat = Some(beat);
+    }
+
+    if sent_chains.as_ref() != Some(&state.chains) {
+        for bus in [Bus::A, Bus::B] {
+            let effects = state.chains.bus(bus).to_vec();
+            let _ = outputs
+                .audio_cmd_tx
+                .send(audio::Command::SetChain { bus, effects });
+        }
+
+        *sent_chains = Some(state.chains.clone());
+    }
+}
+
+/// Moves recorded pad presses back by the measured key to audio latency, if
+/// it has changed and none is configured.
+fn compensate_latency(
+    state: &mut AppState,
+    latency: &LatencyStats,
+    play_config: &PlayConfig,
+    journal: &std::sync::Mutex<Journal>,
+) {
+    let AppState::Play(state) = state else { return; };
+
+    if play_config.latency_compensation_ms.is_some() {
+        return;
+    }
+
+    if let Some(compensation) = latency.compensation() {
+        if compensation != state.latency_compensation {
+            debug!("compensating for {compensation:?} of latency");
+            let command = Command::SetLatencyCompensation(compensation);
+            journal
+                .lock()
+                .unwrap()
+                .apply(state, Input::Command(command));
+        }
+    }
+}
+
+/// Runs a script's hook, and carries out what it asked for. A script that
+/// fails is stopped, so that it doesn't fail again on every beat.
+fn process_hook(
+    state: &mut PlayState,
+    script: &mut Option<Script>,
+    hook: Hook,
+    journal: &std::sync::Mutex<Journal>,
+    outputs: &Outputs,
+    toasts: &mut VecDeque<Toast>,
+) {
+    let Some(running) = script else { return; };
+
+    match running.run(hook) {
+        Ok(actions) => {
+            for action in actions {
+                let effects = journal.lock().unwrap().apply(state, Input::Script(action));
+                outputs.execute(effects, Priority::Urgent);
+            }
+        }
+        Err(err) => {
+            warn!("script failed: {err:?}");
+            push_toast(
+                toasts,
+                Problem {
+                    subsystem: None,
+                    message: format!("the script was stopped: {err:#}"),
+                },
+            );
+            *script = None;
+        }
+    }
+}
+
+fn process_keyboard_event(
+    state: &mut AppState,
+    event: keyboard::Event,
+    journal: &std::sync::Mutex<Journal>,
+    outputs: &Outputs,
+) {
+    let AppState::Play(state) = state else { return; };
+    let _span = trace_span!("keyboard_event", ?event).entered();
+
+    let mut trace = None;
+
+    let input = match event {
+        keyboard::Event::Key(key, scan) => {
+            let (x, y) = key.key;
+
+            let pressed = match key.edge {
+                keypad::Edge::High | keypad::Edge::Rising => true,
+                keypad::Edge::Low | keypad::Edge::Falling => false,
+            };
+
+            trace = Some(Trace {
+                dispatched: Some(Instant::now()),
+                ..scan
+            });
+
+            Input::Key {
+                x: x as usize,
+                y: y as usize,
+                pressed,
+            }
+        }
+        keyboard::Event::Gpio { action, pressed } => Input::Gpio { action, pressed },
+        // NeoKey keys act as a second set of function keys
+        keyboard::Event::AuxKey { key, pressed } => Input::Key {
+            x: key,
+            y: 0,
+            pressed,
+        },
+        keyboard::Event::Analog { control, value } => Input::Analog { control, value },
+        // handled by process_events
+        keyboard::Event::Status { .. } | keyboard::Event::Settings { .. } => return,
+    };
+
+    let press = match input {
+        Input::Key {
+            x,
+            y,
+            pressed: true,
+        } if y > 0 => Some(Hook::PadPress { x, y }),
+        _ => None,
+    };
+
+    let effects = journal.lock().unwrap().apply(state, input);
+
+    // these are all responses to the user, so they should be seen right away
+    outputs.execute_traced(effects, Priority::Urgent, trace);
+
+    if let Some(press) = press {
+        outputs.hook(press);
+    }
+}
+
+fn process_loading_command(
+    state: &mut LoadingState,
+    cmd: Command,
+    ct: &CancellationToken,
+    outputs: &Outputs,
+) {
+    let (Command::RetryLoading, LoadingStage::Failed { .. }) = (cmd, &state.stage) else {
+        return;
+    };
+
+    info!("retrying loading");
+
+    *state = LoadingState {
+        animation: LoadingAnimation::start(ct, &outputs.kb_cmd_tx),
+        stage: LoadingStage::DiscoveringAudio,
+    };
+
+    let _ = outputs.audio_cmd_tx.send(audio::Command::Reload);
+}
+
+#[allow(clippy::too_many_arguments)]
+fn process_audio_event(
+    state: &mut AppState,
+    event: audio::Event,
+    clock: Arc<dyn Clock>,
+    config: &PlayConfig,
+    saved: &SavedState,
+    set_list: &Arc<SetList>,
+    palette: &Palette,
+    journal: &std::sync::Mutex<Journal>,
+    outputs: &Outputs,
+) {
+    let loading = match &mut *state {
+        AppState::Play(state) => {
+            if let audio::Event::LoadingFailed { path, error } = event {
+                // the audio subsystem was restarted, and the sounds that are
+                // already loaded are still usable
+                warn!("failed to reload sounds from {path:?}: {error}");
+                return;
+            }
+
+            let effects = journal.lock().unwrap().apply(state, Input::Audio(event));
+            outputs.execute(effects, Priority::Bulk);
+            return;
+        }
+        AppState::Loading(loading) => loading,
+    };
+
+    match event {
+        audio::Event::LoadingEnd {
+            sounds,
+            missing_roots,
+        } => {
+            loading.animation.cancel();
+
+            let mut inner = PlayState::new(sounds, clock);
+            let (width, height) = outputs.grid_size;
+            inner.set_grid_size(width, height);
+            inner.missing_roots = missing_roots.into_iter().collect();
+            inner.configure(config);
+            inner.restore(saved);
+            inner.set_list = set_list.clone();
+            inner.palette = palette.clone();
+
+            journal.lock().unwrap().start_from(&inner);
+            outputs.execute(inner.keyboard_leds(), Priority::Bulk);
+            play_animation(&outputs.kb_cmd_tx, "success");
+            *state = AppState::Play(Box::new(inner));
+        }
+        audio::Event::LoadingProgress {
+            progress,
+            num_files,
+            path,
+        } => {
+            let started = match loading.stage {
+                LoadingStage::BufferingAudio { started, .. } => started,
+                _ => Instant::now(),
+            };
+
+            loading.stage = LoadingStage::BufferingAudio {
+                progress,
+                num_files,
+                current: path,
+                started,
+            };
+        }
+        audio::Event::LoadingFailed { path, error } => {
+            loading.animation.cancel();
+            loading.stage = LoadingStage::Failed { path, error };
+
+            outputs.set_all_keys(Color::from_u8(255, 0, 0));
+            play_animation(&outputs.kb_cmd_tx, "error");
+        }
+        _ => {}
+    }
+}
+
+/// Forgets that `subsystem` was restarting, once it has shown that it is
+/// running again.
+fn clear_restarting(faults: &mut BTreeMap<Subsystem, Fault>, subsystem: Subsystem) {
+    if let Some(Fault::Restarting { .. }) = faults.get(&subsystem) {
+        info!("{subsystem} has restarted");
+        faults.remove(&subsystem);
+    }
+}
+
+fn process_fault(state: &AppState, fault: &Fault, outputs: &Outputs) {
+    match fault {
+        Fault::Restarting {
+            subsystem: Subsystem::Keyboard,
+            ..
+        } => {
+            // the restarted keyboard starts out blank, so queue up the LEDs for
+            // it
+            if let AppState::Play(state) = state {
+                outputs.execute(state.keyboard_leds(), Priority::Bulk);
+            }
+        }
+        Fault::Degraded { .. } | Fault::Restarting { .. } => {}
+        Fault::Failed { .. } => {
+            // the app can't work properly anymore, so make that obvious
+            outputs.set_all_keys(Color::from_u8(255, 0, 0));
+            play_animation(&outputs.kb_cmd_tx, "error");
+        }
+    }
+}
+
+/// Plays one of the animations on the keyboard, which replaces the one that
+/// is playing. See [`crate::animation`].
+pub(super) fn play_animation(kb_cmd_tx: &flume::Sender<keyboard::Command>, name: &str) {
+    let _ = kb_cmd_tx.send(keyboard::Command::PlayAnimation {
+        name: name.to_owned(),
+    });
+}
+
+fn set_solid_color(
+    kb_cmd_tx: &flume::Sender<keyboard::Command>,
+    x: usize,
+    y: usize,
+    color: Color,
+    priority: Priority,
+) {
+    let _ = kb_cmd_tx.send(keyboard::Command::SetState {
+        x: x as u16,
+        y: y as u16,
+        state: keyboard::PixelState::Solid {
+            color,
+            update: true,
+        },
+        priority,
+    });
+}
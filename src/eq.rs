@@ -0,0 +1,277 @@
+//! Three-band kill EQ, like the one on each channel of a DJ mixer. The signal
+//! is split into low, mid and high bands with Linkwitz-Riley crossovers, and
+//! each band can be cut out completely. With nothing cut, the bands add back
+//! up to the signal with only its phase shifted.
+
+use std::f32::consts::PI;
+
+/// Crossover between the low and mid bands, in Hz.
+const LOW_CROSSOVER: f32 = 300.;
+
+/// Crossover between the mid and high bands, in Hz.
+const HIGH_CROSSOVER: f32 = 3_000.;
+
+/// How long a band takes to fade in or out when it is killed or brought back,
+/// so that switching doesn't click.
+const FADE_SECS: f32 = 0.005;
+
+/// One of the bands of the EQ.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Band {
+    Low,
+    Mid,
+    High,
+}
+
+impl Band {
+    pub const ALL: [Band; 3] = [Band::Low, Band::Mid, Band::High];
+
+    fn bit(self) -> u8 {
+        match self {
+            Band::Low => 1,
+            Band::Mid => 2,
+            Band::High => 4,
+        }
+    }
+}
+
+/// Which bands are cut out.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Kills(u8);
+
+impl Kills {
+    pub fn is_killed(self, band: Band) -> bool {
+        self.0 & band.bit() != 0
+    }
+
+    pub fn toggle(&mut self, band: Band) {
+        self.0 ^= band.bit();
+    }
+
+    /// The kills as a bit per band, so that they can be shared with the audio
+    /// thread in an atomic.
+    pub fn bits(self) -> u8 {
+        self.0
+    }
+
+    pub fn from_bits(bits: u8) -> Self {
+        Self(bits & 7)
+    }
+}
+
+/// A second-order IIR filter, with coefficients from the Audio EQ Cookbook.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Biquad {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+    x1: f32,
+    x2: f32,
+    y1: f32,
+    y2: f32,
+}
+
+impl Biquad {
+    pub(crate) fn low_pass(cutoff: f32, sample_rate: u32) -> Self {
+        let (cos, alpha) = Self::params(cutoff, sample_rate);
+        Self::new(
+            (1. - cos) / 2.,
+            1. - cos,
+            (1. - cos) / 2.,
+            1. + alpha,
+            -2. * cos,
+            1. - alpha,
+        )
+    }
+
+    pub(crate) fn high_pass(cutoff: f32, sample_rate: u32) -> Self {
+        let (cos, alpha) = Self::params(cutoff, sample_rate);
+        Self::new(
+            (1. + cos) / 2.,
+            -(1. + cos),
+            (1. + cos) / 2.,
+            1. + alpha,
+            -2. * cos,
+            1. - alpha,
+        )
+    }
+
+    fn all_pass(cutoff: f32, sample_rate: u32) -> Self {
+        let (cos, alpha) = Self::params(cutoff, sample_rate);
+        Self::new(
+            1. - alpha,
+            -2. * cos,
+            1. + alpha,
+            1. + alpha,
+            -2. * cos,
+            1. - alpha,
+        )
+    }
+
+    /// cos(w0) and alpha for a Butterworth response.
+    fn params(cutoff: f32, sample_rate: u32) -> (f32, f32) {
+        let w0 = 2. * PI * cutoff / sample_rate as f32;
+        (w0.cos(), w0.sin() / 2f32.sqrt())
+    }
+
+    fn new(b0: f32, b1: f32, b2: f32, a0: f32, a1: f32, a2: f32) -> Self {
+        Self {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn process(&mut self, x: f32) -> f32 {
+        let y = self.b0 * x + self.b1 * self.x1 + self.b2 * self.x2
+            - self.a1 * self.y1
+            - self.a2 * self.y2;
+
+        self.x2 = self.x1;
+        self.x1 = x;
+        self.y2 = self.y1;
+        self.y1 = y;
+
+        y
+    }
+}
+
+/// Runs a sample through filters one after another.
+fn cascade(filters: &mut [Biquad], x: f32) -> f32 {
+    filters.iter_mut().fold(x, |x, filter| filter.process(x))
+}
+
+/// The filters that split one channel into bands. Each crossover is a pair of
+/// Butterworth filters, and the low band goes through an all-pass filter to
+/// match the phase that the high crossover gives the other two.
+#[derive(Debug, Clone)]
+struct Crossover {
+    low: [Biquad; 3],
+    not_low: [Biquad; 2],
+    mid: [Biquad; 2],
+    high: [Biquad; 2],
+}
+
+impl Crossover {
+    fn new(sample_rate: u32) -> Self {
+        let lp = |hz| Biquad::low_pass(hz, sample_rate);
+        let hp = |hz| Biquad::high_pass(hz, sample_rate);
+
+        Self {
+            low: [
+                lp(LOW_CROSSOVER),
+                lp(LOW_CROSSOVER),
+                Biquad::all_pass(HIGH_CROSSOVER, sample_rate),
+            ],
+            not_low: [hp(LOW_CROSSOVER), hp(LOW_CROSSOVER)],
+            mid: [lp(HIGH_CROSSOVER), lp(HIGH_CROSSOVER)],
+            high: [hp(HIGH_CROSSOVER), hp(HIGH_CROSSOVER)],
+        }
+    }
+
+    /// Splits a sample into its low, mid and high bands.
+    fn split(&mut self, x: f32) -> [f32; 3] {
+        let low = cascade(&mut self.low, x);
+        let not_low = cascade(&mut self.not_low, x);
+
+        [
+            low,
+            cascade(&mut self.mid, not_low),
+            cascade(&mut self.high, not_low),
+        ]
+    }
+}
+
+/// Kill EQ for an interleaved signal.
+#[derive(Debug, Clone)]
+pub struct KillEq {
+    /// the filters for each channel
+    channels: Vec<Crossover>,
+    /// gain of each band, which moves towards 0 or 1 by `step` each frame
+    gains: [f32; 3],
+    step: f32,
+}
+
+impl KillEq {
+    pub fn new(channels: u16, sample_rate: u32) -> Self {
+        Self {
+            channels: (0..channels).map(|_| Crossover::new(sample_rate)).collect(),
+            gains: [1.; 3],
+            step: 1. / (FADE_SECS * sample_rate as f32),
+        }
+    }
+
+    /// Filters the next sample, which is on `channel`. The band gains move on
+    /// at the start of each frame.
+    pub fn process(&mut self, channel: usize, sample: f32, kills: Kills) -> f32 {
+        if channel == 0 {
+            for (gain, band) in self.gains.iter_mut().zip(Band::ALL) {
+                let target = if kills.is_killed(band) { 0. } else { 1. };
+                *gain += (target - *gain).clamp(-self.step, self.step);
+            }
+        }
+
+        let bands = self.channels[channel].split(sample);
+
+        bands
+            .iter()
+            .zip(self.gains)
+            .map(|(band, gain)| band * gain)
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::f32::consts::PI;
+
+    use super::{Band, KillEq, Kills};
+
+    const RATE: u32 = 44_100;
+
+    /// Peak level of a sine wave at `hz` through the EQ, after it has
+    /// settled.
+    fn level(hz: f32, kills: Kills) -> f32 {
+        let mut eq = KillEq::new(1, RATE);
+
+        (0..RATE as usize / 2)
+            .map(|i| (2. * PI * hz * i as f32 / RATE as f32).sin())
+            .map(|x| eq.process(0, x, kills))
+            .skip(RATE as usize / 4)
+            .fold(0f32, |peak, y| peak.max(y.abs()))
+    }
+
+    fn killed(bands: &[Band]) -> Kills {
+        let mut kills = Kills::default();
+
+        for &band in bands {
+            kills.toggle(band);
+        }
+
+        kills
+    }
+
+    #[test]
+    fn no_kills_keep_the_level() {
+        for hz in [50., 300., 1_000., 3_000., 12_000.] {
+            let level = level(hz, Kills::default());
+            assert!((level - 1.).abs() < 0.01, "{level} at {hz}Hz");
+        }
+    }
+
+    #[test]
+    fn kills_cut_their_band() {
+        assert!(level(50., killed(&[Band::Low])) < 0.1);
+        assert!(level(50., killed(&[Band::High])) > 0.9);
+
+        assert!(level(12_000., killed(&[Band::High])) < 0.1);
+        assert!(level(12_000., killed(&[Band::Low])) > 0.9);
+
+        assert!(level(1_000., killed(&Band::ALL)) < 1e-3);
+    }
+}
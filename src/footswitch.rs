@@ -0,0 +1,113 @@
+//! Footswitches wired straight to the Pi's GPIO pins, for when the NeoTrellis's
+//! spare Seesaw pins are taken or out of reach. Each one pulls its pin to
+//! ground when pressed, and does the same as a Seesaw GPIO input.
+
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use rppal::gpio::{Gpio, InputPin};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, trace};
+
+use crate::{config::FootswitchConfig, keyboard, util::Interval};
+
+/// How long a switch has to stay in a new position before it counts, so that
+/// the contacts bouncing doesn't register as several presses.
+const DEBOUNCE: Duration = Duration::from_millis(20);
+
+/// Filters out the bouncing of a switch's contacts.
+#[derive(Debug, Clone, Default)]
+struct Debouncer {
+    pressed: bool,
+    /// when the switch started reading differently from `pressed`
+    changed_at: Option<Instant>,
+}
+
+impl Debouncer {
+    /// Takes a reading of the switch. Returns whether it is pressed if that
+    /// has changed.
+    fn update(&mut self, pressed: bool, now: Instant) -> Option<bool> {
+        if pressed == self.pressed {
+            self.changed_at = None;
+            return None;
+        }
+
+        let changed_at = *self.changed_at.get_or_insert(now);
+
+        if now - changed_at < DEBOUNCE {
+            return None;
+        }
+
+        self.pressed = pressed;
+        self.changed_at = None;
+        Some(pressed)
+    }
+}
+
+/// Polls the footswitches until cancelled, sending presses on `evt_tx` as
+/// [`keyboard::Event::Gpio`].
+pub fn run(
+    ct: CancellationToken,
+    config: Vec<FootswitchConfig>,
+    evt_tx: flume::Sender<keyboard::Event>,
+) -> anyhow::Result<()> {
+    let gpio = Gpio::new().context("failed to open GPIO")?;
+
+    let mut switches: Vec<(InputPin, &FootswitchConfig, Debouncer)> = vec![];
+
+    for switch in &config {
+        let pin = gpio
+            .get(switch.pin)
+            .with_context(|| format!("failed to open GPIO pin {}", switch.pin))?
+            .into_input_pullup();
+
+        switches.push((pin, switch, Debouncer::default()));
+    }
+
+    debug!("running footswitch loop");
+
+    // sample well within the debounce time
+    let mut interval = Interval::new(Duration::from_millis(2));
+
+    while !ct.is_cancelled() {
+        interval.tick();
+        let now = Instant::now();
+
+        for (pin, switch, debouncer) in &mut switches {
+            if let Some(pressed) = debouncer.update(pin.is_low(), now) {
+                trace!("footswitch on pin {} pressed = {pressed}", switch.pin);
+                let _ = evt_tx.send(keyboard::Event::Gpio {
+                    action: switch.action,
+                    pressed,
+                });
+            }
+        }
+    }
+
+    debug!("footswitch task exited");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn debouncer_ignores_bounces() {
+        let start = Instant::now();
+        let ms = |ms| start + Duration::from_millis(ms);
+        let mut debouncer = Debouncer::default();
+
+        // contacts bounce when the switch is pressed
+        assert_eq!(debouncer.update(true, ms(0)), None);
+        assert_eq!(debouncer.update(false, ms(1)), None);
+        assert_eq!(debouncer.update(true, ms(2)), None);
+        assert_eq!(debouncer.update(true, ms(21)), None);
+        assert_eq!(debouncer.update(true, ms(22)), Some(true));
+        assert_eq!(debouncer.update(true, ms(50)), None);
+
+        assert_eq!(debouncer.update(false, ms(60)), None);
+        assert_eq!(debouncer.update(false, ms(80)), Some(false));
+    }
+}
@@ -0,0 +1,221 @@
+//! Momentary effects on the master mix, which are held on while a pad is held
+//! down in FX mode. They all work off a buffer of the last few seconds of the
+//! mix, and the mix goes straight through when none is on.
+
+use std::time::Duration;
+
+/// How much of the mix is kept for the effects to play back, in seconds.
+const HISTORY_SECS: usize = 4;
+
+/// How much of each echo of a delay throw comes back again.
+const FEEDBACK: f32 = 0.5;
+
+/// An effect on the master mix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fx {
+    /// Plays the last `length` of the mix over and over.
+    Repeat(Duration),
+    /// Echoes the mix every `time`.
+    Delay(Duration),
+    /// Slows the mix down to a stop over the given time, like a turntable
+    /// being switched off.
+    TapeStop(Duration),
+}
+
+impl Fx {
+    /// The effect as a kind and a time in microseconds, so that it can be
+    /// shared with the audio thread in an atomic.
+    pub fn to_bits(fx: Option<Fx>) -> u64 {
+        let (kind, time) = match fx {
+            None => return 0,
+            Some(Fx::Repeat(time)) => (1, time),
+            Some(Fx::Delay(time)) => (2, time),
+            Some(Fx::TapeStop(time)) => (3, time),
+        };
+
+        kind << 32 | time.as_micros().min(u32::MAX as u128) as u64
+    }
+
+    pub fn from_bits(bits: u64) -> Option<Fx> {
+        let time = Duration::from_micros(bits & u32::MAX as u64);
+
+        match bits >> 32 {
+            1 => Some(Fx::Repeat(time)),
+            2 => Some(Fx::Delay(time)),
+            3 => Some(Fx::TapeStop(time)),
+            _ => None,
+        }
+    }
+}
+
+/// Effects for an interleaved signal.
+#[derive(Debug, Clone)]
+pub struct MasterFx {
+    channels: usize,
+    sample_rate: u32,
+    /// the last [`HISTORY_SECS`] of frames, indexed by frame number modulo
+    /// its length
+    history: Vec<f32>,
+    len: usize,
+    /// number of the current frame, which starts a whole history in so that
+    /// looking back never goes below zero
+    frame: usize,
+    fx: Option<Fx>,
+    /// frame that the current effect started on
+    started: usize,
+    /// position that a tape stop is playing from, in frames, and its speed
+    read: f64,
+    speed: f64,
+}
+
+impl MasterFx {
+    pub fn new(channels: u16, sample_rate: u32) -> Self {
+        let len = HISTORY_SECS * sample_rate as usize;
+
+        Self {
+            channels: channels as usize,
+            sample_rate,
+            history: vec![0.; len * channels as usize],
+            len,
+            frame: len,
+            fx: None,
+            started: len,
+            read: 0.,
+            speed: 1.,
+        }
+    }
+
+    /// Runs the next sample, which is on `channel`, through `fx`. A change of
+    /// effect is picked up at the start of a frame.
+    pub fn process(&mut self, channel: usize, sample: f32, fx: Option<Fx>) -> f32 {
+        if channel == 0 && fx != self.fx {
+            self.fx = fx;
+            self.started = self.frame;
+            self.read = (self.frame - 1) as f64;
+            self.speed = 1.;
+        }
+
+        let out = match self.fx {
+            None => {
+                self.write(channel, sample);
+                sample
+            }
+            Some(Fx::Repeat(length)) => {
+                // the history is left alone, so that the slice being
+                // repeated isn't written over
+                let length = self.frames(length).clamp(1, self.len - 1);
+                let frame = self.started - length + (self.frame - self.started) % length;
+                self.history[self.index(frame, channel)]
+            }
+            Some(Fx::Delay(time)) => {
+                let time = self.frames(time).clamp(1, self.len - 1);
+                let out = sample + FEEDBACK * self.history[self.index(self.frame - time, channel)];
+                self.write(channel, out);
+                out
+            }
+            Some(Fx::TapeStop(_)) => {
+                self.write(channel, sample);
+
+                let frame = self.read.floor();
+                let frac = (self.read - frame) as f32;
+                let a = self.history[self.index(frame as usize, channel)];
+                let b = self.history[self.index(frame as usize + 1, channel)];
+
+                // fade out the very end, where the tape is barely moving
+                (a + (b - a) * frac) * (self.speed as f32 * 8.).min(1.)
+            }
+        };
+
+        if channel + 1 == self.channels {
+            self.frame += 1;
+
+            if let Some(Fx::TapeStop(time)) = self.fx {
+                // the playback falls behind by at most half of the stop, so
+                // it has to fit in the history twice over
+                let time = self.frames(time).clamp(1, 2 * (self.len - 2));
+                self.read += self.speed;
+                self.speed = (1. - (self.frame - self.started) as f64 / time as f64).max(0.);
+            }
+        }
+
+        out
+    }
+
+    fn frames(&self, time: Duration) -> usize {
+        (time.as_secs_f64() * self.sample_rate as f64) as usize
+    }
+
+    fn index(&self, frame: usize, channel: usize) -> usize {
+        frame % self.len * self.channels + channel
+    }
+
+    fn write(&mut self, channel: usize, sample: f32) {
+        let index = self.index(self.frame, channel);
+        self.history[index] = sample;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{Fx, MasterFx};
+
+    const RATE: u32 = 1_000;
+
+    /// Runs a mono signal that counts up from 1 through `fx`, which is turned
+    /// on after `dry` samples.
+    fn run(fx: Fx, dry: usize, len: usize) -> Vec<f32> {
+        let mut master = MasterFx::new(1, RATE);
+
+        (0..len)
+            .map(|i| {
+                let fx = (i >= dry).then_some(fx);
+                master.process(0, (i + 1) as f32, fx)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn bits_round_trip() {
+        for fx in [
+            None,
+            Some(Fx::Repeat(Duration::from_millis(125))),
+            Some(Fx::Delay(Duration::from_micros(333_333))),
+            Some(Fx::TapeStop(Duration::from_secs(2))),
+        ] {
+            assert_eq!(Fx::from_bits(Fx::to_bits(fx)), fx);
+        }
+    }
+
+    #[test]
+    fn repeat_loops_the_last_slice() {
+        let out = run(Fx::Repeat(Duration::from_millis(3)), 5, 12);
+        assert_eq!(out, [1., 2., 3., 4., 5., 3., 4., 5., 3., 4., 5., 3.]);
+    }
+
+    #[test]
+    fn delay_echoes_with_feedback() {
+        let mut master = MasterFx::new(1, RATE);
+        let fx = Some(Fx::Delay(Duration::from_millis(2)));
+        let impulse = [1., 0., 0., 0., 0., 0.];
+
+        let out: Vec<_> = impulse.iter().map(|&x| master.process(0, x, fx)).collect();
+        assert_eq!(out, [1., 0., 0.5, 0., 0.25, 0.]);
+    }
+
+    #[test]
+    fn tape_stop_slows_down_then_goes_quiet() {
+        let out = run(Fx::TapeStop(Duration::from_millis(100)), 10, 200);
+
+        // the first sample of the stop is the last one before it
+        assert_eq!(out[10], 10.);
+
+        // each sample moves on less than the one before
+        let steps: Vec<_> = out[10..60].windows(2).map(|w| w[1] - w[0]).collect();
+        assert!(steps.windows(2).all(|w| w[1] <= w[0] + 1e-3), "{steps:?}");
+        assert!(steps[0] > 0.9 && steps[48] < 0.6, "{steps:?}");
+
+        assert!(out[150..].iter().all(|&x| x == 0.));
+    }
+}
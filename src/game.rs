@@ -0,0 +1,385 @@
+//! Rhythm trainer, a game on the pads. After a bar of count-in, the pads
+//! light up in time with a pattern, from a beat before each hit until it is
+//! due, and each press is judged by how close to its hit it was heard. The
+//! looper keeps playing underneath, so there is something to play along to.
+
+use std::time::Duration;
+
+use crate::engine::{BEATS_PER_BAR, TICKS_PER_BEAT};
+
+/// Ticks in a step of a pattern, which are 16th notes.
+pub const STEP: usize = TICKS_PER_BEAT / 4;
+
+/// How many ticks a pad flashes the judgement of a press for.
+pub const FLASH: usize = STEP * 2;
+
+const BAR: usize = TICKS_PER_BEAT * BEATS_PER_BAR;
+
+/// Presses at most this far from a hit are perfect, good or ok. Presses
+/// further away from every hit don't count towards any of them.
+const PERFECT: Duration = Duration::from_millis(30);
+const GOOD: Duration = Duration::from_millis(70);
+const OK: Duration = Duration::from_millis(120);
+
+/// The pads that are played when no pads are bound: the bottom row.
+const DEFAULT_PADS: [(usize, usize); 4] = [(0, 3), (1, 3), (2, 3), (3, 3)];
+
+/// A bar of hits. Each lane is played on its own pad, and is written as a
+/// step per character, where `x` is a hit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pattern {
+    pub name: &'static str,
+    lanes: &'static [&'static str],
+}
+
+impl Pattern {
+    /// The lane and step of every hit in the bar, in order of the steps.
+    fn hits(&self) -> Vec<(usize, usize)> {
+        let mut hits: Vec<_> = self
+            .lanes
+            .iter()
+            .enumerate()
+            .flat_map(|(lane, steps)| {
+                steps
+                    .chars()
+                    .enumerate()
+                    .filter(|&(_, c)| c == 'x')
+                    .map(move |(step, _)| (lane, step))
+            })
+            .collect();
+
+        hits.sort_by_key(|&(lane, step)| (step, lane));
+        hits
+    }
+}
+
+/// The patterns that the game can be played with, from the easiest.
+pub const PATTERNS: [Pattern; 5] = [
+    Pattern {
+        name: "Four on the floor",
+        lanes: &["x...x...x...x..."],
+    },
+    Pattern {
+        name: "Backbeat",
+        lanes: &["x.......x.......", "....x.......x..."],
+    },
+    Pattern {
+        name: "Offbeats",
+        lanes: &["x...x...x...x...", "..x...x...x...x."],
+    },
+    Pattern {
+        name: "Eighths",
+        lanes: &["x.......x.x.....", "....x.......x...", "x.x.x.x.x.x.x.x."],
+    },
+    Pattern {
+        name: "Syncopated",
+        lanes: &["x..x..x...x..x..", "....x.......x...", "..x.......x....x"],
+    },
+];
+
+/// How close to a hit a press was.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Judgement {
+    Perfect,
+    Good,
+    Ok,
+    /// The hit went by without being pressed.
+    Miss,
+}
+
+impl Judgement {
+    /// The judgement of a press that was `error` away from its hit, if it
+    /// was close enough to be one.
+    fn of(error: Duration) -> Option<Self> {
+        match error {
+            e if e <= PERFECT => Some(Judgement::Perfect),
+            e if e <= GOOD => Some(Judgement::Good),
+            e if e <= OK => Some(Judgement::Ok),
+            _ => None,
+        }
+    }
+
+    fn points(self) -> usize {
+        match self {
+            Judgement::Perfect => 3,
+            Judgement::Good => 2,
+            Judgement::Ok => 1,
+            Judgement::Miss => 0,
+        }
+    }
+}
+
+/// How a game is going.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Score {
+    pub perfect: usize,
+    pub good: usize,
+    pub ok: usize,
+    pub miss: usize,
+    /// presses that weren't near any hit on their pad
+    pub stray: usize,
+    /// hits in a row that weren't missed, which a stray press also breaks
+    pub combo: usize,
+    pub best_combo: usize,
+}
+
+impl Score {
+    fn record(&mut self, judgement: Judgement) {
+        match judgement {
+            Judgement::Perfect => self.perfect += 1,
+            Judgement::Good => self.good += 1,
+            Judgement::Ok => self.ok += 1,
+            Judgement::Miss => self.miss += 1,
+        }
+
+        if judgement == Judgement::Miss {
+            self.combo = 0;
+        } else {
+            self.combo += 1;
+            self.best_combo = self.best_combo.max(self.combo);
+        }
+    }
+
+    /// How accurate the hits that have been judged were, from 0.0 to 1.0,
+    /// where 1.0 is all of them perfect.
+    pub fn accuracy(&self) -> f32 {
+        let judged = self.perfect + self.good + self.ok + self.miss;
+        if judged == 0 {
+            return 0.;
+        }
+
+        let points = self.perfect * Judgement::Perfect.points()
+            + self.good * Judgement::Good.points()
+            + self.ok * Judgement::Ok.points();
+
+        points as f32 / (judged * Judgement::Perfect.points()) as f32
+    }
+}
+
+/// A hit that the player is to press.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cue {
+    /// the tick of the looper that it is due on
+    pub tick: usize,
+    /// the pad that it is played on, as (x, y)
+    pub pad: (usize, usize),
+    /// how it was played, and the tick that it was judged on
+    pub judged: Option<(Judgement, usize)>,
+}
+
+/// A game that is being played.
+#[derive(Debug, Clone)]
+pub struct Game {
+    /// the index of the pattern in [`PATTERNS`]
+    pub pattern: usize,
+    /// the pads that the pattern's lanes are played on, in order
+    pub pads: Vec<(usize, usize)>,
+    pub cues: Vec<Cue>,
+    /// the tick that the first bar starts on, after the count-in
+    pub start: usize,
+    /// the tick that the last bar ends on
+    pub end: usize,
+    pub score: Score,
+    /// the pad of the last stray press, and the tick it was on
+    stray: Option<((usize, usize), usize)>,
+}
+
+impl Game {
+    /// Sets up `bars` bars of a pattern in [`PATTERNS`], starting after the
+    /// bar that the looper is in at tick `now` and a bar of count-in. Its
+    /// lanes are played on `pads` in turn, or on the bottom row if there
+    /// are none.
+    pub fn new(pattern: usize, pads: &[(usize, usize)], now: usize, bars: usize) -> Self {
+        let pattern = pattern.min(PATTERNS.len() - 1);
+        let pads = match pads {
+            [] => DEFAULT_PADS.to_vec(),
+            pads => pads.to_vec(),
+        };
+
+        let start = (now / BAR + 2) * BAR;
+        let hits = PATTERNS[pattern].hits();
+
+        let cues = (0..bars)
+            .flat_map(|bar| hits.iter().map(move |&(lane, step)| (bar, lane, step)))
+            .map(|(bar, lane, step)| Cue {
+                tick: start + bar * BAR + step * STEP,
+                pad: pads[lane % pads.len()],
+                judged: None,
+            })
+            .collect();
+
+        Self {
+            pattern,
+            pads,
+            cues,
+            start,
+            end: start + bars * BAR,
+            score: Score::default(),
+            stray: None,
+        }
+    }
+
+    /// Judges a press of `pad` that was heard at `at` on the looper, which
+    /// is ticks of length `tick` long, against the nearest hit on it that
+    /// hasn't been played. `now` is the tick that the looper is on. None if
+    /// no hit was close enough, which breaks the combo.
+    pub fn press(
+        &mut self,
+        pad: (usize, usize),
+        at: Duration,
+        tick: Duration,
+        now: usize,
+    ) -> Option<Judgement> {
+        let error = |cue: &Cue| (tick * cue.tick as u32).abs_diff(at);
+
+        let judged = self
+            .cues
+            .iter_mut()
+            .filter(|cue| cue.pad == pad && cue.judged.is_none())
+            .min_by_key(|cue| error(cue))
+            .and_then(|cue| {
+                let judgement = Judgement::of(error(cue))?;
+                cue.judged = Some((judgement, now));
+                Some(judgement)
+            });
+
+        match judged {
+            Some(judgement) => self.score.record(judgement),
+            None => {
+                self.score.stray += 1;
+                self.score.combo = 0;
+                self.stray = Some((pad, now));
+            }
+        }
+
+        judged
+    }
+
+    /// Misses the hits that are too long ago to be pressed by the time `at`
+    /// on the looper, which is on tick `now`. Returns whether there were any.
+    pub fn expire(&mut self, at: Duration, tick: Duration, now: usize) -> bool {
+        let mut missed = false;
+
+        for cue in &mut self.cues {
+            if cue.judged.is_none() && tick * cue.tick as u32 + OK < at {
+                cue.judged = Some((Judgement::Miss, now));
+                self.score.record(Judgement::Miss);
+                missed = true;
+            }
+        }
+
+        missed
+    }
+
+    /// Whether the last bar has ended and every hit has been judged.
+    pub fn is_over(&self, now: usize) -> bool {
+        now >= self.end && self.cues.iter().all(|cue| cue.judged.is_some())
+    }
+
+    /// The judgement that `pad` flashes on tick `now`, if it was played or
+    /// missed less than [`FLASH`] ticks ago. A stray press flashes as a miss.
+    pub fn flash(&self, pad: (usize, usize), now: usize) -> Option<Judgement> {
+        let recent = |at: usize| now.saturating_sub(at) < FLASH;
+
+        let stray = self
+            .stray
+            .filter(|&(p, at)| p == pad && recent(at))
+            .map(|(_, at)| (Judgement::Miss, at));
+
+        self.cues
+            .iter()
+            .filter(|cue| cue.pad == pad)
+            .filter_map(|cue| cue.judged)
+            .filter(|&(_, at)| recent(at))
+            .chain(stray)
+            .max_by_key(|&(_, at)| at)
+            .map(|(judgement, _)| judgement)
+    }
+
+    /// How many ticks from `now` the next hit on `pad` that hasn't been
+    /// played is due in, or 0 if it is late.
+    pub fn next_hit(&self, pad: (usize, usize), now: usize) -> Option<usize> {
+        self.cues
+            .iter()
+            .filter(|cue| cue.pad == pad && cue.judged.is_none())
+            .map(|cue| cue.tick.saturating_sub(now))
+            .min()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{Game, Judgement, BAR, PATTERNS, STEP};
+
+    const TICK: Duration = Duration::from_millis(10);
+
+    fn at(tick: usize) -> Duration {
+        TICK * tick as u32
+    }
+
+    #[test]
+    fn games_start_after_a_bar_of_count_in() {
+        let pads = [(0, 1), (1, 1)];
+        let game = Game::new(1, &pads, 10, 2);
+
+        assert_eq!(PATTERNS[1].name, "Backbeat");
+        assert_eq!(game.start, 2 * BAR);
+        assert_eq!(game.end, 4 * BAR);
+
+        let cues: Vec<_> = game.cues.iter().map(|cue| (cue.tick, cue.pad)).collect();
+        assert_eq!(
+            &cues[..4],
+            [
+                (2 * BAR, (0, 1)),
+                (2 * BAR + 4 * STEP, (1, 1)),
+                (2 * BAR + 8 * STEP, (0, 1)),
+                (2 * BAR + 12 * STEP, (1, 1)),
+            ]
+        );
+        assert_eq!(cues.len(), 8);
+
+        // with no pads bound, the bottom row is played
+        assert_eq!(Game::new(1, &[], 0, 1).pads[..2], [(0, 3), (1, 3)]);
+    }
+
+    #[test]
+    fn presses_are_judged_by_how_far_from_the_hit_they_are_heard() {
+        let mut game = Game::new(0, &[(0, 1)], 0, 1);
+        let first = game.start;
+
+        // 20ms late, 50ms early, and nowhere near a hit
+        let late = at(first) + Duration::from_millis(20);
+        let early = at(first + 4 * STEP) - Duration::from_millis(50);
+        let stray = at(first + 6 * STEP);
+
+        assert_eq!(
+            game.press((0, 1), late, TICK, first + 2),
+            Some(Judgement::Perfect)
+        );
+        assert_eq!(
+            game.press((0, 1), early, TICK, first + 4 * STEP - 5),
+            Some(Judgement::Good)
+        );
+        assert_eq!(game.score.combo, 2);
+        assert_eq!(game.press((0, 1), stray, TICK, first + 6 * STEP), None);
+        assert_eq!(game.score.combo, 0);
+        assert_eq!(
+            game.flash((0, 1), first + 6 * STEP + 1),
+            Some(Judgement::Miss)
+        );
+
+        // the other two hits go by
+        assert!(!game.is_over(game.end));
+        assert!(game.expire(at(game.end + 20), TICK, game.end + 20));
+        assert!(game.is_over(game.end + 20));
+
+        assert_eq!(game.score.perfect, 1);
+        assert_eq!(game.score.good, 1);
+        assert_eq!(game.score.miss, 2);
+        assert_eq!(game.score.stray, 1);
+        assert_eq!(game.score.best_combo, 2);
+        assert!((game.score.accuracy() - 5. / 12.).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,133 @@
+//! A grid of keys whose size is picked at runtime, by the keyboard that is
+//! plugged in, rather than fixed to the NeoTrellis's 4x4.
+
+use std::ops::{Index, IndexMut};
+
+/// Width and height of the NeoTrellis's grid, which the layouts of the
+/// modes other than free play are made for.
+pub const NEOTRELLIS: (usize, usize) = (4, 4);
+
+/// Width and height of the grid of pads on the MIDI grid controllers. See
+/// [`crate::midi_grid`].
+pub const MIDI_GRID: (usize, usize) = (8, 8);
+
+/// Cells in rows from the top, which are indexed as `grid[y][x]`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Grid<T> {
+    width: usize,
+    height: usize,
+    cells: Vec<T>,
+}
+
+impl<T: Default> Grid<T> {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self::from_fn(width, height, |_, _| T::default())
+    }
+}
+
+impl<T> Grid<T> {
+    /// A grid with `cell(x, y)` in each of its cells.
+    pub fn from_fn(width: usize, height: usize, mut cell: impl FnMut(usize, usize) -> T) -> Self {
+        Self {
+            width,
+            height,
+            cells: (0..width * height)
+                .map(|i| cell(i % width, i / width))
+                .collect(),
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn contains(&self, x: usize, y: usize) -> bool {
+        x < self.width && y < self.height
+    }
+
+    /// The row `y`, if the grid has it.
+    pub fn get(&self, y: usize) -> Option<&[T]> {
+        (y < self.height).then(|| &self[y])
+    }
+
+    pub fn get_mut(&mut self, y: usize) -> Option<&mut [T]> {
+        (y < self.height).then(|| &mut self[y])
+    }
+
+    /// The rows, from the top.
+    pub fn iter(&self) -> std::slice::Chunks<'_, T> {
+        self.cells.chunks(self.width.max(1))
+    }
+
+    pub fn iter_mut(&mut self) -> std::slice::ChunksMut<'_, T> {
+        self.cells.chunks_mut(self.width.max(1))
+    }
+
+    /// The cells, a row at a time from the top.
+    pub fn cells(&self) -> &[T] {
+        &self.cells
+    }
+
+    pub fn cells_mut(&mut self) -> &mut [T] {
+        &mut self.cells
+    }
+
+    pub fn into_cells(self) -> Vec<T> {
+        self.cells
+    }
+
+    /// The (x, y) position of each cell, in the same order as
+    /// [`Grid::cells`]. It doesn't borrow the grid, so that the cells can be
+    /// changed along the way.
+    pub fn positions(&self) -> impl Iterator<Item = (usize, usize)> {
+        let width = self.width;
+        (0..self.height).flat_map(move |y| (0..width).map(move |x| (x, y)))
+    }
+
+    /// A grid of the same size, with `f` of each cell.
+    pub fn map<U>(&self, f: impl FnMut(&T) -> U) -> Grid<U> {
+        Grid {
+            width: self.width,
+            height: self.height,
+            cells: self.cells.iter().map(f).collect(),
+        }
+    }
+}
+
+impl<T> Index<usize> for Grid<T> {
+    type Output = [T];
+
+    fn index(&self, y: usize) -> &[T] {
+        &self.cells[y * self.width..][..self.width]
+    }
+}
+
+impl<T> IndexMut<usize> for Grid<T> {
+    fn index_mut(&mut self, y: usize) -> &mut [T] {
+        &mut self.cells[y * self.width..][..self.width]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Grid;
+
+    #[test]
+    fn cells_are_indexed_by_row_then_column() {
+        let mut grid = Grid::from_fn(3, 2, |x, y| (x, y));
+        assert_eq!(grid[1][2], (2, 1));
+        assert_eq!(grid.get(2), None);
+
+        grid[0][1] = (9, 9);
+        assert_eq!(grid.cells()[1], (9, 9));
+        assert_eq!(grid.iter().count(), 2);
+        assert_eq!(
+            grid.positions().collect::<Vec<_>>(),
+            [(0, 0), (1, 0), (2, 0), (0, 1), (1, 1), (2, 1)]
+        );
+    }
+}
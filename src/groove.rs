@@ -0,0 +1,139 @@
+//! Groove templates: the timing of someone playing the pads without
+//! quantization, kept as how far ahead or behind the beat they played each
+//! 16th of the bar. A groove can be put on the quantized loops, which moves
+//! each of their triggers on a 16th by the offset of that 16th, so that they
+//! swing like the playing did.
+
+use serde::{Deserialize, Serialize};
+
+use crate::engine::{BEATS_PER_BAR, TICKS_PER_BEAT};
+
+/// Ticks in a step of a groove, which are 16th notes.
+const STEP: usize = TICKS_PER_BEAT / 4;
+
+/// Steps in a bar, which a groove has an offset for each of.
+pub const STEPS: usize = BEATS_PER_BAR * 4;
+
+/// A named groove template.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Groove {
+    pub name: String,
+    /// how many ticks each step of the bar is moved by, where a positive
+    /// offset is behind the beat
+    pub offsets: Vec<isize>,
+}
+
+impl Groove {
+    /// The ticks that a trigger on `tick` is moved by. Triggers that aren't
+    /// on a step aren't moved.
+    pub fn offset(&self, tick: usize) -> isize {
+        if !tick.is_multiple_of(STEP) {
+            return 0;
+        }
+
+        let step = tick / STEP % STEPS;
+        self.offsets.get(step).copied().unwrap_or(0)
+    }
+
+    /// The tick that a trigger on `tick` is played on. Triggers aren't moved
+    /// to before the first tick.
+    fn moved(&self, tick: usize) -> usize {
+        (tick as isize + self.offset(tick)).max(0) as usize
+    }
+
+    /// The ticks whose triggers are played on `tick`: those on nearby steps
+    /// that are moved onto it, and `tick` itself if it isn't moved off.
+    pub fn sources(&self, tick: usize) -> impl Iterator<Item = usize> + '_ {
+        let nearest = (tick + STEP / 2) / STEP;
+        let steps = nearest.saturating_sub(1)..=nearest + 1;
+
+        steps
+            .map(|step| step * STEP)
+            .chain((!tick.is_multiple_of(STEP)).then_some(tick))
+            .filter(move |&source| self.moved(source) == tick)
+    }
+}
+
+/// Takes note of when the pads are pressed, relative to the nearest step,
+/// to make a groove from.
+#[derive(Debug, Clone, Default)]
+pub struct GrooveRecorder {
+    /// the sum of the offsets of the presses on each step of the bar, and
+    /// how many there were
+    steps: [(isize, usize); STEPS],
+}
+
+impl GrooveRecorder {
+    /// Takes note of a press that was heard on tick `at` of the looper.
+    pub fn press(&mut self, at: usize) {
+        let step = (at + STEP / 2) / STEP;
+        let offset = at as isize - (step * STEP) as isize;
+
+        let (sum, count) = &mut self.steps[step % STEPS];
+        *sum += offset;
+        *count += 1;
+    }
+
+    /// How many presses have been taken note of.
+    pub fn presses(&self) -> usize {
+        self.steps.iter().map(|&(_, count)| count).sum()
+    }
+
+    /// The groove of the presses so far, with the average offset of each
+    /// step. Steps that weren't played aren't moved.
+    pub fn groove(&self, name: String) -> Groove {
+        let offsets = self
+            .steps
+            .iter()
+            .map(|&(sum, count)| match count {
+                0 => 0,
+                count => (sum as f32 / count as f32).round() as isize,
+            })
+            .collect();
+
+        Groove { name, offsets }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{GrooveRecorder, STEP, STEPS};
+
+    #[test]
+    fn presses_are_averaged_into_the_offsets_of_their_steps() {
+        let mut recorder = GrooveRecorder::default();
+
+        // the downbeat on time, and the second 16th late by 3 and 5 ticks,
+        // over two bars
+        let bar = STEPS * STEP;
+        for at in [0, STEP + 3, bar, bar + STEP + 5, 2 * STEP - 2] {
+            recorder.press(at);
+        }
+
+        let groove = recorder.groove("late".to_owned());
+        assert_eq!(recorder.presses(), 5);
+        assert_eq!(&groove.offsets[..3], [0, 4, -2]);
+        assert_eq!(groove.offsets.len(), STEPS);
+    }
+
+    #[test]
+    fn triggers_on_steps_are_moved_by_their_offsets() {
+        let mut recorder = GrooveRecorder::default();
+        recorder.press(STEP + 4);
+        recorder.press(2 * STEP - 3);
+        let groove = recorder.groove("swing".to_owned());
+
+        assert_eq!(groove.offset(STEP), 4);
+        assert_eq!(groove.offset(STEP + STEPS * STEP), 4);
+        assert_eq!(groove.offset(STEP + 1), 0);
+
+        // the second step is played late, the third early, and ticks in
+        // between where they were
+        let sources = |tick| groove.sources(tick).collect::<Vec<_>>();
+        assert_eq!(sources(STEP), vec![]);
+        assert_eq!(sources(STEP + 4), vec![STEP, STEP + 4]);
+        assert_eq!(sources(2 * STEP - 3), vec![2 * STEP, 2 * STEP - 3]);
+        assert_eq!(sources(2 * STEP), vec![]);
+        assert_eq!(sources(0), vec![0]);
+    }
+}
@@ -0,0 +1,167 @@
+//! Watches the Raspberry Pi's temperature and power supply. An undervolted or
+//! throttled Pi can't keep up with the audio, so it is worth knowing about
+//! before a set rather than during it.
+
+use std::time::Duration;
+
+use tokio_util::sync::CancellationToken;
+use tracing::debug;
+
+/// How often the Pi's health is read.
+const POLL_PERIOD: Duration = Duration::from_secs(2);
+
+/// Where the kernel reports the CPU temperature, in thousandths of a °C.
+const TEMPERATURE_PATH: &str = "/sys/class/thermal/thermal_zone0/temp";
+
+/// Where newer kernels report the firmware's throttling flags, as a hex
+/// number. Older ones only report them through `vcgencmd get_throttled`.
+const THROTTLED_PATH: &str = "/sys/devices/platform/soc/soc:firmware/get_throttled";
+
+/// Above this temperature in °C, the Pi is about to start throttling.
+pub const HOT_TEMPERATURE: f32 = 80.;
+
+/// The firmware's throttling flags, as reported by `vcgencmd get_throttled`.
+/// The low bits say what is happening now, and the high bits what has
+/// happened since the Pi booted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Throttled(pub u32);
+
+impl Throttled {
+    const UNDER_VOLTAGE: u32 = 1 << 0;
+    const FREQUENCY_CAPPED: u32 = 1 << 1;
+    const THROTTLED: u32 = 1 << 2;
+    const SOFT_TEMPERATURE_LIMIT: u32 = 1 << 3;
+    /// shifts a flag from "now" to "since boot"
+    const OCCURRED: u32 = 16;
+
+    /// Parses `vcgencmd get_throttled` output like `throttled=0x50005`, or
+    /// the bare hex number in sysfs.
+    pub fn parse(text: &str) -> Option<Self> {
+        let text = text.trim();
+        let text = text.strip_prefix("throttled=").unwrap_or(text);
+        let text = text.strip_prefix("0x").unwrap_or(text);
+        u32::from_str_radix(text, 16).ok().map(Self)
+    }
+
+    pub fn under_voltage(&self) -> bool {
+        self.0 & Self::UNDER_VOLTAGE != 0
+    }
+
+    pub fn under_voltage_occurred(&self) -> bool {
+        self.0 & (Self::UNDER_VOLTAGE << Self::OCCURRED) != 0
+    }
+
+    /// Whether the CPU is running slower than it should, for any reason.
+    pub fn throttled(&self) -> bool {
+        self.0 & (Self::FREQUENCY_CAPPED | Self::THROTTLED | Self::SOFT_TEMPERATURE_LIMIT) != 0
+    }
+}
+
+/// A reading of the Pi's health. Either part is None if it can't be read,
+/// e.g. when not running on a Pi.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Health {
+    /// CPU temperature in °C
+    pub temperature: Option<f32>,
+    pub throttled: Option<Throttled>,
+}
+
+impl Health {
+    /// What is wrong right now, if anything.
+    pub fn warnings(&self) -> Vec<&'static str> {
+        let mut warnings = vec![];
+
+        if let Some(throttled) = self.throttled {
+            if throttled.under_voltage() {
+                warnings.push("under-voltage");
+            }
+
+            if throttled.throttled() {
+                warnings.push("throttled");
+            }
+        }
+
+        if matches!(self.temperature, Some(t) if t >= HOT_TEMPERATURE) {
+            warnings.push("hot");
+        }
+
+        warnings
+    }
+
+    async fn read() -> Self {
+        let temperature = tokio::fs::read_to_string(TEMPERATURE_PATH)
+            .await
+            .ok()
+            .and_then(|text| text.trim().parse::<f32>().ok())
+            .map(|millis| millis / 1000.);
+
+        let throttled = match tokio::fs::read_to_string(THROTTLED_PATH).await {
+            Ok(text) => Throttled::parse(&text),
+            Err(_) => read_vcgencmd().await,
+        };
+
+        Self {
+            temperature,
+            throttled,
+        }
+    }
+}
+
+async fn read_vcgencmd() -> Option<Throttled> {
+    let output = tokio::process::Command::new("vcgencmd")
+        .arg("get_throttled")
+        .output()
+        .await
+        .ok()?;
+
+    Throttled::parse(std::str::from_utf8(&output.stdout).ok()?)
+}
+
+/// Reads the Pi's health every [`POLL_PERIOD`] and sends it when it changes.
+pub async fn run(ct: CancellationToken, health_tx: flume::Sender<Health>) {
+    let mut last = None;
+    let mut interval = tokio::time::interval(POLL_PERIOD);
+
+    loop {
+        tokio::select! {
+            _ = ct.cancelled() => break,
+            _ = interval.tick() => {}
+        }
+
+        let health = Health::read().await;
+
+        if last == Some(health) {
+            continue;
+        }
+
+        if last.is_none() && health == Health::default() {
+            debug!("not running on a pi, or its health can't be read");
+        }
+
+        last = Some(health);
+
+        if health_tx.send_async(health).await.is_err() {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_throttled() {
+        let throttled = Throttled::parse("throttled=0x50005\n").unwrap();
+        assert!(throttled.under_voltage());
+        assert!(throttled.under_voltage_occurred());
+        assert!(throttled.throttled());
+
+        let throttled = Throttled::parse("50000").unwrap();
+        assert!(!throttled.under_voltage());
+        assert!(throttled.under_voltage_occurred());
+        assert!(!throttled.throttled());
+
+        assert_eq!(Throttled::parse("error"), None);
+    }
+}
@@ -0,0 +1,224 @@
+//! Journal of the inputs that have changed the looper's state. The state at
+//! the end of it, or at any point in it, can be rebuilt by replaying the
+//! inputs onto the state that it started from, on a virtual clock. `pidj-sim`
+//! replays it at the end of a run, to check that nothing changes the state
+//! without going through [`PlayState::apply`].
+//!
+//! The looper's ticks aren't kept, since they follow from the time. They are
+//! played again in between the inputs.
+//!
+//! The journal is only kept in memory, and only back to the last
+//! [`JOURNAL_LEN`] inputs. Undo and the saved state don't go through it; see
+//! [`crate::engine::PadHistory`] and [`crate::persist`].
+
+use std::{sync::Arc, time::Instant};
+
+use crate::{
+    clock::{Clock, VirtualClock},
+    engine::{Effect, Input, PlayState},
+};
+
+/// How many inputs the journal keeps, which is a good few minutes of
+/// playing. Past this, it starts again from the current state, so that it
+/// doesn't grow over a long set.
+pub const JOURNAL_LEN: usize = 10_000;
+
+/// An input, and when it happened.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub at: Instant,
+    pub input: Input,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Journal {
+    /// the state before the first entry, and when it was taken, since the
+    /// state's clock has moved on since
+    base: Option<(PlayState, Instant)>,
+    entries: Vec<Entry>,
+}
+
+impl Journal {
+    /// Applies `input` to `state` and keeps it. The journal starts from
+    /// `state` if it is empty.
+    pub fn apply(&mut self, state: &mut PlayState, input: Input) -> Vec<Effect> {
+        if self.base.is_none() || self.entries.len() >= JOURNAL_LEN {
+            self.start_from(state);
+        }
+
+        self.entries.push(Entry {
+            at: state.now(),
+            input: input.clone(),
+        });
+
+        state.apply(input)
+    }
+
+    /// Drops the entries, and starts the journal again from `state`.
+    pub fn start_from(&mut self, state: &PlayState) {
+        self.base = Some((state.clone(), state.now()));
+        self.entries.clear();
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// Rebuilds the state as it was just after the entries up to `until`
+    /// were applied, along with the virtual clock that it runs on, which is
+    /// at `until`. None if the journal hasn't started.
+    pub fn replay(&self, until: Instant) -> Option<(PlayState, VirtualClock)> {
+        let (base, started) = self.base.as_ref()?;
+        let clock = VirtualClock::starting_at(*started);
+        let mut state = base.clone().with_clock(Arc::new(clock.clone()));
+        let mut last_tick = None;
+
+        for entry in self.entries.iter().take_while(|entry| entry.at <= until) {
+            run_until(&mut state, &clock, entry.at, &mut last_tick);
+            state.apply(entry.input.clone());
+        }
+
+        run_until(&mut state, &clock, until, &mut last_tick);
+
+        Some((state, clock))
+    }
+
+    /// Rebuilds the current state. See [`Journal::replay`].
+    pub fn replay_all(&self) -> Option<(PlayState, VirtualClock)> {
+        let end = match self.entries.last() {
+            Some(entry) => entry.at,
+            None => self.base.as_ref()?.1,
+        };
+
+        self.replay(end)
+    }
+}
+
+/// Moves `clock` on to `at`, playing the looper's ticks on the way like the
+/// engine does.
+fn run_until(
+    state: &mut PlayState,
+    clock: &VirtualClock,
+    at: Instant,
+    last_tick: &mut Option<usize>,
+) {
+    loop {
+        let next = state.tick_due(last_tick).map(|(_, deadline)| deadline);
+
+        match next {
+            Some(deadline) if deadline <= at => {
+                clock.advance(deadline.saturating_duration_since(clock.now()));
+            }
+            _ => break,
+        }
+    }
+
+    clock.advance(at.saturating_duration_since(clock.now()));
+    state.tick_due(last_tick);
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use super::{run_until, Journal};
+    use crate::audio::{SoundId, SoundInfo};
+    use crate::clock::{Clock, VirtualClock};
+    use crate::driver::adafruit::seesaw::neopixel::Color;
+    use crate::engine::{Command, Input, PlayState};
+
+    #[test]
+    fn replay_rebuilds_the_state() {
+        let clock = VirtualClock::new();
+        let kick = SoundInfo {
+            id: SoundId(0),
+            path: PathBuf::from("audio/kick.wav"),
+            root: PathBuf::from("audio"),
+            duration: Duration::from_millis(500),
+            peaks: vec![],
+            loudness_db: -12.,
+            key: None,
+            tags: Default::default(),
+            ready: true,
+        };
+        let mut state = PlayState::new(vec![kick], Arc::new(clock.clone()));
+        state.sound_keys[0][0].binding = Some(SoundId(0));
+
+        let mut journal = Journal::default();
+        let mut last_tick = None;
+        let mut tap = |state: &mut PlayState, x, y| {
+            journal.apply(
+                state,
+                Input::Key {
+                    x,
+                    y,
+                    pressed: true,
+                },
+            );
+            journal.apply(
+                state,
+                Input::Key {
+                    x,
+                    y,
+                    pressed: false,
+                },
+            );
+        };
+
+        // F4 = looper on, then the pad is added to the loops
+        tap(&mut state, 3, 0);
+        run_until(
+            &mut state,
+            &clock,
+            clock.now() + Duration::from_millis(250),
+            &mut last_tick,
+        );
+        tap(&mut state, 0, 1);
+        run_until(
+            &mut state,
+            &clock,
+            clock.now() + Duration::from_secs(5),
+            &mut last_tick,
+        );
+
+        let before_color = clock.now();
+        run_until(
+            &mut state,
+            &clock,
+            before_color + Duration::from_millis(10),
+            &mut last_tick,
+        );
+
+        let red = Color::from_u8(255, 0, 0);
+        journal.apply(
+            &mut state,
+            Input::Command(Command::SetPadColor {
+                x: 0,
+                y: 1,
+                color: Some(red),
+            }),
+        );
+
+        let (replayed, replay_clock) = journal.replay_all().unwrap();
+        assert_eq!(replay_clock.now(), clock.now());
+        assert_eq!(replayed.loop_divider, state.loop_divider);
+        assert_eq!(replayed.sound_keys[0][0].color, Some(red));
+        assert_eq!(replayed.last_played, state.last_played);
+
+        let loops = |state: &PlayState| {
+            state
+                .loops
+                .iter()
+                .map(|l| (l.sound, l.offset, l.period))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(loops(&replayed), loops(&state));
+        assert_eq!(loops(&state).len(), 1);
+
+        // and as it was before the color was set
+        let (earlier, _) = journal.replay(before_color).unwrap();
+        assert_eq!(earlier.sound_keys[0][0].color, None);
+    }
+}
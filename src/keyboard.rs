@@ -1,27 +1,138 @@
-use std::{sync::Mutex, time::Duration};
+use std::{
+    collections::BTreeMap,
+    ops::DerefMut,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
 
-use anyhow::Context;
+use anyhow::{bail, Context};
+use embedded_hal::i2c::I2c;
 
-use rppal::i2c::I2c;
 use tokio_util::sync::CancellationToken;
-use tracing::{debug, trace};
+use tracing::{debug, info, trace, warn};
 
 use crate::{
+    animation::{Animation, Player},
+    config::{AnalogControl, AuxDeviceConfig, GpioAction, KeyboardConfig},
     driver::{
         adafruit::seesaw::{
+            adc,
+            asynch::{AsyncNeoTrellis, AsyncSeeSaw},
+            gpio,
             keypad::Edge,
-            neopixel::{Color, NeoPixel},
-            neotrellis::{KeyEvent, NeoTrellis},
+            neokey::{self, NeoKey},
+            neopixel::{self, Color, NeoPixel},
+            neoslider::NeoSlider,
+            neotrellis::{self, KeyEvent, NeoTrellis},
             SeeSaw,
         },
-        ThreadDelay,
+        open_i2c, SpawnBlockingI2c, ThreadDelay, TokioDelay,
     },
+    engine::{Problem, Subsystem},
+    grid::{Grid, NEOTRELLIS},
+    latency::Trace,
     util::Interval,
 };
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub enum Command {
-    SetState { x: u16, y: u16, state: PixelState },
+    SetState {
+        x: u16,
+        y: u16,
+        state: PixelState,
+        priority: Priority,
+    },
+    /// Sets every pixel at once, so that switching modes doesn't ripple
+    /// across the grid. Drawn as soon as possible, like an urgent
+    /// [`Command::SetState`]. Pixels that the keyboard doesn't have are left
+    /// out.
+    SetFrame(Grid<PixelState>),
+    /// Plays one of the animations over the LEDs, in place of the one that is
+    /// playing. See [`crate::animation`].
+    PlayAnimation { name: String },
+    /// Stops the animation that is playing, e.g. one that repeats.
+    StopAnimation,
+    /// Sets the duty cycle of one of the Seesaw's PWM pins, where `u16::MAX`
+    /// is fully on.
+    SetPwm { pin: u8, duty: u16 },
+    /// Sets the frequency of one of the Seesaw's PWM pins in Hz.
+    SetPwmFrequency { pin: u8, frequency: u16 },
+    /// Sets the brightness of the keypad LEDs, where 255 is full brightness.
+    /// This is saved on the NeoTrellis so that it stays with the board.
+    SetBrightness { brightness: u8 },
+    /// Sets the set list entry that the unit starts on, which is saved on the
+    /// NeoTrellis. See [`DeviceSettings::kit`].
+    SetKit { kit: Option<u8> },
+    /// Moves the NeoTrellis to another I2C address, which its Seesaw saves in
+    /// its EEPROM, and sets it up again. It is found at the new address from
+    /// then on, unless `keyboard.address` in the config pins the old one.
+    SetAddress { address: u8 },
+    /// Peak level of the master mix, where 1.0 is full scale, for the VU
+    /// meter.
+    Level(f32),
+}
+
+/// How quickly a [`Command::SetState`] needs to be drawn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Priority {
+    /// Drawn on the next frame, e.g. animations.
+    #[default]
+    Bulk,
+    /// Drawn as soon as possible, e.g. feedback for a key press.
+    Urgent,
+}
+
+/// How often the keyboard LEDs are redrawn.
+const FRAME_PERIOD: Duration = Duration::from_millis(1000 / 30);
+
+/// Settings stored in the NeoTrellis' EEPROM, so that they stay with the board
+/// when the SD card is moved between units. The board's I2C address is kept
+/// in the EEPROM too, by the Seesaw itself (see [`Command::SetAddress`]).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceSettings {
+    /// brightness of the keypad LEDs, where 255 is full brightness
+    pub brightness: u8,
+    /// set list entry that the unit starts on, so that units that share an
+    /// SD card can each start on their own kit
+    pub kit: Option<u8>,
+}
+
+impl DeviceSettings {
+    /// Marks the EEPROM as containing settings, because it is blank on a new
+    /// board.
+    const MAGIC: u8 = 0xD7;
+    const EEPROM_ADDRESS: u8 = 0x00;
+    /// Stands for no kit. A blank EEPROM reads as this, so boards that were
+    /// saved before the kit was don't start on one.
+    const NO_KIT: u8 = 0xFF;
+
+    /// The settings saved on a board, or None if it doesn't have any, in
+    /// which case `config`'s are used.
+    fn from_bytes(bytes: [u8; 3]) -> Option<Self> {
+        match bytes {
+            [Self::MAGIC, brightness, kit] => Some(Self {
+                brightness,
+                kit: (kit != Self::NO_KIT).then_some(kit),
+            }),
+            _ => None,
+        }
+    }
+
+    fn to_bytes(self) -> [u8; 3] {
+        [
+            Self::MAGIC,
+            self.brightness,
+            self.kit.unwrap_or(Self::NO_KIT),
+        ]
+    }
+
+    /// Settings for a board that doesn't have any saved yet.
+    fn unsaved(config: &KeyboardConfig) -> Self {
+        Self {
+            brightness: config.brightness.unwrap_or(u8::MAX),
+            kit: None,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -45,21 +156,631 @@ pub enum PixelState {
     },
 }
 
+/// Puts a [`Command::SetFrame`] into the pixel states of a grid, where the
+/// two overlap.
+fn set_frame(pixel_states: &mut Grid<PixelState>, frame: Grid<PixelState>) {
+    for (y, row) in frame.iter().enumerate() {
+        let Some(states) = pixel_states.get_mut(y) else {
+            break;
+        };
+
+        for (state, new) in states.iter_mut().zip(row) {
+            *state = *new;
+        }
+    }
+}
+
+/// Sets the pixel at (x, y), if the grid has it.
+fn set_state(pixel_states: &mut Grid<PixelState>, x: u16, y: u16, state: PixelState) {
+    match pixel_states
+        .get_mut(y as usize)
+        .and_then(|row| row.get_mut(x as usize))
+    {
+        Some(pixel) => *pixel = state,
+        None => trace!("there is no pixel at ({x}, {y})"),
+    }
+}
+
+impl PixelState {
+    /// Advances the pixel's animation by one frame. Returns the colour that the
+    /// pixel should be set to, or None if it doesn't need to be updated.
+    pub(crate) fn step(&mut self) -> Option<Color> {
+        match self {
+            // solid color pixels -> do nothing
+            PixelState::Solid { color, update } => {
+                if *update {
+                    *update = false;
+                    Some(*color)
+                } else {
+                    None
+                }
+            }
+            // fading pixels -> update
+            PixelState::FadeLinear {
+                from,
+                to,
+                duration,
+                progress,
+            } => {
+                *progress += duration.as_secs_f64();
+
+                let p = *progress;
+                let rp = 1. - p;
+
+                if p < 1. {
+                    Some(Color {
+                        r: (from.r as f64 * rp + to.r as f64 * p) as u8,
+                        g: (from.g as f64 * rp + to.g as f64 * p) as u8,
+                        b: (from.b as f64 * rp + to.b as f64 * p) as u8,
+                        w: (from.w as f64 * rp + to.w as f64 * p) as u8,
+                    })
+                } else {
+                    let to = *to;
+                    *self = PixelState::Solid {
+                        color: to,
+                        update: true,
+                    };
+                    Some(to)
+                }
+            }
+            PixelState::FadeExp {
+                from,
+                to,
+                duration,
+                progress,
+            } => {
+                *progress += duration.as_secs_f64();
+
+                let p = *progress;
+                let p = p * p * p;
+                let rp = 1. - p;
+
+                if p < 1. {
+                    Some(Color {
+                        r: (from.r as f64 * rp + to.r as f64 * p) as u8,
+                        g: (from.g as f64 * rp + to.g as f64 * p) as u8,
+                        b: (from.b as f64 * rp + to.b as f64 * p) as u8,
+                        w: (from.w as f64 * rp + to.w as f64 * p) as u8,
+                    })
+                } else {
+                    *self = PixelState::Solid {
+                        color: *to,
+                        update: true,
+                    };
+                    None
+                }
+            }
+        }
+    }
+
+    /// Makes a solid pixel draw its colour again on the next frame.
+    pub(crate) fn redraw(&mut self) {
+        if let PixelState::Solid { update, .. } = self {
+            *update = true;
+        }
+    }
+}
+
+/// How long the keys have to be left alone before the VU meter takes over the
+/// whole grid.
+const METER_IDLE: Duration = Duration::from_secs(10);
+
+/// The quietest level that lights the VU meter, in dB below full scale.
+const METER_RANGE_DB: f32 = 36.;
+
+/// How far the VU meter falls per frame, as a fraction of its height, so that
+/// it falls back like a needle instead of flickering.
+const METER_FALL: f32 = 0.05;
+
+/// Shows the level of the master mix on the keypad LEDs, on top of the pixel
+/// states. Pixels keep animating underneath the meter, and show their own
+/// colour again when the meter stops covering them.
+struct VuMeter {
+    column: Option<u16>,
+    /// rows of the grid, which the meter is as tall as
+    height: usize,
+    /// latest level, as a fraction of the meter's height
+    level: f32,
+    /// height that the meter is drawn at
+    shown: f32,
+    /// what the meter last drew on each pixel, so that it only sends changes
+    drawn: Grid<Option<Color>>,
+}
+
+impl VuMeter {
+    /// A meter on a grid of `size`, as its width and height.
+    fn new(column: Option<u16>, (width, height): (usize, usize)) -> Self {
+        Self {
+            column,
+            height,
+            level: 0.,
+            shown: 0.,
+            drawn: Grid::new(width, height),
+        }
+    }
+
+    /// Forgets what the meter drew, so that it draws everything again.
+    fn redraw(&mut self) {
+        self.drawn.cells_mut().fill(None);
+    }
+
+    fn set_level(&mut self, peak: f32) {
+        let db = 20. * peak.max(1e-6).log10();
+        self.level = ((db + METER_RANGE_DB) / METER_RANGE_DB).clamp(0., 1.);
+    }
+
+    /// Colour of the meter at (x, y), or None if it doesn't cover the pixel.
+    /// It is lit from the bottom up, going from green to red.
+    fn color(&self, x: u16, y: u16, idle: bool) -> Option<Color> {
+        let column = self.column?;
+
+        if x != column && !idle {
+            return None;
+        }
+
+        let height = self.height as f32;
+        let segment = (self.height - 1 - y as usize) as f32;
+
+        if self.shown * height <= segment {
+            return Some(Color::BLACK);
+        }
+
+        // the bottom half is green, the next quarter amber and the top red
+        let top = (segment + 1.) / height;
+
+        Some(if top <= 0.5 {
+            Color::from_u8(0, 255, 0)
+        } else if top <= 0.75 {
+            Color::from_u8(255, 200, 0)
+        } else {
+            Color::from_u8(255, 0, 0)
+        })
+    }
+
+    /// Advances the meter and the pixel states by one frame. Returns the
+    /// pixels that need to be set, and their colours.
+    fn step(&mut self, idle: bool, pixel_states: &mut Grid<PixelState>) -> Vec<(u16, u16, Color)> {
+        self.shown = self.level.max(self.shown - METER_FALL);
+
+        let mut changes = vec![];
+        let positions = pixel_states.positions();
+
+        for (i, ((x, y), state)) in positions.zip(pixel_states.cells_mut()).enumerate() {
+            let (x, y) = (x as u16, y as u16);
+
+            match self.color(x, y, idle) {
+                Some(color) => {
+                    state.step();
+
+                    if self.drawn.cells_mut()[i].replace(color) != Some(color) {
+                        changes.push((x, y, color));
+                    }
+                }
+                None => {
+                    // uncovered, so the pixel's own colour has to be drawn
+                    if self.drawn.cells_mut()[i].take().is_some() {
+                        state.redraw();
+                    }
+
+                    if let Some(color) = state.step() {
+                        changes.push((x, y, color));
+                    }
+                }
+            }
+        }
+
+        changes
+    }
+}
+
+/// Starts playing the animation called `name`, from the ones in the config or
+/// the built-in ones, on a grid of `size`.
+fn play_animation(
+    animation: &mut Option<Player>,
+    name: &str,
+    custom: &BTreeMap<String, Animation>,
+    size: (usize, usize),
+) {
+    match Animation::named(name, custom) {
+        Some(named) => *animation = Player::new(&named, size, Instant::now()),
+        None => warn!("there is no animation called {name:?}"),
+    }
+}
+
+/// Advances the animation that is playing, or the VU meter while there isn't
+/// one, and the pixel states underneath by one frame. Returns the pixels that
+/// need to be set, and their colours.
+fn step_frame(
+    animation: &mut Option<Player>,
+    meter: &mut VuMeter,
+    idle: bool,
+    pixel_states: &mut Grid<PixelState>,
+) -> Vec<(u16, u16, Color)> {
+    if let Some(player) = animation {
+        if let Some(changes) = player.step(Instant::now(), pixel_states) {
+            return changes;
+        }
+
+        // the animation is over, so everything under it is drawn again
+        *animation = None;
+
+        for state in pixel_states.cells_mut() {
+            state.redraw();
+        }
+
+        meter.redraw();
+    }
+
+    meter.step(idle, pixel_states)
+}
+
+/// A grid of keys with LEDs under them, which [`run_leds`] draws on: the
+/// NeoTrellis, or a MIDI grid controller in its place (see
+/// [`crate::midi_grid`]). Each backend reads its own keys, and sends them as
+/// [`Event::Key`].
+pub trait Backend {
+    /// Width and height of the grid of keys.
+    fn grid_size(&self) -> (usize, usize);
+
+    /// Sets the keys that have changed to their new colours, and shows them.
+    fn draw(&mut self, changes: &[(u16, u16, Color)]) -> anyhow::Result<()>;
+
+    /// Carries out a command that [`run_leds`] doesn't handle itself, like
+    /// setting a PWM pin. Returns whether every key has to be drawn again,
+    /// e.g. because the brightness changed.
+    fn command(&mut self, cmd: Command) -> anyhow::Result<bool> {
+        trace!("command {cmd:?} is not supported by this keyboard");
+        Ok(false)
+    }
+}
+
+/// Draws the pixel states that are sent in commands on `backend` until
+/// cancelled, with the animations and the VU meter over them, and then turns
+/// the keys off. `last_key` is when a key was last pressed, so that the VU
+/// meter knows when it is idle.
+pub(crate) fn run_leds(
+    ct: &CancellationToken,
+    backend: &mut impl Backend,
+    cmd_rx: &flume::Receiver<Command>,
+    animations: &BTreeMap<String, Animation>,
+    meter_column: Option<u16>,
+    last_key: &Mutex<Instant>,
+) -> anyhow::Result<()> {
+    let (width, height) = backend.grid_size();
+    let mut pixel_states = Grid::from_fn(width, height, |_, _| PixelState::Solid {
+        color: Color::WHITE,
+        update: true,
+    });
+
+    let mut next_frame = Instant::now();
+    let mut meter = VuMeter::new(meter_column, (width, height));
+    let mut animation = None;
+
+    debug!("running keyboard colour loop");
+
+    'frame: while !ct.is_cancelled() {
+        let idle = last_key.lock().unwrap().elapsed() >= METER_IDLE;
+        let changes = step_frame(&mut animation, &mut meter, idle, &mut pixel_states);
+        backend.draw(&changes)?;
+
+        next_frame = (next_frame + FRAME_PERIOD).max(Instant::now());
+        let mut deadline = next_frame;
+
+        // execute commands until it's time for the next frame. pixel states
+        // are only drawn once per frame, so the last state set for a pixel
+        // wins, and urgent ones bring the frame forward (after picking up
+        // whatever else has already been sent)
+        loop {
+            let cmd = match cmd_rx.recv_deadline(deadline) {
+                Ok(cmd) => cmd,
+                Err(flume::RecvTimeoutError::Timeout) => break,
+                Err(flume::RecvTimeoutError::Disconnected) => break 'frame,
+            };
+
+            trace!("executing command {cmd:?}");
+
+            match cmd {
+                Command::SetState {
+                    x,
+                    y,
+                    state,
+                    priority,
+                } => {
+                    set_state(&mut pixel_states, x, y, state);
+
+                    if priority == Priority::Urgent {
+                        deadline = Instant::now();
+                    }
+                }
+                Command::SetFrame(frame) => {
+                    set_frame(&mut pixel_states, frame);
+                    deadline = Instant::now();
+                }
+                Command::PlayAnimation { name } => {
+                    play_animation(&mut animation, &name, animations, (width, height));
+                }
+                Command::StopAnimation => {
+                    if let Some(player) = &mut animation {
+                        player.stop();
+                    }
+                }
+                Command::Level(peak) => meter.set_level(peak),
+                cmd => {
+                    if backend.command(cmd)? {
+                        for state in pixel_states.cells_mut() {
+                            state.redraw();
+                        }
+
+                        meter.redraw();
+                    }
+                }
+            }
+        }
+    }
+
+    // when program is exited, turn the keyboard off
+    let off: Vec<_> = pixel_states
+        .positions()
+        .map(|(x, y)| (x as u16, y as u16, Color::BLACK))
+        .collect();
+    backend.draw(&off)?;
+
+    debug!("exiting keyboard colour loop");
+
+    Ok(())
+}
+
 #[derive(Debug, Clone, Copy)]
 pub enum Event {
-    Key(KeyEvent),
+    /// A key was pressed or released. The trace records when the keypad read
+    /// that found it started and finished.
+    Key(KeyEvent, Trace),
+    /// An input connected to one of the spare Seesaw pins was pressed or
+    /// released.
+    Gpio { action: GpioAction, pressed: bool },
+    /// An input connected to one of the Seesaw's analog pins moved. The value
+    /// is between 0 and 1.
+    Analog { control: AnalogControl, value: f32 },
+    /// A key on an auxiliary NeoKey was pressed or released. Keys are numbered
+    /// from left to right.
+    AuxKey { key: usize, pressed: bool },
+    /// Sent every [`STATUS_PERIOD`] with the firmware version of the
+    /// NeoTrellis's Seesaw and its temperature in °C.
+    Status { version: u32, temperature: u32 },
+    /// The settings saved on the NeoTrellis and its I2C address, sent when
+    /// they are read at startup and whenever they change.
+    Settings {
+        settings: DeviceSettings,
+        address: u8,
+    },
 }
 
+/// How often the keyboard reports its [`Event::Status`].
+pub const STATUS_PERIOD: Duration = Duration::from_secs(5);
+
+/// At or above this temperature in °C, the NeoTrellis is warned about. Its
+/// Seesaw is rated up to 85°C, but in a closed box the LEDs and the Pi are
+/// what heat it, so it is worth knowing well before then.
+pub const HOT_TEMPERATURE: u32 = 60;
+
+/// How far below [`HOT_TEMPERATURE`] the NeoTrellis has to cool before it
+/// stops being hot, so that a reading that wavers around it doesn't warn
+/// again and again.
+const HOT_HYSTERESIS: u32 = 5;
+
+/// Whether the NeoTrellis is hot at `temperature`, given whether it was
+/// hot at the last reading.
+pub fn is_hot(temperature: u32, was_hot: bool) -> bool {
+    match was_hot {
+        true => temperature + HOT_HYSTERESIS > HOT_TEMPERATURE,
+        false => temperature >= HOT_TEMPERATURE,
+    }
+}
+
+/// How far an analog input has to move (in ADC steps) before an event is sent,
+/// so that noise doesn't cause a stream of events.
+const ANALOG_THRESHOLD: u16 = 4;
+
+/// How often the Seesaw is asked whether it is back after a software reset,
+/// and how long it gets to come back.
+const RESET_POLL: Duration = Duration::from_millis(10);
+const RESET_TIMEOUT: Duration = Duration::from_secs(1);
+
 pub fn run(
     ct: CancellationToken,
+    config: KeyboardConfig,
     cmd_rx: flume::Receiver<Command>,
     evt_tx: flume::Sender<Event>,
+    problem_tx: flume::Sender<Problem>,
 ) -> anyhow::Result<()> {
-    let i2c = I2c::new().context("failed to open i2c bus")?;
-    let mut seesaw = SeeSaw { i2c, address: 0x2E };
+    let i2c = open_i2c(config.bus.as_ref())?;
+    run_with_bus(ct, config, i2c, cmd_rx, evt_tx, problem_tx)
+}
+
+/// The NeoTrellis's LEDs, which are shared with the thread that reads its
+/// keys. Colours are dimmed to the brightness in its settings.
+struct TrellisLeds<'a, N> {
+    nt: &'a Mutex<N>,
+    settings: DeviceSettings,
+    /// GPIO inputs to set up again if the board is moved to another address
+    gpio_mask: u32,
+    evt_tx: flume::Sender<Event>,
+}
+
+impl<I2C, S, NP> Backend for TrellisLeds<'_, NeoTrellis<I2C, S, NP>>
+where
+    I2C: I2c,
+    I2C::Error: std::error::Error + Send + Sync + 'static,
+    S: DerefMut<Target = SeeSaw<I2C>>,
+    NP: DerefMut<Target = NeoPixel<I2C, S, neopixel::GRB, 16>>,
+{
+    fn grid_size(&self) -> (usize, usize) {
+        NEOTRELLIS
+    }
+
+    fn draw(&mut self, changes: &[(u16, u16, Color)]) -> anyhow::Result<()> {
+        let mut nt = self.nt.lock().unwrap();
+
+        for &(x, y, color) in changes {
+            nt.set_pixel_color(x, y, color.scale(self.settings.brightness))
+                .context("failed to set pixel color")?;
+        }
+
+        std::thread::sleep(Duration::from_micros(300));
+        nt.show().context("failed to show pixels")
+    }
+
+    fn command(&mut self, cmd: Command) -> anyhow::Result<bool> {
+        let mut nt = self.nt.lock().unwrap();
+
+        match cmd {
+            Command::SetPwm { pin, duty } => {
+                nt.set_pwm(pin, duty)
+                    .context("failed to set pwm duty cycle")?;
+            }
+            Command::SetPwmFrequency { pin, frequency } => {
+                nt.set_pwm_frequency(pin, frequency)
+                    .context("failed to set pwm frequency")?;
+            }
+            Command::SetBrightness { brightness } => {
+                self.settings.brightness = brightness;
+
+                nt.write_eeprom(DeviceSettings::EEPROM_ADDRESS, &self.settings.to_bytes())
+                    .context("failed to write settings to eeprom")?;
+                self.report(nt.address);
+
+                // redraw every pixel at the new brightness
+                return Ok(true);
+            }
+            Command::SetKit { kit } => {
+                self.settings.kit = kit;
+
+                nt.write_eeprom(DeviceSettings::EEPROM_ADDRESS, &self.settings.to_bytes())
+                    .context("failed to write settings to eeprom")?;
+                self.report(nt.address);
+            }
+            Command::SetAddress { address } => {
+                info!("moving the neotrellis to address {address:#04x}");
+
+                nt.set_i2c_address(address)
+                    .context("failed to set i2c address")?;
+                wait_for_reset(&mut nt)?;
+                setup(&mut nt, self.gpio_mask)?;
+                self.report(address);
+
+                // the reset turned the LEDs off
+                return Ok(true);
+            }
+            cmd => trace!("command {cmd:?} is not supported by the neotrellis"),
+        }
+
+        Ok(false)
+    }
+}
+
+impl<N> TrellisLeds<'_, N> {
+    /// Tells the engine what the board's settings and address are now.
+    fn report(&self, address: u8) {
+        let _ = self.evt_tx.send(Event::Settings {
+            settings: self.settings,
+            address,
+        });
+    }
+}
+
+/// Waits for a Seesaw that was just reset to answer again, by asking for its
+/// version until it replies.
+fn wait_for_reset<I2C>(seesaw: &mut SeeSaw<I2C>) -> anyhow::Result<()>
+where
+    I2C: I2c,
+    I2C::Error: std::error::Error + Send + Sync + 'static,
+{
     let mut delay = ThreadDelay;
+    let start = Instant::now();
 
-    seesaw.sw_reset()?;
+    loop {
+        std::thread::sleep(RESET_POLL);
+
+        match seesaw.get_version(&mut delay) {
+            Ok(_) => return Ok(()),
+            Err(_) if start.elapsed() < RESET_TIMEOUT => {}
+            Err(err) => return Err(err).context("seesaw didn't come back after being reset"),
+        }
+    }
+}
+
+/// Sets up the NeoTrellis's LEDs, its keypad and the GPIO inputs in
+/// `gpio_mask`, after its Seesaw has been reset.
+fn setup<I2C, S, NP>(nt: &mut NeoTrellis<I2C, S, NP>, gpio_mask: u32) -> anyhow::Result<()>
+where
+    I2C: I2c,
+    I2C::Error: std::error::Error + Send + Sync + 'static,
+    S: DerefMut<Target = SeeSaw<I2C>>,
+    NP: DerefMut<Target = NeoPixel<I2C, S, neopixel::GRB, 16>>,
+{
+    nt.init().context("failed to initialize neotrellis")?;
+
+    for x in 0..4 {
+        for y in 0..4 {
+            nt.set_keypad_event(x, y, Edge::Rising, true)
+                .context("failed to enable keypad rising edge event")?;
+            nt.set_keypad_event(x, y, Edge::Falling, true)
+                .context("failed to enable keypad falling edge event")?;
+        }
+    }
+
+    if gpio_mask != 0 {
+        nt.set_gpio_pin_mode_bulk(gpio_mask, gpio::PinMode::InputPullUp)
+            .context("failed to configure gpio inputs")?;
+    }
+
+    Ok(())
+}
+
+/// Same as [`run`], but drives the keyboard over the given bus instead of
+/// opening the one in the config. Auxiliary devices still open their own buses.
+pub fn run_with_bus<I2C>(
+    ct: CancellationToken,
+    config: KeyboardConfig,
+    i2c: I2C,
+    cmd_rx: flume::Receiver<Command>,
+    evt_tx: flume::Sender<Event>,
+    problem_tx: flume::Sender<Problem>,
+) -> anyhow::Result<()>
+where
+    I2C: I2c + Send,
+    I2C::Error: std::error::Error + Send + Sync + 'static,
+{
+    let mut seesaw = SeeSaw::new(i2c, neotrellis::DEFAULT_ADDRESS);
+    let mut delay = ThreadDelay;
+
+    match config.address {
+        Some(address) => seesaw.address = address,
+        None => {
+            let found = seesaw.scan(neotrellis::ADDRESSES, &mut delay);
+
+            match &found[..] {
+                [] => bail!(
+                    "no seesaw device found at addresses {:#04x}..={:#04x}",
+                    neotrellis::ADDRESSES.start(),
+                    neotrellis::ADDRESSES.end()
+                ),
+                [address] => seesaw.address = *address,
+                [address, ..] => {
+                    warn!(
+                        "found multiple seesaw devices at {found:#04x?}, using {address:#04x}; \
+                        set keyboard.address in {} to choose another",
+                        crate::config::CONFIG_PATH
+                    );
+                    seesaw.address = *address;
+                }
+            }
+        }
+    }
+
+    debug!("using seesaw at address {:#04x}", seesaw.address);
+
+    seesaw.sw_reset().context("failed to reset seesaw")?;
     let seesaw_ver = seesaw
         .get_version(&mut delay)
         .context("failed to get seesaw version")?;
@@ -67,191 +788,513 @@ pub fn run(
 
     let mut np = NeoPixel::new(&mut seesaw);
     let mut nt = NeoTrellis::new(&mut np);
-    nt.init()?;
+    let gpio_mask = gpio::pin_mask(config.gpio_inputs.iter().map(|input| input.pin));
+    setup(&mut nt, gpio_mask)?;
 
-    for x in 0..4 {
-        for y in 0..4 {
-            nt.set_keypad_event(x, y, Edge::Rising, true)?;
-            nt.set_keypad_event(x, y, Edge::Falling, true)?;
-        }
-    }
+    let mut settings_buf = [0u8; 3];
+    nt.read_eeprom(
+        DeviceSettings::EEPROM_ADDRESS,
+        &mut settings_buf,
+        &mut delay,
+    )
+    .context("failed to read settings from eeprom")?;
+    let settings = DeviceSettings::from_bytes(settings_buf)
+        .unwrap_or_else(|| DeviceSettings::unsaved(&config));
+    debug!("loaded device settings {settings:?}");
+
+    let _ = evt_tx.send(Event::Settings {
+        settings,
+        address: nt.address,
+    });
 
     debug!("initialized adafruit neotrellis driver");
 
     let nt = Mutex::new(nt);
 
-    std::thread::scope(|s| {
-        s.spawn({
+    // when a key was last pressed, so that the VU meter knows when it is idle
+    let last_key = Mutex::new(Instant::now());
+
+    // cancelled when either of the threads that the keyboard needs fails, so
+    // that the other one stops too
+    let ct = ct.child_token();
+
+    let result = std::thread::scope(|s| {
+        let leds = s.spawn({
             let nt = &nt;
+            let last_key = &last_key;
+            let config = &config;
             let ct = ct.clone();
-            move || -> anyhow::Result<()> {
-                let mut pixel_states = vec![
-                    PixelState::Solid {
-                        color: Color::WHITE,
-                        update: true,
-                    };
-                    16
-                ];
-
-                let mut interval = Interval::new(Duration::from_millis(1000 / 30));
-
-                debug!("running keyboard colour loop");
-
-                while !ct.is_cancelled() {
-                    interval.tick();
-
-                    {
-                        let mut nt = nt.lock().unwrap();
-
-                        for (i, state) in pixel_states.iter_mut().enumerate() {
-                            let x = (i % 4) as u16;
-                            let y = (i / 4) as u16;
-
-                            match state {
-                                // solid color pixels -> do nothing
-                                PixelState::Solid { color, update } => {
-                                    if *update {
-                                        nt.set_pixel_color(x, y, *color)?;
-                                        *update = false;
-                                    }
-                                }
-                                // fading pixels -> update
-                                PixelState::FadeLinear {
-                                    from,
-                                    to,
-                                    duration,
-                                    progress,
-                                } => {
-                                    *progress += duration.as_secs_f64();
-
-                                    let p = *progress;
-                                    let rp = 1. - p;
-
-                                    if p < 1. {
-                                        let current = Color {
-                                            r: (from.r as f64 * rp + to.r as f64 * p) as u8,
-                                            g: (from.g as f64 * rp + to.g as f64 * p) as u8,
-                                            b: (from.b as f64 * rp + to.b as f64 * p) as u8,
-                                            w: (from.w as f64 * rp + to.w as f64 * p) as u8,
-                                        };
-
-                                        nt.set_pixel_color(x, y, current)?;
-                                    } else {
-                                        nt.set_pixel_color(x, y, *to)?;
-                                        *state = PixelState::Solid {
-                                            color: *to,
-                                            update: true,
-                                        };
-                                    }
-                                }
-                                PixelState::FadeExp {
-                                    from,
-                                    to,
-                                    duration,
-                                    progress,
-                                } => {
-                                    *progress += duration.as_secs_f64();
-
-                                    let p = *progress;
-                                    let p = p * p * p;
-                                    let rp = 1. - p;
-
-                                    if p < 1. {
-                                        let current = Color {
-                                            r: (from.r as f64 * rp + to.r as f64 * p) as u8,
-                                            g: (from.g as f64 * rp + to.g as f64 * p) as u8,
-                                            b: (from.b as f64 * rp + to.b as f64 * p) as u8,
-                                            w: (from.w as f64 * rp + to.w as f64 * p) as u8,
-                                        };
-
-                                        nt.set_pixel_color(x, y, current)?;
-                                    } else {
-                                        *state = PixelState::Solid {
-                                            color: *to,
-                                            update: true,
-                                        };
-                                    }
-                                }
-                            }
-                        }
+            let evt_tx = evt_tx.clone();
+            move || {
+                let mut leds = TrellisLeds {
+                    nt,
+                    settings,
+                    gpio_mask,
+                    evt_tx,
+                };
 
-                        std::thread::sleep(Duration::from_micros(300));
-                        nt.show()?;
-                    }
+                let result = run_leds(
+                    &ct,
+                    &mut leds,
+                    &cmd_rx,
+                    &config.animations,
+                    config.meter_column,
+                    last_key,
+                );
 
-                    match cmd_rx.try_recv() {
-                        Ok(mut cmd) => {
-                            // then pull all of the pending commands out of the channel and
-                            // execute them
-                            loop {
-                                trace!("executing command {cmd:?}");
-
-                                match cmd {
-                                    Command::SetState { x, y, state } => {
-                                        let i = (y * 4 + x) as usize;
-                                        pixel_states[i] = state;
-                                    }
-                                }
-
-                                cmd = match cmd_rx.try_recv() {
-                                    Ok(cmd) => cmd,
-                                    Err(_) => break,
-                                };
-                            }
-                        }
-                        Err(flume::TryRecvError::Empty) => {
-                            // there are no commands, so we just keep going
-                        }
-                        Err(flume::TryRecvError::Disconnected) => break,
-                    };
+                if result.is_err() {
+                    ct.cancel();
                 }
 
-                // when program is exited, turn the keyboard off
-                {
-                    let nt = &mut *nt.lock().unwrap();
-                    for x in 0..4 {
-                        for y in 0..4 {
-                            nt.set_pixel_color(x, y, Color::BLACK)?;
-                        }
+                result
+            }
+        });
+
+        for aux in &config.aux_devices {
+            s.spawn({
+                let ct = ct.clone();
+                let evt_tx = evt_tx.clone();
+                let problem_tx = problem_tx.clone();
+                move || -> anyhow::Result<()> {
+                    let result = run_aux_device(&ct, aux, &evt_tx);
+
+                    if let Err(err) = &result {
+                        warn!("auxiliary device {aux:?} failed: {err:?}");
+
+                        let name = match aux {
+                            AuxDeviceConfig::NeoKey { address, .. } => {
+                                format!("NeoKey at {address:#04x}")
+                            }
+                            AuxDeviceConfig::NeoSlider { address, .. } => {
+                                format!("NeoSlider at {address:#04x}")
+                            }
+                        };
+
+                        // the keypad keeps working without it
+                        let _ = problem_tx.send(Problem {
+                            subsystem: Some(Subsystem::Keyboard),
+                            message: format!("{name} stopped: {err:#}"),
+                        });
                     }
 
-                    std::thread::sleep(Duration::from_micros(300));
-                    nt.show()?;
+                    result
                 }
+            });
+        }
+
+        let events = s.spawn({
+            let nt = &nt;
+            let last_key = &last_key;
+            let config = &config;
+            let ct = ct.clone();
+            move || {
+                let result =
+                    run_keyboard_events(&ct, nt, last_key, config, gpio_mask, seesaw_ver, &evt_tx);
 
-                debug!("exiting keyboard colour loop");
+                if result.is_err() {
+                    ct.cancel();
+                }
 
-                Ok(())
+                result
             }
         });
 
-        s.spawn({
-            let nt = &nt;
-            move || -> anyhow::Result<()> {
-                debug!("starting keyboard event loop");
+        // the event loop's error comes first, since the LEDs usually fail
+        // because of the same fault
+        [events.join(), leds.join()]
+            .into_iter()
+            .try_for_each(|joined| joined.unwrap_or_else(|panic| std::panic::resume_unwind(panic)))
+    });
 
-                // sample keyboard for events at 30Hz
+    debug!("keyboard task exited");
 
-                let mut interval = Interval::new(Duration::from_millis(1000 / 30));
+    result
+}
 
-                while !ct.is_cancelled() {
-                    interval.tick();
-                    let mut nt = nt.lock().unwrap();
+/// Reads the NeoTrellis's keypad, its temperature and its GPIO and analog
+/// inputs in `config` until `ct` is cancelled, and sends what changed as
+/// events.
+fn run_keyboard_events<I2C, S, NP>(
+    ct: &CancellationToken,
+    nt: &Mutex<NeoTrellis<I2C, S, NP>>,
+    last_key: &Mutex<Instant>,
+    config: &KeyboardConfig,
+    gpio_mask: u32,
+    seesaw_ver: u32,
+    evt_tx: &flume::Sender<Event>,
+) -> anyhow::Result<()>
+where
+    I2C: I2c,
+    I2C::Error: std::error::Error + Send + Sync + 'static,
+    S: DerefMut<Target = SeeSaw<I2C>>,
+    NP: DerefMut<Target = NeoPixel<I2C, S, neopixel::GRB, 16>>,
+{
+    let KeyboardConfig {
+        gpio_inputs,
+        analog_inputs,
+        ..
+    } = config;
+    let mut delay = ThreadDelay;
 
-                    for evt in nt.get_keypad_events(&mut delay)? {
-                        trace!("received event {evt:?}");
-                        let _ = evt_tx.send(Event::Key(evt));
-                    }
+    debug!("starting keyboard event loop");
+
+    // inputs are pulled up, so all pins start out high (released)
+    let mut gpio_levels = gpio_mask;
+
+    // last reported value of each analog input; None so that the
+    // initial position is always reported
+    let mut analog_values = vec![None; analog_inputs.len()];
+
+    // sample keyboard for events at 30Hz
+
+    let mut interval = Interval::new(Duration::from_millis(1000 / 30));
+    let mut next_status = Instant::now() + STATUS_PERIOD;
+
+    while !ct.is_cancelled() {
+        interval.tick();
+        let mut nt = nt.lock().unwrap();
+
+        if Instant::now() >= next_status {
+            next_status += STATUS_PERIOD;
+
+            let temperature = nt
+                .get_temp(&mut delay)
+                .context("failed to read seesaw temperature")?;
+            let _ = evt_tx.send(Event::Status {
+                version: seesaw_ver,
+                temperature,
+            });
+        }
+
+        let scan_start = Instant::now();
+        let evts = nt
+            .get_keypad_events(&mut delay)
+            .context("failed to read keypad events")?;
+        let scan = Trace::new(scan_start, Instant::now());
+
+        if !evts.is_empty() {
+            *last_key.lock().unwrap() = Instant::now();
+        }
+
+        for evt in evts {
+            trace!("received event {evt:?}");
+            let _ = evt_tx.send(Event::Key(evt, scan));
+        }
+
+        if gpio_mask != 0 {
+            let levels = nt
+                .get_gpio_bulk(&mut delay)
+                .context("failed to read gpio inputs")?
+                & gpio_mask;
+            let changed = levels ^ gpio_levels;
+            gpio_levels = levels;
+
+            for input in gpio_inputs {
+                let pin = 1 << input.pin;
+
+                if changed & pin != 0 {
+                    let pressed = levels & pin == 0;
+                    trace!("gpio input {input:?} pressed = {pressed}");
+                    let _ = evt_tx.send(Event::Gpio {
+                        action: input.action,
+                        pressed,
+                    });
                 }
+            }
+        }
 
-                debug!("exiting keyboard event loop");
+        for (input, last) in analog_inputs.iter().zip(analog_values.iter_mut()) {
+            let value = nt
+                .get_analog(input.channel, &mut delay)
+                .context("failed to read analog input")?;
 
-                Ok(())
+            let moved = match *last {
+                Some(last) => value.abs_diff(last) >= ANALOG_THRESHOLD,
+                None => true,
+            };
+
+            if moved {
+                *last = Some(value);
+                let _ = evt_tx.send(Event::Analog {
+                    control: input.control,
+                    value: value as f32 / adc::MAX_VALUE as f32,
+                });
             }
-        });
+        }
+    }
+
+    debug!("exiting keyboard event loop");
+
+    Ok(())
+}
+
+/// Runs the keyboard on the tokio runtime using the async Seesaw driver. Unlike
+/// [`run`], this only drives the keypad and its LEDs, and reports its
+/// [`Event::Status`].
+pub async fn run_async(
+    ct: CancellationToken,
+    config: KeyboardConfig,
+    cmd_rx: flume::Receiver<Command>,
+    evt_tx: flume::Sender<Event>,
+) -> anyhow::Result<()> {
+    if !config.gpio_inputs.is_empty()
+        || !config.analog_inputs.is_empty()
+        || !config.aux_devices.is_empty()
+    {
+        warn!("gpio, analog and auxiliary inputs are not supported by the async keyboard driver");
+    }
+
+    let i2c = SpawnBlockingI2c::new(open_i2c(config.bus.as_ref())?);
+    let address = config.address.unwrap_or(neotrellis::DEFAULT_ADDRESS);
+    let mut nt = AsyncNeoTrellis::new(AsyncSeeSaw::new(i2c, address));
+    let mut delay = TokioDelay;
+
+    nt.0.sw_reset().await.context("failed to reset seesaw")?;
+    let seesaw_ver =
+        nt.0.get_version(&mut delay)
+            .await
+            .context("failed to get seesaw version")?;
+    debug!("initialized async adafruit seesaw driver, ver = {seesaw_ver}");
+
+    nt.init().await.context("failed to initialize neotrellis")?;
+
+    for x in 0..4 {
+        for y in 0..4 {
+            nt.set_keypad_event(x, y, Edge::Rising, true)
+                .await
+                .context("failed to enable keypad rising edge event")?;
+            nt.set_keypad_event(x, y, Edge::Falling, true)
+                .await
+                .context("failed to enable keypad falling edge event")?;
+        }
+    }
+
+    debug!("initialized async adafruit neotrellis driver");
+
+    let mut settings_buf = [0u8; 3];
+    nt.0.read_eeprom(
+        DeviceSettings::EEPROM_ADDRESS,
+        &mut settings_buf,
+        &mut delay,
+    )
+    .await
+    .context("failed to read settings from eeprom")?;
+    let mut settings = DeviceSettings::from_bytes(settings_buf)
+        .unwrap_or_else(|| DeviceSettings::unsaved(&config));
+    debug!("loaded device settings {settings:?}");
+
+    let _ = evt_tx.send(Event::Settings { settings, address });
+
+    let (width, height) = NEOTRELLIS;
+    let mut pixel_states = Grid::from_fn(width, height, |_, _| PixelState::Solid {
+        color: Color::WHITE,
+        update: true,
     });
 
-    debug!("keyboard task exited");
+    let mut meter = VuMeter::new(config.meter_column, NEOTRELLIS);
+    let mut animation = None;
+    let mut last_key = Instant::now();
+
+    // update colours and sample keyboard for events at 30Hz
+    let mut interval = tokio::time::interval(Duration::from_millis(1000 / 30));
+    let mut status =
+        tokio::time::interval_at(tokio::time::Instant::now() + STATUS_PERIOD, STATUS_PERIOD);
+
+    loop {
+        tokio::select! {
+            _ = ct.cancelled() => break,
+            _ = status.tick() => {
+                let temperature =
+                    nt.0.get_temp(&mut delay)
+                        .await
+                        .context("failed to read seesaw temperature")?;
+                let _ = evt_tx.send(Event::Status {
+                    version: seesaw_ver,
+                    temperature,
+                });
+            }
+            _ = interval.tick() => {
+                let idle = last_key.elapsed() >= METER_IDLE;
+
+                for (x, y, color) in step_frame(&mut animation, &mut meter, idle, &mut pixel_states) {
+                    nt.set_pixel_color(x, y, color.scale(settings.brightness))
+                        .await
+                        .context("failed to set pixel color")?;
+                }
+
+                nt.show().await.context("failed to show pixels")?;
+
+                let scan_start = Instant::now();
+                let evts = nt
+                    .get_keypad_events(&mut delay)
+                    .await
+                    .context("failed to read keypad events")?;
+                let scan = Trace::new(scan_start, Instant::now());
+
+                if !evts.is_empty() {
+                    last_key = Instant::now();
+                }
+
+                for evt in evts {
+                    trace!("received event {evt:?}");
+                    let _ = evt_tx.send(Event::Key(evt, scan));
+                }
+            }
+            cmd = cmd_rx.recv_async() => match cmd {
+                Ok(Command::SetState { x, y, state, .. }) => {
+                    set_state(&mut pixel_states, x, y, state);
+                }
+                Ok(Command::SetFrame(frame)) => set_frame(&mut pixel_states, frame),
+                Ok(Command::PlayAnimation { name }) => {
+                    play_animation(&mut animation, &name, &config.animations, NEOTRELLIS);
+                }
+                Ok(Command::StopAnimation) => {
+                    if let Some(player) = &mut animation {
+                        player.stop();
+                    }
+                }
+                Ok(Command::Level(peak)) => meter.set_level(peak),
+                Ok(Command::SetPwm { pin, duty }) => {
+                    nt.0.set_pwm(pin, duty)
+                        .await
+                        .context("failed to set pwm duty cycle")?;
+                }
+                Ok(Command::SetPwmFrequency { pin, frequency }) => {
+                    nt.0.set_pwm_frequency(pin, frequency)
+                        .await
+                        .context("failed to set pwm frequency")?;
+                }
+                Ok(Command::SetBrightness { brightness }) => {
+                    settings.brightness = brightness;
+
+                    nt.0.write_eeprom(DeviceSettings::EEPROM_ADDRESS, &settings.to_bytes())
+                        .await
+                        .context("failed to write settings to eeprom")?;
+                    let _ = evt_tx.send(Event::Settings { settings, address });
+
+                    // redraw every pixel at the new brightness
+                    for state in pixel_states.cells_mut() {
+                        state.redraw();
+                    }
+
+                    meter.redraw();
+                }
+                Ok(Command::SetKit { kit }) => {
+                    settings.kit = kit;
+
+                    nt.0.write_eeprom(DeviceSettings::EEPROM_ADDRESS, &settings.to_bytes())
+                        .await
+                        .context("failed to write settings to eeprom")?;
+                    let _ = evt_tx.send(Event::Settings { settings, address });
+                }
+                Ok(cmd) => warn!("command {cmd:?} is not supported by the async keyboard driver"),
+                Err(_) => break,
+            },
+        }
+    }
+
+    // when program is exited, turn the keyboard off
+    for (x, y) in pixel_states.positions() {
+        nt.set_pixel_color(x as u16, y as u16, Color::BLACK)
+            .await
+            .context("failed to clear pixel color")?;
+    }
+
+    nt.show().await.context("failed to show pixels")?;
+
+    debug!("async keyboard task exited");
+
+    Ok(())
+}
+
+/// Polls an auxiliary Seesaw board until cancelled, sending its events on
+/// `evt_tx`.
+fn run_aux_device(
+    ct: &CancellationToken,
+    config: &AuxDeviceConfig,
+    evt_tx: &flume::Sender<Event>,
+) -> anyhow::Result<()> {
+    let (bus, address) = match config {
+        AuxDeviceConfig::NeoKey { bus, address } => (bus, *address),
+        AuxDeviceConfig::NeoSlider { bus, address, .. } => (bus, *address),
+    };
+
+    let i2c = open_i2c(bus.as_ref())?;
+    let mut seesaw = SeeSaw::new(i2c, address);
+    let mut delay = ThreadDelay;
+
+    seesaw.sw_reset().context("failed to reset seesaw")?;
+    debug!("initialized auxiliary seesaw at address {address:#04x}");
+
+    let mut interval = Interval::new(Duration::from_millis(1000 / 30));
+
+    match config {
+        AuxDeviceConfig::NeoKey { .. } => {
+            let mut np = NeoPixel::new(&mut seesaw);
+            let mut nk = NeoKey::new(&mut np);
+            nk.init().context("failed to initialize neokey")?;
+
+            let mut pressed = [false; neokey::KEY_COUNT];
+
+            while !ct.is_cancelled() {
+                interval.tick();
+
+                let keys = nk.get_keys(&mut delay).context("failed to read neokey")?;
+
+                for (key, (now, before)) in keys.iter().zip(pressed.iter()).enumerate() {
+                    if now != before {
+                        trace!("neokey {key} pressed = {now}");
+                        let _ = evt_tx.send(Event::AuxKey { key, pressed: *now });
+
+                        // light keys while they are held
+                        let color = if *now { Color::WHITE } else { Color::BLACK };
+                        nk.set_key_color(key, color)
+                            .context("failed to set neokey color")?;
+                    }
+                }
+
+                if keys != pressed {
+                    nk.show().context("failed to show neokey pixels")?;
+                    pressed = keys;
+                }
+            }
+        }
+        AuxDeviceConfig::NeoSlider { control, .. } => {
+            let mut np = NeoPixel::new(&mut seesaw);
+            let mut ns = NeoSlider::new(&mut np);
+            ns.init().context("failed to initialize neoslider")?;
+
+            let mut last = None;
+
+            while !ct.is_cancelled() {
+                interval.tick();
+
+                let value = ns
+                    .get_value(&mut delay)
+                    .context("failed to read neoslider")?;
+
+                let moved = match last {
+                    Some(last) => {
+                        f32::abs(value - last) >= ANALOG_THRESHOLD as f32 / adc::MAX_VALUE as f32
+                    }
+                    None => true,
+                };
+
+                if moved {
+                    last = Some(value);
+                    let _ = evt_tx.send(Event::Analog {
+                        control: *control,
+                        value,
+                    });
+
+                    ns.set_level(value, Color::WHITE)
+                        .context("failed to set neoslider level")?;
+                }
+            }
+        }
+    }
+
+    debug!("auxiliary device at address {address:#04x} exited");
 
     Ok(())
 }
@@ -0,0 +1,377 @@
+//! What the function keys do. Each action is bound to one or more chords: the
+//! function keys that are held, and then the function key or pad that is
+//! pressed. The built-in layout can be changed in the config file, e.g. to
+//! swap what F2 and F3 do:
+//!
+//! ```toml
+//! [[play.keymap]]
+//! action = "toggle_quantize"
+//! chords = ["F3"]
+//!
+//! [[play.keymap]]
+//! action = "clear_loops"
+//! chords = ["F2"]
+//!
+//! [[play.keymap]]
+//! action = "fx_mode"
+//! chords = ["F3+F1"]
+//! ```
+//!
+//! Actions that aren't in the config keep their built-in chords, unless a
+//! chord is taken by one that is.
+
+use std::fmt;
+
+use anyhow::{bail, Context};
+use serde::{Deserialize, Serialize};
+
+/// Something that the function keys can do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyAction {
+    /// Picks another sound for the pad that is pressed.
+    Reassign,
+    /// Records the next bars of the master mix onto the pad that is pressed.
+    Bounce,
+    ToggleQuantize,
+    ClearLoops,
+    CycleLoopMode,
+    BpmUp,
+    BpmDown,
+    BpmHalve,
+    BpmDouble,
+    FxMode,
+    DeckMode,
+    UndoPadEdit,
+    RedoPadEdit,
+    NextSetEntry,
+    /// Stops the looper, or starts it again from the top of the bar.
+    StartStop,
+    /// Turns the arpeggiator on or off.
+    ToggleArp,
+    /// Starts the rhythm trainer with its first pattern. It has no chord
+    /// unless one is given in the config.
+    RhythmGame,
+    /// Splits the grid between two players. See
+    /// [`crate::engine::PlayState::split_mode`].
+    SplitMode,
+}
+
+impl KeyAction {
+    /// Whether this can be undone by a chord that the key it was bound to
+    /// starts. F2 toggles quantization as soon as it is pressed, so F2 + F1
+    /// toggles it back before going into FX mode.
+    pub fn is_toggle(&self) -> bool {
+        matches!(self, KeyAction::ToggleQuantize | KeyAction::CycleLoopMode)
+    }
+
+    /// What the action does, for the help that shows while an Fn key is held
+    /// down.
+    pub fn describe(&self) -> &'static str {
+        match self {
+            KeyAction::Reassign => "Pick another sound for the pad",
+            KeyAction::Bounce => "Record the next bars onto the pad",
+            KeyAction::ToggleQuantize => "Turn quantization on or off",
+            KeyAction::ClearLoops => "Clear the loops",
+            KeyAction::CycleLoopMode => "Next loop mode",
+            KeyAction::BpmUp => "Raise the BPM",
+            KeyAction::BpmDown => "Lower the BPM",
+            KeyAction::BpmHalve => "Halve the BPM",
+            KeyAction::BpmDouble => "Double the BPM",
+            KeyAction::FxMode => "FX mode",
+            KeyAction::DeckMode => "Deck mode",
+            KeyAction::UndoPadEdit => "Undo the last pad edit",
+            KeyAction::RedoPadEdit => "Redo the pad edit that was undone",
+            KeyAction::NextSetEntry => "Next entry in the set list",
+            KeyAction::StartStop => "Stop the looper, or start it from the top",
+            KeyAction::ToggleArp => "Turn the arpeggiator on or off",
+            KeyAction::RhythmGame => "Start the rhythm trainer",
+            KeyAction::SplitMode => "Split the grid between two players",
+        }
+    }
+}
+
+/// The key that completes a chord.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChordKey {
+    /// The function key in this column, counting from 0.
+    Fn(usize),
+    /// Any pad.
+    Pad,
+}
+
+/// Function keys that are held, and then a key that is pressed, written like
+/// `F2+F3+F4+Pad`. The order of the held keys doesn't matter.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Chord {
+    /// columns of the function keys, counting from 0
+    pub held: Vec<usize>,
+    pub key: ChordKey,
+}
+
+impl Chord {
+    fn new(held: &[usize], key: ChordKey) -> Self {
+        Self {
+            held: held.to_vec(),
+            key,
+        }
+    }
+
+    /// Parses a chord like `F1+F3` or `F1+Pad`. Function keys are numbered
+    /// from 1, as they are labelled.
+    pub fn parse(text: &str) -> anyhow::Result<Self> {
+        let parse_fn = |part: &str| -> anyhow::Result<usize> {
+            let number = part
+                .strip_prefix('F')
+                .and_then(|n| n.parse::<usize>().ok())
+                .filter(|&n| n > 0)
+                .with_context(|| format!("{part:?} is not a function key like F1"))?;
+            Ok(number - 1)
+        };
+
+        let mut parts: Vec<_> = text.split('+').map(str::trim).collect();
+        let last = parts.pop().unwrap();
+
+        let key = match last {
+            "Pad" => ChordKey::Pad,
+            part => ChordKey::Fn(parse_fn(part)?),
+        };
+
+        let held = parts
+            .into_iter()
+            .map(parse_fn)
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        for (i, x) in held.iter().enumerate() {
+            if held[..i].contains(x) || key == ChordKey::Fn(*x) {
+                bail!("F{} is in {text:?} more than once", x + 1);
+            }
+        }
+
+        Ok(Self { held, key })
+    }
+}
+
+impl TryFrom<String> for Chord {
+    type Error = anyhow::Error;
+
+    fn try_from(text: String) -> anyhow::Result<Self> {
+        Self::parse(&text)
+    }
+}
+
+impl From<Chord> for String {
+    fn from(chord: Chord) -> Self {
+        chord.to_string()
+    }
+}
+
+impl fmt::Display for Chord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for x in &self.held {
+            write!(f, "F{}+", x + 1)?;
+        }
+
+        match self.key {
+            ChordKey::Fn(x) => write!(f, "F{}", x + 1),
+            ChordKey::Pad => f.write_str("Pad"),
+        }
+    }
+}
+
+/// An action and the chords that it is bound to in the config file.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct KeyBinding {
+    pub action: KeyAction,
+    pub chords: Vec<Chord>,
+}
+
+/// An action and one of its chords.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Binding {
+    pub chord: Chord,
+    pub action: KeyAction,
+}
+
+/// The chords of every action. Where chords with as many keys match, the one
+/// that comes first wins.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Keymap {
+    bindings: Vec<Binding>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        use ChordKey::{Fn, Pad};
+        use KeyAction::*;
+
+        let bindings = [
+            (&[0][..], Pad, Reassign),
+            (&[1, 2, 3], Pad, Bounce),
+            (&[], Fn(1), ToggleQuantize),
+            (&[], Fn(2), ClearLoops),
+            (&[], Fn(3), CycleLoopMode),
+            (&[1], Fn(0), FxMode),
+            (&[3], Fn(0), UndoPadEdit),
+            (&[3], Fn(1), RedoPadEdit),
+            (&[3], Fn(2), SplitMode),
+            (&[0], Fn(1), DeckMode),
+            (&[1], Fn(2), BpmHalve),
+            (&[1], Fn(3), BpmDouble),
+            (&[0], Fn(2), BpmDown),
+            (&[0], Fn(3), BpmUp),
+            // the first of F3 and F4 changes the BPM, but the entry sets its
+            // own
+            (&[0, 2], Fn(3), NextSetEntry),
+            (&[0, 3], Fn(2), NextSetEntry),
+        ];
+
+        Self {
+            bindings: bindings
+                .into_iter()
+                .map(|(held, key, action)| Binding {
+                    chord: Chord::new(held, key),
+                    action,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl Keymap {
+    /// The built-in keymap, with the actions in `overrides` bound to their
+    /// chords instead. Built-in chords that an override takes are dropped.
+    pub fn with_overrides(overrides: &[KeyBinding]) -> Self {
+        let overridden = |action: KeyAction| overrides.iter().any(|o| o.action == action);
+        let taken = |chord: &Chord| {
+            overrides
+                .iter()
+                .flat_map(|o| &o.chords)
+                .any(|other| other.key == chord.key && same_keys(&other.held, &chord.held))
+        };
+
+        let mut bindings: Vec<_> = Self::default()
+            .bindings
+            .into_iter()
+            .filter(|b| !overridden(b.action) && !taken(&b.chord))
+            .collect();
+
+        for o in overrides {
+            bindings.extend(o.chords.iter().map(|chord| Binding {
+                chord: chord.clone(),
+                action: o.action,
+            }));
+        }
+
+        Self { bindings }
+    }
+
+    /// The binding of pressing `key` while the function keys in `held` are
+    /// down. If more than one chord matches, the one with the most keys
+    /// wins, so that F2 + F3 halves the BPM rather than clearing the loops.
+    /// Keys that are held but not in the chord are ignored.
+    pub fn resolve(&self, held: &[bool], key: ChordKey) -> Option<&Binding> {
+        let is_held = |x: &usize| held.get(*x).copied().unwrap_or(false);
+
+        self.bindings
+            .iter()
+            .filter(|b| b.chord.key == key && b.chord.held.iter().all(is_held))
+            .fold(None, |best: Option<&Binding>, b| match best {
+                Some(best) if best.chord.held.len() >= b.chord.held.len() => Some(best),
+                _ => Some(b),
+            })
+    }
+
+    /// Every chord and its action, in the order that they win ties in.
+    pub fn bindings(&self) -> &[Binding] {
+        &self.bindings
+    }
+
+    /// The action of pressing the function key in column `x` on its own.
+    pub fn tap(&self, x: usize) -> Option<KeyAction> {
+        self.bindings
+            .iter()
+            .find(|b| b.chord.held.is_empty() && b.chord.key == ChordKey::Fn(x))
+            .map(|b| b.action)
+    }
+}
+
+fn same_keys(a: &[usize], b: &[usize]) -> bool {
+    a.len() == b.len() && a.iter().all(|x| b.contains(x))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Chord, ChordKey, KeyAction, KeyBinding, Keymap};
+
+    /// The action of pressing `key` while the function keys with the given
+    /// numbers are held.
+    fn resolve(keymap: &Keymap, held: &[usize], key: ChordKey) -> Option<KeyAction> {
+        let held: Vec<_> = (1..=4).map(|n| held.contains(&n)).collect();
+        keymap.resolve(&held, key).map(|b| b.action)
+    }
+
+    #[test]
+    fn chords_are_parsed_as_they_are_written() {
+        let chord = Chord::parse("F2+F3+F4+Pad").unwrap();
+        assert_eq!(chord.held, vec![1, 2, 3]);
+        assert_eq!(chord.key, ChordKey::Pad);
+        assert_eq!(chord.to_string(), "F2+F3+F4+Pad");
+
+        assert_eq!(Chord::parse("F1").unwrap().key, ChordKey::Fn(0));
+        assert!(Chord::parse("F0").is_err());
+        assert!(Chord::parse("F1+F1").is_err());
+        assert!(Chord::parse("Pad+F1").is_err());
+        assert!(Chord::parse("").is_err());
+    }
+
+    #[test]
+    fn the_chord_with_the_most_keys_wins() {
+        let keymap = Keymap::default();
+
+        assert_eq!(
+            resolve(&keymap, &[], ChordKey::Fn(2)),
+            Some(KeyAction::ClearLoops)
+        );
+        assert_eq!(
+            resolve(&keymap, &[2], ChordKey::Fn(2)),
+            Some(KeyAction::BpmHalve)
+        );
+        assert_eq!(
+            resolve(&keymap, &[1, 3], ChordKey::Fn(3)),
+            Some(KeyAction::NextSetEntry)
+        );
+        assert_eq!(
+            resolve(&keymap, &[2, 3, 4], ChordKey::Pad),
+            Some(KeyAction::Bounce)
+        );
+        assert_eq!(
+            resolve(&keymap, &[4], ChordKey::Fn(2)),
+            Some(KeyAction::SplitMode)
+        );
+        assert_eq!(resolve(&keymap, &[3], ChordKey::Pad), None);
+        assert_eq!(resolve(&keymap, &[], ChordKey::Fn(0)), None);
+    }
+
+    #[test]
+    fn overrides_take_the_chords_of_built_in_actions() {
+        let binding = |action, chord| KeyBinding {
+            action,
+            chords: vec![Chord::parse(chord).unwrap()],
+        };
+        let overrides = [
+            binding(KeyAction::ToggleQuantize, "F3"),
+            binding(KeyAction::StartStop, "F1"),
+        ];
+        let keymap = Keymap::with_overrides(&overrides);
+
+        assert_eq!(keymap.tap(0), Some(KeyAction::StartStop));
+        assert_eq!(keymap.tap(1), None);
+        assert_eq!(keymap.tap(2), Some(KeyAction::ToggleQuantize));
+        assert_eq!(
+            resolve(&keymap, &[2], ChordKey::Fn(2)),
+            Some(KeyAction::BpmHalve)
+        );
+    }
+}
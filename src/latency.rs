@@ -0,0 +1,277 @@
+//! Measures how long it takes for a key press to be heard, broken down by the
+//! subsystem that the time is spent in.
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+/// Timestamps of a key press as it passes through the app. Each subsystem
+/// fills in its own timestamp and passes the trace on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Trace {
+    /// when the keyboard started reading the keypad FIFO that the press was
+    /// found in
+    pub scan_start: Instant,
+    /// when the keyboard had read the FIFO and sent the event
+    pub scanned: Instant,
+    /// when the engine picked up the event
+    pub dispatched: Option<Instant>,
+    /// when the audio subsystem received the play command
+    pub received: Option<Instant>,
+    /// when the output stream pulled the first sample of the sound
+    pub first_sample: Option<Instant>,
+}
+
+impl Trace {
+    pub fn new(scan_start: Instant, scanned: Instant) -> Self {
+        Self {
+            scan_start,
+            scanned,
+            dispatched: None,
+            received: None,
+            first_sample: None,
+        }
+    }
+
+    /// Time spent in each stage that the trace has got through.
+    pub fn stages(&self) -> impl Iterator<Item = (Stage, Duration)> {
+        let times = [
+            Some(self.scan_start),
+            Some(self.scanned),
+            self.dispatched,
+            self.received,
+            self.first_sample,
+        ];
+
+        Stage::ALL
+            .into_iter()
+            .zip(
+                times
+                    .windows(2)
+                    .map(|w| Some(w[1]? - w[0]?))
+                    .collect::<Vec<_>>(),
+            )
+            .filter_map(|(stage, duration)| Some((stage, duration?)))
+    }
+
+    /// Time from the start of the keypad read to the first sample.
+    pub fn total(&self) -> Option<Duration> {
+        Some(self.first_sample? - self.scan_start)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Stage {
+    /// Reading the keypad over I2C.
+    Scan,
+    /// Waiting for the engine to handle the key event.
+    Dispatch,
+    /// Waiting for the audio subsystem to receive the play command.
+    Command,
+    /// Waiting for the output stream to start playing the sound.
+    Output,
+}
+
+impl Stage {
+    pub const ALL: [Stage; 4] = [Stage::Scan, Stage::Dispatch, Stage::Command, Stage::Output];
+}
+
+impl std::fmt::Display for Stage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Stage::Scan => "scan",
+            Stage::Dispatch => "dispatch",
+            Stage::Command => "command",
+            Stage::Output => "output",
+        })
+    }
+}
+
+/// How many of the most recent samples are kept for each stage.
+const WINDOW: usize = 128;
+
+/// Upper bounds of the histogram buckets. Anything slower goes in a final
+/// overflow bucket.
+pub const BUCKETS: [Duration; 6] = [
+    Duration::from_millis(1),
+    Duration::from_millis(2),
+    Duration::from_millis(5),
+    Duration::from_millis(10),
+    Duration::from_millis(20),
+    Duration::from_millis(50),
+];
+
+/// How many key presses have to be heard before the measured latency is used
+/// to compensate for it.
+const MIN_COMPENSATION_SAMPLES: usize = 16;
+
+/// Rolling window of latency samples for each stage, and for the whole trip.
+#[derive(Debug, Clone, Default)]
+pub struct LatencyStats {
+    stages: BTreeMap<Stage, VecDeque<Duration>>,
+    total: VecDeque<Duration>,
+}
+
+/// Summary of the samples in a [`LatencyStats`] window.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Summary {
+    pub count: usize,
+    pub p50: Duration,
+    pub p95: Duration,
+    pub max: Duration,
+    /// Number of samples in each of [`BUCKETS`], plus the overflow bucket.
+    pub histogram: [usize; BUCKETS.len() + 1],
+}
+
+impl LatencyStats {
+    pub fn record(&mut self, trace: &Trace) {
+        for (stage, duration) in trace.stages() {
+            push(self.stages.entry(stage).or_default(), duration);
+        }
+
+        if let Some(total) = trace.total() {
+            push(&mut self.total, total);
+        }
+    }
+
+    pub fn stage(&self, stage: Stage) -> Summary {
+        self.stages.get(&stage).map(summarize).unwrap_or_default()
+    }
+
+    pub fn total(&self) -> Summary {
+        summarize(&self.total)
+    }
+
+    /// How far back key presses should be moved when they are recorded, so
+    /// that they land where they were heard: the median of the whole trip,
+    /// to the millisecond. None until enough presses have been heard.
+    pub fn compensation(&self) -> Option<Duration> {
+        let total = self.total();
+
+        if total.count < MIN_COMPENSATION_SAMPLES {
+            return None;
+        }
+
+        Some(Duration::from_millis(total.p50.as_millis() as u64))
+    }
+}
+
+/// Rolling window of how late the looper woke up for its ticks.
+#[derive(Debug, Clone, Default)]
+pub struct TickJitter {
+    late: VecDeque<Duration>,
+}
+
+impl TickJitter {
+    pub fn record(&mut self, late: Duration) {
+        push(&mut self.late, late);
+    }
+
+    pub fn summary(&self) -> Summary {
+        summarize(&self.late)
+    }
+}
+
+fn push(window: &mut VecDeque<Duration>, sample: Duration) {
+    if window.len() == WINDOW {
+        window.pop_front();
+    }
+
+    window.push_back(sample);
+}
+
+fn summarize(window: &VecDeque<Duration>) -> Summary {
+    if window.is_empty() {
+        return Summary::default();
+    }
+
+    let mut sorted: Vec<_> = window.iter().copied().collect();
+    sorted.sort();
+
+    let percentile = |p: usize| sorted[(sorted.len() - 1) * p / 100];
+
+    let mut histogram = [0; BUCKETS.len() + 1];
+
+    for sample in &sorted {
+        let bucket = BUCKETS
+            .iter()
+            .position(|bound| sample <= bound)
+            .unwrap_or(BUCKETS.len());
+        histogram[bucket] += 1;
+    }
+
+    Summary {
+        count: sorted.len(),
+        p50: percentile(50),
+        p95: percentile(95),
+        max: *sorted.last().unwrap(),
+        histogram,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use super::{LatencyStats, Stage, Trace};
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn stages_of_partial_trace() {
+        let start = Instant::now();
+        let mut trace = Trace::new(start, start + ms(14));
+        trace.dispatched = Some(start + ms(15));
+
+        assert_eq!(
+            trace.stages().collect::<Vec<_>>(),
+            vec![(Stage::Scan, ms(14)), (Stage::Dispatch, ms(1))]
+        );
+        assert_eq!(trace.total(), None);
+    }
+
+    #[test]
+    fn summary_of_window() {
+        let start = Instant::now();
+        let mut stats = LatencyStats::default();
+
+        for i in 1..=200 {
+            let mut trace = Trace::new(start, start + ms(i % 100));
+            trace.dispatched = Some(trace.scanned);
+            trace.received = Some(trace.scanned);
+            trace.first_sample = Some(trace.scanned + ms(30));
+            stats.record(&trace);
+        }
+
+        let scan = stats.stage(Stage::Scan);
+        assert_eq!(scan.count, 128);
+        assert_eq!(scan.max, ms(99));
+        assert_eq!(scan.histogram.iter().sum::<usize>(), 128);
+
+        let output = stats.stage(Stage::Output);
+        assert_eq!((output.p50, output.p95), (ms(30), ms(30)));
+        assert_eq!(output.histogram[5], 128);
+    }
+
+    #[test]
+    fn compensation_waits_for_samples() {
+        let start = Instant::now();
+        let mut stats = LatencyStats::default();
+        let mut trace = Trace::new(start, start + ms(2));
+        trace.dispatched = Some(trace.scanned);
+        trace.received = Some(trace.scanned);
+        trace.first_sample = Some(trace.scanned + Duration::from_micros(10_600));
+
+        for _ in 0..15 {
+            stats.record(&trace);
+        }
+
+        assert_eq!(stats.compensation(), None);
+
+        stats.record(&trace);
+        assert_eq!(stats.compensation(), Some(ms(12)));
+    }
+}
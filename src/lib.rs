@@ -0,0 +1,50 @@
+//! The PI DJ engine, which drives a NeoTrellis keyboard and plays sounds from
+//! the `audio` directory. The `pidj` binary is an egui front-end for it, and
+//! `pidj-sim` runs it headlessly from a script of key presses.
+
+pub mod analysis;
+pub mod animation;
+pub mod archive;
+pub mod audio;
+pub mod bank;
+pub mod bundle;
+pub mod bus;
+pub mod cache;
+pub mod chroma;
+pub mod clock;
+pub mod compressor;
+pub mod config;
+pub mod deck;
+pub mod driver;
+pub mod effect;
+pub mod encoder;
+pub mod engine;
+pub mod eq;
+pub mod footswitch;
+pub mod fx;
+pub mod game;
+pub mod grid;
+pub mod groove;
+pub mod health;
+pub mod journal;
+pub mod keyboard;
+pub mod keymap;
+pub mod latency;
+pub mod lighting;
+pub mod logbuf;
+pub mod midi_grid;
+pub mod pad;
+pub mod palette;
+pub mod persist;
+pub mod pool;
+pub mod pwm;
+pub mod render;
+pub mod scene;
+pub mod script;
+pub mod setlist;
+pub mod sim;
+pub mod slice;
+pub mod tags;
+pub mod tape;
+pub mod trigger;
+mod util;
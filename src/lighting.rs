@@ -0,0 +1,184 @@
+//! Ambient lighting on an APA102 LED strip, wired to one of the Pi's SPI buses.
+//! The strip fills up with the level of the master mix like a VU meter, and
+//! flashes on every beat of the looper, brighter on the downbeat.
+//!
+//! WS2812 strips need tighter timing than the Pi's SPI can give reliably, so
+//! only APA102 (and compatible, e.g. SK9822) strips are supported.
+
+use std::time::Duration;
+
+use anyhow::{bail, Context};
+use rppal::spi::{Bus, Mode, SlaveSelect, Spi};
+use tokio_util::sync::CancellationToken;
+use tracing::debug;
+
+use crate::{config::LedStripConfig, driver::adafruit::seesaw::neopixel::Color, util::Interval};
+
+/// What the engine tells the lighting task about.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Event {
+    /// The looper reached a beat, counting from 0 at the start of the bar.
+    Beat(usize),
+    /// Peak level of the master mix, where 1.0 is full scale.
+    Level(f32),
+}
+
+/// How far the level falls per frame, as a fraction of the strip, so that it
+/// falls back smoothly.
+const LEVEL_FALL: f32 = 0.02;
+
+/// How much of a beat's flash is left after each frame.
+const FLASH_DECAY: f32 = 0.85;
+
+/// The quietest level that lights the strip, in dB below full scale.
+const RANGE_DB: f32 = 36.;
+
+/// What is shown on the strip, which is animated every frame.
+#[derive(Debug, Clone, Default)]
+struct Lights {
+    /// latest level, as a fraction of the strip
+    level: f32,
+    /// level that the strip is drawn at
+    shown: f32,
+    /// brightness of the beat flash, from 0 to 1
+    flash: f32,
+    /// whether the flash is for a downbeat
+    downbeat: bool,
+}
+
+impl Lights {
+    fn event(&mut self, event: Event) {
+        match event {
+            Event::Beat(beat) => {
+                self.flash = 1.;
+                self.downbeat = beat == 0;
+            }
+            Event::Level(peak) => {
+                let db = 20. * peak.max(1e-6).log10();
+                self.level = ((db + RANGE_DB) / RANGE_DB).clamp(0., 1.);
+            }
+        }
+    }
+
+    /// Advances the animation by one frame and draws a strip of `len` LEDs.
+    fn frame(&mut self, len: usize) -> Vec<Color> {
+        self.shown = self.level.max(self.shown - LEVEL_FALL);
+        self.flash *= FLASH_DECAY;
+
+        let flash = match self.downbeat {
+            true => (255., 120., 0.),
+            false => (255., 255., 255.),
+        };
+
+        (0..len)
+            .map(|i| {
+                let position = (i as f32 + 0.5) / len as f32;
+
+                // green at the bottom of the meter to red at the top
+                let (r, g) = match position < self.shown {
+                    true => (255. * position, 255. * (1. - position)),
+                    false => (0., 0.),
+                };
+
+                let mix = |base: f32, flash: f32| (base + (flash - base) * self.flash * 0.5) as u8;
+
+                Color::from_u8(mix(r, flash.0), mix(g, flash.1), mix(0., flash.2))
+            })
+            .collect()
+    }
+}
+
+/// Encodes a frame for an APA102 strip. `brightness` is the strip's global
+/// brightness, from 0 to 31.
+fn apa102_frame(pixels: &[Color], brightness: u8) -> Vec<u8> {
+    let mut frame = vec![0; 4];
+
+    for pixel in pixels {
+        frame.extend_from_slice(&[0xe0 | brightness.min(31), pixel.b, pixel.g, pixel.r]);
+    }
+
+    // the data is delayed by half a clock per LED, so the end frame has to
+    // clock it the rest of the way down the strip
+    frame.extend(std::iter::repeat_n(0xff, (pixels.len() / 16).max(1) * 4));
+    frame
+}
+
+/// Draws on the LED strip until cancelled, following the events on `evt_rx`.
+pub fn run(
+    ct: CancellationToken,
+    config: LedStripConfig,
+    evt_rx: flume::Receiver<Event>,
+) -> anyhow::Result<()> {
+    let bus = match config.bus {
+        0 => Bus::Spi0,
+        1 => Bus::Spi1,
+        2 => Bus::Spi2,
+        3 => Bus::Spi3,
+        4 => Bus::Spi4,
+        5 => Bus::Spi5,
+        6 => Bus::Spi6,
+        bus => bail!("there is no SPI bus {bus}"),
+    };
+
+    let mut spi = Spi::new(bus, SlaveSelect::Ss0, config.clock_hz, Mode::Mode0)
+        .with_context(|| format!("failed to open SPI bus {}", config.bus))?;
+
+    let mut lights = Lights::default();
+
+    debug!("running LED strip loop");
+
+    let mut interval = Interval::new(Duration::from_millis(1000 / 60));
+
+    'frame: while !ct.is_cancelled() {
+        interval.tick();
+
+        loop {
+            match evt_rx.try_recv() {
+                Ok(event) => lights.event(event),
+                Err(flume::TryRecvError::Empty) => break,
+                Err(flume::TryRecvError::Disconnected) => break 'frame,
+            }
+        }
+
+        let frame = apa102_frame(&lights.frame(config.len), config.brightness);
+        spi.write(&frame).context("failed to write to LED strip")?;
+    }
+
+    // when program is exited, turn the strip off
+    let frame = apa102_frame(&vec![Color::BLACK; config.len], 0);
+    spi.write(&frame).context("failed to write to LED strip")?;
+
+    debug!("LED strip task exited");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn encode_apa102_frame() {
+        let frame = apa102_frame(&[Color::from_u8(1, 2, 3), Color::BLACK], 40);
+
+        assert_eq!(&frame[..4], &[0, 0, 0, 0]);
+        assert_eq!(&frame[4..8], &[0xff, 3, 2, 1]);
+        assert_eq!(&frame[8..12], &[0xe0 | 31, 0, 0, 0]);
+        assert_eq!(&frame[12..], &[0xff; 4]);
+    }
+
+    #[test]
+    fn level_fills_the_strip() {
+        let mut lights = Lights::default();
+        let lit = |pixels: Vec<Color>| pixels.iter().filter(|p| p.r > 0 || p.g > 0).count();
+
+        assert_eq!(lit(lights.frame(10)), 0);
+
+        lights.event(Event::Level(1.));
+        assert_eq!(lit(lights.frame(10)), 10);
+
+        // falls back slowly once it goes quiet
+        lights.event(Event::Level(0.));
+        assert_eq!(lit(lights.frame(10)), 10);
+    }
+}
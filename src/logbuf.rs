@@ -0,0 +1,73 @@
+//! Keeps the most recent log messages in memory, so that they can be read on
+//! the device without a terminal.
+
+use std::{
+    collections::VecDeque,
+    fmt::Write,
+    sync::{Arc, Mutex},
+};
+
+use tracing::{field::Field, Event, Level, Subscriber};
+use tracing_subscriber::{layer::Context, Layer};
+
+/// How many messages are kept.
+const CAPACITY: usize = 500;
+
+#[derive(Debug, Clone)]
+pub struct LogLine {
+    pub level: Level,
+    pub target: String,
+    pub text: String,
+}
+
+/// A tracing layer that records the most recent messages. Clones share the
+/// same buffer.
+#[derive(Debug, Clone, Default)]
+pub struct LogBuffer(Arc<Mutex<VecDeque<LogLine>>>);
+
+impl LogBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The recorded messages, oldest first.
+    pub fn lines(&self) -> Vec<LogLine> {
+        self.0.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+impl<S: Subscriber> Layer<S> for LogBuffer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let mut text = Text(String::new());
+        event.record(&mut text);
+
+        let mut lines = self.0.lock().unwrap();
+
+        if lines.len() == CAPACITY {
+            lines.pop_front();
+        }
+
+        lines.push_back(LogLine {
+            level: *event.metadata().level(),
+            target: event.metadata().target().to_owned(),
+            text: text.0,
+        });
+    }
+}
+
+/// Formats an event's message, followed by its other fields.
+struct Text(String);
+
+impl tracing::field::Visit for Text {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if !self.0.is_empty() {
+            self.0.push(' ');
+        }
+
+        if field.name() == "message" {
+            let _ = write!(self.0, "{value:?}");
+        } else {
+            let _ = write!(self.0, "{}={value:?}", field.name());
+        }
+    }
+}
@@ -1,20 +1,84 @@
+use std::{
+    future::Future,
+    panic::AssertUnwindSafe,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Context};
 use tokio_util::sync::CancellationToken;
-use tracing::info;
-use tracing_subscriber::EnvFilter;
+use tracing::{error, info};
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
+
+use pidj::{
+    audio,
+    bus::Bus,
+    clock::{Clock, SystemClock, VirtualClock},
+    config, encoder,
+    engine::{Fault, Problem, Subsystem},
+    footswitch, health, keyboard, lighting,
+    logbuf::LogBuffer,
+    midi_grid, trigger,
+};
+
+/// How many times a failed subsystem is restarted before giving up on it.
+const MAX_RESTARTS: usize = 3;
+
+/// How long to wait before restarting a failed subsystem, so that a persistent
+/// fault (e.g. an unplugged board) doesn't spin.
+const RESTART_DELAY: Duration = Duration::from_secs(1);
+
+/// How long a subsystem has to run without failing for its restarts to be
+/// counted from zero again.
+const RESTART_RESET: Duration = Duration::from_secs(60);
 
 mod app;
-mod audio;
-mod driver;
-mod keyboard;
-mod util;
+mod rotate;
+mod theme;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
+    // recent log messages are also kept for the diagnostics view
+    let log = LogBuffer::new();
+
     tracing_subscriber::fmt()
         .pretty()
         .with_env_filter(EnvFilter::from_default_env())
+        .finish()
+        .with(log.clone())
         .init();
 
+    let config = config::Config::load(config::CONFIG_PATH)?;
+
+    // runs without any hardware and on a virtual clock
+    let simulate = std::env::args().any(|arg| arg == "--simulate");
+
+    // shows how long key presses take to be heard
+    let diagnostics = std::env::args().any(|arg| arg == "--diagnostics");
+
+    // the display settings can be overridden for a one-off run on another
+    // screen, without touching the config file
+    let mut display = config.display.clone();
+
+    if let Some(scale) = arg_value("--scale") {
+        display.scale = scale.parse().context("--scale must be a number")?;
+    }
+
+    if let Some(size) = arg_value("--size") {
+        display.size = Some(parse_size(&size).context("--size must look like 480x320")?);
+    }
+
+    if std::env::args().any(|arg| arg == "--windowed") {
+        display.windowed = true;
+    }
+
+    if let Some(rotation) = arg_value("--rotate") {
+        let degrees: u16 = rotation
+            .parse()
+            .context("--rotate must be a number of degrees")?;
+        display.rotation = degrees.try_into().map_err(|err: String| anyhow!(err))?;
+    }
+
     let ct = CancellationToken::new();
 
     ctrlc::set_handler({
@@ -25,42 +89,368 @@ async fn main() -> anyhow::Result<()> {
         }
     })?;
 
-    let (kb_cmd_tx, kb_cmd_rx) = flume::bounded(256);
-    let (kb_evt_tx, kb_evt_rx) = flume::bounded(256);
+    let bus = Bus::new();
 
-    let (audio_cmd_tx, audio_cmd_rx) = flume::bounded(256);
-    let (audio_evt_tx, audio_evt_rx) = flume::bounded(256);
+    let kb_cmd_rx = bus.keyboard.commands.rx();
+    let kb_evt_tx = bus.keyboard.events.tx();
+    let fault_tx = bus.faults.tx();
+    let problem_tx = bus.problems.tx();
 
-    let kb_join = std::thread::spawn({
-        let ct = ct.clone();
-        move || keyboard::run(ct, kb_cmd_rx, kb_evt_tx)
+    // there is no pi to watch when simulating
+    if !simulate {
+        tokio::spawn(health::run(ct.clone(), bus.health.tx()));
+    }
+
+    // footswitches send the same events as the keyboard's GPIO inputs
+    let fs_evt_tx = kb_evt_tx.clone();
+
+    // the keyboard either gets its own threads, or runs as a task alongside
+    // the audio system
+    let (kb_join, kb_async) = if simulate {
+        let kb_join = supervise(Subsystem::Keyboard, ct.clone(), fault_tx.clone(), {
+            let ct = ct.clone();
+            let config = config.keyboard.clone();
+            let problem_tx = problem_tx.clone();
+            move || {
+                let i2c = pidj::driver::mock::MockI2c::new();
+                keyboard::run_with_bus(
+                    ct.clone(),
+                    config.clone(),
+                    i2c,
+                    kb_cmd_rx.clone(),
+                    kb_evt_tx.clone(),
+                    problem_tx.clone(),
+                )
+            }
+        });
+
+        (Some(kb_join), None)
+    } else if let Some(grid) = config.keyboard.midi_grid.clone() {
+        let kb_join = supervise(Subsystem::Keyboard, ct.clone(), fault_tx.clone(), {
+            let ct = ct.clone();
+            let animations = config.keyboard.animations.clone();
+            move || {
+                midi_grid::run(
+                    ct.clone(),
+                    grid.clone(),
+                    animations.clone(),
+                    kb_cmd_rx.clone(),
+                    kb_evt_tx.clone(),
+                )
+            }
+        });
+
+        (Some(kb_join), None)
+    } else if config.keyboard.use_async {
+        (None, Some((config.keyboard.clone(), kb_cmd_rx, kb_evt_tx)))
+    } else {
+        let kb_join = supervise(Subsystem::Keyboard, ct.clone(), fault_tx.clone(), {
+            let ct = ct.clone();
+            let config = config.keyboard.clone();
+            let problem_tx = problem_tx.clone();
+            move || {
+                keyboard::run(
+                    ct.clone(),
+                    config.clone(),
+                    kb_cmd_rx.clone(),
+                    kb_evt_tx.clone(),
+                    problem_tx.clone(),
+                )
+            }
+        });
+
+        (Some(kb_join), None)
+    };
+
+    // the encoder is optional, so it only gets a thread if it is configured
+    let enc_join = config.encoder.clone().filter(|_| !simulate).map(|config| {
+        supervise(Subsystem::Encoder, ct.clone(), fault_tx.clone(), {
+            let ct = ct.clone();
+            let enc_cmd_rx = bus.encoder.commands.rx();
+            let enc_evt_tx = bus.encoder.events.tx();
+            move || {
+                encoder::run(
+                    ct.clone(),
+                    config.clone(),
+                    enc_cmd_rx.clone(),
+                    enc_evt_tx.clone(),
+                )
+            }
+        })
+    });
+
+    let fs_join = (!config.footswitches.is_empty() && !simulate).then(|| {
+        supervise(Subsystem::Footswitches, ct.clone(), fault_tx.clone(), {
+            let ct = ct.clone();
+            let config = config.footswitches.clone();
+            move || footswitch::run(ct.clone(), config.clone(), fs_evt_tx.clone())
+        })
     });
 
+    let trigger_join = config
+        .trigger_out
+        .clone()
+        .filter(|_| !simulate)
+        .map(|config| {
+            supervise(Subsystem::Trigger, ct.clone(), fault_tx.clone(), {
+                let ct = ct.clone();
+                let trigger_rx = bus.trigger.rx();
+                move || trigger::run(ct.clone(), config.clone(), trigger_rx.clone())
+            })
+        });
+
+    let light_join = config
+        .led_strip
+        .clone()
+        .filter(|_| !simulate)
+        .map(|config| {
+            supervise(Subsystem::LedStrip, ct.clone(), fault_tx.clone(), {
+                let ct = ct.clone();
+                let light_rx = bus.lighting.rx();
+                move || lighting::run(ct.clone(), config.clone(), light_rx.clone())
+            })
+        });
+
     let async_join = std::thread::spawn({
         let ct = ct.clone();
-        move || async_main(ct.clone(), audio_cmd_rx, audio_evt_tx)
+        let fault_tx = fault_tx.clone();
+        let config = config.audio.clone();
+        let audio_cmd_rx = bus.audio.commands.rx();
+        let audio_evt_tx = bus.audio.events.tx();
+        move || {
+            async_main(
+                ct.clone(),
+                kb_async,
+                config,
+                audio_cmd_rx,
+                audio_evt_tx,
+                fault_tx,
+                problem_tx,
+            )
+        }
     });
 
-    app::run(ct.clone(), kb_cmd_tx, kb_evt_rx, audio_cmd_tx, audio_evt_rx)?;
+    let clock: Arc<dyn Clock> = if simulate {
+        let clock = VirtualClock::new();
+        tokio::spawn(run_virtual_clock(ct.clone(), clock.clone()));
+        Arc::new(clock)
+    } else {
+        Arc::new(SystemClock)
+    };
+
+    app::run(ct.clone(), clock, config, display, diagnostics, log, bus)?;
     ct.cancel();
 
     async_join.join().unwrap()?;
-    kb_join.join().unwrap()?;
+
+    if let Some(kb_join) = kb_join {
+        kb_join.join().unwrap()?;
+    }
+
+    if let Some(enc_join) = enc_join {
+        enc_join.join().unwrap()?;
+    }
+
+    if let Some(fs_join) = fs_join {
+        fs_join.join().unwrap()?;
+    }
+
+    if let Some(trigger_join) = trigger_join {
+        trigger_join.join().unwrap()?;
+    }
+
+    if let Some(light_join) = light_join {
+        light_join.join().unwrap()?;
+    }
 
     info!("exit");
 
     Ok(())
 }
 
+/// Gets the argument that follows `name` on the command line.
+fn arg_value(name: &str) -> Option<String> {
+    let mut args = std::env::args();
+    args.find(|arg| arg == name)?;
+    args.next()
+}
+
+/// Parses a window size like `480x320`.
+fn parse_size(size: &str) -> anyhow::Result<[f32; 2]> {
+    let (width, height) = size
+        .split_once('x')
+        .ok_or_else(|| anyhow!("no 'x' in {size:?}"))?;
+    Ok([width.parse()?, height.parse()?])
+}
+
+/// Runs a subsystem on its own thread, restarting it up to [`MAX_RESTARTS`]
+/// times if it fails or panics. Failures are reported through `fault_tx`.
+fn supervise(
+    subsystem: Subsystem,
+    ct: CancellationToken,
+    fault_tx: flume::Sender<Fault>,
+    mut run: impl FnMut() -> anyhow::Result<()> + Send + 'static,
+) -> std::thread::JoinHandle<anyhow::Result<()>> {
+    std::thread::spawn(move || {
+        let mut attempt = 0;
+
+        loop {
+            let started = Instant::now();
+            let result = std::panic::catch_unwind(AssertUnwindSafe(&mut run))
+                .unwrap_or_else(|_| Err(anyhow!("{subsystem} panicked")));
+
+            attempt = next_attempt(attempt, started);
+
+            match on_exit(subsystem, &ct, &fault_tx, attempt, result) {
+                Some(result) => return result,
+                None => std::thread::sleep(RESTART_DELAY),
+            }
+        }
+    })
+}
+
+/// Same as [`supervise`], but for subsystems that run as tasks.
+async fn supervise_async<F, Fut>(
+    subsystem: Subsystem,
+    ct: CancellationToken,
+    fault_tx: flume::Sender<Fault>,
+    mut run: F,
+) -> anyhow::Result<()>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = anyhow::Result<()>> + Send + 'static,
+{
+    let mut attempt = 0;
+
+    loop {
+        let started = Instant::now();
+        let result = tokio::spawn(run())
+            .await
+            .unwrap_or_else(|_| Err(anyhow!("{subsystem} panicked")));
+
+        attempt = next_attempt(attempt, started);
+
+        match on_exit(subsystem, &ct, &fault_tx, attempt, result) {
+            Some(result) => return result,
+            None => tokio::time::sleep(RESTART_DELAY).await,
+        }
+    }
+}
+
+/// Counts a subsystem's restart attempts, which start over if it ran for
+/// [`RESTART_RESET`] since it was last `started`, so that occasional failures
+/// over a long set don't add up to [`MAX_RESTARTS`].
+fn next_attempt(attempt: usize, started: Instant) -> usize {
+    if started.elapsed() >= RESTART_RESET {
+        1
+    } else {
+        attempt + 1
+    }
+}
+
+/// Decides what to do after a supervised subsystem exits. Returns the result
+/// that the supervisor should exit with, or None if the subsystem should be
+/// restarted.
+fn on_exit(
+    subsystem: Subsystem,
+    ct: &CancellationToken,
+    fault_tx: &flume::Sender<Fault>,
+    attempt: usize,
+    result: anyhow::Result<()>,
+) -> Option<anyhow::Result<()>> {
+    let err = match result {
+        Err(err) if !ct.is_cancelled() => err,
+        // the subsystem exited cleanly, or failed while the app was shutting
+        // down, so there is nothing to restart
+        result => return Some(result),
+    };
+
+    error!("{subsystem} failed: {err:?}");
+
+    if attempt > MAX_RESTARTS {
+        let _ = fault_tx.send(Fault::Failed {
+            subsystem,
+            error: format!("{err:#}"),
+        });
+
+        return Some(Err(err));
+    }
+
+    info!("restarting {subsystem} (attempt {attempt}/{MAX_RESTARTS})");
+
+    let _ = fault_tx.send(Fault::Restarting {
+        subsystem,
+        attempt,
+        error: format!("{err:#}"),
+    });
+
+    None
+}
+
+/// Advances the virtual clock in fixed steps, so that the looper sees the same
+/// times on every run regardless of how the tasks get scheduled.
+async fn run_virtual_clock(ct: CancellationToken, clock: VirtualClock) {
+    const STEP: Duration = Duration::from_millis(1);
+
+    let mut interval = tokio::time::interval(STEP);
+
+    while !ct.is_cancelled() {
+        interval.tick().await;
+        clock.advance(STEP);
+    }
+}
+
 #[tokio::main]
 async fn async_main(
     ct: CancellationToken,
+    kb: Option<(
+        config::KeyboardConfig,
+        flume::Receiver<keyboard::Command>,
+        flume::Sender<keyboard::Event>,
+    )>,
+    audio_config: config::AudioConfig,
     audio_cmd_rx: flume::Receiver<audio::Command>,
     audio_evt_tx: flume::Sender<audio::Event>,
+    fault_tx: flume::Sender<Fault>,
+    problem_tx: flume::Sender<Problem>,
 ) -> anyhow::Result<()> {
-    let audio_join = tokio::spawn(audio::run(ct.clone(), audio_cmd_rx, audio_evt_tx));
+    let kb_join = kb.map(|(config, kb_cmd_rx, kb_evt_tx)| {
+        tokio::spawn(supervise_async(
+            Subsystem::Keyboard,
+            ct.clone(),
+            fault_tx.clone(),
+            {
+                let ct = ct.clone();
+                move || {
+                    keyboard::run_async(
+                        ct.clone(),
+                        config.clone(),
+                        kb_cmd_rx.clone(),
+                        kb_evt_tx.clone(),
+                    )
+                }
+            },
+        ))
+    });
+
+    let audio_join = tokio::spawn(supervise_async(Subsystem::Audio, ct.clone(), fault_tx, {
+        let ct = ct.clone();
+        move || {
+            audio::run(
+                ct.clone(),
+                audio_config.clone(),
+                audio_cmd_rx.clone(),
+                audio_evt_tx.clone(),
+                problem_tx.clone(),
+            )
+        }
+    }));
     audio_join.await.unwrap()?;
 
+    if let Some(kb_join) = kb_join {
+        kb_join.await.unwrap()?;
+    }
+
     info!("async exit");
 
     Ok(())
@@ -0,0 +1,299 @@
+//! Drives a USB MIDI grid controller (Novation Launchpad or Akai APC Mini)
+//! in place of the NeoTrellis, so that pidj can be played without one. The
+//! whole of the controller's 8x8 grid is used: free play spreads the pads
+//! across it, and the other modes are laid out on its top-left 4x4 corner,
+//! where the NeoTrellis's keys would be.
+//!
+//! The controller is opened as an ALSA raw MIDI device, e.g.
+//! `/dev/snd/midiC1D0`. It is a [`Backend`] like the NeoTrellis, so it takes
+//! the same commands and sends the same events.
+
+use std::{
+    collections::BTreeMap,
+    fs::{File, OpenOptions},
+    io::{Read, Write},
+    path::Path,
+    sync::Mutex,
+    time::Instant,
+};
+
+use anyhow::Context;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, warn};
+
+use crate::{
+    animation::Animation,
+    config::{MidiGridConfig, MidiGridModel},
+    driver::adafruit::seesaw::{keypad::Edge, neopixel::Color, neotrellis::KeyEvent},
+    grid::MIDI_GRID,
+    keyboard::{run_leds, Backend, Command, Event},
+    latency::Trace,
+};
+
+/// Colours that the controller can show, and the velocities that show them.
+/// Pads only take a colour from their palette.
+fn palette(model: MidiGridModel) -> &'static [((u8, u8, u8), u8)] {
+    match model {
+        MidiGridModel::Launchpad => &[
+            ((255, 255, 255), 3),
+            ((255, 0, 0), 5),
+            ((255, 100, 0), 9),
+            ((255, 255, 0), 13),
+            ((0, 255, 0), 21),
+            ((0, 255, 255), 37),
+            ((0, 0, 255), 45),
+            ((255, 0, 255), 53),
+        ],
+        MidiGridModel::ApcMini => &[((0, 255, 0), 1), ((255, 0, 0), 3), ((255, 255, 0), 5)],
+    }
+}
+
+/// Velocity of the palette colour that is closest to `color`, where 0 is off.
+fn velocity(model: MidiGridModel, color: Color) -> u8 {
+    let Color { r, g, b, .. } = color;
+
+    // the palettes only have bright colours, so dim ones are left off
+    if r.max(g).max(b) < 32 {
+        return 0;
+    }
+
+    let distance = |(pr, pg, pb): (u8, u8, u8)| {
+        let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2);
+        d(r, pr) + d(g, pg) + d(b, pb)
+    };
+
+    palette(model)
+        .iter()
+        .min_by_key(|(rgb, _)| distance(*rgb))
+        .map_or(0, |(_, velocity)| *velocity)
+}
+
+/// Note of the pad at (x, y) on the pidj grid.
+fn note(model: MidiGridModel, x: u16, y: u16) -> u8 {
+    match model {
+        // programmer mode numbers rows from 1 at the bottom, and columns from
+        // 1 on the left
+        MidiGridModel::Launchpad => (10 * (8 - y) + x + 1) as u8,
+        // notes go up from 0 in the bottom left corner
+        MidiGridModel::ApcMini => ((7 - y) * 8 + x) as u8,
+    }
+}
+
+/// Position on the pidj grid of the pad that plays `note`, if it is on it.
+fn key(model: MidiGridModel, note: u8) -> Option<(u16, u16)> {
+    let (width, height) = MIDI_GRID;
+
+    (0..height as u16)
+        .flat_map(|y| (0..width as u16).map(move |x| (x, y)))
+        .find(|&(x, y)| self::note(model, x, y) == note)
+}
+
+/// Picks note on and note off messages out of a MIDI byte stream.
+#[derive(Debug, Default)]
+struct Parser {
+    /// status byte of the message being read, which carries over to the next
+    /// message if it is left out (running status)
+    status: Option<u8>,
+    data: Vec<u8>,
+}
+
+impl Parser {
+    /// Reads one byte. Returns the note and whether it was pressed when a
+    /// note message is complete.
+    fn push(&mut self, byte: u8) -> Option<(u8, bool)> {
+        if byte & 0x80 != 0 {
+            // real-time messages can come in the middle of other messages
+            if byte < 0xf8 {
+                self.status = Some(byte);
+                self.data.clear();
+            }
+
+            return None;
+        }
+
+        let status = self.status?;
+        self.data.push(byte);
+
+        // system messages aren't used, and their data is skipped
+        let len = match status & 0xf0 {
+            0xc0 | 0xd0 => 1,
+            0xf0 => return None,
+            _ => 2,
+        };
+
+        if self.data.len() < len {
+            return None;
+        }
+
+        let data = std::mem::take(&mut self.data);
+
+        match (status & 0xf0, data.as_slice()) {
+            (0x90, &[note, velocity]) => Some((note, velocity > 0)),
+            (0x80, &[note, _]) => Some((note, false)),
+            _ => None,
+        }
+    }
+}
+
+/// The controller's pads, which show the colour in their palette that is
+/// closest to the one that they are set to.
+struct Pads {
+    output: File,
+    model: MidiGridModel,
+}
+
+impl Backend for Pads {
+    fn grid_size(&self) -> (usize, usize) {
+        MIDI_GRID
+    }
+
+    fn draw(&mut self, changes: &[(u16, u16, Color)]) -> anyhow::Result<()> {
+        let mut messages = vec![];
+
+        for &(x, y, color) in changes {
+            messages.extend_from_slice(&[
+                0x90,
+                note(self.model, x, y),
+                velocity(self.model, color),
+            ]);
+        }
+
+        if !messages.is_empty() {
+            self.output
+                .write_all(&messages)
+                .context("failed to set MIDI grid colors")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Runs the controller until cancelled. `animations` are the ones made in
+/// the config, which can be played on it as well as the built-in ones.
+pub fn run(
+    ct: CancellationToken,
+    config: MidiGridConfig,
+    animations: BTreeMap<String, Animation>,
+    cmd_rx: flume::Receiver<Command>,
+    evt_tx: flume::Sender<Event>,
+) -> anyhow::Result<()> {
+    let path = &config.device;
+    let model = config.model;
+
+    let mut output = OpenOptions::new()
+        .write(true)
+        .open(path)
+        .with_context(|| format!("failed to open MIDI device {path:?}"))?;
+
+    if model == MidiGridModel::Launchpad {
+        // programmer mode, so that the pads send the notes in `note`. This is
+        // the Launchpad Mini MK3's message, which the Launchpad X ignores, so
+        // send the X's too.
+        for product in [0x0d, 0x0c] {
+            output
+                .write_all(&[0xf0, 0x00, 0x20, 0x29, 0x02, product, 0x0e, 0x01, 0xf7])
+                .context("failed to put Launchpad into programmer mode")?;
+        }
+    }
+
+    // reads block, so they get their own thread. It ends when the device is
+    // unplugged or the engine stops listening.
+    std::thread::spawn({
+        let path = path.clone();
+        let input =
+            File::open(&path).with_context(|| format!("failed to open MIDI device {path:?}"))?;
+        move || read_keys(&path, input, model, evt_tx)
+    });
+
+    debug!("running MIDI grid loop");
+
+    // the VU meter isn't shown on the controller, so it doesn't matter when
+    // a key was last pressed
+    let last_key = Mutex::new(Instant::now());
+
+    run_leds(
+        &ct,
+        &mut Pads { output, model },
+        &cmd_rx,
+        &animations,
+        None,
+        &last_key,
+    )?;
+
+    debug!("MIDI grid task exited");
+
+    Ok(())
+}
+
+fn read_keys(path: &Path, mut input: File, model: MidiGridModel, evt_tx: flume::Sender<Event>) {
+    let mut parser = Parser::default();
+    let mut buf = [0; 64];
+
+    loop {
+        let scan_start = Instant::now();
+
+        let len = match input.read(&mut buf) {
+            Ok(0) => break,
+            Ok(len) => len,
+            Err(err) => {
+                warn!("failed to read from MIDI device {path:?}: {err}");
+                break;
+            }
+        };
+
+        let scan = Trace::new(scan_start, Instant::now());
+
+        for &byte in &buf[..len] {
+            let Some((note, pressed)) = parser.push(byte) else {
+                continue;
+            };
+            let Some(key) = key(model, note) else {
+                continue;
+            };
+
+            let edge = if pressed { Edge::Rising } else { Edge::Falling };
+
+            if evt_tx
+                .send(Event::Key(KeyEvent { key, edge }, scan))
+                .is_err()
+            {
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_notes_with_running_status() {
+        let mut parser = Parser::default();
+        let notes: Vec<_> = [0x90, 81, 127, 0xf8, 82, 0, 0x80, 81, 64]
+            .into_iter()
+            .filter_map(|byte| parser.push(byte))
+            .collect();
+
+        assert_eq!(notes, vec![(81, true), (82, false), (81, false)]);
+    }
+
+    #[test]
+    fn map_pads_to_notes_and_colors() {
+        assert_eq!(note(MidiGridModel::Launchpad, 0, 0), 81);
+        assert_eq!(key(MidiGridModel::Launchpad, 54), Some((3, 3)));
+        assert_eq!(note(MidiGridModel::ApcMini, 0, 0), 56);
+        assert_eq!(key(MidiGridModel::ApcMini, 0), Some((0, 7)));
+        assert_eq!(key(MidiGridModel::ApcMini, 64), None);
+
+        assert_eq!(velocity(MidiGridModel::ApcMini, Color::BLACK), 0);
+        assert_eq!(
+            velocity(MidiGridModel::ApcMini, Color::from_u8(200, 20, 0)),
+            3
+        );
+        assert_eq!(
+            velocity(MidiGridModel::Launchpad, Color::from_u8(0, 200, 255)),
+            37
+        );
+    }
+}
@@ -0,0 +1,178 @@
+//! How each pad plays its sound: its level, pan, pitch and trim, whether it
+//! cuts off other pads, and whether it keeps playing when it is let go. The
+//! settings are edited in the pad's detail view.
+
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+/// How many choke groups there are.
+pub const CHOKE_GROUPS: u8 = 4;
+
+/// How far a pad's pitch can be moved, in semitones either way.
+pub const PITCH_RANGE: i8 = 12;
+
+/// How far a pad's gain can be moved, in dB either way.
+pub const GAIN_RANGE_DB: f32 = 24.;
+
+/// What a pad does when it is let go.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PlaybackMode {
+    /// The sound plays to the end, however long the pad is held.
+    #[default]
+    OneShot,
+    /// The sound stops when the pad is let go.
+    Gate,
+    /// The sound plays until the pad is pressed again.
+    Toggle,
+}
+
+impl PlaybackMode {
+    pub const ALL: [PlaybackMode; 3] = [
+        PlaybackMode::OneShot,
+        PlaybackMode::Gate,
+        PlaybackMode::Toggle,
+    ];
+}
+
+impl std::fmt::Display for PlaybackMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            PlaybackMode::OneShot => "One-shot",
+            PlaybackMode::Gate => "Gate",
+            PlaybackMode::Toggle => "Toggle",
+        })
+    }
+}
+
+/// How a pad plays its sound.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PadSettings {
+    /// added to the sound's level
+    pub gain_db: f32,
+    /// from -1.0 (only left) to 1.0 (only right)
+    pub pan: f32,
+    /// in semitones. Like on a sampler, the sound is sped up or slowed down
+    /// to change it, so its length changes too.
+    pub pitch: i8,
+    /// how much of the start of the sound is skipped
+    pub trim_start_ms: u32,
+    /// how much of the end of the sound is cut off
+    pub trim_end_ms: u32,
+    /// pads in the same group cut each other off, like open and closed
+    /// hi-hats. From 1 to [`CHOKE_GROUPS`].
+    pub choke_group: Option<u8>,
+    pub mode: PlaybackMode,
+}
+
+impl Default for PadSettings {
+    fn default() -> Self {
+        Self {
+            gain_db: 0.,
+            pan: 0.,
+            pitch: 0,
+            trim_start_ms: 0,
+            trim_end_ms: 0,
+            choke_group: None,
+            mode: PlaybackMode::OneShot,
+        }
+    }
+}
+
+impl PadSettings {
+    /// The settings with everything out of range brought back into it.
+    pub fn clamped(self) -> Self {
+        Self {
+            gain_db: self.gain_db.clamp(-GAIN_RANGE_DB, GAIN_RANGE_DB),
+            pan: self.pan.clamp(-1., 1.),
+            pitch: self.pitch.clamp(-PITCH_RANGE, PITCH_RANGE),
+            choke_group: self
+                .choke_group
+                .filter(|group| (1..=CHOKE_GROUPS).contains(group)),
+            ..self
+        }
+    }
+
+    pub fn trim_start(&self) -> Duration {
+        Duration::from_millis(self.trim_start_ms as u64)
+    }
+
+    pub fn trim_end(&self) -> Duration {
+        Duration::from_millis(self.trim_end_ms as u64)
+    }
+
+    /// Gains of the left and right channels. The pan is constant-power, so
+    /// that the sound is as loud wherever it is.
+    pub fn gains(&self) -> [f32; 2] {
+        let gain = 10f32.powf(self.gain_db / 20.);
+        let angle = (self.pan.clamp(-1., 1.) + 1.) * std::f32::consts::FRAC_PI_4;
+
+        // both are 1/√2 in the middle, so it is brought back up to unity
+        let centre = std::f32::consts::SQRT_2;
+        [gain * angle.cos() * centre, gain * angle.sin() * centre]
+    }
+
+    /// How fast the sound plays, where 1.0 is its own speed.
+    pub fn rate(&self) -> f32 {
+        2f32.powf(self.pitch as f32 / 12.)
+    }
+
+    /// How long a sound of `duration` plays for with these settings, if it
+    /// is started from `start`.
+    pub fn length_from(&self, duration: Duration, start: Duration) -> Duration {
+        duration
+            .saturating_sub(start)
+            .saturating_sub(self.trim_end())
+            .div_f32(self.rate())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::PadSettings;
+
+    #[test]
+    fn gains_pan_at_constant_power() {
+        let centre = PadSettings::default().gains();
+        assert!((centre[0] - 1.).abs() < 1e-6 && (centre[1] - 1.).abs() < 1e-6);
+
+        let left = PadSettings {
+            pan: -1.,
+            ..Default::default()
+        };
+        let [l, r] = left.gains();
+        assert!((l - 2f32.sqrt()).abs() < 1e-6 && r.abs() < 1e-6);
+
+        let quieter = PadSettings {
+            gain_db: -6.,
+            ..Default::default()
+        };
+        assert!((quieter.gains()[0] - 0.501).abs() < 1e-3);
+    }
+
+    #[test]
+    fn pitch_and_trim_change_the_length() {
+        let settings = PadSettings {
+            pitch: 12,
+            trim_start_ms: 500,
+            trim_end_ms: 500,
+            ..Default::default()
+        };
+
+        assert!((settings.rate() - 2.).abs() < 1e-6);
+        let length = settings.length_from(Duration::from_secs(3), settings.trim_start());
+        assert_eq!(length, Duration::from_secs(1));
+
+        let clamped = PadSettings {
+            pitch: 40,
+            choke_group: Some(9),
+            ..Default::default()
+        }
+        .clamped();
+        assert_eq!((clamped.pitch, clamped.choke_group), (12, None));
+    }
+}
@@ -0,0 +1,215 @@
+//! Colours of the keypad LEDs. Each [`Palette`] gives a colour to every role
+//! that an LED can have, so that the layout can be told apart by people who
+//! don't see red and green apart, or under bright stage lights. Palettes can
+//! also be made in the config, where the roles that they leave out keep their
+//! default colours:
+//!
+//! ```toml
+//! [keyboard]
+//! palette = "mine"
+//!
+//! [keyboard.palettes.mine]
+//! back = [255, 255, 255]
+//! bands = [[0, 0, 255], [255, 255, 0], [255, 0, 255]]
+//! ```
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::driver::adafruit::seesaw::neopixel::Color;
+
+/// A colour in a palette, as red, green and blue.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct Rgb(pub [u8; 3]);
+
+impl Rgb {
+    pub fn color(self) -> Color {
+        let Rgb([r, g, b]) = self;
+        Color::from_u8(r, g, b)
+    }
+
+    /// The colour at `brightness`, where 255 leaves it unchanged.
+    pub fn dim(self, brightness: u8) -> Color {
+        let Rgb([r, g, b]) = self;
+        let scale = |c: u8| ((c as u16 * brightness as u16) / 255) as u8;
+        Color::from_u8(scale(r), scale(g), scale(b))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Palette {
+    /// the key that leaves a mode, and a pad that is being recorded onto
+    pub back: Rgb,
+    /// the Fn key of the beat that is playing, and of the first beat of the
+    /// bar
+    pub beat: Rgb,
+    pub downbeat: Rgb,
+    /// decks A and B
+    pub decks: [Rgb; 2],
+    /// something that is set or picked, e.g. a deck's loop or a starred sound
+    pub highlight: Rgb,
+    /// a scene that is kept, and the key that goes up a folder
+    pub stored: Rgb,
+    /// something that can be done or is on, e.g. picking the selected sound
+    pub active: Rgb,
+    /// turning the pages of the pad browser
+    pub pages: Rgb,
+    /// the sort modes of the browser: by name, by length and by BPM
+    pub sort: [Rgb; 3],
+    /// the high, mid and low bands in the mixer
+    pub bands: [Rgb; 3],
+    /// the columns of FX mode
+    pub fx: [Rgb; 4],
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self {
+            back: Rgb([255, 0, 0]),
+            beat: Rgb([0, 200, 255]),
+            downbeat: Rgb([255, 100, 0]),
+            decks: [Rgb([0, 200, 255]), Rgb([255, 100, 0])],
+            highlight: Rgb([255, 200, 0]),
+            stored: Rgb([255, 160, 0]),
+            active: Rgb([0, 255, 0]),
+            pages: Rgb([0, 200, 255]),
+            sort: [Rgb([0, 200, 255]), Rgb([200, 0, 255]), Rgb([255, 0, 100])],
+            bands: [Rgb([0, 200, 255]), Rgb([0, 255, 0]), Rgb([255, 0, 100])],
+            fx: [
+                Rgb([0, 100, 255]),
+                Rgb([255, 200, 0]),
+                Rgb([0, 255, 100]),
+                Rgb([255, 0, 50]),
+            ],
+        }
+    }
+}
+
+// the colour-blind palettes are made from Okabe and Ito's colours, which
+// differ in blue and yellow as much as in red and green
+const ORANGE: Rgb = Rgb([230, 159, 0]);
+const SKY_BLUE: Rgb = Rgb([86, 180, 233]);
+const BLUISH_GREEN: Rgb = Rgb([0, 158, 115]);
+const YELLOW: Rgb = Rgb([240, 228, 66]);
+const BLUE: Rgb = Rgb([0, 114, 178]);
+const VERMILLION: Rgb = Rgb([213, 94, 0]);
+const REDDISH_PURPLE: Rgb = Rgb([204, 121, 167]);
+
+impl Palette {
+    /// Names of the built-in palettes.
+    pub const BUILTIN: [&'static str; 4] = ["default", "deuteranopia", "protanopia", "stage"];
+
+    /// For red-green colour blindness where green is weak. Nothing is told
+    /// apart by red and green alone.
+    pub fn deuteranopia() -> Self {
+        Self {
+            back: VERMILLION,
+            beat: SKY_BLUE,
+            downbeat: ORANGE,
+            decks: [SKY_BLUE, ORANGE],
+            highlight: YELLOW,
+            stored: ORANGE,
+            active: BLUE,
+            pages: SKY_BLUE,
+            sort: [SKY_BLUE, REDDISH_PURPLE, YELLOW],
+            bands: [SKY_BLUE, YELLOW, VERMILLION],
+            fx: [BLUE, YELLOW, BLUISH_GREEN, VERMILLION],
+        }
+    }
+
+    /// For red-green colour blindness where red is weak, so that reds look
+    /// dark. Red is left out for brighter colours.
+    pub fn protanopia() -> Self {
+        Self {
+            back: ORANGE,
+            stored: SKY_BLUE,
+            bands: [SKY_BLUE, YELLOW, REDDISH_PURPLE],
+            fx: [BLUE, YELLOW, BLUISH_GREEN, REDDISH_PURPLE],
+            ..Self::deuteranopia()
+        }
+    }
+
+    /// Fully saturated colours at full brightness, to be seen under stage
+    /// lights.
+    pub fn stage() -> Self {
+        Self {
+            back: Rgb([255, 0, 0]),
+            beat: Rgb([0, 255, 255]),
+            downbeat: Rgb([255, 255, 0]),
+            decks: [Rgb([0, 255, 255]), Rgb([255, 128, 0])],
+            highlight: Rgb([255, 255, 0]),
+            stored: Rgb([255, 128, 0]),
+            active: Rgb([0, 255, 0]),
+            pages: Rgb([0, 255, 255]),
+            sort: [Rgb([0, 255, 255]), Rgb([255, 0, 255]), Rgb([255, 0, 128])],
+            bands: [Rgb([0, 255, 255]), Rgb([0, 255, 0]), Rgb([255, 0, 128])],
+            fx: [
+                Rgb([0, 128, 255]),
+                Rgb([255, 255, 0]),
+                Rgb([0, 255, 128]),
+                Rgb([255, 0, 64]),
+            ],
+        }
+    }
+
+    /// The palette called `name`, from the ones in the config or the built-in
+    /// ones. A palette in the config can take the name of a built-in one.
+    pub fn named(name: &str, custom: &BTreeMap<String, Palette>) -> Option<Self> {
+        if let Some(palette) = custom.get(name) {
+            return Some(palette.clone());
+        }
+
+        match name {
+            "default" => Some(Self::default()),
+            "deuteranopia" => Some(Self::deuteranopia()),
+            "protanopia" => Some(Self::protanopia()),
+            "stage" => Some(Self::stage()),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use super::{Palette, Rgb};
+
+    #[test]
+    fn palettes_are_found_by_name() {
+        let mine = Palette {
+            back: Rgb([255, 255, 255]),
+            ..Palette::default()
+        };
+        let custom = BTreeMap::from([("mine".to_owned(), mine.clone())]);
+
+        assert_eq!(Palette::named("mine", &custom), Some(mine));
+        assert_eq!(Palette::named("stage", &custom), Some(Palette::stage()));
+        assert_eq!(Palette::named("neon", &custom), None);
+
+        for name in Palette::BUILTIN {
+            assert!(Palette::named(name, &BTreeMap::new()).is_some());
+        }
+    }
+
+    #[test]
+    fn colour_blind_palettes_keep_their_roles_apart() {
+        for palette in [Palette::deuteranopia(), Palette::protanopia()] {
+            // the back key is next to keys in the other roles
+            assert_ne!(palette.back, palette.active);
+            assert_ne!(palette.back, palette.highlight);
+            assert_ne!(palette.decks[0], palette.decks[1]);
+
+            for (i, a) in palette.fx.iter().enumerate() {
+                assert!(!palette.fx[i + 1..].contains(a));
+            }
+
+            for (i, a) in palette.bands.iter().enumerate() {
+                assert!(!palette.bands[i + 1..].contains(a));
+            }
+        }
+    }
+}
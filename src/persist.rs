@@ -0,0 +1,90 @@
+//! App state that is kept between runs. Sounds are stored by path, since their
+//! ids depend on the order that they were loaded in.
+
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+
+use crate::{effect::Chains, groove::Groove, pad::PadSettings, util::to_toml};
+
+/// Path of the saved state file, relative to the working directory.
+pub const STATE_PATH: &str = "pidj-state.toml";
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SavedState {
+    /// Sounds that have been starred in the sound browser.
+    pub favorites: BTreeSet<PathBuf>,
+
+    /// Sounds that were recently assigned to a key, most recent first.
+    pub recent: Vec<PathBuf>,
+
+    /// The BPM that the looper was last at, which it starts at instead of
+    /// the one in the config.
+    pub bpm: Option<f32>,
+
+    /// The pads that had sounds bound to them.
+    pub pads: Vec<SavedPad>,
+
+    /// The effect chains of the kit that was loaded last.
+    #[serde(skip_serializing_if = "Chains::is_empty")]
+    pub chains: Chains,
+
+    /// Groove templates that have been recorded.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub grooves: Vec<Groove>,
+}
+
+/// A pad with a sound bound to it, and how it plays it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SavedPad {
+    /// as [x, y], where y is between 1 and 3
+    pub pad: [usize; 2],
+    pub sound: PathBuf,
+    #[serde(default)]
+    pub color: Option<[u8; 3]>,
+    #[serde(default)]
+    pub loop_divider: Option<isize>,
+    #[serde(default)]
+    pub key_sync: bool,
+    #[serde(default)]
+    pub settings: PadSettings,
+}
+
+impl SavedState {
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read state file {path:?}"))?;
+
+        toml::from_str(&text).with_context(|| format!("failed to parse state file {path:?}"))
+    }
+
+    pub async fn save(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        let text = to_toml(self).context("failed to serialize state")?;
+
+        // the state is written next to the file and moved over it, so that
+        // the file isn't left half-written if the power goes during a save
+        let partial = path.with_extension("part");
+
+        async {
+            let mut file = tokio::fs::File::create(&partial).await?;
+            file.write_all(text.as_bytes()).await?;
+            file.sync_all().await?;
+            tokio::fs::rename(&partial, path).await
+        }
+        .await
+        .with_context(|| format!("failed to write state file {path:?}"))
+    }
+}
@@ -0,0 +1,376 @@
+//! Fixed set of voices that sounds are mixed on. The voices are allocated up
+//! front and the sounds are shared, so that playing a sound on the output
+//! stream's thread never allocates.
+
+use std::sync::Arc;
+
+/// How many sounds can play on a pool at once. Past this, the sound that has
+/// been playing longest is cut off.
+pub const MAX_VOICES: usize = 32;
+
+/// Channels of the samples that are mixed.
+const CHANNELS: usize = 2;
+
+/// How a voice plays its sound.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Voicing {
+    /// gain of each channel
+    pub gains: [f32; CHANNELS],
+    /// frames of the sound that are played for each frame of the mix
+    pub rate: f32,
+    /// index of the sample that the sound stops at, if it stops before its
+    /// end
+    pub end: Option<usize>,
+    /// voices in the same choke group cut each other off
+    pub choke: Option<u8>,
+    /// the pad that started the voice, so that it can be stopped when the pad
+    /// is let go
+    pub pad: Option<u8>,
+    /// index of the sample that the sound goes back to when it gets to its
+    /// end, if it loops until it is stopped
+    pub looped: Option<usize>,
+    /// frames at each end of a looping sound that fade out and back in, so
+    /// that it doesn't click where it wraps round
+    pub seam: usize,
+    /// the looper's id for the voice, so that it can stop a looping sound
+    pub looper: Option<u32>,
+}
+
+impl Default for Voicing {
+    fn default() -> Self {
+        Self {
+            gains: [1.; CHANNELS],
+            rate: 1.,
+            end: None,
+            choke: None,
+            pad: None,
+            looped: None,
+            seam: 0,
+            looper: None,
+        }
+    }
+}
+
+/// A sound that is playing.
+#[derive(Debug)]
+struct Voice {
+    /// interleaved samples of the sound
+    samples: Arc<[f32]>,
+    /// index of the next sample
+    position: usize,
+    /// how far the voice is between the frame at `position` and the next one,
+    /// if it doesn't play at its own rate
+    fraction: f32,
+    voicing: Voicing,
+    /// whether a looping sound has gone back to its start yet
+    wrapped: bool,
+    /// how many voices had been started on the pool before this one
+    started: u64,
+}
+
+impl Voice {
+    /// Gain of the current frame of a looping sound, which fades out over the
+    /// seam at its end and back in at its start, the first time excepted.
+    fn seam_gain(&self, end: usize) -> f32 {
+        let (Some(start), seam) = (self.voicing.looped, self.voicing.seam) else {
+            return 1.;
+        };
+
+        if seam == 0 {
+            return 1.;
+        }
+
+        let to_end = (end - self.position).div_ceil(CHANNELS);
+        let mut gain = (to_end as f32 / seam as f32).min(1.);
+
+        if self.wrapped {
+            let from_start = self.position.saturating_sub(start) / CHANNELS + 1;
+            gain = gain.min(from_start as f32 / seam as f32);
+        }
+
+        gain
+    }
+}
+
+#[derive(Debug)]
+pub struct VoicePool {
+    voices: Vec<Voice>,
+    /// channel of the next sample
+    channel: usize,
+    /// how many voices have been started, so that the oldest can be told
+    /// apart from the one that is furthest into its sound
+    starts: u64,
+}
+
+impl VoicePool {
+    pub fn new() -> Self {
+        Self {
+            voices: Vec::with_capacity(MAX_VOICES),
+            channel: 0,
+            starts: 0,
+        }
+    }
+
+    /// Starts playing `samples` from the sample at `position`. It has to be
+    /// called between frames, and `position` has to be at the start of a
+    /// frame, so that the channels of the voices line up.
+    pub fn start(&mut self, samples: Arc<[f32]>, position: usize) {
+        self.start_voiced(samples, position, Voicing::default());
+    }
+
+    /// Starts playing `samples` like [`VoicePool::start`], but the way that
+    /// `voicing` says. Voices in its choke group are cut off.
+    pub fn start_voiced(&mut self, samples: Arc<[f32]>, position: usize, voicing: Voicing) {
+        if let Some(group) = voicing.choke {
+            self.choke(group);
+        }
+
+        let voice = Voice {
+            samples,
+            position,
+            fraction: 0.,
+            voicing,
+            wrapped: false,
+            started: self.starts,
+        };
+
+        self.starts += 1;
+
+        if self.voices.len() < MAX_VOICES {
+            self.voices.push(voice);
+            return;
+        }
+
+        let oldest = self
+            .voices
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, voice)| voice.started)
+            .map(|(i, _)| i)
+            .unwrap_or_default();
+
+        self.voices[oldest] = voice;
+    }
+
+    /// Cuts off the voices in a choke group.
+    pub fn choke(&mut self, group: u8) {
+        self.voices
+            .retain(|voice| voice.voicing.choke != Some(group));
+    }
+
+    /// Stops the voices that a pad started.
+    pub fn release(&mut self, pad: u8) {
+        self.voices.retain(|voice| voice.voicing.pad != Some(pad));
+    }
+
+    /// Stops the looping sound that the looper gave this id.
+    pub fn stop_loop(&mut self, looper: u32) {
+        self.voices
+            .retain(|voice| voice.voicing.looper != Some(looper));
+    }
+
+    /// How many sounds are playing.
+    pub fn len(&self) -> usize {
+        self.voices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.voices.is_empty()
+    }
+
+    /// Mixes the next sample of every voice, and lets go of the voices that
+    /// have finished.
+    pub fn next_sample(&mut self) -> f32 {
+        let channel = self.channel;
+        let end_of_frame = channel == CHANNELS - 1;
+        let mut sum = 0.;
+        let mut i = 0;
+
+        self.channel = (channel + 1) % CHANNELS;
+
+        while i < self.voices.len() {
+            let voice = &mut self.voices[i];
+            let end = voice
+                .voicing
+                .end
+                .map_or(voice.samples.len(), |end| end.min(voice.samples.len()));
+
+            // a looping sound goes straight back to its start, so there is no
+            // gap between one time round and the next
+            if voice.position >= end {
+                if let Some(start) = voice.voicing.looped.filter(|&start| start < end) {
+                    voice.position = start + (voice.position - end) % (end - start);
+                    voice.wrapped = true;
+                }
+            }
+
+            match voice
+                .samples
+                .get(voice.position)
+                .filter(|_| voice.position < end)
+            {
+                Some(&sample) => {
+                    // in between frames, the sample is interpolated
+                    let sample = match voice.samples.get(voice.position + CHANNELS) {
+                        Some(&next) if voice.fraction > 0. => {
+                            sample + (next - sample) * voice.fraction
+                        }
+                        _ => sample,
+                    };
+
+                    sum += sample * voice.voicing.gains[channel] * voice.seam_gain(end);
+                    voice.position += 1;
+
+                    // the voice moved on by a frame, but it should have moved
+                    // on by its rate
+                    if end_of_frame && voice.voicing.rate != 1. {
+                        let step = voice.fraction + voice.voicing.rate;
+                        let frames = step.floor();
+
+                        voice.fraction = step - frames;
+                        voice.position =
+                            voice.position.saturating_sub(CHANNELS) + frames as usize * CHANNELS;
+                    }
+
+                    i += 1;
+                }
+                None => {
+                    self.voices.swap_remove(i);
+                }
+            }
+        }
+
+        sum
+    }
+}
+
+impl Default for VoicePool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use super::{VoicePool, Voicing, MAX_VOICES};
+
+    #[test]
+    fn voices_are_mixed_until_they_finish() {
+        let mut pool = VoicePool::new();
+        pool.start(Arc::from([1., 2., 3.]), 0);
+        pool.start(Arc::from([10., 20., 30., 40.]), 1);
+
+        let out: Vec<_> = (0..4).map(|_| pool.next_sample()).collect();
+        assert_eq!(out, [21., 32., 43., 0.]);
+        assert!(pool.is_empty());
+    }
+
+    #[test]
+    fn full_pool_cuts_off_the_oldest_voice() {
+        let mut pool = VoicePool::new();
+        let long: Arc<[f32]> = Arc::from(vec![1.; 100]);
+
+        // the first voice starts from the top, and the rest further in
+        pool.start(Arc::from(vec![2.; 100]), 0);
+
+        for i in 1..MAX_VOICES {
+            pool.start(long.clone(), i * 2);
+        }
+
+        pool.start(Arc::from([0.5]), 0);
+
+        // the voice that was started first was swapped for the new one, not
+        // the one that was furthest in
+        assert_eq!(pool.len(), MAX_VOICES);
+        assert_eq!(pool.next_sample(), MAX_VOICES as f32 - 0.5);
+        assert_eq!(pool.voices.capacity(), MAX_VOICES);
+    }
+
+    #[test]
+    fn voicing_pans_pitches_and_chokes() {
+        let mut pool = VoicePool::new();
+        let frames: Arc<[f32]> = Arc::from([1., 1., 2., 2., 3., 3., 4., 4.]);
+
+        // an octave up plays every other frame, and stops at the end
+        let voicing = Voicing {
+            gains: [1., 0.5],
+            rate: 2.,
+            end: Some(6),
+            choke: Some(1),
+            ..Default::default()
+        };
+        pool.start_voiced(frames.clone(), 0, voicing);
+
+        let out: Vec<_> = (0..6).map(|_| pool.next_sample()).collect();
+        assert_eq!(out, [1., 0.5, 3., 1.5, 0., 0.]);
+
+        // half speed goes through the frames in between
+        let slow = Voicing {
+            rate: 0.5,
+            ..Default::default()
+        };
+        pool.start_voiced(frames.clone(), 0, slow);
+
+        let out: Vec<_> = (0..4).map(|_| pool.next_sample()).collect();
+        assert_eq!(out, [1., 1., 1.5, 1.5]);
+
+        // starting another voice in the group cuts off the first
+        pool.start_voiced(frames.clone(), 0, voicing);
+        pool.start_voiced(frames.clone(), 0, voicing);
+        assert_eq!(pool.len(), 2);
+
+        pool.start_voiced(
+            frames,
+            0,
+            Voicing {
+                pad: Some(5),
+                ..Default::default()
+            },
+        );
+        pool.release(5);
+        assert_eq!(pool.len(), 2);
+    }
+
+    #[test]
+    fn looped_voices_wrap_round_until_stopped() {
+        let mut pool = VoicePool::new();
+        let frames: Arc<[f32]> = Arc::from([1., 1., 2., 2., 3., 3., 4., 4.]);
+
+        // only the left channel, to keep it short
+        let left = |pool: &mut VoicePool, frames: usize| -> Vec<f32> {
+            (0..frames)
+                .map(|_| {
+                    let sample = pool.next_sample();
+                    pool.next_sample();
+                    sample
+                })
+                .collect()
+        };
+
+        let voicing = Voicing {
+            gains: [1., 0.],
+            end: Some(6),
+            looped: Some(0),
+            looper: Some(3),
+            ..Default::default()
+        };
+        pool.start_voiced(frames.clone(), 0, voicing);
+        assert_eq!(left(&mut pool, 8), [1., 2., 3., 1., 2., 3., 1., 2.]);
+
+        pool.stop_loop(3);
+        assert!(pool.is_empty());
+
+        // the seam fades out at the end, and in again after the first time
+        let ones: Arc<[f32]> = Arc::from([1.; 8]);
+        let seamed = Voicing {
+            gains: [1., 0.],
+            looped: Some(0),
+            seam: 2,
+            ..Default::default()
+        };
+        pool.start_voiced(ones, 0, seamed);
+        assert_eq!(left(&mut pool, 8), [1., 1., 1., 0.5, 0.5, 1., 1., 0.5]);
+    }
+}
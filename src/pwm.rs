@@ -0,0 +1,147 @@
+//! Outputs wired to the NeoTrellis's PWM pins, which the engine drives from
+//! what it hears about: an LED bar that follows the level of the master mix,
+//! or a small fan that speeds up as the board warms up. See
+//! [`crate::config::PwmOutputConfig`].
+
+use crate::{
+    config::{PwmOutputConfig, PwmSource},
+    keyboard::Command,
+};
+
+/// The quietest level that turns a level output on, in dB below full scale.
+const RANGE_DB: f32 = 36.;
+
+/// Steps that the duty cycle is rounded to, so that a level that wavers
+/// doesn't send a command for every change.
+const STEPS: f32 = 64.;
+
+/// The outputs, and the duty cycles that they were last set to.
+#[derive(Debug, Clone, Default)]
+pub struct PwmOutputs {
+    outputs: Vec<PwmOutputConfig>,
+    duties: Vec<Option<u16>>,
+}
+
+impl PwmOutputs {
+    pub fn new(outputs: Vec<PwmOutputConfig>) -> Self {
+        Self {
+            duties: vec![None; outputs.len()],
+            outputs,
+        }
+    }
+
+    /// Commands that set the pins' frequencies, for when the keyboard has
+    /// (re)started. The outputs are set again on the next update.
+    pub fn start(&mut self) -> Vec<Command> {
+        self.duties.fill(None);
+
+        self.outputs
+            .iter()
+            .filter_map(|output| {
+                Some(Command::SetPwmFrequency {
+                    pin: output.pin,
+                    frequency: output.frequency?,
+                })
+            })
+            .collect()
+    }
+
+    /// Commands that set the outputs that follow the level to the peak level
+    /// of the master mix, where 1.0 is full scale.
+    pub fn level(&mut self, peak: f32) -> Vec<Command> {
+        let db = 20. * peak.max(1e-6).log10();
+        let fraction = (db + RANGE_DB) / RANGE_DB;
+
+        self.update(|source| matches!(source, PwmSource::Level).then_some(fraction))
+    }
+
+    /// Commands that set the outputs that follow the NeoTrellis's
+    /// temperature, in °C.
+    pub fn temperature(&mut self, temperature: u32) -> Vec<Command> {
+        self.update(|source| match *source {
+            PwmSource::Temperature { from, to } => {
+                let span = to.saturating_sub(from).max(1) as f32;
+                Some((temperature as f32 - from as f32) / span)
+            }
+            PwmSource::Level => None,
+        })
+    }
+
+    /// Sets each output that `fraction` gives a fraction of fully on for,
+    /// and returns the commands for the ones that have changed.
+    fn update(&mut self, fraction: impl Fn(&PwmSource) -> Option<f32>) -> Vec<Command> {
+        let mut commands = vec![];
+
+        for (output, last) in self.outputs.iter().zip(&mut self.duties) {
+            let Some(fraction) = fraction(&output.source) else {
+                continue;
+            };
+            let steps = (fraction.clamp(0., 1.) * STEPS).round();
+            let duty = (steps / STEPS * u16::MAX as f32) as u16;
+
+            if last.replace(duty) != Some(duty) {
+                commands.push(Command::SetPwm {
+                    pin: output.pin,
+                    duty,
+                });
+            }
+        }
+
+        commands
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::PwmOutputs;
+    use crate::{
+        config::{PwmOutputConfig, PwmSource},
+        keyboard::Command,
+    };
+
+    fn duties(commands: Vec<Command>) -> Vec<(u8, u16)> {
+        commands
+            .into_iter()
+            .map(|cmd| match cmd {
+                Command::SetPwm { pin, duty } => (pin, duty),
+                cmd => panic!("unexpected command {cmd:?}"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn outputs_follow_their_source_and_only_send_changes() {
+        let mut pwm = PwmOutputs::new(vec![
+            PwmOutputConfig {
+                pin: 4,
+                frequency: None,
+                source: PwmSource::Level,
+            },
+            PwmOutputConfig {
+                pin: 5,
+                frequency: Some(25_000),
+                source: PwmSource::Temperature { from: 40, to: 60 },
+            },
+        ]);
+
+        assert!(matches!(
+            pwm.start()[..],
+            [Command::SetPwmFrequency {
+                pin: 5,
+                frequency: 25_000
+            }]
+        ));
+
+        assert_eq!(duties(pwm.level(1.)), [(4, u16::MAX)]);
+        assert_eq!(duties(pwm.level(0.)), [(4, 0)]);
+        assert!(pwm.level(1e-9).is_empty());
+
+        assert_eq!(duties(pwm.temperature(30)), [(5, 0)]);
+        assert_eq!(duties(pwm.temperature(50)), [(5, u16::MAX / 2)]);
+        assert_eq!(duties(pwm.temperature(70)), [(5, u16::MAX)]);
+
+        // a restarted keyboard has to be told again
+        pwm.start();
+        assert_eq!(duties(pwm.temperature(70)), [(5, u16::MAX)]);
+    }
+}
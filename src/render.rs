@@ -0,0 +1,194 @@
+//! Rendering the loops offline, faster than they play, to bounce them onto a
+//! pad or to export a stem of each bus. A render runs on its own thread with
+//! its own voices and shared copies of the sounds, so the output stream is
+//! never held up by it.
+
+use std::{
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{
+    audio::SoundInfo,
+    pool::{VoicePool, Voicing},
+};
+
+/// How many frames are rendered between progress reports.
+const CHUNK_FRAMES: usize = 4_096;
+
+/// Channels of the samples that are rendered.
+const CHANNELS: usize = 2;
+
+/// What a render is for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RenderTarget {
+    /// Bounces the loops onto the pad at (x, y), which plays them as a loop.
+    Pad(usize, usize),
+    /// Writes the loops on each bus to a WAV file.
+    Stems,
+}
+
+/// How a render is getting on, as reported by the audio subsystem.
+#[derive(Debug, Clone)]
+pub enum RenderStatus {
+    /// The fraction of the render that is done, from 0.0 to 1.0.
+    Progress(f32),
+    /// A render onto a pad has finished, and can be played like the sounds
+    /// that were loaded.
+    Bounced(SoundInfo),
+    /// The stems were written to this directory.
+    Exported(PathBuf),
+    Cancelled,
+    Failed(String),
+}
+
+/// A sound that starts playing during a render.
+#[derive(Debug, Clone)]
+pub struct Hit {
+    /// the frame of the render that it starts on
+    pub frame: usize,
+    pub samples: Arc<[f32]>,
+    /// index of the sample that it starts from
+    pub position: usize,
+    pub voicing: Voicing,
+}
+
+/// Mixes `hits` into `frames` frames of interleaved stereo. Sounds that are
+/// still playing at the end are wrapped round onto the start, so that the
+/// render loops without a gap. `progress` is told the fraction that is done
+/// after every chunk, and the render is given up on if it returns false.
+pub fn render(
+    mut hits: Vec<Hit>,
+    frames: usize,
+    mut progress: impl FnMut(f32) -> bool,
+) -> Option<Vec<f32>> {
+    hits.sort_by_key(|hit| hit.frame);
+
+    let mut out = vec![0.; frames * CHANNELS];
+    let mut pool = VoicePool::new();
+    let mut hits = hits.into_iter().peekable();
+    let mut frame = 0;
+
+    // the tails can't be longer than the render, or they would pile up on
+    // top of themselves
+    while frame < frames || (!pool.is_empty() && frame < frames * 2) {
+        let done = frame.min(frames) as f32 / frames as f32;
+
+        if frame > 0 && frame % CHUNK_FRAMES == 0 && !progress(done) {
+            return None;
+        }
+
+        while let Some(hit) = hits.next_if(|hit| hit.frame <= frame) {
+            pool.start_voiced(hit.samples, hit.position, hit.voicing);
+        }
+
+        let i = (frame % frames.max(1)) * CHANNELS;
+
+        for sample in out.iter_mut().skip(i).take(CHANNELS) {
+            *sample += pool.next_sample();
+        }
+
+        frame += 1;
+    }
+
+    progress(1.);
+    Some(out)
+}
+
+/// Writes interleaved stereo samples as a 16-bit WAV file.
+pub fn write_wav(path: &Path, samples: &[f32], sample_rate: u32) -> io::Result<()> {
+    let mut file = io::BufWriter::new(std::fs::File::create(path)?);
+    file.write_all(&wav(samples, sample_rate))?;
+    file.flush()
+}
+
+fn wav(samples: &[f32], sample_rate: u32) -> Vec<u8> {
+    let data_len = samples.len() as u32 * 2;
+    let mut wav = wav_header(data_len, sample_rate);
+    wav.reserve(data_len as usize);
+
+    for &sample in samples {
+        wav.extend(pcm16(sample).to_le_bytes());
+    }
+
+    wav
+}
+
+/// The 44-byte header of a 16-bit stereo WAV file with `data_len` bytes of
+/// samples.
+pub(crate) fn wav_header(data_len: u32, sample_rate: u32) -> Vec<u8> {
+    let block_align = CHANNELS as u16 * 2;
+
+    let mut wav = Vec::with_capacity(44);
+    wav.extend(b"RIFF");
+    wav.extend((36 + data_len).to_le_bytes());
+    wav.extend(b"WAVEfmt ");
+    wav.extend(16u32.to_le_bytes());
+    // integer PCM
+    wav.extend(1u16.to_le_bytes());
+    wav.extend((CHANNELS as u16).to_le_bytes());
+    wav.extend(sample_rate.to_le_bytes());
+    wav.extend((sample_rate * block_align as u32).to_le_bytes());
+    wav.extend(block_align.to_le_bytes());
+    wav.extend(16u16.to_le_bytes());
+    wav.extend(b"data");
+    wav.extend(data_len.to_le_bytes());
+    wav
+}
+
+/// A sample as a 16-bit integer, clipped at full scale.
+pub(crate) fn pcm16(sample: f32) -> i16 {
+    (sample.clamp(-1., 1.) * i16::MAX as f32).round() as i16
+}
+
+#[cfg(test)]
+mod test {
+    use crate::pool::Voicing;
+
+    use super::{render, wav, Hit};
+
+    fn hit(frame: usize, frames: usize) -> Hit {
+        Hit {
+            frame,
+            samples: vec![0.5; frames * 2].into(),
+            position: 0,
+            voicing: Voicing::default(),
+        }
+    }
+
+    #[test]
+    fn tails_wrap_round_to_the_start() {
+        let out = render(vec![hit(6, 4), hit(0, 2)], 8, |_| true).unwrap();
+
+        assert_eq!(
+            out.chunks(2).map(|frame| frame[0]).collect::<Vec<_>>(),
+            vec![1., 1., 0., 0., 0., 0., 0.5, 0.5]
+        );
+    }
+
+    #[test]
+    fn render_can_be_cancelled() {
+        let frames = 10_000;
+        let mut reports = vec![];
+
+        let out = render(vec![hit(0, 1)], frames, |done| {
+            reports.push(done);
+            reports.len() < 2
+        });
+
+        assert!(out.is_none());
+        assert_eq!(reports.len(), 2);
+        assert!(reports[0] > 0. && reports[0] < 1.);
+    }
+
+    #[test]
+    fn wav_header_matches_data() {
+        let bytes = wav(&[0., 1., -1., 0.5], 44_100);
+
+        assert_eq!(bytes.len(), 44 + 8);
+        assert_eq!(&bytes[0..4], b"RIFF");
+        assert_eq!(u32::from_le_bytes(bytes[40..44].try_into().unwrap()), 8);
+        assert_eq!(i16::from_le_bytes([bytes[46], bytes[47]]), i16::MAX);
+    }
+}
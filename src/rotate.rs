@@ -0,0 +1,173 @@
+//! Turns the whole UI for a screen that isn't mounted the right way up. egui
+//! can't turn what it draws, so the app is run on a context of its own that
+//! is the screen's size turned round, and what it draws is turned and painted
+//! onto the window's context. Pointer and touch input is turned the other way
+//! on the way in.
+
+use std::collections::HashMap;
+
+use egui::{
+    epaint::{ClippedPrimitive, Primitive},
+    Event, Id, LayerId, Order, Pos2, Rect, Shape, TextureHandle, TextureId, Vec2,
+};
+use pidj::config::Rotation;
+
+pub struct Rotated<A> {
+    app: A,
+    rotation: Rotation,
+    /// the context that the app runs on
+    ctx: egui::Context,
+    /// the app's textures, e.g. its font atlas, as they are on the window's
+    /// context
+    textures: HashMap<TextureId, TextureHandle>,
+}
+
+impl<A: eframe::App> Rotated<A> {
+    pub fn new(app: A, rotation: Rotation) -> Self {
+        Self {
+            app,
+            rotation,
+            ctx: egui::Context::default(),
+            textures: HashMap::new(),
+        }
+    }
+
+    /// The context that the app runs on, which its theme is applied to.
+    pub fn ctx(&self) -> &egui::Context {
+        &self.ctx
+    }
+}
+
+impl<A: eframe::App> eframe::App for Rotated<A> {
+    fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
+        let screen = ctx.input().screen_rect();
+        let turn = Turn {
+            rotation: self.rotation,
+            screen: screen.size(),
+        };
+
+        let mut input = ctx.input().raw.clone();
+        input.pixels_per_point = Some(ctx.pixels_per_point());
+        input.screen_rect = Some(Rect::from_min_size(Pos2::ZERO, turn.inner_size()));
+
+        for event in &mut input.events {
+            match event {
+                Event::PointerMoved(pos)
+                | Event::PointerButton { pos, .. }
+                | Event::Touch { pos, .. } => *pos = turn.to_inner(*pos),
+                Event::Scroll(delta) => *delta = turn.vec_to_inner(*delta),
+                _ => {}
+            }
+        }
+
+        let output = self
+            .ctx
+            .run(input, |app_ctx| self.app.update(app_ctx, frame));
+
+        for (id, delta) in output.textures_delta.set {
+            match (self.textures.get_mut(&id), delta.pos) {
+                (Some(handle), Some(pos)) => handle.set_partial(pos, delta.image, delta.options),
+                (Some(handle), None) => handle.set(delta.image, delta.options),
+                (None, _) => {
+                    let handle =
+                        ctx.load_texture(format!("rotated {id:?}"), delta.image, delta.options);
+                    self.textures.insert(id, handle);
+                }
+            }
+        }
+
+        let painter = ctx.layer_painter(LayerId::new(Order::Background, Id::new("rotated")));
+
+        for ClippedPrimitive {
+            clip_rect,
+            primitive,
+        } in self.ctx.tessellate(output.shapes)
+        {
+            // paint callbacks draw straight to the screen, so they can't be
+            // turned, and the app doesn't use any
+            let Primitive::Mesh(mut mesh) = primitive else {
+                continue;
+            };
+
+            let Some(texture) = self.textures.get(&mesh.texture_id) else {
+                continue;
+            };
+            mesh.texture_id = texture.id();
+
+            for vertex in &mut mesh.vertices {
+                vertex.pos = turn.to_outer(vertex.pos);
+            }
+
+            let clip_rect =
+                Rect::from_two_pos(turn.to_outer(clip_rect.min), turn.to_outer(clip_rect.max));
+            painter.with_clip_rect(clip_rect).add(Shape::mesh(mesh));
+        }
+
+        for id in output.textures_delta.free {
+            self.textures.remove(&id);
+        }
+
+        // the theme sets the scale on the app's context, which the window
+        // follows
+        if self.ctx.pixels_per_point() != ctx.pixels_per_point() {
+            ctx.set_pixels_per_point(self.ctx.pixels_per_point());
+        }
+
+        *ctx.output() = output.platform_output;
+
+        if output.repaint_after.is_zero() {
+            ctx.request_repaint();
+        } else {
+            ctx.request_repaint_after(output.repaint_after);
+        }
+    }
+}
+
+/// Turns points between the window, which is `screen` in size, and the app,
+/// which is the window turned by `rotation`.
+struct Turn {
+    rotation: Rotation,
+    screen: Vec2,
+}
+
+impl Turn {
+    fn inner_size(&self) -> Vec2 {
+        match self.rotation.is_sideways() {
+            true => Vec2::new(self.screen.y, self.screen.x),
+            false => self.screen,
+        }
+    }
+
+    /// Where a point in the app is drawn in the window.
+    fn to_outer(&self, pos: Pos2) -> Pos2 {
+        let Vec2 { x: w, y: h } = self.screen;
+
+        match self.rotation {
+            Rotation::None => pos,
+            Rotation::Clockwise => Pos2::new(w - pos.y, pos.x),
+            Rotation::UpsideDown => Pos2::new(w - pos.x, h - pos.y),
+            Rotation::Anticlockwise => Pos2::new(pos.y, h - pos.x),
+        }
+    }
+
+    /// Where a point in the window is in the app.
+    fn to_inner(&self, pos: Pos2) -> Pos2 {
+        let Vec2 { x: w, y: h } = self.screen;
+
+        match self.rotation {
+            Rotation::None => pos,
+            Rotation::Clockwise => Pos2::new(pos.y, w - pos.x),
+            Rotation::UpsideDown => Pos2::new(w - pos.x, h - pos.y),
+            Rotation::Anticlockwise => Pos2::new(h - pos.y, pos.x),
+        }
+    }
+
+    fn vec_to_inner(&self, v: Vec2) -> Vec2 {
+        match self.rotation {
+            Rotation::None => v,
+            Rotation::Clockwise => Vec2::new(v.y, -v.x),
+            Rotation::UpsideDown => -v,
+            Rotation::Anticlockwise => Vec2::new(-v.y, v.x),
+        }
+    }
+}
@@ -0,0 +1,179 @@
+//! Scenes: two slots that the mix and the loops can be kept in and brought
+//! back from, either straight away or by morphing to them over a number of
+//! bars, e.g. to strip a mix back before a drop and bring it all in on the
+//! one.
+
+use std::collections::BTreeSet;
+
+use crate::{
+    effect::{Chains, EffectConfig},
+    engine::LoopState,
+    eq::Kills,
+};
+
+/// One of the two scene slots.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Slot {
+    A,
+    B,
+}
+
+impl Slot {
+    pub fn index(self) -> usize {
+        match self {
+            Slot::A => 0,
+            Slot::B => 1,
+        }
+    }
+}
+
+/// The mixer settings, effects and loops that are kept in a slot.
+#[derive(Debug, Clone)]
+pub struct Scene {
+    pub volume: f32,
+    pub crossfade: f32,
+    pub filter_cutoff: u32,
+    pub eq: [Kills; 2],
+    pub chains: Chains,
+    pub loops: Vec<LoopState>,
+}
+
+impl Scene {
+    /// The mix `t` of the way from this scene to `to`, from 0.0 to 1.0. The
+    /// levels, the filter and the parameters of effects that are in both
+    /// are blended. The kills, and effects that are only in one of them,
+    /// switch over halfway. The loops are left as they are in this scene.
+    pub fn morph(&self, to: &Scene, t: f32) -> Scene {
+        let t = t.clamp(0., 1.);
+        let halfway = |from, to| if t < 0.5 { from } else { to };
+
+        // the filter is blended in octaves, so that it sweeps evenly
+        let [from_hz, to_hz] =
+            [self.filter_cutoff, to.filter_cutoff].map(|hz| (hz.max(1) as f32).ln());
+
+        Scene {
+            volume: lerp(self.volume, to.volume, t),
+            crossfade: lerp(self.crossfade, to.crossfade, t),
+            filter_cutoff: lerp(from_hz, to_hz, t).exp().round() as u32,
+            eq: halfway(self.eq, to.eq),
+            chains: Chains {
+                a: morph_chain(&self.chains.a, &to.chains.a, t),
+                b: morph_chain(&self.chains.b, &to.chains.b, t),
+            },
+            loops: self.loops.clone(),
+        }
+    }
+}
+
+/// A morph of the mix to the scene in a slot, which the looper moves along
+/// on every tick.
+#[derive(Debug, Clone)]
+pub struct Morph {
+    /// the mix when the morph started
+    pub from: Scene,
+    pub to: Slot,
+    /// the tick that the morph started on
+    pub start: usize,
+    /// how many ticks the morph lasts
+    pub ticks: usize,
+    /// whether the loops have been switched over to the scene's yet
+    pub switched: bool,
+}
+
+impl Morph {
+    /// How far through the morph the given tick is, from 0.0 to 1.0.
+    pub fn progress(&self, tick: usize) -> f32 {
+        (tick.saturating_sub(self.start) as f32 / self.ticks.max(1) as f32).min(1.)
+    }
+}
+
+fn lerp(from: f32, to: f32, t: f32) -> f32 {
+    from + (to - from) * t
+}
+
+/// Blends the parameters of a chain that has the same effects at both ends,
+/// or else switches from one chain to the other halfway.
+fn morph_chain(from: &[EffectConfig], to: &[EffectConfig], t: f32) -> Vec<EffectConfig> {
+    let same = from.len() == to.len() && from.iter().zip(to).all(|(f, t)| f.effect == t.effect);
+
+    if !same {
+        return if t < 0.5 { from } else { to }.to_vec();
+    }
+
+    from.iter()
+        .zip(to)
+        .map(|(from, to)| {
+            let names: BTreeSet<_> = from.params.keys().chain(to.params.keys()).collect();
+
+            let params = names
+                .into_iter()
+                .filter_map(|name| {
+                    let value = match (from.params.get(name), to.params.get(name)) {
+                        (Some(&f), Some(&v)) => lerp(f, v, t),
+                        (Some(&f), None) if t < 0.5 => f,
+                        (None, Some(&v)) if t >= 0.5 => v,
+                        _ => return None,
+                    };
+
+                    Some((name.clone(), value))
+                })
+                .collect();
+
+            EffectConfig {
+                effect: to.effect.clone(),
+                params,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        effect::{Chains, EffectConfig},
+        eq::{Band, Kills},
+    };
+
+    use super::Scene;
+
+    fn scene(volume: f32, cutoff: f32, kills: Kills) -> Scene {
+        Scene {
+            volume,
+            crossfade: 0.5,
+            filter_cutoff: 20_000,
+            eq: [kills, Kills::default()],
+            chains: Chains {
+                a: vec![EffectConfig {
+                    effect: "filter".to_owned(),
+                    params: [("cutoff".to_owned(), cutoff)].into_iter().collect(),
+                }],
+                b: vec![],
+            },
+            loops: vec![],
+        }
+    }
+
+    #[test]
+    fn morph_blends_levels_and_switches_kills_halfway() {
+        let mut kills = Kills::default();
+        kills.toggle(Band::Low);
+
+        let from = scene(1., 200., Kills::default());
+        let mut to = scene(0.5, 1_000., kills);
+        to.filter_cutoff = 200;
+
+        let quarter = from.morph(&to, 0.25);
+        assert_eq!(quarter.volume, 0.875);
+        assert_eq!(quarter.chains.a[0].params["cutoff"], 400.);
+        assert_eq!(quarter.eq[0], Kills::default());
+
+        // the filter sweeps by octaves
+        let half = from.morph(&to, 0.5);
+        assert_eq!(half.filter_cutoff, 2_000);
+        assert_eq!(half.eq[0], kills);
+
+        let end = from.morph(&to, 1.);
+        assert_eq!(end.volume, 0.5);
+        assert_eq!(end.filter_cutoff, 200);
+    }
+}
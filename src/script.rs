@@ -0,0 +1,291 @@
+//! Scripts that add behaviour to the pads without rebuilding the app, e.g. a
+//! game of Simon says or a generative sequence. A script is a
+//! [Rhai](https://rhai.rs) file that defines any of these hooks:
+//!
+//! ```rhai
+//! // once, when the script is loaded
+//! fn on_start() { this.presses = 0; }
+//! // a pad was pressed; y is from 1, since row 0 is the function keys
+//! fn on_pad_press(x, y) { this.presses += 1; }
+//! // the looper reached a beat, from 0 at the start of the bar
+//! fn on_beat(beat) { if beat == 0 { play(0, 1); } }
+//! // a loop played its sound, given by its path in the audio directory
+//! fn on_loop_trigger(sound) { print(sound); }
+//! ```
+//!
+//! `this` is an object map that is kept between hooks. The hooks can call:
+//!
+//! - `play(x, y)`: plays the sound on a pad, with the pad's settings
+//! - `play_sound(path)`: plays a sound by its path in the audio directory
+//! - `set_led(x, y, r, g, b)`: sets the LED under a key, where y is 0 for the
+//!   function keys
+//! - `set_tempo(bpm)`
+//! - `ramp_tempo(bpm, bars)`: glides the tempo to `bpm` over `bars` bars
+//!
+//! What a hook does is carried out once it returns. Hooks are cut short if
+//! they run for too long, so that a script can't hold up the looper.
+
+use std::{
+    collections::BTreeSet,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{anyhow, Context};
+use rhai::{CallFnOptions, Dynamic, EvalAltResult, FuncArgs, Map, Scope, AST};
+use tracing::{debug, info};
+
+use crate::driver::adafruit::seesaw::neopixel::Color;
+
+/// How many operations a hook can run before it is stopped.
+const MAX_OPERATIONS: u64 = 100_000;
+
+/// Something that happened that scripts can react to.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Hook {
+    PadPress {
+        x: usize,
+        y: usize,
+    },
+    Beat(usize),
+    /// A loop played the sound with this path in the audio directory.
+    LoopTrigger(String),
+}
+
+/// What a script asked for.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScriptAction {
+    Play { x: usize, y: usize },
+    PlaySound(String),
+    SetLed { x: usize, y: usize, color: Color },
+    SetBpm(f32),
+    RampBpm { bpm: f32, bars: usize },
+}
+
+pub struct Script {
+    engine: rhai::Engine,
+    ast: AST,
+    /// the hooks that the script defines
+    hooks: BTreeSet<String>,
+    /// bound to `this` in the hooks
+    this: Dynamic,
+    /// what the running hook asked for
+    actions: Arc<Mutex<Vec<ScriptAction>>>,
+}
+
+impl Script {
+    /// Loads the script at `path`, for a keyboard whose grid is `grid_size`
+    /// keys wide and high.
+    pub fn load(path: impl AsRef<Path>, grid_size: (usize, usize)) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let source = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read script {path:?}"))?;
+
+        Self::compile(&source, grid_size).with_context(|| format!("failed to load script {path:?}"))
+    }
+
+    /// Compiles a script, and runs its `on_start` hook. The keys that it can
+    /// play and light up are the ones on a grid of `grid_size`.
+    pub fn compile(source: &str, grid_size: (usize, usize)) -> anyhow::Result<Self> {
+        let actions = Arc::new(Mutex::new(vec![]));
+        let mut engine = rhai::Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        engine.on_print(|text| info!("script: {text}"));
+        engine.on_debug(|text, _, pos| debug!("script at {pos}: {text}"));
+        register_api(&mut engine, &actions, grid_size);
+
+        let ast = engine.compile(source).map_err(|err| anyhow!("{err}"))?;
+        engine.run_ast(&ast).map_err(|err| anyhow!("{err}"))?;
+
+        let hooks = ast.iter_functions().map(|f| f.name.to_string()).collect();
+
+        let mut script = Self {
+            engine,
+            ast,
+            hooks,
+            this: Dynamic::from_map(Map::new()),
+            actions,
+        };
+
+        // whatever on_start asked for is dropped, since there is nothing to
+        // play it on yet
+        script.call("on_start", ())?;
+
+        Ok(script)
+    }
+
+    /// Runs the hook for `hook`, if the script defines it, and returns what
+    /// it asked for.
+    pub fn run(&mut self, hook: Hook) -> anyhow::Result<Vec<ScriptAction>> {
+        match hook {
+            Hook::PadPress { x, y } => self.call("on_pad_press", (x as i64, y as i64)),
+            Hook::Beat(beat) => self.call("on_beat", (beat as i64,)),
+            Hook::LoopTrigger(sound) => self.call("on_loop_trigger", (sound,)),
+        }
+    }
+
+    fn call(&mut self, name: &str, args: impl FuncArgs) -> anyhow::Result<Vec<ScriptAction>> {
+        self.actions.lock().unwrap().clear();
+
+        if !self.hooks.contains(name) {
+            return Ok(vec![]);
+        }
+
+        // the script's top level was already run when it was loaded
+        let options = CallFnOptions::new()
+            .eval_ast(false)
+            .bind_this_ptr(&mut self.this);
+
+        let _ = self
+            .engine
+            .call_fn_with_options::<Dynamic>(options, &mut Scope::new(), &self.ast, name, args)
+            .map_err(|err| anyhow!("{name} failed: {err}"))?;
+
+        Ok(std::mem::take(&mut *self.actions.lock().unwrap()))
+    }
+}
+
+impl std::fmt::Debug for Script {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Script")
+            .field("hooks", &self.hooks)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Registers the functions that scripts can call, which add to `actions`.
+/// Keys outside of a grid of `grid_size` are refused.
+fn register_api(
+    engine: &mut rhai::Engine,
+    actions: &Arc<Mutex<Vec<ScriptAction>>>,
+    grid_size: (usize, usize),
+) {
+    let to_actions = actions.clone();
+    engine.register_fn(
+        "play",
+        move |x: i64, y: i64| -> Result<(), Box<EvalAltResult>> {
+            let (x, y) = key(x, y, 1, grid_size)?;
+            to_actions.lock().unwrap().push(ScriptAction::Play { x, y });
+            Ok(())
+        },
+    );
+
+    let to_actions = actions.clone();
+    engine.register_fn("play_sound", move |path: &str| {
+        to_actions
+            .lock()
+            .unwrap()
+            .push(ScriptAction::PlaySound(path.to_owned()));
+    });
+
+    let to_actions = actions.clone();
+    engine.register_fn(
+        "set_led",
+        move |x: i64, y: i64, r: i64, g: i64, b: i64| -> Result<(), Box<EvalAltResult>> {
+            let (x, y) = key(x, y, 0, grid_size)?;
+            let [r, g, b] = [r, g, b].map(|c| c.clamp(0, 255) as u8);
+            to_actions.lock().unwrap().push(ScriptAction::SetLed {
+                x,
+                y,
+                color: Color::from_u8(r, g, b),
+            });
+            Ok(())
+        },
+    );
+
+    let to_actions = actions.clone();
+    engine.register_fn("set_tempo", move |bpm: f64| {
+        to_actions
+            .lock()
+            .unwrap()
+            .push(ScriptAction::SetBpm(bpm as f32));
+    });
+
+    let to_actions = actions.clone();
+    engine.register_fn("set_tempo", move |bpm: i64| {
+        to_actions
+            .lock()
+            .unwrap()
+            .push(ScriptAction::SetBpm(bpm as f32));
+    });
+
+    let to_actions = actions.clone();
+    engine.register_fn("ramp_tempo", move |bpm: f64, bars: i64| {
+        to_actions.lock().unwrap().push(ScriptAction::RampBpm {
+            bpm: bpm as f32,
+            bars: bars.max(0) as usize,
+        });
+    });
+
+    let to_actions = actions.clone();
+    engine.register_fn("ramp_tempo", move |bpm: i64, bars: i64| {
+        to_actions.lock().unwrap().push(ScriptAction::RampBpm {
+            bpm: bpm as f32,
+            bars: bars.max(0) as usize,
+        });
+    });
+}
+
+/// Checks that (x, y) is a key on a grid of `(width, height)`, with rows from
+/// `min_y`.
+fn key(
+    x: i64,
+    y: i64,
+    min_y: i64,
+    (width, height): (usize, usize),
+) -> Result<(usize, usize), Box<EvalAltResult>> {
+    if (0..width as i64).contains(&x) && (min_y..height as i64).contains(&y) {
+        Ok((x as usize, y as usize))
+    } else {
+        Err(format!("there is no pad at ({x}, {y})").into())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Hook, Script, ScriptAction};
+
+    const GRID: (usize, usize) = (4, 4);
+
+    #[test]
+    fn hooks_keep_state_and_ask_for_actions() {
+        let mut script = Script::compile(
+            r#"
+            fn on_start() { this.presses = 0; }
+            fn on_pad_press(x, y) {
+                this.presses += 1;
+                if this.presses == 2 { play(x, y); set_tempo(120); }
+            }
+            "#,
+            GRID,
+        )
+        .unwrap();
+
+        let press = Hook::PadPress { x: 1, y: 2 };
+        assert_eq!(script.run(press.clone()).unwrap(), vec![]);
+        assert_eq!(
+            script.run(press).unwrap(),
+            vec![
+                ScriptAction::Play { x: 1, y: 2 },
+                ScriptAction::SetBpm(120.)
+            ]
+        );
+
+        // hooks that aren't defined do nothing
+        assert_eq!(script.run(Hook::Beat(0)).unwrap(), vec![]);
+
+        let mut broken = Script::compile("fn on_beat(beat) { play(9, 9); }", GRID).unwrap();
+        assert!(broken.run(Hook::Beat(0)).is_err());
+    }
+
+    #[test]
+    fn keys_are_checked_against_the_grid() {
+        let source = "fn on_beat(beat) { play(7, 7); set_led(7, 0, 255, 0, 0); }";
+
+        let mut small = Script::compile(source, GRID).unwrap();
+        assert!(small.run(Hook::Beat(0)).is_err());
+
+        let mut big = Script::compile(source, (8, 8)).unwrap();
+        assert_eq!(big.run(Hook::Beat(0)).unwrap().len(), 2);
+    }
+}
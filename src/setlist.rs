@@ -0,0 +1,232 @@
+//! Set lists: kits and patterns that are prepared ahead of a gig and stepped
+//! through in order during it.
+//!
+//! A kit is a TOML file that binds sounds to pads, and can set a tempo:
+//!
+//! ```toml
+//! bpm = 122
+//!
+//! [[pads]]
+//! pad = [0, 1]
+//! sound = "Kicks/Deep Kick.wav"
+//! color = [255, 0, 0]
+//!
+//! [[patterns]]
+//! name = "intro"
+//! loops = [{ pad = [0, 1], every = 1 }, { pad = [1, 1], every = 2, offset = 1 }]
+//!
+//! [[macros]]
+//! pad = [3, 3]
+//! name = "drop"
+//! actions = [
+//!     { action = "play", value = "FX/Riser.wav" },
+//!     { action = "bpm", value = 128 },
+//!     { action = "toggle_quantize" },
+//!     { action = "pattern", value = "intro" },
+//! ]
+//! ```
+//!
+//! and a set list is a TOML file of entries, each of which loads a kit, starts
+//! one of its patterns and sets the BPM, or leaves it to the kit:
+//!
+//! ```toml
+//! [[entries]]
+//! name = "Opener"
+//! kit = "kits/house.toml"
+//! pattern = "intro"
+//! bpm = 122
+//! ```
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    effect::{Chains, Registry},
+    util::to_toml,
+};
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct SetEntry {
+    pub name: String,
+    /// kit file to load, relative to the set list. The pads are left as they
+    /// are if there isn't one.
+    pub kit: Option<PathBuf>,
+    /// name of one of the kit's patterns to start. The loops are cleared if
+    /// there isn't one.
+    pub pattern: Option<String>,
+    /// BPM to switch to, or 0 to use the kit's
+    #[serde(default)]
+    pub bpm: f32,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Kit {
+    /// tempo that the kit is played at, which is switched to when it is
+    /// loaded unless [`crate::config::PlayConfig::keep_tempo`] is set
+    pub bpm: Option<f32>,
+    pub pads: Vec<KitPad>,
+    pub macros: Vec<KitMacro>,
+    pub patterns: Vec<Pattern>,
+    /// effects on each bus. See [`crate::effect`].
+    #[serde(skip_serializing_if = "Chains::is_empty")]
+    pub chains: Chains,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KitPad {
+    /// [x, y] of the pad, where y = 1 is the top row of pads
+    pub pad: [usize; 2],
+    /// path of the sound, relative to the audio directory
+    pub sound: PathBuf,
+    /// custom color of the pad, as [r, g, b]
+    pub color: Option<[u8; 3]>,
+}
+
+/// A pad that does several things at once when it is pressed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KitMacro {
+    pub pad: [usize; 2],
+    pub name: String,
+    pub actions: Vec<MacroAction>,
+    pub color: Option<[u8; 3]>,
+}
+
+/// An action of a macro pad. It is a table with the action's name, and its
+/// value if it has one, since TOML can't write an enum that holds a value any
+/// other way.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "action", content = "value", rename_all = "snake_case")]
+pub enum MacroAction {
+    /// Plays a sound, given by its path relative to the audio directory.
+    Play(PathBuf),
+    Bpm(f32),
+    ToggleQuantize,
+    /// Switches the loops to another of the kit's patterns.
+    Pattern(String),
+}
+
+/// Loops to start together, playing the sounds bound to pads.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Pattern {
+    pub name: String,
+    pub loops: Vec<PatternLoop>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PatternLoop {
+    pub pad: [usize; 2],
+    /// period of the loop in beats
+    pub every: f32,
+    /// how many beats into the period the sound plays
+    #[serde(default)]
+    pub offset: f32,
+}
+
+/// A set list, with the kits that its entries use.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SetList {
+    pub entries: Vec<SetEntry>,
+    /// keyed by the path that the entries refer to them by
+    pub kits: BTreeMap<PathBuf, Kit>,
+}
+
+#[derive(Deserialize)]
+struct SetListFile {
+    entries: Vec<SetEntry>,
+}
+
+impl SetList {
+    /// Loads the set list at `path`, and the kits that it uses.
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read set list {path:?}"))?;
+        let file: SetListFile =
+            toml::from_str(&text).with_context(|| format!("failed to parse set list {path:?}"))?;
+
+        let dir = path.parent().unwrap_or(Path::new(""));
+        let mut kits = BTreeMap::new();
+
+        for kit_path in file.entries.iter().filter_map(|e| e.kit.as_ref()) {
+            if !kits.contains_key(kit_path) {
+                kits.insert(kit_path.clone(), Kit::load(dir.join(kit_path))?);
+            }
+        }
+
+        let set_list = Self {
+            entries: file.entries,
+            kits,
+        };
+
+        // catch typos before the gig rather than during it
+        for entry in &set_list.entries {
+            if let (Some(name), (_, None)) = (&entry.pattern, set_list.resolve(entry)) {
+                bail!(
+                    "set list entry {:?} starts pattern {name:?}, which isn't in its kit",
+                    entry.name
+                );
+            }
+        }
+
+        Ok(set_list)
+    }
+
+    /// The kit and pattern that an entry uses, if it has them.
+    pub fn resolve(&self, entry: &SetEntry) -> (Option<&Kit>, Option<&Pattern>) {
+        let kit = entry.kit.as_ref().and_then(|path| self.kits.get(path));
+        let pattern = entry.pattern.as_ref().and_then(|name| kit?.pattern(name));
+
+        (kit, pattern)
+    }
+}
+
+impl Kit {
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read kit {path:?}"))?;
+
+        Self::from_toml(&text).with_context(|| format!("failed to parse kit {path:?}"))
+    }
+
+    /// Parses a kit, checking that the patterns its macros start are in it
+    /// and that the effects in its chains exist.
+    pub fn from_toml(text: &str) -> anyhow::Result<Self> {
+        let kit: Self = toml::from_str(text)?;
+
+        let effects = Registry::builtin();
+
+        for config in kit.chains.a.iter().chain(&kit.chains.b) {
+            effects.check(config)?;
+        }
+
+        for m in &kit.macros {
+            for action in &m.actions {
+                if let MacroAction::Pattern(name) = action {
+                    if kit.pattern(name).is_none() {
+                        bail!(
+                            "macro {:?} starts pattern {name:?}, which isn't in the kit",
+                            m.name
+                        );
+                    }
+                }
+            }
+        }
+
+        Ok(kit)
+    }
+
+    pub fn to_toml(&self) -> anyhow::Result<String> {
+        Ok(to_toml(self)?)
+    }
+
+    pub fn pattern(&self, name: &str) -> Option<&Pattern> {
+        self.patterns.iter().find(|pattern| pattern.name == name)
+    }
+}
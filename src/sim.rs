@@ -0,0 +1,176 @@
+//! Scripts of key presses that `pidj-sim` plays on the virtual keypad, so
+//! that the looper can be put through the same presses on a machine without
+//! a keypad or a sound card, e.g. to reproduce a bug on CI. Times are from
+//! when the sounds have loaded, and keys are (x, y) on the NeoTrellis, so the
+//! Fn keys are on the top row:
+//!
+//! ```yaml
+//! # the first Fn key tapped, and then the first pad held for a quarter of a
+//! # second
+//! steps:
+//!   - at_ms: 0
+//!     tap: [0, 0]
+//!   - at_ms: 500
+//!     press: [0, 1]
+//!   - at_ms: 750
+//!     release: [0, 1]
+//! # how long to keep running after the last step, e.g. for a loop to play
+//! tail_ms: 4000
+//! ```
+
+use std::{path::Path, time::Duration};
+
+use anyhow::{bail, Context};
+use serde::Deserialize;
+
+use crate::{driver::adafruit::seesaw::keypad::Edge, grid::NEOTRELLIS};
+
+/// How long a tapped key is held down for.
+pub const TAP: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Script {
+    pub steps: Vec<Step>,
+    /// how long to keep running after the last step, in milliseconds
+    #[serde(default)]
+    pub tail_ms: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Step {
+    /// milliseconds after the sounds have loaded
+    pub at_ms: u64,
+    #[serde(flatten)]
+    pub key: KeyStep,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyStep {
+    Press([u16; 2]),
+    Release([u16; 2]),
+    /// A press, and a release [`TAP`] after it.
+    Tap([u16; 2]),
+}
+
+impl KeyStep {
+    fn key(self) -> [u16; 2] {
+        match self {
+            Self::Press(key) | Self::Release(key) | Self::Tap(key) => key,
+        }
+    }
+}
+
+/// A key going down or up, some time after the start of a script.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub at: Duration,
+    pub key: (u16, u16),
+    pub edge: Edge,
+}
+
+impl Script {
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read script {path:?}"))?;
+
+        Self::parse(&text).with_context(|| format!("failed to parse script {path:?}"))
+    }
+
+    /// Parses a script, and checks that its keys are on the keypad.
+    pub fn parse(text: &str) -> anyhow::Result<Self> {
+        let script: Self = serde_yaml::from_str(text)?;
+        let (width, height) = NEOTRELLIS;
+
+        for step in &script.steps {
+            let [x, y] = step.key.key();
+
+            if x as usize >= width || y as usize >= height {
+                bail!("key ({x}, {y}) at {}ms is not on the keypad", step.at_ms);
+            }
+        }
+
+        Ok(script)
+    }
+
+    /// The keys going down and up, in the order that they do. Steps at the
+    /// same time stay in the order that they were written in.
+    pub fn events(&self) -> Vec<KeyEvent> {
+        let mut events: Vec<_> = self
+            .steps
+            .iter()
+            .flat_map(|step| {
+                let at = Duration::from_millis(step.at_ms);
+                let [x, y] = step.key.key();
+                let event = |at, edge| KeyEvent {
+                    at,
+                    key: (x, y),
+                    edge,
+                };
+
+                match step.key {
+                    KeyStep::Press(_) => vec![event(at, Edge::Rising)],
+                    KeyStep::Release(_) => vec![event(at, Edge::Falling)],
+                    KeyStep::Tap(_) => {
+                        vec![event(at, Edge::Rising), event(at + TAP, Edge::Falling)]
+                    }
+                }
+            })
+            .collect();
+
+        events.sort_by_key(|event| event.at);
+        events
+    }
+
+    /// How long the script runs for, with the tail after the last step.
+    pub fn duration(&self) -> Duration {
+        let last = self
+            .events()
+            .last()
+            .map_or(Duration::ZERO, |event| event.at);
+        last + Duration::from_millis(self.tail_ms)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{KeyEvent, Script, TAP};
+    use crate::driver::adafruit::seesaw::keypad::Edge;
+
+    #[test]
+    fn scripts_are_played_in_time_order() {
+        let script = Script::parse(
+            "
+steps:
+  - at_ms: 100
+    release: [1, 2]
+  - at_ms: 0
+    press: [1, 2]
+  - at_ms: 100
+    tap: [3, 0]
+tail_ms: 1000
+",
+        )
+        .unwrap();
+
+        let ms = Duration::from_millis;
+        let event = |at, key, edge| KeyEvent { at, key, edge };
+
+        assert_eq!(
+            script.events(),
+            [
+                event(ms(0), (1, 2), Edge::Rising),
+                event(ms(100), (1, 2), Edge::Falling),
+                event(ms(100), (3, 0), Edge::Rising),
+                event(ms(100) + TAP, (3, 0), Edge::Falling),
+            ]
+        );
+        assert_eq!(script.duration(), ms(1100) + TAP);
+
+        assert!(Script::parse("steps: [{ at_ms: 0, tap: [4, 0] }]").is_err());
+    }
+}
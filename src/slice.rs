@@ -0,0 +1,127 @@
+//! Cutting a sound up at its transients, like the hits of a drum break, so
+//! that each hit can be played from its own pad. The transients are found in
+//! the sound's waveform overview, which is quick enough to do again on every
+//! move of the sensitivity slider.
+
+use std::time::Duration;
+
+/// Most slices a sound can be cut into: one for each pad.
+pub const MAX_SLICES: usize = 12;
+
+/// Sensitivity that slicing starts at.
+pub const DEFAULT_SENSITIVITY: f32 = 0.5;
+
+/// Fewest points of the overview that slices can be apart, so that one hit
+/// isn't cut up as it builds.
+const MIN_GAP: usize = 3;
+
+/// How much the level has to jump by, as a fraction of the loudest peak, to
+/// be a transient at the highest sensitivity.
+const NOISE_FLOOR: f32 = 0.02;
+
+/// Where the slices of a sound with the overview `peaks` start, as indices
+/// into `peaks`. The first one is always at the start. Between 0.0 and 1.0, a
+/// higher `sensitivity` finds quieter transients. Only the strongest `max`
+/// are kept.
+pub fn transients(peaks: &[f32], sensitivity: f32, max: usize) -> Vec<usize> {
+    let loudest = peaks.iter().copied().fold(0f32, f32::max);
+
+    if peaks.is_empty() || loudest <= 0. || max == 0 {
+        return vec![0];
+    }
+
+    // how far the level jumped at each point
+    let mut rises: Vec<(usize, f32)> = peaks
+        .windows(2)
+        .enumerate()
+        .map(|(i, pair)| (i + 1, (pair[1] - pair[0]) / loudest))
+        .collect();
+
+    let threshold = (1. - sensitivity.clamp(0., 1.)).max(NOISE_FLOOR);
+    rises.retain(|&(_, rise)| rise >= threshold);
+    rises.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+    let mut slices: Vec<usize> = vec![0];
+
+    for (i, _) in rises {
+        if slices.len() == max {
+            break;
+        }
+
+        if slices.iter().all(|&slice| slice.abs_diff(i) >= MIN_GAP) {
+            slices.push(i);
+        }
+    }
+
+    slices.sort_unstable();
+    slices
+}
+
+/// The start and end of each slice of a sound that is `duration` long. See
+/// [`transients`].
+pub fn slices(
+    peaks: &[f32],
+    duration: Duration,
+    sensitivity: f32,
+    max: usize,
+) -> Vec<(Duration, Duration)> {
+    let points = peaks.len().max(1) as f64;
+    let at = |i: usize| duration.mul_f64(i as f64 / points);
+    let starts = transients(peaks, sensitivity, max);
+
+    starts
+        .iter()
+        .enumerate()
+        .map(|(n, &start)| {
+            let end = starts.get(n + 1).map_or(duration, |&next| at(next));
+            (at(start), end)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{slices, transients};
+
+    /// An overview with hits of the given levels at the given points, which
+    /// die away after them.
+    fn overview(hits: &[(usize, f32)]) -> Vec<f32> {
+        let mut peaks = vec![0.01f32; 64];
+
+        for &(at, level) in hits {
+            for (i, peak) in peaks[at..].iter_mut().enumerate() {
+                *peak = peak.max(level * 0.7f32.powi(i as i32));
+            }
+        }
+
+        peaks
+    }
+
+    #[test]
+    fn sensitivity_finds_quieter_hits() {
+        let peaks = overview(&[(0, 1.), (16, 0.9), (32, 0.3), (48, 0.8)]);
+
+        assert_eq!(transients(&peaks, 0.5, 12), vec![0, 16, 48]);
+        assert_eq!(transients(&peaks, 0.9, 12), vec![0, 16, 32, 48]);
+        assert_eq!(transients(&peaks, 0., 12), vec![0]);
+
+        // only the strongest are kept
+        assert_eq!(transients(&peaks, 0.9, 2), vec![0, 16]);
+    }
+
+    #[test]
+    fn slices_cover_the_sound() {
+        let peaks = overview(&[(0, 1.), (32, 1.)]);
+        let slices = slices(&peaks, Duration::from_secs(2), 0.5, 12);
+
+        assert_eq!(
+            slices,
+            vec![
+                (Duration::ZERO, Duration::from_secs(1)),
+                (Duration::from_secs(1), Duration::from_secs(2)),
+            ]
+        );
+    }
+}
@@ -0,0 +1,375 @@
+//! Reads the title, artist and tempo that sample packs tag their files with:
+//! ID3v2 in MP3s (and in WAVs, as an `id3 ` chunk), Vorbis comments in FLACs,
+//! and RIFF INFO and ACID chunks in WAVs.
+
+use std::{
+    io::{self, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use tracing::debug;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Tags {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub bpm: Option<f32>,
+}
+
+impl Tags {
+    /// Reads the tags of the file at `path`. Files without tags, or whose tags
+    /// can't be read, have none.
+    pub fn read(path: &Path) -> Self {
+        let result = crate::archive::open(path).and_then(|mut reader| {
+            let mut magic = [0; 4];
+            reader.read_exact(&mut magic)?;
+            reader.seek(SeekFrom::Start(0))?;
+
+            match &magic {
+                [b'I', b'D', b'3', _] => read_id3(&mut reader),
+                b"fLaC" => read_flac(&mut reader),
+                b"RIFF" => read_riff(&mut reader),
+                _ => Ok(Self::default()),
+            }
+        });
+
+        match result {
+            Ok(tags) => tags,
+            Err(err) => {
+                debug!("failed to read tags of {path:?}: {err}");
+                Self::default()
+            }
+        }
+    }
+
+    /// Fills in whatever `self` is missing from `other`.
+    fn or(self, other: Self) -> Self {
+        Self {
+            title: self.title.or(other.title),
+            artist: self.artist.or(other.artist),
+            bpm: self.bpm.or(other.bpm),
+        }
+    }
+}
+
+/// Tags with nothing but whitespace in them are left out.
+fn non_empty(text: &str) -> Option<String> {
+    let text = text.trim_matches(|c: char| c == '\0' || c.is_whitespace());
+    (!text.is_empty()).then(|| text.to_owned())
+}
+
+fn parse_bpm(text: &str) -> Option<f32> {
+    text.trim_matches(|c: char| c == '\0' || c.is_whitespace())
+        .parse()
+        .ok()
+        .filter(|bpm: &f32| *bpm > 0.)
+}
+
+fn read_bytes(reader: &mut impl Read, len: usize) -> io::Result<Vec<u8>> {
+    let mut bytes = vec![0; len];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// ID3v2 sizes use 7 bits per byte, so that they never look like an MPEG
+/// sync word.
+fn syncsafe(bytes: &[u8]) -> usize {
+    bytes.iter().fold(0, |n, &b| n << 7 | (b & 0x7f) as usize)
+}
+
+fn big_endian(bytes: &[u8]) -> usize {
+    bytes.iter().fold(0, |n, &b| n << 8 | b as usize)
+}
+
+fn read_id3(reader: &mut impl Read) -> io::Result<Tags> {
+    let header = read_bytes(reader, 10)?;
+    let version = header[3];
+    let flags = header[5];
+    let tag = read_bytes(reader, syncsafe(&header[6..10]))?;
+
+    Ok(parse_id3_frames(&tag, version, flags))
+}
+
+fn parse_id3_frames(tag: &[u8], version: u8, flags: u8) -> Tags {
+    let mut tags = Tags::default();
+
+    // ID3v2.2 has 3-character frame IDs and sizes, and no frame flags
+    let (id_len, size_len, header_len) = match version {
+        2 => (3, 3, 6),
+        _ => (4, 4, 10),
+    };
+
+    let mut pos = 0;
+
+    // skip the extended header, whose size is counted differently in v2.3
+    if flags & 0x40 != 0 && version >= 3 && tag.len() >= 4 {
+        pos = match version {
+            3 => big_endian(&tag[..4]) + 4,
+            _ => syncsafe(&tag[..4]),
+        };
+    }
+
+    while pos + header_len <= tag.len() {
+        let id = &tag[pos..pos + id_len];
+
+        // the rest of the tag is padding
+        if id[0] == 0 {
+            break;
+        }
+
+        let size_bytes = &tag[pos + id_len..pos + id_len + size_len];
+        let size = match version {
+            4 => syncsafe(size_bytes),
+            _ => big_endian(size_bytes),
+        };
+
+        let start = pos + header_len;
+        let Some(body) = tag.get(start..start + size) else {
+            break;
+        };
+        pos = start + size;
+
+        let text = || id3_text(body);
+
+        match id {
+            b"TIT2" | b"TT2" => {
+                tags.title = tags.title.or_else(|| text().as_deref().and_then(non_empty))
+            }
+            b"TPE1" | b"TP1" => {
+                tags.artist = tags
+                    .artist
+                    .or_else(|| text().as_deref().and_then(non_empty))
+            }
+            b"TBPM" | b"TBP" => {
+                tags.bpm = tags.bpm.or_else(|| text().as_deref().and_then(parse_bpm))
+            }
+            _ => {}
+        }
+    }
+
+    tags
+}
+
+/// Decodes the body of an ID3v2 text frame. Frames with several values
+/// separated by nulls give the first one.
+fn id3_text(body: &[u8]) -> Option<String> {
+    let (&encoding, text) = body.split_first()?;
+
+    let text = match encoding {
+        // ISO-8859-1, whose code points are the same as Unicode's
+        0 => text.iter().map(|&b| b as char).collect(),
+        // UTF-16 with a byte order mark, or big-endian without one
+        1 | 2 => {
+            let big_endian = encoding == 2 || text.starts_with(&[0xfe, 0xff]);
+            let units: Vec<u16> = text
+                .chunks_exact(2)
+                .map(|pair| match big_endian {
+                    true => u16::from_be_bytes([pair[0], pair[1]]),
+                    false => u16::from_le_bytes([pair[0], pair[1]]),
+                })
+                .skip_while(|&unit| unit == 0xfeff)
+                .collect();
+
+            String::from_utf16_lossy(&units)
+        }
+        _ => String::from_utf8_lossy(text).into_owned(),
+    };
+
+    text.split('\0').next().map(str::to_owned)
+}
+
+fn read_flac(reader: &mut (impl Read + Seek)) -> io::Result<Tags> {
+    reader.seek(SeekFrom::Start(4))?;
+
+    loop {
+        let header = read_bytes(reader, 4)?;
+        let last = header[0] & 0x80 != 0;
+        let len = big_endian(&header[1..4]);
+
+        if header[0] & 0x7f == 4 {
+            let block = read_bytes(reader, len)?;
+            return Ok(parse_vorbis_comments(&block).unwrap_or_default());
+        }
+
+        if last {
+            return Ok(Tags::default());
+        }
+
+        reader.seek(SeekFrom::Current(len as i64))?;
+    }
+}
+
+/// Parses a Vorbis comment block, whose lengths are little-endian unlike the
+/// rest of FLAC. Returns None if it is cut short.
+fn parse_vorbis_comments(block: &[u8]) -> Option<Tags> {
+    let mut tags = Tags::default();
+    let mut pos = 0;
+
+    let mut next = |len: usize| {
+        let bytes = block.get(pos..pos + len)?;
+        pos += len;
+        Some(bytes)
+    };
+
+    let u32_le = |bytes: &[u8]| u32::from_le_bytes(bytes.try_into().unwrap()) as usize;
+
+    let vendor_len = u32_le(next(4)?);
+    next(vendor_len)?;
+
+    for _ in 0..u32_le(next(4)?) {
+        let len = u32_le(next(4)?);
+        let comment = String::from_utf8_lossy(next(len)?);
+        let Some((key, value)) = comment.split_once('=') else {
+            continue;
+        };
+
+        match key.to_ascii_uppercase().as_str() {
+            "TITLE" => tags.title = tags.title.or_else(|| non_empty(value)),
+            "ARTIST" => tags.artist = tags.artist.or_else(|| non_empty(value)),
+            "BPM" | "TEMPO" => tags.bpm = tags.bpm.or_else(|| parse_bpm(value)),
+            _ => {}
+        }
+    }
+
+    Some(tags)
+}
+
+fn read_riff(reader: &mut (impl Read + Seek)) -> io::Result<Tags> {
+    let header = read_bytes(reader, 12)?;
+
+    if &header[8..12] != b"WAVE" {
+        return Ok(Tags::default());
+    }
+
+    let mut tags = Tags::default();
+
+    loop {
+        let mut chunk = [0; 8];
+
+        match reader.read_exact(&mut chunk) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(err),
+        }
+
+        let len = u32::from_le_bytes(chunk[4..8].try_into().unwrap()) as usize;
+
+        match &chunk[..4] {
+            b"LIST" => {
+                let body = read_bytes(reader, len)?;
+
+                if body.starts_with(b"INFO") {
+                    tags = tags.or(parse_riff_info(&body[4..]));
+                }
+            }
+            b"acid" => {
+                let body = read_bytes(reader, len)?;
+
+                // tempo is the last field of the chunk, as an f32
+                if let Some(bytes) = body.get(20..24) {
+                    let bpm = f32::from_le_bytes(bytes.try_into().unwrap());
+                    tags.bpm = tags.bpm.or(Some(bpm).filter(|bpm| *bpm > 0.));
+                }
+            }
+            b"id3 " | b"ID3 " => {
+                let body = read_bytes(reader, len)?;
+                tags = tags.or(read_id3(&mut &body[..])?);
+            }
+            _ => {
+                reader.seek(SeekFrom::Current(len as i64))?;
+            }
+        }
+
+        // chunks are padded to an even length
+        reader.seek(SeekFrom::Current(len as i64 % 2))?;
+    }
+
+    Ok(tags)
+}
+
+fn parse_riff_info(mut body: &[u8]) -> Tags {
+    let mut tags = Tags::default();
+
+    while body.len() >= 8 {
+        let id = &body[..4];
+        let len = u32::from_le_bytes(body[4..8].try_into().unwrap()) as usize;
+        let Some(value) = body.get(8..8 + len) else {
+            break;
+        };
+        let value = String::from_utf8_lossy(value);
+
+        match id {
+            b"INAM" => tags.title = tags.title.or_else(|| non_empty(&value)),
+            b"IART" => tags.artist = tags.artist.or_else(|| non_empty(&value)),
+            _ => {}
+        }
+
+        body = body.get(8 + len + len % 2..).unwrap_or_default();
+    }
+
+    tags
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn id3_frame(id: &[u8], text: &str) -> Vec<u8> {
+        let mut frame = id.to_vec();
+        frame.extend_from_slice(&(text.len() as u32 + 1).to_be_bytes());
+        frame.extend_from_slice(&[0, 0, 3]);
+        frame.extend_from_slice(text.as_bytes());
+        frame
+    }
+
+    #[test]
+    fn read_id3_in_riff() {
+        let mut frames = [
+            id3_frame(b"TIT2", "Warm Pad"),
+            id3_frame(b"TPE1", "Someone"),
+            id3_frame(b"TBPM", "124"),
+        ]
+        .concat();
+        frames.extend_from_slice(&[0; 10]);
+
+        let mut id3 = b"ID3\x03\x00\x00".to_vec();
+        id3.extend((0..4).rev().map(|i| (frames.len() >> (7 * i)) as u8 & 0x7f));
+        id3.extend_from_slice(&frames);
+
+        let mut info = b"INFO".to_vec();
+        info.extend_from_slice(b"INAM\x05\x00\x00\x00Other\x00");
+
+        let mut wav = b"RIFF\x00\x00\x00\x00WAVE".to_vec();
+        wav.extend_from_slice(b"data\x03\x00\x00\x00\x01\x02\x03\x00");
+        wav.extend_from_slice(b"LIST");
+        wav.extend_from_slice(&(info.len() as u32).to_le_bytes());
+        wav.extend_from_slice(&info);
+        wav.extend_from_slice(b"id3 ");
+        wav.extend_from_slice(&(id3.len() as u32).to_le_bytes());
+        wav.extend_from_slice(&id3);
+
+        let tags = read_riff(&mut io::Cursor::new(wav)).unwrap();
+
+        // the INFO chunk came first
+        assert_eq!(tags.title.as_deref(), Some("Other"));
+        assert_eq!(tags.artist.as_deref(), Some("Someone"));
+        assert_eq!(tags.bpm, Some(124.));
+    }
+
+    #[test]
+    fn read_vorbis_comments() {
+        let field = |text: &str| [&(text.len() as u32).to_le_bytes()[..], text.as_bytes()].concat();
+        let block = [
+            field("vendor"),
+            2u32.to_le_bytes().to_vec(),
+            field("title=Bassline"),
+            field("BPM=90.5"),
+        ]
+        .concat();
+
+        let tags = parse_vorbis_comments(&block).unwrap();
+        assert_eq!(tags.title.as_deref(), Some("Bassline"));
+        assert_eq!(tags.artist, None);
+        assert_eq!(tags.bpm, Some(90.5));
+    }
+}
@@ -0,0 +1,235 @@
+//! Taping the master mix to disk, to keep a whole rehearsal. The tape can be
+//! split into a new file, or take, whenever a pattern starts or the looper
+//! starts, so that it comes out in pieces named after when they started and
+//! what was playing.
+
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info};
+
+use crate::render::{pcm16, wav_header};
+
+/// When a tape starts a new take.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TapeSplit {
+    /// The tape is one file from start to finish.
+    #[default]
+    Never,
+    /// Whenever a pattern or a set list entry is started.
+    Pattern,
+    /// Whenever the looper is started.
+    Transport,
+}
+
+/// What the writer of a tape is told.
+#[derive(Debug)]
+pub enum TapeMessage {
+    /// Finishes the take that is being written, if there is one, and starts
+    /// one at this path.
+    Take(PathBuf),
+    /// Interleaved stereo samples of the master mix.
+    Samples(Vec<f32>),
+    /// Finishes the take that is being written.
+    Stop,
+}
+
+/// Writes the takes of a tape as the messages come in, until the sender is
+/// dropped. Samples that come when there isn't a take are dropped. A take is
+/// given up on when it can't be written to, and the error is passed to
+/// `failed`.
+pub fn write(
+    rx: flume::Receiver<TapeMessage>,
+    sample_rate: u32,
+    mut failed: impl FnMut(&Path, io::Error),
+) {
+    let mut take: Option<(PathBuf, WavWriter)> = None;
+
+    for message in rx.iter() {
+        match message {
+            TapeMessage::Take(path) => {
+                finish(take.take(), &mut failed);
+
+                match WavWriter::create(&path, sample_rate) {
+                    Ok(writer) => {
+                        info!("taping the master mix to {path:?}");
+                        take = Some((path, writer));
+                    }
+                    Err(err) => failed(&path, err),
+                }
+            }
+            TapeMessage::Samples(samples) => {
+                let Some((path, writer)) = &mut take else {
+                    continue;
+                };
+
+                if let Err(err) = writer.write(&samples) {
+                    failed(path, err);
+                    take = None;
+                }
+            }
+            TapeMessage::Stop => finish(take.take(), &mut failed),
+        }
+    }
+
+    finish(take, &mut failed);
+}
+
+fn finish(take: Option<(PathBuf, WavWriter)>, failed: &mut impl FnMut(&Path, io::Error)) {
+    let Some((path, writer)) = take else { return; };
+
+    match writer.finish() {
+        Ok(()) => debug!("finished take {path:?}"),
+        Err(err) => failed(&path, err),
+    }
+}
+
+/// The name of a take that started at `time`, in UTC, while `pattern` was
+/// playing, e.g. `2023-11-14 22.13.20 Intro`.
+pub fn take_name(time: SystemTime, pattern: Option<&str>) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let (year, month, day) = civil_date(secs / 86_400);
+    let secs = secs % 86_400;
+
+    let mut name = format!(
+        "{year}-{month:02}-{day:02} {:02}.{:02}.{:02}",
+        secs / 3_600,
+        secs / 60 % 60,
+        secs % 60
+    );
+
+    if let Some(pattern) = pattern {
+        // patterns are named by people, not for file systems
+        let pattern: String = pattern
+            .chars()
+            .map(|c| match c {
+                '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '-',
+                c => c,
+            })
+            .collect();
+
+        name.push(' ');
+        name.push_str(pattern.trim());
+    }
+
+    name
+}
+
+/// The year, month and day that is `days` after 1970-01-01, in the proleptic
+/// Gregorian calendar.
+fn civil_date(days: u64) -> (u64, u64, u64) {
+    // eras of 400 years, starting on 0000-03-01, so that leap days come last
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + (month <= 2) as u64;
+
+    (year, month, day)
+}
+
+/// Writes a 16-bit stereo WAV file a block of samples at a time, and fills in
+/// its length when it is finished.
+pub struct WavWriter {
+    file: BufWriter<File>,
+    /// bytes of samples that have been written
+    data_len: u32,
+}
+
+impl WavWriter {
+    /// Creates the file at `path`, and the directory that it is in.
+    pub fn create(path: &Path, sample_rate: u32) -> io::Result<Self> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(&wav_header(0, sample_rate))?;
+
+        Ok(Self { file, data_len: 0 })
+    }
+
+    pub fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+        for &sample in samples {
+            self.file.write_all(&pcm16(sample).to_le_bytes())?;
+        }
+
+        // a WAV file can't say that it is any longer than this
+        self.data_len = self.data_len.saturating_add(samples.len() as u32 * 2);
+        Ok(())
+    }
+
+    /// Fills in the length of the file, and writes out what is left of it.
+    pub fn finish(mut self) -> io::Result<()> {
+        self.file.seek(SeekFrom::Start(4))?;
+        self.file
+            .write_all(&self.data_len.saturating_add(36).to_le_bytes())?;
+        self.file.seek(SeekFrom::Start(40))?;
+        self.file.write_all(&self.data_len.to_le_bytes())?;
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::{take_name, write, TapeMessage};
+    use crate::util::TempDir;
+
+    #[test]
+    fn takes_are_written_to_their_own_files() {
+        let dir = TempDir::new("tape");
+        let (tx, rx) = flume::unbounded();
+
+        // samples from before the first take are dropped
+        tx.send(TapeMessage::Samples(vec![1.; 4])).unwrap();
+        tx.send(TapeMessage::Take(dir.join("a.wav"))).unwrap();
+        tx.send(TapeMessage::Samples(vec![0.5, -0.5])).unwrap();
+        tx.send(TapeMessage::Take(dir.join("b.wav"))).unwrap();
+        tx.send(TapeMessage::Samples(vec![0.; 4])).unwrap();
+        tx.send(TapeMessage::Samples(vec![0.; 2])).unwrap();
+        tx.send(TapeMessage::Stop).unwrap();
+        drop(tx);
+
+        write(rx, 44_100, |path, err| panic!("{path:?}: {err}"));
+
+        for (name, data_len) in [("a.wav", 4), ("b.wav", 12)] {
+            let bytes = std::fs::read(dir.join(name)).unwrap();
+            assert_eq!(bytes.len(), 44 + data_len);
+            assert_eq!(
+                u32::from_le_bytes(bytes[40..44].try_into().unwrap()),
+                data_len as u32
+            );
+        }
+    }
+
+    #[test]
+    fn takes_are_named_by_time_and_pattern() {
+        let time = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+
+        assert_eq!(take_name(time, None), "2023-11-14 22.13.20");
+        assert_eq!(
+            take_name(time, Some("Intro/Verse")),
+            "2023-11-14 22.13.20 Intro-Verse"
+        );
+        assert_eq!(
+            take_name(UNIX_EPOCH + Duration::from_secs(951_782_400), None),
+            "2000-02-29 00.00.00"
+        );
+    }
+}
@@ -0,0 +1,95 @@
+//! Looks of the UI. Each [`Theme`] that can be picked in the config is
+//! described by a [`ThemeStyle`], which is turned into an egui style.
+
+use egui::style::Margin;
+use egui::{Color32, Vec2};
+
+use pidj::config::Theme;
+
+/// Colours and sizing of a theme. Colours that aren't set come from egui's
+/// dark or light visuals.
+pub struct ThemeStyle {
+    pub dark: bool,
+    /// Multiplies the display scale, so that everything (including text) is
+    /// drawn bigger.
+    pub zoom: f32,
+    pub text: Option<Color32>,
+    pub background: Option<Color32>,
+    pub selection: Option<Color32>,
+}
+
+const DARK: ThemeStyle = ThemeStyle {
+    dark: true,
+    zoom: 1.,
+    text: None,
+    background: None,
+    selection: None,
+};
+
+/// Big, white-on-black and bright yellow, so that it can be read from a
+/// standing position under stage lights.
+const STAGE: ThemeStyle = ThemeStyle {
+    dark: true,
+    zoom: 1.5,
+    text: Some(Color32::WHITE),
+    background: Some(Color32::BLACK),
+    selection: Some(Color32::from_rgb(255, 210, 0)),
+};
+
+const LIGHT: ThemeStyle = ThemeStyle {
+    dark: false,
+    zoom: 1.,
+    text: None,
+    background: None,
+    selection: None,
+};
+
+impl ThemeStyle {
+    pub fn of(theme: Theme) -> &'static ThemeStyle {
+        match theme {
+            Theme::Dark => &DARK,
+            Theme::Stage => &STAGE,
+            Theme::Light => &LIGHT,
+        }
+    }
+
+    fn visuals(&self) -> egui::Visuals {
+        let mut visuals = if self.dark {
+            egui::Visuals::dark()
+        } else {
+            egui::Visuals::light()
+        };
+
+        visuals.override_text_color = self.text;
+
+        if let Some(background) = self.background {
+            visuals.panel_fill = background;
+            visuals.window_fill = background;
+            visuals.extreme_bg_color = background;
+        }
+
+        if let Some(selection) = self.selection {
+            visuals.selection.bg_fill = selection;
+            visuals.selection.stroke.color = Color32::BLACK;
+        }
+
+        visuals
+    }
+}
+
+/// Applies a theme to the whole UI. `scale` is the display scale that the
+/// theme's zoom is applied on top of.
+pub fn apply(ctx: &egui::Context, theme: Theme, scale: f32) {
+    let style = ThemeStyle::of(theme);
+
+    ctx.set_pixels_per_point(scale * style.zoom);
+    ctx.set_style(egui::Style {
+        spacing: egui::style::Spacing {
+            window_margin: Margin::same(0.0),
+            item_spacing: Vec2::new(1.0, 1.0),
+            ..Default::default()
+        },
+        visuals: style.visuals(),
+        ..Default::default()
+    });
+}
@@ -0,0 +1,54 @@
+//! Trigger output on one of the Pi's GPIO pins, which pulses in time with the
+//! looper so that modular synths and drum machines with a sync input can
+//! follow pidj without MIDI.
+//!
+//! The Pi's pins are 3.3V, which most sync inputs read as high. Inputs that
+//! need 5V should be driven through a transistor or a level shifter.
+
+use std::time::Duration;
+
+use anyhow::Context;
+use rppal::gpio::Gpio;
+use tokio_util::sync::CancellationToken;
+use tracing::debug;
+
+use crate::config::TriggerOutConfig;
+
+/// Sends a pulse on the pin every time `()` is received on `pulse_rx`, until
+/// cancelled.
+pub fn run(
+    ct: CancellationToken,
+    config: TriggerOutConfig,
+    pulse_rx: flume::Receiver<()>,
+) -> anyhow::Result<()> {
+    let mut pin = Gpio::new()
+        .context("failed to open GPIO")?
+        .get(config.pin)
+        .with_context(|| format!("failed to open GPIO pin {}", config.pin))?
+        .into_output_low();
+
+    let pulse = Duration::from_millis(config.pulse_ms);
+
+    debug!("running trigger output loop");
+
+    while !ct.is_cancelled() {
+        // wake up now and then to check whether the app is exiting
+        match pulse_rx.recv_timeout(Duration::from_millis(100)) {
+            Ok(()) => {
+                pin.set_high();
+                std::thread::sleep(pulse);
+                pin.set_low();
+
+                // pulses that were due while this one was high are dropped,
+                // rather than sent late
+                while pulse_rx.try_recv().is_ok() {}
+            }
+            Err(flume::RecvTimeoutError::Timeout) => {}
+            Err(flume::RecvTimeoutError::Disconnected) => break,
+        }
+    }
+
+    debug!("trigger output task exited");
+
+    Ok(())
+}
@@ -3,43 +3,189 @@ use std::{
     time::{Duration, Instant},
 };
 
+use serde::Serialize;
+
+/// Ticks on a fixed schedule of deadlines, so that time spent between ticks
+/// doesn't shift the phase of the loop that is waiting on it.
+///
+/// If a tick is late by more than a whole period, the ticks that were missed
+/// are dropped and the next tick is at the next deadline on the schedule, like
+/// tokio's `MissedTickBehavior::Skip`. Bursting through the missed ticks would
+/// only mean polling the hardware several times in a row.
 pub struct Interval {
-    last_tick: Instant,
+    next_tick: Instant,
     period: Duration,
 }
 
 impl Interval {
+    /// Creates an interval whose first tick is one period from now.
     pub fn new(period: Duration) -> Self {
         Self {
-            last_tick: Instant::now(),
+            next_tick: Instant::now() + period,
             period,
         }
     }
 
+    /// Sleeps until the next deadline.
     pub fn tick(&mut self) {
-        let current_tick = Instant::now();
-        let last_tick_duration = current_tick - self.last_tick;
-        self.last_tick = current_tick;
+        let wait = self.schedule(Instant::now());
 
-        if last_tick_duration < self.period {
-            std::thread::sleep(self.period - last_tick_duration);
+        if !wait.is_zero() {
+            std::thread::sleep(wait);
         }
     }
+
+    /// Moves the schedule on as if [`Interval::tick`] was called at `now`, and
+    /// returns how long it should sleep for.
+    fn schedule(&mut self, now: Instant) -> Duration {
+        let deadline = self.next_tick;
+        let wait = deadline.saturating_duration_since(now);
+
+        // when this tick actually happens, and how many deadlines have passed
+        // since the one it was meant to happen at
+        let ticked = now.max(deadline);
+        let missed = (ticked - deadline).as_nanos() / self.period.as_nanos();
+
+        self.next_tick = deadline + self.period * (missed + 1) as u32;
+
+        wait
+    }
 }
 
 /// Computes the intersection of two paths (finds the longest shared segment at
 /// the beginning of the paths).
 pub fn path_intersection(left: impl AsRef<Path>, right: impl AsRef<Path>) -> PathBuf {
     left.as_ref()
-        .into_iter()
-        .zip(right.as_ref().into_iter())
+        .iter()
+        .zip(right.as_ref())
         .map_while(|(l, r)| if l == r { Some(l) } else { None })
         .collect()
 }
 
+/// Hashes a name to a hue in degrees, which is the same every run. Similar
+/// names get very different hues.
+pub fn hue_hash(name: &str) -> f32 {
+    // FNV-1a, since std's hasher may change between releases
+    let hash = name.bytes().fold(0x811c9dc5u32, |hash, b| {
+        (hash ^ b as u32).wrapping_mul(0x01000193)
+    });
+
+    (hash % 360) as f32
+}
+
+/// Scores how well `query` fuzzy-matches `candidate`. Every character of the
+/// query has to appear in the candidate in order, ignoring case and spaces in
+/// the query. Runs of consecutive characters and matches at the start of words
+/// score higher, and characters skipped between matches score lower. Returns
+/// None if the query doesn't match.
+pub fn fuzzy_score(query: &str, candidate: &str) -> Option<i32> {
+    let mut query = query
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .flat_map(char::to_lowercase)
+        .peekable();
+
+    let mut score = 0;
+    let mut started = false;
+    let mut prev: Option<char> = None;
+    let mut prev_matched = false;
+
+    for c in candidate.chars() {
+        let Some(&q) = query.peek() else {
+            break;
+        };
+
+        if c.to_lowercase().next() == Some(q) {
+            query.next();
+            score += 1;
+
+            if prev_matched {
+                score += 4;
+            }
+
+            let word_start = match prev {
+                Some(prev) => !prev.is_alphanumeric() || (prev.is_lowercase() && c.is_uppercase()),
+                None => true,
+            };
+
+            if word_start {
+                score += 3;
+            }
+
+            started = true;
+            prev_matched = true;
+        } else {
+            if started {
+                score -= 1;
+            }
+
+            prev_matched = false;
+        }
+
+        prev = Some(c);
+    }
+
+    query.peek().is_none().then_some(score)
+}
+
+/// Serializes `value` as TOML. It goes through a [`toml::Value`] first, which
+/// puts plain values before tables, as the serializer needs.
+pub fn to_toml<T: Serialize + ?Sized>(value: &T) -> Result<String, toml::ser::Error> {
+    toml::Value::try_from(value).and_then(|value| toml::to_string_pretty(&value))
+}
+
+/// An empty directory for a test to write files in, which is removed when it
+/// is dropped, even if the test fails.
+#[cfg(test)]
+pub struct TempDir(PathBuf);
+
+#[cfg(test)]
+impl TempDir {
+    /// Creates the directory, named after `name` and the test process so
+    /// that tests running at the same time don't share it.
+    pub fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("pidj-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        Self(dir)
+    }
+}
+
+#[cfg(test)]
+impl std::ops::Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
 #[cfg(test)]
 mod test {
     use std::path::PathBuf;
+    use std::time::{Duration, Instant};
+
+    use super::Interval;
+
+    const PERIOD: Duration = Duration::from_millis(10);
+
+    fn interval() -> (Interval, Instant) {
+        let start = Instant::now();
+
+        let interval = Interval {
+            next_tick: start + PERIOD,
+            period: PERIOD,
+        };
+
+        (interval, start)
+    }
 
     #[test]
     fn path_intersection() {
@@ -50,4 +196,59 @@ mod test {
         let e = PathBuf::from("/home/pi/audio/Cymatics - Lofi Starter Pack/Claps");
         assert_eq!(o, e);
     }
+
+    #[test]
+    fn interval_keeps_phase_after_small_overrun() {
+        let (mut interval, start) = interval();
+        let ms = Duration::from_millis;
+
+        assert_eq!(interval.schedule(start), PERIOD);
+
+        // the loop body took 3ms, so the next tick is only 7ms away
+        assert_eq!(interval.schedule(start + ms(13)), ms(7));
+        assert_eq!(interval.next_tick, start + ms(30));
+
+        // late by less than a period: no wait, but the schedule is kept
+        assert_eq!(interval.schedule(start + ms(35)), Duration::ZERO);
+        assert_eq!(interval.next_tick, start + ms(40));
+    }
+
+    #[test]
+    fn interval_skips_missed_ticks() {
+        let (mut interval, start) = interval();
+        let ms = Duration::from_millis;
+
+        assert_eq!(interval.schedule(start + ms(35)), Duration::ZERO);
+        assert_eq!(interval.next_tick, start + ms(40));
+        assert_eq!(interval.schedule(start + ms(36)), ms(4));
+    }
+
+    #[test]
+    fn hue_hash() {
+        use super::hue_hash;
+
+        assert_eq!(hue_hash("Kicks"), hue_hash("Kicks"));
+        assert_ne!(hue_hash("Kicks"), hue_hash("Snares"));
+        assert!((0. ..360.).contains(&hue_hash("FX")));
+    }
+
+    #[test]
+    fn fuzzy_score() {
+        use super::fuzzy_score;
+
+        assert_eq!(fuzzy_score("xyz", "Old Clap"), None);
+        assert_eq!(fuzzy_score("palc", "Old Clap"), None);
+        assert!(fuzzy_score("", "Old Clap").is_some());
+
+        // a run at the start of a word beats the same letters spread out
+        let together = fuzzy_score("clap", "Old Clap").unwrap();
+        let spread = fuzzy_score("clap", "Cool Lamp Pad").unwrap();
+        assert!(together > spread, "{together} <= {spread}");
+
+        // case and spaces in the query don't matter
+        assert_eq!(
+            fuzzy_score("old clap", "Old Clap"),
+            fuzzy_score("OLDCLAP", "Old Clap")
+        );
+    }
 }
@@ -0,0 +1,301 @@
+use std::time::{Duration, Instant};
+
+use pidj::{
+    config::KeyboardConfig,
+    driver::{
+        adafruit::seesaw::{
+            eeprom,
+            keypad::{self, Edge},
+            neopixel::{self, Color},
+            neotrellis::{self, KeyEvent},
+            status,
+        },
+        mock::{MockI2c, RegisterWrite},
+    },
+    grid::Grid,
+    keyboard::{self, Command, DeviceSettings, Event, PixelState, Priority},
+};
+use tokio_util::sync::CancellationToken;
+
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+struct Harness {
+    ct: CancellationToken,
+    i2c: MockI2c,
+    cmd_tx: flume::Sender<Command>,
+    evt_rx: flume::Receiver<Event>,
+    join: std::thread::JoinHandle<anyhow::Result<()>>,
+}
+
+impl Harness {
+    fn start() -> Self {
+        Self::start_with(MockI2c::new())
+    }
+
+    /// Starts the keyboard on `i2c`, which can have registers set up first.
+    fn start_with(i2c: MockI2c) -> Self {
+        let ct = CancellationToken::new();
+        let (cmd_tx, cmd_rx) = flume::unbounded();
+        let (evt_tx, evt_rx) = flume::unbounded();
+        let (problem_tx, _) = flume::unbounded();
+
+        let config = KeyboardConfig {
+            address: Some(neotrellis::DEFAULT_ADDRESS),
+            ..Default::default()
+        };
+
+        let join = std::thread::spawn({
+            let ct = ct.clone();
+            let i2c = i2c.clone();
+            move || keyboard::run_with_bus(ct, config, i2c, cmd_rx, evt_tx, problem_tx)
+        });
+
+        Self {
+            ct,
+            i2c,
+            cmd_tx,
+            evt_rx,
+            join,
+        }
+    }
+
+    /// Waits until the keyboard has written a register write matching `pred`,
+    /// and returns the writes up to and including it.
+    fn wait_for_write(&self, pred: impl Fn(&RegisterWrite) -> bool) -> Vec<RegisterWrite> {
+        let start = Instant::now();
+        let mut writes = vec![];
+
+        while start.elapsed() < TIMEOUT {
+            for write in self.i2c.take_writes() {
+                let found = pred(&write);
+                writes.push(write);
+
+                if found {
+                    return writes;
+                }
+            }
+
+            std::thread::sleep(Duration::from_millis(5));
+        }
+
+        panic!("timed out waiting for register write, got {writes:#04x?}");
+    }
+
+    fn stop(self) {
+        self.ct.cancel();
+        self.join.join().unwrap().unwrap();
+    }
+}
+
+fn is_show(write: &RegisterWrite) -> bool {
+    write.base == neopixel::BASE && write.function == neopixel::functions::SHOW
+}
+
+#[test]
+fn set_state_writes_pixel_and_shows() {
+    let harness = Harness::start();
+
+    // wait for the initial frame to be drawn
+    harness.wait_for_write(is_show);
+
+    harness
+        .cmd_tx
+        .send(Command::SetState {
+            x: 1,
+            y: 2,
+            state: PixelState::Solid {
+                color: Color::from_u8(10, 20, 30),
+                update: true,
+            },
+            priority: Priority::Urgent,
+        })
+        .unwrap();
+
+    let writes = harness
+        .wait_for_write(|w| w.base == neopixel::BASE && w.function == neopixel::functions::BUF);
+
+    // pixel 9 at 3 bytes per pixel, in GRB order
+    let buf = writes.last().unwrap();
+    assert_eq!(buf.address, neotrellis::DEFAULT_ADDRESS);
+    assert_eq!(buf.data, vec![0, 27, 20, 10, 30]);
+
+    harness.wait_for_write(is_show);
+
+    harness.stop();
+}
+
+#[test]
+fn set_frame_draws_every_pixel_before_showing() {
+    let harness = Harness::start();
+    harness.wait_for_write(is_show);
+
+    let state = PixelState::Solid {
+        color: Color::from_u8(10, 20, 30),
+        update: true,
+    };
+    harness
+        .cmd_tx
+        .send(Command::SetFrame(Grid::from_fn(4, 4, |_, _| state)))
+        .unwrap();
+
+    let writes = harness.wait_for_write(is_show);
+    let bufs = writes
+        .iter()
+        .filter(|w| w.base == neopixel::BASE && w.function == neopixel::functions::BUF)
+        .count();
+    assert_eq!(bufs, 16);
+
+    harness.stop();
+}
+
+#[test]
+fn keypad_fifo_produces_key_events() {
+    let harness = Harness::start();
+
+    // key (1, 2) is key 9 on the neotrellis, which the seesaw calls key 17
+    harness.i2c.push_keypad_event(keypad::KeyEvent {
+        key: 17,
+        edge: Edge::Rising,
+    });
+    harness.i2c.push_keypad_event(keypad::KeyEvent {
+        key: 17,
+        edge: Edge::Falling,
+    });
+
+    // the board's settings are reported before any keys
+    assert!(matches!(
+        harness.evt_rx.recv_timeout(TIMEOUT).unwrap(),
+        Event::Settings { .. }
+    ));
+
+    let expected = [Edge::Rising, Edge::Falling];
+
+    for edge in expected {
+        match harness.evt_rx.recv_timeout(TIMEOUT).unwrap() {
+            Event::Key(evt, _) => assert_eq!(evt, KeyEvent { key: (1, 2), edge }),
+            evt => panic!("unexpected event {evt:?}"),
+        }
+    }
+
+    harness.stop();
+}
+
+#[test]
+fn disconnected_neotrellis_stops_the_keyboard_with_an_error() {
+    let harness = Harness::start();
+
+    assert!(matches!(
+        harness.evt_rx.recv_timeout(TIMEOUT).unwrap(),
+        Event::Settings { .. }
+    ));
+
+    harness.i2c.disconnect();
+
+    let start = Instant::now();
+
+    while !harness.join.is_finished() {
+        assert!(start.elapsed() < TIMEOUT, "keyboard kept running");
+        std::thread::sleep(Duration::from_millis(5));
+    }
+
+    let err = harness.join.join().unwrap().unwrap_err();
+    assert!(format!("{err:#}").contains("disconnected"), "{err:#}");
+}
+
+#[test]
+fn moved_neotrellis_is_set_up_again_at_its_new_address() {
+    let harness = Harness::start();
+    harness.evt_rx.recv_timeout(TIMEOUT).unwrap();
+
+    harness
+        .cmd_tx
+        .send(Command::SetAddress { address: 0x2F })
+        .unwrap();
+
+    // the keypad is set up again after the reset, at the new address
+    let writes = harness.wait_for_write(|w| w.address == 0x2F && w.base == keypad::BASE);
+    let reset = writes
+        .iter()
+        .position(|w| w.base == status::BASE && w.function == status::functions::SWRST)
+        .expect("the seesaw wasn't reset");
+    assert_eq!(writes[reset].address, neotrellis::DEFAULT_ADDRESS);
+
+    match harness.evt_rx.recv_timeout(TIMEOUT).unwrap() {
+        Event::Settings { address, .. } => assert_eq!(address, 0x2F),
+        evt => panic!("unexpected event {evt:?}"),
+    }
+
+    harness.stop();
+}
+
+#[test]
+fn exiting_turns_pixels_off() {
+    let harness = Harness::start();
+    harness.wait_for_write(is_show);
+
+    let i2c = harness.i2c.clone();
+    i2c.take_writes();
+    harness.stop();
+
+    let bufs: Vec<_> = i2c
+        .writes()
+        .into_iter()
+        .filter(|w| w.base == neopixel::BASE && w.function == neopixel::functions::BUF)
+        .collect();
+
+    assert_eq!(bufs.len(), 16);
+    assert!(bufs.iter().all(|w| w.data[2..] == [0, 0, 0]));
+}
+
+#[test]
+fn hot_neotrellis_has_to_cool_down_before_it_stops_being_hot() {
+    let hot = keyboard::HOT_TEMPERATURE;
+
+    assert!(!keyboard::is_hot(hot - 1, false));
+    assert!(keyboard::is_hot(hot, false));
+
+    // a reading that wavers just under the threshold stays hot
+    assert!(keyboard::is_hot(hot - 1, true));
+    assert!(!keyboard::is_hot(hot - 10, true));
+}
+
+#[test]
+fn settings_are_read_from_and_saved_to_the_eeprom() {
+    let i2c = MockI2c::new();
+    // magic byte, brightness and no kit
+    i2c.set_register(eeprom::BASE, 0, &[0xD7, 40, 0xFF]);
+    let harness = Harness::start_with(i2c);
+
+    let settings = |evt| match evt {
+        Event::Settings { settings, address } => {
+            assert_eq!(address, neotrellis::DEFAULT_ADDRESS);
+            settings
+        }
+        evt => panic!("unexpected event {evt:?}"),
+    };
+
+    assert_eq!(
+        settings(harness.evt_rx.recv_timeout(TIMEOUT).unwrap()),
+        DeviceSettings {
+            brightness: 40,
+            kit: None
+        }
+    );
+
+    harness
+        .cmd_tx
+        .send(Command::SetKit { kit: Some(2) })
+        .unwrap();
+
+    let writes = harness.wait_for_write(|write| write.base == eeprom::BASE);
+    assert_eq!(writes.last().unwrap().data, [0xD7, 40, 2]);
+    assert_eq!(
+        settings(harness.evt_rx.recv_timeout(TIMEOUT).unwrap()),
+        DeviceSettings {
+            brightness: 40,
+            kit: Some(2)
+        }
+    );
+
+    harness.stop();
+}